@@ -0,0 +1,202 @@
+//! Times finding the nearest hits of a bundle of camera and bounce rays through the cover
+//! scene, with the `bvh` crate's own nearest-first iterator, which can't skip boxes past the
+//! nearest hit found so far, and with `World::hit`, which can. Also times inferring a material
+//! from a texture and its companion maps, and rendering the cover scene tile by tile on 1, 8,
+//! 16, 32 and all of the machine's threads, to see how the tiled renderer scales, and how long
+//! the preview takes to present a sweep band by band while other threads keep reading the
+//! display, copying the whole frame every band or only what changed. Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rt::{
+    accel::{self, BvhLayout},
+    camera::{Float, Image},
+    display::DisplayBuffer,
+    hittable::{Hit, World},
+    intersection::Intersection,
+    material::Material,
+    material_inference::{ImageSource, ImageStats},
+    scenes,
+    tiles::{ExecutionOptions, TileRenderer},
+    vec3::{Ray, Vec3},
+};
+use std::{
+    hint::black_box,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// What `World::hit` did before it pruned, for comparison
+fn unpruned_hit<'a>(world: &'a World, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'a>> {
+    let mut nearest = None;
+    let mut end = range.end;
+    for shape in world.bvh.nearest_traverse_iterator(ray, &world.shapes) {
+        if let Some(hit) = shape.hit(ray, &(range.start..end)) {
+            end = hit.t;
+            nearest = Some(hit);
+        }
+    }
+    nearest
+}
+
+fn nearest_hits(c: &mut Criterion) {
+    let (camera, shapes, surroundings) =
+        scenes::built_in_scene("cover", scenes::BUILT_IN_COVER_SEED).expect("cover is built in");
+    let camera = camera.with_resolution(400, 300);
+    let mut world = surroundings.build(shapes);
+    let rays = accel::sample_rays(&camera, &world);
+    let range = world.numeric.min_hit_distance..Float::INFINITY;
+    let count = |hit: &dyn Fn(&Ray) -> bool| rays.iter().filter(|ray| hit(black_box(ray))).count();
+
+    let mut group = c.benchmark_group(format!(
+        "nearest hits of {} rays among {} shapes",
+        rays.len(),
+        world.shapes.len()
+    ));
+    group.sample_size(10);
+    let expected = count(&|ray| unpruned_hit(&world, ray, &range).is_some());
+    group.bench_function("binary, unpruned", |b| {
+        b.iter(|| count(&|ray| unpruned_hit(&world, ray, &range).is_some()))
+    });
+    for layout in [BvhLayout::Binary, BvhLayout::Compressed] {
+        world
+            .set_bvh_layout(layout)
+            .expect("cover scene fits a compressed BVH");
+        let hits = count(&|ray| world.hit(ray, &range).is_some());
+        assert_eq!(
+            hits,
+            expected,
+            "{} layout found different hits",
+            layout.name()
+        );
+        group.bench_function(format!("{}, pruned", layout.name()), |b| {
+            b.iter(|| count(&|ray| world.hit(ray, &range).is_some()))
+        });
+    }
+    group.finish();
+}
+
+fn material_inference(c: &mut Criterion) {
+    // A megapixel of noise, so no pixel is like the one before
+    let pixels = (0..1024 * 1024u64)
+        .map(|i| {
+            let hash = i.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            Vec3::new(
+                (hash >> 40 & 0xff) as Float / 255.0,
+                (hash >> 48 & 0xff) as Float / 255.0,
+                (hash >> 56) as Float / 255.0,
+            )
+        })
+        .collect();
+    let image = Image {
+        pixels,
+        width: 1024,
+        height: 1024,
+        gamma: 1.0,
+        metadata: Vec::new(),
+        alpha: None,
+    };
+    c.bench_function("image statistics of 1024x1024", |b| {
+        b.iter(|| ImageStats::of(black_box(&image)))
+    });
+
+    // Four maps to find, load and weigh up, of which two are left out
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/textures/metal/brick_color.png");
+    c.bench_function("inferring a material with companion maps", |b| {
+        b.iter(|| Material::infer_from_image(ImageSource::Path(black_box(&path))).unwrap())
+    });
+}
+
+fn tiled_scaling(c: &mut Criterion) {
+    let (camera, shapes, surroundings) =
+        scenes::built_in_scene("cover", scenes::BUILT_IN_COVER_SEED).expect("cover is built in");
+    let mut camera = camera.with_resolution(160, 90).with_sampling(2, 8);
+    camera.seed = Some(1);
+    let world = surroundings.build(shapes);
+
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    let mut thread_counts = vec![1, 8, 16, 32, cores];
+    thread_counts.sort_unstable();
+    thread_counts.dedup();
+    let mut group = c.benchmark_group(format!("tiled render of cover on {} cores", cores));
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(
+        (camera.image_width * camera.image_height * camera.samples_per_pixel()) as u64,
+    ));
+    for threads in thread_counts {
+        let options = ExecutionOptions {
+            threads: Some(threads),
+            ..ExecutionOptions::default()
+        };
+        let renderer = TileRenderer::new(&options).expect("the pool starts");
+        group.bench_function(format!("{} threads", threads), |b| {
+            b.iter(|| renderer.render_image(&camera, &world))
+        });
+    }
+    group.finish();
+}
+
+fn presentation_latency(c: &mut Criterion) {
+    // A 1080p RGBA frame, published 32 rows at a time like the preview does
+    let (width, height, rows) = (1920, 1080, 32);
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    let mut reader_counts = vec![1, 8, cores];
+    reader_counts.sort_unstable();
+    reader_counts.dedup();
+    let mut group = c.benchmark_group("presenting a 1080p sweep in 32-row bands");
+    group.sample_size(10);
+    for readers in reader_counts {
+        let (buffer, mut writer) = DisplayBuffer::new(width * height * 4, 0);
+        let done = Arc::new(AtomicBool::new(false));
+        let threads: Vec<_> = (0..readers)
+            .map(|_| {
+                let (buffer, done) = (buffer.clone(), done.clone());
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        black_box(buffer.read(|frame| frame[frame.len() / 2]));
+                    }
+                })
+            })
+            .collect();
+        let bands = || {
+            (0..height)
+                .step_by(rows)
+                .map(|start| start * width * 4..(start + rows).min(height) * width * 4)
+        };
+        group.bench_function(format!("whole frame copied, {} readers", readers), |b| {
+            b.iter(|| {
+                for band in bands() {
+                    writer.publish(|back, front| {
+                        back.copy_from_slice(front);
+                        back[band].fill(black_box(255));
+                    });
+                }
+            })
+        });
+        group.bench_function(format!("last band copied, {} readers", readers), |b| {
+            b.iter(|| {
+                for band in bands() {
+                    writer.publish_range(band, |back| back.fill(black_box(255)));
+                }
+            })
+        });
+        done.store(true, Ordering::Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    nearest_hits,
+    material_inference,
+    tiled_scaling,
+    presentation_latency
+);
+criterion_main!(benches);
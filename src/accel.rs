@@ -0,0 +1,726 @@
+use crate::{
+    camera::{Camera, Float},
+    hittable::{Hit, Shape, World},
+    vec3::{Ray, Vec3, Vec3Ext},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bvh::{Bvh, BvhNode},
+};
+use itertools::Itertools;
+use rayon::prelude::*;
+use std::{
+    collections::HashSet,
+    fmt,
+    mem::size_of,
+    time::{Duration, Instant},
+};
+
+/// Children per node of a [`CompressedBvh`]
+pub const WIDTH: usize = 4;
+
+/// Marks a child reference as a shape index rather than a node index
+const LEAF: u32 = 1 << 31;
+
+/// Nodes whose children are only this far apart are quantized against a fixed tiny step, so
+/// the exponent stays in range for degenerate bounds
+const MIN_EXPONENT: i32 = i8::MIN as i32;
+
+/// Slab distances are widened by this fraction so rounding in the ray-box test can't turn a hit
+/// into a miss. Boxes are only ever made larger, which costs an occasional extra node visit.
+const SLAB_SLACK: Float = 1e-12;
+
+/// Entries the traversal stack holds before spilling onto the heap
+const STACK_SIZE: usize = 64;
+
+/// Which layout a world's `BVH` is traversed in
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BvhLayout {
+    /// The `bvh` crate's binary tree, with full `f64` bounds for every child
+    #[default]
+    Binary,
+    /// A [`CompressedBvh`] built from the binary tree
+    Compressed,
+}
+
+impl BvhLayout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BvhLayout::Binary => "binary",
+            BvhLayout::Compressed => "compressed",
+        }
+    }
+
+    /// The inverse of [`BvhLayout::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "binary" => Some(BvhLayout::Binary),
+            "compressed" => Some(BvhLayout::Compressed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccelError {
+    /// A shape's bounds aren't finite or don't fit in an `f32`, so they can't be quantized
+    UnboundedShape(usize),
+    /// More shapes or nodes than child references have room for
+    TooLarge(usize),
+}
+
+impl fmt::Display for AccelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccelError::UnboundedShape(index) => {
+                write!(f, "shape {} has bounds too large to quantize", index)
+            }
+            AccelError::TooLarge(count) => write!(
+                f,
+                "{} shapes or nodes are more than a compressed BVH can refer to",
+                count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AccelError {}
+
+/// A node of a [`CompressedBvh`], exactly one cache line. Its children's bounds are stored as
+/// 8-bit steps from `origin`, where each axis's step is a power of two so they decompress
+/// exactly, and are rounded outward so they always contain the bounds they stand for.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(64))]
+pub struct WideNode {
+    /// The node's minimum corner, rounded down to `f32`
+    origin: [f32; 3],
+    /// Each axis's step is `2^exponent`
+    exponent: [i8; 3],
+    child_count: u8,
+    /// Per axis, each child's minimum in steps from `origin`
+    lo: [[u8; WIDTH]; 3],
+    /// Per axis, each child's maximum in steps from `origin`
+    hi: [[u8; WIDTH]; 3],
+    /// Node indices, or shape indices marked with [`LEAF`]
+    children: [u32; WIDTH],
+}
+
+const _: () = assert!(size_of::<WideNode>() == 64);
+
+/// Returns `2^exponent`, which is always a normal `f64` for an `i8` exponent
+fn step(exponent: i8) -> Float {
+    Float::from_bits(((exponent as i32 + 1023) as u64) << 52)
+}
+
+impl WideNode {
+    /// A node with no children
+    const EMPTY: WideNode = WideNode {
+        origin: [0.0; 3],
+        exponent: [0; 3],
+        child_count: 0,
+        lo: [[0; WIDTH]; 3],
+        hi: [[0; WIDTH]; 3],
+        children: [0; WIDTH],
+    };
+
+    /// Returns child `i`'s bounds on `axis` as decompressed for traversal
+    fn child_bounds(&self, axis: usize, i: usize) -> (Float, Float) {
+        let origin = self.origin[axis] as Float;
+        let step = step(self.exponent[axis]);
+        (
+            origin + self.lo[axis][i] as Float * step,
+            origin + self.hi[axis][i] as Float * step,
+        )
+    }
+
+    /// Quantizes `children`, at most [`WIDTH`] of them, against their combined bounds
+    fn quantize(children: &[(Aabb<Float, 3>, u32)]) -> Self {
+        let mut node = WideNode {
+            child_count: children.len() as u8,
+            ..WideNode::EMPTY
+        };
+        let bounds = children
+            .iter()
+            .fold(Aabb::empty(), |bounds, (child, _)| bounds.join(child));
+        for axis in 0..3 {
+            let min = bounds.min[axis];
+            let mut origin = min as f32;
+            if origin as Float > min {
+                origin = origin.next_down();
+            }
+            let extent = bounds.max[axis] - origin as Float;
+            let mut exponent = (extent / 255.0).log2().ceil().max(MIN_EXPONENT as Float) as i32;
+            // The logarithm can round either way, so make sure the last step reaches the max
+            while (origin as Float) + 255.0 * step(exponent as i8) < bounds.max[axis] {
+                exponent += 1;
+            }
+            node.origin[axis] = origin;
+            node.exponent[axis] = exponent as i8;
+            let (origin, step) = (origin as Float, step(exponent as i8));
+            for (i, (child, _)) in children.iter().enumerate() {
+                // Rounded outward, then nudged in case the subtraction rounded the wrong way
+                let mut lo = ((child.min[axis] - origin) / step)
+                    .floor()
+                    .clamp(0.0, 255.0) as u8;
+                while lo > 0 && origin + lo as Float * step > child.min[axis] {
+                    lo -= 1;
+                }
+                let mut hi = ((child.max[axis] - origin) / step).ceil().clamp(0.0, 255.0) as u8;
+                while hi < 255 && origin + (hi as Float) * step < child.max[axis] {
+                    hi += 1;
+                }
+                node.lo[axis][i] = lo;
+                node.hi[axis][i] = hi;
+            }
+        }
+        for (i, (_, child)) in children.iter().enumerate() {
+            node.children[i] = *child;
+        }
+        node
+    }
+}
+
+/// A 4-wide `BVH` converted from the `bvh` crate's binary one, with child bounds quantized to
+/// 8 bits per side. Each node takes one cache line for four children where the binary tree
+/// takes two 128-byte nodes for two, and a ray loads the bounds of all four children at once.
+/// Refers to the same shapes as the tree it came from, by index.
+pub struct CompressedBvh {
+    nodes: Vec<WideNode>,
+}
+
+/// A subtree of the binary tree being folded into a wide node
+#[derive(Clone, Copy)]
+struct Subtree {
+    bounds: Aabb<Float, 3>,
+    node: usize,
+}
+
+impl CompressedBvh {
+    /// Converts `bvh`, which was built over `shapes`. Each wide node takes the children of a
+    /// binary node and keeps opening up whichever of them has the largest surface area until
+    /// it has four.
+    pub fn from_bvh<S: Bounded<Float, 3>>(
+        bvh: &Bvh<Float, 3>,
+        shapes: &[S],
+    ) -> Result<Self, AccelError> {
+        if shapes.len() >= LEAF as usize || bvh.nodes.len() >= LEAF as usize {
+            return Err(AccelError::TooLarge(shapes.len().max(bvh.nodes.len())));
+        }
+        // Also rules out NaNs and infinities
+        let representable = |c: &Float| c.abs() < f32::MAX as Float;
+        if let Some(index) = shapes.iter().position(|shape| {
+            let aabb = shape.aabb();
+            !(aabb.min.iter().all(representable) && aabb.max.iter().all(representable))
+        }) {
+            return Err(AccelError::UnboundedShape(index));
+        }
+        let mut accel = CompressedBvh { nodes: Vec::new() };
+        match bvh.nodes.first() {
+            None => {}
+            Some(&BvhNode::Leaf { shape_index, .. }) => {
+                let leaf = (shapes[shape_index].aabb(), LEAF | shape_index as u32);
+                accel.nodes.push(WideNode::quantize(&[leaf]));
+            }
+            Some(_) => {
+                accel.convert(bvh, 0);
+            }
+        }
+        Ok(accel)
+    }
+
+    /// Converts binary inner node `node` and everything under it, returning its wide node's index
+    fn convert(&mut self, bvh: &Bvh<Float, 3>, node: usize) -> u32 {
+        let mut subtrees = children_of(bvh, node).to_vec();
+        while subtrees.len() < WIDTH {
+            let largest = subtrees
+                .iter()
+                .enumerate()
+                .filter(|(_, subtree)| matches!(bvh.nodes[subtree.node], BvhNode::Node { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    a.bounds.surface_area().total_cmp(&b.bounds.surface_area())
+                })
+                .map(|(i, _)| i);
+            let Some(largest) = largest else {
+                break;
+            };
+            let opened = subtrees.swap_remove(largest);
+            subtrees.extend(children_of(bvh, opened.node));
+        }
+
+        let index = self.nodes.len();
+        // Filled in once the children have their indices
+        self.nodes.push(WideNode::EMPTY);
+        let children: Vec<(Aabb<Float, 3>, u32)> = subtrees
+            .iter()
+            .map(|subtree| match bvh.nodes[subtree.node] {
+                BvhNode::Leaf { shape_index, .. } => (subtree.bounds, LEAF | shape_index as u32),
+                BvhNode::Node { .. } => (subtree.bounds, self.convert(bvh, subtree.node)),
+            })
+            .collect();
+        self.nodes[index] = WideNode::quantize(&children);
+        index as u32
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Bytes taken by the nodes
+    pub fn memory_bytes(&self) -> usize {
+        self.nodes.len() * size_of::<WideNode>()
+    }
+
+    /// Returns the shapes whose bounds `ray` hits, roughly nearest first. Boxes further than
+    /// [`Traverse::limit`] are skipped, so callers looking for the nearest hit can shrink it as
+    /// they go.
+    pub fn traverse<'a, S>(&'a self, ray: &Ray, shapes: &'a [S]) -> Traverse<'a, S> {
+        let mut traverse = Traverse {
+            nodes: &self.nodes,
+            shapes,
+            origin: ray.origin.coords.into(),
+            inv_direction: ray.inv_direction.into(),
+            max_distance: Float::INFINITY,
+            stack: TraversalStack::new(),
+        };
+        if !self.nodes.is_empty() {
+            traverse.stack.push(0, 0.0);
+        }
+        traverse
+    }
+}
+
+fn children_of(bvh: &Bvh<Float, 3>, node: usize) -> [Subtree; 2] {
+    match bvh.nodes[node] {
+        BvhNode::Node {
+            child_l_index,
+            child_l_aabb,
+            child_r_index,
+            child_r_aabb,
+            ..
+        } => [
+            Subtree {
+                bounds: child_l_aabb,
+                node: child_l_index,
+            },
+            Subtree {
+                bounds: child_r_aabb,
+                node: child_r_index,
+            },
+        ],
+        BvhNode::Leaf { .. } => unreachable!("only inner nodes have children"),
+    }
+}
+
+/// Nodes a traversal has still to visit, with the distance the ray enters their bounds at
+struct TraversalStack<T> {
+    entries: [(T, Float); STACK_SIZE],
+    size: usize,
+    /// Takes over from `entries` in trees too deep for it
+    spill: Vec<(T, Float)>,
+}
+
+impl<T: Copy + Default> TraversalStack<T> {
+    fn new() -> Self {
+        TraversalStack {
+            entries: [(T::default(), 0.0); STACK_SIZE],
+            size: 0,
+            spill: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, node: T, distance: Float) {
+        if self.size < STACK_SIZE {
+            self.entries[self.size] = (node, distance);
+            self.size += 1;
+        } else {
+            self.spill.push((node, distance));
+        }
+    }
+
+    fn pop(&mut self) -> Option<(T, Float)> {
+        if let Some(entry) = self.spill.pop() {
+            return Some(entry);
+        }
+        if self.size == 0 {
+            return None;
+        }
+        self.size -= 1;
+        Some(self.entries[self.size])
+    }
+}
+
+/// Returns the distance a ray enters the box spanning `bounds(axis)` on each axis at, if it
+/// does before `max_distance`. A ray parallel to a slab and starting on one of its planes makes
+/// NaNs, and then that slab doesn't rule anything out.
+fn enter_box(
+    origin: &[Float; 3],
+    inv_direction: &[Float; 3],
+    max_distance: Float,
+    bounds: impl Fn(usize) -> (Float, Float),
+) -> Option<Float> {
+    let (mut near, mut far) = (0.0 as Float, max_distance);
+    for axis in 0..3 {
+        let (lo, hi) = bounds(axis);
+        let t0 = (lo - origin[axis]) * inv_direction[axis];
+        let t1 = (hi - origin[axis]) * inv_direction[axis];
+        if t0.is_nan() || t1.is_nan() {
+            continue;
+        }
+        let (t0, t1) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+        near = near.max(t0);
+        far = far.min(t1);
+    }
+    // Widened only now, since widening an infinite distance would make a NaN. Parallel to a
+    // slab and outside it puts both of its distances at infinity, which never hits.
+    let near = near - near * SLAB_SLACK;
+    let far = far + far.abs() * SLAB_SLACK;
+    (near <= far && near < Float::INFINITY).then_some(near)
+}
+
+/// Iterator over the shapes a ray might hit in a [`CompressedBvh`], see
+/// [`CompressedBvh::traverse`]
+pub struct Traverse<'a, S> {
+    nodes: &'a [WideNode],
+    shapes: &'a [S],
+    origin: [Float; 3],
+    inv_direction: [Float; 3],
+    max_distance: Float,
+    /// Child references
+    stack: TraversalStack<u32>,
+}
+
+impl<S> Traverse<'_, S> {
+    /// Stops returning shapes whose bounds the ray enters further away than `distance`
+    pub fn limit(&mut self, distance: Float) {
+        self.max_distance = self.max_distance.min(distance);
+    }
+}
+
+impl<'a, S> Iterator for Traverse<'a, S> {
+    type Item = &'a S;
+
+    fn next(&mut self) -> Option<&'a S> {
+        while let Some((child, distance)) = self.stack.pop() {
+            if distance > self.max_distance {
+                continue;
+            }
+            if child & LEAF != 0 {
+                return Some(&self.shapes[(child & !LEAF) as usize]);
+            }
+            let node = &self.nodes[child as usize];
+            let mut hits = [(0, 0.0); WIDTH];
+            let mut count = 0;
+            for i in 0..node.child_count as usize {
+                let bounds = |axis| node.child_bounds(axis, i);
+                if let Some(distance) =
+                    enter_box(&self.origin, &self.inv_direction, self.max_distance, bounds)
+                {
+                    hits[count] = (node.children[i], distance);
+                    count += 1;
+                }
+            }
+            // Furthest first, so the nearest comes off the stack first
+            hits[..count].sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+            for &(child, distance) in &hits[..count] {
+                self.stack.push(child, distance);
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the shapes a ray might hit in the `bvh` crate's binary tree, nearer child
+/// first. Unlike the crate's own iterators it skips boxes further than
+/// [`BinaryTraverse::limit`], so finding a close hit early prunes the rest of the tree.
+pub struct BinaryTraverse<'a, S> {
+    nodes: &'a [BvhNode<Float, 3>],
+    shapes: &'a [S],
+    origin: [Float; 3],
+    inv_direction: [Float; 3],
+    max_distance: Float,
+    /// Node indices
+    stack: TraversalStack<usize>,
+}
+
+impl<'a, S> BinaryTraverse<'a, S> {
+    pub fn new(bvh: &'a Bvh<Float, 3>, ray: &Ray, shapes: &'a [S]) -> Self {
+        let mut traverse = BinaryTraverse {
+            nodes: &bvh.nodes,
+            shapes,
+            origin: ray.origin.coords.into(),
+            inv_direction: ray.inv_direction.into(),
+            max_distance: Float::INFINITY,
+            stack: TraversalStack::new(),
+        };
+        if !bvh.nodes.is_empty() {
+            traverse.stack.push(0, 0.0);
+        }
+        traverse
+    }
+
+    /// Stops returning shapes whose bounds the ray enters further away than `distance`
+    pub fn limit(&mut self, distance: Float) {
+        self.max_distance = self.max_distance.min(distance);
+    }
+
+    fn enter(&self, bounds: &Aabb<Float, 3>) -> Option<Float> {
+        enter_box(
+            &self.origin,
+            &self.inv_direction,
+            self.max_distance,
+            |axis| (bounds.min[axis], bounds.max[axis]),
+        )
+    }
+}
+
+impl<'a, S> Iterator for BinaryTraverse<'a, S> {
+    type Item = &'a S;
+
+    fn next(&mut self) -> Option<&'a S> {
+        let nodes = self.nodes;
+        while let Some((node, distance)) = self.stack.pop() {
+            if distance > self.max_distance {
+                continue;
+            }
+            match &nodes[node] {
+                BvhNode::Leaf { shape_index, .. } => return Some(&self.shapes[*shape_index]),
+                BvhNode::Node {
+                    child_l_index,
+                    child_l_aabb,
+                    child_r_index,
+                    child_r_aabb,
+                    ..
+                } => {
+                    let left = self.enter(child_l_aabb).map(|t| (*child_l_index, t));
+                    let right = self.enter(child_r_aabb).map(|t| (*child_r_index, t));
+                    // Further first, so the nearer comes off the stack first
+                    let (near, far) = match (left, right) {
+                        (Some(l), Some(r)) if r.1 < l.1 => (Some(r), Some(l)),
+                        _ => (left, right),
+                    };
+                    for (child, distance) in far.into_iter().chain(near) {
+                        self.stack.push(child, distance);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// How a [`CompressedBvh`] compares with the binary tree it came from on a set of rays
+#[derive(Debug, Clone)]
+pub struct LayoutComparison {
+    pub shapes: usize,
+    pub rays: usize,
+    pub binary_bytes: usize,
+    pub compressed_bytes: usize,
+    pub binary_time: Duration,
+    pub compressed_time: Duration,
+    /// Shapes a ray hits that the binary tree found and the compressed one didn't, which should
+    /// never happen
+    pub missed: usize,
+    /// Shapes the compressed tree found for a ray that the binary one didn't, from its looser
+    /// bounds. Harmless but wasted work.
+    pub extra: usize,
+}
+
+impl LayoutComparison {
+    /// Times finding the nearest hits of `rays` in `world` with each layout, and checks that
+    /// the compressed tree finds every shape the binary one does for every ray. Leaves the
+    /// world in the layout it was in.
+    pub fn measure(world: &mut World, rays: &[Ray]) -> Result<Self, AccelError> {
+        let layout = world.bvh_layout();
+        let range = world.numeric.min_hit_distance..Float::INFINITY;
+        let time = |world: &World| {
+            let start = Instant::now();
+            let hits = rays
+                .par_iter()
+                .filter(|ray| world.hit(ray, &range).is_some())
+                .count();
+            std::hint::black_box(hits);
+            start.elapsed()
+        };
+
+        world.set_bvh_layout(BvhLayout::Binary)?;
+        let binary_bytes = world.bvh_memory_bytes();
+        let binary_time = time(world);
+        world.set_bvh_layout(BvhLayout::Compressed)?;
+        let compressed_bytes = world.bvh_memory_bytes();
+        let compressed_time = time(world);
+
+        let accel = CompressedBvh::from_bvh(&world.bvh, &world.shapes)?;
+        let index = |shape: &Shape| {
+            (shape as *const Shape as usize - world.shapes.as_ptr() as usize) / size_of::<Shape>()
+        };
+        let (missed, extra) = rays
+            .par_iter()
+            .map(|ray| {
+                let binary: HashSet<usize> = world
+                    .bvh
+                    .traverse_iterator(ray, &world.shapes)
+                    .map(index)
+                    .collect();
+                let compressed: HashSet<usize> =
+                    accel.traverse(ray, &world.shapes).map(index).collect();
+                // The binary tree's own box test lets through shapes the ray can't hit, so only
+                // shapes the ray really hits count as missed
+                let missed = binary
+                    .difference(&compressed)
+                    .filter(|&&i| world.shapes[i].hit(ray, &range).is_some())
+                    .count();
+                (missed, compressed.difference(&binary).count())
+            })
+            .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        world.set_bvh_layout(layout)?;
+
+        Ok(LayoutComparison {
+            shapes: world.shapes.len(),
+            rays: rays.len(),
+            binary_bytes,
+            compressed_bytes,
+            binary_time,
+            compressed_time,
+            missed,
+            extra,
+        })
+    }
+}
+
+/// Rays for comparing layouts: one camera ray through every pixel of `camera`, and one
+/// bouncing off wherever each of those hits in a random direction, like diffuse bounces do
+pub fn sample_rays(camera: &Camera, world: &World) -> Vec<Ray> {
+    let range = world.numeric.min_hit_distance..camera.t_range().end;
+    let primary = (0..camera.image_height)
+        .cartesian_product(0..camera.image_width)
+        .map(|(y, x)| camera.primary_ray(x, y, 0))
+        .collect_vec();
+    let bounces = primary
+        .par_iter()
+        .filter_map(|ray| {
+            let hit = world.hit(ray, &range)?;
+            let direction = Vec3::random_on_hemisphere(&mut rand::thread_rng(), &hit.normal);
+            let origin = world
+                .numeric
+                .offset_ray_origin(&hit.point, &hit.normal, &direction);
+            Some(Ray::new(origin.into(), direction))
+        })
+        .collect::<Vec<_>>();
+    primary.into_iter().chain(bounces).collect()
+}
+
+impl fmt::Display for LayoutComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: usize| bytes as Float / (1024.0 * 1024.0);
+        let mrays = |time: Duration| self.rays as Float / time.as_secs_f64().max(1e-9) / 1e6;
+        writeln!(f, "{} shapes, {} rays", self.shapes, self.rays)?;
+        writeln!(
+            f,
+            "binary:     {:>9.2} MiB, {:>7.2} Mrays/s",
+            mib(self.binary_bytes),
+            mrays(self.binary_time)
+        )?;
+        writeln!(
+            f,
+            "compressed: {:>9.2} MiB, {:>7.2} Mrays/s ({:.1}x less memory, {:+.1}% speed)",
+            mib(self.compressed_bytes),
+            mrays(self.compressed_time),
+            self.binary_bytes as Float / self.compressed_bytes.max(1) as Float,
+            (self.binary_time.as_secs_f64() / self.compressed_time.as_secs_f64().max(1e-9) - 1.0)
+                * 100.0
+        )?;
+        write!(
+            f,
+            "{} shapes missed by the compressed BVH, {} extra",
+            self.missed, self.extra
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Sphere, Triangle},
+        material::{Lambertian, Material},
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::{ops::Range, sync::Arc};
+
+    /// Spheres and triangles of every size scattered through a box, some overlapping
+    fn scattered_shapes(rng: &mut StdRng) -> Vec<Shape> {
+        let material: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let place = |rng: &mut StdRng| Vec3::random(rng, -5.0, 5.0);
+        let mut shapes: Vec<Shape> = (0..300)
+            .map(|_| {
+                let center = place(rng);
+                Sphere::new(center, rng.gen_range(0.05..0.5), material.clone()).into()
+            })
+            .collect();
+        shapes.extend((0..300).map(|_| {
+            let a = place(rng);
+            let size = rng.gen_range(0.1..2.0);
+            let b = a + Vec3::random_unit(rng) * size;
+            let c = a + Vec3::random_unit(rng) * size;
+            Triangle::new(a, b, c, material.clone()).into()
+        }));
+        shapes
+    }
+
+    /// Index and distance of the nearest shape `candidates` leads `ray` to, narrowing the
+    /// search with `limit` as nearer hits turn up
+    fn nearest<'a, I: Iterator<Item = &'a Shape>>(
+        shapes: &'a [Shape],
+        mut candidates: I,
+        limit: impl Fn(&mut I, Float),
+        ray: &Ray,
+        range: &Range<Float>,
+    ) -> Option<(usize, Float)> {
+        let mut nearest = None;
+        let mut end = range.end;
+        while let Some(shape) = candidates.next() {
+            if let Some(hit) = shape.hit(ray, &(range.start..end)) {
+                end = hit.t;
+                limit(&mut candidates, hit.t);
+                let index = (shape as *const Shape as usize - shapes.as_ptr() as usize)
+                    / size_of::<Shape>();
+                nearest = Some((index, hit.t));
+            }
+        }
+        nearest
+    }
+
+    #[test]
+    fn both_layouts_find_the_same_nearest_hits() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let world = World::build(scattered_shapes(&mut rng));
+        let accel = CompressedBvh::from_bvh(&world.bvh, &world.shapes).unwrap();
+        let range = world.numeric.min_hit_distance..Float::INFINITY;
+        let shapes = &world.shapes;
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // From inside the box as well as outside it, toward somewhere in it
+            let origin = Vec3::random(&mut rng, -8.0, 8.0);
+            let toward = Vec3::random(&mut rng, -5.0, 5.0);
+            let ray = Ray::new(origin.into(), (toward - origin).normalize());
+            let binary = nearest(
+                shapes,
+                BinaryTraverse::new(&world.bvh, &ray, shapes),
+                BinaryTraverse::limit,
+                &ray,
+                &range,
+            );
+            let compressed = nearest(
+                shapes,
+                accel.traverse(&ray, shapes),
+                Traverse::limit,
+                &ray,
+                &range,
+            );
+            assert_eq!(binary, compressed, "from {:?}", origin);
+            hits += binary.is_some() as usize;
+        }
+        // Enough of the rays hit something for the comparison to mean anything
+        assert!(hits > 1000, "only {} rays hit", hits);
+    }
+}
@@ -0,0 +1,664 @@
+use crate::{
+    camera::Float,
+    hittable::{Shape, World},
+    vec3::Vec3,
+};
+use nalgebra::{Similarity3, Translation3, Unit, UnitQuaternion};
+use serde::Deserialize;
+use std::fmt;
+
+/// How a value eases from one keyframe to the next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Starts and stops gently, following smoothstep
+    Smooth,
+}
+
+impl Interpolation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Linear => "linear",
+            Interpolation::Smooth => "smooth",
+        }
+    }
+
+    /// The inverse of [`Interpolation::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Interpolation::Linear),
+            "smooth" => Some(Interpolation::Smooth),
+            _ => None,
+        }
+    }
+
+    /// Turns how far along a segment a frame is, from 0 to 1, into how far its value should be
+    pub(crate) fn ease(&self, t: Float) -> Float {
+        match self {
+            Interpolation::Linear => t,
+            Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframe<T> {
+    pub frame: Float,
+    pub value: T,
+    /// How the value eases from this keyframe to the next one
+    pub interpolation: Interpolation,
+}
+
+/// Keyframes of one part of a transform, kept sorted by frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel<T>(Vec<Keyframe<T>>);
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Channel(Vec::new())
+    }
+}
+
+impl<T: Clone> Channel<T> {
+    pub fn keyframes(&self) -> &[Keyframe<T>] {
+        &self.0
+    }
+
+    /// Adds a keyframe wherever it belongs in time, so keyframes can be given in any order.
+    /// Fails if there's already one on the same frame.
+    pub fn insert(&mut self, keyframe: Keyframe<T>) -> Result<(), AnimationError> {
+        match self
+            .0
+            .binary_search_by(|existing| existing.frame.total_cmp(&keyframe.frame))
+        {
+            Ok(_) => Err(AnimationError::DuplicateKeyframe(keyframe.frame)),
+            Err(index) => {
+                self.0.insert(index, keyframe);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the value at `frame`, blending the keyframes around it with `blend`. Holds the
+    /// first and last values before and after the keyframes. `None` if there are no keyframes.
+    fn value_at(&self, frame: Float, blend: impl Fn(&T, &T, Float) -> T) -> Option<T> {
+        let next = self.0.partition_point(|keyframe| keyframe.frame <= frame);
+        match (next.checked_sub(1).map(|i| &self.0[i]), self.0.get(next)) {
+            (None, None) => None,
+            (Some(only), None) | (None, Some(only)) => Some(only.value.clone()),
+            (Some(from), Some(to)) => {
+                let t = (frame - from.frame) / (to.frame - from.frame);
+                Some(blend(&from.value, &to.value, from.interpolation.ease(t)))
+            }
+        }
+    }
+}
+
+/// Keyframes moving one scene object on top of where it was placed, turning and scaling it
+/// around its resting position
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    /// Name of the object, as given by [`crate::object::ObjectId::name`]
+    pub object: String,
+    pub translation: Channel<Vec3>,
+    pub rotation: Channel<UnitQuaternion<Float>>,
+    pub scale: Channel<Float>,
+}
+
+impl Track {
+    pub fn new(object: &str) -> Self {
+        Track {
+            object: object.to_string(),
+            ..Track::default()
+        }
+    }
+
+    pub fn translation_at(&self, frame: Float) -> Vec3 {
+        self.translation
+            .value_at(frame, |from, to, t| from.lerp(to, t))
+            .unwrap_or_else(Vec3::zeros)
+    }
+
+    pub fn rotation_at(&self, frame: Float) -> UnitQuaternion<Float> {
+        self.rotation
+            .value_at(frame, |from, to, t| {
+                // Slerp has no single answer halfway around, so that snaps between the two
+                from.try_slerp(to, t, Float::EPSILON)
+                    .unwrap_or(if t < 0.5 { *from } else { *to })
+            })
+            .unwrap_or_else(UnitQuaternion::identity)
+    }
+
+    pub fn scale_at(&self, frame: Float) -> Float {
+        self.scale
+            .value_at(frame, |from, to, t| from + (to - from) * t)
+            .unwrap_or(1.0)
+    }
+
+    /// The transform the object gets at `frame` on top of where it rests: scaled, then rotated,
+    /// then translated
+    pub fn transform_at(&self, frame: Float) -> Similarity3<Float> {
+        Similarity3::from_parts(
+            Translation3::from(self.translation_at(frame)),
+            self.rotation_at(frame),
+            self.scale_at(frame),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationError {
+    DuplicateKeyframe(Float),
+    /// No instance in the scene is called this. Only instances can be animated, since they move
+    /// without rebuilding their geometry.
+    UnknownObject(String),
+    /// No shape in the scene belongs to an object with this name for the camera to look at
+    UnknownTarget(String),
+    Malformed(String),
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationError::DuplicateKeyframe(frame) => {
+                write!(f, "there's already a keyframe on frame {}", frame)
+            }
+            AnimationError::UnknownObject(object) => write!(
+                f,
+                "no instance called '{}' to animate (only instances can be animated)",
+                object
+            ),
+            AnimationError::UnknownTarget(object) => {
+                write!(f, "no object called '{}' for the camera to track", object)
+            }
+            AnimationError::Malformed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for AnimationError {}
+
+/// Keyframed movement of scene objects, evaluated per frame. Objects without a track aren't
+/// touched at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Animation {
+    pub tracks: Vec<Track>,
+}
+
+impl Animation {
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Returns the track for `object`, adding an empty one if it doesn't have one yet
+    pub fn track_mut(&mut self, object: &str) -> &mut Track {
+        let index = match self.tracks.iter().position(|track| track.object == object) {
+            Some(index) => index,
+            None => {
+                self.tracks.push(Track::new(object));
+                self.tracks.len() - 1
+            }
+        };
+        &mut self.tracks[index]
+    }
+
+    /// This animation's tracks, along with those of `base` for objects this one leaves alone,
+    /// e.g. a render job's on top of its scene's
+    pub fn over(&self, base: &Animation) -> Animation {
+        let unchanged = base.tracks.iter().filter(|track| {
+            !self
+                .tracks
+                .iter()
+                .any(|replacing| replacing.object == track.object)
+        });
+        Animation {
+            tracks: unchanged.chain(&self.tracks).cloned().collect(),
+        }
+    }
+
+    /// Moves every animated instance in `world` to where it is at `frame` and refits the
+    /// world's `BVH`. Returns how many instances moved.
+    pub fn apply(&self, world: &mut World, frame: Float) -> Result<usize, AnimationError> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        let mut found = vec![false; self.tracks.len()];
+        let mut moved = 0;
+        for shape in &mut world.shapes {
+            let Shape::Instance(instance) = shape else {
+                continue;
+            };
+            let name = instance.object.name();
+            let Some(track) = self.tracks.iter().position(|track| *track.object == *name) else {
+                continue;
+            };
+            found[track] = true;
+            // Rotates and scales the object around where it rests rather than the world's origin
+            let rest = *instance.rest_transform();
+            let origin =
+                Similarity3::from_parts(rest.isometry.translation, UnitQuaternion::identity(), 1.0);
+            let transform =
+                origin * self.tracks[track].transform_at(frame) * origin.inverse() * rest;
+            instance.set_transform(transform);
+            moved += 1;
+        }
+        if let Some(missing) = found.iter().position(|&found| !found) {
+            return Err(AnimationError::UnknownObject(
+                self.tracks[missing].object.clone(),
+            ));
+        }
+        world.refit();
+        Ok(moved)
+    }
+
+    /// Serializes every keyframe as an `animate <object> <channel> <frame> <values...>
+    /// <interpolation>` line, the inverse of [`Animation::parse_line`]
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for track in &self.tracks {
+            let mut line = |channel: &str, frame: Float, values: String, ease: Interpolation| {
+                lines.push(format!(
+                    "animate {} {} {} {} {}",
+                    track.object,
+                    channel,
+                    frame,
+                    values,
+                    ease.name()
+                ));
+            };
+            for key in track.translation.keyframes() {
+                let v = key.value;
+                let values = format!("{} {} {}", v.x, v.y, v.z);
+                line("translate", key.frame, values, key.interpolation);
+            }
+            for key in track.rotation.keyframes() {
+                let (axis, angle) = key.value.axis_angle().unwrap_or((Vec3::z_axis(), 0.0));
+                let values = format!("{} {} {} {}", axis.x, axis.y, axis.z, angle.to_degrees());
+                line("rotate", key.frame, values, key.interpolation);
+            }
+            for key in track.scale.keyframes() {
+                let values = key.value.to_string();
+                line("scale", key.frame, values, key.interpolation);
+            }
+        }
+        lines
+    }
+
+    /// Adds the keyframe described by the words after `animate` on a line: the object's name,
+    /// then `translate <frame> <x> <y> <z>`, `rotate <frame> <axis x> <y> <z> <degrees>` or
+    /// `scale <frame> <factor>`, optionally followed by `linear` or `smooth`
+    pub fn parse_line(&mut self, words: &[&str]) -> Result<(), AnimationError> {
+        let malformed = |message: String| AnimationError::Malformed(message);
+        let [object, channel, frame, values @ ..] = words else {
+            return Err(malformed(
+                "animate needs an object, a channel, a frame and values".to_string(),
+            ));
+        };
+        let (interpolation, values) = match values.split_last() {
+            Some((last, rest)) if last.parse::<Float>().is_err() => (
+                Interpolation::from_name(last)
+                    .ok_or_else(|| malformed(format!("unknown interpolation '{}'", last)))?,
+                rest,
+            ),
+            _ => (Interpolation::default(), values),
+        };
+        let number = |word: &str| {
+            word.parse::<Float>()
+                .map_err(|_| malformed(format!("'{}' is not a valid value", word)))
+        };
+        let frame = number(frame)?;
+        let values = values
+            .iter()
+            .map(|word| number(word))
+            .collect::<Result<Vec<Float>, _>>()?;
+        let expected = match *channel {
+            "translate" => 3,
+            "rotate" => 4,
+            "scale" => 1,
+            _ => {
+                return Err(malformed(format!(
+                    "unknown animation channel '{}'",
+                    channel
+                )))
+            }
+        };
+        if values.len() != expected {
+            return Err(malformed(format!(
+                "{} needs {} value(s) but found {}",
+                channel,
+                expected,
+                values.len()
+            )));
+        }
+        let track = self.track_mut(object);
+        match *channel {
+            "translate" => track.translation.insert(Keyframe {
+                frame,
+                value: Vec3::new(values[0], values[1], values[2]),
+                interpolation,
+            }),
+            "rotate" => {
+                let axis = Vec3::new(values[0], values[1], values[2]);
+                let axis = Unit::try_new(axis, Float::EPSILON)
+                    .ok_or_else(|| malformed("rotation axis can't be zero".to_string()))?;
+                track.rotation.insert(Keyframe {
+                    frame,
+                    value: UnitQuaternion::from_axis_angle(&axis, values[3].to_radians()),
+                    interpolation,
+                })
+            }
+            _ => track.scale.insert(Keyframe {
+                frame,
+                value: values[0],
+                interpolation,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{Background, Hit, Sphere},
+        instance::{Instance, Prototype},
+        material::DiffuseLight,
+        object::ObjectId,
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    fn keyframe<T>(frame: Float, value: T, interpolation: Interpolation) -> Keyframe<T> {
+        Keyframe {
+            frame,
+            value,
+            interpolation,
+        }
+    }
+
+    #[test]
+    fn translations_interpolate_linearly_between_keyframes() {
+        let mut track = Track::new("ball");
+        let end = Vec3::new(4.0, 8.0, -4.0);
+        track
+            .translation
+            .insert(keyframe(10.0, Vec3::zeros(), Interpolation::Linear))
+            .unwrap();
+        track
+            .translation
+            .insert(keyframe(14.0, end, Interpolation::Linear))
+            .unwrap();
+        assert_eq!(track.translation_at(11.0), end * 0.25);
+        assert_eq!(track.translation_at(12.0), end * 0.5);
+        assert_eq!(track.translation_at(13.0), end * 0.75);
+        // Held before the first keyframe and after the last
+        assert_eq!(track.translation_at(0.0), Vec3::zeros());
+        assert_eq!(track.translation_at(20.0), end);
+    }
+
+    #[test]
+    fn smooth_keyframes_ease_in_and_out() {
+        let mut channel = Channel::default();
+        channel
+            .insert(keyframe(0.0, 0.0, Interpolation::Smooth))
+            .unwrap();
+        channel
+            .insert(keyframe(4.0, 1.0, Interpolation::Linear))
+            .unwrap();
+        let track = Track {
+            scale: channel,
+            ..Track::new("ball")
+        };
+        assert_eq!(track.scale_at(1.0), 0.15625);
+        assert_eq!(track.scale_at(2.0), 0.5);
+        assert_eq!(track.scale_at(3.0), 0.84375);
+    }
+
+    #[test]
+    fn keyframes_given_out_of_order_are_sorted() {
+        let mut channel = Channel::default();
+        for frame in [5.0, 1.0, 3.0] {
+            channel
+                .insert(keyframe(frame, frame, Interpolation::Linear))
+                .unwrap();
+        }
+        let frames: Vec<Float> = channel.keyframes().iter().map(|key| key.frame).collect();
+        assert_eq!(frames, vec![1.0, 3.0, 5.0]);
+        assert_eq!(
+            channel.insert(keyframe(3.0, 0.0, Interpolation::Linear)),
+            Err(AnimationError::DuplicateKeyframe(3.0))
+        );
+    }
+
+    #[test]
+    fn rotations_slerp_around_the_shortest_way() {
+        let mut track = Track::new("ball");
+        for (frame, degrees) in [(0.0, 0.0 as Float), (2.0, 90.0)] {
+            let rotation = UnitQuaternion::from_axis_angle(&Vec3::z_axis(), degrees.to_radians());
+            track
+                .rotation
+                .insert(keyframe(frame, rotation, Interpolation::Linear))
+                .unwrap();
+        }
+        let halfway = track.rotation_at(1.0);
+        let expected = UnitQuaternion::from_axis_angle(&Vec3::z_axis(), 45.0_f64.to_radians());
+        assert!(halfway.angle_to(&expected) < 1e-12);
+    }
+
+    #[test]
+    fn lines_parse_back_to_the_same_animation() {
+        let mut animation = Animation::default();
+        for line in [
+            "ball translate 0 1 2 3",
+            "ball translate 10 -1 0 0.5 smooth",
+            "ball rotate 0 0 0 1 90",
+            "crate scale 5 2 linear",
+        ] {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            animation.parse_line(&words).unwrap();
+        }
+        let mut parsed = Animation::default();
+        for line in animation.to_lines() {
+            let words: Vec<&str> = line.split_whitespace().skip(1).collect();
+            parsed.parse_line(&words).unwrap();
+        }
+        assert_eq!(parsed.tracks.len(), 2);
+        for (original, parsed) in animation.tracks.iter().zip(&parsed.tracks) {
+            for frame in [0.0, 2.5, 5.0, 10.0] {
+                assert!(
+                    (original.translation_at(frame) - parsed.translation_at(frame)).norm() < 1e-9
+                );
+                assert!(
+                    original
+                        .rotation_at(frame)
+                        .angle_to(&parsed.rotation_at(frame))
+                        < 1e-9
+                );
+                assert_eq!(original.scale_at(frame), parsed.scale_at(frame));
+            }
+        }
+        assert!(matches!(
+            animation.parse_line(&["ball", "wobble", "0", "1"]),
+            Err(AnimationError::Malformed(_))
+        ));
+        assert!(matches!(
+            animation.parse_line(&["ball", "translate", "0", "1", "2"]),
+            Err(AnimationError::Malformed(_))
+        ));
+    }
+
+    /// A glowing ball as an instance called `name`, resting at the origin, in the dark
+    fn glowing_ball(name: &str) -> World {
+        let light = DiffuseLight::new(SolidColor::new(Vec3::repeat(1.0)).into());
+        let ball = Sphere::new(Vec3::zeros(), 0.5, Arc::new(light.into())).into();
+        let prototype = Arc::new(Prototype::new(vec![ball]));
+        let instance =
+            Instance::new(prototype, Similarity3::identity()).with_object(ObjectId::register(name));
+        let mut world = World::build(vec![instance.into()]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::zeros(),
+            top: Vec3::zeros(),
+        };
+        world
+    }
+
+    #[test]
+    fn tracks_must_name_an_instance() {
+        let mut world = glowing_ball("test/animation/named");
+        let mut animation = Animation::default();
+        animation.track_mut("test/animation/missing");
+        assert_eq!(
+            animation.apply(&mut world, 0.0),
+            Err(AnimationError::UnknownObject(
+                "test/animation/missing".to_string()
+            ))
+        );
+        assert_eq!(Animation::default().apply(&mut world, 0.0), Ok(0));
+    }
+
+    #[test]
+    fn animated_balls_move_across_the_frame() {
+        let name = "test/animation/moving";
+        let mut world = glowing_ball(name);
+        let mut animation = Animation::default();
+        let track = animation.track_mut(name);
+        for (frame, x) in [(0.0, -2.0), (9.0, 2.0)] {
+            track
+                .translation
+                .insert(keyframe(
+                    frame,
+                    Vec3::new(x, 0.0, 0.0),
+                    Interpolation::Linear,
+                ))
+                .unwrap();
+        }
+        let size = 16;
+        let camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, 0.0, 10.0))
+            .with_look_at(Vec3::zeros())
+            .with_up(Vec3::y())
+            .with_vertical_fov(40.0)
+            .with_resolution(size, size)
+            .with_samples(4)
+            .with_max_depth(2)
+            .build()
+            .unwrap();
+
+        let centroids: Vec<Float> = (0..10)
+            .map(|frame| {
+                assert_eq!(animation.apply(&mut world, frame as Float), Ok(1));
+                let image = camera.render_image(&world);
+                let (sum, weight) = image.pixels.iter().enumerate().fold(
+                    (0.0, 0.0),
+                    |(sum, weight), (i, color)| {
+                        let x = (i % size) as Float + 0.5;
+                        (sum + x * color.x, weight + color.x)
+                    },
+                );
+                sum / weight
+            })
+            .collect();
+        assert!(
+            centroids.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            centroids
+        );
+        // Two units either side of the center, with the frame 3.64 units tall at that distance
+        let half_height = 10.0 * (20.0 as Float).to_radians().tan();
+        let expected = |x: Float| size as Float / 2.0 * (1.0 + x / half_height);
+        assert!(
+            (centroids[0] - expected(-2.0)).abs() < 0.75,
+            "{:?}",
+            centroids
+        );
+        assert!(
+            (centroids[9] - expected(2.0)).abs() < 0.75,
+            "{:?}",
+            centroids
+        );
+    }
+
+    #[test]
+    fn later_animations_replace_tracks_for_the_same_object() {
+        let mut scene = Animation::default();
+        scene
+            .parse_line(&["ball", "translate", "0", "1", "0", "0"])
+            .unwrap();
+        scene.parse_line(&["sail", "scale", "0", "2"]).unwrap();
+        let mut job = Animation::default();
+        job.parse_line(&["ball", "translate", "0", "0", "3", "0"])
+            .unwrap();
+        let played = job.over(&scene);
+        assert_eq!(played.tracks.len(), 2);
+        let track = |name: &str| played.tracks.iter().find(|track| track.object == name);
+        assert_eq!(
+            track("ball").unwrap().translation_at(0.0),
+            Vec3::new(0.0, 3.0, 0.0)
+        );
+        assert_eq!(track("sail").unwrap().scale_at(0.0), 2.0);
+        assert_eq!(Animation::default().over(&scene), scene);
+    }
+
+    #[test]
+    fn scene_files_animate_their_named_objects() {
+        let source = r#"(
+            camera: (center: (0, 0, 10), lookat: (0, 0, 0)),
+            materials: {"gray": Lambertian(texture: Solid((0.5, 0.5, 0.5)))},
+            objects: [
+                Sphere(material: "gray", center: (0, 0, 0), radius: 0.5, name: "test/scene_file/ball"),
+                Triangle(
+                    material: "gray",
+                    vertices: ((-6, -0.5, 0), (-4, -0.5, 0), (-5, 1, 0)),
+                    name: "test/scene_file/sail",
+                ),
+                Sphere(material: "gray", center: (0, 5, 0), radius: 0.5, name: "test/scene_file/still"),
+            ],
+            animation: [
+                Translate(object: "test/scene_file/ball", frame: 0, to: (0, 0, 0)),
+                Translate(object: "test/scene_file/ball", frame: 10, to: (4, 0, 0)),
+                Rotate(object: "test/scene_file/sail", frame: 0, axis: (0, 0, 1), degrees: 0),
+                Rotate(object: "test/scene_file/sail", frame: 10, axis: (0, 0, 1), degrees: 180),
+            ],
+        )"#;
+        let scene = crate::scene_file::SceneFile::parse(source, std::path::Path::new("")).unwrap();
+        assert_eq!(scene.animation.tracks.len(), 2);
+        let (_camera, shapes) = scene.build(&mut Default::default()).unwrap();
+        // Only what's animated is made an instance
+        let instances = shapes
+            .iter()
+            .filter(|shape| matches!(shape, Shape::Instance(_)))
+            .count();
+        assert_eq!(instances, 2);
+        let mut world = World::build(shapes);
+
+        let hit_from_above = |world: &World, x: Float, y: Float| {
+            let ray = crate::vec3::Ray::new(Vec3::new(x, y, 5.0).into(), -Vec3::z());
+            world
+                .hit(&ray, &(0.0..Float::INFINITY))
+                .map(|hit| hit.object.name().to_string())
+        };
+        let ball = Some("test/scene_file/ball".to_string());
+        let sail = Some("test/scene_file/sail".to_string());
+        // At rest before the first frame is applied, where the file put them
+        assert_eq!(hit_from_above(&world, 0.0, 0.0), ball);
+        assert_eq!(hit_from_above(&world, -5.0, 0.8), sail);
+
+        assert_eq!(scene.animation.apply(&mut world, 5.0), Ok(2));
+        assert_eq!(hit_from_above(&world, 0.0, 0.0), None);
+        assert_eq!(hit_from_above(&world, 2.0, 0.0), ball);
+        // Turned a quarter around its middle, so its tip points along -x
+        assert_eq!(hit_from_above(&world, -5.8, 0.0), sail);
+        assert_eq!(hit_from_above(&world, -5.0, 0.8), None);
+
+        assert_eq!(scene.animation.apply(&mut world, 10.0), Ok(2));
+        assert_eq!(hit_from_above(&world, 4.0, 0.0), ball);
+        assert_eq!(hit_from_above(&world, -5.0, -0.8), sail);
+        assert_eq!(
+            hit_from_above(&world, 0.0, 5.0),
+            Some("test/scene_file/still".to_string())
+        );
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Environment variable listing more directories to look for assets in, separated like `PATH`
+pub const ASSET_PATH_VAR: &str = "RT_ASSET_PATH";
+
+/// Where the project keeps its textures and meshes, relative to the working directory
+pub const PROJECT_ASSETS: &str = "src/assets";
+
+/// Assets compiled into the binary, by their paths under [`PROJECT_ASSETS`]. They're the last
+/// resort for images, so scenes using them render anywhere.
+pub const EMBEDDED_ASSETS: [(&str, &[u8]); 4] = [
+    (
+        "textures/earth.png",
+        include_bytes!("./assets/textures/earth.png"),
+    ),
+    (
+        "textures/mars.jpg",
+        include_bytes!("./assets/textures/mars.jpg"),
+    ),
+    (
+        "textures/moon_hires.jpg",
+        include_bytes!("./assets/textures/moon_hires.jpg"),
+    ),
+    (
+        "textures/saul.webp",
+        include_bytes!("./assets/textures/saul.webp"),
+    ),
+];
+
+/// The asset compiled into the binary as `name`, see [`EMBEDDED_ASSETS`]
+pub fn embedded_asset(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED_ASSETS
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, bytes)| *bytes)
+}
+
+/// A file an [`AssetResolver`] found
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundFile {
+    pub path: PathBuf,
+    /// The trailing part of the path it was found by under one of the search directories, or
+    /// `None` if it was where the path said
+    pub trailing: Option<PathBuf>,
+}
+
+/// Where an asset was found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Asset {
+    File(FoundFile),
+    /// Compiled into the binary under `name`, see [`EMBEDDED_ASSETS`]
+    Embedded {
+        name: &'static str,
+        bytes: &'static [u8],
+    },
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Asset::File(file) => write!(f, "{}", file.path.display()),
+            Asset::Embedded { name, .. } => write!(f, "{} (embedded)", name),
+        }
+    }
+}
+
+/// An asset that isn't anywhere an [`AssetResolver`] looked
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetError {
+    pub path: PathBuf,
+    /// Where it was looked for, in order
+    pub looked_in: Vec<String>,
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "can't find '{}' (looked in: {})",
+            self.path.display(),
+            self.looked_in.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+/// Finds the files scenes refer to on machines other than the one they were made on. An asset
+/// is looked for, in order:
+///
+/// 1. where its path says, which for paths in scene files and libraries is relative to the file
+///    they're written in,
+/// 2. under each search directory, which are the scene file's own directory, then the project's
+///    [`PROJECT_ASSETS`], then the directories in [`ASSET_PATH_VAR`], by the trailing parts of
+///    its path, longest first, so `/home/someone/rt/src/assets/meshes/car/scene.gltf` is found
+///    as `meshes/car/scene.gltf` under any of them,
+/// 3. among the [`EMBEDDED_ASSETS`] by the same trailing parts, except for files that loaders
+///    have to read from disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetResolver {
+    roots: Vec<PathBuf>,
+}
+
+impl AssetResolver {
+    /// Looks in `roots` in order, and nowhere else
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        AssetResolver { roots }
+    }
+
+    /// Looks in `directory`, usually the scene file's, then the project's assets, then the
+    /// directories in [`ASSET_PATH_VAR`]
+    pub fn from_env(directory: &Path) -> Self {
+        let mut roots = vec![directory.to_path_buf(), PathBuf::from(PROJECT_ASSETS)];
+        if let Some(paths) = std::env::var_os(ASSET_PATH_VAR) {
+            roots.extend(std::env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()));
+        }
+        AssetResolver::new(roots)
+    }
+
+    /// The search directories, in the order they're looked in
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Finds the asset at `path`, which may be one compiled into the binary
+    pub fn resolve(&self, path: &Path) -> Result<Asset, AssetError> {
+        match self.resolve_file(path) {
+            Ok(file) => Ok(Asset::File(file)),
+            Err(mut err) => {
+                let parts = trailing_parts(path);
+                let embedded = (0..parts.len()).find_map(|start| {
+                    let name = parts[start..].join("/");
+                    EMBEDDED_ASSETS
+                        .iter()
+                        .find(|(embedded, _)| *embedded == name)
+                });
+                match embedded {
+                    Some(&(name, bytes)) => Ok(Asset::Embedded { name, bytes }),
+                    None => {
+                        err.looked_in.push("embedded assets".to_string());
+                        Err(err)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds the file at `path` on disk, for loaders that can't read assets from memory
+    pub fn resolve_file(&self, path: &Path) -> Result<FoundFile, AssetError> {
+        if path.is_file() {
+            return Ok(FoundFile {
+                path: path.to_path_buf(),
+                trailing: None,
+            });
+        }
+        let parts = trailing_parts(path);
+        for start in 0..parts.len() {
+            let trailing: PathBuf = parts[start..].iter().collect();
+            for root in &self.roots {
+                let candidate = root.join(&trailing);
+                if candidate.is_file() {
+                    return Ok(FoundFile {
+                        path: candidate,
+                        trailing: Some(trailing),
+                    });
+                }
+            }
+        }
+        let display = |path: &Path| {
+            if path.as_os_str().is_empty() {
+                ".".to_string()
+            } else {
+                path.display().to_string()
+            }
+        };
+        Err(AssetError {
+            path: path.to_path_buf(),
+            looked_in: std::iter::once(display(path))
+                .chain(self.roots.iter().map(|root| display(root)))
+                .collect(),
+        })
+    }
+}
+
+/// The names making up `path`, without its root or any `.` or `..`
+fn trailing_parts(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Something a scene needs to render, with where it's referred to
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetReference {
+    /// The line or material that refers to it
+    pub location: String,
+    /// What kind of file it is, like "mesh" or "image"
+    pub kind: &'static str,
+    pub path: PathBuf,
+}
+
+/// Why a scene couldn't be bundled
+#[derive(Debug)]
+pub enum BundleError {
+    Missing(AssetError),
+    Io { path: PathBuf, error: io::Error },
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleError::Missing(err) => write!(f, "{}", err),
+            BundleError::Io { path, error } => {
+                write!(f, "failed to copy to {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BundleError {}
+
+/// Where `file`, referred to as `path`, goes in a bundle of a scene in `directory`: where it is
+/// relative to the scene if it's under the scene's directory, the trailing part of the path it
+/// was found by, or else the names in `path` after its last `..`, of which absolute paths only
+/// keep the file's name and the directory it's in to keep e.g. exports that are all called
+/// `scene.gltf` apart. The resolver finds all of these from the bundled scene by step 2.
+fn bundle_path(file: &FoundFile, path: &Path, directory: &Path) -> PathBuf {
+    if let Ok(inside) = file.path.strip_prefix(directory) {
+        if inside
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return inside.to_path_buf();
+        }
+    }
+    if let Some(trailing) = &file.trailing {
+        return trailing.clone();
+    }
+    let mut parts: Vec<_> = path
+        .components()
+        .rev()
+        .take_while(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    if path.is_absolute() {
+        parts.truncate(2);
+    }
+    parts.iter().rev().collect()
+}
+
+/// The files a glTF file refers to by URI, like its buffers and images, relative to it. Ones
+/// outside its directory are left out, since they'd land outside a bundle.
+fn gltf_dependencies(path: &Path) -> Vec<PathBuf> {
+    let Ok(gltf) = gltf::Gltf::open(path) else {
+        return Vec::new();
+    };
+    let buffers = gltf.buffers().filter_map(|buffer| match buffer.source() {
+        gltf::buffer::Source::Uri(uri) => Some(uri),
+        gltf::buffer::Source::Bin => None,
+    });
+    let images = gltf.images().filter_map(|image| match image.source() {
+        gltf::image::Source::Uri { uri, .. } => Some(uri),
+        gltf::image::Source::View { .. } => None,
+    });
+    buffers
+        .chain(images)
+        .filter(|uri| !uri.starts_with("data:") && !uri.contains("://"))
+        .map(|uri| PathBuf::from(percent_decode(uri)))
+        .filter(|path| {
+            path.components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        })
+        .collect()
+}
+
+/// Undoes the `%20`-style escapes in a URI
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Copies every file in `references`, found by `resolver`, into `out_dir` laid out so the scene
+/// in `directory` renders from there on any machine, along with the buffers and images of its
+/// glTF files. Embedded assets are left out, since every build has them. Returns where each
+/// file was copied to.
+pub fn bundle(
+    references: &[AssetReference],
+    resolver: &AssetResolver,
+    directory: &Path,
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>, BundleError> {
+    let copy = |from: &Path, to: PathBuf| -> Result<PathBuf, BundleError> {
+        let io_error = |error| BundleError::Io {
+            path: to.clone(),
+            error,
+        };
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::copy(from, &to).map_err(io_error)?;
+        Ok(to)
+    };
+    let mut copied: Vec<PathBuf> = Vec::new();
+    for reference in references {
+        let file = match resolver.resolve(&reference.path) {
+            Ok(Asset::File(file)) => file,
+            Ok(Asset::Embedded { .. }) => continue,
+            Err(err) => return Err(BundleError::Missing(err)),
+        };
+        let to = out_dir.join(bundle_path(&file, &reference.path, directory));
+        if copied.contains(&to) {
+            continue;
+        }
+        copied.push(copy(&file.path, to.clone())?);
+        if reference.kind == "gltf" {
+            let from_dir = file.path.parent().unwrap_or(Path::new(""));
+            let to_dir = to.parent().unwrap_or(out_dir);
+            for dependency in gltf_dependencies(&file.path) {
+                let from = from_dir.join(&dependency);
+                // Missing ones are the loader's to report, like when rendering
+                if !from.is_file() {
+                    continue;
+                }
+                copied.push(copy(&from, to_dir.join(&dependency))?);
+            }
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{scratch, write};
+
+    #[test]
+    fn search_directories_are_tried_in_order() {
+        let root = scratch("order");
+        let (first, second) = (root.join("first"), root.join("second"));
+        write(&first.join("c.obj"), "first");
+        write(&second.join("b/c.obj"), "second");
+        write(&first.join("shared/d.obj"), "first");
+        write(&second.join("shared/d.obj"), "second");
+        write(&root.join("direct/shared/d.obj"), "direct");
+        let resolver = AssetResolver::new(vec![first.clone(), second.clone()]);
+        let found = |path: &str| resolver.resolve_file(Path::new(path)).unwrap();
+
+        // The path itself wins when it's there
+        let direct = root.join("direct/shared/d.obj");
+        let file = found(direct.to_str().unwrap());
+        assert_eq!((file.path, file.trailing), (direct, None));
+        // Then the earlier directory, for the same trailing part
+        let file = found("/home/someone/shared/d.obj");
+        assert_eq!(file.path, first.join("shared/d.obj"));
+        assert_eq!(file.trailing, Some(PathBuf::from("shared/d.obj")));
+        // But a longer trailing part in a later directory beats a shorter one in an earlier one
+        assert_eq!(found("/elsewhere/b/c.obj").path, second.join("b/c.obj"));
+        assert_eq!(found("../../c.obj").path, first.join("c.obj"));
+
+        let from_env = AssetResolver::from_env(&root);
+        assert_eq!(
+            from_env.roots()[..2],
+            [root.clone(), PathBuf::from(PROJECT_ASSETS)]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn missing_assets_list_everywhere_they_were_looked_for() {
+        let resolver = AssetResolver::new(vec![PathBuf::new(), PathBuf::from("/srv/assets")]);
+        let err = resolver.resolve(Path::new("meshes/gone.obj")).unwrap_err();
+        assert_eq!(
+            err.looked_in,
+            ["meshes/gone.obj", ".", "/srv/assets", "embedded assets"]
+        );
+        assert_eq!(
+            err.to_string(),
+            "can't find 'meshes/gone.obj' (looked in: meshes/gone.obj, ., /srv/assets, \
+             embedded assets)"
+        );
+        // Loaders reading from disk don't look among the embedded assets
+        let err = resolver
+            .resolve_file(Path::new("textures/earth.png"))
+            .unwrap_err();
+        assert_eq!(err.looked_in.last().unwrap(), "/srv/assets");
+
+        // Embedded assets are found by the trailing part of any path
+        match resolver.resolve(Path::new("/nowhere/textures/earth.png")) {
+            Ok(Asset::Embedded { name, bytes }) => {
+                assert_eq!(name, "textures/earth.png");
+                assert_eq!(Some(bytes), embedded_asset("textures/earth.png"));
+            }
+            other => panic!("{:?}", other.map(|asset| asset.to_string())),
+        }
+    }
+
+    #[test]
+    fn bundled_assets_are_found_without_the_original_files() {
+        let root = scratch("bundle");
+        let scene = root.join("scene");
+        let library = root.join("library");
+        let loose = root.join("loose/rocks/rock.obj");
+        write(&scene.join("scene.rt"), "the scene");
+        write(&scene.join("textures/wood.png"), "wood");
+        write(&loose, "rock");
+        write(
+            &library.join("meshes/car/scene.gltf"),
+            r#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "uri": "car.bin", "byteLength": 4 }],
+                "images": [{ "uri": "tex/paint%20coat.png" }, { "uri": "../outside.png" }]
+            }"#,
+        );
+        write(&library.join("meshes/car/car.bin"), "car!");
+        write(&library.join("meshes/car/tex/paint coat.png"), "paint");
+        write(&library.join("meshes/outside.png"), "outside");
+
+        let reference = |kind, path: &Path| AssetReference {
+            location: "scene".to_string(),
+            kind,
+            path: path.to_path_buf(),
+        };
+        let references = [
+            reference("source", &scene.join("scene.rt")),
+            reference("image", Path::new("textures/wood.png")),
+            reference("gltf", Path::new("/home/someone/rt/meshes/car/scene.gltf")),
+            reference("mesh", &loose),
+            // Embedded, so every build has it already
+            reference("image", Path::new("/nowhere/textures/earth.png")),
+            // The same file twice is only copied once
+            reference("image", &scene.join("textures/wood.png")),
+        ];
+        let resolver = AssetResolver::new(vec![scene.clone(), library.clone()]);
+        let out = root.join("out");
+        let copied = bundle(&references, &resolver, &scene, &out).unwrap();
+        let expected = [
+            "scene.rt",
+            "textures/wood.png",
+            "meshes/car/scene.gltf",
+            "meshes/car/car.bin",
+            "meshes/car/tex/paint coat.png",
+            "rocks/rock.obj",
+        ];
+        assert_eq!(copied, expected.map(|path| out.join(path)));
+
+        // Found from the bundle alone, by the paths the scene was written with
+        for directory in ["scene", "library", "loose"] {
+            fs::remove_dir_all(root.join(directory)).unwrap();
+        }
+        let bundled = AssetResolver::new(vec![out.clone()]);
+        let contents = ["the scene", "wood", "", "rock", "", "wood"];
+        for (reference, contents) in references.iter().zip(contents) {
+            let path = match bundled.resolve(&reference.path).unwrap() {
+                Asset::File(file) => file.path,
+                Asset::Embedded { .. } => continue,
+            };
+            if !contents.is_empty() {
+                assert_eq!(fs::read_to_string(path).unwrap(), contents);
+            }
+        }
+        assert_eq!(
+            gltf_dependencies(&out.join("meshes/car/scene.gltf")),
+            [
+                PathBuf::from("car.bin"),
+                PathBuf::from("tex/paint coat.png")
+            ]
+        );
+        assert!(!out.join("meshes/outside.png").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
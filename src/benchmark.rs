@@ -0,0 +1,663 @@
+use crate::{
+    camera::{self, Camera, Float, Image},
+    hittable::{Shape, World},
+    material::{Lambertian, Material, Metal},
+    perf::{self, JsonObject, JsonValue, SessionHeader},
+    scenes,
+    texture::ImageTexture,
+    vec3::Vec3,
+};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Version of the results' layout, bumped whenever a field changes meaning so old baselines
+/// aren't compared against new results
+pub const SCHEMA_VERSION: u32 = 1;
+/// Every scene is rendered this small, so the whole pack runs in well under a minute and its
+/// references are a few kilobytes each
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 48;
+/// Seeds both the generated scenes and the cameras, so every run traces the same paths
+pub const SEED: u64 = 0x5EED;
+/// Times each measurement is taken, keeping the fastest, since single timings of renders this
+/// small vary by more than the noise threshold
+pub const ROUNDS: usize = 5;
+/// Samples per pixel of the render that throughput is measured on
+pub const THROUGHPUT_SAMPLES: usize = 32;
+/// Samples per pixel of the embedded references
+pub const REFERENCE_SAMPLES: usize = 4096;
+/// Samples per pixel after which a scene is given up on reaching [`TARGET_MSE`]
+pub const MAX_CONVERGENCE_SAMPLES: usize = 1024;
+/// Mean squared error against the reference, in gamma encoded values from 0 to 1, that counts
+/// as converged. It's an RMS error of about 8 levels out of 255.
+pub const TARGET_MSE: Float = 1e-3;
+/// How much worse than the baseline a metric has to get to be flagged, as a fraction of the
+/// baseline, unless `--noise` says otherwise
+pub const DEFAULT_NOISE: Float = 0.1;
+
+/// The scenes of the benchmark pack, each stressing a different part of the renderer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchScene {
+    /// The final scene of Ray Tracing in One Weekend: hundreds of small spheres of every material
+    SphereField,
+    /// A sphereflake of 66,430 mostly tiny spheres, built from instances
+    Sphereflake,
+    /// A textured heightfield of 32,768 triangles
+    Terrain,
+    /// A pile of glass spheres that paths refract through many times
+    GlassCluster,
+    /// The Cornell box with the bidirectional integrator, lit only by its area light
+    LitInterior,
+}
+
+impl BenchScene {
+    pub const ALL: [BenchScene; 5] = [
+        BenchScene::SphereField,
+        BenchScene::Sphereflake,
+        BenchScene::Terrain,
+        BenchScene::GlassCluster,
+        BenchScene::LitInterior,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchScene::SphereField => "sphere_field",
+            BenchScene::Sphereflake => "sphereflake",
+            BenchScene::Terrain => "terrain",
+            BenchScene::GlassCluster => "glass_cluster",
+            BenchScene::LitInterior => "lit_interior",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        BenchScene::ALL
+            .into_iter()
+            .find(|scene| scene.name() == name)
+    }
+
+    /// What the scene mostly spends its time on
+    pub fn workload(&self) -> &'static str {
+        match self {
+            BenchScene::SphereField => "mixed materials",
+            BenchScene::Sphereflake => "traversal",
+            BenchScene::Terrain => "triangles and textures",
+            BenchScene::GlassCluster => "deep refraction",
+            BenchScene::LitInterior => "area lights",
+        }
+    }
+
+    /// The scene's shapes and a camera looking at them at the benchmark's size, seeded
+    pub fn build(&self) -> (Camera, World) {
+        let (camera, world) = match self {
+            BenchScene::SphereField => {
+                let (shapes, surroundings) = scenes::rtiow_final(SEED);
+                (scenes::rtiow_camera(), surroundings.build(shapes))
+            }
+            BenchScene::Sphereflake => {
+                let camera = looking(Vec3::new(3.2, -3.6, 2.6), Vec3::new(0.0, 0.0, 1.0), 16);
+                (camera, World::build(sphereflake()))
+            }
+            BenchScene::Terrain => {
+                let camera = looking(Vec3::new(0.0, -11.0, 5.0), Vec3::new(0.0, 0.0, 0.5), 8);
+                (camera, World::build(scenes::terrain(20.0, 128, SEED)))
+            }
+            BenchScene::GlassCluster => {
+                let camera = looking(Vec3::new(0.0, -6.0, 2.5), Vec3::new(0.0, 0.0, 0.7), 32);
+                (camera, World::build(scenes::glass_cluster(40, SEED)))
+            }
+            BenchScene::LitInterior => {
+                let (shapes, surroundings) = scenes::cornell_box();
+                (scenes::cornell_box_camera(), surroundings.build(shapes))
+            }
+        };
+        let mut camera = camera
+            .with_resolution(WIDTH, HEIGHT)
+            .with_sampling(THROUGHPUT_SAMPLES, camera.max_depth());
+        camera.seed = Some(SEED);
+        (camera, world)
+    }
+
+    /// The scene rendered with [`REFERENCE_SAMPLES`] samples per pixel, as it was when the
+    /// binary was built
+    pub fn reference(&self) -> Result<Image, String> {
+        let bytes: &[u8] = match self {
+            BenchScene::SphereField => include_bytes!("../benches/references/sphere_field.png"),
+            BenchScene::Sphereflake => include_bytes!("../benches/references/sphereflake.png"),
+            BenchScene::Terrain => include_bytes!("../benches/references/terrain.png"),
+            BenchScene::GlassCluster => include_bytes!("../benches/references/glass_cluster.png"),
+            BenchScene::LitInterior => include_bytes!("../benches/references/lit_interior.png"),
+        };
+        let image = ImageTexture::load_image(bytes).map_err(|err| err.to_string())?;
+        if (image.width, image.height) != (WIDTH, HEIGHT) {
+            return Err(format!(
+                "the {} reference is {}x{}, not {}x{}",
+                self.name(),
+                image.width,
+                image.height,
+                WIDTH,
+                HEIGHT
+            ));
+        }
+        Ok(image)
+    }
+}
+
+/// A camera with Z up and no depth of field
+fn looking(center: Vec3, lookat: Vec3, max_depth: usize) -> Camera {
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH,
+        HEIGHT,
+        THROUGHPUT_SAMPLES,
+        max_depth,
+        40.0,
+        0.001..Float::MAX,
+    )
+}
+
+/// A depth 5 sphereflake with a mirror root on a grey floor
+fn sphereflake() -> Vec<Shape> {
+    let mut shapes = scenes::sphereflake(5, 1.0, |level| {
+        let material: Material = match level {
+            0 => Metal::new_solid(Vec3::new(0.8, 0.8, 0.85), None).into(),
+            level => {
+                let t = level as Float / 5.0;
+                Lambertian::new_rgb_solid(0.8 - 0.5 * t, 0.3 + 0.3 * t, 0.2 + 0.6 * t).into()
+            }
+        };
+        Arc::new(material)
+    });
+    let floor = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    shapes.extend(scenes::generate_ground_plane(20.0, 20.0, 0.0, floor, true));
+    shapes
+}
+
+/// Sums samples `samples` of every pixel, a row at a time from the top
+fn render_sums(camera: &Camera, world: &World, samples: Range<usize>) -> Vec<Vec3> {
+    (0..camera.image_width * camera.image_height)
+        .into_par_iter()
+        .map(|k| {
+            let (x, y) = (k % camera.image_width, k / camera.image_width);
+            samples
+                .clone()
+                .map(|i| {
+                    camera.watchdog.begin_sample(x, y, i);
+                    let color = camera.render_sample(world, x, y, i);
+                    camera.watchdog.end_sample();
+                    color
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Mean squared error of the mean of `sums` over `samples` samples against `reference`, both
+/// rounded to bytes with the camera's gamma as they would be written out
+fn mse(camera: &Camera, sums: &[Vec3], samples: usize, reference: &Image) -> Float {
+    let error: Float = sums
+        .iter()
+        .zip(&reference.pixels)
+        .map(|(sum, expected)| {
+            let encoded = camera::rgb8(&(sum / samples as Float), camera.gamma);
+            let encoded = Vec3::from_iterator(encoded.map(|c| c as Float / 255.0));
+            (encoded - expected).norm_squared()
+        })
+        .sum();
+    error / (3 * sums.len()) as Float
+}
+
+/// How long a scene took to get within [`TARGET_MSE`] of its reference
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Convergence {
+    /// Samples per pixel it took, or `None` if [`MAX_CONVERGENCE_SAMPLES`] weren't enough
+    samples: Option<usize>,
+    seconds: Option<f64>,
+    /// The error after the last pass
+    mse: Float,
+}
+
+/// Renders passes that double the samples so far until the image is within [`TARGET_MSE`] of
+/// `reference`, timing only the rendering
+fn converge(camera: &Camera, world: &World, reference: &Image) -> Convergence {
+    let mut sums = vec![Vec3::zeros(); camera.image_width * camera.image_height];
+    let mut rendered = 0;
+    let mut elapsed = Duration::ZERO;
+    loop {
+        let pass = rendered.max(1).min(MAX_CONVERGENCE_SAMPLES - rendered);
+        let start = Instant::now();
+        let pass_sums = render_sums(camera, world, rendered..rendered + pass);
+        elapsed += start.elapsed();
+        for (sum, pass_sum) in sums.iter_mut().zip(pass_sums) {
+            *sum += pass_sum;
+        }
+        rendered += pass;
+        let mse = mse(camera, &sums, rendered, reference);
+        if mse <= TARGET_MSE {
+            return Convergence {
+                samples: Some(rendered),
+                seconds: Some(elapsed.as_secs_f64()),
+                mse,
+            };
+        }
+        if rendered >= MAX_CONVERGENCE_SAMPLES {
+            return Convergence {
+                samples: None,
+                seconds: None,
+                mse,
+            };
+        }
+    }
+}
+
+/// Renders every scene's reference, with [`REFERENCE_SAMPLES`] samples per pixel, into `dir`
+/// as `<name>.png`. The references are embedded in the binary, so it has to be rebuilt for
+/// new ones to count.
+pub fn write_references(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for scene in BenchScene::ALL {
+        println!("Rendering the {} reference", scene.name());
+        let (camera, world) = scene.build();
+        let sums = render_sums(&camera, &world, 0..REFERENCE_SAMPLES);
+        let pixels = sums.iter().map(|sum| sum / REFERENCE_SAMPLES as Float);
+        let image = camera.image_from_pixels(pixels.collect());
+        Camera::write_png(image, &dir.join(format!("{}.png", scene.name())))?;
+    }
+    Ok(())
+}
+
+/// Asks the kernel to start tracking the peak resident set size afresh, from what's resident
+/// now, so the next reading only covers what comes after. Only Linux can.
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+/// A line of `/proc/self/status` in bytes, on Linux
+fn memory_status(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// What one scene measured
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneResult {
+    pub scene: String,
+    pub workload: String,
+    pub shapes: usize,
+    /// Generating the scene and building its BVH
+    pub build_seconds: f64,
+    /// Rendering [`THROUGHPUT_SAMPLES`] samples per pixel
+    pub render_seconds: f64,
+    /// Rays traced per second of that render, in millions, counting bounces and shadow rays.
+    /// `None` for integrators that don't report their bounces.
+    pub mrays_per_second: Option<f64>,
+    /// Camera rays per second of that render, in millions, which every integrator has
+    pub camera_mrays_per_second: f64,
+    /// Samples per pixel it took to get within [`TARGET_MSE`] of the reference
+    pub samples_to_target: Option<usize>,
+    pub seconds_to_target: Option<f64>,
+    pub final_mse: f64,
+    /// The most memory resident while the scene ran, above what was resident before it, so
+    /// it doesn't depend on which scenes ran first. Only measured on Linux.
+    pub peak_rss_mb: Option<f64>,
+}
+
+impl SceneResult {
+    pub fn to_json(&self) -> String {
+        let mut json = JsonObject::new("bench_scene");
+        json.string("scene", &self.scene);
+        json.string("workload", &self.workload);
+        json.number("shapes", self.shapes as f64);
+        json.number("build_seconds", self.build_seconds);
+        json.number("render_seconds", self.render_seconds);
+        json.number(
+            "mrays_per_second",
+            self.mrays_per_second.unwrap_or(f64::NAN),
+        );
+        json.number("camera_mrays_per_second", self.camera_mrays_per_second);
+        json.number("target_mse", TARGET_MSE);
+        let samples_to_target = self.samples_to_target.map_or(f64::NAN, |s| s as f64);
+        json.number("samples_to_target", samples_to_target);
+        json.number(
+            "seconds_to_target",
+            self.seconds_to_target.unwrap_or(f64::NAN),
+        );
+        json.number("final_mse", self.final_mse);
+        json.number("peak_rss_mb", self.peak_rss_mb.unwrap_or(f64::NAN));
+        json.finish()
+    }
+
+    /// Reads a result back from the fields of a `bench_scene` line
+    pub fn from_fields(fields: &HashMap<String, JsonValue>) -> Option<Self> {
+        let number = |key: &str| fields.get(key).and_then(JsonValue::as_f64);
+        let string = |key: &str| {
+            fields
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+        };
+        Some(SceneResult {
+            scene: string("scene")?,
+            workload: string("workload").unwrap_or_default(),
+            shapes: number("shapes").unwrap_or(0.0) as usize,
+            build_seconds: number("build_seconds")?,
+            render_seconds: number("render_seconds")?,
+            mrays_per_second: number("mrays_per_second"),
+            camera_mrays_per_second: number("camera_mrays_per_second")?,
+            samples_to_target: number("samples_to_target").map(|s| s as usize),
+            seconds_to_target: number("seconds_to_target"),
+            final_mse: number("final_mse")?,
+            peak_rss_mb: number("peak_rss_mb"),
+        })
+    }
+}
+
+/// Runs one scene: builds it, renders it to time throughput, then renders it until it matches
+/// its reference. Each step runs [`ROUNDS`] times, keeping the fastest.
+pub fn run_scene(scene: BenchScene) -> Result<SceneResult, String> {
+    let reference = scene.reference()?;
+    let resident_before = memory_status("VmRSS:");
+    reset_peak_rss();
+    let fastest = |seconds: &mut f64, start: Instant| {
+        *seconds = seconds.min(start.elapsed().as_secs_f64().max(1e-9));
+    };
+
+    let mut build_seconds = f64::INFINITY;
+    let mut built = None;
+    for _ in 0..ROUNDS {
+        // Drop the last round's scene first, so it isn't resident alongside this one's
+        drop(built.take());
+        let start = Instant::now();
+        built = Some(scene.build());
+        fastest(&mut build_seconds, start);
+    }
+    let (camera, world) = built.expect("ROUNDS is at least 1");
+
+    let mut render_seconds = f64::INFINITY;
+    let mut traced = None;
+    for _ in 0..ROUNDS {
+        let traced_before = camera.watchdog.path_stats().traced_rays;
+        let start = Instant::now();
+        render_sums(&camera, &world, 0..THROUGHPUT_SAMPLES);
+        fastest(&mut render_seconds, start);
+        traced = perf::traced_since(&camera.watchdog, traced_before);
+    }
+    let camera_rays = (WIDTH * HEIGHT * THROUGHPUT_SAMPLES) as f64;
+
+    // Seeded, so every round takes the same samples and only the time differs
+    let mut convergence = converge(&camera, &world, &reference);
+    for _ in 1..ROUNDS {
+        let round = converge(&camera, &world, &reference);
+        convergence.seconds = convergence
+            .seconds
+            .zip(round.seconds)
+            .map(|(a, b)| a.min(b));
+    }
+
+    let peak_rss = memory_status("VmHWM:")
+        .zip(resident_before)
+        .map(|(peak, before)| peak.saturating_sub(before));
+    Ok(SceneResult {
+        scene: scene.name().to_string(),
+        workload: scene.workload().to_string(),
+        shapes: world.shapes.len(),
+        build_seconds,
+        render_seconds,
+        mrays_per_second: traced.map(|traced| traced as f64 / render_seconds / 1e6),
+        camera_mrays_per_second: camera_rays / render_seconds / 1e6,
+        samples_to_target: convergence.samples,
+        seconds_to_target: convergence.seconds,
+        final_mse: convergence.mse,
+        peak_rss_mb: peak_rss.map(|bytes| bytes as f64 / (1024.0 * 1024.0)),
+    })
+}
+
+/// A whole run of the benchmark: what it ran on, then every scene's results
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkRun {
+    pub schema: u32,
+    pub version: String,
+    pub git_hash: Option<String>,
+    pub threads: usize,
+    pub cores: usize,
+    pub started: u64,
+    pub scenes: Vec<SceneResult>,
+}
+
+impl BenchmarkRun {
+    /// Runs `scenes` in order, printing each one's name as it starts
+    pub fn run(scenes: &[BenchScene]) -> Result<Self, String> {
+        let here = SessionHeader::here("benchmark");
+        let mut results = Vec::with_capacity(scenes.len());
+        for scene in scenes {
+            println!("Running {} ({})", scene.name(), scene.workload());
+            results.push(run_scene(*scene)?);
+        }
+        Ok(BenchmarkRun {
+            schema: SCHEMA_VERSION,
+            version: here.version,
+            git_hash: here.git_hash,
+            threads: here.threads,
+            cores: here.cores,
+            started: here.started,
+            scenes: results,
+        })
+    }
+
+    /// JSON Lines: a `benchmark` header, then a `bench_scene` line per scene
+    pub fn to_jsonl(&self) -> String {
+        let mut header = JsonObject::new("benchmark");
+        header.number("schema", self.schema as f64);
+        header.string("version", &self.version);
+        if let Some(git_hash) = &self.git_hash {
+            header.string("git_hash", git_hash);
+        }
+        header.number("threads", self.threads as f64);
+        header.number("cores", self.cores as f64);
+        header.number("started", self.started as f64);
+        header.number("width", WIDTH as f64);
+        header.number("height", HEIGHT as f64);
+        header.number("seed", SEED as f64);
+        let mut lines = vec![header.finish()];
+        lines.extend(self.scenes.iter().map(SceneResult::to_json));
+        lines.join("\n") + "\n"
+    }
+
+    /// Reads back what [`BenchmarkRun::to_jsonl`] wrote
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .and_then(perf::parse_object)
+            .filter(|fields| fields.get("type").and_then(JsonValue::as_str) == Some("benchmark"))
+            .ok_or("doesn't start with a benchmark header")?;
+        let number = |key: &str| header.get(key).and_then(JsonValue::as_f64);
+        let schema = number("schema").unwrap_or(0.0) as u32;
+        if schema != SCHEMA_VERSION {
+            return Err(format!(
+                "has schema {}, but this build writes schema {}",
+                schema, SCHEMA_VERSION
+            ));
+        }
+        let scenes = lines
+            .enumerate()
+            .map(|(i, line)| {
+                perf::parse_object(line)
+                    .as_ref()
+                    .and_then(SceneResult::from_fields)
+                    .ok_or(format!("line {} isn't a scene's results", i + 2))
+            })
+            .collect::<Result<_, _>>()?;
+        let text = |key: &str| {
+            header
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+        };
+        Ok(BenchmarkRun {
+            schema,
+            version: text("version").unwrap_or_default(),
+            git_hash: text("git_hash"),
+            threads: number("threads").unwrap_or(0.0) as usize,
+            cores: number("cores").unwrap_or(0.0) as usize,
+            started: number("started").unwrap_or(0.0) as u64,
+            scenes,
+        })
+    }
+}
+
+impl fmt::Display for BenchmarkRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: Option<f64>, precision: usize| {
+            value.map_or("-".to_string(), |value| format!("{:.*}", precision, value))
+        };
+        write!(
+            f,
+            "{:<14}{:>10}{:>10}{:>10}{:>10}{:>12}{:>10}",
+            "scene", "build s", "Mrays/s", "cam Mr/s", "spp", "to target s", "RSS MB"
+        )?;
+        for scene in &self.scenes {
+            write!(
+                f,
+                "\n{:<14}{:>10.3}{:>10}{:>10.3}{:>10}{:>12}{:>10}",
+                scene.scene,
+                scene.build_seconds,
+                optional(scene.mrays_per_second, 3),
+                scene.camera_mrays_per_second,
+                optional(scene.samples_to_target.map(|s| s as f64), 0),
+                optional(scene.seconds_to_target, 3),
+                optional(scene.peak_rss_mb, 1),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A metric compared between runs, and which way is better
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    MraysPerSecond,
+    CameraMraysPerSecond,
+    SecondsToTarget,
+    BuildSeconds,
+    PeakRss,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 5] = [
+        Metric::MraysPerSecond,
+        Metric::CameraMraysPerSecond,
+        Metric::SecondsToTarget,
+        Metric::BuildSeconds,
+        Metric::PeakRss,
+    ];
+
+    /// The metric's field in the results
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::MraysPerSecond => "mrays_per_second",
+            Metric::CameraMraysPerSecond => "camera_mrays_per_second",
+            Metric::SecondsToTarget => "seconds_to_target",
+            Metric::BuildSeconds => "build_seconds",
+            Metric::PeakRss => "peak_rss_mb",
+        }
+    }
+
+    pub fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::MraysPerSecond | Metric::CameraMraysPerSecond)
+    }
+
+    pub fn value(&self, result: &SceneResult) -> Option<f64> {
+        match self {
+            Metric::MraysPerSecond => result.mrays_per_second,
+            Metric::CameraMraysPerSecond => Some(result.camera_mrays_per_second),
+            Metric::SecondsToTarget => result.seconds_to_target,
+            Metric::BuildSeconds => Some(result.build_seconds),
+            Metric::PeakRss => result.peak_rss_mb,
+        }
+    }
+}
+
+/// How one metric of one scene changed from the baseline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub scene: String,
+    pub metric: Metric,
+    pub baseline: Option<f64>,
+    pub current: Option<f64>,
+    /// How much worse it got as a fraction of the baseline, negative when it got better.
+    /// Reaching the target before but not now is infinitely worse.
+    pub worse_by: Option<f64>,
+    pub regressed: bool,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.4}", v));
+        write!(
+            f,
+            "{:<14}{:<25}{:>12}{:>12}",
+            self.scene,
+            self.metric.name(),
+            value(self.baseline),
+            value(self.current)
+        )?;
+        match self.worse_by {
+            Some(worse_by) if worse_by < 0.0 => write!(f, "{:>7.1}% better", -worse_by * 100.0)?,
+            Some(worse_by) => write!(f, "{:>8.1}% worse", worse_by * 100.0)?,
+            None => {}
+        }
+        if self.regressed {
+            write!(f, "  REGRESSION")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares every metric of the scenes both runs have, flagging those more than `noise` worse
+/// than the baseline, as a fraction of it
+pub fn compare(baseline: &BenchmarkRun, current: &BenchmarkRun, noise: Float) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for result in &current.scenes {
+        let Some(before) = baseline.scenes.iter().find(|b| b.scene == result.scene) else {
+            continue;
+        };
+        for metric in Metric::ALL {
+            let (old, new) = (metric.value(before), metric.value(result));
+            let worse_by = match (old, new) {
+                (Some(old), Some(new)) if old > 0.0 => {
+                    let change = (new - old) / old;
+                    Some(if metric.higher_is_better() {
+                        -change
+                    } else {
+                        change
+                    })
+                }
+                // Converged before but not any more
+                (Some(_), None) if metric == Metric::SecondsToTarget => Some(f64::INFINITY),
+                _ => None,
+            };
+            changes.push(Change {
+                scene: result.scene.clone(),
+                metric,
+                baseline: old,
+                current: new,
+                worse_by,
+                regressed: worse_by.is_some_and(|worse_by| worse_by > noise),
+            });
+        }
+    }
+    changes
+}
@@ -0,0 +1,642 @@
+use crate::{
+    camera::{power_heuristic, Float},
+    hittable::{Hit, World},
+    intersection::Intersection,
+    lights::{AreaLights, LightRef},
+    material::Scatter,
+    rng::SampleRng,
+    shading::{PathState, ShadingContext},
+    vec3::{Point3, Ray, Vec3, Vec3Ext},
+};
+use std::f64::consts::PI;
+
+/// How far a hit may be from an area light's surface and still count as on it, as a fraction of
+/// the scene's size
+const ON_LIGHT_RATIO: Float = 1e-6;
+
+/// How light scatters at a path vertex, which decides whether paths can be connected through it
+#[derive(Debug, Clone, Copy)]
+enum VertexKind {
+    /// Where camera paths start. Never connected to, since every sample belongs to one pixel.
+    Camera,
+    /// On an area light, giving off `radiance` toward the side the vertex's normal points to.
+    /// `light` is `None` for emissive surfaces that aren't sampled, which only camera paths find.
+    Light {
+        radiance: Vec3,
+        light: Option<LightRef>,
+    },
+    /// Scatters like a Lambertian with this albedo, so paths can be connected through it
+    Diffuse { albedo: Vec3 },
+    /// Can only be scattered off by sampling it, like mirrors and clear glass. Rough metal and
+    /// frosted glass are treated this way too, see [`radiance`].
+    Specular,
+}
+
+/// One point of a path traced from the camera or from a light
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    point: Point3,
+    /// Unit normal facing the vertex before this one on its path, or for lights the side they
+    /// give off light from
+    normal: Vec3,
+    /// Everything the path picked up on its way here, divided by the probability densities it
+    /// was sampled with
+    throughput: Vec3,
+}
+
+impl Vertex {
+    fn is_connectible(&self) -> bool {
+        matches!(self.kind, VertexKind::Diffuse { .. })
+    }
+
+    /// Probability density of a path sampling `to` by scattering off this vertex, or leaving it if
+    /// it's a light, per unit area at `to`. Zero for specular vertices, whose densities are
+    /// deltas that cancel out between every strategy that can sample them.
+    fn density_toward(&self, to: &Vertex) -> Float {
+        let offset = to.point - self.point;
+        let distance_squared = offset.norm_squared();
+        if distance_squared <= 0.0 {
+            return 0.0;
+        }
+        let direction = offset / distance_squared.sqrt();
+        let per_solid_angle = match self.kind {
+            VertexKind::Diffuse { .. } => self.normal.dot(&direction).abs() / PI,
+            VertexKind::Light { .. } => self.normal.dot(&direction).max(0.0) / PI,
+            VertexKind::Camera | VertexKind::Specular => return 0.0,
+        };
+        per_solid_angle * to.normal.dot(&direction).abs() / distance_squared
+    }
+}
+
+/// Estimates the light arriving along the camera ray `ray`, which hit `hit`, by tracing a path
+/// from the camera and one from a light and connecting every vertex of one to every vertex of
+/// the other. Each of the resulting paths is weighted against all the other ways the same path
+/// could have been sampled with multiple importance sampling (Veach's power heuristic), so
+/// whichever strategy finds a kind of path most easily dominates it.
+///
+/// Only diffuse vertices are connected; paths through mirrors and glass are only found by
+/// following them. Light paths start from area lights, not the sky, so the sky is found by
+/// escaping and sampled from diffuse vertices like in the path tracer. Paths are as long as the
+/// path tracer's with the same `max_depth`, so both converge to the same image. Random numbers
+/// come from `rng`.
+///
+/// Rough metal and frosted glass aren't connected through either, and their densities are taken
+/// to cancel out like a mirror's when weighting. The weights are worked out the same way for
+/// every strategy that finds a path, so they still add up to one and the estimate stays
+/// unbiased, but strategies on either side of such a vertex aren't weighted as well as they
+/// could be and those paths converge more slowly than they would with the materials' own
+/// densities.
+pub fn radiance(
+    world: &World,
+    ray: &Ray,
+    hit: Option<Intersection>,
+    max_depth: usize,
+    t_max: Float,
+    rng: &mut SampleRng,
+) -> Vec3 {
+    let lights = world.area_lights();
+    let mut color = Vec3::zeros();
+    let camera = camera_subpath(world, ray, hit, max_depth, t_max, &mut color, rng);
+    let light = light_subpath(world, lights, max_depth, t_max, rng);
+
+    // The camera path found a light on its own
+    if let Some(last) = camera.last() {
+        if let VertexKind::Light { radiance, .. } = last.kind {
+            color += last.throughput.component_mul(&radiance) * mis_weight(&camera, lights, 0);
+        }
+    }
+    // A path with `s` vertices from the light and `t` from the camera has `s + t - 1` segments,
+    // which can be one more than the path tracer's bounces
+    for t in 2..=camera.len() {
+        for s in 1..=light.len().min((max_depth + 2).saturating_sub(t)) {
+            color += connect(world, lights, &camera[..t], &light[..s]);
+        }
+    }
+    color
+}
+
+/// Follows a path from the camera ray `ray` for up to `max_depth` bounces, stopping at the first
+/// light it hits. Light paths never start from the sky, so this adds the sky it escapes to and
+/// samples from diffuse vertices to `color` itself, weighted against each other.
+fn camera_subpath(
+    world: &World,
+    ray: &Ray,
+    hit: Option<Intersection>,
+    max_depth: usize,
+    t_max: Float,
+    color: &mut Vec3,
+    rng: &mut SampleRng,
+) -> Vec<Vertex> {
+    let range = world.numeric.min_hit_distance..t_max;
+    let mut vertices = vec![Vertex {
+        kind: VertexKind::Camera,
+        point: ray.origin.coords,
+        normal: ray.direction.normalize(),
+        throughput: Vec3::ONE,
+    }];
+    let mut ray = Ray::new(ray.origin, ray.direction);
+    let mut hit = hit;
+    let mut throughput = Vec3::ONE;
+    loop {
+        let previous = vertices[vertices.len() - 1];
+        let Some(intersection) = hit else {
+            let direction = ray.direction.normalize();
+            let weight = match previous.kind {
+                VertexKind::Diffuse { .. } => {
+                    let bounce_pdf = previous.normal.dot(&direction).max(0.0) / PI;
+                    power_heuristic(bounce_pdf, world.pdf_sky(&direction))
+                }
+                _ => 1.0,
+            };
+            *color += throughput.component_mul(&world.sky_color_toward(&direction)) * weight;
+            break;
+        };
+        let depth = vertices.len() - 1;
+        let material = intersection.material;
+        if material.is_emissive() {
+            let tolerance = world.numeric.scene_scale * ON_LIGHT_RATIO;
+            let light = world.area_lights().light_at(
+                &world.shapes,
+                &intersection.point,
+                material,
+                tolerance,
+            );
+            vertices.push(Vertex {
+                kind: VertexKind::Light {
+                    radiance: material.emitted(&intersection),
+                    light,
+                },
+                point: intersection.point,
+                normal: intersection.normal,
+                throughput,
+            });
+            break;
+        }
+        let path = PathState::new(depth, throughput);
+        let mut context = ShadingContext::new(&ray, &intersection, rng).with_path(path);
+        let Some((attenuation, scattered)) = material.scatter(&mut context) else {
+            break;
+        };
+        let kind = if material.is_diffuse() {
+            VertexKind::Diffuse {
+                albedo: attenuation,
+            }
+        } else {
+            VertexKind::Specular
+        };
+        let vertex = Vertex {
+            kind,
+            point: intersection.point,
+            normal: intersection.normal,
+            throughput,
+        };
+        vertices.push(vertex);
+        let bounces = depth < max_depth;
+        if let VertexKind::Diffuse { albedo } = kind {
+            let sky = sky_light(world, &vertex, bounces, t_max, rng);
+            *color += throughput.component_mul(&albedo).component_mul(&sky);
+        }
+        if !bounces {
+            break;
+        }
+        throughput.component_mul_assign(&attenuation);
+        let origin = world.numeric.offset_ray_origin(
+            &intersection.point,
+            &intersection.normal,
+            &scattered.direction,
+        );
+        ray = Ray::new(origin.into(), scattered.direction);
+        hit = world.hit(&ray, &range);
+    }
+    vertices
+}
+
+/// Estimates the light reaching the diffuse `vertex` straight from the sky by sampling it where
+/// it's brightest, weighted against the path escaping the same way if it `also_bounces`.
+/// Multiply by the vertex's throughput and albedo.
+fn sky_light(
+    world: &World,
+    vertex: &Vertex,
+    also_bounces: bool,
+    t_max: Float,
+    rng: &mut SampleRng,
+) -> Vec3 {
+    let sample = world.sample_sky_importance(rng);
+    let cos_theta = sample.direction.dot(&vertex.normal);
+    if cos_theta <= 0.0 || sample.pdf <= 0.0 {
+        return Vec3::zeros();
+    }
+    let origin = world
+        .numeric
+        .offset_ray_origin(&vertex.point, &vertex.normal, &sample.direction);
+    let shadow_ray = Ray::new(origin.into(), sample.direction);
+    if world
+        .hit(&shadow_ray, &(world.numeric.min_hit_distance..t_max))
+        .is_some()
+    {
+        return Vec3::zeros();
+    }
+    let bounce_pdf = cos_theta / PI;
+    let weight = if also_bounces {
+        power_heuristic(sample.pdf, bounce_pdf)
+    } else {
+        1.0
+    };
+    // The Lambertian BRDF times the cosine is the albedo times `bounce_pdf`
+    sample.radiance * (bounce_pdf * weight / sample.pdf)
+}
+
+/// Follows a path from a point picked on an area light, with up to `max_vertices` vertices
+/// including the one on the light. Empty if there are no lights.
+fn light_subpath(
+    world: &World,
+    lights: &AreaLights,
+    max_vertices: usize,
+    t_max: Float,
+    rng: &mut SampleRng,
+) -> Vec<Vertex> {
+    let mut vertices = Vec::new();
+    if max_vertices == 0 {
+        return vertices;
+    }
+    let Some(sample) = lights.sample(&world.shapes, rng) else {
+        return vertices;
+    };
+    if sample.pdf <= 0.0 {
+        return vertices;
+    }
+    vertices.push(Vertex {
+        kind: VertexKind::Light {
+            radiance: sample.radiance,
+            light: Some(sample.light),
+        },
+        point: sample.point,
+        normal: sample.normal,
+        throughput: Vec3::repeat(1.0 / sample.pdf),
+    });
+
+    // Leaves in a cosine-weighted direction, whose density cancels the cosine out
+    let mut direction = sample.normal + Vec3::random_unit(rng);
+    if direction.near_zero() {
+        direction = sample.normal;
+    }
+    let mut throughput = sample.radiance * (PI / sample.pdf);
+    let origin = world
+        .numeric
+        .offset_ray_origin(&sample.point, &sample.normal, &direction);
+    let mut ray = Ray::new(origin.into(), direction);
+    let range = world.numeric.min_hit_distance..t_max;
+    while vertices.len() < max_vertices && throughput != Vec3::zeros() {
+        let Some(hit) = world.hit(&ray, &range) else {
+            break;
+        };
+        // Lights absorb what hits them, like they do for camera paths
+        if hit.material.is_emissive() {
+            break;
+        }
+        // Light paths carry importance from the light rather than toward the camera, so their
+        // throughput isn't the camera path's kind and is left out
+        let path = PathState::new(vertices.len() - 1, Vec3::ONE);
+        let mut context = ShadingContext::new(&ray, &hit, rng).with_path(path);
+        let Some((attenuation, scattered)) = hit.material.scatter(&mut context) else {
+            break;
+        };
+        let kind = if hit.material.is_diffuse() {
+            VertexKind::Diffuse {
+                albedo: attenuation,
+            }
+        } else {
+            VertexKind::Specular
+        };
+        vertices.push(Vertex {
+            kind,
+            point: hit.point,
+            normal: hit.normal,
+            throughput,
+        });
+        throughput.component_mul_assign(&attenuation);
+        let origin = world
+            .numeric
+            .offset_ray_origin(&hit.point, &hit.normal, &scattered.direction);
+        ray = Ray::new(origin.into(), scattered.direction);
+    }
+    vertices
+}
+
+/// Whether nothing is in the way between two vertices
+fn visible(world: &World, from: &Vertex, to: &Vertex) -> bool {
+    let direction = to.point - from.point;
+    let start = world
+        .numeric
+        .offset_ray_origin(&from.point, &from.normal, &direction);
+    let end = world
+        .numeric
+        .offset_ray_origin(&to.point, &to.normal, &-direction);
+    let offset = end - start;
+    let distance = offset.norm();
+    if distance <= world.numeric.min_hit_distance {
+        return true;
+    }
+    let ray = Ray::new(start.into(), offset / distance);
+    world
+        .hit(&ray, &(world.numeric.min_hit_distance..distance))
+        .is_none()
+}
+
+/// Returns the light carried by the path made by joining the last vertex of `camera` to the last
+/// vertex of `light`, weighted against the other strategies
+fn connect(world: &World, lights: &AreaLights, camera: &[Vertex], light: &[Vertex]) -> Vec3 {
+    let (eye, source) = (&camera[camera.len() - 1], &light[light.len() - 1]);
+    let offset = source.point - eye.point;
+    let distance_squared = offset.norm_squared();
+    if distance_squared <= 0.0 {
+        return Vec3::zeros();
+    }
+    let direction = offset / distance_squared.sqrt();
+    // Both ends only scatter toward the side they were reached from
+    let eye_cos = eye.normal.dot(&direction);
+    let source_cos = -source.normal.dot(&direction);
+    if eye_cos <= 0.0 || source_cos <= 0.0 {
+        return Vec3::zeros();
+    }
+    let VertexKind::Diffuse { albedo } = eye.kind else {
+        return Vec3::zeros();
+    };
+    let eye_scattering = albedo / PI;
+    let source_scattering = match source.kind {
+        VertexKind::Light { radiance, .. } => radiance,
+        VertexKind::Diffuse { albedo } => albedo / PI,
+        VertexKind::Camera | VertexKind::Specular => return Vec3::zeros(),
+    };
+    let geometry = eye_cos * source_cos / distance_squared;
+    let contribution = eye
+        .throughput
+        .component_mul(&eye_scattering)
+        .component_mul(&source_scattering)
+        .component_mul(&source.throughput)
+        * geometry;
+    if contribution == Vec3::zeros() || !visible(world, eye, source) {
+        return Vec3::zeros();
+    }
+    let path: Vec<Vertex> = camera.iter().chain(light.iter().rev()).copied().collect();
+    contribution * mis_weight(&path, lights, light.len())
+}
+
+/// Weight that the power heuristic gives the strategy that sampled the last `light_vertices`
+/// vertices of `path` from a light and the rest from the camera, against every other strategy
+/// this integrator has for sampling the same path. `path` runs from the camera to a light.
+///
+/// Rather than every strategy's density, this follows how the density changes as the connection
+/// moves one vertex along the path, since everywhere else the strategies' densities agree.
+fn mis_weight(path: &[Vertex], lights: &AreaLights, light_vertices: usize) -> Float {
+    let k = path.len() - 1;
+    let position_pdf = match path[k].kind {
+        VertexKind::Light {
+            light: Some(light), ..
+        } => lights.pdf(light),
+        _ => 0.0,
+    };
+    // Zero densities stand for specular deltas, which cancel out
+    let remap = |pdf: Float| if pdf > 0.0 { pdf } else { 1.0 };
+    // Densities of sampling vertex `i` from the camera's and the light's side
+    let camera_pdf = |i: usize| remap(path[i - 1].density_toward(&path[i]));
+    let light_pdf = |i: usize| {
+        remap(match i == k {
+            true => position_pdf,
+            false => path[i + 1].density_toward(&path[i]),
+        })
+    };
+    // Whether the integrator tries the strategy with `s` light vertices. The camera always
+    // contributes its own vertex and at least one more.
+    let tried = |s: usize| {
+        let t = k + 1 - s;
+        s == 0
+            || (t >= 2
+                && position_pdf > 0.0
+                && path[t - 1].is_connectible()
+                && (s == 1 || path[t].is_connectible()))
+    };
+
+    let mut sum = 1.0;
+    let mut ratio = 1.0;
+    for s in light_vertices + 1..k {
+        let i = k + 1 - s;
+        ratio *= light_pdf(i) / camera_pdf(i);
+        if tried(s) {
+            sum += ratio * ratio;
+        }
+    }
+    ratio = 1.0;
+    for s in (0..light_vertices).rev() {
+        let i = k - s;
+        ratio *= camera_pdf(i) / light_pdf(i);
+        if tried(s) {
+            sum += ratio * ratio;
+        }
+    }
+    1.0 / sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{Camera, Integrator, RenderFidelity},
+        hittable::{Background, Shape, Triangle},
+        material::{DiffuseLight, Lambertian, Material},
+        scenes,
+    };
+    use std::sync::Arc;
+
+    /// A `side` by `side` square centered on `center`, facing `u` cross `v`
+    fn square(center: Vec3, u: Vec3, v: Vec3, side: Float, material: Arc<Material>) -> [Shape; 2] {
+        let (u, v) = (u * side, v * side);
+        let origin = center - (u + v) / 2.0;
+        [
+            Triangle::new(origin, origin + u, origin + u + v, material.clone()).into(),
+            Triangle::new(origin, origin + u + v, origin + v, material).into(),
+        ]
+    }
+
+    /// A small gray patch on the floor facing up at a square light `distance` above it facing
+    /// down, in the dark
+    fn parallel_patches(light_side: Float, distance: Float, albedo: Float) -> World {
+        let gray: Arc<Material> =
+            Arc::new(Lambertian::new_rgb_solid(albedo, albedo, albedo).into());
+        let light: Arc<Material> = Arc::new(DiffuseLight::new_rgb_solid(2.0, 2.0, 2.0).into());
+        let (x, y, z) = (Vec3::x(), Vec3::y(), Vec3::z());
+        let mut shapes = Vec::new();
+        shapes.extend(square(Vec3::zeros(), x, y, 0.1, gray));
+        shapes.extend(square(z * distance, y, x, light_side, light));
+        let mut world = World::build(shapes);
+        world.background = Background::Gradient {
+            up: z,
+            bottom: Vec3::zeros(),
+            top: Vec3::zeros(),
+        };
+        world
+    }
+
+    /// Form factor from a point to a parallel square of side `side` centered `distance` straight
+    /// above it, from the one to a corner of a rectangle, four times over
+    fn form_factor(side: Float, distance: Float) -> Float {
+        let a = side / 2.0 / distance;
+        let root = (1.0 + a * a).sqrt();
+        4.0 / PI * (a / root) * (a / root).atan()
+    }
+
+    #[test]
+    fn direct_light_between_parallel_patches_matches_the_form_factor() {
+        for (side, distance) in [(1.0, 1.0), (0.4, 1.0), (3.0, 0.5)] {
+            let world = parallel_patches(side, distance, 0.5);
+            // Diffuse surfaces look the same from everywhere, so the camera looks from the side
+            let origin = Vec3::new(1.0, 0.3, distance / 2.0);
+            let ray = Ray::new(origin.into(), -origin);
+            let range = world.numeric.min_hit_distance..Float::MAX;
+            assert!(world.hit(&ray, &range).is_some());
+            let samples = 20_000;
+            let total: Vec3 = (0..samples)
+                .map(|i| {
+                    let mut rng = SampleRng::seeded(1, 0, 0, i);
+                    // One bounce, so light is found by hitting it and by connecting to it
+                    let hit = world.hit(&ray, &range);
+                    radiance(&world, &ray, hit, 1, Float::MAX, &mut rng)
+                })
+                .sum();
+            let expected = 0.5 * 2.0 * form_factor(side, distance);
+            let mean = total.x / samples as Float;
+            assert!(
+                (mean - expected).abs() < 0.02 * expected,
+                "a {} light {} away gives {}, not {}",
+                side,
+                distance,
+                mean,
+                expected
+            );
+        }
+    }
+
+    fn diffuse(point: Vec3, normal: Vec3) -> Vertex {
+        Vertex {
+            kind: VertexKind::Diffuse {
+                albedo: Vec3::repeat(0.5),
+            },
+            point,
+            normal,
+            throughput: Vec3::ONE,
+        }
+    }
+
+    #[test]
+    fn weights_of_every_strategy_add_up_to_one() {
+        let world = parallel_patches(1.0, 1.0, 0.5);
+        let lights = world.area_lights();
+        let camera = Vertex {
+            kind: VertexKind::Camera,
+            point: Vec3::new(1.0, 0.3, 0.5),
+            normal: -Vec3::new(1.0, 0.3, 0.5).normalize(),
+            throughput: Vec3::ONE,
+        };
+        let light = Vertex {
+            kind: VertexKind::Light {
+                radiance: Vec3::repeat(2.0),
+                light: Some(LightRef(0)),
+            },
+            point: Vec3::new(0.1, -0.2, 1.0),
+            normal: -Vec3::z(),
+            throughput: Vec3::ONE,
+        };
+        let floor = diffuse(Vec3::zeros(), Vec3::z());
+        let wall = diffuse(Vec3::new(0.5, 0.2, 0.4), -Vec3::x());
+        let mirror = Vertex {
+            kind: VertexKind::Specular,
+            ..diffuse(Vec3::new(-0.4, 0.1, 0.6), Vec3::x())
+        };
+        // Paths with the numbers of light vertices of the strategies that can sample them
+        let paths = [
+            (vec![camera, floor, light], vec![0, 1]),
+            (vec![camera, floor, wall, light], vec![0, 1, 2]),
+            (vec![camera, wall, floor, wall, light], vec![0, 1, 2, 3]),
+            // Nothing is connected to or through the mirror, whose densities cancel
+            (vec![camera, floor, mirror, wall, light], vec![0, 1]),
+            (
+                vec![camera, wall, floor, mirror, wall, light],
+                vec![0, 1, 4],
+            ),
+        ];
+        for (path, strategies) in paths {
+            let weights: Vec<Float> = strategies
+                .iter()
+                .map(|&s| mis_weight(&path, lights, s))
+                .collect();
+            let sum: Float = weights.iter().sum();
+            assert!((sum - 1.0).abs() < 1e-9, "{:?} adds up to {}", weights, sum);
+        }
+
+        // For a direct connection, the two strategies' weights follow their densities
+        let path = [camera, floor, light];
+        let hitting = floor.density_toward(&light);
+        let connecting = lights.pdf(LightRef(0));
+        let expected = hitting * hitting / (hitting * hitting + connecting * connecting);
+        assert!((mis_weight(&path, lights, 0) - expected).abs() < 1e-9);
+    }
+
+    /// Average luminance over the camera's pixels and the variance of that average
+    fn mean_and_variance(camera: &Camera, world: &World, samples: usize) -> (Float, Float) {
+        let pixels = (camera.image_width * camera.image_height) as Float;
+        let (mean, variance) = (0..camera.image_height)
+            .flat_map(|y| (0..camera.image_width).map(move |x| (x, y)))
+            .map(|(x, y)| camera.render_pixel_moments(world, x, y, samples).0)
+            .fold((0.0, 0.0), |(mean, variance), moments| {
+                (
+                    mean + moments.luminance_mean(),
+                    variance + moments.luminance_variance() / samples as Float,
+                )
+            });
+        (mean / pixels, variance / (pixels * pixels))
+    }
+
+    /// The scene's camera at a low resolution with a seed, with both integrators
+    fn integrators(camera: Camera) -> (Camera, Camera) {
+        let mut bidirectional = camera.with_resolution(8, 8);
+        bidirectional.seed = Some(3);
+        let mut path_tracer = bidirectional.clone();
+        path_tracer.integrator = Integrator::PathTracer;
+        path_tracer.fidelity = RenderFidelity::Reference;
+        (bidirectional, path_tracer)
+    }
+
+    #[test]
+    fn cornell_box_mean_agrees_with_the_path_tracer() {
+        let (shapes, surroundings) = scenes::cornell_box();
+        let world = surroundings.build(shapes);
+        let (bidirectional, path_tracer) = integrators(scenes::cornell_box_camera());
+        let (mean, variance) = mean_and_variance(&bidirectional, &world, 64);
+        let (expected, expected_variance) = mean_and_variance(&path_tracer, &world, 256);
+        // Four standard deviations of the difference
+        let bound = 4.0 * (variance + expected_variance).sqrt();
+        assert!(
+            (mean - expected).abs() < bound,
+            "bidirectional {} and path tracer {} differ by more than {}",
+            mean,
+            expected,
+            bound
+        );
+    }
+
+    #[test]
+    fn small_lights_in_closed_rooms_are_less_noisy() {
+        let (shapes, surroundings) = scenes::enclosed_room();
+        let world = surroundings.build(shapes);
+        let (bidirectional, path_tracer) = integrators(scenes::enclosed_room_camera());
+        let (_, variance) = mean_and_variance(&bidirectional, &world, 32);
+        let (_, path_variance) = mean_and_variance(&path_tracer, &world, 32);
+        assert!(
+            variance * 4.0 < path_variance,
+            "bidirectional variance {} against the path tracer's {}",
+            variance,
+            path_variance
+        );
+    }
+}
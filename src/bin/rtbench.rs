@@ -0,0 +1,85 @@
+//! Runs the benchmark pack, a handful of small generated scenes that each stress a different
+//! part of the renderer, and writes what each measured as JSON Lines: rays per second, build
+//! time, peak memory and how long it took to match its embedded reference. Run with
+//! `cargo run --release --bin rtbench`.
+//!
+//! `--out <path>` writes the results somewhere other than `rtbench.jsonl`, and `--scene <name>`
+//! runs only that scene, and can be given more than once. `--baseline <path>` compares the
+//! results against an earlier run's and exits with an error if any metric got worse by more
+//! than the noise threshold, a fraction of the baseline set with `--noise <fraction>`.
+//! `--write-references <dir>` renders new references into `dir` instead, for when the scenes or
+//! the renderer's output change on purpose; copy them into `benches/references` and rebuild.
+
+use rt::benchmark::{self, BenchScene, BenchmarkRun};
+use std::path::Path;
+
+fn main() {
+    if let Err(err) = run(&std::env::args().skip(1).collect::<Vec<_>>()) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = "rtbench.jsonl".to_string();
+    let mut baseline = None;
+    let mut noise = benchmark::DEFAULT_NOISE;
+    let mut scenes = Vec::new();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut value = || flags.next().ok_or(format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--out" => out = value()?.clone(),
+            "--baseline" => baseline = Some(value()?.clone()),
+            "--noise" => noise = value()?.parse()?,
+            "--scene" => {
+                let name = value()?;
+                scenes.push(
+                    BenchScene::from_name(name)
+                        .ok_or_else(|| format!("unknown benchmark scene '{}'", name))?,
+                );
+            }
+            "--write-references" => {
+                benchmark::write_references(Path::new(value()?))?;
+                return Ok(());
+            }
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    if scenes.is_empty() {
+        scenes = BenchScene::ALL.to_vec();
+    }
+
+    // Read the baseline first, so a bad path fails before the benchmark runs
+    let baseline = match baseline {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path)?;
+            Some(BenchmarkRun::parse(&text).map_err(|err| format!("{} {}", path, err))?)
+        }
+        None => None,
+    };
+
+    let run = BenchmarkRun::run(&scenes)?;
+    std::fs::write(&out, run.to_jsonl())?;
+    println!("{}", run);
+    println!("Wrote {}", out);
+
+    let Some(baseline) = baseline else {
+        return Ok(());
+    };
+    let changes = benchmark::compare(&baseline, &run, noise);
+    for change in &changes {
+        println!("{}", change);
+    }
+    let regressions = changes.iter().filter(|change| change.regressed).count();
+    if regressions > 0 {
+        return Err(format!(
+            "{} metrics regressed by more than {:.0}%",
+            regressions,
+            noise * 100.0
+        )
+        .into());
+    }
+    println!("No regressions beyond {:.0}%", noise * 100.0);
+    Ok(())
+}
@@ -0,0 +1,579 @@
+use crate::{
+    camera::Float,
+    hittable::Hit,
+    intersection::Intersection,
+    material::Material,
+    object::ObjectId,
+    uv::UvMode,
+    vec3::{Point3, Ray, RayExt, Vec2, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+};
+use std::{ops::Range, sync::Arc};
+
+/// Returns the `(entry, exit)` distances of `ray` through the slab `min[axis]..=max[axis]`.
+/// Rays parallel to the slab are either inside it forever or never.
+fn slab(ray: &Ray, min: Float, max: Float, axis: usize) -> Option<(Float, Float)> {
+    let origin = ray.origin[axis];
+    let direction = ray.direction[axis];
+    if direction == 0.0 {
+        return (min..=max)
+            .contains(&origin)
+            .then_some((Float::NEG_INFINITY, Float::INFINITY));
+    }
+    let t0 = (min - origin) / direction;
+    let t1 = (max - origin) / direction;
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Intersects the intervals of `ray` through the slabs of a box, returning the overall
+/// `(entry, exit)` distances along with the axes the ray enters and exits through
+fn slab_test(ray: &Ray, min: &Point3, max: &Point3) -> Option<(Float, usize, Float, usize)> {
+    let (mut entry, mut entry_axis) = (Float::NEG_INFINITY, 0);
+    let (mut exit, mut exit_axis) = (Float::INFINITY, 0);
+    for axis in 0..3 {
+        let (t0, t1) = slab(ray, min[axis], max[axis], axis)?;
+        if t0 > entry {
+            (entry, entry_axis) = (t0, axis);
+        }
+        if t1 < exit {
+            (exit, exit_axis) = (t1, axis);
+        }
+    }
+    (entry <= exit).then_some((entry, entry_axis, exit, exit_axis))
+}
+
+/// Returns the `(entry, exit)` distances of `ray` through the sphere at `center`
+fn sphere_interval(ray: &Ray, center: &Point3, radius: Float) -> Option<(Float, Float)> {
+    let oc = center - ray.origin.coords;
+    let a = ray.direction.norm_squared();
+    let h = ray.direction.dot(&oc);
+    let c = oc.norm_squared() - radius * radius;
+    let discriminant = h * h - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt_disc = discriminant.sqrt();
+    Some(((h - sqrt_disc) / a, (h + sqrt_disc) / a))
+}
+
+/// Returns the `(entry, exit)` distances of `ray` through the infinite cylinder of `radius`
+/// around the line through `center` parallel to `axis`
+fn cylinder_interval(
+    ray: &Ray,
+    center: &Point3,
+    radius: Float,
+    axis: usize,
+) -> Option<(Float, Float)> {
+    // Project onto the plane perpendicular to the axis, where it's a circle
+    let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+    let oc = Vec2::new(center[i] - ray.origin[i], center[j] - ray.origin[j]);
+    let direction = Vec2::new(ray.direction[i], ray.direction[j]);
+    let a = direction.norm_squared();
+    let c = oc.norm_squared() - radius * radius;
+    if a == 0.0 {
+        return (c <= 0.0).then_some((Float::NEG_INFINITY, Float::INFINITY));
+    }
+    let h = direction.dot(&oc);
+    let discriminant = h * h - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt_disc = discriminant.sqrt();
+    Some(((h - sqrt_disc) / a, (h + sqrt_disc) / a))
+}
+
+fn overlap(a: (Float, Float), b: (Float, Float)) -> Option<(Float, Float)> {
+    let interval = (a.0.max(b.0), a.1.min(b.1));
+    (interval.0 <= interval.1).then_some(interval)
+}
+
+/// Picks the nearer of a convex shape's entry and exit distances that lies in `range`.
+/// Returns the distance and whether it's the entry, i.e. whether the ray hits the front face.
+fn first_in_range(entry: Float, exit: Float, range: &Range<Float>) -> Option<(Float, bool)> {
+    if range.contains(&entry) {
+        Some((entry, true))
+    } else if range.contains(&exit) {
+        Some((exit, false))
+    } else {
+        None
+    }
+}
+
+/// Returns the UV of `point` on the face of the box from `min` to `max` perpendicular to `axis`
+fn face_uv(uv_mode: UvMode, point: &Point3, min: &Point3, max: &Point3, axis: usize) -> Vec2 {
+    let local = (2.0 * point - min - max).component_div(&(max - min));
+    uv_mode.uv_on_face(local, axis)
+}
+
+/// Returns the unit vector along `axis` pointing in the sign of `sign`
+fn axis_normal(axis: usize, sign: Float) -> Vec3 {
+    let mut normal = Vec3::zeros();
+    normal[axis] = if sign < 0.0 { -1.0 } else { 1.0 };
+    normal
+}
+
+/// A box aligned with the world axes. Unlike a triangulated box it's one primitive, and it's
+/// solid from both sides so it also works as a room seen from the inside.
+pub struct AaBox {
+    min: Point3,
+    max: Point3,
+    /// How textures are laid out over the faces, each getting the whole texture by default
+    pub uv_mode: UvMode,
+    pub material: Arc<Material>,
+    node_index: usize,
+    /// The scene object this box belongs to
+    pub object: ObjectId,
+}
+
+impl AaBox {
+    /// Returns a box spanning the two opposite corners `a` and `b`
+    pub fn new(a: Point3, b: Point3, material: Arc<Material>) -> Self {
+        AaBox {
+            min: a.inf(&b),
+            max: a.sup(&b),
+            uv_mode: UvMode::Faces,
+            material,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn with_uv_mode(mut self, uv_mode: UvMode) -> Self {
+        self.uv_mode = uv_mode;
+        self
+    }
+}
+
+impl Hit for AaBox {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        let (entry, entry_axis, exit, exit_axis) = slab_test(ray, &self.min, &self.max)?;
+        let (t, is_front_face) = first_in_range(entry, exit, range)?;
+        let axis = if is_front_face { entry_axis } else { exit_axis };
+
+        // Faces point outward against the ray on entry and along it on exit, and the normal is
+        // flipped to face the ray on exit, so either way it opposes the ray
+        let normal = axis_normal(axis, -ray.direction[axis]);
+
+        let point = ray.at(t);
+        let uv = face_uv(self.uv_mode, &point, &self.min, &self.max, axis);
+        Some(
+            Intersection::new(point, normal, t, &self.material, is_front_face, uv)
+                .with_object(self.object),
+        )
+    }
+}
+
+impl Bounded<Float, 3> for AaBox {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        Aabb::with_bounds(self.min.into(), self.max.into())
+    }
+}
+
+impl BHShape<Float, 3> for AaBox {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// An axis-aligned box with its edges and corners rounded off by `radius`.
+///
+/// The solid is the union of three boxes (the inner core grown by `radius` along one axis each),
+/// twelve capped cylinders along the edges, and eight corner spheres. The union is convex, so a
+/// ray passes through it in one interval: from the earliest entry into any part to the latest exit.
+pub struct RoundedBox {
+    center: Point3,
+    /// Half the size of the box along each axis, including the rounding
+    half_extents: Vec3,
+    radius: Float,
+    /// How textures are laid out over the faces, each getting the whole texture by default
+    pub uv_mode: UvMode,
+    pub material: Arc<Material>,
+    node_index: usize,
+    /// The scene object this box belongs to
+    pub object: ObjectId,
+}
+
+impl RoundedBox {
+    /// Returns a box spanning the two opposite corners `a` and `b` with edges rounded by `radius`.
+    /// The radius is limited to half the box's smallest side.
+    pub fn new(a: Point3, b: Point3, radius: Float, material: Arc<Material>) -> Self {
+        let half_extents = (b - a).abs() / 2.0;
+        RoundedBox {
+            center: (a + b) / 2.0,
+            half_extents,
+            radius: radius.clamp(0.0, half_extents.min()),
+            uv_mode: UvMode::Faces,
+            material,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn with_uv_mode(mut self, uv_mode: UvMode) -> Self {
+        self.uv_mode = uv_mode;
+        self
+    }
+
+    pub fn radius(&self) -> Float {
+        self.radius
+    }
+
+    /// Half extents of the core box whose rounded-off offset is this box
+    fn inner(&self) -> Vec3 {
+        self.half_extents.add_scalar(-self.radius)
+    }
+
+    /// Returns the `(entry, exit)` distances of `ray` through the box, without range checks
+    fn interval(&self, ray: &Ray) -> Option<(Float, Float)> {
+        let inner = self.inner();
+        let min = self.center - self.half_extents;
+        let max = self.center + self.half_extents;
+        // Anything that misses the outer box misses every part
+        slab_test(ray, &min, &max)?;
+
+        let mut interval: Option<(Float, Float)> = None;
+        let mut include = |part: Option<(Float, Float)>| {
+            if let Some((entry, exit)) = part {
+                interval =
+                    Some(interval.map_or((entry, exit), |(e, x)| (e.min(entry), x.max(exit))));
+            }
+        };
+
+        let slabs: Vec<(Float, Float)> = (0..3)
+            .map(|axis| {
+                slab(
+                    ray,
+                    self.center[axis] - inner[axis],
+                    self.center[axis] + inner[axis],
+                    axis,
+                )
+                .unwrap_or((Float::INFINITY, Float::NEG_INFINITY))
+            })
+            .collect();
+
+        // Core box grown along each axis in turn
+        for axis in 0..3 {
+            let mut grown_min = self.center - inner;
+            let mut grown_max = self.center + inner;
+            grown_min[axis] -= self.radius;
+            grown_max[axis] += self.radius;
+            include(
+                slab_test(ray, &grown_min, &grown_max).map(|(entry, _, exit, _)| (entry, exit)),
+            );
+        }
+
+        for corner in 0..8 {
+            let signs = Vec3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let corner_center = self.center + inner.component_mul(&signs);
+            include(sphere_interval(ray, &corner_center, self.radius));
+
+            // Each edge runs from its lower corner along one axis
+            for axis in 0..3 {
+                if signs[axis] > 0.0 {
+                    continue;
+                }
+                let edge = cylinder_interval(ray, &corner_center, self.radius, axis)
+                    .and_then(|cylinder| overlap(cylinder, slabs[axis]));
+                include(edge);
+            }
+        }
+        interval
+    }
+
+    /// Returns the outward normal at `point` on the surface, which points from the nearest point
+    /// on the core box. It's exact on edges and corners, where it's the sphere or cylinder normal.
+    fn outward_normal(&self, point: &Point3) -> Vec3 {
+        let local = point - self.center;
+        let outside_core = local.abs() - self.inner();
+        let offset = outside_core
+            .map(|d| d.max(0.0))
+            .component_mul(&local.map(|c| if c < 0.0 { -1.0 } else { 1.0 }));
+        if offset.norm_squared() > 0.0 {
+            offset.normalize()
+        } else {
+            // Only reachable with a radius of zero, where the box has sharp edges
+            let axis = outside_core.imax();
+            axis_normal(axis, local[axis])
+        }
+    }
+}
+
+impl Hit for RoundedBox {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        let (entry, exit) = self.interval(ray)?;
+        let (t, is_front_face) = first_in_range(entry, exit, range)?;
+
+        let point = ray.at(t);
+        let outward_normal = self.outward_normal(&point);
+        let normal = if is_front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+
+        let min = self.center - self.half_extents;
+        let max = self.center + self.half_extents;
+        let uv = face_uv(
+            self.uv_mode,
+            &point,
+            &min,
+            &max,
+            outward_normal.abs().imax(),
+        );
+        Some(
+            Intersection::new(point, normal, t, &self.material, is_front_face, uv)
+                .with_object(self.object),
+        )
+    }
+}
+
+impl Bounded<Float, 3> for RoundedBox {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        let min = self.center - self.half_extents;
+        let max = self.center + self.half_extents;
+        Aabb::with_bounds(min.into(), max.into())
+    }
+}
+
+impl BHShape<Float, 3> for RoundedBox {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, texture::SolidColor};
+
+    const TOLERANCE: Float = 1e-9;
+    const ALL: Range<Float> = 0.0..Float::INFINITY;
+
+    fn material() -> Arc<Material> {
+        Arc::new(Lambertian::new(SolidColor::new(Vec3::new(0.5, 0.5, 0.5)).into()).into())
+    }
+
+    /// The box from -1 to 1 on every axis
+    fn unit_box() -> AaBox {
+        AaBox::new(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            material(),
+        )
+    }
+
+    /// The same box with its edges rounded by half its half size
+    fn rounded_box() -> RoundedBox {
+        RoundedBox::new(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            0.5,
+            material(),
+        )
+    }
+
+    fn ray(origin: Point3, direction: Vec3) -> Ray {
+        Ray::new(origin.into(), direction)
+    }
+
+    /// Signed distance from `point` to the surface of [`rounded_box`]
+    fn rounded_box_distance(point: &Point3) -> Float {
+        (point.abs().add_scalar(-0.5)).map(|d| d.max(0.0)).norm() - 0.5
+    }
+
+    #[test]
+    fn slabs_give_the_face_entered() {
+        let aa_box = unit_box();
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut origin = Vec3::zeros();
+                origin[axis] = 3.0 * sign;
+                let hit = aa_box.hit(&ray(origin, -origin), &ALL).unwrap();
+                assert!((hit.t - 2.0).abs() < TOLERANCE);
+                assert_eq!(hit.normal, axis_normal(axis, sign));
+                assert!(hit.is_front_face);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_from_inside_hit_the_back_of_the_far_face() {
+        let aa_box = unit_box();
+        let hit = aa_box
+            .hit(
+                &ray(Vec3::new(0.2, 0.1, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+                &ALL,
+            )
+            .unwrap();
+        assert!((hit.t - 0.8).abs() < TOLERANCE);
+        assert!(!hit.is_front_face);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ranges_skip_to_the_exit() {
+        let aa_box = unit_box();
+        let hit = aa_box
+            .hit(&ray(Vec3::new(-3.0, 0.0, 0.0), Vec3::x()), &(2.5..10.0))
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < TOLERANCE);
+        assert!(!hit.is_front_face);
+        assert!(aa_box
+            .hit(&ray(Vec3::new(-3.0, 0.0, 0.0), Vec3::x()), &(4.5..10.0))
+            .is_none());
+    }
+
+    #[test]
+    fn rays_grazing_a_face_hit_its_edge_and_rays_just_past_it_miss() {
+        let aa_box = unit_box();
+        // Running along the top face, right on the box
+        assert!(aa_box
+            .hit(&ray(Vec3::new(-3.0, 0.0, 1.0), Vec3::x()), &ALL)
+            .is_some());
+        assert!(aa_box
+            .hit(&ray(Vec3::new(-3.0, 0.0, 1.0 + 1e-9), Vec3::x()), &ALL)
+            .is_none());
+        // Running along an edge
+        assert!(aa_box
+            .hit(&ray(Vec3::new(-3.0, 1.0, 1.0), Vec3::x()), &ALL)
+            .is_some());
+        assert!(aa_box
+            .hit(&ray(Vec3::new(-3.0, 1.0 + 1e-9, 1.0), Vec3::x()), &ALL)
+            .is_none());
+    }
+
+    #[test]
+    fn rays_through_a_corner_touch_it() {
+        let aa_box = unit_box();
+        let corner = Vec3::new(1.0, 1.0, 1.0);
+        let direction = Vec3::new(-1.0, 0.5, -0.25);
+        let hit = aa_box
+            .hit(&ray(corner - direction, direction), &ALL)
+            .unwrap();
+        assert!((hit.point - corner).norm() < TOLERANCE);
+        let past = corner + Vec3::new(0.0, 1e-9, 0.0);
+        assert!(aa_box
+            .hit(&ray(past - direction, direction), &ALL)
+            .is_none());
+    }
+
+    #[test]
+    fn rounded_corners_are_sphere_caps() {
+        let rounded = rounded_box();
+        let diagonal = Vec3::new(1.0, 1.0, 1.0).normalize();
+        let hit = rounded.hit(&ray(diagonal * 5.0, -diagonal), &ALL).unwrap();
+        // The corner sphere's center is half out along the diagonal of the core
+        let surface = 0.5 * (3.0 as Float).sqrt() + 0.5;
+        assert!((hit.t - (5.0 - surface)).abs() < TOLERANCE);
+        assert!((hit.normal - diagonal).norm() < TOLERANCE);
+        assert!(hit.is_front_face);
+    }
+
+    #[test]
+    fn rays_past_a_rounded_edge_miss_inside_the_sharp_box() {
+        let rounded = rounded_box();
+        // The edge along x rounds about (y, z) = (0.5, 0.5) with radius 0.5
+        let inside = 0.5 + 0.3;
+        let hit = rounded
+            .hit(&ray(Vec3::new(-3.0, inside, inside), Vec3::x()), &ALL)
+            .unwrap();
+        assert!(
+            hit.t > 2.0,
+            "enters at the rounded end of the edge, not the sharp face"
+        );
+        let outside = 0.5 + 0.4;
+        assert!(rounded
+            .hit(&ray(Vec3::new(-3.0, outside, outside), Vec3::x()), &ALL)
+            .is_none());
+        assert!(unit_box()
+            .hit(&ray(Vec3::new(-3.0, outside, outside), Vec3::x()), &ALL)
+            .is_some());
+    }
+
+    #[test]
+    fn rounded_hits_lie_on_the_surface_with_its_normal() {
+        let rounded = rounded_box();
+        let steps = 12;
+        for i in 0..steps {
+            for j in 1..steps {
+                let phi = i as Float / steps as Float * std::f64::consts::TAU;
+                let theta = j as Float / steps as Float * std::f64::consts::PI;
+                let outward = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                // Aimed off center, so rays cross faces, edges and corners
+                let target = Vec3::new(0.3, -0.2, 0.1);
+                let hit = rounded
+                    .hit(&ray(target + outward * 5.0, -outward), &ALL)
+                    .unwrap();
+                assert!(
+                    rounded_box_distance(&hit.point).abs() < 1e-7,
+                    "{:?}",
+                    outward
+                );
+                // The gradient of the distance is the outward normal
+                let gradient = Vec3::from_fn(|axis, _| {
+                    let mut step = Vec3::zeros();
+                    step[axis] = 1e-6;
+                    (rounded_box_distance(&(hit.point + step))
+                        - rounded_box_distance(&(hit.point - step)))
+                        / 2e-6
+                });
+                assert!(
+                    (hit.normal - gradient.normalize()).norm() < 1e-4,
+                    "{:?}",
+                    outward
+                );
+                assert!(hit.is_front_face);
+            }
+        }
+    }
+
+    #[test]
+    fn rounded_boxes_work_as_rooms() {
+        let rounded = rounded_box();
+        let direction = Vec3::new(1.0, 1.0, 1.0).normalize();
+        let hit = rounded.hit(&ray(Vec3::zeros(), direction), &ALL).unwrap();
+        assert!((hit.t - (0.5 * (3.0 as Float).sqrt() + 0.5)).abs() < TOLERANCE);
+        assert!(!hit.is_front_face);
+        assert!((hit.normal + direction).norm() < TOLERANCE);
+    }
+
+    #[test]
+    fn radius_is_limited_to_half_the_smallest_side() {
+        let thin = RoundedBox::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(4.0, 4.0, 1.0),
+            3.0,
+            material(),
+        );
+        assert_eq!(thin.radius(), 0.5);
+    }
+}
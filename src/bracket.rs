@@ -0,0 +1,211 @@
+use crate::camera::Float;
+use std::fmt;
+
+/// The most images one bracket may write, so a typo like a tiny step doesn't fill the disk
+pub const MAX_EXPOSURES: usize = 64;
+
+/// EVs are kept to this many steps per stop, which is finer than anyone brackets at, so
+/// `0.1 * 3` comes out as `0.3` and every EV gets a filename of its own
+const EV_STEPS: Float = 1000.0;
+
+/// Exposures to write one render at, as EV offsets from the job's own exposure. Each offset
+/// doubles or halves the linear colors per stop before the rest of the post-processing, so
+/// every image goes through the same tonemap as a render at that exposure would.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bracket {
+    evs: Vec<Float>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BracketError {
+    /// The text isn't a range or a list of numbers
+    Malformed(String),
+    /// The range's step isn't a positive number
+    BadStep(String),
+    /// The range or list has no exposures in it
+    Empty,
+    /// More exposures than [`MAX_EXPOSURES`]
+    TooMany(usize),
+    /// The same EV twice, which would write the same file twice
+    Duplicate(Float),
+}
+
+impl fmt::Display for BracketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BracketError::Malformed(text) => write!(
+                f,
+                "'{}' is not a bracket, which is a range like '-2..=2:1' or a list like '-1,0,1'",
+                text
+            ),
+            BracketError::BadStep(step) => {
+                write!(f, "the bracket's step '{}' is not a positive number", step)
+            }
+            BracketError::Empty => write!(f, "the bracket has no exposures in it"),
+            BracketError::TooMany(count) => write!(
+                f,
+                "the bracket has {} exposures, but at most {} are allowed",
+                count, MAX_EXPOSURES
+            ),
+            BracketError::Duplicate(ev) => {
+                write!(f, "the bracket has {} EV more than once", ev_label(*ev))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BracketError {}
+
+/// Rounds to [`EV_STEPS`], without a negative zero
+fn round_ev(ev: Float) -> Float {
+    (ev * EV_STEPS).round() / EV_STEPS + 0.0
+}
+
+/// Returns an EV offset as it's written in filenames and metadata, with its sign, e.g. `+1`,
+/// `-0.5` or `0`
+pub fn ev_label(ev: Float) -> String {
+    if ev == 0.0 {
+        "0".to_string()
+    } else {
+        format!("{:+}", ev)
+    }
+}
+
+impl Bracket {
+    /// Parses a bracket from a range, `<first>..=<last>:<step>` including the last EV or
+    /// `<first>..<last>:<step>` without it, where the step defaults to 1, or from a list of EVs
+    /// separated by commas, e.g. `-2..=2:1`, `-1..=1:0.5`, `0..3` or `-2,0,2`
+    pub fn parse(text: &str) -> Result<Self, BracketError> {
+        let malformed = || BracketError::Malformed(text.to_string());
+        let number = |word: &str| word.trim().parse::<Float>().ok().filter(|v| v.is_finite());
+        let evs = if let Some((first, rest)) = text.split_once("..") {
+            let (inclusive, rest) = match rest.strip_prefix('=') {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let (last, step) = match rest.split_once(':') {
+                Some((last, step)) => (last, Some(step)),
+                None => (rest, None),
+            };
+            let (first, last) = (
+                number(first).ok_or_else(malformed)?,
+                number(last).ok_or_else(malformed)?,
+            );
+            let step = match step {
+                Some(step) => number(step)
+                    .filter(|step| *step > 0.0)
+                    .ok_or_else(|| BracketError::BadStep(step.trim().to_string()))?,
+                None => 1.0,
+            };
+            // Counted in steps from the first EV so rounding errors don't add up
+            let steps = ((last - first) / step * EV_STEPS).round() / EV_STEPS;
+            let count = if steps < 0.0 {
+                0
+            } else if inclusive || steps.fract() != 0.0 {
+                steps.floor() as usize + 1
+            } else {
+                steps as usize
+            };
+            if count > MAX_EXPOSURES {
+                return Err(BracketError::TooMany(count));
+            }
+            (0..count)
+                .map(|i| round_ev(first + i as Float * step))
+                .collect()
+        } else {
+            text.split(',')
+                .map(|word| number(word).map(round_ev).ok_or_else(malformed))
+                .collect::<Result<Vec<Float>, _>>()?
+        };
+        Bracket::new(evs)
+    }
+
+    /// A bracket of exactly `evs`, in order
+    pub fn new(evs: Vec<Float>) -> Result<Self, BracketError> {
+        if evs.is_empty() {
+            return Err(BracketError::Empty);
+        }
+        if evs.len() > MAX_EXPOSURES {
+            return Err(BracketError::TooMany(evs.len()));
+        }
+        let evs: Vec<Float> = evs.into_iter().map(round_ev).collect();
+        for (i, ev) in evs.iter().enumerate() {
+            if evs[..i].contains(ev) {
+                return Err(BracketError::Duplicate(*ev));
+            }
+        }
+        Ok(Bracket { evs })
+    }
+
+    /// The EV offsets, in the order their images are written
+    pub fn evs(&self) -> &[Float] {
+        &self.evs
+    }
+
+    /// The bracket as a list that [`Bracket::parse`] reads back exactly
+    pub fn to_list(&self) -> String {
+        self.evs
+            .iter()
+            .map(|&ev| ev_label(ev))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evs(text: &str) -> Vec<Float> {
+        Bracket::parse(text).unwrap().evs().to_vec()
+    }
+
+    #[test]
+    fn ranges_and_lists_parse_to_their_evs() {
+        assert_eq!(evs("-2..=2:1"), [-2.0, -1.0, 0.0, 1.0, 2.0]);
+        assert_eq!(evs("-2..2:1"), [-2.0, -1.0, 0.0, 1.0]);
+        // The step defaults to a stop
+        assert_eq!(evs("0..3"), [0.0, 1.0, 2.0]);
+        assert_eq!(evs("-1..=1:0.5"), [-1.0, -0.5, 0.0, 0.5, 1.0]);
+        // Tenths land on tenths rather than drifting
+        assert_eq!(evs("0..=0.3:0.1"), [0.0, 0.1, 0.2, 0.3]);
+        // A step that doesn't divide the range stops before the end either way
+        assert_eq!(evs("0..=1:0.4"), [0.0, 0.4, 0.8]);
+        assert_eq!(evs("0..1:0.4"), [0.0, 0.4, 0.8]);
+        assert_eq!(evs(" -2 , 0 ,2"), [-2.0, 0.0, 2.0]);
+        assert_eq!(evs("1,-1"), [1.0, -1.0]);
+        assert_eq!(evs("-0"), [0.0]);
+        assert!(evs("-0")[0].is_sign_positive());
+    }
+
+    #[test]
+    fn bad_brackets_say_what_is_wrong() {
+        let err = |text: &str| Bracket::parse(text).unwrap_err();
+        assert_eq!(err("plenty"), BracketError::Malformed("plenty".to_string()));
+        assert_eq!(err("-1..=x"), BracketError::Malformed("-1..=x".to_string()));
+        assert_eq!(err("1,,2"), BracketError::Malformed("1,,2".to_string()));
+        assert_eq!(err("0..=1:inf"), BracketError::BadStep("inf".to_string()));
+        assert_eq!(err("0..=1:0"), BracketError::BadStep("0".to_string()));
+        assert_eq!(err("0..=1:-1"), BracketError::BadStep("-1".to_string()));
+        assert_eq!(err("2..=1"), BracketError::Empty);
+        assert_eq!(err("1..1"), BracketError::Empty);
+        assert_eq!(err("0..=100:1"), BracketError::TooMany(101));
+        assert_eq!(err("0,1,0.0001"), BracketError::Duplicate(0.0));
+        assert_eq!(
+            Bracket::new((0..65).map(|ev| ev as Float).collect()),
+            Err(BracketError::TooMany(65))
+        );
+    }
+
+    #[test]
+    fn lists_read_back_exactly() {
+        for text in ["-2..=2:1", "-1..=1:0.25", "0..=0.3:0.1", "3,-0.5,0"] {
+            let bracket = Bracket::parse(text).unwrap();
+            assert_eq!(Bracket::parse(&bracket.to_list()), Ok(bracket.clone()));
+        }
+        assert_eq!(
+            Bracket::parse("-1..=1:0.5").unwrap().to_list(),
+            "-1,-0.5,0,+0.5,+1"
+        );
+    }
+}
@@ -1,18 +1,41 @@
 use crate::{
+    bidirectional,
+    convergence::PixelMoments,
+    cost::{CostMap, PixelCost},
+    denoise::DenoiseParams,
+    draft,
     hittable::{Hit, World},
     intersection::Intersection,
-    material::Scatter,
-    vec3::{Point3, Ray, Vec3, Vec3Ext},
+    material::{Material, Scatter},
+    material_override::MaterialOverride,
+    object::ObjectId,
+    postprocess::PostProcess,
+    rng::{self, SampleRng},
+    scene_lights::SceneLight,
+    scenes::MAX_DEPTH,
+    shading::{scatter_once, PathState, ShadingContext},
+    shadow_matte,
+    sky_importance::SkySample,
+    technical,
+    tiles::{self, Tile, DEFAULT_TILE_SIZE},
+    validation::{self, Stage},
+    vec3::{Point3, Ray, Vec2, Vec3, Vec3Ext},
+    watchdog::{SampleCost, Watchdog},
+    window::{HEIGHT, WIDTH},
 };
 use image::GenericImageView;
-use indicatif::{ParallelProgressIterator, ProgressIterator};
+use indicatif::ParallelProgressIterator;
 use itertools::Itertools;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use rayon::prelude::*;
 use std::{
+    f64::consts::PI,
+    fmt,
     fs::File,
     io::{BufWriter, Write},
-    ops::{Index, Range},
+    ops::{Index, IndexMut, Range},
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 pub type Float = f64;
@@ -21,10 +44,306 @@ pub type Float = f64;
 pub const T_MIN: Float = 0.0;
 pub const T_MAX: Float = Float::MAX;
 
-#[derive(Default)]
+/// Number of consecutive zero-advance hits after which a path is terminated
+const MAX_ZERO_ADVANCE_STREAK: usize = 4;
+
+/// How long a path has gone without moving forward, see [`MAX_ZERO_ADVANCE_STREAK`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ZeroAdvance {
+    /// Where the path last hit something, or `None` before its first hit
+    last_hit: Option<Point3>,
+    /// Hits in a row that landed within the zero-advance distance of the hit before them
+    streak: usize,
+}
+
+impl ZeroAdvance {
+    /// The streak once the path hits `point`, which grows if that's within `distance` of the
+    /// last hit and starts over otherwise
+    fn after(self, point: Point3, distance: Float) -> Self {
+        let stuck = self
+            .last_hit
+            .is_some_and(|last_hit| (point - last_hit).norm() < distance);
+        ZeroAdvance {
+            last_hit: Some(point),
+            streak: if stuck { self.streak + 1 } else { 0 },
+        }
+    }
+}
+
+/// Throughput below which paths start playing russian roulette. Paths this dim barely add
+/// anything, so with roulette doing the culling the depth limit is only a backstop.
+pub const DEFAULT_THROUGHPUT_THRESHOLD: Float = 1e-3;
+
+/// Bounces a path makes before russian roulette can end it. Dark surfaces seen directly or in
+/// the first few reflections would otherwise lose most of their paths right away and come out
+/// noisier than brighter ones.
+pub const DEFAULT_ROULETTE_MIN_DEPTH: usize = 3;
+
+/// Least chance a path playing russian roulette has of going on, which bounds how much
+/// survivors are boosted by and so how bright a single lucky path can make a pixel
+const MIN_CONTINUE_PROBABILITY: Float = 0.05;
+
+/// How much the renderer may trade accuracy for speed. Every variance-reduction trick checks this
+/// one switch, so adding a new trick means deciding how it behaves in reference renders.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RenderFidelity {
+    /// Uses every trick available to converge faster
+    #[default]
+    Production,
+    /// Brute-force sampling spread evenly over the pixels, with all variance reduction and
+    /// caching disabled, as a baseline to check for bias
+    Reference,
+}
+
+impl RenderFidelity {
+    /// Whether paths may be terminated early by russian roulette instead of only by the depth limit
+    pub fn russian_roulette(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    /// Whether pixel samples may use a quasi-random sequence instead of uniform random offsets
+    pub fn quasi_random_samples(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    /// Whether diffuse surfaces may sample the sky directly, weighted against their own bounces
+    pub fn sky_importance_sampling(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    /// Whether diffuse surfaces may also sample the sun's disc directly, weighted against their
+    /// bounces and the sky samples
+    pub fn sun_sampling(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    /// Whether diffuse surfaces may sample rect lights that rays can also hit, weighted against
+    /// their bounces. Spot lights and hidden rect lights can't be hit, so they're always sampled.
+    pub fn area_light_sampling(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    /// Whether the preview may stop sampling sky pixels once they've converged, see
+    /// [`crate::sky_cache::SkyCache`]
+    pub fn sky_cache(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    /// Whether the preview may give noisier pixels more samples than others after its first
+    /// sweeps, instead of sampling every pixel evenly
+    pub fn adaptive_sampling(&self) -> bool {
+        *self == RenderFidelity::Production
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderFidelity::Production => "production",
+            RenderFidelity::Reference => "reference",
+        }
+    }
+
+    /// The inverse of [`RenderFidelity::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "production" => Some(RenderFidelity::Production),
+            "reference" => Some(RenderFidelity::Reference),
+            _ => None,
+        }
+    }
+}
+
+/// How the light arriving through each pixel is estimated
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Integrator {
+    /// Follows paths from the camera, sampling the sky at diffuse surfaces along the way
+    #[default]
+    PathTracer,
+    /// Connects paths from the camera with paths from the lights, see [`crate::bidirectional`].
+    /// Much slower per sample, but converges where light is hard to find from the camera.
+    Bidirectional,
+    /// Shades only what camera rays hit first, with every surface lit by the whole sky, see
+    /// [`crate::draft`]. Nowhere near right, but free of noise from the first sample, which is
+    /// what the preview shows while the camera moves.
+    Draft,
+    /// Shades what camera rays hit first by how squarely it faces the camera, with back faces
+    /// and degenerate hits flagged in color, see [`crate::technical`]. For checking geometry
+    /// rather than light, and usually set up along with the rest of [`technical::preset`].
+    Technical,
+}
+
+impl Integrator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::PathTracer => "path",
+            Integrator::Bidirectional => "bidirectional",
+            Integrator::Draft => "draft",
+            Integrator::Technical => "technical",
+        }
+    }
+
+    /// The inverse of [`Integrator::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "path" => Some(Integrator::PathTracer),
+            "bidirectional" => Some(Integrator::Bidirectional),
+            "draft" => Some(Integrator::Draft),
+            "technical" => Some(Integrator::Technical),
+            _ => None,
+        }
+    }
+}
+
+/// Multiple importance sampling weight for a sample drawn with density `pdf` when `other_pdf` is
+/// the density of the other strategy that could have found the same direction. With more than
+/// one other strategy, pass the `hypot` of their densities.
+pub(crate) fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// What happened where a traced path hit something
+#[derive(Debug, Clone)]
+pub struct BounceEvent {
+    pub depth: usize,
+    pub object: ObjectId,
+    pub material: &'static str,
+    pub t: Float,
+    pub point: Point3,
+    pub normal: Vec3,
+    pub uv: Vec2,
+    pub is_front_face: bool,
+    /// Direction the path continued in and the attenuation it picked up, unless it was absorbed
+    pub scattered: Option<(Vec3, Vec3)>,
+    /// Light from sampling the sky and the sun directly, already multiplied by the attenuation
+    pub sky_light: Vec3,
+    /// Light from sampling spot and rect lights directly, already multiplied by the attenuation
+    pub scene_light: Vec3,
+    /// Light the surface gives off itself
+    pub emitted: Vec3,
+    /// Whether russian roulette let the path continue, if it was played
+    pub survived_roulette: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PathEvent {
+    Bounce(BounceEvent),
+    /// The surface absorbed the path
+    Absorbed,
+    /// The path ran out of bounces
+    DepthLimit,
+    /// The path was cut off for hitting the same point over and over
+    Stuck,
+    /// The path left the scene and saw the sky, with the weight multiple importance sampling
+    /// gave it against sampling the sky and the sun directly
+    Escaped {
+        direction: Vec3,
+        sky_color: Vec3,
+        weight: Float,
+    },
+}
+
+/// Everything that happened to one sample's path, from [`Camera::trace_sample`]
+#[derive(Debug, Clone)]
+pub struct PathTrace {
+    pub pixel: (usize, usize),
+    pub sample: usize,
+    pub seed: Option<u64>,
+    pub events: Vec<PathEvent>,
+    /// The sample's color, which is what it added to the pixel
+    pub radiance: Vec3,
+}
+
+impl fmt::Display for PathTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vector = |v: &Vec3| format!("({:.6}, {:.6}, {:.6})", v.x, v.y, v.z);
+        let seed = match self.seed {
+            Some(seed) => seed.to_string(),
+            None => "none (not reproducible)".to_string(),
+        };
+        writeln!(
+            f,
+            "Sample {} of pixel ({}, {}), seed {}",
+            self.sample, self.pixel.0, self.pixel.1, seed
+        )?;
+        for event in &self.events {
+            match event {
+                PathEvent::Bounce(bounce) => {
+                    writeln!(
+                        f,
+                        "  [{}] hit {} ({}) at t = {:.6}, point {}, normal {}, uv ({:.4}, {:.4}), {} face",
+                        bounce.depth,
+                        bounce.object,
+                        bounce.material,
+                        bounce.t,
+                        vector(&bounce.point),
+                        vector(&bounce.normal),
+                        bounce.uv.x,
+                        bounce.uv.y,
+                        if bounce.is_front_face { "front" } else { "back" }
+                    )?;
+                    if let Some((direction, attenuation)) = &bounce.scattered {
+                        writeln!(
+                            f,
+                            "      scattered toward {} with attenuation {}",
+                            vector(direction),
+                            vector(attenuation)
+                        )?;
+                    }
+                    if bounce.emitted != Vec3::zeros() {
+                        writeln!(f, "      gave off {}", vector(&bounce.emitted))?;
+                    }
+                    if bounce.sky_light != Vec3::zeros() {
+                        writeln!(f, "      sky sample added {}", vector(&bounce.sky_light))?;
+                    }
+                    if bounce.scene_light != Vec3::zeros() {
+                        writeln!(
+                            f,
+                            "      light samples added {}",
+                            vector(&bounce.scene_light)
+                        )?;
+                    }
+                    match bounce.survived_roulette {
+                        Some(true) => writeln!(f, "      survived russian roulette")?,
+                        Some(false) => writeln!(f, "      terminated by russian roulette")?,
+                        None => {}
+                    }
+                }
+                PathEvent::Absorbed => writeln!(f, "  absorbed")?,
+                PathEvent::DepthLimit => writeln!(f, "  reached the depth limit")?,
+                PathEvent::Stuck => writeln!(f, "  terminated for not advancing")?,
+                PathEvent::Escaped {
+                    direction,
+                    sky_color,
+                    weight,
+                } => writeln!(
+                    f,
+                    "  escaped toward {}, sky {} weighted by {:.6}",
+                    vector(direction),
+                    vector(sky_color),
+                    weight
+                )?,
+            }
+        }
+        write!(f, "  radiance {}", vector(&self.radiance))
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Camera {
     /// Defines the center point of the camera
     pub center: Point3,
+    /// Defines the point the camera is looking at
+    pub lookat: Point3,
+    /// Defines the camera's up direction
+    pub up: Vec3,
+    /// Defines the vertical field of view in degrees
+    pub vertical_fov: Float,
+    /// Defines the distance from the camera's center to the plane of perfect focus
+    pub focus_distance: Float,
     /// Defines the rendered image's width in pixels
     pub image_width: usize,
     /// Defines the rendered image's height in pixels
@@ -46,29 +365,84 @@ pub struct Camera {
     pub pixel_dv: Vec3,
     /// Defines the minimum and maximum distances from the camera to be rendered
     t_range: Range<Float>,
-    /// Defines the "random" sequnece for pixel samples. Halton sequence for now
-    rng_map: Vec<(Float, Float)>,
+    /// Defines the "random" sequence for pixel and lens samples. Halton sequence for now
+    /// Shared between cameras since it's expensive to generate and never changes
+    rng_map: Arc<HaltonSamples>,
+    /// Tracks what each render worker is doing so stalls can be diagnosed
+    pub watchdog: Arc<Watchdog>,
+    /// Controls which approximations the renderer is allowed to make
+    pub fidelity: RenderFidelity,
+    /// Lets diffuse surfaces sample the sun's disc directly when the fidelity allows it, so
+    /// renders with and without it can be compared. On for new cameras.
+    pub sun_sampling: bool,
+    /// Shades every surface but lights with one material, without touching the world. The
+    /// bidirectional integrator ignores it. Off for new cameras.
+    pub material_override: Option<MaterialOverride>,
+    /// Decides how each sample's light is estimated
+    pub integrator: Integrator,
+    /// Paths whose throughput drops below this play russian roulette to go on, see
+    /// [`DEFAULT_THROUGHPUT_THRESHOLD`]. Only used when the fidelity allows roulette.
+    pub throughput_threshold: Float,
+    /// Bounces before russian roulette can end a path, see [`DEFAULT_ROULETTE_MIN_DEPTH`]
+    pub roulette_min_depth: usize,
+    /// Gamma that rendered images get encoded with
+    pub gamma: Float,
+    /// Processing applied to copies of rendered images as they're written out
+    pub post_process: PostProcess,
+    /// Takes fireflies and noise out of rendered images before they're post-processed, see
+    /// [`Image::denoise`]. Off for new cameras.
+    pub quick_denoise: Option<DenoiseParams>,
+    /// Seeds every sample's random numbers from its pixel and index, so any sample can be
+    /// replayed exactly. `None` draws fresh random numbers every time, which the preview needs
+    /// since it renders the same sample indices over and over.
+    pub seed: Option<u64>,
 }
 
-pub type Pixel = (usize, usize, Vec3);
+/// Gamma that images are encoded with unless a camera asks for something else
+pub const DEFAULT_GAMMA: Float = 2.2;
+
+/// Encodes a linear color as 8-bit RGB with `gamma`, clamping it to the displayable range
+pub fn rgb8(color: &Vec3, gamma: Float) -> [u8; 3] {
+    let encoded = color.map(|c| c.clamp(0.0, 1.0).powf(1.0 / gamma));
+    validation::check(Stage::EncodedOutput, &(encoded * 255.0));
+    let (r, g, b) = encoded.as_rgb_linear();
+    [r, g, b]
+}
+
+/// Header of a binary (P6) PPM, with each metadata line as a comment
+pub fn ppm_header(width: usize, height: usize, metadata: &[String]) -> String {
+    let comments: String = metadata
+        .iter()
+        .map(|line| format!("# {}\n", line.replace('\n', " ")))
+        .collect();
+    format!("P6\n{}{} {}\n255\n", comments, width, height)
+}
 
-#[derive(Default)]
 pub struct Image {
-    pub pixels: Vec<Pixel>,
+    /// Linear colors a row at a time from the top, so pixel `(x, y)` is at `y * width + x`
+    pub pixels: Vec<Vec3>,
     pub width: usize,
     pub height: usize,
+    /// Gamma the linear pixel colors are encoded with when the image is written out
+    pub gamma: Float,
+    /// Lines describing how the image was made, written as comments in a PPM's header and as
+    /// `Comment` text in a PNG
+    pub metadata: Vec<String>,
+    /// How much of each pixel is covered, from 0 for see-through to 1, in the same order as the
+    /// colors. Only images for compositing have one, see [`crate::material::ShadowCatcher`].
+    pub alpha: Option<Vec<Float>>,
 }
 
 impl From<image::DynamicImage> for Image {
     fn from(image: image::DynamicImage) -> Self {
         let pixels = image
             .pixels()
-            .map(|(x, y, color)| {
+            .map(|(_x, _y, color)| {
                 let c = image::Pixel::channels(&color);
                 let r = c[0] as Float / 255.0;
                 let g = c[1] as Float / 255.0;
                 let b = c[2] as Float / 255.0;
-                (x as usize, y as usize, Vec3::new(r, g, b)) as Pixel
+                Vec3::new(r, g, b)
             })
             .collect();
 
@@ -76,51 +450,129 @@ impl From<image::DynamicImage> for Image {
             pixels,
             width: image.width() as usize,
             height: image.height() as usize,
+            gamma: DEFAULT_GAMMA,
+            metadata: Vec::new(),
+            alpha: None,
         }
     }
 }
 
-impl From<&gltf::image::Data> for Image {
-    fn from(image: &gltf::image::Data) -> Self {
+impl TryFrom<&gltf::image::Data> for Image {
+    type Error = String;
+
+    fn try_from(image: &gltf::image::Data) -> Result<Self, Self::Error> {
         // TODO: this is sus as hell and has not been tested very much at all
         let (chunk_size, max) = match image.format {
             gltf::image::Format::R8 => (1, u8::MAX as u64),
             gltf::image::Format::R8G8 => (2, u8::MAX as u64),
             gltf::image::Format::R8G8B8 => (3, u8::MAX as u64),
             gltf::image::Format::R8G8B8A8 => (4, u8::MAX as u64),
-            gltf::image::Format::R16 => todo!("red16"),
             gltf::image::Format::R16G16 => (2, u16::MAX as u64),
             gltf::image::Format::R16G16B16 => (3, u16::MAX as u64),
-            gltf::image::Format::R16G16B16A16 => todo!("rgba16"),
-            gltf::image::Format::R32G32B32FLOAT => todo!("rgb_float32"),
-            gltf::image::Format::R32G32B32A32FLOAT => todo!("rgba_float32"),
-            // I don't even know what these strange formats are, i have no business writing
-            // code for them
-            // gltf::image::Format::R16G16B16A16 => (4, u16::MAX as u64),
-            // gltf::image::Format::R32G32B32FLOAT => (3, f32::MAX as u64),
-            // gltf::image::Format::R32G32B32A32FLOAT => (4, f32::MAX as u64),
+            format @ (gltf::image::Format::R16
+            | gltf::image::Format::R16G16B16A16
+            | gltf::image::Format::R32G32B32FLOAT
+            | gltf::image::Format::R32G32B32A32FLOAT) => {
+                return Err(format!("unsupported pixel format {:?}", format))
+            } // I don't even know what these strange formats are, i have no business writing
+              // code for them
+              // gltf::image::Format::R16G16B16A16 => (4, u16::MAX as u64),
+              // gltf::image::Format::R32G32B32FLOAT => (3, f32::MAX as u64),
+              // gltf::image::Format::R32G32B32A32FLOAT => (4, f32::MAX as u64),
         };
 
-        let pixels: Vec<Pixel> = image
+        let pixels: Vec<Vec3> = image
             .pixels
             .par_chunks_exact(chunk_size)
-            .enumerate()
-            .map(|(i, chunk)| {
-                let x = i % image.width as usize;
-                let y = i / image.width as usize;
-                let c = Vec3::new(
+            .map(|chunk| {
+                Vec3::new(
                     chunk[0] as Float / max as Float,
                     *chunk.get(1).unwrap_or(&0) as Float / max as Float,
                     *chunk.get(2).unwrap_or(&0) as Float / max as Float,
-                );
-                (x, y, c)
+                )
             })
             .collect::<_>();
-        Image {
+        if pixels.len() < image.width as usize * image.height as usize {
+            return Err(format!(
+                "image data is truncated: {} of {}x{} pixels",
+                pixels.len(),
+                image.width,
+                image.height
+            ));
+        }
+        Ok(Image {
             pixels,
             width: image.width as usize,
             height: image.height as usize,
+            gamma: DEFAULT_GAMMA,
+            metadata: Vec::new(),
+            alpha: None,
+        })
+    }
+}
+
+impl Image {
+    /// Encodes the image as a binary (P6) PPM with 8-bit RGB values encoded with the image's gamma.
+    /// Rows are encoded in parallel straight into the output buffer. PPMs have no alpha channel,
+    /// so the image's is left out.
+    pub fn encode_ppm(&self) -> Vec<u8> {
+        let header = ppm_header(self.width, self.height, &self.metadata);
+        let mut bytes = vec![0u8; header.len() + self.width * 3 * self.height];
+        bytes[..header.len()].copy_from_slice(header.as_bytes());
+        self.write_rgb8(&mut bytes[header.len()..]);
+        bytes
+    }
+
+    /// Encodes the image as an 8-bit PNG, with the same gamma and row order as
+    /// [`Image::encode_ppm`], and RGBA if the image has an alpha channel, which isn't gamma
+    /// encoded. Each line of metadata is kept as `Comment` text.
+    pub fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut rgb = vec![0u8; self.width * 3 * self.height];
+        self.write_rgb8(&mut rgb);
+        let (data, color_type) = match &self.alpha {
+            Some(alpha) => {
+                let rgba = rgb
+                    .chunks_exact(3)
+                    .zip(alpha)
+                    .flat_map(|(color, alpha)| {
+                        let alpha = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+                        [color[0], color[1], color[2], alpha]
+                    })
+                    .collect();
+                (rgba, png::ColorType::Rgba)
+            }
+            None => (rgb, png::ColorType::Rgb),
+        };
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width as u32, self.height as u32);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        for line in &self.metadata {
+            encoder.add_itxt_chunk("Comment".to_string(), line.clone())?;
         }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(bytes)
+    }
+
+    /// Fills `bytes` with the pixels as 8-bit RGB values encoded with the image's gamma, a row at
+    /// a time from the top and in parallel
+    fn write_rgb8(&self, bytes: &mut [u8]) {
+        let row_bytes = self.width * 3;
+        if row_bytes == 0 {
+            return;
+        }
+        bytes
+            .par_chunks_mut(row_bytes)
+            .zip(self.pixels.par_chunks(self.width))
+            .enumerate()
+            .for_each(|(y, (row, pixels))| {
+                for (x, (out, color)) in row.chunks_exact_mut(3).zip(pixels).enumerate() {
+                    validation::enter_pixel(x, y);
+                    out.copy_from_slice(&rgb8(color, self.gamma));
+                }
+            });
     }
 }
 
@@ -129,10 +581,24 @@ impl Index<(usize, usize)> for Image {
 
     fn index(&self, index: (usize, usize)) -> &Self::Output {
         let (x, y) = index;
-        &self.pixels[y * self.width + x].2
+        &self.pixels[y * self.width + x]
     }
 }
 
+impl IndexMut<(usize, usize)> for Image {
+    fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
+        let (x, y) = index;
+        &mut self.pixels[y * self.width + x]
+    }
+}
+
+/// Adds a sample's color and whether it escaped to a running total. Samples are added in order
+/// rather than in whatever tree rayon splits them into, so a seeded pixel sums to the same bits
+/// every time.
+fn sum_samples((color, escaped): (Vec3, usize), sample: (Vec3, usize)) -> (Vec3, usize) {
+    (color + sample.0, escaped + sample.1)
+}
+
 // Used to generate pixel sample offset values for rays for faster convergence / less noise
 // Maybe use a uniform pattern instead? Need to do more research into this...
 // TODO: read this https://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
@@ -164,7 +630,266 @@ fn halton_sequence(base: u64, sequence_length: u64) -> impl std::iter::Iterator<
     })
 }
 
+/// The quasi-random points each sample index uses: Halton sequences in bases 2 and 3 for where
+/// in the pixel the sample goes, and 5 and 7 for where on the lens it starts, so lens samples
+/// are stratified along with pixel samples instead of clumping at low sample counts
+#[derive(Default)]
+struct HaltonSamples {
+    points: Vec<[Float; 4]>,
+}
+
+impl HaltonSamples {
+    fn new(count: u64) -> Self {
+        let points = halton_sequence(2, count)
+            .zip(halton_sequence(3, count))
+            .zip(halton_sequence(5, count).zip(halton_sequence(7, count)))
+            .map(|((x, y), (u, v))| [x, y, u, v])
+            .collect();
+        HaltonSamples { points }
+    }
+
+    /// Offset of sample `i` within its pixel, each from 0 to 1
+    fn pixel(&self, i: usize) -> (Float, Float) {
+        let [x, y, _, _] = self.points[i];
+        (x, y)
+    }
+
+    /// Point of the unit square that sample `i` maps onto the lens
+    fn lens(&self, i: usize) -> (Float, Float) {
+        let [_, _, u, v] = self.points[i];
+        (u, v)
+    }
+}
+
+/// Below this, up is taken to be along the view direction, relative to its length
+const PARALLEL_UP_EPSILON: Float = 1e-6;
+
+/// The unit vector pointing right in the image of a camera looking along `-w` with `up` up.
+/// When up is along the view direction, e.g. looking straight down, any roll fits, so it's
+/// measured from the X axis instead, or the Y axis if the view is along X.
+fn camera_right(up: &Vec3, w: &Vec3) -> Vec3 {
+    let right = up.cross(w);
+    let length = right.norm();
+    if length >= PARALLEL_UP_EPSILON * up.norm() && length.is_finite() && length > 0.0 {
+        return right / length;
+    }
+    let reference = if w.x.abs() < 0.9 {
+        Vec3::x()
+    } else {
+        Vec3::y()
+    };
+    reference.cross(w).normalize()
+}
+
+/// Why a [`CameraBuilder`] couldn't make a camera
+#[derive(Debug, Clone, PartialEq)]
+pub enum CameraError {
+    /// The image would have no pixels
+    ZeroResolution { width: usize, height: usize },
+    /// The vertical field of view has to be more than 0 and less than 180 degrees
+    FieldOfView(Float),
+    /// The plane of focus has to be in front of the camera
+    FocusDistance(Float),
+    /// Defocus blur can't be negative or infinite
+    DefocusAngle(Float),
+    /// The camera looks at the point it's at, so it doesn't look anywhere
+    NoViewDirection(Point3),
+    /// Up is zero or not finite, so it doesn't point anywhere
+    NoUpDirection(Vec3),
+    /// Nothing could be rendered between the near and far distances
+    EmptyRange(Range<Float>),
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraError::ZeroResolution { width, height } => {
+                write!(f, "resolution {}x{} has no pixels", width, height)
+            }
+            CameraError::FieldOfView(fov) => write!(
+                f,
+                "vertical field of view {} isn't between 0 and 180 degrees",
+                fov
+            ),
+            CameraError::FocusDistance(distance) => {
+                write!(
+                    f,
+                    "focus distance {} isn't in front of the camera",
+                    distance
+                )
+            }
+            CameraError::DefocusAngle(angle) => {
+                write!(
+                    f,
+                    "defocus angle {} isn't a finite angle of 0 or more",
+                    angle
+                )
+            }
+            CameraError::NoViewDirection(point) => write!(
+                f,
+                "the camera is at ({}, {}, {}), the point it looks at",
+                point.x, point.y, point.z
+            ),
+            CameraError::NoUpDirection(up) => write!(
+                f,
+                "up ({}, {}, {}) doesn't point anywhere",
+                up.x, up.y, up.z
+            ),
+            CameraError::EmptyRange(range) => {
+                write!(
+                    f,
+                    "nothing is rendered from {} to {}",
+                    range.start, range.end
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
+
+/// Settings for a [`Camera`], built up with the `with_` methods and checked by
+/// [`CameraBuilder::build`]. By default the camera is at the origin looking along +Y with Z up,
+/// focused on the point it looks at with no blur.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraBuilder {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub up: Vec3,
+    /// In degrees
+    pub vertical_fov: Float,
+    pub width: usize,
+    pub height: usize,
+    /// Samples of every pixel in batch renders
+    pub samples: usize,
+    pub max_depth: usize,
+    /// Distance to the plane of perfect focus, or `None` to focus on `look_at`
+    pub focus_distance: Option<Float>,
+    /// Variation of the angle of rays through each pixel in degrees, 0 for none
+    pub defocus_angle: Float,
+    pub t_range: Range<Float>,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        CameraBuilder {
+            look_from: Point3::zeros(),
+            look_at: Point3::y(),
+            up: Vec3::z(),
+            vertical_fov: 20.0,
+            width: WIDTH as usize,
+            height: HEIGHT as usize,
+            samples: 32,
+            max_depth: MAX_DEPTH,
+            focus_distance: None,
+            defocus_angle: 0.0,
+            t_range: T_MIN..T_MAX,
+        }
+    }
+}
+
+impl CameraBuilder {
+    pub fn with_look_from(mut self, look_from: Point3) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn with_look_at(mut self, look_at: Point3) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn with_up(mut self, up: Vec3) -> Self {
+        self.up = up;
+        self
+    }
+
+    pub fn with_vertical_fov(mut self, degrees: Float) -> Self {
+        self.vertical_fov = degrees;
+        self
+    }
+
+    pub fn with_resolution(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_focus_distance(mut self, focus_distance: Float) -> Self {
+        self.focus_distance = Some(focus_distance);
+        self
+    }
+
+    pub fn with_defocus_angle(mut self, degrees: Float) -> Self {
+        self.defocus_angle = degrees;
+        self
+    }
+
+    pub fn with_t_range(mut self, t_range: Range<Float>) -> Self {
+        self.t_range = t_range;
+        self
+    }
+
+    /// Makes the camera, or says which setting makes no sense
+    pub fn build(self) -> Result<Camera, CameraError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(CameraError::ZeroResolution {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if !(self.vertical_fov > 0.0 && self.vertical_fov < 180.0) {
+            return Err(CameraError::FieldOfView(self.vertical_fov));
+        }
+        let view = self.look_at - self.look_from;
+        if !(view.norm() > 0.0 && view.norm().is_finite()) {
+            return Err(CameraError::NoViewDirection(self.look_from));
+        }
+        if !(self.up.norm() > 0.0 && self.up.norm().is_finite()) {
+            return Err(CameraError::NoUpDirection(self.up));
+        }
+        let focus_distance = self.focus_distance.unwrap_or(view.norm());
+        if !(focus_distance > 0.0 && focus_distance.is_finite()) {
+            return Err(CameraError::FocusDistance(focus_distance));
+        }
+        if !(self.defocus_angle >= 0.0 && self.defocus_angle.is_finite()) {
+            return Err(CameraError::DefocusAngle(self.defocus_angle));
+        }
+        if !(self.t_range.start >= 0.0 && self.t_range.start < self.t_range.end) {
+            return Err(CameraError::EmptyRange(self.t_range));
+        }
+        Ok(Camera::new(
+            self.look_from,
+            self.look_at,
+            self.up,
+            focus_distance,
+            self.defocus_angle,
+            self.width,
+            self.height,
+            self.samples,
+            self.max_depth,
+            self.vertical_fov,
+            self.t_range,
+        ))
+    }
+}
+
 impl Camera {
+    /// Starts a [`CameraBuilder`], which names each setting instead of taking them in order
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         center: Vec3,
@@ -179,56 +904,111 @@ impl Camera {
         vertical_fov: Float,
         t_range: Range<Float>,
     ) -> Self {
-        let w = (center - lookat).normalize();
-        let u = up.cross(&w).normalize();
+        let rng_map = HaltonSamples::new(1024 * 1024);
+
+        let mut camera = Camera {
+            center,
+            lookat,
+            up,
+            vertical_fov,
+            focus_distance,
+            defocus_angle,
+            image_width,
+            image_height,
+            samples_per_pixel,
+            max_depth,
+            t_range,
+            rng_map: Arc::new(rng_map),
+            gamma: DEFAULT_GAMMA,
+            throughput_threshold: DEFAULT_THROUGHPUT_THRESHOLD,
+            roulette_min_depth: DEFAULT_ROULETTE_MIN_DEPTH,
+            sun_sampling: true,
+            ..Default::default()
+        };
+        camera.orient();
+        camera
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn defocus_angle(&self) -> Float {
+        self.defocus_angle
+    }
+
+    pub fn t_range(&self) -> Range<Float> {
+        self.t_range.clone()
+    }
+
+    /// Returns a copy of this camera moved to `center`, looking at `lookat` and focused at
+    /// `focus_distance`. Shares the sample sequence and watchdog with the original.
+    pub fn with_view(&self, center: Point3, lookat: Point3, focus_distance: Float) -> Self {
+        let mut camera = self.clone();
+        camera.center = center;
+        camera.lookat = lookat;
+        camera.focus_distance = focus_distance;
+        camera.orient();
+        camera
+    }
+
+    /// Returns a copy of this camera rendering `width` by `height` pixels, with the same view and
+    /// vertical field of view. Shares the sample sequence and watchdog with the original.
+    pub fn with_resolution(&self, width: usize, height: usize) -> Self {
+        let mut camera = self.clone();
+        camera.image_width = width;
+        camera.image_height = height;
+        camera.orient();
+        camera
+    }
+
+    /// Returns a copy of this camera taking `samples_per_pixel` samples of every pixel in batch
+    /// renders, with paths `max_depth` bounces long at most. Shares the sample sequence and
+    /// watchdog with the original.
+    pub fn with_sampling(&self, samples_per_pixel: usize, max_depth: usize) -> Self {
+        let mut camera = self.clone();
+        camera.samples_per_pixel = samples_per_pixel;
+        camera.max_depth = max_depth;
+        camera
+    }
+
+    /// Recomputes the viewport and defocus disk from the camera's position and lens settings
+    fn orient(&mut self) {
+        let w = (self.center - self.lookat).normalize();
+        let u = camera_right(&self.up, &w);
         let v = w.cross(&u);
-        let h = (vertical_fov.to_radians() / 2.0).tan();
-        let viewport_height = 2.0 * h * focus_distance;
+        let h = (self.vertical_fov.to_radians() / 2.0).tan();
+        let viewport_height = 2.0 * h * self.focus_distance;
 
         // Viewport distance between pixels
-        let aspect_ratio = image_width as Float / image_height as Float;
+        let aspect_ratio = self.image_width as Float / self.image_height as Float;
         let viewport_width = viewport_height * aspect_ratio;
 
         // Displacement vectors from left to right and top to bottom of viewport
         let viewport_u = u * viewport_width; // Left to right across horizontal edge
         let viewport_v = -v * viewport_height; // Down vertical edge
 
-        let pixel_du = viewport_u / (image_width as Float);
-        let pixel_dv = viewport_v / (image_height as Float);
+        self.pixel_du = viewport_u / (self.image_width as Float);
+        self.pixel_dv = viewport_v / (self.image_height as Float);
 
-        let vp_upper_left = center - (w * focus_distance) - viewport_u / 2.0 - viewport_v / 2.0;
+        let vp_upper_left =
+            self.center - (w * self.focus_distance) - viewport_u / 2.0 - viewport_v / 2.0;
 
         // Top left pixel center
-        let pixel00_loc = vp_upper_left + (pixel_du + pixel_dv) / 2.0;
-
-        let defocus_radius = focus_distance * (defocus_angle / 2.0).to_radians().tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
-
-        let rng_map = halton_sequence(2, 1024 * 1024)
-            .zip(halton_sequence(3, 1024 * 1024))
-            .collect_vec();
+        self.pixel00_loc = vp_upper_left + (self.pixel_du + self.pixel_dv) / 2.0;
 
-        Camera {
-            center,
-            defocus_angle,
-            defocus_disk_u,
-            defocus_disk_v,
-            image_width,
-            image_height,
-            samples_per_pixel,
-            max_depth,
-            pixel00_loc,
-            pixel_du,
-            pixel_dv,
-            t_range,
-            rng_map,
-        }
+        let defocus_radius = self.focus_distance * (self.defocus_angle / 2.0).to_radians().tan();
+        self.defocus_disk_u = u * defocus_radius;
+        self.defocus_disk_v = v * defocus_radius;
     }
 
     /// Return a camera ray originating from the defocus disk and directed at a random
     /// point around the pixel location `x, y`.
-    fn get_ray(&self, x: usize, y: usize, i: usize) -> Ray {
+    fn get_ray(&self, x: usize, y: usize, i: usize, rng: &mut SampleRng) -> Ray {
         // Halton sequence sampling (I have no idea if I'm doing this right, I think not, but IDK)
         // https://psgraphics.blogspot.com/2018/10/flavors-of-sampling-in-ray-tracing.html
         // TODO: adaptive sampling? ReSTIR? No idea!
@@ -236,23 +1016,47 @@ impl Camera {
         // https://cseweb.ucsd.edu/classes/sp17/cse168-a/CSE168_07_Random.pdf
         // https://cs184.eecs.berkeley.edu/sp24
 
-        let offset = self.rng_map[i];
+        let quasi_random = self.fidelity.quasi_random_samples();
+        let offset = if quasi_random {
+            self.rng_map.pixel(i)
+        } else {
+            (rng.gen(), rng.gen())
+        };
 
         let pixel_sample = self.pixel00_loc
             + (self.pixel_du * (x as Float + offset.0))
             + (self.pixel_dv * (y as Float + offset.1));
-        // TODO: make this use an Option<Float> instead of a Float for when I want no blur at all
-        // Then it can avoid accessing the rng_map and doing extra math it doesn't have to
-        // kind of annoying since it requires some Camera refactoring
-        let origin = if self.defocus_angle <= 0.0 {
-            self.center // no blur
+        // Without blur every ray starts at the center, so the lens isn't sampled at all
+        if self.defocus_angle <= 0.0 {
+            return Ray::new(self.center.into(), pixel_sample - self.center);
+        }
+        let lens = if quasi_random {
+            self.rng_map.lens(i)
         } else {
-            // TODO: implement better sampling technique for this (QMC stuff)
-            self.defocus_disk_sample() // random blur
+            (rng.gen(), rng.gen())
         };
+        let origin = self.defocus_disk_sample(lens);
         Ray::new(origin.into(), pixel_sample - origin)
     }
 
+    /// The pixel `point` is seen in through the center of the lens, or `None` if it's behind the
+    /// camera or outside the image
+    pub fn pixel_of(&self, point: &Point3) -> Option<(usize, usize)> {
+        let forward = self.pixel_du.cross(&self.pixel_dv).normalize();
+        let direction = point - self.center;
+        let to_viewport = (self.pixel00_loc - self.center).dot(&forward);
+        let along = direction.dot(&forward);
+        if along * to_viewport <= 0.0 {
+            return None;
+        }
+        let on_viewport = self.center + direction * (to_viewport / along) - self.pixel00_loc;
+        let x = on_viewport.dot(&self.pixel_du) / self.pixel_du.norm_squared() + 0.5;
+        let y = on_viewport.dot(&self.pixel_dv) / self.pixel_dv.norm_squared() + 0.5;
+        let inside = (0.0..self.image_width as Float).contains(&x)
+            && (0.0..self.image_height as Float).contains(&y);
+        inside.then_some((x as usize, y as usize))
+    }
+
     pub fn debug_ray(&self, x: f64, y: f64) -> Ray {
         let pixel_sample =
             self.pixel00_loc + (self.pixel_du * (x as Float)) + (self.pixel_dv * (y as Float));
@@ -264,8 +1068,10 @@ impl Camera {
         world: &'a World,
         ray: &Ray,
     ) -> Option<(Intersection<'a>, Vec3, Option<Ray>)> {
-        if let Some(hit) = world.hit(ray, &(0.001..self.t_range.end)) {
-            if let Some((attenuation, scattered)) = hit.material.scatter(ray, &hit) {
+        if let Some(hit) = world.hit(ray, &(world.numeric.min_hit_distance..self.t_range.end)) {
+            if let Some((attenuation, scattered)) =
+                scatter_once(ray, &hit, &mut SampleRng::default())
+            {
                 Some((hit, attenuation, Some(scattered)))
             } else {
                 Some((hit, Vec3::zeros(), None)) // Light was absorbed, not scattered
@@ -275,98 +1081,1489 @@ impl Camera {
         }
     }
 
-    /// Returns whether the ray survives
-    /// TODO: benchmark this shit in both MSE and speed (or some weird combined MSE/second unit)
-    fn russian_roulette(&self, ray_color: Vec3) -> Option<Vec3> {
-        // TODO: how to add a constant parameter to this so that it on average keeps more rays than as is
-        let continue_probability = ray_color.max();
-        // Has to be max otherwise the largest color value could exceed 1.0 if the mean was less than 1.0
-        // ex: (1.0, 0.0, 0.0) -> mean of 1/3 -> probability 1/3 -> (3.0, 0.0, 0.0) BROKEN!!!
-        // This also holds for functions that can return a value less the the max
-        // May or may not be fixed by having colors in a non [0,1] range but tbh i have no idea
-        // maybe treating colors as probabilities will come back to bite me when i implement emissives...
-        if thread_rng().gen_bool(continue_probability) {
-            Some(ray_color * 1.0 / continue_probability)
+    /// Whether a path at `depth` whose throughput would become `throughput` plays russian
+    /// roulette before bouncing again. NaN throughput plays, so that the path ends.
+    fn plays_roulette(&self, depth: usize, throughput: &Vec3) -> bool {
+        self.fidelity.russian_roulette()
+            && depth >= self.roulette_min_depth
+            && (throughput.max() < self.throughput_threshold
+                || throughput.iter().any(|c| c.is_nan()))
+    }
+
+    /// Decides whether a path at `depth` whose throughput would become `throughput` after
+    /// bouncing with `attenuation` goes on, returning the attenuation to bounce with and the new
+    /// throughput if it does. Paths are only culled past the minimum depth once their throughput
+    /// drops below the threshold, with a survival chance of their throughput over the threshold
+    /// but at least [`MIN_CONTINUE_PROBABILITY`], and survivors are boosted by one over that
+    /// chance to make up for the ones that were culled so the result stays unbiased. Paths that
+    /// can't carry any more light end right away.
+    fn russian_roulette(
+        &self,
+        depth: usize,
+        attenuation: Vec3,
+        throughput: Vec3,
+        rng: &mut SampleRng,
+    ) -> Option<(Vec3, Vec3)> {
+        if !self.plays_roulette(depth, &throughput) {
+            return Some((attenuation, throughput));
+        }
+        let brightest = throughput.max();
+        // NaN would panic when drawing the survival chance
+        if throughput.iter().any(|c| c.is_nan()) || brightest <= 0.0 {
+            return None;
+        }
+        let continue_probability =
+            (brightest / self.throughput_threshold).clamp(MIN_CONTINUE_PROBABILITY, 1.0);
+        if rng.gen_bool(continue_probability) {
+            let boost = 1.0 / continue_probability;
+            Some((attenuation * boost, throughput * boost))
         } else {
             None
         }
     }
 
+    /// How wide a pixel's cone is by the time `path` gets where it is, ignoring how curved
+    /// mirrors and lenses along the way widen it
+    fn footprint(&self, path: &PathState) -> Float {
+        path.travelled * self.pixel_dv.norm() / self.focus_distance
+    }
+
+    /// Whether diffuse surfaces sample the sun's disc in `world` directly
+    fn samples_sun(&self, world: &World) -> bool {
+        self.sun_sampling && self.fidelity.sun_sampling() && world.sun().is_some()
+    }
+
+    /// Estimates the light reaching a diffuse hit straight from the sky, by sampling the sky where
+    /// it's brightest. `also_bounces` says whether the path will also bounce off the hit, in which
+    /// case this is weighted against the bounce finding the sky. Glass and alpha-masked surfaces
+    /// in the way dim the light instead of blocking it. Multiply by the surface's albedo.
+    fn sky_lighting(
+        &self,
+        world: &World,
+        hit: &Intersection,
+        also_bounces: bool,
+        rng: &mut SampleRng,
+    ) -> Vec3 {
+        let sample = world.sample_sky_importance(rng);
+        let samples_sun = self.samples_sun(world);
+        self.direct_lighting(world, hit, &sample, None, also_bounces, |direction| {
+            if samples_sun {
+                world.pdf_sun(direction)
+            } else {
+                0.0
+            }
+        })
+    }
+
+    /// Like [`Camera::sky_lighting`], sampling the sun's disc instead, which the sky samples
+    /// almost never find. Weighted against them too.
+    fn sun_lighting(
+        &self,
+        world: &World,
+        hit: &Intersection,
+        also_bounces: bool,
+        rng: &mut SampleRng,
+    ) -> Vec3 {
+        let Some(sample) = world.sample_sun(rng) else {
+            return Vec3::zeros();
+        };
+        self.direct_lighting(world, hit, &sample, None, also_bounces, |direction| {
+            world.pdf_sky(direction)
+        })
+    }
+
+    /// Estimates the light reaching a diffuse hit straight from the spot and rect lights, from one
+    /// of them picked for where the hit is and which way it faces, see [`World::scene_lights`].
+    /// Rect lights that rays can hit are only sampled with
+    /// [`RenderFidelity::area_light_sampling`], weighted against the bounce finding them if
+    /// `also_bounces`, while spot lights and hidden rect lights are only ever found this way.
+    /// Multiply by the surface's albedo.
+    fn scene_lighting(
+        &self,
+        world: &World,
+        hit: &Intersection,
+        also_bounces: bool,
+        rng: &mut SampleRng,
+    ) -> Vec3 {
+        let Some((scene_light, selection_pdf)) =
+            world
+                .scene_lights()
+                .sample_for(&world.shapes, &hit.point, &hit.normal, rng)
+        else {
+            return Vec3::zeros();
+        };
+        let (sample, bounce_finds_it) = match scene_light {
+            // The bounce finds it instead, and picking it is a wasted sample
+            SceneLight::Rect(rect) if rect.visible && !self.fidelity.area_light_sampling() => {
+                return Vec3::zeros()
+            }
+            SceneLight::Rect(rect) => (rect.sample(&hit.point, rng), rect.visible),
+            SceneLight::Spot(spot) => (spot.sample(&hit.point), false),
+        };
+        let Some((mut sample, on_light)) = sample else {
+            return Vec3::zeros();
+        };
+        // Picking the light is part of picking the direction, so its chance goes in the density
+        sample.pdf *= selection_pdf;
+        let also_bounces = also_bounces && bounce_finds_it;
+        self.direct_lighting(world, hit, &sample, Some(on_light), also_bounces, |_| 0.0)
+    }
+
+    /// The light from `sample` reaching a diffuse hit, weighted against the bounce if
+    /// `also_bounces` and against the other light sampling strategy, with density `other_pdf`.
+    /// `on_light` is the point the sample is toward, or `None` for light from infinitely far
+    /// away like the sky's.
+    fn direct_lighting(
+        &self,
+        world: &World,
+        hit: &Intersection,
+        sample: &SkySample,
+        on_light: Option<Point3>,
+        also_bounces: bool,
+        other_pdf: impl Fn(&Vec3) -> Float,
+    ) -> Vec3 {
+        let cos_theta = sample.direction.dot(&hit.normal);
+        if cos_theta <= 0.0 || sample.pdf <= 0.0 {
+            return Vec3::zeros();
+        }
+        let origin = world
+            .numeric
+            .offset_ray_origin(&hit.point, &hit.normal, &sample.direction);
+        // Aimed from the offset origin right at the light, stopping short so a light that rays
+        // can hit doesn't shadow itself
+        let (direction, end) = match on_light {
+            Some(on_light) => {
+                let to_light = on_light - origin;
+                let distance = to_light.norm();
+                (
+                    to_light / distance,
+                    distance - world.numeric.min_hit_distance,
+                )
+            }
+            None => (sample.direction, self.t_range.end),
+        };
+        let shadow_ray = Ray::new(origin.into(), direction);
+        let range = world.numeric.min_hit_distance..end.min(self.t_range.end);
+        let transmittance = world.transmittance(&shadow_ray, &range);
+        if transmittance == Vec3::zeros() {
+            #[cfg(feature = "diagnostics")]
+            if crate::splat::recording() {
+                if let Some(blocker) = world.hit(&shadow_ray, &range) {
+                    crate::splat::record_shadow(crate::splat::ShadowSplat {
+                        point: blocker.point,
+                        distance: blocker.t * shadow_ray.direction.norm(),
+                    });
+                }
+            }
+            return Vec3::zeros();
+        }
+        let bounce_pdf = cos_theta / PI;
+        let competing_bounce_pdf = if also_bounces { bounce_pdf } else { 0.0 };
+        let weight = power_heuristic(
+            sample.pdf,
+            competing_bounce_pdf.hypot(other_pdf(&sample.direction)),
+        );
+        // The Lambertian BRDF times the cosine is the albedo times `bounce_pdf`
+        sample.radiance.component_mul(&transmittance) * (bounce_pdf * weight / sample.pdf)
+    }
+
     /// Fires a ray from the camera into the world and recursively bounces to determine the ray's color
-    fn raycast(&self, world: &World, ray: &Ray, depth: usize) -> Vec3 {
-        if let Some(hit) = world.hit(ray, &(0.001..self.t_range.end)) {
-            if let Some((attenuation, scattered)) = hit.material.scatter(ray, &hit) {
+    /// `path` is where the path is as the ray leaves, with `travelled` up to the ray's origin
+    /// `zero_advance` tracks how many bounces in a row failed to move the path forward
+    /// `diffuse_normal` is the normal of the diffuse surface the ray bounced off, if that surface
+    /// also sampled the sky or rect lights directly
+    /// `trace` collects what happens at each bounce, when debugging a single sample
+    /// `rng` is the sample's random numbers
+    #[allow(clippy::too_many_arguments)]
+    fn raycast(
+        &self,
+        world: &World,
+        ray: &Ray,
+        path: PathState,
+        zero_advance: ZeroAdvance,
+        diffuse_normal: Option<Vec3>,
+        trace: Option<&mut Vec<PathEvent>>,
+        rng: &mut SampleRng,
+    ) -> Vec3 {
+        let hit = world.hit(ray, &(world.numeric.min_hit_distance..self.t_range.end));
+        self.shade(
+            world,
+            ray,
+            self.overridden(hit),
+            path,
+            zero_advance,
+            diffuse_normal,
+            trace,
+            rng,
+        )
+    }
+
+    /// Determines the color of `ray` from what it hit, or the sky if `hit` is `None`
+    #[allow(clippy::too_many_arguments)]
+    fn shade(
+        &self,
+        world: &World,
+        ray: &Ray,
+        hit: Option<Intersection>,
+        path: PathState,
+        zero_advance: ZeroAdvance,
+        diffuse_normal: Option<Vec3>,
+        mut trace: Option<&mut Vec<PathEvent>>,
+        rng: &mut SampleRng,
+    ) -> Vec3 {
+        let depth = path.depth;
+        self.watchdog.record_depth(depth);
+        if let Some(hit) = hit {
+            if let Material::ShadowCatcher(_) = hit.material {
+                // Stands in for the ground of whatever the render is composited over
+                let direction = ray.direction.normalize();
+                let lit = shadow_matte::caught_light(world, &hit.point, &hit.normal, rng);
+                return world.sky_color_toward(&direction) * lit;
+            }
+            // Guard against paths that keep hitting the same point (e.g. degenerate scatter
+            // directions), which would otherwise burn through the whole depth budget in place
+            let zero_advance = zero_advance.after(hit.point, world.numeric.zero_advance_distance);
+            let mut emitted = hit.material.emitted(&hit);
+            let samples_area_lights = self.fidelity.area_light_sampling();
+            if let Some(normal) =
+                diffuse_normal.filter(|_| samples_area_lights && emitted != Vec3::zeros())
+            {
+                // The surface the ray bounced off also sampled this light directly, if it's one
+                let lights = world.scene_lights();
+                if let Some((light, rect)) = lights.rect_with(&world.shapes, hit.material) {
+                    let bounce_pdf = ray.direction.normalize().dot(&normal).max(0.0) / PI;
+                    let origin = ray.origin.coords;
+                    let light_pdf = lights.selection_pdf(&origin, &normal, light)
+                        * rect.pdf(&origin, &hit.point);
+                    emitted *= power_heuristic(bounce_pdf, light_pdf);
+                }
+            }
+            let mut bounce = trace.is_some().then(|| BounceEvent {
+                depth,
+                object: hit.object,
+                material: hit.material.name(),
+                t: hit.t,
+                point: hit.point,
+                normal: hit.normal,
+                uv: hit.uv,
+                is_front_face: hit.is_front_face,
+                scattered: None,
+                sky_light: Vec3::zeros(),
+                scene_light: Vec3::zeros(),
+                emitted,
+                survived_roulette: None,
+            });
+            if zero_advance.streak > MAX_ZERO_ADVANCE_STREAK {
+                self.watchdog.record_zero_advance_termination();
+                if let (Some(trace), Some(bounce)) = (trace, bounce) {
+                    trace.push(PathEvent::Bounce(bounce));
+                    trace.push(PathEvent::Stuck);
+                }
+                return Vec3::zeros();
+            }
+
+            let path = PathState {
+                travelled: path.travelled + hit.t * ray.direction.norm(),
+                ..path
+            };
+            let mut context = ShadingContext::new(ray, &hit, rng)
+                .with_path(path)
+                .with_footprint(self.footprint(&path));
+            if let Some((attenuation, mut scattered)) = hit.material.scatter(&mut context) {
+                if !hit.material.is_emissive() {
+                    validation::check(Stage::ScatterAttenuation, &attenuation);
+                }
+                let origin = world.numeric.offset_ray_origin(
+                    &scattered.origin.coords,
+                    &hit.normal,
+                    &scattered.direction,
+                );
+                scattered = Ray::new(origin.into(), scattered.direction);
+                if zero_advance.streak > 0 {
+                    // Nudge the next ray further off the surface so it has a chance to escape,
+                    // though not so far that it counts as having moved on by itself
+                    let offset =
+                        hit.normal * world.numeric.ray_offset * zero_advance.streak as Float;
+                    let offset = if scattered.direction.dot(&hit.normal) < 0.0 {
+                        -offset
+                    } else {
+                        offset
+                    };
+                    scattered = Ray::new(scattered.origin + offset, scattered.direction);
+                }
+                let bounces = depth < self.max_depth;
+                let is_diffuse = hit.material.is_diffuse();
+                let samples_sky = self.fidelity.sky_importance_sampling() && is_diffuse;
+                let sky_light = if samples_sky {
+                    let mut light = self.sky_lighting(world, &hit, bounces, rng);
+                    if self.samples_sun(world) {
+                        light += self.sun_lighting(world, &hit, bounces, rng);
+                    }
+                    attenuation.component_mul(&light)
+                } else {
+                    Vec3::zeros()
+                };
+                // Spot lights can only be found by sampling them, whatever the fidelity
+                let scene_light = if is_diffuse {
+                    attenuation.component_mul(&self.scene_lighting(world, &hit, bounces, rng))
+                } else {
+                    Vec3::zeros()
+                };
                 // Recursively send out new rays as they bounce until the depth limit or roulette
-                if depth < self.max_depth {
-                    if let Some(roulette_color) = self.russian_roulette(attenuation) {
-                        let bounced_ray = self.raycast(world, &scattered, depth + 1);
-                        return roulette_color.component_mul(&bounced_ray);
+                let next_throughput = path.throughput.component_mul(&attenuation);
+                let plays_roulette = bounces && self.plays_roulette(depth, &next_throughput);
+                let survivor = bounces
+                    .then(|| self.russian_roulette(depth, attenuation, next_throughput, rng))
+                    .flatten();
+                if let (Some(trace), Some(mut bounce)) = (trace.as_deref_mut(), bounce.take()) {
+                    bounce.scattered = Some((scattered.direction, attenuation));
+                    bounce.sky_light = sky_light;
+                    bounce.scene_light = scene_light;
+                    if plays_roulette {
+                        bounce.survived_roulette = Some(survivor.is_some());
                     }
+                    trace.push(PathEvent::Bounce(bounce));
+                    if !bounces {
+                        trace.push(PathEvent::DepthLimit);
+                    }
+                }
+                if let Some((survivor_color, next_throughput)) = survivor {
+                    let next_path = PathState {
+                        depth: depth + 1,
+                        throughput: next_throughput,
+                        ..path
+                    };
+                    let bounced_ray = self.raycast(
+                        world,
+                        &scattered,
+                        next_path,
+                        zero_advance,
+                        (is_diffuse && (samples_sky || samples_area_lights)).then_some(hit.normal),
+                        trace,
+                        rng,
+                    );
+                    return emitted
+                        + sky_light
+                        + scene_light
+                        + survivor_color.component_mul(&bounced_ray);
                 }
+                return emitted + sky_light + scene_light;
             }
-            Vec3::new(0.0, 0.0, 0.0) // Light was absorbed, not scattered
+            if let (Some(trace), Some(bounce)) = (trace, bounce) {
+                trace.push(PathEvent::Bounce(bounce));
+                trace.push(PathEvent::Absorbed);
+            }
+            emitted // Light was absorbed, not scattered
         } else {
             // Ray missed all other objects and hit the sky box
             let direction = ray.direction.normalize();
-            world.sky_color_toward(&direction)
+            let sky_color = world.sky_color_toward(&direction);
+            let weight = match diffuse_normal.filter(|_| self.fidelity.sky_importance_sampling()) {
+                // The surface also sampled the sky directly, and maybe the sun, so split the credit
+                Some(normal) => {
+                    let bounce_pdf = direction.dot(&normal).max(0.0) / PI;
+                    let sun_pdf = if self.samples_sun(world) {
+                        world.pdf_sun(&direction)
+                    } else {
+                        0.0
+                    };
+                    power_heuristic(bounce_pdf, world.pdf_sky(&direction).hypot(sun_pdf))
+                }
+                None => 1.0,
+            };
+            if let Some(trace) = trace {
+                trace.push(PathEvent::Escaped {
+                    direction,
+                    sky_color,
+                    weight,
+                });
+            }
+            sky_color * weight
         }
     }
 
+    /// Renders sample `i` of pixel `(x, y)`. With a seed set, the result depends only on the
+    /// seed, the pixel and `i`, so it can be replayed with [`Camera::trace_sample`].
+    pub fn render_sample(&self, world: &World, x: usize, y: usize, i: usize) -> Vec3 {
+        self.sample(world, x, y, i, None).0
+    }
+
+    /// Replays sample `i` of pixel `(x, y)`, recording every bounce of its path. Only matches
+    /// what a render got for that sample when the camera has a seed. Bidirectional and draft
+    /// samples don't record their paths.
+    pub fn trace_sample(&self, world: &World, x: usize, y: usize, i: usize) -> PathTrace {
+        let mut events = Vec::new();
+        let (radiance, _) = self.sample(world, x, y, i, Some(&mut events));
+        PathTrace {
+            pixel: (x, y),
+            sample: i,
+            seed: self.seed,
+            events,
+            radiance,
+        }
+    }
+
+    /// Returns the sample's color and whether its camera ray missed all geometry
+    fn sample(
+        &self,
+        world: &World,
+        x: usize,
+        y: usize,
+        i: usize,
+        trace: Option<&mut Vec<PathEvent>>,
+    ) -> (Vec3, bool) {
+        let mut rng = SampleRng::for_sample(self.seed, x, y, i);
+        let ray = self.get_ray(x, y, i, &mut rng);
+        let _hits = rng::seed_hits(&mut rng);
+        let hit = world.hit(&ray, &(world.numeric.min_hit_distance..self.t_range.end));
+        self.shade_camera_ray(world, &ray, hit, trace, &mut rng)
+    }
+
+    /// Returns the color of a camera ray that hit `hit` and whether it missed all geometry
+    fn shade_camera_ray(
+        &self,
+        world: &World,
+        ray: &Ray,
+        hit: Option<Intersection>,
+        trace: Option<&mut Vec<PathEvent>>,
+        rng: &mut SampleRng,
+    ) -> (Vec3, bool) {
+        let escaped = hit.is_none();
+        let color = match self.integrator {
+            Integrator::PathTracer => {
+                let hit = self.overridden(hit);
+                self.shade(
+                    world,
+                    ray,
+                    hit,
+                    PathState::default(),
+                    ZeroAdvance::default(),
+                    None,
+                    trace,
+                    rng,
+                )
+            }
+            Integrator::Bidirectional => {
+                bidirectional::radiance(world, ray, hit, self.max_depth, self.t_range.end, rng)
+            }
+            Integrator::Draft => draft::radiance(world, ray, self.overridden(hit)),
+            Integrator::Technical => technical::radiance(ray, self.overridden(hit)),
+        };
+        (color, escaped)
+    }
+
+    /// `hit` with the camera's material override applied, if it has one
+    fn overridden<'a>(&'a self, hit: Option<Intersection<'a>>) -> Option<Intersection<'a>> {
+        match &self.material_override {
+            Some(material_override) => hit.map(|hit| material_override.apply(hit)),
+            None => hit,
+        }
+    }
+
+    /// The camera ray of sample `i` of pixel `(x, y)`, which is the one [`Camera::render_sample`]
+    /// traces when the camera has a seed
+    pub fn primary_ray(&self, x: usize, y: usize, i: usize) -> Ray {
+        self.get_ray(x, y, i, &mut SampleRng::for_sample(self.seed, x, y, i))
+    }
+
+    /// Like [`Camera::render_pixel_escapes`], for camera rays whose first hits were found ahead
+    /// of time, like all at once on the GPU. `rays` holds each sample's ray from
+    /// [`Camera::primary_ray`], and `first_hit` returns what sample `i`'s ray hits first.
+    pub fn render_pixel_from_primary<'w>(
+        &self,
+        world: &'w World,
+        x: usize,
+        y: usize,
+        rays: &[Ray],
+        first_hit: impl Fn(usize) -> Option<Intersection<'w>> + Sync,
+    ) -> (Vec3, usize) {
+        let (color, escaped) = (0..rays.len())
+            .into_par_iter()
+            .map(|i| {
+                self.watchdog.begin_sample(x, y, i);
+                let mut rng = SampleRng::for_sample(self.seed, x, y, i);
+                // Draws the ray's random numbers again, so the rest of the sample gets the same
+                // ones it would have without the ray found ahead of time
+                self.get_ray(x, y, i, &mut rng);
+                let _hits = rng::seed_hits(&mut rng);
+                let (color, escaped) =
+                    self.shade_camera_ray(world, &rays[i], first_hit(i), None, &mut rng);
+                validation::check(Stage::AccumulationInput, &color);
+                self.watchdog.end_sample();
+                (color, usize::from(escaped))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold((Vec3::zeros(), 0), sum_samples);
+        (color / rays.len().max(1) as Float, escaped)
+    }
+
     pub fn render_pixel(&self, world: &World, x: usize, y: usize, num_samples: usize) -> Vec3 {
+        self.render_pixel_escapes(world, x, y, num_samples).0
+    }
+
+    /// Like [`Camera::render_pixel`], but also returns how many of the samples' camera rays
+    /// missed all geometry and went straight to the sky
+    pub fn render_pixel_escapes(
+        &self,
+        world: &World,
+        x: usize,
+        y: usize,
+        num_samples: usize,
+    ) -> (Vec3, usize) {
+        let (color, escaped, _cost) = self.render_pixel_cost(world, x, y, num_samples);
+        (color, escaped)
+    }
+
+    /// Like [`Camera::render_pixel_escapes`], but also returns what the samples cost, as the
+    /// watchdog timed and counted them
+    pub fn render_pixel_cost(
+        &self,
+        world: &World,
+        x: usize,
+        y: usize,
+        num_samples: usize,
+    ) -> (Vec3, usize, PixelCost) {
+        let mut total = (Vec3::zeros(), 0);
+        let mut cost = PixelCost::default();
+        for (color, escaped, sample_cost) in self.pixel_samples(world, x, y, num_samples) {
+            total = sum_samples(total, (color, escaped));
+            cost += sample_cost;
+        }
+        let (color, escaped) = total;
+        (color / num_samples as Float, escaped, cost) // average color across all samples
+    }
+
+    /// Like [`Camera::render_pixel_cost`], but returns the samples' [`PixelMoments`] instead of
+    /// their average, so their variance is known too
+    pub fn render_pixel_moments(
+        &self,
+        world: &World,
+        x: usize,
+        y: usize,
+        num_samples: usize,
+    ) -> (PixelMoments, usize, PixelCost) {
+        let mut moments = PixelMoments::default();
+        let mut escaped = 0;
+        let mut cost = PixelCost::default();
+        for (color, sample_escaped, sample_cost) in self.pixel_samples(world, x, y, num_samples) {
+            moments.add(color);
+            escaped += sample_escaped;
+            cost += sample_cost;
+        }
+        (moments, escaped, cost)
+    }
+
+    /// Renders `num_samples` samples of pixel `(x, y)` in parallel, returning each one's color,
+    /// whether it escaped and what it cost
+    fn pixel_samples(
+        &self,
+        world: &World,
+        x: usize,
+        y: usize,
+        num_samples: usize,
+    ) -> Vec<(Vec3, usize, SampleCost)> {
         (0..num_samples)
             .into_par_iter()
             .map(|i| {
                 // TODO: the way this uses its "random" samples is really suspicious...
-                let ray = self.get_ray(x, y, i);
-                self.raycast(world, &ray, 0)
+                self.watchdog.begin_sample(x, y, i);
+                let (color, escaped) = self.sample(world, x, y, i, None);
+                validation::check(Stage::AccumulationInput, &color);
+                let cost = self.watchdog.end_sample();
+                (color, usize::from(escaped), cost)
             })
-            .sum::<Vec3>()
-            / num_samples as Float // average color across all samples
+            .collect()
     }
 
+    /// Renders every pixel of the image in [`DEFAULT_TILE_SIZE`] tiles, see
+    /// [`Camera::render_image_in_tiles`]
     pub fn render_image(&self, world: &World) -> Image {
-        let colors = (0..self.image_height)
+        self.render_image_in_tiles(world, DEFAULT_TILE_SIZE)
+    }
+
+    /// Renders every pixel of the image, a square tile `tile_size` pixels wide at a time on the
+    /// global pool, so each task works on neighboring pixels that hit the same textures and
+    /// BVH nodes. The progress bar counts tiles.
+    pub fn render_image_in_tiles(&self, world: &World, tile_size: usize) -> Image {
+        self.render_tiles(world, tile_size, None)
+    }
+
+    /// Like [`Camera::render_image`], also adding what every pixel's samples cost to `costs`,
+    /// which should be the image's size
+    pub fn render_image_with_cost(&self, world: &World, costs: &CostMap) -> Image {
+        self.render_tiles(world, DEFAULT_TILE_SIZE, Some(costs))
+    }
+
+    fn render_tiles(&self, world: &World, tile_size: usize, costs: Option<&CostMap>) -> Image {
+        let (width, height) = (self.image_width, self.image_height);
+        let rendered: Vec<(Tile, Vec<Vec3>)> = tiles::tiles(width, height, tile_size)
+            .into_par_iter()
+            .progress()
+            .map(|tile| {
+                let colors = tile
+                    .pixels()
+                    .map(|(x, y)| {
+                        let (color, _, cost) =
+                            self.render_pixel_cost(world, x, y, self.samples_per_pixel);
+                        if let Some(costs) = costs {
+                            costs.add(x, y, &cost);
+                        }
+                        color
+                    })
+                    .collect();
+                (tile, colors)
+            })
+            .collect();
+        let mut pixels = vec![Vec3::zeros(); width * height];
+        for (tile, colors) in rendered {
+            for (y, row) in tile.ys.clone().zip(colors.chunks_exact(tile.xs.len())) {
+                let start = y * width + tile.xs.start;
+                pixels[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+        let mut image = self.image_from_pixels(pixels);
+        self.add_alpha(world, &mut image);
+        match &self.quick_denoise {
+            Some(params) => image.denoise(params),
+            None => image,
+        }
+    }
+
+    /// Renders how much of each pixel the scene covers, for compositing it over something else,
+    /// averaged over `num_samples` camera rays like [`Camera::render_guides`]. Rays that escape
+    /// cover nothing, and ones that hit a shadow catcher cover it as much as it's in shadow, see
+    /// [`shadow_matte::caught_light`]. Everything else is fully covered.
+    pub fn render_alpha(&self, world: &World, num_samples: usize) -> Vec<Float> {
+        let num_samples = num_samples.max(1);
+        let range = world.numeric.min_hit_distance..self.t_range.end;
+        (0..self.image_height)
             .cartesian_product(0..self.image_width)
             .collect_vec()
             .into_par_iter()
-            .progress()
-            .map(|(y, x)| (x, y, self.render_pixel(world, x, y, self.samples_per_pixel)))
-            .collect::<_>();
+            .map(|(y, x)| {
+                let covered: Float = (0..num_samples)
+                    .map(|i| {
+                        let mut rng = SampleRng::for_sample(self.seed, x, y, i);
+                        let ray = self.get_ray(x, y, i, &mut rng);
+                        let _hits = rng::seed_hits(&mut rng);
+                        match world.hit(&ray, &range) {
+                            None => 0.0,
+                            Some(hit) if matches!(hit.material, Material::ShadowCatcher(_)) => {
+                                1.0 - shadow_matte::caught_light(
+                                    world,
+                                    &hit.point,
+                                    &hit.normal,
+                                    &mut rng,
+                                )
+                            }
+                            Some(_) => 1.0,
+                        }
+                    })
+                    .sum();
+                covered / num_samples as Float
+            })
+            .collect()
+    }
+
+    /// Gives `image`, rendered by this camera, an alpha channel if `world` has a shadow catcher
+    /// and it doesn't have one yet, so it can be composited over something else
+    pub fn add_alpha(&self, world: &World, image: &mut Image) {
+        if image.alpha.is_none() && world.has_shadow_catchers() {
+            image.alpha = Some(self.render_alpha(world, self.samples_per_pixel));
+        }
+    }
 
+    /// Renders the albedo and normal of whatever each pixel's camera rays first hit, averaged
+    /// over `num_samples` rays so edges are antialiased like the render. Rays that escape get
+    /// the sky's color as albedo and no normal. Meant as guides for denoising.
+    pub fn render_guides(&self, world: &World, num_samples: usize) -> (Image, Image) {
+        let num_samples = num_samples.max(1);
+        let range = world.numeric.min_hit_distance..self.t_range.end;
+        let (albedo, normal): (Vec<Vec3>, Vec<Vec3>) = (0..self.image_height)
+            .cartesian_product(0..self.image_width)
+            .collect_vec()
+            .into_par_iter()
+            .map(|(y, x)| {
+                let (mut albedo, mut normal) = (Vec3::zeros(), Vec3::zeros());
+                for i in 0..num_samples {
+                    let mut rng = SampleRng::for_sample(self.seed, x, y, i);
+                    let ray = self.get_ray(x, y, i, &mut rng);
+                    let _hits = rng::seed_hits(&mut rng);
+                    let Some(hit) = world.hit(&ray, &range) else {
+                        let direction = ray.direction.normalize();
+                        albedo += world.sky_color_toward(&direction);
+                        continue;
+                    };
+                    albedo += match scatter_once(&ray, &hit, &mut rng) {
+                        Some((attenuation, _)) => attenuation,
+                        None => hit.material.emitted(&hit),
+                    };
+                    normal += hit.normal;
+                }
+                let albedo = (albedo / num_samples as Float).map(|c| c.clamp(0.0, 1.0));
+                let normal = normal / num_samples as Float;
+                (albedo, normal)
+            })
+            .unzip();
+        let image = |pixels| Image {
+            pixels,
+            width: self.image_width,
+            height: self.image_height,
+            gamma: self.gamma,
+            metadata: Vec::new(),
+            alpha: None,
+        };
+        (image(albedo), image(normal))
+    }
+
+    /// Wraps pixels rendered by the camera, a row at a time from the top, in an image, with its
+    /// settings as metadata
+    pub fn image_from_pixels(&self, pixels: Vec<Vec3>) -> Image {
+        let mut metadata = vec![
+            format!("samples per pixel: {}", self.samples_per_pixel),
+            format!("max depth: {}", self.max_depth),
+            format!("fidelity: {}", self.fidelity.name()),
+            format!("integrator: {}", self.integrator.name()),
+            format!("throughput threshold: {}", self.throughput_threshold),
+            format!("gamma: {}", self.gamma),
+        ];
+        if let Some(seed) = self.seed {
+            metadata.push(format!("seed: {}", seed));
+        }
+        if !self.sun_sampling {
+            metadata.push("sun sampling: off".to_string());
+        }
+        if self.roulette_min_depth != DEFAULT_ROULETTE_MIN_DEPTH {
+            metadata.push(format!("roulette min depth: {}", self.roulette_min_depth));
+        }
         Image {
-            pixels: colors,
+            pixels,
             width: self.image_width,
             height: self.image_height,
+            gamma: self.gamma,
+            metadata,
+            alpha: None,
         }
     }
 
     pub fn write_image(image: Image, out_file: File) -> std::io::Result<()> {
         let mut buf_writer = BufWriter::new(out_file);
+        buf_writer.write_all(&image.encode_ppm())?;
+        buf_writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the image to `path` as an 8-bit RGB PNG, or RGBA if it has an alpha channel, see
+    /// [`Image::encode_png`]
+    pub fn write_png(image: Image, path: &Path) -> std::io::Result<()> {
+        let bytes = image.encode_png().map_err(std::io::Error::other)?;
+        std::fs::write(path, bytes)
+    }
+
+    /// Writes the image to `path` as a PNG if it ends in `.png`, and as a PPM otherwise
+    pub fn save_image(image: Image, path: &Path) -> std::io::Result<()> {
+        let is_png = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        if is_png {
+            Camera::write_png(image, path)
+        } else {
+            Camera::write_image(image, File::create(path)?)
+        }
+    }
+
+    /// Where an image bound for `path` is written until it's finished: next to it, with the same
+    /// extension so it's saved in the same format
+    pub fn partial_image_path(path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{}.partial.{}", stem, extension.to_string_lossy()),
+            None => format!("{}.partial", stem),
+        };
+        path.with_file_name(name)
+    }
+
+    /// Checks an image can be saved to `path` before it's rendered, by creating the file
+    /// [`Camera::replace_image`] writes it to first, and returns that file's path. Whatever is at
+    /// `path` already is left alone.
+    pub fn claim_image_path(path: &Path) -> std::io::Result<PathBuf> {
+        if path.is_dir() {
+            return Err(std::io::Error::other("it's a directory"));
+        }
+        let partial = Camera::partial_image_path(path);
+        File::create(&partial)?;
+        Ok(partial)
+    }
+
+    /// Saves the image next to `path` with [`Camera::save_image`] and then moves it there, so
+    /// `path` only ever holds a finished image
+    pub fn replace_image(image: Image, path: &Path) -> std::io::Result<()> {
+        let partial = Camera::partial_image_path(path);
+        Camera::save_image(image, &partial)?;
+        std::fs::rename(&partial, path)
+    }
 
-        // Write header metadata necessary for PPM file:
-        let header = format!(
-            "P3\n{} {} # width, height\n255 # max color value\n",
-            image.width, image.height
+    /// Returns the point of the camera's defocus disk that `(u, v)` in the unit square maps to
+    fn defocus_disk_sample(&self, (u, v): (Float, Float)) -> Vec3 {
+        let p = Vec3::from_unit_square_to_disc(u, v);
+        self.center + (self.defocus_disk_u * p.x) + (self.defocus_disk_v * p.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Background, Shape, Sphere, Triangle},
+        lights::LightSelection,
+        material::{Lambertian, Metal},
+        scene_lights::{RectLight, SpotLight},
+        scenes,
+        scratch::scratch,
+        sky_importance::luminance,
+        texture::SolidColor,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::time::Instant;
+
+    fn lambertian(albedo: Vec3) -> Arc<Material> {
+        Arc::new(Lambertian::new(SolidColor::new(albedo).into()).into())
+    }
+
+    /// A gray floor under a spot light, in the dark so the spot is all that lights it
+    fn spot_lit_floor() -> World {
+        let gray = lambertian(Vec3::repeat(0.5));
+        let floor = Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, gray).into();
+        let spot = SpotLight::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            -Vec3::z(),
+            Vec3::repeat(50.0),
+            20.0,
+            30.0,
         );
-        buf_writer.write_all(header.as_bytes())?;
+        let mut world = World::build(vec![floor, Shape::SpotLight(spot)]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::zeros(),
+            top: Vec3::zeros(),
+        };
+        world
+    }
 
-        // Write the colors in the PPM format with integer RGB values in [0, 255]
-        for (x, _y, color) in image.pixels.into_iter().progress() {
-            buf_writer.write_all(color.as_rgb_gamma_string().as_bytes())?;
-            if x == image.width - 1 {
-                buf_writer.write_all("\n".as_bytes())?;
-            } else {
-                buf_writer.write_all(" ".as_bytes())?;
+    /// A gray floor and ball lit by a rect light that rays can hit, a spot light and a dim
+    /// gradient sky, so every light sampling strategy has something to find
+    fn lit_box() -> World {
+        let gray = lambertian(Vec3::repeat(0.5));
+        let red = lambertian(Vec3::new(0.7, 0.2, 0.2));
+        let floor = Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, gray).into();
+        let ball = Sphere::new(Vec3::new(0.0, 0.0, 1.0), 1.0, red).into();
+        let rect = RectLight::new(
+            Vec3::new(-1.0, 1.0, 4.0),
+            Vec3::x() * 2.0,
+            -Vec3::y() * 2.0,
+            Vec3::repeat(4.0),
+        );
+        let spot = SpotLight::new(
+            Vec3::new(2.0, -2.0, 4.0),
+            Vec3::new(-0.4, 0.4, -1.0),
+            Vec3::repeat(60.0),
+            15.0,
+            25.0,
+        );
+        let mut world = World::build(vec![
+            floor,
+            ball,
+            Shape::RectLight(rect),
+            Shape::SpotLight(spot),
+        ]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::repeat(0.05),
+            top: Vec3::new(0.2, 0.3, 0.5),
+        };
+        world
+    }
+
+    /// An image of smoothly varying colors
+    fn gradient_image(width: usize, height: usize) -> Image {
+        let pixels = (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| {
+                    Vec3::new(
+                        x as Float / width as Float,
+                        y as Float / height as Float,
+                        0.5,
+                    )
+                })
+            })
+            .collect();
+        Image {
+            pixels,
+            width,
+            height,
+            gamma: 2.0,
+            metadata: vec!["test".into()],
+            alpha: None,
+        }
+    }
+
+    #[test]
+    fn ppm_encoding_matches_pixel_by_pixel() {
+        let image = gradient_image(37, 11);
+        let mut expected = ppm_header(37, 11, &image.metadata).into_bytes();
+        expected.extend(image.pixels.iter().flat_map(|color| rgb8(color, 2.0)));
+        assert_eq!(image.encode_ppm(), expected);
+    }
+
+    #[test]
+    fn ppm_encoding_speeds_up_with_threads() {
+        let threads = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        let image = gradient_image(1920, 1080);
+        let encode = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let start = Instant::now();
+            let bytes = pool.install(|| image.encode_ppm());
+            (bytes, start.elapsed())
+        };
+        let (single, single_time) = encode(1);
+        let (many, many_time) = encode(threads);
+        assert!(
+            single == many,
+            "{} threads encoded different bytes",
+            threads
+        );
+        // Only a machine with cores to spare can show the speedup
+        if threads >= 4 {
+            assert!(
+                many_time * 2 < single_time,
+                "{} threads took {:?}, one took {:?}",
+                threads,
+                many_time,
+                single_time
+            );
+        }
+    }
+
+    fn test_camera(fidelity: RenderFidelity) -> Camera {
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -8.0, 4.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(30.0)
+            .with_resolution(8, 8)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        camera.fidelity = fidelity;
+        camera.seed = Some(7);
+        camera
+    }
+
+    /// Average luminance of every pixel with `samples` samples each
+    fn mean_luminance(camera: &Camera, world: &World, samples: usize) -> Float {
+        let (width, height) = (camera.image_width, camera.image_height);
+        let total: Float = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| luminance(&camera.render_pixel(world, x, y, samples)))
+            .sum();
+        total / (width * height) as Float
+    }
+
+    /// Average luminance of every pixel with `samples` samples each, and the variance of that
+    /// average going by each pixel's own variance
+    fn mean_luminance_and_variance(
+        camera: &Camera,
+        world: &World,
+        samples: usize,
+    ) -> (Float, Float) {
+        let (width, height) = (camera.image_width, camera.image_height);
+        let pixels = (width * height) as Float;
+        let (mean, variance) = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| camera.render_pixel_moments(world, x, y, samples).0)
+            .fold((0.0, 0.0), |(mean, variance), moments| {
+                (
+                    mean + moments.luminance_mean(),
+                    variance + moments.luminance_variance() / samples as Float,
+                )
+            });
+        (mean / pixels, variance / (pixels * pixels))
+    }
+
+    #[test]
+    fn production_agrees_with_reference() {
+        let world = lit_box();
+        let (reference, reference_variance) =
+            mean_luminance_and_variance(&test_camera(RenderFidelity::Reference), &world, 64);
+        let (production, production_variance) =
+            mean_luminance_and_variance(&test_camera(RenderFidelity::Production), &world, 64);
+        // Four standard deviations of the difference, which a correct renderer almost never
+        // strays past
+        let bound = 4.0 * (reference_variance + production_variance).sqrt();
+        assert!(
+            (reference - production).abs() < bound,
+            "reference {} and production {} differ by more than {}",
+            reference,
+            production,
+            bound
+        );
+    }
+
+    #[test]
+    fn throughput_termination_matches_deep_paths_in_closed_rooms() {
+        let rooms = [
+            (scenes::cornell_box_camera(), scenes::cornell_box()),
+            (scenes::enclosed_room_camera(), scenes::enclosed_room()),
+        ];
+        for (camera, (shapes, surroundings)) in rooms {
+            let world = surroundings.build(shapes);
+            let mut camera = camera.with_resolution(8, 8);
+            camera.integrator = Integrator::PathTracer;
+            camera.seed = Some(7);
+            // Every path followed to the depth limit there was before roulette took over
+            let mut deep = camera.with_sampling(128, 100);
+            deep.fidelity = RenderFidelity::Reference;
+            let mut terminated = camera.with_sampling(128, MAX_DEPTH);
+            terminated.fidelity = RenderFidelity::Production;
+            let (deep_mean, deep_variance) = mean_luminance_and_variance(&deep, &world, 128);
+            let (mean, variance) = mean_luminance_and_variance(&terminated, &world, 128);
+            let bound = 4.0 * (deep_variance + variance).sqrt();
+            assert!(
+                (deep_mean - mean).abs() < bound,
+                "depth 100 gives {} and throughput termination {}, more than {} apart",
+                deep_mean,
+                mean,
+                bound
+            );
+        }
+    }
+
+    #[test]
+    fn replayed_samples_match_the_render() {
+        let world = lit_box();
+        let samples = 4;
+        for fidelity in [RenderFidelity::Reference, RenderFidelity::Production] {
+            let camera = test_camera(fidelity);
+            for (x, y) in [(0, 0), (3, 4), (4, 6), (7, 2)] {
+                let traces: Vec<PathTrace> = (0..samples)
+                    .map(|i| camera.trace_sample(&world, x, y, i))
+                    .collect();
+                for (i, trace) in traces.iter().enumerate() {
+                    assert!(!trace.events.is_empty());
+                    assert_eq!(trace.radiance, camera.render_sample(&world, x, y, i));
+                }
+                // Summed in the same order the render sums its samples
+                let replayed = traces
+                    .iter()
+                    .fold(Vec3::zeros(), |total, trace| total + trace.radiance)
+                    / samples as Float;
+                assert_eq!(
+                    replayed,
+                    camera.render_pixel(&world, x, y, samples),
+                    "{:?} pixel ({}, {})",
+                    fidelity,
+                    x,
+                    y
+                );
             }
         }
-        buf_writer.flush()?;
-        Ok(())
     }
 
-    /// Returns a random point in the camera's defocus disk
-    fn defocus_disk_sample(&self) -> Vec3 {
-        // TODO: QMC? No idea how, though!
-        let p: Vec3 = Vec3::random_in_unit_disc(&mut thread_rng());
-        self.center + (self.defocus_disk_u * p.x) + (self.defocus_disk_v * p.y)
+    #[test]
+    fn seeded_renders_repeat_exactly() {
+        let world = lit_box();
+        for fidelity in [RenderFidelity::Reference, RenderFidelity::Production] {
+            let render = |seed| {
+                let mut camera = Camera::builder()
+                    .with_look_from(Vec3::new(0.0, -8.0, 4.0))
+                    .with_look_at(Vec3::zeros())
+                    .with_vertical_fov(30.0)
+                    .with_resolution(32, 32)
+                    .with_samples(4)
+                    .with_max_depth(8)
+                    .build()
+                    .unwrap();
+                camera.fidelity = fidelity;
+                camera.seed = Some(seed);
+                camera.render_image(&world).pixels
+            };
+            let first = render(11);
+            assert!(first == render(11), "{} renders differ", fidelity.name());
+            assert!(
+                first != render(12),
+                "{} renders ignore the seed",
+                fidelity.name()
+            );
+        }
+    }
+
+    #[test]
+    fn zero_advance_grows_only_while_hits_stay_put() {
+        let start = ZeroAdvance::default().after(Vec3::zeros(), 0.1);
+        assert_eq!(start.streak, 0);
+        let stuck = start.after(Vec3::new(0.05, 0.0, 0.0), 0.1);
+        assert_eq!(stuck.streak, 1);
+        let moved = stuck.after(Vec3::new(1.0, 0.0, 0.0), 0.1);
+        assert_eq!(moved.streak, 0);
+    }
+
+    #[test]
+    fn paths_stuck_in_a_pocket_are_cut_off() {
+        // The pocket is smaller than the zero-advance distance of a scene stretched out by the
+        // far ball, so the paths that bounce around inside it never count as moving
+        let white = lambertian(Vec3::repeat(0.9));
+        let pocket = Sphere::new(Vec3::zeros(), 3e-5, white.clone()).into();
+        let far_ball = Sphere::new(Vec3::new(100.0, 0.0, 0.0), 1.0, white).into();
+        let world = World::build(vec![pocket, far_ball]);
+        assert!(6e-5 < world.numeric.zero_advance_distance);
+
+        let mut camera = Camera::builder()
+            .with_look_at(Vec3::x())
+            .with_resolution(4, 4)
+            .with_max_depth(50)
+            .build()
+            .unwrap();
+        camera.fidelity = RenderFidelity::Reference;
+        camera.seed = Some(3);
+        camera.render_pixel(&world, 1, 1, 16);
+        assert!(camera.watchdog.path_stats().zero_advance_terminations > 0);
+    }
+
+    /// A glossy ball on a gray floor under a rect light in the dark, with the light as a
+    /// [`RectLight`], or as two emissive triangles in its place that only paths can find
+    fn rect_lit_glossy_ball(light_as_geometry: bool) -> World {
+        let gray = lambertian(Vec3::repeat(0.5));
+        let glossy = Arc::new(Metal::new_solid(Vec3::repeat(0.8), Some(0.3)).into());
+        let floor = Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, gray).into();
+        let ball = Sphere::new(Vec3::new(0.0, 0.0, 1.0), 1.0, glossy).into();
+        let (corner, u, v) = (Vec3::new(-1.0, 1.0, 4.0), Vec3::x() * 2.0, -Vec3::y() * 2.0);
+        let rect = RectLight::new(corner, u, v, Vec3::repeat(4.0));
+        let mut shapes = vec![floor, ball];
+        if light_as_geometry {
+            let material = rect.material.clone();
+            let far = corner + u + v;
+            shapes.push(Triangle::new(corner, corner + u, far, material.clone()).into());
+            shapes.push(Triangle::new(corner, far, corner + v, material).into());
+        } else {
+            shapes.push(Shape::RectLight(rect));
+        }
+        let mut world = World::build(shapes);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::zeros(),
+            top: Vec3::zeros(),
+        };
+        world
+    }
+
+    #[test]
+    fn rect_lights_converge_to_the_emissive_surface_they_stand_for() {
+        let camera = test_camera(RenderFidelity::Production);
+        let (sampled, sampled_variance) =
+            mean_luminance_and_variance(&camera, &rect_lit_glossy_ball(false), 64);
+        let (found, found_variance) =
+            mean_luminance_and_variance(&camera, &rect_lit_glossy_ball(true), 64);
+        assert!(sampled > 0.01);
+        let bound = 4.0 * (sampled_variance + found_variance).sqrt();
+        assert!(
+            (sampled - found).abs() < bound,
+            "sampled light {} and emissive geometry {} differ by more than {}",
+            sampled,
+            found,
+            bound
+        );
+        // Sampling the light directly is the point, so it has to be less noisy than hoping to
+        // hit it
+        assert!(
+            sampled_variance < found_variance,
+            "{} {}",
+            sampled_variance,
+            found_variance
+        );
+    }
+
+    #[test]
+    fn reference_renders_sample_spot_lights() {
+        let world = spot_lit_floor();
+        let camera = test_camera(RenderFidelity::Reference);
+        assert!(mean_luminance(&camera, &world, 4) > 0.01);
+    }
+
+    /// A gray floor in the dark under `count` small hidden rect lights of very different power,
+    /// scattered over a ceiling well beyond what the camera sees
+    fn panel_lit_floor(count: usize, selection: LightSelection) -> World {
+        let mut rng = StdRng::seed_from_u64(5);
+        let gray = lambertian(Vec3::repeat(0.5));
+        let mut shapes = vec![Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, gray).into()];
+        for _ in 0..count {
+            let corner = Vec3::new(
+                rng.gen_range(-30.0..30.0),
+                rng.gen_range(-30.0..30.0),
+                rng.gen_range(3.0..4.0),
+            );
+            let radiance = Vec3::repeat((rng.gen::<Float>() * 6.0).exp());
+            let panel = RectLight::new(corner, Vec3::y() * 0.2, Vec3::x() * 0.2, radiance);
+            shapes.push(Shape::RectLight(panel.with_visible(false)));
+        }
+        let mut world = World::build(shapes);
+        world.light_selection = selection;
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::zeros(),
+            top: Vec3::zeros(),
+        };
+        world
+    }
+
+    #[test]
+    fn picking_lights_by_where_hits_are_cuts_the_noise_of_many_lights() {
+        let camera = test_camera(RenderFidelity::Production);
+        let (uniform, uniform_variance) = mean_luminance_and_variance(
+            &camera,
+            &panel_lit_floor(400, LightSelection::Uniform),
+            32,
+        );
+        let (tree, tree_variance) =
+            mean_luminance_and_variance(&camera, &panel_lit_floor(400, LightSelection::Tree), 32);
+        assert!(uniform > 0.01);
+        let bound = 4.0 * (uniform_variance + tree_variance).sqrt();
+        assert!(
+            (uniform - tree).abs() < bound,
+            "uniform {} and tree {} differ by more than {}",
+            uniform,
+            tree,
+            bound
+        );
+        // The samples needed for the same noise go with the variance
+        assert!(
+            tree_variance * 4.0 < uniform_variance,
+            "tree {} against uniform {}",
+            tree_variance,
+            uniform_variance
+        );
+    }
+
+    #[test]
+    fn roulette_waits_for_the_minimum_depth() {
+        let camera = test_camera(RenderFidelity::Production);
+        let mut rng = SampleRng::seeded(1, 0, 0, 0);
+        let (attenuation, dim) = (Vec3::repeat(0.5), Vec3::repeat(1e-9));
+        for depth in 0..camera.roulette_min_depth {
+            for _ in 0..1000 {
+                assert_eq!(
+                    camera.russian_roulette(depth, attenuation, dim, &mut rng),
+                    Some((attenuation, dim)),
+                    "depth {}",
+                    depth
+                );
+            }
+        }
+        // From then on dim paths mostly end, with survivors boosted by the least odds' inverse
+        let draws = 20_000;
+        let survivors: Vec<(Vec3, Vec3)> = (0..draws)
+            .filter_map(|_| {
+                camera.russian_roulette(camera.roulette_min_depth, attenuation, dim, &mut rng)
+            })
+            .collect();
+        let rate = survivors.len() as Float / draws as Float;
+        assert!((rate - MIN_CONTINUE_PROBABILITY).abs() < 0.01, "{}", rate);
+        let boost = 1.0 / MIN_CONTINUE_PROBABILITY;
+        assert!(survivors
+            .iter()
+            .all(|survivor| *survivor == (attenuation * boost, dim * boost)));
+        // Reference renders never play
+        let reference = test_camera(RenderFidelity::Reference);
+        assert_eq!(
+            reference.russian_roulette(10, attenuation, dim, &mut rng),
+            Some((attenuation, dim))
+        );
+    }
+
+    #[test]
+    fn roulette_never_panics_on_black_or_overbright_attenuation() {
+        let camera = test_camera(RenderFidelity::Production);
+        let mut rng = SampleRng::seeded(2, 0, 0, 0);
+        let depth = camera.roulette_min_depth;
+        // Nothing left to carry, or nothing that can be reasoned about
+        assert_eq!(
+            camera.russian_roulette(depth, Vec3::zeros(), Vec3::zeros(), &mut rng),
+            None
+        );
+        let nan = Vec3::repeat(Float::NAN);
+        assert_eq!(camera.russian_roulette(depth, nan, nan, &mut rng), None);
+        // Odds above one are clamped instead of panicking, and such bright paths don't play
+        let bright = Vec3::repeat(40.0);
+        assert_eq!(
+            camera.russian_roulette(depth, bright, bright, &mut rng),
+            Some((bright, bright))
+        );
+
+        // Whole renders of a pure black and an overbright surface
+        let black = Sphere::new(Vec3::new(-1.2, 0.0, 1.0), 1.0, lambertian(Vec3::zeros()));
+        let hot = Sphere::new(Vec3::new(1.2, 0.0, 1.0), 1.0, lambertian(Vec3::repeat(3.0)));
+        let floor = Sphere::new(
+            Vec3::new(0.0, 0.0, -1000.0),
+            1000.0,
+            lambertian(Vec3::repeat(0.01)),
+        );
+        let mut world = World::build(vec![black.into(), hot.into(), floor.into()]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::repeat(0.1),
+            top: Vec3::repeat(0.8),
+        };
+        let mut camera = camera;
+        camera.max_depth = 12;
+        for y in 0..camera.image_height {
+            for x in 0..camera.image_width {
+                let color = camera.render_pixel(&world, x, y, 8);
+                assert!(color.iter().all(|c| c.is_finite()), "({}, {})", x, y);
+            }
+        }
+        // No path played roulette before the minimum depth
+        for sample in 0..64 {
+            let trace = camera.trace_sample(&world, 4, 4, sample);
+            for event in &trace.events {
+                if let PathEvent::Bounce(bounce) = event {
+                    if bounce.survived_roulette.is_some() {
+                        assert!(bounce.depth >= camera.roulette_min_depth, "{}", trace);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks that the camera's right, up and backward vectors are finite and orthonormal, with
+    /// the middle of the image straight ahead
+    fn assert_orthonormal_basis(camera: &Camera) {
+        let u = camera.pixel_du.normalize();
+        let v = -camera.pixel_dv.normalize();
+        let w = (camera.center - camera.lookat).normalize();
+        let basis = [u, v, w];
+        assert!(
+            basis.iter().all(|axis| axis.iter().all(|c| c.is_finite())),
+            "{:?}",
+            basis
+        );
+        for (i, a) in basis.iter().enumerate() {
+            for b in &basis[i + 1..] {
+                assert!(a.dot(b).abs() < 1e-9, "{:?}", basis);
+            }
+        }
+        assert!((u.cross(&v) - w).norm() < 1e-9, "{:?}", basis);
+        let (width, height) = (camera.image_width as Float, camera.image_height as Float);
+        let middle = camera.pixel00_loc
+            + camera.pixel_du * (width - 1.0) / 2.0
+            + camera.pixel_dv * (height - 1.0) / 2.0;
+        assert!(((middle - camera.center).normalize() + w).norm() < 1e-9);
+    }
+
+    fn looking_down(up: Vec3) -> Result<Camera, CameraError> {
+        Camera::builder()
+            .with_look_from(Vec3::new(0.0, 0.0, 20.0))
+            .with_look_at(Vec3::zeros())
+            .with_up(up)
+            .with_resolution(8, 8)
+            .build()
+    }
+
+    #[test]
+    fn cameras_looking_along_up_get_a_basis() {
+        for up in [
+            Vec3::z(),
+            -Vec3::z(),
+            Vec3::z() * 1e-30,
+            Vec3::new(1e-9, 0.0, 1.0),
+            Vec3::new(0.0, -1e-9, -3.0),
+            Vec3::new(1e-3, 1e-3, 1.0),
+        ] {
+            assert_orthonormal_basis(&looking_down(up).unwrap());
+        }
+        // Along X, where the fallback can't be X itself
+        let camera = Camera::builder()
+            .with_look_from(Vec3::new(-5.0, 0.0, 0.0))
+            .with_look_at(Vec3::zeros())
+            .with_up(Vec3::x())
+            .build()
+            .unwrap();
+        assert_orthonormal_basis(&camera);
+        assert_eq!(
+            looking_down(Vec3::zeros()).err(),
+            Some(CameraError::NoUpDirection(Vec3::zeros()))
+        );
+        assert!(looking_down(Vec3::new(Float::NAN, 0.0, 1.0)).is_err());
+    }
+
+    #[test]
+    fn nearly_parallel_up_keeps_the_straight_down_roll() {
+        let straight = looking_down(Vec3::z()).unwrap();
+        for up in [Vec3::new(1e-9, 0.0, 1.0), Vec3::new(0.0, 1e-9, 1.0)] {
+            let nearly = looking_down(up).unwrap();
+            assert!(
+                (nearly.pixel_du - straight.pixel_du).norm() < 1e-6 * straight.pixel_du.norm(),
+                "{:?}",
+                up
+            );
+        }
+        // Flipping up doesn't matter when it has no say in the roll
+        let flipped = looking_down(-Vec3::z()).unwrap();
+        assert_eq!(flipped.pixel_du, straight.pixel_du);
+    }
+
+    #[test]
+    fn straight_down_cameras_render() {
+        let mut camera = crate::scenes::topdown_cam().with_resolution(8, 8);
+        camera.seed = Some(3);
+        assert_orthonormal_basis(&camera);
+        let world = lit_box();
+        for y in 0..camera.image_height {
+            for x in 0..camera.image_width {
+                let color = camera.render_pixel(&world, x, y, 2);
+                assert!(color.iter().all(|c| c.is_finite()), "({}, {})", x, y);
+            }
+        }
+        // The floor and ball show, not NaN-black
+        assert!(mean_luminance(&camera, &world, 2) > 0.01);
+    }
+
+    #[test]
+    fn images_only_replace_what_was_saved_before_once_theyre_done() {
+        let directory = scratch("replace");
+        let image = || Image {
+            pixels: vec![Vec3::repeat(0.5); 4],
+            width: 2,
+            height: 2,
+            gamma: DEFAULT_GAMMA,
+            metadata: Vec::new(),
+            alpha: None,
+        };
+        for name in ["out.ppm", "out.png", "out"] {
+            let path = directory.join(name);
+            std::fs::write(&path, b"an earlier render").unwrap();
+            let partial = Camera::claim_image_path(&path).unwrap();
+            assert_eq!(partial.parent(), Some(directory.as_path()));
+            assert_eq!(
+                partial.extension(),
+                path.extension().or(Some("partial".as_ref()))
+            );
+            // Claiming it doesn't touch what's there
+            assert_eq!(std::fs::read(&path).unwrap(), b"an earlier render");
+            Camera::replace_image(image(), &path).unwrap();
+            assert!(!partial.exists());
+            let written = std::fs::read(&path).unwrap();
+            let expected = if name.ends_with(".png") {
+                image().encode_png().unwrap()
+            } else {
+                image().encode_ppm()
+            };
+            assert_eq!(written, expected, "{}", name);
+        }
+        // Paths that can't be written to are found out before anything is rendered
+        assert!(Camera::claim_image_path(&directory).is_err());
+        assert!(Camera::claim_image_path(&directory.join("missing/out.ppm")).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
     }
 }
@@ -0,0 +1,507 @@
+use crate::{
+    animation::{AnimationError, Interpolation},
+    camera::Float,
+    hittable::{Shape, World},
+    vec3::{Point3, Vec3},
+};
+use bvh::aabb::{Aabb, Bounded};
+use std::ops::{Add, Mul, Sub};
+
+/// Points sampled along each segment of a path to measure how long it is
+const ARC_LENGTH_SAMPLES: usize = 64;
+
+/// Below this sine of the angle between the view direction and up, the camera counts as looking
+/// straight along up, which leaves its roll undefined
+const OVERHEAD_SINE: Float = 1e-3;
+
+/// What the camera looks at from one point on its path
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookAt {
+    Point(Point3),
+    /// The center of the bounds of every shape of the scene object with this name, wherever
+    /// it's been animated to on the frame
+    Object(String),
+}
+
+/// A control point of a [`CameraPath`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraKey {
+    pub position: Point3,
+    pub look_at: LookAt,
+    /// Vertical field of view in degrees here, for dolly zooms. Keys without one use the job's.
+    pub vertical_fov: Option<Float>,
+}
+
+/// Where the camera is and what it sees on one frame of a [`CameraPath`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraView {
+    pub center: Point3,
+    pub lookat: Point3,
+    pub up: Vec3,
+    pub vertical_fov: Float,
+}
+
+/// A camera flying through control points on a Catmull-Rom spline between two frames, at an
+/// even speed along the curve apart from the easing. What it looks at and its field of view
+/// follow splines through the keys too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraPath {
+    pub start_frame: Float,
+    pub end_frame: Float,
+    /// How the camera speeds up and slows down over the whole path
+    pub easing: Interpolation,
+    pub keys: Vec<CameraKey>,
+}
+
+/// The point `t` of the way from `p1` to `p2` on the uniform Catmull-Rom spline through `p0`
+/// to `p3`. Passes through `p1` at 0 and `p2` at 1 exactly.
+pub fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: Float) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Float, Output = T>,
+{
+    let (t2, t3) = (t * t, t * t * t);
+    // Written as a weighted sum of the points, so each one is hit exactly at the ends
+    p0 * ((-t3 + 2.0 * t2 - t) * 0.5)
+        + p1 * ((3.0 * t3 - 5.0 * t2 + 2.0) * 0.5)
+        + p2 * ((-3.0 * t3 + 4.0 * t2 + t) * 0.5)
+        + p3 * ((t3 - t2) * 0.5)
+}
+
+/// The Catmull-Rom spline through `points` at `u`, from 0 at the first point to one less than
+/// the number of points at the last. The ends get tangents from points mirrored past them, so
+/// the curve leaves the first point heading for the second, and likewise at the last.
+pub fn spline_at<T>(points: &[T], u: Float) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Float, Output = T>,
+{
+    let n = points.len();
+    if n == 1 {
+        return points[0];
+    }
+    let segment = (u.max(0.0) as usize).min(n - 2);
+    let t = (u - segment as Float).clamp(0.0, 1.0);
+    let point = |i: isize| -> T {
+        if i < 0 {
+            points[0] * 2.0 - points[1]
+        } else if i as usize >= n {
+            points[n - 1] * 2.0 - points[n - 2]
+        } else {
+            points[i as usize]
+        }
+    };
+    let i = segment as isize;
+    catmull_rom(point(i - 1), point(i), point(i + 1), point(i + 2), t)
+}
+
+/// Distance along a spline from its start, sampled evenly in its parameter, for moving along it
+/// at an even speed
+#[derive(Debug, Clone)]
+pub struct ArcLength {
+    /// Distance at each parameter `i / ARC_LENGTH_SAMPLES`
+    lengths: Vec<Float>,
+}
+
+impl ArcLength {
+    pub fn new(points: &[Point3]) -> Self {
+        let samples = points.len().saturating_sub(1) * ARC_LENGTH_SAMPLES;
+        let mut lengths = Vec::with_capacity(samples + 1);
+        lengths.push(0.0);
+        let mut previous = points[0];
+        for i in 1..=samples {
+            let point = spline_at(points, i as Float / ARC_LENGTH_SAMPLES as Float);
+            lengths.push(lengths[i - 1] + (point - previous).norm());
+            previous = point;
+        }
+        ArcLength { lengths }
+    }
+
+    pub fn total(&self) -> Float {
+        *self.lengths.last().expect("there's always a start")
+    }
+
+    /// The spline parameter `fraction` of the way along the curve by distance
+    pub fn parameter_at(&self, fraction: Float) -> Float {
+        let total = self.total();
+        if total <= 0.0 {
+            // Every point is in the same place, so go by the parameter instead
+            return fraction.clamp(0.0, 1.0) * (self.lengths.len() - 1) as Float
+                / ARC_LENGTH_SAMPLES as Float;
+        }
+        let distance = fraction.clamp(0.0, 1.0) * total;
+        let after = self
+            .lengths
+            .partition_point(|&length| length < distance)
+            .clamp(1, self.lengths.len() - 1);
+        let (from, to) = (self.lengths[after - 1], self.lengths[after]);
+        let t = if to > from {
+            (distance - from) / (to - from)
+        } else {
+            0.0
+        };
+        (after - 1) as Float / ARC_LENGTH_SAMPLES as Float + t / ARC_LENGTH_SAMPLES as Float
+    }
+}
+
+/// The center of the bounds of every shape in `world` that belongs to the object called `name`
+pub fn object_center(world: &World, name: &str) -> Option<Point3> {
+    let bounds = world
+        .shapes
+        .iter()
+        .filter(|shape| {
+            let object = match shape {
+                Shape::Sphere(sphere) => sphere.object,
+                Shape::Triangle(triangle) => triangle.object,
+                Shape::TriangleFragment(fragment) => fragment.triangle().object,
+                Shape::AaBox(aa_box) => aa_box.object,
+                Shape::RoundedBox(rounded) => rounded.object,
+                Shape::Instance(instance) => instance.object,
+                Shape::Mesh(mesh) => mesh.object,
+                Shape::HeterogeneousMedium(medium) => medium.object,
+                Shape::RectLight(light) => light.object,
+                Shape::SpotLight(light) => light.object,
+            };
+            *object.name() == *name
+        })
+        .fold(Aabb::empty(), |bounds, shape| bounds.join(&shape.aabb()));
+    (bounds.min.x <= bounds.max.x).then(|| bounds.center().coords)
+}
+
+/// Up direction to orient a camera at `center` looking at `lookat` with, which is `up` unless
+/// that's the way it's looking. Then the camera's top points where it's heading, which is how
+/// it was oriented just before, so flying straight over what it looks at doesn't leave its roll
+/// undefined.
+fn camera_up(center: Point3, lookat: Point3, up: Vec3, heading: Vec3) -> Vec3 {
+    let forward = (lookat - center).normalize();
+    if forward.cross(&up.normalize()).norm() >= OVERHEAD_SINE {
+        return up;
+    }
+    let heading = heading - forward * heading.dot(&forward);
+    if heading.norm() > Float::EPSILON {
+        return heading.normalize();
+    }
+    // Flying straight along up too, so any direction across the view will do
+    let across = if forward.x.abs() < 0.9 {
+        Vec3::x()
+    } else {
+        Vec3::y()
+    };
+    (across - forward * across.dot(&forward)).normalize()
+}
+
+impl CameraPath {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Fraction of the way through the path at `frame` once it's eased, from 0 to 1
+    fn progress(&self, frame: Float) -> Float {
+        let span = self.end_frame - self.start_frame;
+        let t = if span > 0.0 {
+            (frame - self.start_frame) / span
+        } else if frame >= self.end_frame {
+            1.0
+        } else {
+            0.0
+        };
+        self.easing.ease(t.clamp(0.0, 1.0))
+    }
+
+    /// Where the camera is on `frame`, looking at objects where they are in `world`. Keys
+    /// without a field of view use `vertical_fov`, and `up` is the camera's usual up.
+    /// `None` if the path has no keys.
+    pub fn view_at(
+        &self,
+        frame: Float,
+        world: &World,
+        vertical_fov: Float,
+        up: Vec3,
+    ) -> Result<Option<CameraView>, AnimationError> {
+        if self.keys.is_empty() {
+            return Ok(None);
+        }
+        let positions: Vec<Point3> = self.keys.iter().map(|key| key.position).collect();
+        let targets = self
+            .keys
+            .iter()
+            .map(|key| match &key.look_at {
+                LookAt::Point(point) => Ok(*point),
+                LookAt::Object(name) => object_center(world, name)
+                    .ok_or_else(|| AnimationError::UnknownTarget(name.clone())),
+            })
+            .collect::<Result<Vec<Point3>, _>>()?;
+        let fovs: Vec<Float> = self
+            .keys
+            .iter()
+            .map(|key| key.vertical_fov.unwrap_or(vertical_fov))
+            .collect();
+
+        let arc_length = ArcLength::new(&positions);
+        let u = arc_length.parameter_at(self.progress(frame));
+        let center = spline_at(&positions, u);
+        let lookat = spline_at(&targets, u);
+        // A small step either way along the curve, shortened at its ends
+        let step = 1.0 / ARC_LENGTH_SAMPLES as Float;
+        let last = (positions.len() - 1) as Float;
+        let heading = spline_at(&positions, (u + step).min(last))
+            - spline_at(&positions, (u - step).max(0.0));
+        Ok(Some(CameraView {
+            center,
+            lookat,
+            up: camera_up(center, lookat, up, heading),
+            vertical_fov: spline_at(&fovs, u),
+        }))
+    }
+
+    /// Serializes the path as a `camera_path <start frame> <end frame> <easing>` line followed
+    /// by a `camera_key` line per key, the inverse of [`CameraPath::parse_line`]
+    pub fn to_lines(&self) -> Vec<String> {
+        if self.keys.is_empty() {
+            return Vec::new();
+        }
+        let vector = |v: &Vec3| format!("{} {} {}", v.x, v.y, v.z);
+        let mut lines = vec![format!(
+            "camera_path {} {} {}",
+            self.start_frame,
+            self.end_frame,
+            self.easing.name()
+        )];
+        for key in &self.keys {
+            let look_at = match &key.look_at {
+                LookAt::Point(point) => format!("look {}", vector(point)),
+                LookAt::Object(name) => format!("track {}", name),
+            };
+            let fov = key
+                .vertical_fov
+                .map_or(String::new(), |fov| format!(" fov {}", fov));
+            lines.push(format!(
+                "camera_key {} {}{}",
+                vector(&key.position),
+                look_at,
+                fov
+            ));
+        }
+        lines
+    }
+
+    /// Reads a `camera_path <start frame> <end frame> [linear|smooth]` line, which sets when the
+    /// camera flies through the keys, or a `camera_key <x> <y> <z> look <x> <y> <z> [fov
+    /// <degrees>]` or `camera_key <x> <y> <z> track <object> [fov <degrees>]` line, which adds
+    /// the next key. `key` is the first word and `words` the rest.
+    pub fn parse_line(&mut self, key: &str, words: &[&str]) -> Result<(), AnimationError> {
+        let malformed = |message: String| AnimationError::Malformed(message);
+        let number = |word: &str| {
+            word.parse::<Float>()
+                .map_err(|_| malformed(format!("'{}' is not a valid value", word)))
+        };
+        let vector = |words: &[&str]| -> Result<Vec3, AnimationError> {
+            Ok(Vec3::new(
+                number(words[0])?,
+                number(words[1])?,
+                number(words[2])?,
+            ))
+        };
+        match (key, words) {
+            ("camera_path", [start, end, easing @ ..]) => {
+                self.easing = match easing {
+                    [] => Interpolation::default(),
+                    [name] => Interpolation::from_name(name)
+                        .ok_or_else(|| malformed(format!("unknown easing '{}'", name)))?,
+                    _ => {
+                        return Err(malformed(
+                            "camera_path needs a start frame, an end frame and an easing"
+                                .to_string(),
+                        ))
+                    }
+                };
+                self.start_frame = number(start)?;
+                self.end_frame = number(end)?;
+                Ok(())
+            }
+            ("camera_key", [x, y, z, rest @ ..]) => {
+                let position = vector(&[x, y, z])?;
+                let (look_at, rest) =
+                    match rest {
+                        ["look", x, y, z, rest @ ..] => (LookAt::Point(vector(&[x, y, z])?), rest),
+                        ["track", object, rest @ ..] => (LookAt::Object(object.to_string()), rest),
+                        _ => return Err(malformed(
+                            "camera_key needs 'look <x> <y> <z>' or 'track <object>' after its \
+                             position"
+                                .to_string(),
+                        )),
+                    };
+                let vertical_fov = match rest {
+                    [] => None,
+                    ["fov", degrees] => Some(number(degrees)?),
+                    _ => {
+                        return Err(malformed(format!(
+                            "unexpected '{}' after camera_key's target",
+                            rest.join(" ")
+                        )))
+                    }
+                };
+                self.keys.push(CameraKey {
+                    position,
+                    look_at,
+                    vertical_fov,
+                });
+                Ok(())
+            }
+            _ => Err(malformed(format!(
+                "{} is missing values, see CameraPath::parse_line",
+                key
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+
+    /// Keys spaced very unevenly, so the spline's parameter runs at very different speeds along
+    /// each segment
+    fn uneven_keys() -> Vec<Point3> {
+        vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(10.0, 0.0, 5.0),
+            Vec3::new(12.0, 3.0, 5.0),
+            Vec3::new(12.5, 3.0, 5.5),
+        ]
+    }
+
+    /// A path through `positions` over frames 0 to `end_frame`, always looking at the origin
+    fn path(positions: &[Point3], end_frame: Float, easing: Interpolation) -> CameraPath {
+        CameraPath {
+            start_frame: 0.0,
+            end_frame,
+            easing,
+            keys: positions
+                .iter()
+                .map(|&position| CameraKey {
+                    position,
+                    look_at: LookAt::Point(Vec3::zeros()),
+                    vertical_fov: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn view(path: &CameraPath, frame: Float) -> CameraView {
+        let world = World::build(Vec::new());
+        path.view_at(frame, &world, 40.0, Vec3::z())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn splines_pass_through_their_control_points() {
+        let keys = uneven_keys();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(spline_at(&keys, i as Float), *key);
+        }
+        let fovs = [40.0, 25.0, 70.0];
+        for (i, fov) in fovs.iter().enumerate() {
+            assert_eq!(spline_at(&fovs, i as Float), *fov);
+        }
+
+        // The path starts and ends on its first and last keys, and goes through the rest
+        let path = path(&keys, 1000.0, Interpolation::Linear);
+        assert!((view(&path, 0.0).center - keys[0]).norm() < 1e-12);
+        assert!((view(&path, 1000.0).center - keys[4]).norm() < 1e-12);
+        let centers: Vec<Point3> = (0..=1000)
+            .map(|frame| view(&path, frame as Float).center)
+            .collect();
+        for key in &keys {
+            let closest = centers
+                .iter()
+                .map(|center| (center - key).norm())
+                .fold(Float::INFINITY, Float::min);
+            // Half a frame's travel at most, which is about a hundredth of a unit
+            assert!(closest < 0.01, "{:?} is {} away", key, closest);
+        }
+    }
+
+    #[test]
+    fn linear_paths_move_at_an_even_speed() {
+        let keys = uneven_keys();
+        let path = path(&keys, 60.0, Interpolation::Linear);
+        // Distance travelled between each frame and the next, measured along the curve
+        const SUBSTEPS: usize = 32;
+        let steps: Vec<Float> = (0..60)
+            .map(|frame| {
+                let centers: Vec<Point3> = (0..=SUBSTEPS)
+                    .map(|i| view(&path, frame as Float + i as Float / SUBSTEPS as Float).center)
+                    .collect();
+                centers.windows(2).map(|w| (w[1] - w[0]).norm()).sum()
+            })
+            .collect();
+        let (shortest, longest) = steps
+            .iter()
+            .fold((Float::INFINITY, 0.0 as Float), |(a, b), &s| {
+                (a.min(s), b.max(s))
+            });
+        assert!(longest / shortest < 1.02, "{} to {}", shortest, longest);
+        let total = ArcLength::new(&keys).total();
+        assert!((steps.iter().sum::<Float>() - total).abs() < 0.01 * total);
+
+        // Stepping the spline's parameter evenly instead is nowhere near even
+        let naive: Vec<Float> = (0..60)
+            .map(|i| {
+                let u = |i: usize| i as Float / 60.0 * 4.0;
+                (spline_at(&keys, u(i + 1)) - spline_at(&keys, u(i))).norm()
+            })
+            .collect();
+        let naive_ratio = naive.iter().cloned().fold(0.0, Float::max)
+            / naive.iter().cloned().fold(Float::INFINITY, Float::min);
+        assert!(naive_ratio > 5.0, "{}", naive_ratio);
+    }
+
+    #[test]
+    fn thirty_frame_paths_have_no_degenerate_frames() {
+        // Flies straight over what it looks at, so it looks down along up halfway, and zooms
+        // while it does
+        let mut path = path(
+            &[
+                Vec3::new(-6.0, 0.0, 1.0),
+                Vec3::new(-2.0, 0.0, 4.0),
+                Vec3::new(0.0, 0.0, 5.0),
+                Vec3::new(2.0, 0.0, 4.0),
+                Vec3::new(6.0, 0.0, 1.0),
+            ],
+            29.0,
+            Interpolation::Smooth,
+        );
+        for (key, fov) in path.keys.iter_mut().zip([30.0, 50.0, 80.0, 50.0, 30.0]) {
+            key.vertical_fov = Some(fov);
+        }
+        // Every frame, and the middle of the path where it passes over the origin
+        let frames = (0..30).map(|frame| frame as Float).chain([14.5]);
+        for frame in frames {
+            let view = view(&path, frame as Float);
+            let camera = Camera::builder()
+                .with_look_from(view.center)
+                .with_look_at(view.lookat)
+                .with_up(view.up)
+                .with_vertical_fov(view.vertical_fov)
+                .with_resolution(32, 18)
+                .build()
+                .unwrap_or_else(|err| panic!("frame {}: {}", frame, err));
+            let forward = (view.lookat - view.center).normalize();
+            assert!(
+                forward.cross(&view.up.normalize()).norm() >= OVERHEAD_SINE,
+                "frame {} has no roll",
+                frame
+            );
+            // The middle of the image is what the camera looks at
+            let middle = camera.debug_ray(15.5, 8.5).direction.normalize();
+            assert!(middle.dot(&forward) > 0.999, "frame {}", frame);
+            // And the top of the image is well away from it, in a definite direction
+            let top = camera.debug_ray(15.5, -0.5).direction.normalize();
+            let across = top - forward * top.dot(&forward);
+            assert!(across.iter().all(|c| c.is_finite()), "frame {}", frame);
+            assert!(across.norm() > 0.1, "frame {}", frame);
+        }
+    }
+}
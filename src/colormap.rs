@@ -0,0 +1,65 @@
+use crate::{camera::Float, vec3::Vec3};
+
+/// Matplotlib's viridis at every tenth of the way along, in display (gamma encoded) RGB. Gets
+/// steadily lighter, so it reads the same in grayscale and to colorblind eyes.
+const VIRIDIS: [[Float; 3]; 11] = [
+    [0.267, 0.005, 0.329],
+    [0.283, 0.141, 0.458],
+    [0.254, 0.265, 0.530],
+    [0.207, 0.372, 0.553],
+    [0.164, 0.471, 0.558],
+    [0.128, 0.567, 0.551],
+    [0.135, 0.659, 0.518],
+    [0.267, 0.749, 0.441],
+    [0.478, 0.821, 0.317],
+    [0.741, 0.873, 0.150],
+    [0.993, 0.906, 0.144],
+];
+
+/// The viridis color `t` of the way from dark purple at 0 to yellow at 1, for false color
+/// heatmaps. Clamps `t` to that range, with NaN counting as 0. The color is already gamma
+/// encoded, so images of it should be written with a gamma of 1.
+pub fn viridis(t: Float) -> Vec3 {
+    let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+    let position = t * (VIRIDIS.len() - 1) as Float;
+    let below = (position.floor() as usize).min(VIRIDIS.len() - 2);
+    let along = position - below as Float;
+    let [r0, g0, b0] = VIRIDIS[below];
+    let [r1, g1, b1] = VIRIDIS[below + 1];
+    Vec3::new(r0, g0, b0).lerp(&Vec3::new(r1, g1, b1), along)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sky_importance::luminance;
+
+    #[test]
+    fn viridis_runs_from_purple_to_yellow() {
+        let [first, .., last] = VIRIDIS;
+        assert_eq!(viridis(0.0), Vec3::from(first));
+        assert_eq!(viridis(1.0), Vec3::from(last));
+        // Out of range and NaN are clamped
+        assert_eq!(viridis(-3.0), viridis(0.0));
+        assert_eq!(viridis(Float::NAN), viridis(0.0));
+        assert_eq!(viridis(7.0), viridis(1.0));
+        // Each stop is where it says
+        for (index, stop) in VIRIDIS.iter().enumerate() {
+            let color = viridis(index as Float / 10.0);
+            assert!((color - Vec3::from(*stop)).abs().max() < 1e-12, "{}", color);
+        }
+    }
+
+    #[test]
+    fn viridis_gets_steadily_lighter() {
+        let lightness: Vec<Float> = (0..=1000)
+            .map(|step| luminance(&viridis(step as Float / 1000.0)))
+            .collect();
+        for (t, pair) in lightness.windows(2).enumerate() {
+            assert!(pair[1] > pair[0], "darker at {}: {:?}", t, pair);
+        }
+        for color in (0..=100).map(|step| viridis(step as Float / 100.0)) {
+            assert!(color.min() >= 0.0 && color.max() <= 1.0, "{}", color);
+        }
+    }
+}
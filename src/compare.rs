@@ -0,0 +1,178 @@
+use crate::camera::{self, Float, Image};
+use std::{fmt, fs, path::Path};
+
+/// Multiplies differences in the difference view so small changes are still visible
+const DIFFERENCE_GAIN: Float = 8.0;
+/// Color of the line drawn where the split view switches images, as RGBA
+const SPLIT_LINE_COLOR: [u8; 4] = [0xff, 0xd0, 0x00, 0xff];
+
+/// What the preview shows when comparing against a reference
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CompareMode {
+    /// Just the live render
+    #[default]
+    Off,
+    /// The reference left of the split and the live render right of it
+    Split,
+    /// The amplified absolute difference between the two, black where they match
+    Difference,
+}
+
+impl CompareMode {
+    /// The mode the V key switches to from this one
+    pub fn next(&self) -> Self {
+        match self {
+            CompareMode::Off => CompareMode::Split,
+            CompareMode::Split => CompareMode::Difference,
+            CompareMode::Difference => CompareMode::Off,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompareMode::Off => "off",
+            CompareMode::Split => "split",
+            CompareMode::Difference => "difference",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompareError {
+    Load(String),
+    /// The reference's size doesn't match the preview's, which is given second
+    SizeMismatch {
+        reference: (usize, usize),
+        preview: (usize, usize),
+    },
+}
+
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompareError::Load(reason) => write!(f, "failed to load reference image: {}", reason),
+            CompareError::SizeMismatch { reference, preview } => write!(
+                f,
+                "reference image is {}x{} but the preview is {}x{}",
+                reference.0, reference.1, preview.0, preview.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+/// A reference image to A/B the preview against. Comparing happens when the preview is drawn,
+/// so the accumulated samples are never touched.
+pub struct Comparison {
+    /// The reference as RGBA bytes, put through the same display transform as the preview
+    reference: Vec<u8>,
+    width: usize,
+    height: usize,
+    pub mode: CompareMode,
+    /// Where the split view switches images, as a fraction of the width from the left
+    pub split: Float,
+}
+
+impl Comparison {
+    /// Loads the PNG, PPM or other image at `path` to compare a `width` by `height` preview
+    /// shown with `gamma` against
+    pub fn load(
+        path: impl AsRef<Path>,
+        width: usize,
+        height: usize,
+        gamma: Float,
+    ) -> Result<Self, CompareError> {
+        let data = fs::read(path).map_err(|err| CompareError::Load(err.to_string()))?;
+        let image: Image = image::load_from_memory(&data)
+            .map_err(|err| CompareError::Load(err.to_string()))?
+            .into();
+        Comparison::new(&image, width, height, gamma)
+    }
+
+    /// Undoes the gamma `image` was written with, then puts it through the same display transform
+    /// as the live render, shown with `gamma`, so the two are compared from the same linear values
+    pub fn new(
+        image: &Image,
+        width: usize,
+        height: usize,
+        gamma: Float,
+    ) -> Result<Self, CompareError> {
+        if (image.width, image.height) != (width, height) {
+            return Err(CompareError::SizeMismatch {
+                reference: (image.width, image.height),
+                preview: (width, height),
+            });
+        }
+        let mut reference = vec![0xff; width * height * 4];
+        for (k, color) in image.pixels.iter().enumerate() {
+            let linear = color.map(|c| c.clamp(0.0, 1.0).powf(image.gamma));
+            let i = k * 4;
+            reference[i..i + 3].copy_from_slice(&camera::rgb8(&linear, gamma));
+        }
+        Ok(Comparison {
+            reference,
+            width,
+            height,
+            mode: CompareMode::Off,
+            split: 0.5,
+        })
+    }
+
+    /// The width and height of the preview the reference can be compared against
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Writes what the preview should show into `frame`, given the live render's RGBA bytes
+    pub fn present(&self, live: &[u8], frame: &mut [u8]) {
+        match self.mode {
+            CompareMode::Off => frame.copy_from_slice(live),
+            CompareMode::Split => {
+                let split_x = (self.split.clamp(0.0, 1.0) * self.width as Float) as usize;
+                let row_bytes = self.width * 4;
+                for ((out, live), reference) in frame
+                    .chunks_exact_mut(row_bytes)
+                    .zip(live.chunks_exact(row_bytes))
+                    .zip(self.reference.chunks_exact(row_bytes))
+                {
+                    let split_byte = split_x.min(self.width) * 4;
+                    out[..split_byte].copy_from_slice(&reference[..split_byte]);
+                    out[split_byte..].copy_from_slice(&live[split_byte..]);
+                    if split_x < self.width {
+                        out[split_byte..split_byte + 4].copy_from_slice(&SPLIT_LINE_COLOR);
+                    }
+                }
+            }
+            CompareMode::Difference => {
+                for ((out, live), reference) in frame
+                    .chunks_exact_mut(4)
+                    .zip(live.chunks_exact(4))
+                    .zip(self.reference.chunks_exact(4))
+                {
+                    for channel in 0..3 {
+                        let difference = live[channel].abs_diff(reference[channel]) as Float;
+                        out[channel] = (difference * DIFFERENCE_GAIN).min(255.0) as u8;
+                    }
+                    out[3] = 0xff;
+                }
+            }
+        }
+    }
+
+    /// Returns the largest per-channel difference between the live render and the reference,
+    /// from 0 to 255, along with how many pixels differ at all
+    pub fn difference_stats(&self, live: &[u8]) -> (u8, usize) {
+        let mut largest = 0;
+        let mut differing = 0;
+        for (live, reference) in live.chunks_exact(4).zip(self.reference.chunks_exact(4)) {
+            let difference = (0..3)
+                .map(|channel| live[channel].abs_diff(reference[channel]))
+                .max()
+                .unwrap_or(0);
+            largest = largest.max(difference);
+            differing += usize::from(difference > 0);
+        }
+        (largest, differing)
+    }
+}
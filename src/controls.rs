@@ -0,0 +1,372 @@
+use crate::{
+    camera::{Camera, Float},
+    vec3::{Point3, Vec3},
+};
+use std::f64::consts::FRAC_PI_2;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode},
+};
+
+/// Radians of orbit per pixel of mouse movement
+const ORBIT_SPEED: Float = 0.005;
+/// Fraction of the pivot distance dollied per scroll wheel line
+const DOLLY_SPEED: Float = 0.1;
+/// Keeps the camera from reaching the poles, where the view direction becomes parallel to `up`
+const MAX_PITCH: Float = FRAC_PI_2 - 0.01;
+const MIN_DISTANCE: Float = 1e-3;
+/// Fraction of orbit velocity lost per second while gliding after a release
+const INERTIA_DAMPING: Float = 6.0;
+/// Glide velocity (radians/second) below which inertia stops
+const INERTIA_CUTOFF: Float = 0.01;
+/// Pivot distances flown per second while a movement key is held, so flying covers a small
+/// scene as quickly as a large one
+const FLY_SPEED: Float = 0.5;
+
+/// Movement keys held down
+#[derive(Debug, Clone, Copy, Default)]
+struct FlyKeys {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+impl FlyKeys {
+    /// The flag for `key` if it's a movement key
+    fn flag(&mut self, key: VirtualKeyCode) -> Option<&mut bool> {
+        match key {
+            VirtualKeyCode::W => Some(&mut self.forward),
+            VirtualKeyCode::S => Some(&mut self.back),
+            VirtualKeyCode::A => Some(&mut self.left),
+            VirtualKeyCode::D => Some(&mut self.right),
+            VirtualKeyCode::E => Some(&mut self.up),
+            VirtualKeyCode::Q => Some(&mut self.down),
+            _ => None,
+        }
+    }
+
+    /// How far to fly along the view direction, to the right and along up, each -1, 0 or 1
+    fn axes(&self) -> (Float, Float, Float) {
+        let axis =
+            |positive: bool, negative: bool| positive as i8 as Float - negative as i8 as Float;
+        (
+            axis(self.forward, self.back),
+            axis(self.right, self.left),
+            axis(self.up, self.down),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Drag {
+    None,
+    Orbit,
+    Pan,
+}
+
+/// Turntable-style camera controller for the preview window.
+///
+/// Left-drag orbits around a pivot point, middle-drag pans the pivot in the view plane, and the
+/// scroll wheel dollies toward or away from the pivot. Holding W, A, S or D flies forward, left,
+/// back or right, and E or Q up or down, taking the pivot along. The pivot starts at the camera's
+/// `lookat` and can be moved to the last clicked surface point with
+/// [`CameraController::focus_selection`].
+pub struct CameraController {
+    pivot: Point3,
+    distance: Float,
+    /// Angle around `up`, in radians
+    yaw: Float,
+    /// Angle above the plane perpendicular to `up`, in radians
+    pitch: Float,
+    /// Orthonormal frame with `up` as its third axis, used to convert yaw/pitch into directions
+    frame: (Vec3, Vec3, Vec3),
+    drag: Drag,
+    last_cursor: Option<PhysicalPosition<f64>>,
+    /// Orbit velocity in (yaw, pitch) radians per second, used for gliding after a release
+    velocity: (Float, Float),
+    pub inertia: bool,
+    fly: FlyKeys,
+    /// Last surface point picked with a click
+    selection: Option<Point3>,
+    /// Set whenever the camera moves and cleared by [`CameraController::take_camera`]
+    dirty: bool,
+}
+
+impl CameraController {
+    pub fn new(camera: &Camera) -> Self {
+        let up = camera.up.normalize();
+        // Any vector not parallel to up will do for building the frame
+        let reference = if up.x.abs() < 0.9 {
+            Vec3::x_axis().into_inner()
+        } else {
+            Vec3::y_axis().into_inner()
+        };
+        let e1 = (reference - up * reference.dot(&up)).normalize();
+        let e2 = up.cross(&e1);
+
+        let mut controller = CameraController {
+            pivot: camera.lookat,
+            distance: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            frame: (e1, e2, up),
+            drag: Drag::None,
+            last_cursor: None,
+            velocity: (0.0, 0.0),
+            inertia: true,
+            fly: FlyKeys::default(),
+            selection: None,
+            dirty: false,
+        };
+        controller.look_from(camera.center);
+        controller
+    }
+
+    /// Sets distance, yaw, and pitch so that the camera sits at `eye` looking at the pivot
+    fn look_from(&mut self, eye: Point3) {
+        let (e1, e2, up) = self.frame;
+        let offset = eye - self.pivot;
+        self.distance = offset.norm().max(MIN_DISTANCE);
+        self.pitch = (offset.dot(&up) / self.distance)
+            .clamp(-1.0, 1.0)
+            .asin()
+            .clamp(-MAX_PITCH, MAX_PITCH);
+        self.yaw = offset.dot(&e2).atan2(offset.dot(&e1));
+    }
+
+    pub fn eye(&self) -> Point3 {
+        let (e1, e2, up) = self.frame;
+        let horizontal = e1 * self.yaw.cos() + e2 * self.yaw.sin();
+        let direction = horizontal * self.pitch.cos() + up * self.pitch.sin();
+        self.pivot + direction * self.distance
+    }
+
+    /// Remembers a clicked surface point so it can become the pivot with `focus_selection`
+    pub fn select(&mut self, point: Point3) {
+        self.selection = Some(point);
+    }
+
+    /// Moves the pivot to the last selected point, keeping the camera where it is
+    pub fn focus_selection(&mut self) {
+        if let Some(point) = self.selection {
+            let eye = self.eye();
+            self.pivot = point;
+            self.look_from(eye);
+            self.velocity = (0.0, 0.0);
+            self.dirty = true;
+        }
+    }
+
+    pub fn mouse_input(&mut self, button: MouseButton, state: ElementState) {
+        match (button, state) {
+            (MouseButton::Left, ElementState::Pressed) => {
+                self.drag = Drag::Orbit;
+                self.velocity = (0.0, 0.0);
+            }
+            (MouseButton::Middle, ElementState::Pressed) => {
+                self.drag = Drag::Pan;
+                self.velocity = (0.0, 0.0);
+            }
+            (MouseButton::Left, ElementState::Released) if self.drag == Drag::Orbit => {
+                self.drag = Drag::None;
+                if !self.inertia {
+                    self.velocity = (0.0, 0.0);
+                }
+            }
+            (MouseButton::Middle, ElementState::Released) if self.drag == Drag::Pan => {
+                self.drag = Drag::None;
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles cursor movement. `dt` is the time in seconds since the previous cursor event and is
+    /// only used to estimate the glide velocity.
+    pub fn cursor_moved(&mut self, position: PhysicalPosition<f64>, dt: Float, camera: &Camera) {
+        let last = self.last_cursor.replace(position);
+        let Some(last) = last else {
+            return;
+        };
+        let dx = position.x - last.x;
+        let dy = position.y - last.y;
+
+        match self.drag {
+            Drag::Orbit => {
+                let d_yaw = -dx * ORBIT_SPEED;
+                let d_pitch = dy * ORBIT_SPEED;
+                self.rotate(d_yaw, d_pitch);
+                if dt > 0.0 {
+                    self.velocity = (d_yaw / dt, d_pitch / dt);
+                }
+            }
+            Drag::Pan => {
+                let (_, _, up) = self.frame;
+                let forward = (self.pivot - self.eye()).normalize();
+                let right = forward.cross(&up).normalize();
+                let view_up = right.cross(&forward);
+                // World units per pixel at the pivot's depth, so the pivot tracks the cursor
+                let scale = 2.0 * self.distance * (camera.vertical_fov.to_radians() / 2.0).tan()
+                    / camera.image_height as Float;
+                self.pivot += (-right * dx + view_up * dy) * scale;
+                self.dirty = true;
+            }
+            Drag::None => (),
+        }
+    }
+
+    pub fn scroll(&mut self, delta: MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y as Float,
+            MouseScrollDelta::PixelDelta(position) => position.y / 20.0,
+        };
+        // Exponential so each notch covers the same fraction of the remaining distance
+        self.distance = (self.distance * (-lines * DOLLY_SPEED).exp()).max(MIN_DISTANCE);
+        self.dirty = true;
+    }
+
+    /// Handles a key going down or up, which only matters for the movement keys
+    pub fn key_input(&mut self, key: VirtualKeyCode, state: ElementState) {
+        if let Some(held) = self.fly.flag(key) {
+            *held = state == ElementState::Pressed;
+        }
+    }
+
+    /// Stops flying, for when the window loses focus and won't hear the keys come back up
+    pub fn release_keys(&mut self) {
+        self.fly = FlyKeys::default();
+    }
+
+    /// Advances flying and inertial gliding by `dt` seconds
+    pub fn tick(&mut self, dt: Float) {
+        self.fly(dt);
+        if self.drag != Drag::None || !self.inertia {
+            return;
+        }
+        let (yaw_rate, pitch_rate) = self.velocity;
+        if yaw_rate.hypot(pitch_rate) < INERTIA_CUTOFF {
+            self.velocity = (0.0, 0.0);
+            return;
+        }
+        self.rotate(yaw_rate * dt, pitch_rate * dt);
+        let decay = (-INERTIA_DAMPING * dt).exp();
+        self.velocity = (yaw_rate * decay, pitch_rate * decay);
+    }
+
+    /// Moves the pivot, and the camera with it, along the held movement keys for `dt` seconds
+    fn fly(&mut self, dt: Float) {
+        let (forward_amount, right_amount, up_amount) = self.fly.axes();
+        if forward_amount == 0.0 && right_amount == 0.0 && up_amount == 0.0 {
+            return;
+        }
+        let (_, _, up) = self.frame;
+        let forward = (self.pivot - self.eye()).normalize();
+        let right = forward.cross(&up).normalize();
+        let direction = forward * forward_amount + right * right_amount + up * up_amount;
+        self.pivot += direction.normalize() * FLY_SPEED * self.distance * dt;
+        self.dirty = true;
+    }
+
+    fn rotate(&mut self, d_yaw: Float, d_pitch: Float) {
+        self.yaw += d_yaw;
+        self.pitch = (self.pitch + d_pitch).clamp(-MAX_PITCH, MAX_PITCH);
+        self.dirty = true;
+    }
+
+    /// Returns `camera` moved to the controller's current view if the view changed since the last
+    /// call, focused on the pivot
+    pub fn take_camera(&mut self, camera: &Camera) -> Option<Camera> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        Some(camera.with_view(self.eye(), self.pivot, self.distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn looking_from(look_from: Point3, up: Vec3) -> Camera {
+        Camera::builder()
+            .with_look_from(look_from)
+            .with_look_at(Vec3::new(1.0, 2.0, 0.5))
+            .with_up(up)
+            .with_resolution(8, 8)
+            .build()
+            .unwrap()
+    }
+
+    /// Drags the orbit by `dy` pixels down the screen, as one cursor movement
+    fn drag_down(controller: &mut CameraController, camera: &Camera, dy: f64) {
+        controller.mouse_input(MouseButton::Left, ElementState::Pressed);
+        controller.cursor_moved(PhysicalPosition::new(0.0, 0.0), 0.0, camera);
+        controller.cursor_moved(PhysicalPosition::new(0.0, dy), 0.0, camera);
+        controller.mouse_input(MouseButton::Left, ElementState::Released);
+    }
+
+    #[test]
+    fn the_eye_goes_back_where_it_was_looked_from() {
+        for (look_from, up) in [
+            (Vec3::new(4.0, -3.0, 2.0), Vec3::z()),
+            (Vec3::new(-2.0, 5.0, -1.0), Vec3::y()),
+            (Vec3::new(0.0, 0.0, 9.0), Vec3::new(1.0, 0.2, 0.1)),
+            (Vec3::new(3.0, -1.0, -4.0), Vec3::z()),
+        ] {
+            let camera = looking_from(look_from, up);
+            let controller = CameraController::new(&camera);
+            let eye = controller.eye();
+            assert!(
+                (eye - look_from).norm() < 1e-9,
+                "{:?} from {:?}",
+                eye,
+                look_from
+            );
+        }
+    }
+
+    #[test]
+    fn orbiting_stops_short_of_the_poles() {
+        let camera = looking_from(Vec3::new(4.0, -3.0, 2.0), Vec3::z());
+        for dy in [1e5, -1e5] {
+            let mut controller = CameraController::new(&camera);
+            drag_down(&mut controller, &camera, dy);
+            assert_eq!(controller.pitch, MAX_PITCH * dy.signum());
+            // Still far enough from straight up or down for the view to have a right direction
+            let forward = (controller.pivot - controller.eye()).normalize();
+            assert!(forward.cross(&Vec3::z()).norm() > 1e-3);
+            let moved = controller.take_camera(&camera).unwrap();
+            assert_eq!(moved.center, controller.eye());
+        }
+
+        // Looking from right over the pivot starts at the limit too, still that far away
+        let above = looking_from(Vec3::new(1.0, 2.0, 6.5), Vec3::z());
+        let controller = CameraController::new(&above);
+        assert_eq!(controller.pitch, MAX_PITCH);
+        assert!((controller.distance - 6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zooming_covers_the_same_fraction_each_notch_and_stops_at_the_pivot() {
+        let camera = looking_from(Vec3::new(4.0, -3.0, 2.0), Vec3::z());
+        let mut controller = CameraController::new(&camera);
+        let start = controller.distance;
+        controller.scroll(MouseScrollDelta::LineDelta(0.0, 1.0));
+        assert!((controller.distance - start * (-DOLLY_SPEED).exp()).abs() < 1e-12);
+        controller.scroll(MouseScrollDelta::LineDelta(0.0, -2.0));
+        assert!((controller.distance - start * DOLLY_SPEED.exp()).abs() < 1e-12);
+
+        controller.scroll(MouseScrollDelta::LineDelta(0.0, 1e4));
+        assert_eq!(controller.distance, MIN_DISTANCE);
+        let eye = controller.eye();
+        assert!(eye != controller.pivot);
+        // Zooming back out from the limit works
+        controller.scroll(MouseScrollDelta::LineDelta(0.0, -10.0));
+        assert!(controller.distance > MIN_DISTANCE);
+        assert!(controller.take_camera(&camera).is_some());
+        assert!(controller.take_camera(&camera).is_none());
+    }
+}
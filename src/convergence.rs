@@ -0,0 +1,587 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    hittable::World,
+    perf::{self, PerfLog, SweepRecord},
+    sky_importance::luminance,
+    tiles::TileRenderer,
+    vec3::Vec3,
+};
+use rayon::prelude::*;
+use std::{
+    f64::consts::PI,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Samples per pixel of the first pass, enough for a pixel's variance to mean something. Every
+/// later pass renders as many samples as all the passes before it.
+const FIRST_PASS_SAMPLES: usize = 16;
+
+/// When to stop rendering an image: once nearly every pixel's confidence interval is within a
+/// relative error of its luminance. The camera's samples per pixel caps how far it goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopCriterion {
+    /// Half-width of each pixel's confidence interval, as a fraction of its luminance
+    pub relative_error: Float,
+    /// Chance that a pixel's true luminance is inside its interval, e.g. 0.95
+    pub confidence: Float,
+    /// Fraction of the pixels, not counting outliers, that have to meet the target
+    pub pixel_fraction: Float,
+    /// Fraction of the pixels with the worst error that are left out, so a few fireflies can't
+    /// hold the render up
+    pub outlier_fraction: Float,
+    /// Pixels darker than this are held to `relative_error` of it instead of their own
+    /// luminance, since relative error means nothing near black
+    pub luminance_floor: Float,
+    /// Stops after about this long even if the target isn't met
+    pub max_time: Option<Duration>,
+}
+
+impl Default for StopCriterion {
+    fn default() -> Self {
+        StopCriterion {
+            relative_error: 0.01,
+            confidence: 0.95,
+            pixel_fraction: 0.99,
+            outlier_fraction: 0.001,
+            luminance_floor: 0.01,
+            max_time: None,
+        }
+    }
+}
+
+impl StopCriterion {
+    /// How far pixel `moments` is from the target, as its interval's half-width over the
+    /// half-width allowed. At most 1 once it's met.
+    pub fn error_ratio(&self, moments: &PixelMoments) -> Float {
+        let allowed =
+            self.relative_error * moments.luminance_mean().abs().max(self.luminance_floor);
+        moments.half_width(self.confidence) / allowed
+    }
+
+    /// Fraction of the pixels that meet the target, out of all of them but the outliers
+    pub fn converged_fraction(&self, pixels: &[PixelMoments]) -> Float {
+        let outliers = (self.outlier_fraction * pixels.len() as Float).floor() as usize;
+        let counted = pixels.len().saturating_sub(outliers);
+        if counted == 0 {
+            return 1.0;
+        }
+        let converged = pixels
+            .par_iter()
+            .filter(|moments| self.error_ratio(moments) <= 1.0)
+            .count();
+        // The outliers are the worst pixels, so they're the first to be left out of the count
+        converged.min(counted) as Float / counted as Float
+    }
+}
+
+/// Running statistics of a pixel's samples: their color's sum and their luminance's mean and
+/// sum of squared deviations, kept with Welford's method so bright pixels don't lose the variance
+/// to rounding
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PixelMoments {
+    pub samples: usize,
+    pub color_sum: Vec3,
+    luminance_mean: Float,
+    squared_deviations: Float,
+}
+
+impl PixelMoments {
+    pub fn add(&mut self, color: Vec3) {
+        self.samples += 1;
+        self.color_sum += color;
+        let value = luminance(&color);
+        let delta = value - self.luminance_mean;
+        self.luminance_mean += delta / self.samples as Float;
+        self.squared_deviations += delta * (value - self.luminance_mean);
+    }
+
+    /// Adds every sample `other` has seen, with Chan et al.'s update, as if they had been added
+    /// one at a time
+    pub fn merge(&mut self, other: &PixelMoments) {
+        if other.samples == 0 {
+            return;
+        }
+        let samples = self.samples + other.samples;
+        let delta = other.luminance_mean - self.luminance_mean;
+        let weight = other.samples as Float / samples as Float;
+        self.luminance_mean += delta * weight;
+        self.squared_deviations +=
+            other.squared_deviations + delta * delta * self.samples as Float * weight;
+        self.samples = samples;
+        self.color_sum += other.color_sum;
+    }
+
+    /// Average color of the samples, or black if there are none
+    pub fn color(&self) -> Vec3 {
+        match self.samples {
+            0 => Vec3::zeros(),
+            samples => self.color_sum / samples as Float,
+        }
+    }
+
+    pub fn luminance_mean(&self) -> Float {
+        self.luminance_mean
+    }
+
+    /// Unbiased variance of the samples' luminance, infinite with fewer than two
+    pub fn luminance_variance(&self) -> Float {
+        match self.samples {
+            0 | 1 => Float::INFINITY,
+            samples => self.squared_deviations / (samples - 1) as Float,
+        }
+    }
+
+    /// Half-width of the Student-t interval that holds the true mean luminance with probability
+    /// `confidence`, infinite with fewer than two samples
+    pub fn half_width(&self, confidence: Float) -> Float {
+        if self.samples < 2 {
+            return Float::INFINITY;
+        }
+        let quantile = student_t_quantile(0.5 + confidence / 2.0, self.samples - 1);
+        quantile * (self.luminance_variance() / self.samples as Float).sqrt()
+    }
+}
+
+/// Fraction of an even share of samples [`share_samples`] gives every pixel whatever its
+/// variance, so none stop getting samples because of a bad estimate
+pub const SAMPLE_FLOOR: Float = 0.25;
+
+/// Shares `budget` samples out among pixels, each with the variance of its samples' luminance,
+/// or `None` for pixels that aren't being sampled. Every pixel gets [`SAMPLE_FLOOR`] of an even
+/// share, at least one sample, and the rest go to the pixels in proportion to their variance.
+/// Pixels with too few samples for a variance count as the noisiest ones. Rounds down, so the
+/// total can fall a little short of `budget`, except that a `budget` smaller than the number of
+/// pixels being sampled still gives each of them one sample, going over it.
+pub fn share_samples(variances: &[Option<Float>], budget: usize) -> Vec<usize> {
+    let sampled = variances.iter().flatten().count();
+    if sampled == 0 {
+        return vec![0; variances.len()];
+    }
+    let even = budget as Float / sampled as Float;
+    let floor = ((even * SAMPLE_FLOOR) as usize).max(1);
+    let noisiest = variances
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|variance| variance.is_finite())
+        .fold(0.0, Float::max);
+    let weight = |variance: Float| {
+        if variance.is_finite() {
+            variance.max(0.0)
+        } else {
+            noisiest
+        }
+    };
+    let total: Float = variances.iter().flatten().map(|&v| weight(v)).sum();
+    let spare = budget.saturating_sub(floor * sampled) as Float;
+    variances
+        .iter()
+        .map(|variance| match *variance {
+            None => 0,
+            // Nothing to tell the pixels apart by, so they all get the same
+            Some(_) if total <= 0.0 => floor + (spare / sampled as Float) as usize,
+            Some(variance) => floor + (spare * weight(variance) / total) as usize,
+        })
+        .collect()
+}
+
+/// Value the standard normal distribution is below with probability `p`, from Acklam's
+/// rational approximation, within about 1e-9 of it
+pub fn normal_quantile(p: Float) -> Float {
+    const A: [Float; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [Float; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [Float; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [Float; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const LOW: Float = 0.024_25;
+    if p <= 0.0 {
+        return Float::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return Float::INFINITY;
+    }
+    let tail = |q: Float| {
+        let q = (-2.0 * q.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < LOW {
+        tail(p)
+    } else if p > 1.0 - LOW {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Value Student's t distribution with `degrees_of_freedom` is below with probability `p`.
+/// Exact for one and two degrees of freedom, and a Cornish-Fisher expansion around the normal
+/// distribution past that, which is within half a percent from three on.
+pub fn student_t_quantile(p: Float, degrees_of_freedom: usize) -> Float {
+    let n = degrees_of_freedom as Float;
+    match degrees_of_freedom {
+        0 => Float::NAN,
+        1 => (PI * (p - 0.5)).tan(),
+        2 => (2.0 * p - 1.0) / (2.0 * p * (1.0 - p)).sqrt(),
+        _ => {
+            let z = normal_quantile(p);
+            let z2 = z * z;
+            let g1 = (z2 + 1.0) * z / 4.0;
+            let g2 = ((5.0 * z2 + 16.0) * z2 + 3.0) * z / 96.0;
+            let g3 = (((3.0 * z2 + 19.0) * z2 + 17.0) * z2 - 15.0) * z / 384.0;
+            let g4 =
+                ((((79.0 * z2 + 776.0) * z2 + 1482.0) * z2 - 1920.0) * z2 - 945.0) * z / 92160.0;
+            z + g1 / n + g2 / n.powi(2) + g3 / n.powi(3) + g4 / n.powi(4)
+        }
+    }
+}
+
+/// Why a render with a [`StopCriterion`] stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Converged,
+    /// Reached the camera's samples per pixel first
+    SampleLimit,
+    /// Ran out of its `max_time` first
+    TimeLimit,
+}
+
+impl StopReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::Converged => "converged",
+            StopReason::SampleLimit => "sample limit",
+            StopReason::TimeLimit => "time limit",
+        }
+    }
+}
+
+/// How a render with a [`StopCriterion`] went
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvergenceReport {
+    pub samples_per_pixel: usize,
+    pub passes: usize,
+    /// Of the pixels that aren't outliers, as in [`StopCriterion::converged_fraction`]
+    pub converged_fraction: Float,
+    /// Median over the pixels of their [`StopCriterion::error_ratio`] times the target error,
+    /// i.e. their interval's half-width relative to their luminance
+    pub median_error: Float,
+    pub seconds: Float,
+    pub reason: StopReason,
+}
+
+impl fmt::Display for ConvergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stopped at {} samples per pixel after {} pass(es) and {:.1} seconds ({}), with \
+             {:.2}% of pixels within the target and a median error of {:.3}%",
+            self.samples_per_pixel,
+            self.passes,
+            self.seconds,
+            self.reason.name(),
+            self.converged_fraction * 100.0,
+            self.median_error * 100.0
+        )
+    }
+}
+
+/// Renders the camera's image in passes of more and more samples per pixel until `criterion`
+/// is met, the camera's samples per pixel are used up or time runs out, on `tiles` if given.
+/// The image's metadata records the target and what was reached.
+pub fn render_until_converged(
+    camera: &Camera,
+    world: &World,
+    criterion: &StopCriterion,
+    tiles: Option<&TileRenderer>,
+) -> (Image, ConvergenceReport) {
+    let (width, height) = (camera.image_width, camera.image_height);
+    let max_samples = camera.samples_per_pixel().max(1);
+    let mut pixels = vec![PixelMoments::default(); width * height];
+    let start = Instant::now();
+    let mut rendered = 0;
+    let mut passes = 0;
+    let perf_render = PerfLog::global().map(PerfLog::begin_render);
+    let reason = loop {
+        let mut pass = rendered.max(FIRST_PASS_SAMPLES).min(max_samples - rendered);
+        if let (Some(max_time), true) = (criterion.max_time, rendered > 0) {
+            // Only as many samples as fit in the time left, going by the passes so far
+            let per_sample = start.elapsed().as_secs_f64() / rendered as Float;
+            let left = max_time.saturating_sub(start.elapsed()).as_secs_f64();
+            pass = pass.min((left / per_sample) as usize);
+            if pass == 0 {
+                break StopReason::TimeLimit;
+            }
+        }
+        let samples = rendered..rendered + pass;
+        let pass_start = Instant::now();
+        let traced_before = camera.watchdog.path_stats().traced_rays;
+        let mut render_pass = || {
+            pixels.par_iter_mut().enumerate().for_each(|(k, moments)| {
+                let (x, y) = (k % width, k / width);
+                for i in samples.clone() {
+                    camera.watchdog.begin_sample(x, y, i);
+                    moments.add(camera.render_sample(world, x, y, i));
+                    camera.watchdog.end_sample();
+                }
+            })
+        };
+        match tiles {
+            Some(tiles) => tiles.install(render_pass),
+            None => render_pass(),
+        }
+        rendered += pass;
+        passes += 1;
+
+        let converged = criterion.converged_fraction(&pixels);
+        if let (Some(log), Some(render)) = (PerfLog::global(), perf_render) {
+            log.sweep(&SweepRecord {
+                render,
+                sweep: passes,
+                samples_added: pass,
+                total_samples: rendered,
+                seconds: pass_start.elapsed().as_secs_f64(),
+                camera_rays: (pass * width * height) as u64,
+                traced_rays: perf::traced_since(&camera.watchdog, traced_before),
+                converged_fraction: Some(converged),
+            });
+        }
+        if converged >= criterion.pixel_fraction {
+            break StopReason::Converged;
+        }
+        if rendered >= max_samples {
+            break StopReason::SampleLimit;
+        }
+        if criterion.max_time.is_some_and(|max| start.elapsed() >= max) {
+            break StopReason::TimeLimit;
+        }
+    };
+
+    let mut errors: Vec<Float> = pixels
+        .par_iter()
+        .map(|moments| criterion.error_ratio(moments) * criterion.relative_error)
+        .collect();
+    errors.sort_unstable_by(Float::total_cmp);
+    let report = ConvergenceReport {
+        samples_per_pixel: rendered,
+        passes,
+        converged_fraction: criterion.converged_fraction(&pixels),
+        median_error: errors.get(errors.len() / 2).copied().unwrap_or(0.0),
+        seconds: start.elapsed().as_secs_f64(),
+        reason,
+    };
+
+    let colors = pixels.iter().map(PixelMoments::color).collect();
+    let mut image = camera.image_from_pixels(colors);
+    let time = criterion.max_time.map_or(String::new(), |max| {
+        format!(" within {} seconds", max.as_secs_f64())
+    });
+    image.metadata.extend([
+        format!(
+            "auto stop target: {}% error at {}% confidence for {}% of pixels, leaving out {}% \
+             as outliers{}",
+            criterion.relative_error * 100.0,
+            criterion.confidence * 100.0,
+            criterion.pixel_fraction * 100.0,
+            criterion.outlier_fraction * 100.0,
+            time
+        ),
+        format!("auto stop: {}", report),
+    ]);
+    if let Some(tiles) = tiles {
+        image.metadata.push(format!("threads: {}", tiles.threads()));
+    }
+    (image, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Sphere, material::Lambertian, rng::SampleRng};
+    use rand::Rng;
+    use std::sync::Arc;
+
+    #[test]
+    fn normal_quantiles_match_the_table() {
+        for (p, z) in [
+            (0.5, 0.0),
+            (0.841_344_746, 1.0),
+            (0.95, 1.644_853_627),
+            (0.975, 1.959_963_985),
+            (0.995, 2.575_829_304),
+            (0.01, -2.326_347_874),
+            (0.000_1, -3.719_016_485),
+        ] {
+            assert!((normal_quantile(p) - z).abs() < 1e-8, "{} at {}", z, p);
+        }
+        assert_eq!(normal_quantile(0.0), Float::NEG_INFINITY);
+        assert_eq!(normal_quantile(1.0), Float::INFINITY);
+    }
+
+    #[test]
+    fn student_t_quantiles_match_the_table() {
+        // Two-sided 95% and 99% critical values
+        for (p, degrees_of_freedom, t) in [
+            (0.975, 1, 12.706),
+            (0.975, 2, 4.303),
+            (0.975, 3, 3.182),
+            (0.975, 5, 2.571),
+            (0.975, 10, 2.228),
+            (0.975, 30, 2.042),
+            (0.975, 1000, 1.962),
+            (0.995, 2, 9.925),
+            (0.995, 4, 4.604),
+            (0.995, 15, 2.947),
+        ] {
+            let quantile = student_t_quantile(p, degrees_of_freedom);
+            assert!(
+                (quantile / t - 1.0).abs() < 0.005,
+                "{} for {} degrees of freedom at {}, not {}",
+                quantile,
+                degrees_of_freedom,
+                p,
+                t
+            );
+        }
+        assert!(student_t_quantile(0.975, 0).is_nan());
+    }
+
+    /// Moments of `samples` gray samples, their luminance being the same as their value
+    fn moments_of(samples: impl IntoIterator<Item = Float>) -> PixelMoments {
+        let mut moments = PixelMoments::default();
+        for value in samples {
+            moments.add(Vec3::repeat(value));
+        }
+        moments
+    }
+
+    #[test]
+    fn half_widths_follow_the_sample_variance() {
+        // Alternating 1 and 3 has a mean of 2 and, over 100 samples, a variance of 100/99
+        let moments = moments_of((0..100).map(|i| if i % 2 == 0 { 1.0 } else { 3.0 }));
+        assert!((moments.luminance_mean() - 2.0).abs() < 1e-12);
+        assert!((moments.luminance_variance() - 100.0 / 99.0).abs() < 1e-12);
+        let expected =
+            student_t_quantile(0.975, 99) * (moments.luminance_variance() / 100.0).sqrt();
+        assert!((moments.half_width(0.95) - expected).abs() < 1e-12);
+        assert_eq!(moments_of([1.0]).half_width(0.95), Float::INFINITY);
+
+        // Split and merged, the moments are the same as added one at a time
+        let mut merged = moments_of((0..30).map(|i| if i % 2 == 0 { 1.0 } else { 3.0 }));
+        merged.merge(&moments_of((30..100).map(|i| {
+            if i % 2 == 0 {
+                1.0
+            } else {
+                3.0
+            }
+        })));
+        assert!((merged.luminance_variance() - moments.luminance_variance()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn intervals_hold_the_true_mean_as_often_as_their_confidence() {
+        // Few samples, where the Student-t interval is wider than the normal one
+        let trials = 4000;
+        let covered = (0..trials)
+            .filter(|&trial| {
+                let mut rng = SampleRng::seeded(7, trial, 0, 0);
+                let moments = moments_of((0..6).map(|_| {
+                    // The sum of twelve uniforms is close to normal, with a variance of 1
+                    let normal: Float = (0..12).map(|_| rng.gen::<Float>()).sum::<Float>() - 6.0;
+                    5.0 + 0.5 * normal
+                }));
+                (moments.luminance_mean() - 5.0).abs() <= moments.half_width(0.9)
+            })
+            .count();
+        let coverage = covered as Float / trials as Float;
+        assert!((coverage - 0.9).abs() < 0.015, "{}", coverage);
+    }
+
+    #[test]
+    fn sample_shares_follow_the_variance() {
+        let shares = share_samples(&[Some(1.0), Some(3.0), None, Some(Float::INFINITY)], 120);
+        // A floor of a quarter of the even 40 each, and the 90 left split 1:3:3
+        assert_eq!(shares, vec![10 + 12, 10 + 38, 0, 10 + 38]);
+        assert!(shares.iter().sum::<usize>() <= 120);
+        assert_eq!(share_samples(&[None, None], 10), vec![0, 0]);
+        assert_eq!(share_samples(&[Some(0.0), Some(0.0)], 10), vec![5, 5]);
+        // Too small a budget to go around still gives each pixel a sample
+        assert_eq!(share_samples(&[Some(1.0); 4], 2), vec![1; 4]);
+    }
+
+    #[test]
+    fn looser_targets_stop_sooner_and_both_are_met() {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let world = World::build(vec![Sphere::new(Vec3::zeros(), 1.0, gray).into()]);
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -4.0, 0.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(25.0)
+            .with_resolution(8, 8)
+            .with_samples(2048)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(11);
+        let reference = camera.render_image(&world);
+        camera.seed = Some(12);
+        let render = |relative_error| {
+            let criterion = StopCriterion {
+                relative_error,
+                pixel_fraction: 0.95,
+                ..StopCriterion::default()
+            };
+            let (image, report) = render_until_converged(&camera, &world, &criterion, None);
+            assert_eq!(report.reason, StopReason::Converged, "{}", report);
+            // Within the target in about as many pixels as the confidence promises
+            let within = image
+                .pixels
+                .iter()
+                .zip(&reference.pixels)
+                .filter(|(color, truth)| {
+                    let truth = luminance(truth);
+                    (luminance(color) - truth).abs()
+                        <= relative_error * truth.max(criterion.luminance_floor)
+                })
+                .count();
+            assert!(
+                within >= 56,
+                "{} of 64 pixels within {}",
+                within,
+                relative_error
+            );
+            report.samples_per_pixel
+        };
+        let loose = render(0.1);
+        let tight = render(0.05);
+        assert!(loose < tight, "{} samples for 10%, {} for 5%", loose, tight);
+    }
+}
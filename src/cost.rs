@@ -0,0 +1,315 @@
+use crate::{
+    camera::{Float, Image},
+    colormap,
+    vec3::{Vec3, Vec3Ext},
+    watchdog::SampleCost,
+};
+use std::{
+    fmt,
+    ops::AddAssign,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// What a pixel's samples cost together, from their [`SampleCost`]s
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PixelCost {
+    pub nanos: u64,
+    pub rays: u64,
+    pub samples: u64,
+}
+
+impl AddAssign<SampleCost> for PixelCost {
+    fn add_assign(&mut self, sample: SampleCost) {
+        self.nanos += sample.nanos;
+        self.rays += sample.rays;
+        self.samples += 1;
+    }
+}
+
+/// Which cost a [`CostMap`] shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CostMetric {
+    /// Wall time of every sample, bounces, shadow rays and shading included. Noisy, since other
+    /// work on the machine counts too.
+    Time,
+    /// Rays traced along the samples' paths, which is repeatable but leaves out shadow rays
+    Rays,
+}
+
+impl CostMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CostMetric::Time => "time",
+            CostMetric::Rays => "rays",
+        }
+    }
+
+    /// The inverse of [`CostMetric::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "time" => Some(CostMetric::Time),
+            "rays" => Some(CostMetric::Rays),
+            _ => None,
+        }
+    }
+
+    /// What a pixel's cost per sample is measured in
+    pub fn unit(&self) -> &'static str {
+        match self {
+            CostMetric::Time => "µs per sample",
+            CostMetric::Rays => "rays per sample",
+        }
+    }
+
+    /// A pixel's cost per sample in [`CostMetric::unit`]s, or 0 if it has no samples
+    fn per_sample(&self, cost: &PixelCost) -> Float {
+        let total = match self {
+            CostMetric::Time => cost.nanos as Float / 1e3,
+            CostMetric::Rays => cost.rays as Float,
+        };
+        total / cost.samples.max(1) as Float
+    }
+}
+
+/// Which cost shows as the top of the color ramp
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CostScale {
+    /// A fixed cost per sample, so two renders' heatmaps can be compared
+    Absolute(Float),
+    /// The cost of the pixel this percent of the way from the cheapest to the most expensive, so
+    /// a few outliers don't wash out the rest of one image
+    Percentile(Float),
+}
+
+impl Default for CostScale {
+    fn default() -> Self {
+        CostScale::Percentile(99.0)
+    }
+}
+
+impl CostScale {
+    /// The cost per sample at the top of the ramp for these costs
+    pub fn max(&self, costs: &[Float]) -> Float {
+        match *self {
+            CostScale::Absolute(max) => max,
+            CostScale::Percentile(percentile) => {
+                let mut sorted = costs.to_vec();
+                sorted.sort_unstable_by(Float::total_cmp);
+                let rank = (percentile / 100.0).clamp(0.0, 1.0) * sorted.len() as Float;
+                sorted
+                    .get((rank.ceil() as usize).saturating_sub(1))
+                    .copied()
+                    .unwrap_or(0.0)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CostScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostScale::Absolute(max) => write!(f, "up to {}", max),
+            CostScale::Percentile(percentile) => write!(f, "up to the {}th percentile", percentile),
+        }
+    }
+}
+
+/// Running totals of what every pixel's samples cost, added to from any number of threads at
+/// once. Totals keep growing from sweep to sweep, so heatmaps show the cost per sample.
+pub struct CostMap {
+    pub width: usize,
+    pub height: usize,
+    nanos: Vec<AtomicU64>,
+    rays: Vec<AtomicU64>,
+    samples: Vec<AtomicU64>,
+}
+
+impl CostMap {
+    pub fn new(width: usize, height: usize) -> Self {
+        let zeros = || (0..width * height).map(|_| AtomicU64::new(0)).collect();
+        CostMap {
+            width,
+            height,
+            nanos: zeros(),
+            rays: zeros(),
+            samples: zeros(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for total in self.nanos.iter().chain(&self.rays).chain(&self.samples) {
+            total.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Adds what some samples of pixel `(x, y)` cost to its totals
+    pub fn add(&self, x: usize, y: usize, cost: &PixelCost) {
+        let i = y * self.width + x;
+        self.nanos[i].fetch_add(cost.nanos, Ordering::Relaxed);
+        self.rays[i].fetch_add(cost.rays, Ordering::Relaxed);
+        self.samples[i].fetch_add(cost.samples, Ordering::Relaxed);
+    }
+
+    /// Everything pixel `(x, y)`'s samples have cost so far
+    pub fn cost(&self, x: usize, y: usize) -> PixelCost {
+        let i = y * self.width + x;
+        PixelCost {
+            nanos: self.nanos[i].load(Ordering::Relaxed),
+            rays: self.rays[i].load(Ordering::Relaxed),
+            samples: self.samples[i].load(Ordering::Relaxed),
+        }
+    }
+
+    /// Every pixel's cost per sample, a row at a time from the top
+    pub fn per_sample(&self, metric: CostMetric) -> Vec<Float> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| metric.per_sample(&self.cost(x, y)))
+            .collect()
+    }
+
+    /// Every pixel's cost per sample in false color, from dark purple for nothing to yellow for
+    /// `scale`'s maximum and more, with the maximum
+    pub fn heatmap(&self, metric: CostMetric, scale: CostScale) -> (Vec<Vec3>, Float) {
+        let costs = self.per_sample(metric);
+        let max = scale.max(&costs);
+        let colors = costs
+            .iter()
+            .map(|cost| colormap::viridis(cost / max.max(Float::MIN_POSITIVE)))
+            .collect();
+        (colors, max)
+    }
+
+    /// The heatmap as an image to write out, with what it shows as metadata
+    pub fn to_image(&self, metric: CostMetric, scale: CostScale) -> Image {
+        let (pixels, max) = self.heatmap(metric, scale);
+        Image {
+            pixels,
+            width: self.width,
+            height: self.height,
+            // The color ramp is already gamma encoded
+            gamma: 1.0,
+            metadata: vec![format!(
+                "cost heatmap: {}, from 0 to {} {} ({})",
+                metric.name(),
+                max,
+                metric.unit(),
+                scale
+            )],
+            alpha: None,
+        }
+    }
+
+    /// Draws the heatmap over an RGBA `frame` of the same size, returning its maximum
+    pub fn present(&self, metric: CostMetric, scale: CostScale, frame: &mut [u8]) -> Float {
+        let (colors, max) = self.heatmap(metric, scale);
+        for (pixel, color) in frame.chunks_exact_mut(4).zip(&colors) {
+            let (r, g, b) = color.as_rgb_linear();
+            pixel[..3].copy_from_slice(&[r, g, b]);
+        }
+        max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{Sphere, World},
+        material::{Dielectric, Lambertian},
+    };
+    use std::sync::Arc;
+
+    /// A glass ball in front of a matte one, so some pixels cost far more than others
+    fn scene() -> (Camera, World) {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let glass = Arc::new(Dielectric::new(1.5).into());
+        let world = World::build(vec![
+            Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, gray).into(),
+            Sphere::new(Vec3::new(0.5, -1.0, 0.0), 0.5, glass).into(),
+        ]);
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -6.0, 0.5))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(40.0)
+            .with_resolution(20, 16)
+            .with_samples(8)
+            .with_max_depth(8)
+            .build()
+            .unwrap();
+        camera.seed = Some(4);
+        (camera, world)
+    }
+
+    #[test]
+    fn per_pixel_costs_sum_to_the_render_totals() {
+        let (camera, world) = scene();
+        let costs = CostMap::new(20, 16);
+        let before = camera.watchdog.path_stats();
+        let image = camera.render_image_with_cost(&world, &costs);
+        let after = camera.watchdog.path_stats();
+        // Counting costs doesn't change the render
+        assert!(image.pixels == camera.render_image(&world).pixels);
+
+        let mut total = PixelCost::default();
+        for y in 0..16 {
+            for x in 0..20 {
+                let cost = costs.cost(x, y);
+                assert_eq!(cost.samples, 8);
+                assert!(cost.rays >= 8, "({}, {}) traced {} rays", x, y, cost.rays);
+                total.nanos += cost.nanos;
+                total.rays += cost.rays;
+                total.samples += cost.samples;
+            }
+        }
+        assert_eq!(total.samples, after.samples - before.samples);
+        assert_eq!(total.rays, after.traced_rays - before.traced_rays);
+        assert_eq!(
+            total.nanos as u128,
+            (after.sample_time - before.sample_time).as_nanos()
+        );
+
+        // The glass costs more rays than the matte ball or the sky
+        let rays = costs.per_sample(CostMetric::Rays);
+        let (least, most) = rays
+            .iter()
+            .fold((Float::INFINITY, 0.0), |(a, b): (Float, Float), &r| {
+                (a.min(r), b.max(r))
+            });
+        assert_eq!(least, 1.0);
+        assert!(most > 2.0, "{}", most);
+
+        costs.clear();
+        assert_eq!(costs.cost(3, 3), PixelCost::default());
+    }
+
+    #[test]
+    fn heatmaps_color_each_pixel_by_its_share_of_the_top() {
+        let costs = CostMap::new(10, 1);
+        for x in 0..10 {
+            // x + 1 rays per sample over two samples
+            let sample = SampleCost {
+                nanos: 1000 * (x as u64 + 1),
+                rays: x as u64 + 1,
+            };
+            let mut cost = PixelCost::default();
+            cost += sample;
+            cost += sample;
+            costs.add(x, 0, &cost);
+        }
+        let (colors, max) = costs.heatmap(CostMetric::Rays, CostScale::Percentile(50.0));
+        assert_eq!(max, 5.0);
+        for (x, color) in colors.iter().enumerate() {
+            assert_eq!(*color, colormap::viridis((x + 1) as Float / 5.0));
+        }
+        let (_, max) = costs.heatmap(CostMetric::Time, CostScale::Percentile(100.0));
+        assert_eq!(max, 10.0);
+        let image = costs.to_image(CostMetric::Time, CostScale::Absolute(20.0));
+        assert_eq!(image.pixels[9], colormap::viridis(0.5));
+        assert_eq!(
+            image.metadata,
+            ["cost heatmap: time, from 0 to 20 µs per sample (up to 20)"]
+        );
+    }
+}
@@ -0,0 +1,561 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    hittable::World,
+    vec3::Vec3,
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::fmt;
+
+/// Camera rays per pixel for the albedo and normal guides. They only trace the first hit, so
+/// this is cheap next to the render itself.
+pub const GUIDE_SAMPLES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DenoiseError {
+    /// Built without the `denoise` feature, or on a platform it doesn't support
+    NotCompiled,
+    /// Open Image Denoise's library couldn't be loaded
+    LibraryMissing(String),
+    /// The denoiser ran but reported an error
+    Denoiser(String),
+    /// The guides don't match the image being denoised
+    SizeMismatch,
+}
+
+impl fmt::Display for DenoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DenoiseError::NotCompiled => write!(
+                f,
+                "denoising needs a Unix build with the `denoise` feature (cargo build --features denoise)"
+            ),
+            DenoiseError::LibraryMissing(reason) => {
+                write!(f, "couldn't load Open Image Denoise: {}", reason)
+            }
+            DenoiseError::Denoiser(message) => write!(f, "Open Image Denoise failed: {}", message),
+            DenoiseError::SizeMismatch => write!(f, "denoising guides don't match the image"),
+        }
+    }
+}
+
+impl std::error::Error for DenoiseError {}
+
+/// Per-pixel guides the denoiser uses to tell noise apart from detail, from
+/// [`Camera::render_guides`]
+pub struct Guides {
+    pub albedo: Image,
+    pub normal: Image,
+}
+
+impl Guides {
+    pub fn render(camera: &Camera, world: &World) -> Self {
+        let (albedo, normal) = camera.render_guides(world, GUIDE_SAMPLES);
+        Guides { albedo, normal }
+    }
+}
+
+/// Lays an image's colors out as rows of packed 32-bit RGB floats, which is what the denoiser
+/// reads and writes
+fn to_float3(image: &Image) -> Vec<f32> {
+    (0..image.height)
+        .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            let color = image[(x, y)];
+            [color.x as f32, color.y as f32, color.z as f32]
+        })
+        .collect()
+}
+
+/// The inverse of [`to_float3`], keeping `like`'s size, gamma and metadata
+fn from_float3(buffer: &[f32], like: &Image) -> Image {
+    let pixels = buffer
+        .chunks_exact(3)
+        .map(|c| Vec3::new(c[0] as Float, c[1] as Float, c[2] as Float))
+        .collect();
+    Image {
+        pixels,
+        width: like.width,
+        height: like.height,
+        gamma: like.gamma,
+        metadata: like.metadata.clone(),
+        alpha: None,
+    }
+}
+
+/// Denoises a linear render with Open Image Denoise, guided by the albedo and normal of what
+/// each pixel sees. The render should be before any post-processing, so things like film
+/// grain aren't mistaken for noise. Fails without touching anything if the denoiser isn't
+/// available, so the raw render can still be used.
+pub fn denoise(beauty: &Image, guides: &Guides) -> Result<Image, DenoiseError> {
+    let same_size = |image: &Image| image.width == beauty.width && image.height == beauty.height;
+    if !same_size(&guides.albedo) || !same_size(&guides.normal) {
+        return Err(DenoiseError::SizeMismatch);
+    }
+    let color = to_float3(beauty);
+    let albedo = to_float3(&guides.albedo);
+    let normal = to_float3(&guides.normal);
+    let mut output = vec![0.0; color.len()];
+    oidn::run(
+        beauty.width,
+        beauty.height,
+        &color,
+        &albedo,
+        &normal,
+        &mut output,
+    )?;
+    if output.iter().any(|c| !c.is_finite()) {
+        return Err(DenoiseError::Denoiser(
+            "the output has non-finite values".to_string(),
+        ));
+    }
+    let mut image = from_float3(&output, beauty);
+    image
+        .metadata
+        .push("denoised: Open Image Denoise, guided by albedo and normals".to_string());
+    Ok(image)
+}
+
+/// How many times brighter than its neighborhood's median a pixel has to be to count as a
+/// firefly, unless told otherwise
+pub const DEFAULT_FIREFLY_RATIO: Float = 4.0;
+/// Passes of the à-trous filter, each reaching twice as far as the last, so the widest reaches
+/// 8 pixels
+const ATROUS_PASSES: u32 = 3;
+/// The B3 spline the à-trous filter smooths with, along each axis
+const ATROUS_KERNEL: [Float; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// How far apart two colors can be at a strength of 1 and still be averaged half as much as
+/// identical ones, in their compressed `c / (1 + c)` form
+const COLOR_SIGMA: Float = 0.1;
+
+/// Settings for [`Image::denoise`], a quick denoiser on the CPU that needs no guides or library
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DenoiseParams {
+    /// Pixels brighter than this many times the median of their 3x3 neighborhood are dimmed to
+    /// it, or `None` to leave fireflies alone
+    pub firefly_ratio: Option<Float>,
+    /// How different neighboring colors can be and still be averaged, so higher values smooth
+    /// more at the cost of soft edges. 0 only takes out fireflies.
+    pub strength: Float,
+}
+
+impl DenoiseParams {
+    pub fn new(strength: Float) -> Self {
+        DenoiseParams {
+            firefly_ratio: Some(DEFAULT_FIREFLY_RATIO),
+            strength,
+        }
+    }
+
+    /// A line describing the settings, for an image's metadata
+    pub fn metadata(&self) -> String {
+        let fireflies = match self.firefly_ratio {
+            Some(ratio) => format!("fireflies over {}x their neighbors' median", ratio),
+            None => "fireflies kept".to_string(),
+        };
+        format!("quick denoise: strength {}, {}", self.strength, fireflies)
+    }
+}
+
+fn luminance(color: &Vec3) -> Float {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+impl Image {
+    /// Returns a copy with fireflies dimmed and noise smoothed by `params`, without guides, so
+    /// it works on any image. Fireflies are dimmed first, so they don't smear into their
+    /// neighbors. The smoothing is an edge-aware à-trous filter: a few passes of a B3 spline
+    /// with holes in it, each averaging in neighbors less the more their color differs, so
+    /// hard edges stay hard. It should be given linear colors before post-processing. The same
+    /// image and settings always give the same result.
+    pub fn denoise(&self, params: &DenoiseParams) -> Image {
+        let mut pixels = match params.firefly_ratio {
+            Some(ratio) => self.clamp_fireflies(ratio),
+            None => self.pixels.clone(),
+        };
+        if params.strength > 0.0 {
+            for pass in 0..ATROUS_PASSES {
+                pixels = self.atrous_pass(&pixels, 1 << pass, params.strength);
+            }
+        }
+        let mut metadata = self.metadata.clone();
+        metadata.push(params.metadata());
+        Image {
+            pixels,
+            width: self.width,
+            height: self.height,
+            gamma: self.gamma,
+            metadata,
+            alpha: self.alpha.clone(),
+        }
+    }
+
+    /// The pixels, with each whose luminance is over `ratio` times the median of its 3x3
+    /// neighborhood's scaled down to that
+    fn clamp_fireflies(&self, ratio: Float) -> Vec<Vec3> {
+        let (width, height) = (self.width, self.height);
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let color = self.pixels[i];
+                let mut neighborhood = Vec::with_capacity(9);
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        neighborhood.push(luminance(&self[(nx, ny)]));
+                    }
+                }
+                neighborhood.sort_by(Float::total_cmp);
+                let limit = ratio * neighborhood[neighborhood.len() / 2].max(0.0);
+                let brightness = luminance(&color);
+                if brightness > limit {
+                    color * (limit / brightness)
+                } else {
+                    color
+                }
+            })
+            .collect()
+    }
+
+    /// One pass of the à-trous filter over `pixels`, which are this image's size, with its taps
+    /// `step` pixels apart. Colors are compared compressed to `c / (1 + c)`, so bright ones
+    /// aren't kept apart just for being bright, and the tolerance narrows on each pass so detail
+    /// the earlier ones kept isn't smoothed away by the wider ones.
+    fn atrous_pass(&self, pixels: &[Vec3], step: usize, strength: Float) -> Vec<Vec3> {
+        let (width, height) = (self.width, self.height);
+        let compress = |color: &Vec3| color.map(|c| c.max(0.0) / (1.0 + c.max(0.0)));
+        let sigma = strength * COLOR_SIGMA / (step as Float).sqrt();
+        let falloff = std::f64::consts::LN_2 as Float / (sigma * sigma);
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let center = compress(&pixels[i]);
+                let (mut sum, mut total) = (Vec3::zeros(), 0.0);
+                for (ky, weight_y) in ATROUS_KERNEL.iter().enumerate() {
+                    let ny = y as isize + (ky as isize - 2) * step as isize;
+                    if ny < 0 || ny >= height as isize {
+                        continue;
+                    }
+                    for (kx, weight_x) in ATROUS_KERNEL.iter().enumerate() {
+                        let nx = x as isize + (kx as isize - 2) * step as isize;
+                        if nx < 0 || nx >= width as isize {
+                            continue;
+                        }
+                        let neighbor = pixels[ny as usize * width + nx as usize];
+                        let distance = (compress(&neighbor) - center).norm_squared();
+                        let weight = weight_x * weight_y * (-distance * falloff).exp();
+                        sum += neighbor * weight;
+                        total += weight;
+                    }
+                }
+                sum / total
+            })
+            .collect()
+    }
+}
+
+/// Open Image Denoise's C API, loaded when it's first used so builds don't need the library
+/// to link and renders without it still work. The `oidn` crate links the library when it's
+/// built, so a `denoise` build on a machine without it wouldn't build at all, let alone fall
+/// back to writing the raw render with an error saying what's missing. Only the ten functions
+/// below are needed, so they're bound by hand instead.
+#[cfg(all(feature = "denoise", unix))]
+mod oidn {
+    use super::DenoiseError;
+    use std::{
+        ffi::{c_char, c_void, CStr, CString},
+        sync::OnceLock,
+    };
+
+    const LIBRARY_NAMES: [&str; 4] = [
+        "libOpenImageDenoise.so.2",
+        "libOpenImageDenoise.so.1",
+        "libOpenImageDenoise.so",
+        "libOpenImageDenoise.dylib",
+    ];
+    const DEVICE_TYPE_DEFAULT: i32 = 0;
+    const FORMAT_FLOAT3: i32 = 3;
+    const ERROR_NONE: i32 = 0;
+
+    type Handle = *mut c_void;
+
+    struct Api {
+        new_device: unsafe extern "C" fn(i32) -> Handle,
+        commit_device: unsafe extern "C" fn(Handle),
+        release_device: unsafe extern "C" fn(Handle),
+        get_device_error: unsafe extern "C" fn(Handle, *mut *const c_char) -> i32,
+        new_filter: unsafe extern "C" fn(Handle, *const c_char) -> Handle,
+        set_shared_filter_image: unsafe extern "C" fn(
+            Handle,
+            *const c_char,
+            *mut c_void,
+            i32,
+            usize,
+            usize,
+            usize,
+            usize,
+            usize,
+        ),
+        set_filter_bool: unsafe extern "C" fn(Handle, *const c_char, bool),
+        commit_filter: unsafe extern "C" fn(Handle),
+        execute_filter: unsafe extern "C" fn(Handle),
+        release_filter: unsafe extern "C" fn(Handle),
+    }
+
+    // The function pointers are for a thread-safe C library
+    unsafe impl Send for Api {}
+    unsafe impl Sync for Api {}
+
+    fn api() -> Result<&'static Api, DenoiseError> {
+        static API: OnceLock<Result<Api, String>> = OnceLock::new();
+        API.get_or_init(load)
+            .as_ref()
+            .map_err(|reason| DenoiseError::LibraryMissing(reason.clone()))
+    }
+
+    /// Turns a symbol into the function pointer type `F` it's declared as in the C API
+    unsafe fn cast<F: Copy>(symbol: *mut c_void) -> F {
+        std::mem::transmute_copy(&symbol)
+    }
+
+    fn load() -> Result<Api, String> {
+        let library = LIBRARY_NAMES
+            .iter()
+            .find_map(|name| {
+                let name = CString::new(*name).ok()?;
+                let library = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW) };
+                (!library.is_null()).then_some(library)
+            })
+            .ok_or_else(|| format!("none of {} could be opened", LIBRARY_NAMES.join(", ")))?;
+        let symbol = |name: &str| {
+            let c_name = CString::new(name).unwrap();
+            let symbol = unsafe { libc::dlsym(library, c_name.as_ptr()) };
+            if symbol.is_null() {
+                Err(format!("the library has no {}", name))
+            } else {
+                Ok(symbol)
+            }
+        };
+        unsafe {
+            Ok(Api {
+                new_device: cast(symbol("oidnNewDevice")?),
+                commit_device: cast(symbol("oidnCommitDevice")?),
+                release_device: cast(symbol("oidnReleaseDevice")?),
+                get_device_error: cast(symbol("oidnGetDeviceError")?),
+                new_filter: cast(symbol("oidnNewFilter")?),
+                set_shared_filter_image: cast(symbol("oidnSetSharedFilterImage")?),
+                set_filter_bool: cast(symbol("oidnSetFilterBool")?),
+                commit_filter: cast(symbol("oidnCommitFilter")?),
+                execute_filter: cast(symbol("oidnExecuteFilter")?),
+                release_filter: cast(symbol("oidnReleaseFilter")?),
+            })
+        }
+    }
+
+    /// Runs the ray tracing filter on packed RGB float buffers of a `width` by `height` image
+    pub fn run(
+        width: usize,
+        height: usize,
+        color: &[f32],
+        albedo: &[f32],
+        normal: &[f32],
+        output: &mut [f32],
+    ) -> Result<(), DenoiseError> {
+        let api = api()?;
+        unsafe {
+            let device = (api.new_device)(DEVICE_TYPE_DEFAULT);
+            if device.is_null() {
+                return Err(DenoiseError::Denoiser(
+                    "couldn't create a device".to_string(),
+                ));
+            }
+            (api.commit_device)(device);
+            let filter = (api.new_filter)(device, c"RT".as_ptr());
+            let set_image = |name: &CStr, buffer: *const f32| {
+                (api.set_shared_filter_image)(
+                    filter,
+                    name.as_ptr(),
+                    buffer as *mut c_void,
+                    FORMAT_FLOAT3,
+                    width,
+                    height,
+                    0,
+                    0,
+                    0,
+                )
+            };
+            if !filter.is_null() {
+                set_image(c"color", color.as_ptr());
+                set_image(c"albedo", albedo.as_ptr());
+                set_image(c"normal", normal.as_ptr());
+                set_image(c"output", output.as_mut_ptr());
+                // Renders aren't clamped, so lights can be far brighter than 1
+                (api.set_filter_bool)(filter, c"hdr".as_ptr(), true);
+                (api.commit_filter)(filter);
+                (api.execute_filter)(filter);
+            }
+            let mut message: *const c_char = std::ptr::null();
+            let error = (api.get_device_error)(device, &mut message);
+            if !filter.is_null() {
+                (api.release_filter)(filter);
+            }
+            let result = if error != ERROR_NONE || filter.is_null() {
+                let message = if message.is_null() {
+                    format!("error code {}", error)
+                } else {
+                    CStr::from_ptr(message).to_string_lossy().into_owned()
+                };
+                Err(DenoiseError::Denoiser(message))
+            } else {
+                Ok(())
+            };
+            (api.release_device)(device);
+            result
+        }
+    }
+}
+
+#[cfg(not(all(feature = "denoise", unix)))]
+mod oidn {
+    use super::DenoiseError;
+
+    pub fn run(
+        _width: usize,
+        _height: usize,
+        _color: &[f32],
+        _albedo: &[f32],
+        _normal: &[f32],
+        _output: &mut [f32],
+    ) -> Result<(), DenoiseError> {
+        Err(DenoiseError::NotCompiled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenes;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn cornell_box() -> World {
+        let (shapes, surroundings) = scenes::cornell_box();
+        surroundings.build(shapes)
+    }
+
+    /// A 16x16 gray image, dark left of column 8 and light from it on, with `noise` added to
+    /// each pixel's gray level
+    fn hard_edge(noise: Float) -> Image {
+        let mut rng = StdRng::seed_from_u64(5);
+        let pixels = (0..16 * 16)
+            .map(|i| {
+                let level = if i % 16 < 8 { 0.2 } else { 0.8 };
+                Vec3::repeat(level + rng.gen_range(-noise..=noise))
+            })
+            .collect();
+        Image {
+            pixels,
+            width: 16,
+            height: 16,
+            gamma: 2.2,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+
+    /// How much the gray levels of `columns` vary, over every row
+    fn variance(image: &Image, columns: std::ops::Range<usize>) -> Float {
+        let levels: Vec<Float> = (0..image.height)
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .map(|pixel| image[pixel].x)
+            .collect();
+        let mean = levels.iter().sum::<Float>() / levels.len() as Float;
+        levels.iter().map(|l| (l - mean).powi(2)).sum::<Float>() / levels.len() as Float
+    }
+
+    #[test]
+    fn quick_denoise_dims_a_hot_pixel_without_softening_the_edge_beside_it() {
+        let mut image = hard_edge(0.0);
+        image.pixels[8 * 16 + 7] = Vec3::repeat(50.0);
+        let denoised = image.denoise(&DenoiseParams::new(1.0));
+
+        // Dimmed to the firefly ratio times its neighborhood's median, the dark side's level
+        let hot = denoised[(7, 8)].x;
+        assert!(hot <= DEFAULT_FIREFLY_RATIO * 0.2 + 1e-9, "{}", hot);
+        for y in 0..16 {
+            for x in 0..16 {
+                if (x, y) == (7, 8) {
+                    continue;
+                }
+                // Nothing smeared from it, and the columns either side of the edge stay apart
+                let expected = if x < 8 { 0.2 } else { 0.8 };
+                let level = denoised[(x, y)].x;
+                assert!((level - expected).abs() < 1e-3, "{} at {:?}", level, (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn quick_denoise_smooths_noise_either_side_of_an_edge_and_keeps_it_sharp() {
+        let image = hard_edge(0.05);
+        let params = DenoiseParams::new(1.0);
+        let denoised = image.denoise(&params);
+        for columns in [0..8, 8..16] {
+            let (before, after) = (
+                variance(&image, columns.clone()),
+                variance(&denoised, columns),
+            );
+            assert!(after < before / 4.0, "{} before, {} after", before, after);
+        }
+        for y in 0..16 {
+            let step = denoised[(8, y)].x - denoised[(7, y)].x;
+            assert!(step > 0.5, "{} across the edge in row {}", step, y);
+        }
+        assert_eq!(image.denoise(&params).pixels, denoised.pixels);
+        assert_eq!(denoised.metadata, vec![params.metadata()]);
+    }
+    #[cfg(all(feature = "denoise", unix))]
+    fn mean_squared_error(image: &Image, reference: &Image) -> Float {
+        let total: Float = image
+            .pixels
+            .iter()
+            .zip(&reference.pixels)
+            .map(|(a, b)| (a - b).norm_squared())
+            .sum();
+        total / image.pixels.len() as Float
+    }
+
+    /// Needs Open Image Denoise installed where [`oidn`] looks for it, since that's what a
+    /// `denoise` build is for
+    #[cfg(all(feature = "denoise", unix))]
+    #[test]
+    fn open_image_denoise_brings_a_noisy_render_closer_to_the_reference() {
+        let world = cornell_box();
+        let mut camera = scenes::cornell_box_camera().with_resolution(32, 32);
+        camera.seed = Some(1);
+        let noisy = camera.with_sampling(4, 8).render_image(&world);
+        let reference = camera.with_sampling(512, 8).render_image(&world);
+        let guides = Guides::render(&camera, &world);
+        let denoised = denoise(&noisy, &guides).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!((denoised.width, denoised.height), (32, 32));
+        assert!(denoised
+            .pixels
+            .iter()
+            .all(|c| c.iter().all(|c| c.is_finite())));
+        let before = mean_squared_error(&noisy, &reference);
+        let after = mean_squared_error(&denoised, &reference);
+        assert!(after < before / 2.0, "{} before, {} after", before, after);
+    }
+
+    #[cfg(not(all(feature = "denoise", unix)))]
+    #[test]
+    fn builds_without_the_feature_say_so() {
+        let world = cornell_box();
+        let camera = scenes::cornell_box_camera().with_resolution(4, 4);
+        let image = camera.with_sampling(1, 2).render_image(&world);
+        let guides = Guides::render(&camera, &world);
+        assert!(matches!(
+            denoise(&image, &guides),
+            Err(DenoiseError::NotCompiled)
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    cell::UnsafeCell,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// A frame buffer shared between one render thread that writes it and any number of threads
+/// that show or save it, without either side ever taking a lock.
+///
+/// There are two frames. Readers only ever see the front one, which is never written while
+/// it's in front. The writer fills in the back one and publishes it by swapping the two, so
+/// readers see either the old frame or the new one whole, and never a half-written one.
+///
+/// Readers announce themselves on the frame they're reading, and the writer waits for the
+/// ones still on a frame that just went to the back to finish before writing over it. Readers
+/// never wait.
+pub struct DisplayBuffer {
+    frames: [UnsafeCell<Box<[u8]>>; 2],
+    /// Index of the front frame
+    front: AtomicUsize,
+    /// Readers currently reading each frame
+    readers: [AtomicUsize; 2],
+    /// How many frames have been published
+    epoch: AtomicUsize,
+}
+
+// Frames are only written by the `DisplayWriter`, while no reader can see them
+unsafe impl Sync for DisplayBuffer {}
+unsafe impl Send for DisplayBuffer {}
+
+/// The only handle that can write to a [`DisplayBuffer`]
+pub struct DisplayWriter {
+    buffer: Arc<DisplayBuffer>,
+    /// Bytes the back frame is behind the front one on, which are what the last publish wrote
+    stale: Range<usize>,
+}
+
+impl DisplayBuffer {
+    /// Returns a buffer of `len` bytes with both frames set to `fill`, and its writer
+    pub fn new(len: usize, fill: u8) -> (Arc<DisplayBuffer>, DisplayWriter) {
+        let frame = || UnsafeCell::new(vec![fill; len].into_boxed_slice());
+        let buffer = Arc::new(DisplayBuffer {
+            frames: [frame(), frame()],
+            front: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            epoch: AtomicUsize::new(0),
+        });
+        let writer = DisplayWriter {
+            buffer: buffer.clone(),
+            stale: 0..0,
+        };
+        (buffer, writer)
+    }
+
+    /// Calls `read` with the most recently published frame, which doesn't change while it runs
+    pub fn read<T>(&self, read: impl FnOnce(&[u8]) -> T) -> T {
+        let front = loop {
+            let front = self.front.load(Ordering::SeqCst);
+            self.readers[front].fetch_add(1, Ordering::SeqCst);
+            // If a swap got in before the reader was counted, the writer may already be past
+            // waiting on this frame, so try again with the new front
+            if self.front.load(Ordering::SeqCst) == front {
+                break front;
+            }
+            self.readers[front].fetch_sub(1, Ordering::SeqCst);
+        };
+        // Decrements the count even if `read` panics, so the writer isn't stuck waiting
+        struct Release<'a>(&'a AtomicUsize);
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        let _release = Release(&self.readers[front]);
+        read(unsafe { &*self.frames[front].get() })
+    }
+
+    /// Copies the most recently published frame
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.read(<[u8]>::to_vec)
+    }
+
+    /// How many frames have been published, so readers can tell when there's a new one
+    pub fn epoch(&self) -> usize {
+        self.epoch.load(Ordering::SeqCst)
+    }
+}
+
+impl DisplayWriter {
+    /// Calls `write` with the back frame to fill in, along with the front frame readers are
+    /// currently seeing, and then publishes the back frame. The back frame still holds the frame
+    /// before the front one, so anything `write` doesn't set should be copied from the front.
+    pub fn publish(&mut self, write: impl FnOnce(&mut [u8], &[u8])) {
+        let len = self.swap(write);
+        self.stale = 0..len;
+    }
+
+    /// Publishes a frame that only differs from the front one in `range`, calling `write` with
+    /// just that part of the back frame. Only what the last publish wrote is copied over from
+    /// the front, so publishing a few rows at a time doesn't copy the whole frame every time.
+    pub fn publish_range(&mut self, range: Range<usize>, write: impl FnOnce(&mut [u8])) {
+        let stale = self.stale.clone();
+        self.swap(|back, front| {
+            back[stale.clone()].copy_from_slice(&front[stale]);
+            write(&mut back[range.clone()]);
+        });
+        self.stale = range;
+    }
+
+    /// Waits for the back frame to be free, calls `write` with it and the front frame, and then
+    /// swaps them. Returns the frames' length.
+    fn swap(&mut self, write: impl FnOnce(&mut [u8], &[u8])) -> usize {
+        let buffer = &*self.buffer;
+        let front = buffer.front.load(Ordering::SeqCst);
+        let back = 1 - front;
+        // Readers that were still on this frame when it went to the back
+        while buffer.readers[back].load(Ordering::SeqCst) != 0 {
+            std::thread::yield_now();
+        }
+        // Nothing else writes either frame, and readers only ever read the front one
+        let (back_frame, front_frame) = unsafe {
+            (
+                &mut **buffer.frames[back].get(),
+                &**buffer.frames[front].get(),
+            )
+        };
+        write(back_frame, front_frame);
+        buffer.front.store(back, Ordering::SeqCst);
+        buffer.epoch.fetch_add(1, Ordering::SeqCst);
+        back_frame.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    const LEN: usize = 4096;
+
+    /// Fills `frame` with its `number` and bytes made from it, writing a checksum last
+    fn write_frame(frame: &mut [u8], number: u32) {
+        frame[..4].copy_from_slice(&number.to_le_bytes());
+        for (i, byte) in frame[4..LEN - 1].iter_mut().enumerate() {
+            *byte = (number as usize).wrapping_mul(31).wrapping_add(i) as u8;
+        }
+        frame[LEN - 1] = checksum(frame);
+    }
+
+    fn checksum(frame: &[u8]) -> u8 {
+        frame[..LEN - 1]
+            .iter()
+            .fold(0u8, |sum, byte| sum.rotate_left(1) ^ byte)
+    }
+
+    /// The frame's number, if every byte of it belongs to that frame
+    fn check_frame(frame: &[u8]) -> Option<u32> {
+        let number = u32::from_le_bytes(frame[..4].try_into().unwrap());
+        let whole = frame[4..LEN - 1]
+            .iter()
+            .enumerate()
+            .all(|(i, &byte)| byte == (number as usize).wrapping_mul(31).wrapping_add(i) as u8);
+        (whole && frame[LEN - 1] == checksum(frame)).then_some(number)
+    }
+
+    #[test]
+    fn readers_never_see_a_torn_frame() {
+        let (buffer, mut writer) = DisplayBuffer::new(LEN, 0);
+        writer.publish(|back, _| write_frame(back, 0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let buffer = buffer.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let (mut last, mut reads) = (0, 0);
+                    while !done.load(Ordering::SeqCst) || reads == 0 {
+                        let epoch = buffer.epoch();
+                        let number = buffer.read(check_frame).expect("read a torn frame");
+                        assert!(number >= last, "frame {} read after {}", number, last);
+                        // Frame `n` is the `n + 1`th published
+                        assert!(
+                            number as usize + 1 >= epoch,
+                            "read a frame older than epoch"
+                        );
+                        last = number;
+                        reads += 1;
+                    }
+                    check_frame(&buffer.snapshot()).expect("snapshot a torn frame")
+                })
+            })
+            .collect();
+
+        for number in 1..=2000 {
+            writer.publish(|back, front| {
+                assert_eq!(check_frame(front), Some(number - 1));
+                write_frame(back, number);
+            });
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), 2000);
+        }
+        assert_eq!(buffer.epoch(), 2001);
+    }
+
+    #[test]
+    fn publishing_ranges_keeps_what_was_published_before() {
+        let (buffer, mut writer) = DisplayBuffer::new(12, 0);
+        let mut expected = vec![0; 12];
+        // Bands in order, then over again, like sweeps of a progressive render, with a whole
+        // frame in the middle
+        for (pass, band) in [0..4, 4..8, 8..12, 0..4, 4..8].into_iter().enumerate() {
+            let value = pass as u8 + 1;
+            writer.publish_range(band.clone(), |back| back.fill(value));
+            expected[band].fill(value);
+            assert_eq!(buffer.snapshot(), expected);
+        }
+        writer.publish(|back, _| back.fill(9));
+        expected.fill(9);
+        writer.publish_range(8..12, |back| back.fill(7));
+        expected[8..12].fill(7);
+        assert_eq!(buffer.snapshot(), expected);
+        writer.publish_range(2..6, |back| back.fill(6));
+        expected[2..6].fill(6);
+        assert_eq!(buffer.snapshot(), expected);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{
+    camera::Float,
+    hittable::World,
+    intersection::Intersection,
+    material::{reflect, reflectance, Material, Scatter},
+    texture::Texture,
+    vec3::{Ray, Vec3},
+};
+
+/// Estimates the light coming back along a camera `ray` that hit `hit` without tracing anything
+/// past it. Every surface is lit by the whole sky as if nothing were in the way: diffuse ones by
+/// its spherical harmonic projection, mirrors by looking up the sky they reflect, and glass by
+/// blending that with the sky straight through it by how much it reflects. With no bounces,
+/// roulette or light sampling there's nothing random left, so one sample is as good as many.
+pub fn radiance(world: &World, ray: &Ray, hit: Option<Intersection>) -> Vec3 {
+    let direction = ray.direction.normalize();
+    match hit {
+        Some(hit) => hit.material.emitted(&hit) + reflected(world, hit.material, &hit, &direction),
+        None => world.sky_color_toward(&direction),
+    }
+}
+
+/// The sky light `material` sends back toward the camera at `hit`, which a ray going along the
+/// unit vector `direction` found
+fn reflected(world: &World, material: &Material, hit: &Intersection, direction: &Vec3) -> Vec3 {
+    let (u, v) = (hit.uv.x, hit.uv.y);
+    let mirror = || reflect(*direction, hit.normal).normalize();
+    match material {
+        Material::Lambertian(lambertian) => lambertian
+            .texture
+            .value(u, v, hit.point)
+            .component_mul(&world.sky_harmonics().diffuse(&hit.normal)),
+        Material::Metal(metal) => {
+            let mirror = mirror();
+            let sharp = world.sky_color_toward(&mirror);
+            // Fuzz blurs the reflection, which the projection already is
+            let blur = metal.fuzz.unwrap_or(0.0).clamp(0.0, 1.0);
+            let blurred = world.sky_harmonics().radiance(&mirror);
+            let color = sharp * (1.0 - blur) + blurred * blur;
+            metal.texture.value(u, v, hit.point).component_mul(&color)
+        }
+        Material::Dielectric(dielectric) => {
+            let ri = if hit.is_front_face {
+                1.0 / dielectric.refractive_index
+            } else {
+                dielectric.refractive_index
+            };
+            let cos_theta = (-direction.dot(&hit.normal)).min(1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let reflects: Float = if ri * sin_theta > 1.0 {
+                1.0
+            } else {
+                reflectance(cos_theta, ri)
+            };
+            // What's behind the glass is left out, so it shows the sky as if it were clear air
+            let through = world.sky_color_toward(direction);
+            let through = match &dielectric.tint {
+                Some(tint) => tint.value(u, v, hit.point).component_mul(&through),
+                None => through,
+            };
+            world.sky_color_toward(&mirror()) * reflects + through * (1.0 - reflects)
+        }
+        Material::AlphaMask(mask) => reflected(world, &mask.base, hit, direction),
+        Material::Volumetric(volumetric) => volumetric
+            .albedo
+            .component_mul(&world.sky_harmonics().average()),
+        Material::DiffuseLight(_) => Vec3::zeros(),
+        // Like the path tracer, without the shadows
+        Material::ShadowCatcher(_) => world.sky_color_toward(direction),
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{
+    camera::{Float, Image, DEFAULT_GAMMA},
+    uv::to_unit_spherical,
+    vec3::Vec3,
+};
+use std::{f64::consts::PI, fmt, path::Path, sync::Arc};
+
+/// A panorama of the surroundings in an equirectangular image, lighting the scene from every
+/// direction rays escape in, see [`crate::hittable::Background::Environment`]. The top row is
+/// straight up (+Z) and the bottom one straight down, and the columns go once around the horizon.
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    image: Arc<Image>,
+    /// How far the map is spun around the up axis, in degrees
+    rotation: Float,
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("width", &self.image.width)
+            .field("height", &self.image.height)
+            .field("rotation", &self.rotation)
+            .finish()
+    }
+}
+
+impl PartialEq for EnvironmentMap {
+    /// Maps are the same if they share their image, which is too big to compare pixel by pixel
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.image, &other.image) && self.rotation == other.rotation
+    }
+}
+
+impl EnvironmentMap {
+    /// Wraps an image of linear radiance, spun `rotation` degrees around the up axis
+    pub fn new(image: Image, rotation: Float) -> Self {
+        EnvironmentMap::shared(Arc::new(image), rotation)
+    }
+
+    /// Like [`EnvironmentMap::new`], with an image other maps may also use
+    pub fn shared(image: Arc<Image>, rotation: Float) -> Self {
+        EnvironmentMap { image, rotation }
+    }
+
+    /// Loads the panorama at `path`. Radiance `.hdr` and OpenEXR files keep their values as they
+    /// are, above 1 included. Other images hold gamma-encoded colors, which are linearized.
+    pub fn load(path: impl AsRef<Path>) -> Result<Image, image::ImageError> {
+        Ok(EnvironmentMap::linearize(image::open(path)?))
+    }
+
+    /// Like [`EnvironmentMap::load`], from a file held in memory
+    pub fn load_from_memory(data: &[u8]) -> Result<Image, image::ImageError> {
+        Ok(EnvironmentMap::linearize(image::load_from_memory(data)?))
+    }
+
+    fn linearize(decoded: image::DynamicImage) -> Image {
+        let is_float = matches!(
+            decoded.color(),
+            image::ColorType::Rgb32F | image::ColorType::Rgba32F
+        );
+        let rgb = decoded.into_rgb32f();
+        let pixels = rgb
+            .pixels()
+            .map(|pixel| {
+                let color = Vec3::new(pixel[0].into(), pixel[1].into(), pixel[2].into());
+                if is_float {
+                    color
+                } else {
+                    color.map(|c| c.max(0.0).powf(DEFAULT_GAMMA))
+                }
+            })
+            .collect();
+        Image {
+            pixels,
+            width: rgb.width() as usize,
+            height: rgb.height() as usize,
+            gamma: DEFAULT_GAMMA,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn rotation(&self) -> Float {
+        self.rotation
+    }
+
+    /// Returns the radiance coming from `direction`, which has to be a unit vector, blended
+    /// bilinearly between the four nearest pixels. Columns wrap around, so there's no seam
+    /// where the first and last meet.
+    pub fn radiance_toward(&self, direction: &Vec3) -> Vec3 {
+        let (width, height) = (self.image.width, self.image.height);
+        if width == 0 || height == 0 {
+            return Vec3::zeros();
+        }
+        let (theta, phi) = to_unit_spherical(*direction);
+        let u = (phi - self.rotation.to_radians()) / (2.0 * PI);
+        // `theta` is measured from straight down, and the top row is straight up
+        let v = 1.0 - theta / PI;
+
+        // Pixel centers are half a pixel in from the edges
+        let x = (u * width as Float - 0.5).rem_euclid(width as Float);
+        let y = (v * height as Float - 0.5).clamp(0.0, (height - 1) as Float);
+        let (x0, y0) = (x.floor() as usize % width, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1) % width, (y0 + 1).min(height - 1));
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+
+        let top = self.image[(x0, y0)] * (1.0 - tx) + self.image[(x1, y0)] * tx;
+        let bottom = self.image[(x0, y1)] * (1.0 - tx) + self.image[(x1, y1)] * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
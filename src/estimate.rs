@@ -0,0 +1,428 @@
+use crate::{
+    camera::{Float, PathEvent},
+    hittable::World,
+    job::RenderJob,
+    tiles::TileRenderer,
+};
+use rayon::prelude::*;
+use std::{
+    fmt,
+    io::{self, BufRead, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+/// Width of the 1 sample per pixel render that measures how long paths are
+const PROBE_WIDTH: usize = 64;
+/// How long the calibration burst renders for
+const CALIBRATION_TIME: Duration = Duration::from_millis(500);
+/// Samples rendered between checks of the calibration clock, per thread
+const CALIBRATION_CHUNK: usize = 64;
+/// Spreads calibration samples over the image instead of starting in a corner. Coprime to any
+/// pixel count that isn't a multiple of it.
+const CALIBRATION_STRIDE: usize = 7919;
+
+/// Exit code `rt render` uses when it declines to start a render that's over its limits, which
+/// is distinct from failing
+pub const DECLINED_EXIT_CODE: i32 = 3;
+
+/// Total rays a render traces: one path per sample, `mean_path_length` rays long on average
+pub fn ray_count(pixels: usize, samples_per_pixel: usize, mean_path_length: Float) -> Float {
+    pixels as Float * samples_per_pixel as Float * mean_path_length
+}
+
+/// Size of the PPM `rt render` writes for an image, not counting the metadata comments in its
+/// header, which are a few hundred bytes
+pub fn ppm_bytes(width: usize, height: usize) -> u64 {
+    let header = format!("P6\n{} {}\n255\n", width, height);
+    header.len() as u64 + width as u64 * height as u64 * 3
+}
+
+/// What a render is expected to cost, from [`estimate`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostEstimate {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub frames: usize,
+    /// Mean number of rays a sample's path traces, camera ray included
+    pub mean_path_length: Float,
+    /// Over every frame
+    pub rays: Float,
+    /// Wall time over every frame, extrapolated from the calibration burst
+    pub seconds: Float,
+    /// Size of every output image together
+    pub output_bytes: u64,
+}
+
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}x{} at {} samples per pixel, {} frame(s)",
+            self.width, self.height, self.samples_per_pixel, self.frames
+        )?;
+        writeln!(
+            f,
+            "  {:.3e} rays ({:.2} per sample)",
+            self.rays, self.mean_path_length
+        )?;
+        writeln!(f, "  about {}", format_duration(self.seconds))?;
+        write!(f, "  {} of output", format_bytes(self.output_bytes))
+    }
+}
+
+fn format_duration(seconds: Float) -> String {
+    match seconds {
+        s if s < 60.0 => format!("{:.1} seconds", s),
+        s if s < 3600.0 => format!("{:.1} minutes", s / 60.0),
+        s if s < 86400.0 => format!("{:.1} hours", s / 3600.0),
+        s => format!("{:.1} days", s / 86400.0),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as Float;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Renders a tiny version of the job at 1 sample per pixel and returns the mean number of rays
+/// its paths traced. Goes through the same path as real renders, so it tracks the renderer.
+pub fn probe_path_length(job: &RenderJob, world: &World) -> Float {
+    let width = job.width.clamp(1, PROBE_WIDTH);
+    let height = (job.height * width / job.width.max(1)).max(1);
+    let probe = RenderJob {
+        width,
+        height,
+        samples_per_pixel: 1,
+        ..job.clone()
+    };
+    let camera = probe.camera();
+    let rays: usize = (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let trace = camera.trace_sample(world, i % width, i / width, 0);
+            let mut rays = 1;
+            for event in &trace.events {
+                // Every surface a path hits scatters one more ray, until it's cut off
+                match event {
+                    PathEvent::Bounce(_) => rays += 1,
+                    PathEvent::Absorbed | PathEvent::DepthLimit | PathEvent::Stuck => rays -= 1,
+                    PathEvent::Escaped { .. } => {}
+                }
+            }
+            rays.max(1)
+        })
+        .sum();
+    rays as Float / (width * height) as Float
+}
+
+/// Renders samples of the job spread over its image for about `budget` and extrapolates how
+/// long the whole render would take, on `tiles` if given like the render itself. Finishes
+/// early if the whole render fits in the budget, and with the exact time if it fits in the
+/// first chunk.
+pub fn calibrate_seconds(
+    job: &RenderJob,
+    world: &World,
+    tiles: Option<&TileRenderer>,
+    budget: Duration,
+) -> Float {
+    let camera = job.camera();
+    let pixels = job.width * job.height;
+    let samples = pixels * job.samples_per_pixel;
+    if samples == 0 {
+        return 0.0;
+    }
+    let threads = tiles.map_or_else(rayon::current_num_threads, TileRenderer::threads);
+    let chunk = CALIBRATION_CHUNK * threads;
+    let render_chunk = |start: usize| {
+        (start..(start + chunk).min(samples))
+            .into_par_iter()
+            .for_each(|k| {
+                let pixel = (k % pixels) * CALIBRATION_STRIDE % pixels;
+                let i = k / pixels;
+                camera.render_sample(world, pixel % job.width, pixel / job.width, i);
+            })
+    };
+    let run_chunk = |start: usize| match tiles {
+        Some(tiles) => tiles.install(|| render_chunk(start)),
+        None => render_chunk(start),
+    };
+    // The first chunk pays for threads starting and tables filling in, so it isn't timed
+    let warm_up = Instant::now();
+    run_chunk(0);
+    if chunk >= samples {
+        return warm_up.elapsed().as_secs_f64();
+    }
+    let start = Instant::now();
+    let mut rendered = chunk;
+    while rendered < samples && start.elapsed() < budget {
+        run_chunk(rendered);
+        rendered = (rendered + chunk).min(samples);
+    }
+    start.elapsed().as_secs_f64() / (rendered - chunk) as Float * samples as Float
+}
+
+/// Estimates what rendering `frames` frames of the job costs, spending about half a second
+/// rendering to find out
+pub fn estimate(
+    job: &RenderJob,
+    world: &World,
+    tiles: Option<&TileRenderer>,
+    frames: usize,
+) -> CostEstimate {
+    let mean_path_length = probe_path_length(job, world);
+    let seconds = calibrate_seconds(job, world, tiles, CALIBRATION_TIME);
+    // A bracket writes every frame once per exposure
+    let images = job
+        .bracket
+        .as_ref()
+        .map_or(1, |bracket| bracket.evs().len());
+    CostEstimate {
+        width: job.width,
+        height: job.height,
+        samples_per_pixel: job.samples_per_pixel,
+        frames,
+        mean_path_length,
+        rays: ray_count(
+            job.width * job.height,
+            job.samples_per_pixel,
+            mean_path_length,
+        ) * frames as Float,
+        seconds: seconds * frames as Float,
+        output_bytes: ppm_bytes(job.width, job.height) * (frames * images) as u64,
+    }
+}
+
+/// How big a render can get before it has to be confirmed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostLimits {
+    pub max_seconds: Float,
+    pub max_output_bytes: u64,
+}
+
+impl Default for CostLimits {
+    fn default() -> Self {
+        CostLimits {
+            max_seconds: 4.0 * 3600.0,
+            max_output_bytes: 4 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl CostLimits {
+    /// Describes every limit `estimate` goes over
+    pub fn exceeded_by(&self, estimate: &CostEstimate) -> Vec<String> {
+        let mut exceeded = Vec::new();
+        if estimate.seconds > self.max_seconds {
+            exceeded.push(format!(
+                "would take {}, over the limit of {}",
+                format_duration(estimate.seconds),
+                format_duration(self.max_seconds)
+            ));
+        }
+        if estimate.output_bytes > self.max_output_bytes {
+            exceeded.push(format!(
+                "would write {}, over the limit of {}",
+                format_bytes(estimate.output_bytes),
+                format_bytes(self.max_output_bytes)
+            ));
+        }
+        exceeded
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Proceed,
+    Decline,
+}
+
+/// Decides whether to go ahead with a render. Renders within `limits` and ones already
+/// confirmed with `assume_yes` always go ahead; otherwise `confirm` is asked with the reasons.
+pub fn decide(
+    estimate: &CostEstimate,
+    limits: &CostLimits,
+    assume_yes: bool,
+    confirm: impl FnOnce(&[String]) -> bool,
+) -> Decision {
+    let exceeded = limits.exceeded_by(estimate);
+    if exceeded.is_empty() || assume_yes || confirm(&exceeded) {
+        Decision::Proceed
+    } else {
+        Decision::Decline
+    }
+}
+
+/// Asks on the terminal whether to go ahead despite `exceeded`. Declines without asking when
+/// nobody's there to answer, like in scripts, which have to pass `--yes` instead.
+pub fn confirm_on_terminal(exceeded: &[String]) -> bool {
+    for reason in exceeded {
+        println!("This render {}", reason);
+    }
+    if !io::stdin().is_terminal() {
+        println!("Pass --yes to render it anyway");
+        return false;
+    }
+    print!("Render it anyway? [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{Camera, Image},
+        hittable::Sphere,
+        job::HandoffSettings,
+        material::Lambertian,
+        vec3::Vec3,
+    };
+    use std::sync::Arc;
+
+    fn ball() -> World {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        World::build(vec![Sphere::new(Vec3::zeros(), 1.0, gray).into()])
+    }
+
+    /// A seeded job looking at the ball from `distance` away, at `vertical_fov` degrees
+    fn job(world: &World, distance: Float, vertical_fov: Float, size: usize) -> RenderJob {
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -distance, 0.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(vertical_fov)
+            .with_resolution(size, size)
+            .with_max_depth(8)
+            .build()
+            .unwrap();
+        camera.seed = Some(2);
+        let settings = HandoffSettings {
+            samples_per_pixel: 16,
+            resolution_scale: 1.0,
+            ..HandoffSettings::default()
+        };
+        RenderJob::from_camera(&camera, world, &settings)
+    }
+
+    fn estimate_of(seconds: Float, output_bytes: u64) -> CostEstimate {
+        CostEstimate {
+            width: 100,
+            height: 100,
+            samples_per_pixel: 1,
+            frames: 1,
+            mean_path_length: 1.0,
+            rays: 1e4,
+            seconds,
+            output_bytes,
+        }
+    }
+
+    #[test]
+    fn ray_counts_and_sizes_are_exact() {
+        assert_eq!(ray_count(1920 * 1080, 64, 2.5), 331_776_000.0);
+        assert_eq!(ray_count(0, 64, 2.5), 0.0);
+        let image = Image {
+            pixels: vec![Vec3::zeros(); 7 * 5],
+            width: 7,
+            height: 5,
+            gamma: 2.2,
+            metadata: Vec::new(),
+            alpha: None,
+        };
+        assert_eq!(ppm_bytes(7, 5), image.encode_ppm().len() as u64);
+    }
+
+    #[test]
+    fn probes_count_a_ray_per_segment_of_the_path() {
+        // Nothing to hit, so every path is just its camera ray
+        let empty = World::build(Vec::new());
+        assert_eq!(probe_path_length(&job(&empty, 3.0, 20.0, 32), &empty), 1.0);
+        // Filling the view, so every path hits the ball once and bounces off into the sky
+        let world = ball();
+        assert_eq!(probe_path_length(&job(&world, 2.0, 20.0, 32), &world), 2.0);
+        // Seen from far off, some paths miss it
+        let far = probe_path_length(&job(&world, 8.0, 20.0, 32), &world);
+        assert!(far > 1.0 && far < 2.0, "{}", far);
+    }
+
+    #[test]
+    fn calibration_extrapolates_to_about_the_render_time() {
+        let world = ball();
+        let job = job(&world, 3.0, 40.0, 48);
+        let estimate = calibrate_seconds(&job, &world, None, Duration::from_millis(50));
+        let start = Instant::now();
+        job.camera().render_image(&world);
+        let actual = start.elapsed().as_secs_f64();
+        assert!(
+            estimate > actual / 4.0 && estimate < actual * 4.0,
+            "estimated {} seconds, took {}",
+            estimate,
+            actual
+        );
+    }
+
+    #[test]
+    fn enormous_renders_are_estimated_without_being_rendered() {
+        let world = ball();
+        let mut huge = job(&world, 3.0, 40.0, 16);
+        (huge.width, huge.height, huge.samples_per_pixel) = (16384, 16384, 4096);
+        let start = Instant::now();
+        // Eight frames of it, which is gigabytes of output too
+        let estimate = estimate(&huge, &world, None, 8);
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(
+            estimate.rays,
+            ray_count(16384 * 16384, 4096, estimate.mean_path_length) * 8.0
+        );
+        assert_eq!(estimate.output_bytes, ppm_bytes(16384, 16384) * 8);
+        let limits = CostLimits::default();
+        assert_eq!(limits.exceeded_by(&estimate).len(), 2, "{}", estimate);
+
+        let mut asked = Vec::new();
+        let decision = decide(&estimate, &limits, false, |exceeded| {
+            asked.extend_from_slice(exceeded);
+            false
+        });
+        assert_eq!(decision, Decision::Decline);
+        assert!(asked[0].starts_with("would take") && asked[1].starts_with("would write"));
+        assert_eq!(
+            decide(&estimate, &limits, false, |_| true),
+            Decision::Proceed
+        );
+        let unasked = |_: &[String]| panic!("confirmed with --yes, so nobody should be asked");
+        assert_eq!(decide(&estimate, &limits, true, unasked), Decision::Proceed);
+    }
+
+    #[test]
+    fn renders_within_the_limits_go_ahead_without_asking() {
+        let limits = CostLimits {
+            max_seconds: 60.0,
+            max_output_bytes: 1000,
+        };
+        let unasked = |_: &[String]| panic!("within the limits, so nobody should be asked");
+        assert_eq!(
+            decide(&estimate_of(60.0, 1000), &limits, false, unasked),
+            Decision::Proceed
+        );
+        let over_time = limits.exceeded_by(&estimate_of(61.0, 1000));
+        assert_eq!(
+            over_time,
+            ["would take 1.0 minutes, over the limit of 1.0 minutes"]
+        );
+        let over_size = limits.exceeded_by(&estimate_of(1.0, 1001));
+        assert_eq!(
+            over_size,
+            ["would write 1001.0 B, over the limit of 1000.0 B"]
+        );
+    }
+}
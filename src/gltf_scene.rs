@@ -0,0 +1,324 @@
+use crate::{
+    camera::{Camera, Float},
+    hittable::{gltf_node_transforms, Shape, Sphere, SUN_ANGLE},
+    material::{DiffuseLight, Material},
+    object::ObjectId,
+    scene_lights::SpotLight,
+    scenes::MAX_DEPTH,
+    texture::{LoadReport, SolidColor},
+    vec3::{Point3, Vec3},
+    window::{HEIGHT, WIDTH},
+};
+use bvh::aabb::{Aabb, Bounded};
+use gltf::{camera::Projection, khr_lights_punctual::Kind};
+use nalgebra::{Matrix4, Vector4};
+use std::{
+    f64::consts::{PI, TAU},
+    sync::Arc,
+};
+
+/// Luminous efficacy glTF's photometric units are converted back to watts with. Blender's
+/// exporter multiplies by the same, so lights come back with the strengths they were given there.
+pub const LUMENS_PER_WATT: Float = 683.0;
+
+/// Radius of the glowing sphere a point light becomes
+pub const POINT_LIGHT_RADIUS: Float = 0.05;
+
+/// How far away the disc a directional light becomes is, in radii of the scene's bounds, so it's
+/// far enough that its light arrives almost parallel without blowing up the scene's tolerances
+const DIRECTIONAL_LIGHT_DISTANCE: Float = 100.0;
+
+/// Samples per pixel of cameras imported from glTF files, as for scene files
+const SAMPLES_PER_PIXEL: usize = 32;
+
+/// Focus distance of imported cameras. glTF cameras have no lens, so nothing is out of focus
+/// unless a defocus angle is set afterwards.
+const FOCUS_DISTANCE: Float = 1.0;
+
+/// What a punctual light gives off, in the units the renderer's emitters use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Gives off light evenly in every direction from `position`, with `intensity` watts per
+    /// steradian
+    Point { intensity: Float },
+    /// Lights everything from `direction` alike, with `irradiance` watts per square meter on a
+    /// surface facing it
+    Directional { irradiance: Float },
+    /// Shines `intensity` watts per steradian down `direction` from `position`, fading out
+    /// between the `inner` and `outer` cone angles, in degrees
+    Spot {
+        intensity: Float,
+        inner: Float,
+        outer: Float,
+    },
+}
+
+/// A light from glTF's KHR_lights_punctual extension, which [`light_shapes`] turns into glowing
+/// spheres or spot lights to add to the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    /// Path of the node it's on, e.g. "scene/rig/key"
+    pub name: String,
+    pub kind: LightKind,
+    pub position: Point3,
+    /// Unit vector the light shines along
+    pub direction: Vec3,
+    /// Linear color, which scales the intensity or irradiance
+    pub color: Vec3,
+}
+
+impl Light {
+    /// Converts `light` on the node at `path`, placed by `matrix`, from glTF's candela and lux.
+    /// `range` is ignored, so lights reach as far as physics says they do.
+    fn from_gltf(
+        light: &gltf::khr_lights_punctual::Light,
+        path: String,
+        matrix: &Matrix4<Float>,
+    ) -> Self {
+        let intensity = Float::from(light.intensity()) / LUMENS_PER_WATT;
+        let kind = match light.kind() {
+            Kind::Directional => LightKind::Directional {
+                irradiance: intensity,
+            },
+            Kind::Point => LightKind::Point { intensity },
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                intensity,
+                inner: Float::from(inner_cone_angle).to_degrees(),
+                outer: Float::from(outer_cone_angle).to_degrees(),
+            },
+        };
+        // Lights shine down their node's -Z
+        let direction = (matrix * Vector4::new(0.0, 0.0, -1.0, 0.0)).xyz();
+        Light {
+            name: path,
+            kind,
+            // `Point3` is a vector, so the origin has to be a point for the translation to apply
+            position: matrix.transform_point(&nalgebra::Point3::origin()).coords,
+            direction: direction.try_normalize(0.0).unwrap_or(-Vec3::z()),
+            color: Vec3::from(light.color().map(Float::from)),
+        }
+    }
+
+    /// The shape this light becomes in a scene whose bounds reach `scene_radius` from their
+    /// center at `scene_center`. A point light is a small sphere giving off the same intensity
+    /// in every direction. A directional light is a sphere as wide as the sun far away against
+    /// the light's direction, giving the same irradiance to what faces it. A spot light is a
+    /// [`SpotLight`].
+    pub fn to_shape(&self, scene_center: &Point3, scene_radius: Float) -> Shape {
+        let (center, radius, radiance) = match self.kind {
+            LightKind::Point { intensity } => {
+                // A sphere of radiance L looks like a disc of area pi r^2 from anywhere, so its
+                // intensity is L pi r^2
+                let area = PI * POINT_LIGHT_RADIUS * POINT_LIGHT_RADIUS;
+                (self.position, POINT_LIGHT_RADIUS, intensity / area)
+            }
+            LightKind::Directional { irradiance } => {
+                let distance = scene_radius.max(1.0) * DIRECTIONAL_LIGHT_DISTANCE;
+                let half_angle = SUN_ANGLE.to_radians() / 2.0;
+                let solid_angle = TAU * (1.0 - half_angle.cos());
+                (
+                    scene_center - self.direction * distance,
+                    distance * half_angle.sin(),
+                    irradiance / solid_angle,
+                )
+            }
+            LightKind::Spot {
+                intensity,
+                inner,
+                outer,
+            } => {
+                let intensity = self.color * intensity;
+                return SpotLight::new(self.position, self.direction, intensity, inner, outer)
+                    .with_object(ObjectId::register(&self.name))
+                    .into();
+            }
+        };
+        let material: Material = DiffuseLight::new(SolidColor::new(self.color).into())
+            .with_intensity(radiance)
+            .into();
+        Sphere::new(center, radius, Arc::new(material))
+            .with_object(ObjectId::register(&self.name))
+            .into()
+    }
+}
+
+/// Turns `lights` into the shapes that give off their light among `shapes`, see
+/// [`Light::to_shape`]
+pub fn light_shapes(lights: &[Light], shapes: &[Shape]) -> Vec<Shape> {
+    let (center, radius) = if shapes.is_empty() {
+        (Point3::zeros(), 1.0)
+    } else {
+        let bounds = shapes
+            .iter()
+            .fold(Aabb::empty(), |bounds, shape| bounds.join(&shape.aabb()));
+        (bounds.center().coords, bounds.half_size().norm())
+    };
+    lights
+        .iter()
+        .map(|light| light.to_shape(&center, radius))
+        .collect()
+}
+
+/// Makes the camera on the node at `path`, placed by `matrix`. Its width is the preview's and its
+/// height follows the camera's aspect ratio, or the preview's if it has none. Orthographic
+/// cameras aren't supported and come back as `None`.
+fn camera_from_gltf(
+    camera: &gltf::Camera,
+    path: &str,
+    matrix: &Matrix4<Float>,
+    report: &mut LoadReport,
+) -> Option<Camera> {
+    let Projection::Perspective(perspective) = camera.projection() else {
+        report.import_notes.push((
+            path.to_string(),
+            "orthographic camera left out, since only perspective cameras are supported"
+                .to_string(),
+        ));
+        return None;
+    };
+    let aspect_ratio = perspective
+        .aspect_ratio()
+        .map(Float::from)
+        .filter(|ratio| *ratio > 0.0)
+        .unwrap_or(Float::from(WIDTH) / Float::from(HEIGHT));
+    let width = WIDTH as usize;
+    let height = ((width as Float / aspect_ratio).round() as usize).max(1);
+    // Cameras look down their node's -Z with +Y up
+    let center = matrix.transform_point(&nalgebra::Point3::origin()).coords;
+    let forward = (matrix * Vector4::new(0.0, 0.0, -1.0, 0.0))
+        .xyz()
+        .try_normalize(0.0)?;
+    let up = (matrix * Vector4::new(0.0, 1.0, 0.0, 0.0))
+        .xyz()
+        .try_normalize(0.0)?;
+    let far = perspective.zfar().map_or(Float::MAX, Float::from);
+    Some(Camera::new(
+        center,
+        center + forward * FOCUS_DISTANCE,
+        up,
+        FOCUS_DISTANCE,
+        0.0,
+        width,
+        height,
+        SAMPLES_PER_PIXEL,
+        MAX_DEPTH,
+        Float::from(perspective.yfov()).to_degrees(),
+        Float::from(perspective.znear())..far,
+    ))
+}
+
+/// Reads the cameras and punctual lights in a glTF file, placed by the nodes they're on. Cameras
+/// come in the order of their nodes in the file, so the first is the one the file's author would
+/// expect to render from. Cameras that can't be imported as authored are noted in `report`.
+pub fn load_cameras_and_lights(
+    file_path: &str,
+    report: &mut LoadReport,
+) -> Result<(Vec<Camera>, Vec<Light>), String> {
+    let gltf = gltf::Gltf::open(file_path).map_err(|err| err.to_string())?;
+    let mut nodes = gltf_node_transforms(&gltf);
+    nodes.sort_by_key(|(node, ..)| node.index());
+    let mut cameras = Vec::new();
+    let mut lights = Vec::new();
+    for (node, path, matrix) in nodes {
+        if let Some(camera) = node.camera() {
+            cameras.extend(camera_from_gltf(&camera, &path, &matrix, report));
+        }
+        if let Some(light) = node.light() {
+            lights.push(Light::from_gltf(&light, path, &matrix));
+        }
+    }
+    Ok((cameras, lights))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{intersection::Intersection, material::Scatter, scenes, vec3::Vec2};
+    use approx::assert_relative_eq;
+    use std::path::Path;
+
+    /// One camera and a point, a spot and a directional light, as Blender exports them: Y-up,
+    /// with the camera and two of the lights under a "rig" node two units up
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/camera_and_lights.gltf"
+    );
+
+    /// The radiance the glowing sphere `shape` gives off, and its radius
+    fn glow(shape: &Shape) -> (Vec3, Float) {
+        let Shape::Sphere(sphere) = shape else {
+            panic!("the light is not a sphere");
+        };
+        let point = sphere.center() + Vec3::z() * sphere.radius();
+        let hit = Intersection::new(point, Vec3::z(), 1.0, &sphere.material, true, Vec2::zeros());
+        (sphere.material.emitted(&hit), sphere.radius())
+    }
+
+    #[test]
+    fn imported_camera_is_where_it_was_authored() {
+        let (camera, shapes, lights) = scenes::load_gltf_scene(Path::new(FIXTURE)).unwrap();
+        assert!(shapes.is_empty());
+        assert_eq!(lights.len(), 3);
+        let camera = camera.expect("the fixture has a camera");
+        // Ten units back from the rig and three up, which is -y and +z once Z is up
+        assert_relative_eq!(camera.center, Vec3::new(0.0, -10.0, 3.0), epsilon = 1e-6);
+        let forward = (camera.lookat - camera.center).normalize();
+        assert_relative_eq!(forward, Vec3::y(), epsilon = 1e-6);
+        assert_relative_eq!(camera.up, Vec3::z(), epsilon = 1e-6);
+        assert_relative_eq!(camera.vertical_fov, 40.0, epsilon = 1e-4);
+        assert_eq!(camera.image_width, WIDTH as usize);
+        assert_eq!(
+            camera.image_height,
+            (WIDTH as Float / 1.7777778).round() as usize
+        );
+    }
+
+    #[test]
+    fn punctual_lights_convert_to_emitter_units() {
+        let mut report = LoadReport::default();
+        let (_, lights) = load_cameras_and_lights(FIXTURE, &mut report).unwrap();
+        assert!(report.is_empty(), "{}", report);
+        let [lamp, spot, sun] = lights.as_slice() else {
+            panic!("{:?}", lights);
+        };
+
+        // 1366 candela is 2 watts per steradian
+        assert_eq!(lamp.name, "Scene/rig/Lamp");
+        assert_eq!(lamp.kind, LightKind::Point { intensity: 2.0 });
+        assert_relative_eq!(lamp.position, Vec3::new(2.0, 0.0, 2.0), epsilon = 1e-6);
+        let (radiance, radius) = glow(&lamp.to_shape(&Point3::zeros(), 10.0));
+        let intensity = radiance * PI * radius * radius;
+        assert_relative_eq!(intensity, Vec3::new(2.0, 1.0, 0.5), epsilon = 1e-9);
+
+        // 3 lux is 3/683 watts per square meter, from a sun straight overhead
+        let LightKind::Directional { irradiance } = sun.kind else {
+            panic!("{:?}", sun.kind);
+        };
+        assert_relative_eq!(irradiance, 3.0 / LUMENS_PER_WATT, epsilon = 1e-12);
+        assert_relative_eq!(sun.direction, -Vec3::z(), epsilon = 1e-6);
+        let shape = sun.to_shape(&Point3::zeros(), 10.0);
+        let Shape::Sphere(disc) = &shape else {
+            panic!("the sun is not a sphere");
+        };
+        assert!(disc.center().z > 100.0);
+        let (radiance, radius) = glow(&shape);
+        let distance = disc.center().norm();
+        let solid_angle = TAU * (1.0 - (1.0 - (radius / distance).powi(2)).sqrt());
+        assert_relative_eq!(radiance.x * solid_angle, irradiance, max_relative = 1e-9);
+
+        // 683 candela down the rig's -z, which is +y here
+        let shape = spot.to_shape(&Point3::zeros(), 10.0);
+        let Shape::SpotLight(light) = &shape else {
+            panic!("the spot is not a spot light");
+        };
+        assert_relative_eq!(light.intensity(), Vec3::repeat(1.0), epsilon = 1e-9);
+        assert_relative_eq!(light.position(), Vec3::new(-1.0, -4.0, 2.0), epsilon = 1e-6);
+        assert_relative_eq!(light.direction(), Vec3::y(), epsilon = 1e-6);
+        let [cos_inner, cos_outer] = light.cone();
+        assert_relative_eq!(cos_inner, Float::from(0.3f32).cos(), epsilon = 1e-9);
+        assert_relative_eq!(cos_outer, Float::from(0.6f32).cos(), epsilon = 1e-9);
+    }
+}
@@ -0,0 +1,48 @@
+//! A tiny bitmap font for stamping words and numbers over images, like the preview's watermarks
+//! and the seeds on a contact sheet of variations
+
+/// A 3x5 character with a bit per column, most significant on the left, top row first
+pub type Glyph = [u8; 5];
+
+/// The digits 0 to 9
+pub const DIGITS: [Glyph; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// The glyphs of `n` written in decimal
+pub fn number(n: u64) -> Vec<Glyph> {
+    n.to_string()
+        .bytes()
+        .map(|digit| DIGITS[(digit - b'0') as usize])
+        .collect()
+}
+
+/// Stamps a word of `glyphs` `scale` pixels per dot by calling `dot` with every pixel it covers,
+/// relative to its top left corner, first with `false` for a shadow half a dot down and to the
+/// right, then with `true` for the glyphs themselves, so they read on any background
+pub fn stamp(glyphs: &[Glyph], scale: usize, mut dot: impl FnMut(usize, usize, bool)) {
+    for (shadow, lit) in [(scale / 2, false), (0, true)] {
+        for (letter, glyph) in glyphs.iter().enumerate() {
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in (0..3).filter(|column| bits & (0b100 >> column) != 0) {
+                    let left = shadow + (letter * 4 + column) * scale;
+                    let top = shadow + row * scale;
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            dot(left + dx, top + dy, lit);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,733 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    hittable::{Hit, Shape, Triangle, World},
+    intersection::Intersection,
+    material::Material,
+    vec3::{Ray, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+    bvh::Bvh,
+};
+use indicatif::ProgressBar;
+use itertools::Itertools;
+use rayon::prelude::*;
+use std::{
+    collections::HashSet,
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Most camera rays sent to the GPU at once, which bounds the memory rays and hits take
+const MAX_BATCH_RAYS: usize = 1 << 18;
+/// Marks leaf nodes in [`GpuScene::nodes`], and rays that hit nothing
+pub const NONE: u32 = u32::MAX;
+/// What a primitive in [`GpuScene::primitives`] is, in the `w` of its first `vec4`
+pub const KIND_TRIANGLE: u32 = 0;
+pub const KIND_SPHERE: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpuError {
+    /// Built without the `gpu` feature
+    NotCompiled,
+    /// The scene has shapes the intersection kernel can't handle
+    Unsupported(&'static str),
+    /// There's no GPU to run on
+    NoAdapter,
+    /// The GPU failed to set up or run the kernel
+    Device(String),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::NotCompiled => write!(
+                f,
+                "finding first hits on the GPU needs the `gpu` feature (cargo build --features gpu)"
+            ),
+            GpuError::Unsupported(shape) => write!(
+                f,
+                "the GPU can only intersect triangles and spheres, and the scene has {}",
+                shape
+            ),
+            GpuError::NoAdapter => write!(f, "no GPU was found"),
+            GpuError::Device(message) => write!(f, "the GPU failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+/// A primitive the GPU intersects, while its `BVH` is built
+struct Primitive {
+    bounds: Aabb<Float, 3>,
+    node_index: usize,
+}
+
+impl Bounded<Float, 3> for Primitive {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        self.bounds
+    }
+}
+
+impl BHShape<Float, 3> for Primitive {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// The triangles and spheres of a world flattened into the buffers the intersection kernel in
+/// `gpu.wgsl` reads, in 32-bit floats stored as their bits
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpuScene {
+    /// A `BVH` laid out to be walked without a stack, two `vec4`s per node. Branches hold the
+    /// min of their bounds and the node to go to if a ray hits them, then the max and the node
+    /// to go to if it misses. Leaves hold their primitive in place of the min's x and [`NONE`]
+    /// in place of the entry, then the exit in the same place as a branch's.
+    pub nodes: Vec<[u32; 4]>,
+    /// Three `vec4`s per primitive. Triangles hold their corners a, b and c in the `xyz` of
+    /// each, and 1 in the second's `w` if they're double sided. Spheres hold their center in
+    /// the first and their radius in the second's `x`. The first's `w` is the kind.
+    pub primitives: Vec<[u32; 4]>,
+    /// Index of the world shape each primitive came from
+    pub shapes: Vec<usize>,
+}
+
+/// Rounds `value` to the 32-bit float on its side of `toward`'s sign, so bounds only ever grow
+fn round_out(value: Float, toward: Float) -> f32 {
+    let rounded = value as f32;
+    if toward < 0.0 && rounded as Float > value {
+        rounded.next_down()
+    } else if toward > 0.0 && (rounded as Float) < value {
+        rounded.next_up()
+    } else {
+        rounded
+    }
+}
+
+fn vec4(xyz: Vec3, w: u32) -> [u32; 4] {
+    [
+        (xyz.x as f32).to_bits(),
+        (xyz.y as f32).to_bits(),
+        (xyz.z as f32).to_bits(),
+        w,
+    ]
+}
+
+impl GpuScene {
+    /// Flattens the triangles, meshes and spheres of `world`. The fragments spatial splits cut
+    /// triangles into are merged back into their triangles. Fails on any other kind of shape,
+    /// since the kernel only intersects these two.
+    pub fn flatten(world: &World) -> Result<Self, GpuError> {
+        let mut scene = GpuScene::default();
+        let mut primitives = Vec::new();
+        // Fragments of the same triangle all point to it
+        let mut fragmented = HashSet::new();
+        for (index, shape) in world.shapes.iter().enumerate() {
+            let triangle = |triangle: &Triangle| {
+                let words = [
+                    vec4(triangle.a, KIND_TRIANGLE),
+                    vec4(triangle.b, triangle.is_double_sided() as u32),
+                    vec4(triangle.c, 0),
+                ];
+                (triangle.aabb(), words)
+            };
+            // Meshes go in face by face, all pointing to the mesh
+            if let Shape::Mesh(mesh) = shape {
+                for face in mesh.triangles() {
+                    let (bounds, words) = triangle(&face);
+                    scene.primitives.extend(words);
+                    scene.shapes.push(index);
+                    primitives.push(Primitive {
+                        bounds,
+                        node_index: 0,
+                    });
+                }
+                continue;
+            }
+            let (bounds, words) = match shape {
+                Shape::Triangle(shape) => triangle(shape),
+                Shape::Mesh(_) => unreachable!("meshes are flattened above"),
+                Shape::TriangleFragment(fragment) => {
+                    if !fragmented.insert(fragment.triangle() as *const Triangle) {
+                        continue;
+                    }
+                    triangle(fragment.triangle())
+                }
+                Shape::Sphere(sphere) => {
+                    let words = [
+                        vec4(sphere.center(), KIND_SPHERE),
+                        [(sphere.radius() as f32).to_bits(), 0, 0, 0],
+                        [0; 4],
+                    ];
+                    (sphere.aabb(), words)
+                }
+                Shape::AaBox(_) => return Err(GpuError::Unsupported("boxes")),
+                Shape::RoundedBox(_) => return Err(GpuError::Unsupported("rounded boxes")),
+                Shape::Instance(_) => return Err(GpuError::Unsupported("instances")),
+                Shape::HeterogeneousMedium(_) => return Err(GpuError::Unsupported("media")),
+                Shape::RectLight(_) => return Err(GpuError::Unsupported("rect lights")),
+                Shape::SpotLight(_) => return Err(GpuError::Unsupported("spot lights")),
+            };
+            scene.primitives.extend(words);
+            scene.shapes.push(index);
+            primitives.push(Primitive {
+                bounds,
+                node_index: 0,
+            });
+        }
+        if primitives.is_empty() {
+            return Ok(scene);
+        }
+
+        let bvh = Bvh::build(&mut primitives);
+        scene.nodes = bvh
+            .flatten()
+            .iter()
+            .flat_map(|node| {
+                if node.entry_index == NONE {
+                    return [[node.shape_index, 0, 0, NONE], [0, 0, 0, node.exit_index]];
+                }
+                let (min, max) = (node.aabb.min, node.aabb.max);
+                let low = |axis: usize| round_out(min[axis], -1.0).to_bits();
+                let high = |axis: usize| round_out(max[axis], 1.0).to_bits();
+                [
+                    [low(0), low(1), low(2), node.entry_index],
+                    [high(0), high(1), high(2), node.exit_index],
+                ]
+            })
+            .collect();
+        Ok(scene)
+    }
+
+    /// Lays rays out the way the kernel reads them, two `vec4`s each
+    pub fn pack_rays(rays: &[Ray]) -> Vec<[f32; 4]> {
+        rays.iter()
+            .flat_map(|ray| {
+                let (origin, direction) = (ray.origin, ray.direction);
+                [
+                    [origin.x as f32, origin.y as f32, origin.z as f32, 0.0],
+                    [
+                        direction.x as f32,
+                        direction.y as f32,
+                        direction.z as f32,
+                        0.0,
+                    ],
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Finds the first hits of camera rays on the GPU, then hands them to the CPU to shade.
+/// Only the first hits move, so materials and textures don't need porting to shaders. The GPU
+/// picks the nearest primitive in 32-bit floats, and the CPU intersects that one primitive
+/// again in full precision for the hit it shades, so renders match ones done all on the CPU.
+pub struct GpuPrimary {
+    scene: GpuScene,
+    backend: backend::Backend,
+    /// Set once a batch fails, after which everything is rendered on the CPU
+    failed: AtomicBool,
+}
+
+impl GpuPrimary {
+    /// Flattens `world` and uploads it to a GPU. Fails if there's no GPU, the scene has shapes
+    /// the kernel can't intersect, or the build doesn't have the `gpu` feature, in which case
+    /// rendering should stay on the CPU.
+    pub fn new(world: &World) -> Result<Self, GpuError> {
+        let scene = GpuScene::flatten(world)?;
+        let backend = backend::Backend::new(&scene)?;
+        Ok(GpuPrimary {
+            scene,
+            backend,
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Sets up the GPU for `world` if `wanted`, or explains why it's staying on the CPU
+    pub fn try_new(world: &World, wanted: bool) -> Option<Self> {
+        if !wanted {
+            return None;
+        }
+        match GpuPrimary::new(world) {
+            Ok(gpu) => {
+                println!(
+                    "Finding camera rays' first hits on {}, over {} primitives",
+                    gpu.adapter_name(),
+                    gpu.scene.shapes.len()
+                );
+                Some(gpu)
+            }
+            Err(err) => {
+                println!("Warning: rendering on the CPU, since {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn adapter_name(&self) -> &str {
+        self.backend.name()
+    }
+
+    /// Returns the primitive each ray hits first within `range`, or [`NONE`]
+    pub fn first_hits(&self, rays: &[Ray], range: &Range<Float>) -> Result<Vec<u32>, GpuError> {
+        let mut primitives = Vec::with_capacity(rays.len());
+        for batch in rays.chunks(MAX_BATCH_RAYS) {
+            let hits = self.backend.first_hits(
+                &GpuScene::pack_rays(batch),
+                range.start as f32,
+                range.end as f32,
+            )?;
+            primitives.extend(hits.iter().map(|hit| hit[0]));
+        }
+        Ok(primitives)
+    }
+
+    /// Turns the primitive the GPU found for `ray` into the hit the CPU would have found, by
+    /// intersecting that primitive again at full precision. Falls back to the whole world when
+    /// the two disagree, and for alpha-masked surfaces, whose hits the world may pass through.
+    fn resolve<'w>(
+        &self,
+        world: &'w World,
+        ray: &Ray,
+        range: &Range<Float>,
+        primitive: u32,
+    ) -> Option<Intersection<'w>> {
+        if primitive == NONE {
+            return None;
+        }
+        let hit = match &world.shapes[self.scene.shapes[primitive as usize]] {
+            // Fragments only report hits inside themselves, which the GPU doesn't know about
+            Shape::TriangleFragment(fragment) => fragment.triangle().hit(ray, range),
+            shape => shape.hit(ray, range),
+        };
+        match hit {
+            Some(hit) if !matches!(hit.material, Material::AlphaMask(_)) => Some(hit.bumped()),
+            _ => world.hit(ray, range),
+        }
+    }
+
+    /// Renders `num_samples` samples of each of `pixels`, returning their average colors and
+    /// how many of their camera rays escaped like [`Camera::render_pixel_escapes`]. Pixels are
+    /// rendered on the CPU alone if the GPU fails.
+    pub fn render_pixels(
+        &self,
+        camera: &Camera,
+        world: &World,
+        pixels: &[(usize, usize)],
+        num_samples: usize,
+    ) -> Vec<(Vec3, usize)> {
+        let range = world.numeric.min_hit_distance..camera.t_range().end;
+        let per_batch = (MAX_BATCH_RAYS / num_samples.max(1)).max(1);
+        let mut colors = Vec::with_capacity(pixels.len());
+        for batch in pixels.chunks(per_batch) {
+            let rays: Vec<Ray> = batch
+                .par_iter()
+                .flat_map_iter(|&(x, y)| (0..num_samples).map(move |i| camera.primary_ray(x, y, i)))
+                .collect();
+            let hits = if self.failed.load(Ordering::Relaxed) {
+                None
+            } else {
+                self.first_hits(&rays, &range)
+                    .inspect_err(|err| {
+                        println!("Warning: rendering the rest on the CPU, since {}", err);
+                        self.failed.store(true, Ordering::Relaxed);
+                    })
+                    .ok()
+            };
+            let Some(hits) = hits else {
+                colors.par_extend(
+                    batch
+                        .par_iter()
+                        .map(|&(x, y)| camera.render_pixel_escapes(world, x, y, num_samples)),
+                );
+                continue;
+            };
+            colors.par_extend(batch.par_iter().enumerate().map(|(j, &(x, y))| {
+                let samples = j * num_samples..(j + 1) * num_samples;
+                let (rays, hits) = (&rays[samples.clone()], &hits[samples]);
+                camera.render_pixel_from_primary(world, x, y, rays, |i| {
+                    self.resolve(world, &rays[i], &range, hits[i])
+                })
+            }));
+        }
+        colors
+    }
+
+    /// Like [`Camera::render_image`], with the camera rays' first hits found on the GPU
+    pub fn render_image(&self, camera: &Camera, world: &World) -> Image {
+        let pixels = (0..camera.image_height)
+            .cartesian_product(0..camera.image_width)
+            .map(|(y, x)| (x, y))
+            .collect_vec();
+        let rows_per_batch = (MAX_BATCH_RAYS / camera.samples_per_pixel().max(1))
+            .div_ceil(camera.image_width)
+            .max(1);
+        let progress = ProgressBar::new(pixels.len() as u64);
+        let mut colors = Vec::with_capacity(pixels.len());
+        for batch in pixels.chunks(rows_per_batch * camera.image_width) {
+            let rendered = self.render_pixels(camera, world, batch, camera.samples_per_pixel());
+            colors.extend(rendered.into_iter().map(|(color, _)| color));
+            progress.inc(batch.len() as u64);
+        }
+        progress.finish();
+        camera.image_from_pixels(colors)
+    }
+
+    /// Times finding the first hits of one camera ray per pixel of `camera` on the GPU and on
+    /// the CPU, which is the part of rendering the GPU takes over
+    pub fn benchmark(
+        &self,
+        camera: &Camera,
+        world: &World,
+    ) -> Result<(Duration, Duration), GpuError> {
+        let range = world.numeric.min_hit_distance..camera.t_range().end;
+        let rays: Vec<Ray> = (0..camera.image_height)
+            .cartesian_product(0..camera.image_width)
+            .map(|(y, x)| camera.primary_ray(x, y, 0))
+            .collect();
+        // The first batch pays for compiling the kernel
+        self.first_hits(&rays[..rays.len().min(64)], &range)?;
+        let gpu_start = Instant::now();
+        self.first_hits(&rays, &range)?;
+        let gpu = gpu_start.elapsed();
+        let cpu_start = Instant::now();
+        let escaped = rays
+            .par_iter()
+            .filter(|ray| world.hit(ray, &range).is_none())
+            .count();
+        let cpu = cpu_start.elapsed();
+        std::hint::black_box(escaped);
+        Ok((gpu, cpu))
+    }
+}
+
+#[cfg(feature = "gpu")]
+mod backend {
+    use super::{GpuError, GpuScene};
+    use wgpu::util::DeviceExt;
+
+    /// Threads per workgroup, which has to match `@workgroup_size` in the kernel
+    const WORKGROUP_SIZE: u32 = 64;
+
+    /// A GPU with the scene uploaded and the kernel compiled
+    pub struct Backend {
+        name: String,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        pipeline: wgpu::ComputePipeline,
+        nodes: wgpu::Buffer,
+        primitives: wgpu::Buffer,
+        node_count: u32,
+    }
+
+    /// Buffers can't be empty, so an empty scene gets one unused element
+    fn non_empty(data: &[[u32; 4]]) -> &[[u32; 4]] {
+        if data.is_empty() {
+            &[[0; 4]]
+        } else {
+            data
+        }
+    }
+
+    impl Backend {
+        pub fn new(scene: &GpuScene) -> Result<Self, GpuError> {
+            pollster::block_on(Backend::connect(scene))
+        }
+
+        async fn connect(scene: &GpuScene) -> Result<Self, GpuError> {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter: false,
+                })
+                .await
+                .ok_or(GpuError::NoAdapter)?;
+            let (device, queue) = adapter
+                .request_device(
+                    &wgpu::DeviceDescriptor {
+                        label: Some("rt_gpu_primary"),
+                        features: wgpu::Features::empty(),
+                        limits: adapter.limits(),
+                    },
+                    None,
+                )
+                .await
+                .map_err(|err| GpuError::Device(err.to_string()))?;
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("first_hits"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("first_hits"),
+                layout: None,
+                module: &module,
+                entry_point: "main",
+            });
+            let storage = |label, data: &[[u32; 4]]| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents: bytemuck::cast_slice(non_empty(data)),
+                    usage: wgpu::BufferUsages::STORAGE,
+                })
+            };
+            let nodes = storage("nodes", &scene.nodes);
+            let primitives = storage("primitives", &scene.primitives);
+            if let Some(err) = device.pop_error_scope().await {
+                return Err(GpuError::Device(err.to_string()));
+            }
+            Ok(Backend {
+                name: adapter.get_info().name,
+                device,
+                queue,
+                pipeline,
+                nodes,
+                primitives,
+                node_count: (scene.nodes.len() / 2) as u32,
+            })
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        /// Runs the kernel over `rays`, laid out by [`GpuScene::pack_rays`], and waits for
+        /// the hit of each
+        pub fn first_hits(
+            &self,
+            rays: &[[f32; 4]],
+            t_min: f32,
+            t_max: f32,
+        ) -> Result<Vec<[u32; 4]>, GpuError> {
+            let ray_count = rays.len() / 2;
+            if ray_count == 0 {
+                return Ok(Vec::new());
+            }
+            let device = &self.device;
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let params = [
+                self.node_count,
+                ray_count as u32,
+                t_min.to_bits(),
+                t_max.to_bits(),
+            ];
+            let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: bytemuck::cast_slice(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let rays = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("rays"),
+                contents: bytemuck::cast_slice(rays),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+            let hits_size = (ray_count * std::mem::size_of::<[u32; 4]>()) as u64;
+            let hits = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("hits"),
+                size: hits_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let readback = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("readback"),
+                size: hits_size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("first_hits"),
+                layout: &self.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    (0, &params),
+                    (1, &self.nodes),
+                    (2, &self.primitives),
+                    (3, &rays),
+                    (4, &hits),
+                ]
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding,
+                    resource: buffer.as_entire_binding(),
+                }),
+            });
+
+            let mut encoder = device.create_command_encoder(&Default::default());
+            {
+                let mut pass = encoder.begin_compute_pass(&Default::default());
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups((ray_count as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+            }
+            encoder.copy_buffer_to_buffer(&hits, 0, &readback, 0, hits_size);
+            self.queue.submit(Some(encoder.finish()));
+
+            let slice = readback.slice(..);
+            let (sender, receiver) = std::sync::mpsc::channel();
+            slice.map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            device.poll(wgpu::Maintain::Wait);
+            if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+                return Err(GpuError::Device(err.to_string()));
+            }
+            receiver
+                .recv()
+                .map_err(|err| GpuError::Device(err.to_string()))?
+                .map_err(|err| GpuError::Device(err.to_string()))?;
+            let hits = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+            readback.unmap();
+            Ok(hits)
+        }
+    }
+}
+
+#[cfg(not(feature = "gpu"))]
+mod backend {
+    use super::{GpuError, GpuScene};
+
+    pub struct Backend;
+
+    impl Backend {
+        pub fn new(_scene: &GpuScene) -> Result<Self, GpuError> {
+            Err(GpuError::NotCompiled)
+        }
+
+        pub fn name(&self) -> &str {
+            "nothing"
+        }
+
+        pub fn first_hits(
+            &self,
+            _rays: &[[f32; 4]],
+            _t_min: f32,
+            _t_max: f32,
+        ) -> Result<Vec<[u32; 4]>, GpuError> {
+            Err(GpuError::NotCompiled)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boxes::AaBox,
+        hittable::Sphere,
+        material::{Lambertian, Metal},
+        scenes,
+    };
+    use std::sync::Arc;
+
+    /// A matte ball and a mirror ball on a triangle floor, all shapes the kernel intersects
+    fn balls() -> World {
+        let floor = Arc::new(Lambertian::new_rgb_solid(0.6, 0.6, 0.5).into());
+        let mut shapes = scenes::generate_ground_plane(10.0, 10.0, 0.0, floor, true);
+        let matte = Arc::new(Lambertian::new_rgb_solid(0.8, 0.3, 0.2).into());
+        let mirror = Arc::new(Metal::new_solid(Vec3::repeat(0.9), None).into());
+        shapes.push(Sphere::new(Vec3::new(-0.6, 0.0, 0.5), 0.5, matte).into());
+        shapes.push(Sphere::new(Vec3::new(0.6, 0.3, 0.5), 0.5, mirror).into());
+        World::build(shapes)
+    }
+
+    #[cfg(feature = "gpu")]
+    fn camera() -> Camera {
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -5.0, 1.5))
+            .with_look_at(Vec3::new(0.0, 0.0, 0.5))
+            .with_vertical_fov(30.0)
+            .with_resolution(48, 32)
+            .with_samples(4)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(11);
+        camera
+    }
+
+    #[test]
+    fn flattening_packs_triangles_and_spheres() {
+        let world = balls();
+        let scene = GpuScene::flatten(&world).unwrap();
+        let count = world.shapes.len();
+        assert_eq!(scene.shapes, (0..count).collect_vec());
+        assert_eq!(scene.primitives.len(), 3 * count);
+        let kinds = scene.primitives.iter().step_by(3).map(|words| words[3]);
+        assert_eq!(
+            kinds.filter(|&kind| kind == KIND_SPHERE).count(),
+            2,
+            "two balls"
+        );
+        // Every primitive is in exactly one leaf
+        let mut leaves: Vec<u32> = scene
+            .nodes
+            .chunks(2)
+            .filter(|node| node[0][3] == NONE)
+            .map(|node| node[0][0])
+            .collect();
+        leaves.sort();
+        assert_eq!(leaves, (0..count as u32).collect_vec());
+    }
+
+    #[test]
+    fn flattening_refuses_shapes_the_kernel_cannot_intersect() {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let world = World::build(vec![
+            AaBox::new(Vec3::zeros(), Vec3::repeat(1.0), gray).into()
+        ]);
+        assert_eq!(
+            GpuScene::flatten(&world),
+            Err(GpuError::Unsupported("boxes"))
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu"))]
+    fn builds_without_the_feature_stay_on_the_cpu() {
+        assert!(matches!(
+            GpuPrimary::new(&balls()),
+            Err(GpuError::NotCompiled)
+        ));
+        assert!(GpuPrimary::try_new(&balls(), true).is_none());
+    }
+
+    /// Needs a GPU, or a software adapter like lavapipe, to run on
+    #[test]
+    #[cfg(feature = "gpu")]
+    fn gpu_renders_match_cpu_ones() {
+        let (camera, world) = (camera(), balls());
+        let gpu = GpuPrimary::new(&world).expect("a GPU to run on");
+
+        // The GPU finds the same first hits as the CPU, apart from grazing ones 32-bit floats
+        // can't resolve, which the CPU corrects for when it intersects again
+        let range = world.numeric.min_hit_distance..camera.t_range().end;
+        let rays: Vec<Ray> = (0..camera.image_height)
+            .cartesian_product(0..camera.image_width)
+            .map(|(y, x)| camera.primary_ray(x, y, 0))
+            .collect();
+        let hits = gpu.first_hits(&rays, &range).unwrap();
+        let agreeing = rays
+            .iter()
+            .zip(&hits)
+            .filter(|(ray, &primitive)| {
+                let cpu = world.hit(ray, &range).map(|hit| hit.t);
+                let gpu = gpu.resolve(&world, ray, &range, primitive).map(|hit| hit.t);
+                cpu == gpu
+            })
+            .count();
+        assert_eq!(agreeing, rays.len());
+
+        let on_gpu = gpu.render_image(&camera, &world);
+        let on_cpu = camera.render_image(&world);
+        assert!(!gpu.failed.load(Ordering::Relaxed), "fell back to the CPU");
+        assert_eq!(on_gpu.pixels, on_cpu.pixels);
+    }
+}
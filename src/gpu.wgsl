@@ -0,0 +1,140 @@
+// Finds the first primitive each camera ray hits, by walking a flattened BVH without a stack.
+// Laid out by `GpuScene` in gpu.rs, which documents the buffers.
+
+struct Params {
+    node_count: u32,
+    ray_count: u32,
+    t_min: f32,
+    t_max: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Two per node: the bounds' min and the entry index, then the max and the exit index
+@group(0) @binding(1) var<storage, read> nodes: array<vec4<u32>>;
+// Three per primitive
+@group(0) @binding(2) var<storage, read> primitives: array<vec4<u32>>;
+// Two per ray: the origin, then the direction
+@group(0) @binding(3) var<storage, read> rays: array<vec4<f32>>;
+// One per ray: the primitive, the distance to it and where on it the ray hit
+@group(0) @binding(4) var<storage, read_write> hits: array<vec4<u32>>;
+
+const NONE: u32 = 0xffffffffu;
+const KIND_SPHERE: u32 = 1u;
+
+fn miss() -> vec3<f32> {
+    return vec3<f32>(-1.0, 0.0, 0.0);
+}
+
+// Returns the distance to the hit and its barycentrics, or a negative distance for a miss.
+// Only hits the front unless it's double sided, like `Triangle::hit`.
+fn hit_triangle(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, double_sided: bool, origin: vec3<f32>, direction: vec3<f32>, t_min: f32, t_max: f32) -> vec3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let p = cross(direction, ac);
+    let det = dot(ab, p);
+    if det == 0.0 || (det < 0.0 && !double_sided) {
+        return miss();
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = dot(s, p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return miss();
+    }
+    let q = cross(s, ab);
+    let v = dot(direction, q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return miss();
+    }
+    let t = dot(ac, q) * inv_det;
+    if t < t_min || t >= t_max {
+        return miss();
+    }
+    return vec3<f32>(t, u, v);
+}
+
+// Returns the distance to the nearest hit in range, or a negative distance for a miss
+fn hit_sphere(center: vec3<f32>, radius: f32, origin: vec3<f32>, direction: vec3<f32>, t_min: f32, t_max: f32) -> vec3<f32> {
+    let oc = center - origin;
+    let a = dot(direction, direction);
+    let h = dot(direction, oc);
+    let c = dot(oc, oc) - radius * radius;
+    let discriminant = h * h - a * c;
+    if discriminant < 0.0 {
+        return miss();
+    }
+    let root = sqrt(discriminant);
+    var t = (h - root) / a;
+    if t < t_min || t >= t_max {
+        t = (h + root) / a;
+        if t < t_min || t >= t_max {
+            return miss();
+        }
+    }
+    return vec3<f32>(t, 0.0, 0.0);
+}
+
+fn hit_primitive(primitive: u32, origin: vec3<f32>, direction: vec3<f32>, t_min: f32, t_max: f32) -> vec3<f32> {
+    let first = primitives[3u * primitive];
+    let second = primitives[3u * primitive + 1u];
+    let third = primitives[3u * primitive + 2u];
+    if first.w == KIND_SPHERE {
+        return hit_sphere(bitcast<vec3<f32>>(first.xyz), bitcast<f32>(second.x), origin, direction, t_min, t_max);
+    }
+    return hit_triangle(
+        bitcast<vec3<f32>>(first.xyz),
+        bitcast<vec3<f32>>(second.xyz),
+        bitcast<vec3<f32>>(third.xyz),
+        second.w != 0u,
+        origin,
+        direction,
+        t_min,
+        t_max,
+    );
+}
+
+fn hits_bounds(low: vec3<f32>, high: vec3<f32>, origin: vec3<f32>, inv_direction: vec3<f32>, t_min: f32, t_max: f32) -> bool {
+    let t0 = (low - origin) * inv_direction;
+    let t1 = (high - origin) * inv_direction;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    let enter = max(max(near.x, near.y), max(near.z, t_min));
+    let exit = min(min(far.x, far.y), min(far.z, t_max));
+    return enter <= exit;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let ray = id.x;
+    if ray >= params.ray_count {
+        return;
+    }
+    let origin = rays[2u * ray].xyz;
+    let direction = rays[2u * ray + 1u].xyz;
+    let inv_direction = 1.0 / direction;
+
+    var nearest = vec4<u32>(NONE, 0u, 0u, 0u);
+    var t_max = params.t_max;
+    var index = 0u;
+    loop {
+        if index >= params.node_count {
+            break;
+        }
+        let low = nodes[2u * index];
+        let high = nodes[2u * index + 1u];
+        if low.w == NONE {
+            // A leaf, with its primitive where a branch has the min's x
+            let hit = hit_primitive(low.x, origin, direction, params.t_min, t_max);
+            if hit.x >= 0.0 {
+                t_max = hit.x;
+                nearest = vec4<u32>(low.x, bitcast<vec3<u32>>(hit));
+            }
+            index = high.w;
+        } else if hits_bounds(bitcast<vec3<f32>>(low.xyz), bitcast<vec3<f32>>(high.xyz), origin, inv_direction, params.t_min, t_max) {
+            index = low.w;
+        } else {
+            index = high.w;
+        }
+    }
+    hits[ray] = nearest;
+}
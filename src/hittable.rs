@@ -1,22 +1,51 @@
 use crate::{
-    camera::Float,
+    accel::{AccelError, BinaryTraverse, BvhLayout, CompressedBvh, Traverse},
+    animation::Animation,
+    boxes::{AaBox, RoundedBox},
+    camera::{Float, Image},
+    environment::EnvironmentMap,
+    instance::{Instance, Prototype},
     intersection::Intersection,
-    material::Material,
+    lights::{AreaLights, LightSelection, SceneLights},
+    material::{Material, Scatter},
+    medium::HeterogeneousMedium,
+    mesh::Mesh,
+    mesh_analysis::{analyze_mesh, thin_glass, SELF_INTERSECTION_SAMPLES},
+    numeric::{NumericContext, DEGENERATE_TRIANGLE_RATIO},
+    object::ObjectId,
+    rng::HitRng,
+    scene_lights::{RectLight, SpotLight},
+    sky_harmonics::SkyHarmonics,
+    sky_importance::{luminance, SkyImportance, SkySample},
+    spatial_split::{self, TriangleFragment},
+    texture::{ImageTexture, LoadReport, TextureLoadFailure},
+    texture_cache::{ContentHash, TextureCache},
+    tonemap::Tonemap,
+    uv::{to_unit_spherical, UvMode},
     vec3::{Point3, Ray, RayExt, Vec2, Vec3, Vec3Ext},
 };
 use bvh::{
     aabb::{Aabb, Bounded},
     bounding_hierarchy::{BHShape, BoundingHierarchy},
-    bvh::Bvh,
+    bvh::{Bvh, BvhNode},
 };
 use enum_dispatch::enum_dispatch;
 use hw_skymodel::rgb::{Channel, SkyParams, SkyState};
-use nalgebra::Matrix4;
-use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use nalgebra::{Matrix3, Matrix4, Quaternion, Similarity3, Translation3, UnitQuaternion, Vector3};
+use rand::Rng;
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+use serde::Deserialize;
 use std::{
-    f64::consts::{PI, TAU},
+    collections::{BTreeSet, HashMap},
+    f64::consts::{FRAC_PI_2, PI, TAU},
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
     ops::Range,
-    sync::Arc,
+    path::Path,
+    sync::{Arc, OnceLock},
 };
 use tobj::GPU_LOAD_OPTIONS;
 
@@ -24,84 +53,661 @@ use tobj::GPU_LOAD_OPTIONS;
 pub struct World {
     pub shapes: Vec<Shape>,
     pub bvh: Bvh<Float, 3>,
+    /// Traversed instead of `bvh` when the world uses [`BvhLayout::Compressed`]
+    accel: Option<CompressedBvh>,
     sky: SkyState,
+    /// What `sky` was made from
+    sky_params: SkyParams,
+    /// Unit vector toward the sun
     sun_direction: Vec3,
+    /// Display transform applied to sky radiance
+    pub tonemap: Tonemap,
+    /// How rays get past alpha-masked surfaces they didn't hit
+    pub transparency: TransparencyMode,
+    /// What rays that escape the scene see
+    pub background: Background,
+    /// Drawn into the sky toward `sun_direction`. Ignored by other backgrounds.
+    pub sun_disc: Option<SunDisc>,
+    /// Tolerances for ray offsets and hit distances, derived from the size of the scene
+    pub numeric: NumericContext,
+    /// Built from the sky the first time it's sampled. Changing the background or tonemap after
+    /// that only makes sky sampling noisier, since sampled directions are still shaded exactly.
+    sky_importance: OnceLock<SkyImportance>,
+    /// Projected from the sky the first time it's needed, with the same caveat as
+    /// `sky_importance`, except that stale harmonics make draft shading wrong rather than noisy
+    sky_harmonics: OnceLock<SkyHarmonics>,
+    /// Found among the shapes the first time lights are sampled
+    area_lights: OnceLock<AreaLights>,
+    /// Found among the shapes the first time they're sampled
+    scene_lights: OnceLock<SceneLights>,
+    /// How diffuse hits pick which spot or rect light to sample, and light paths which area
+    /// light to start from. Changing it after the lights are first sampled does nothing.
+    pub light_selection: LightSelection,
+    /// Shapes `build` left out for failing [`Shape::validate`]. Only checked in debug builds.
+    pub rejected: RejectReport,
+    /// Keyframes the scene came with, e.g. from its [`crate::scene_file::SceneFile`], which
+    /// render jobs play along with their own
+    pub animation: Animation,
+}
+
+/// The light coming from everywhere a ray can escape to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Background {
+    /// Physically based daylight sky lit by the sun
+    #[default]
+    Sky,
+    /// Blends from `bottom` to `top` by the ray's height along `up`, like the background in
+    /// Ray Tracing in One Weekend
+    Gradient { up: Vec3, bottom: Vec3, top: Vec3 },
+    /// A panorama of real surroundings, usually an HDR photo, with no sun of its own since the
+    /// photo has it already
+    Environment(EnvironmentMap),
+}
+
+impl Background {
+    /// The white-to-blue background from Ray Tracing in One Weekend, which is Y-up
+    pub fn rtiow() -> Self {
+        Background::Gradient {
+            up: Vec3::y(),
+            bottom: Vec3::new(1.0, 1.0, 1.0),
+            top: Vec3::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+/// How wide the sun looks from the ground, in degrees
+pub const SUN_ANGLE: Float = 0.53;
+
+/// The sun itself, which the sky model leaves out: it only has the glow around the sun. Without
+/// a disc, daylight scenes are lit by the sky alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunDisc {
+    /// Angular diameter in degrees
+    pub angle: Float,
+    /// Light the disc gives a surface facing it, on top of the sky's. The default sky gives
+    /// open ground about 3.
+    pub irradiance: Float,
+}
+
+impl Default for SunDisc {
+    /// About four times the sky's light, as on a clear day
+    fn default() -> Self {
+        SunDisc {
+            angle: SUN_ANGLE,
+            irradiance: 12.0,
+        }
+    }
+}
+
+impl SunDisc {
+    /// Cosine of the angle between the middle of the disc and its edge
+    fn cos_radius(&self) -> Float {
+        (self.angle.to_radians() / 2.0).cos()
+    }
+
+    /// Solid angle the disc covers, in steradians
+    fn solid_angle(&self) -> Float {
+        TAU * (1.0 - self.cos_radius())
+    }
+}
+
+/// Why a world's sky couldn't be made
+#[derive(Debug, PartialEq)]
+pub enum SkyError {
+    /// The sun direction was zero or not finite, so it doesn't point anywhere
+    NoSunDirection(Vec3),
+    /// Settings outside what the Hosek-Wilkie model was fitted to
+    Model(hw_skymodel::rgb::Error),
+}
+
+impl fmt::Display for SkyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkyError::NoSunDirection(direction) => write!(
+                f,
+                "sun direction ({}, {}, {}) doesn't point anywhere",
+                direction.x, direction.y, direction.z
+            ),
+            SkyError::Model(err) => write!(f, "invalid sky: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SkyError {}
+
+/// Settings for the daylight sky, built up with the `with_` methods and made into what
+/// [`World::build_with_sky`] takes by [`SkySettings::params`]. The model's solar elevation
+/// always follows the sun direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySettings {
+    /// Haziness, from 1 for a perfectly clear sky to 10
+    pub turbidity: Float,
+    /// Reflectance of the ground lighting the sky from below, per channel between 0 and 1
+    pub albedo: Vec3,
+    /// Toward the sun, which doesn't have to be normalized but can't be zero
+    pub sun_direction: Vec3,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        SkySettings {
+            turbidity: 1.0,
+            albedo: Vec3::new(1.0, 1.0, 1.0),
+            sun_direction: Vec3::z(),
+        }
+    }
+}
+
+impl SkySettings {
+    pub fn with_turbidity(mut self, turbidity: Float) -> Self {
+        self.turbidity = turbidity;
+        self
+    }
+
+    pub fn with_albedo(mut self, albedo: Vec3) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    pub fn with_sun_direction(mut self, sun_direction: Vec3) -> Self {
+        self.sun_direction = sun_direction;
+        self
+    }
+
+    /// Puts the sun `degrees` above the horizon, keeping the direction it's in seen from above.
+    /// A sun straight overhead is lowered toward +X.
+    pub fn with_sun_elevation(mut self, degrees: Float) -> Self {
+        let horizontal = Vec3::new(self.sun_direction.x, self.sun_direction.y, 0.0);
+        let heading = horizontal
+            .try_normalize(Float::EPSILON)
+            .unwrap_or(Vec3::x());
+        let elevation = degrees.to_radians();
+        self.sun_direction = heading * elevation.cos() + Vec3::z() * elevation.sin();
+        self
+    }
+
+    /// The model's parameters and the normalized sun direction, for
+    /// [`World::build_with_sky`]. A sun below the horizon is modeled as if it were on it.
+    pub fn params(&self) -> Result<(SkyParams, Vec3), SkyError> {
+        let sun_direction = normalized_sun(self.sun_direction)?;
+        let params = SkyParams {
+            elevation: sun_direction.z.clamp(0.0, 1.0).asin() as f32,
+            turbidity: self.turbidity as f32,
+            albedo: [0, 1, 2].map(|i| self.albedo[i] as f32),
+        };
+        Ok((params, sun_direction))
+    }
+}
+
+fn normalized_sun(direction: Vec3) -> Result<Vec3, SkyError> {
+    direction
+        .try_normalize(Float::EPSILON)
+        .filter(|direction| direction.iter().all(|c| c.is_finite()))
+        .ok_or(SkyError::NoSunDirection(direction))
+}
+
+/// Trade-off between how long the `BVH` takes to build and how fast it is to traverse
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BuildQuality {
+    #[default]
+    Fast,
+    /// Splits triangles much larger than the rest of the scene (like ground planes) into
+    /// fragments with tight bounds, so they don't overlap every other node in the `BVH`
+    HighQuality,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub quality: BuildQuality,
+}
+
+/// Strategies for letting rays through alpha-masked surfaces. Both accept a hit with probability
+/// equal to the surface's alpha, so they converge to the same image.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TransparencyMode {
+    /// Finds the nearest hit, then casts a fresh ray past it if it was rejected
+    #[default]
+    Recast,
+    /// Decides whether each hit is accepted as soon as it's found and keeps traversing the BVH
+    /// past rejected ones. Much cheaper when rays pass through many layers.
+    Stochastic,
+}
+
+/// What a [`World`] is lit by and shown with apart from its shapes, so it can be built from them
+/// later, e.g. in the background while the preview loads. Anything left unset stays as
+/// [`World::build`] has it.
+#[derive(Debug, Clone, Default)]
+pub struct Surroundings {
+    sky: Option<(SkyParams, Vec3)>,
+    background: Option<Background>,
+    sun_disc: Option<SunDisc>,
+    tonemap: Option<Tonemap>,
+    animation: Animation,
+}
+
+impl Surroundings {
+    /// Lights the world with the sky `settings` describe, which is checked here so building
+    /// can't fail later
+    pub fn with_sky(mut self, settings: &SkySettings) -> Result<Self, SkyError> {
+        let (params, sun_direction) = settings.params()?;
+        SkyState::new(&params).map_err(SkyError::Model)?;
+        self.sky = Some((params, sun_direction));
+        Ok(self)
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    pub fn with_sun_disc(mut self, sun_disc: SunDisc) -> Self {
+        self.sun_disc = Some(sun_disc);
+        self
+    }
+
+    pub fn with_tonemap(mut self, tonemap: Tonemap) -> Self {
+        self.tonemap = Some(tonemap);
+        self
+    }
+
+    pub fn with_animation(mut self, animation: Animation) -> Self {
+        self.animation = animation;
+        self
+    }
+
+    /// Builds a `World` of `shapes` in these surroundings
+    pub fn build(&self, shapes: Vec<Shape>) -> World {
+        let mut world = match self.sky {
+            Some((params, sun_direction)) => World::build_with_sky(shapes, params, sun_direction)
+                .expect("with_sky checked the sky"),
+            None => World::build(shapes),
+        };
+        if let Some(background) = &self.background {
+            world.background = background.clone();
+        }
+        if self.sun_disc.is_some() {
+            world.sun_disc = self.sun_disc;
+        }
+        if let Some(tonemap) = &self.tonemap {
+            world.tonemap = tonemap.clone();
+        }
+        world.animation = self.animation.clone();
+        world
+    }
 }
 
 impl World {
     /// Constructs a new `World` and builds its `BVH` in parallel
-    pub fn build(mut shapes: Vec<Shape>) -> Self {
-        let bvh = Bvh::build_par(&mut shapes);
-        let sky = SkyState::new(&SkyParams::default()).expect("error constructing sky model");
+    pub fn build(shapes: Vec<Shape>) -> Self {
+        World::build_with_options(shapes, BuildOptions::default())
+    }
+
+    /// Constructs a new `World`, spending more time on the `BVH` if `options` asks for it
+    pub fn build_with_options(shapes: Vec<Shape>, options: BuildOptions) -> Self {
+        let sky = SkyState::new(&SkyParams::default()).expect("the default sky is valid");
+        World::assemble(shapes, options, sky, SkyParams::default(), Vec3::z())
+    }
+
+    /// Like [`World::build`], under a sky made from `sky_params` with the sun toward
+    /// `sun_direction`, e.g. from [`SkySettings::params`]. The direction is normalized here.
+    pub fn build_with_sky(
+        shapes: Vec<Shape>,
+        sky_params: SkyParams,
+        sun_direction: Vec3,
+    ) -> Result<Self, SkyError> {
+        let sun_direction = normalized_sun(sun_direction)?;
+        let sky = SkyState::new(&sky_params).map_err(SkyError::Model)?;
+        Ok(World::assemble(
+            shapes,
+            BuildOptions::default(),
+            sky,
+            sky_params,
+            sun_direction,
+        ))
+    }
+
+    /// Like [`World::build`], lit by the equirectangular panorama `environment`, e.g. from
+    /// [`EnvironmentMap::load`], spun `rotation` degrees around the up axis
+    pub fn build_with_env(shapes: Vec<Shape>, environment: Image, rotation: Float) -> Self {
+        let mut world = World::build(shapes);
+        world.background = Background::Environment(EnvironmentMap::new(environment, rotation));
+        world
+    }
 
-        // TODO: test best default sun direction, maybe add parameter in `build`
-        let sun_direction = Vec3::new(0.0, 0.0, 1.0).normalize();
+    fn assemble(
+        shapes: Vec<Shape>,
+        options: BuildOptions,
+        sky: SkyState,
+        sky_params: SkyParams,
+        sun_direction: Vec3,
+    ) -> Self {
+        let (shapes, rejected) = if cfg!(debug_assertions) {
+            reject_invalid(shapes)
+        } else {
+            (shapes, RejectReport::default())
+        };
+        if !rejected.is_empty() {
+            println!("World {}", rejected);
+        }
+        let mut shapes = match options.quality {
+            BuildQuality::Fast => shapes,
+            BuildQuality::HighQuality => {
+                let num_primitives = shapes.len();
+                let split = spatial_split::split_large_triangles(shapes);
+                println!(
+                    "Spatial splits turned {} primitives into {} references (+{:.1}%)",
+                    num_primitives,
+                    split.len(),
+                    (split.len() as Float / num_primitives.max(1) as Float - 1.0) * 100.0
+                );
+                split
+            }
+        };
+        let bvh = Bvh::build_par(&mut shapes);
+        let bounds = shapes
+            .iter()
+            .fold(Aabb::empty(), |bounds, shape| bounds.join(&shape.aabb()));
 
         World {
             shapes,
             bvh,
+            accel: None,
             sky,
+            sky_params,
             sun_direction,
+            tonemap: Tonemap::default(),
+            transparency: TransparencyMode::default(),
+            background: Background::default(),
+            sun_disc: None,
+            numeric: NumericContext::from_bounds(&bounds),
+            sky_importance: OnceLock::new(),
+            sky_harmonics: OnceLock::new(),
+            area_lights: OnceLock::new(),
+            scene_lights: OnceLock::new(),
+            light_selection: LightSelection::default(),
+            rejected,
+            animation: Animation::default(),
+        }
+    }
+
+    /// Updates the `BVH` to fit shapes that moved, e.g. instances that were given new
+    /// transforms, without rebuilding it. Cheap, but the tree gets looser the further they move.
+    pub fn refit(&mut self) {
+        fn refit_node(
+            nodes: &mut [BvhNode<Float, 3>],
+            shapes: &[Shape],
+            node: usize,
+        ) -> Aabb<Float, 3> {
+            match nodes[node] {
+                BvhNode::Leaf { shape_index, .. } => shapes[shape_index].aabb(),
+                BvhNode::Node {
+                    child_l_index,
+                    child_r_index,
+                    ..
+                } => {
+                    let left = refit_node(nodes, shapes, child_l_index);
+                    let right = refit_node(nodes, shapes, child_r_index);
+                    *nodes[node].child_l_aabb_mut() = left;
+                    *nodes[node].child_r_aabb_mut() = right;
+                    left.join(&right)
+                }
+            }
+        }
+        if self.bvh.nodes.is_empty() {
+            return;
+        }
+        let bounds = refit_node(&mut self.bvh.nodes, &self.shapes, 0);
+        self.numeric = NumericContext::from_bounds(&bounds);
+        if self.accel.is_some() {
+            if let Err(err) = self.set_bvh_layout(BvhLayout::Compressed) {
+                println!("Warning: back to the binary BVH, {}", err);
+                self.accel = None;
+            }
+        }
+    }
+
+    pub fn bvh_layout(&self) -> BvhLayout {
+        match self.accel {
+            Some(_) => BvhLayout::Compressed,
+            None => BvhLayout::Binary,
+        }
+    }
+
+    /// Switches which layout rays traverse the `BVH` in, converting it if needed. The binary
+    /// tree stays around either way, since the compressed one is made from it again on refits.
+    pub fn set_bvh_layout(&mut self, layout: BvhLayout) -> Result<(), AccelError> {
+        self.accel = match layout {
+            BvhLayout::Binary => None,
+            BvhLayout::Compressed => Some(CompressedBvh::from_bvh(&self.bvh, &self.shapes)?),
+        };
+        Ok(())
+    }
+
+    /// Bytes taken by the nodes of the `BVH` rays traverse
+    pub fn bvh_memory_bytes(&self) -> usize {
+        match &self.accel {
+            Some(accel) => accel.memory_bytes(),
+            None => self.bvh.nodes.len() * std::mem::size_of::<BvhNode<Float, 3>>(),
+        }
+    }
+
+    /// The shapes whose bounds `ray` hits, in no particular order
+    fn candidates<'a: 'r, 'r>(
+        &'a self,
+        ray: &'r Ray,
+    ) -> Candidates<'a, impl Iterator<Item = &'a Shape> + 'r> {
+        match &self.accel {
+            Some(accel) => Candidates::Compressed(accel.traverse(ray, &self.shapes)),
+            None => Candidates::Binary(self.bvh.traverse_iterator(ray, &self.shapes)),
+        }
+    }
+
+    /// Like [`World::candidates`], roughly nearest first, and skipping shapes past
+    /// [`NearestCandidates::limit`]
+    fn nearest_candidates(&self, ray: &Ray) -> NearestCandidates<'_> {
+        match &self.accel {
+            Some(accel) => NearestCandidates::Compressed(accel.traverse(ray, &self.shapes)),
+            None => NearestCandidates::Binary(BinaryTraverse::new(&self.bvh, ray, &self.shapes)),
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn sky_params(&self) -> SkyParams {
+        self.sky_params
+    }
+
+    fn sky_importance(&self) -> &SkyImportance {
+        self.sky_importance
+            .get_or_init(|| SkyImportance::new(|direction| self.sky_glow_toward(direction)))
+    }
+
+    /// The sun's disc, if the world's sky has one
+    pub fn sun(&self) -> Option<&SunDisc> {
+        match self.background {
+            Background::Sky => self.sun_disc.as_ref(),
+            Background::Gradient { .. } | Background::Environment(_) => None,
+        }
+    }
+
+    /// Returns the radiance of the sun's disc, without the sky behind it, or `None` if the sky
+    /// has no disc. Tinted like the sky right around the sun, so a low sun comes out warm.
+    pub fn sun_radiance(&self) -> Option<Vec3> {
+        let sun = self.sun()?;
+        let glow = self.sky_glow_toward(&self.sun_direction);
+        let brightness = luminance(&glow);
+        let tint = if brightness > 0.0 {
+            glow / brightness
+        } else {
+            Vec3::repeat(1.0)
+        };
+        Some(tint * (sun.irradiance / sun.solid_angle()))
+    }
+
+    /// Picks a direction toward the sun's disc uniformly, for lighting diffuse surfaces with it
+    /// directly. The radiance includes the sky behind the disc. Returns `None` if the sky has no
+    /// disc, and doesn't check whether anything is in the way.
+    pub fn sample_sun<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<SkySample> {
+        let sun = self.sun()?;
+        let direction =
+            Vec3::random_in_cone(rng, &self.sun_direction, sun.angle.to_radians() / 2.0);
+        #[cfg(feature = "diagnostics")]
+        {
+            let half_angle = sun.angle.to_radians() / 2.0;
+            let (s, t) =
+                crate::splat::cone_coordinates(&self.sun_direction, half_angle, &direction);
+            crate::splat::record_light(crate::splat::LightSplat {
+                light: crate::splat::SplatLight::Sun,
+                s,
+                t,
+            });
+        }
+        Some(SkySample {
+            direction,
+            radiance: self.sky_color_toward(&direction),
+            pdf: 1.0 / sun.solid_angle(),
+        })
+    }
+
+    /// Returns the probability density of [`World::sample_sun`] picking the unit vector
+    /// `direction`, per solid angle
+    pub fn pdf_sun(&self, direction: &Vec3) -> Float {
+        match self.sun() {
+            Some(sun) if direction.dot(&self.sun_direction) >= sun.cos_radius() => {
+                1.0 / sun.solid_angle()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Picks a direction toward the sky in proportion to how bright it is there, for lighting
+    /// diffuse surfaces from the whole sky dome. Doesn't check whether anything is in the way.
+    /// The sun's disc is too small for this to find, so it's left to [`World::sample_sun`].
+    pub fn sample_sky_importance<R: Rng + ?Sized>(&self, rng: &mut R) -> SkySample {
+        let (direction, pdf) = self.sky_importance().sample(rng);
+        #[cfg(feature = "diagnostics")]
+        crate::splat::record_sky(crate::splat::SkySplat { direction, pdf });
+        SkySample {
+            direction,
+            radiance: self.sky_color_toward(&direction),
+            pdf,
         }
     }
 
-    // Taken from this blog post: https://nelari.us/post/weekend_raytracing_with_wgpu_2/
-    // Notes on tomemapping and color space transformations: https://computergraphics.stackexchange.com/questions/10315/tone-mapping-vs-gamma-correction
-    // In essence: yes, keep the gamma correction at the end.
-    fn uncharted2_tonemap(x: Vec3) -> Vec3 {
-        let a = 0.15;
-        let b = 0.50;
-        let c = 0.10;
-        let d = 0.20;
-        let e = 0.02;
-        let f = 0.30;
-        // let w = 11.2;
+    /// Returns the probability density of [`World::sample_sky_importance`] picking the unit
+    /// vector `direction`, per solid angle
+    pub fn pdf_sky(&self, direction: &Vec3) -> Float {
+        self.sky_importance().pdf(direction)
+    }
 
-        let numerator = x.component_mul(&(a * x + Vec3::new(c * b, c * b, c * b)))
-            + Vec3::new(d * e, d * e, d * e);
-        let denominator =
-            x.component_mul(&(a * x + Vec3::new(b, b, b))) + Vec3::new(d * f, d * f, d * f);
+    /// The sky projected onto spherical harmonics, for lighting surfaces from the whole sky
+    /// without sampling it
+    pub fn sky_harmonics(&self) -> &SkyHarmonics {
+        self.sky_harmonics
+            .get_or_init(|| SkyHarmonics::new(|direction| self.sky_glow_toward(direction)))
+    }
 
-        numerator.component_div(&denominator) - Vec3::new(e / f, e / f, e / f)
+    /// The emissive surfaces that can be sampled directly, see [`AreaLights`]
+    pub fn area_lights(&self) -> &AreaLights {
+        self.area_lights
+            .get_or_init(|| AreaLights::new(&self.shapes, self.light_selection))
     }
 
-    /// Takes an `unclamped_color` and returns a color with values in the range [0.0, 1.0]
-    /// [Taken from this blog post](https://nelari.us/post/weekend_raytracing_with_wgpu_2/)
-    fn uncharted2(x: Vec3) -> Vec3 {
-        // let exposure_bias = 0.246; // determined experimentally for the scene
-        let exposure_bias = 1.1;
+    /// The spot and rect lights among the world's shapes, which diffuse surfaces sample directly,
+    /// see [`SceneLights`]
+    pub fn scene_lights(&self) -> &SceneLights {
+        self.scene_lights
+            .get_or_init(|| SceneLights::new(&self.shapes, self.light_selection))
+    }
 
-        let curr = World::uncharted2_tonemap(exposure_bias * x);
+    /// Whether any shape is made of a [`crate::material::ShadowCatcher`], so renders need an
+    /// alpha channel. Catchers inside instances aren't found.
+    pub fn has_shadow_catchers(&self) -> bool {
+        self.shapes.iter().any(|shape| {
+            let material = match shape {
+                Shape::Sphere(sphere) => &*sphere.material,
+                Shape::Triangle(triangle) => &*triangle.material,
+                Shape::TriangleFragment(fragment) => &*fragment.triangle().material,
+                Shape::AaBox(aabox) => &*aabox.material,
+                Shape::RoundedBox(rounded) => &*rounded.material,
+                Shape::Mesh(mesh) => &*mesh.material,
+                _ => return false,
+            };
+            matches!(material, Material::ShadowCatcher(_))
+        })
+    }
 
-        let w = 11.2;
-        let white_scale = Vec3::ONE.component_div(&World::uncharted2_tonemap(Vec3::new(w, w, w)));
-        white_scale.component_mul(&curr)
+    /// Returns a hash identifying the scene's geometry, for matching up diagnostics with scenes
+    pub fn scene_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.shapes.len().hash(&mut hasher);
+        for shape in &self.shapes {
+            let aabb = shape.aabb();
+            for i in 0..3 {
+                aabb.min[i].to_bits().hash(&mut hasher);
+                aabb.max[i].to_bits().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
     }
 
     // TODO: stop clamping any colors before the final display in the window
     // only tonemap them right before. that way shit can have greater contrast and emit light
     // wait is that even true? hmmmmmmmmmmmmmmmmmmmmmmmmmm
+    /// Returns the tonemapped background color seen looking toward the unit vector `direction`,
+    /// including the sun's disc
     pub fn sky_color_toward(&self, direction: &Vec3) -> Vec3 {
-        let theta = direction.z.acos() as f32;
-        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0) as f32;
+        let color = self.sky_glow_toward(direction);
+        match self.sun_radiance() {
+            _ if self.pdf_sun(direction) == 0.0 => color,
+            Some(sun) => color + sun,
+            None => color,
+        }
+    }
+
+    /// Like [`World::sky_color_toward`], without the sun's disc. The disc isn't tonemapped, so
+    /// it stays bright enough to light the scene.
+    fn sky_glow_toward(&self, direction: &Vec3) -> Vec3 {
+        if let Background::Gradient { up, bottom, top } = &self.background {
+            let a = 0.5 * (direction.dot(up) + 1.0);
+            return self.tonemap.apply(bottom * (1.0 - a) + top * a);
+        }
+        if let Background::Environment(environment) = &self.background {
+            return self.tonemap.apply(environment.radiance_toward(direction));
+        }
+        // The model only covers the sky above the horizon, so below it the horizon carries on.
+        // Clamping also keeps rounding from taking `acos` out of its domain.
+        let theta = direction.z.clamp(0.0, 1.0).acos() as f32;
+        // Both are angles, to the zenith and to the sun
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos() as f32;
         let color = Vec3::new(
             self.sky.radiance(theta, gamma, Channel::R).into(),
             self.sky.radiance(theta, gamma, Channel::G).into(),
             self.sky.radiance(theta, gamma, Channel::B).into(),
         );
-        World::uncharted2(color)
+        self.tonemap.apply(color)
     }
 }
 
 #[enum_dispatch(Shape)]
 pub trait Hit: Send + Sync {
-    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection>;
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>>;
 }
 
 #[enum_dispatch]
 pub enum Shape {
     Sphere,
     Triangle,
+    TriangleFragment,
+    AaBox,
+    RoundedBox,
+    Instance,
+    Mesh,
+    HeterogeneousMedium,
+    RectLight,
+    SpotLight,
 }
 
 // no fucking way this guy is literally me https://old.reddit.com/r/rust/comments/tgwpo7/avoiding_bad_patterns/
@@ -112,6 +718,14 @@ impl Bounded<Float, 3> for Shape {
         match self {
             Shape::Sphere(s) => s.aabb(),
             Shape::Triangle(t) => t.aabb(),
+            Shape::TriangleFragment(f) => f.aabb(),
+            Shape::AaBox(b) => b.aabb(),
+            Shape::RoundedBox(b) => b.aabb(),
+            Shape::Instance(i) => i.aabb(),
+            Shape::Mesh(m) => m.aabb(),
+            Shape::HeterogeneousMedium(m) => m.aabb(),
+            Shape::RectLight(l) => l.aabb(),
+            Shape::SpotLight(l) => l.aabb(),
         }
     }
 }
@@ -121,6 +735,14 @@ impl BHShape<Float, 3> for Shape {
         match self {
             Shape::Sphere(s) => s.set_bh_node_index(index),
             Shape::Triangle(t) => t.set_bh_node_index(index),
+            Shape::TriangleFragment(f) => f.set_bh_node_index(index),
+            Shape::AaBox(b) => b.set_bh_node_index(index),
+            Shape::RoundedBox(b) => b.set_bh_node_index(index),
+            Shape::Instance(i) => i.set_bh_node_index(index),
+            Shape::Mesh(m) => m.set_bh_node_index(index),
+            Shape::HeterogeneousMedium(m) => m.set_bh_node_index(index),
+            Shape::RectLight(l) => l.set_bh_node_index(index),
+            Shape::SpotLight(l) => l.set_bh_node_index(index),
         }
     }
 
@@ -128,24 +750,308 @@ impl BHShape<Float, 3> for Shape {
         match self {
             Shape::Sphere(s) => s.bh_node_index(),
             Shape::Triangle(t) => t.bh_node_index(),
+            Shape::TriangleFragment(f) => f.bh_node_index(),
+            Shape::AaBox(b) => b.bh_node_index(),
+            Shape::RoundedBox(b) => b.bh_node_index(),
+            Shape::Instance(i) => i.bh_node_index(),
+            Shape::Mesh(m) => m.bh_node_index(),
+            Shape::HeterogeneousMedium(m) => m.bh_node_index(),
+            Shape::RectLight(l) => l.bh_node_index(),
+            Shape::SpotLight(l) => l.bh_node_index(),
         }
     }
 }
 
-impl Hit for World {
-    /// Returns nearest hit to camera for the given ray within the given view range
-    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection> {
+impl Shape {
+    /// Checks for inputs that would make the shape unhittable or poison the `BVH` with NaN
+    /// bounds
+    pub fn validate(&self) -> Result<(), ShapeError> {
+        match self {
+            Shape::Sphere(s) => s.validate(),
+            Shape::Triangle(t) => t.validate(),
+            Shape::TriangleFragment(f) => f.triangle().validate(),
+            _ => {
+                let bounds = self.aabb();
+                if !bounds
+                    .min
+                    .iter()
+                    .chain(bounds.max.iter())
+                    .all(|x| x.is_finite())
+                {
+                    return Err(ShapeError::NotFinite("bounds"));
+                }
+                if (0..3).any(|axis| bounds.min[axis] > bounds.max[axis]) {
+                    return Err(ShapeError::Inverted);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Why a shape can't be part of a scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShapeError {
+    /// A NaN or infinite coordinate or size, with which input it was
+    NotFinite(&'static str),
+    /// A sphere radius that's zero or negative
+    NonPositiveRadius(Float),
+    /// A triangle with its corners on one line, which has no area or normal
+    Degenerate,
+    /// Bounds with their minimum above their maximum on some axis
+    Inverted,
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShapeError::NotFinite(what) => write!(f, "{} isn't finite", what),
+            ShapeError::NonPositiveRadius(radius) => {
+                write!(f, "radius {} isn't positive", radius)
+            }
+            ShapeError::Degenerate => write!(f, "triangle has no area"),
+            ShapeError::Inverted => write!(f, "bounds are inside out"),
+        }
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+fn check_finite(point: &Point3, what: &'static str) -> Result<(), ShapeError> {
+    if point.iter().all(|x| x.is_finite()) {
+        Ok(())
+    } else {
+        Err(ShapeError::NotFinite(what))
+    }
+}
+
+/// How many shapes were left out of a scene, by why
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RejectReport {
+    pub not_finite: usize,
+    pub non_positive_radius: usize,
+    pub degenerate: usize,
+    pub inverted: usize,
+}
+
+impl RejectReport {
+    pub fn record(&mut self, error: &ShapeError) {
+        match error {
+            ShapeError::NotFinite(_) => self.not_finite += 1,
+            ShapeError::NonPositiveRadius(_) => self.non_positive_radius += 1,
+            ShapeError::Degenerate => self.degenerate += 1,
+            ShapeError::Inverted => self.inverted += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.not_finite + self.non_positive_radius + self.degenerate + self.inverted
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Keeps the shapes in `results` that were built and counts the ones that weren't
+    pub fn collect<T>(
+        &mut self,
+        results: impl IntoIterator<Item = Result<T, ShapeError>>,
+    ) -> Vec<T> {
+        results
+            .into_iter()
+            .filter_map(|result| result.map_err(|error| self.record(&error)).ok())
+            .collect()
+    }
+}
+
+impl fmt::Display for RejectReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reasons: Vec<String> = [
+            (self.not_finite, "with NaN or infinite coordinates"),
+            (self.non_positive_radius, "with no radius"),
+            (self.degenerate, "with no area"),
+            (self.inverted, "inside out"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, reason)| format!("{} {}", count, reason))
+        .collect();
+        write!(
+            f,
+            "left out {} shape(s): {}",
+            self.total(),
+            reasons.join(", ")
+        )
+    }
+}
+
+/// Splits off the shapes that fail [`Shape::validate`], counting them
+pub fn reject_invalid(shapes: Vec<Shape>) -> (Vec<Shape>, RejectReport) {
+    let mut report = RejectReport::default();
+    let shapes = report.collect(
+        shapes
+            .into_iter()
+            .map(|shape| shape.validate().map(|()| shape)),
+    );
+    (shapes, report)
+}
+
+/// Returns just past `t` so that a surface hit at `t` isn't found again
+fn skip_past(t: Float) -> Float {
+    t + (t.abs() + 1.0) * 1e-9
+}
+
+/// Shadow rays stop looking for more occluders once less than this fraction of light gets through
+const MIN_SHADOW_TRANSMITTANCE: Float = 1e-3;
+
+impl World {
+    /// Returns whether a ray should stop at `hit` rather than pass through it
+    fn accepts_hit(hit: &Intersection) -> bool {
+        let alpha = hit.material.alpha(hit);
+        alpha >= 1.0 || HitRng.gen::<Float>() < alpha
+    }
+
+    /// Returns the nearest hit within `range`. With `stochastic` set, alpha-masked hits are
+    /// accepted or rejected as they're found, and traversal continues past rejected ones.
+    fn nearest_hit(
+        &self,
+        ray: &Ray,
+        range: &Range<Float>,
+        stochastic: bool,
+    ) -> Option<Intersection<'_>> {
         // Only return the nearest collision
         let mut nearest_hit_dist = range.end;
         let mut nearest_hit = None;
-        for shape in self.bvh.nearest_traverse_iterator(ray, &self.shapes) {
-            if let Some(intersection) = shape.hit(ray, &(range.start..nearest_hit_dist)) {
+        let mut candidates = self.nearest_candidates(ray);
+        candidates.limit(range.end);
+        while let Some(shape) = candidates.next() {
+            let mut start = range.start;
+            // A shape can have more than one hit along the ray, so look past rejected ones
+            while let Some(intersection) = shape.hit(ray, &(start..nearest_hit_dist)) {
+                if stochastic && !World::accepts_hit(&intersection) {
+                    start = skip_past(intersection.t);
+                    continue;
+                }
                 nearest_hit_dist = intersection.t;
                 nearest_hit = Some(intersection);
+                candidates.limit(nearest_hit_dist);
+                break;
             }
         }
         nearest_hit
     }
+
+    /// Returns the fraction of light that makes it along `ray` through everything within `range`,
+    /// per channel. Every surface in the way multiplies in its shadow transmittance, so opaque
+    /// surfaces block the light while glass and alpha-masked surfaces only dim it. The product
+    /// doesn't depend on the order surfaces are found in, so the `BVH` is traversed in any order.
+    pub fn transmittance(&self, ray: &Ray, range: &Range<Float>) -> Vec3 {
+        let mut transmittance = Vec3::ONE;
+        for shape in self.candidates(ray) {
+            let mut start = range.start;
+            // A shape can be in the way more than once, like both sides of a glass sphere
+            while let Some(intersection) = shape.hit(ray, &(start..range.end)) {
+                transmittance.component_mul_assign(
+                    &intersection
+                        .material
+                        .shadow_transmittance(ray, &intersection),
+                );
+                if transmittance.max() < MIN_SHADOW_TRANSMITTANCE {
+                    return Vec3::zeros();
+                }
+                start = skip_past(intersection.t);
+            }
+        }
+        transmittance
+    }
+
+    /// Returns whether anything `blocks` accepts is in the way along `ray` within `range`.
+    /// Materials don't matter, so glass and alpha-masked surfaces block as much as any other.
+    pub fn blocked(
+        &self,
+        ray: &Ray,
+        range: &Range<Float>,
+        blocks: impl Fn(ObjectId) -> bool,
+    ) -> bool {
+        self.candidates(ray).any(|shape| {
+            let mut start = range.start;
+            while let Some(intersection) = shape.hit(ray, &(start..range.end)) {
+                if blocks(intersection.object) {
+                    return true;
+                }
+                start = skip_past(intersection.t);
+            }
+            false
+        })
+    }
+}
+
+/// Shapes whose bounds a ray hits, from whichever layout the world's `BVH` is traversed in.
+/// Only ever lives on the stack for one ray, where boxing the big variant would cost more.
+#[allow(clippy::large_enum_variant)]
+enum Candidates<'a, I> {
+    Binary(I),
+    Compressed(Traverse<'a, Shape>),
+}
+
+impl<'a, I: Iterator<Item = &'a Shape>> Iterator for Candidates<'a, I> {
+    type Item = &'a Shape;
+
+    fn next(&mut self) -> Option<&'a Shape> {
+        match self {
+            Candidates::Binary(iter) => iter.next(),
+            Candidates::Compressed(traverse) => traverse.next(),
+        }
+    }
+}
+
+/// Shapes whose bounds a ray hits nearer than the nearest hit found so far, from whichever
+/// layout the world's `BVH` is traversed in
+enum NearestCandidates<'a> {
+    Binary(BinaryTraverse<'a, Shape>),
+    Compressed(Traverse<'a, Shape>),
+}
+
+impl NearestCandidates<'_> {
+    /// Stops returning shapes whose bounds the ray enters further away than `distance`
+    fn limit(&mut self, distance: Float) {
+        match self {
+            NearestCandidates::Binary(traverse) => traverse.limit(distance),
+            NearestCandidates::Compressed(traverse) => traverse.limit(distance),
+        }
+    }
+}
+
+impl<'a> Iterator for NearestCandidates<'a> {
+    type Item = &'a Shape;
+
+    fn next(&mut self) -> Option<&'a Shape> {
+        match self {
+            NearestCandidates::Binary(traverse) => traverse.next(),
+            NearestCandidates::Compressed(traverse) => traverse.next(),
+        }
+    }
+}
+
+impl Hit for World {
+    /// Returns nearest hit to camera for the given ray within the given view range
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        match self.transparency {
+            TransparencyMode::Stochastic => {
+                self.nearest_hit(ray, range, true).map(Intersection::bumped)
+            }
+            TransparencyMode::Recast => {
+                let mut start = range.start;
+                loop {
+                    let hit = self.nearest_hit(ray, &(start..range.end), false)?;
+                    if World::accepts_hit(&hit) {
+                        return Some(hit.bumped());
+                    }
+                    start = skip_past(hit.t);
+                }
+            }
+        }
+    }
 }
 
 // TODO: look up best design practices for triangles in a ray tracer
@@ -158,26 +1064,128 @@ pub struct Triangle {
     pub uv_b: Vec2,
     pub uv_c: Vec2,
     normal: Vec3,
+    /// Unit normals at a, b and c, interpolated across the triangle to shade it smoothly. `None`
+    /// shades it flat with `normal`.
+    vertex_normals: Option<[Vec3; 3]>,
     pub material: Arc<Material>,
     node_index: usize,
+    /// The scene object this triangle is part of
+    pub object: ObjectId,
+    /// Fraction of the triangle's size below which its intersection test treats things as zero
+    epsilon: Float,
+    /// Squared determinant below which a ray is edge-on, from `epsilon` and the edge lengths
+    min_det_squared: Float,
+    /// Closest a hit may be along a ray, from `epsilon` and the longest edge
+    min_distance: Float,
+    /// Whether rays hit the back as well as the front, see [`Triangle::with_double_sided`]
+    double_sided: bool,
+}
+
+/// Whether triangles made of `material` are hit from behind unless they're told otherwise.
+/// Glass is, since rays that go into it have to leave through the back of its faces, and meshes
+/// are often made of single-sided faces. Everything else only shows its front, which lets e.g.
+/// ground planes be seen through from below.
+pub(crate) fn double_sided_by_default(material: &Material) -> bool {
+    matches!(material, Material::Dielectric(_))
+}
+
+/// Returns the squared determinant below which a ray counts as edge-on to the triangle `a`, `b`,
+/// `c`, and the closest distance a hit on it may be, both `epsilon` of its size
+pub(crate) fn triangle_tolerances(
+    a: Point3,
+    b: Point3,
+    c: Point3,
+    epsilon: Float,
+) -> (Float, Float) {
+    let (ab, ac) = ((b - a).norm_squared(), (c - a).norm_squared());
+    let longest = ab.max(ac).max((c - b).norm_squared()).sqrt();
+    (ab * ac * epsilon.powi(2), longest * epsilon)
+}
+
+/// Normal to shade the point `u` of the way toward the second corner and `v` toward the third
+/// with, interpolated from `vertex_normals` if there are any, on the same side as the face
+/// `normal`
+fn shading_normal(normal: &Vec3, vertex_normals: Option<&[Vec3; 3]>, u: Float, v: Float) -> Vec3 {
+    let Some([normal_a, normal_b, normal_c]) = vertex_normals else {
+        return *normal;
+    };
+    let interpolated = (normal_a * (1.0 - u - v) + normal_b * u + normal_c * v).normalize();
+    if !interpolated.iter().all(|x| x.is_finite()) {
+        return *normal;
+    }
+    // Files' normals don't have to agree with the winding, which may have been repaired
+    if interpolated.dot(normal) < 0.0 {
+        -interpolated
+    } else {
+        interpolated
+    }
+}
+
+/// Width and height of the bounds of the corners' texture coordinates `uvs`
+fn uv_extent(uvs: &[Vec2; 3]) -> (Float, Float) {
+    let extent = |values: [Float; 3]| {
+        values.iter().copied().fold(Float::MIN, Float::max)
+            - values.iter().copied().fold(Float::MAX, Float::min)
+    };
+    (extent(uvs.map(|uv| uv.x)), extent(uvs.map(|uv| uv.y)))
+}
+
+/// Texture coordinates of the point `u` of the way toward the second corner and `v` toward the
+/// third, on a triangle with the corners' texture coordinates `uvs`
+fn uv_at([uv_a, uv_b, uv_c]: &[Vec2; 3], u: Float, v: Float) -> Vec2 {
+    let left = uv_a.x.min(uv_b.x).min(uv_c.x);
+    let right = uv_a.x.max(uv_b.x).max(uv_c.x);
+
+    let bot = uv_a.y.min(uv_b.y).min(uv_c.y);
+    let top = uv_a.y.max(uv_b.y).max(uv_c.y);
+
+    let width = right - left;
+    let height = top - bot;
+
+    Vec2::new(left + width * u, bot + height * v)
+}
+
+/// The matrix that moves normals along with points moved by `matrix`, which is the inverse
+/// transpose of its rotation and scale so normals stay perpendicular under non-uniform scaling
+pub(crate) fn normal_matrix(matrix: &Matrix4<Float>) -> Matrix3<Float> {
+    let linear = matrix.fixed_view::<3, 3>(0, 0).into_owned();
+    linear
+        .try_inverse()
+        .map_or(linear, |inverse| inverse.transpose())
 }
 
+/// The texture coordinates of a triangle's corners when it isn't given any
+pub(crate) const DEFAULT_TRIANGLE_UVS: [Vec2; 3] = [
+    Vec2::new(0.0, 0.0),
+    Vec2::new(1.0, 0.0),
+    Vec2::new(0.5, 1.0),
+];
+
 impl Triangle {
     pub fn new(a: Point3, b: Point3, c: Point3, material: Arc<Material>) -> Self {
         // Normalizing early and often to avoid numerical errors
         // Shouldn't matter for performance since shapes are only created once
         let ab = (b - a).normalize();
         let ac = (c - a).normalize();
+        let (min_det_squared, min_distance) =
+            triangle_tolerances(a, b, c, DEGENERATE_TRIANGLE_RATIO);
+        let double_sided = double_sided_by_default(&material);
         Triangle {
             a,
             b,
             c,
-            uv_a: Vec2::new(0.0, 0.0), // 0.0, 0.0
-            uv_b: Vec2::new(1.0, 0.0), // 1.0, 0.0
-            uv_c: Vec2::new(0.5, 1.0), // 0.5, 1.0
+            uv_a: DEFAULT_TRIANGLE_UVS[0],
+            uv_b: DEFAULT_TRIANGLE_UVS[1],
+            uv_c: DEFAULT_TRIANGLE_UVS[2],
             normal: ab.cross(&ac).normalize(),
+            vertex_normals: None,
             material,
             node_index: 0,
+            object: ObjectId::default(),
+            epsilon: DEGENERATE_TRIANGLE_RATIO,
+            min_det_squared,
+            min_distance,
+            double_sided,
         }
     }
 
@@ -194,6 +1202,9 @@ impl Triangle {
         // Shouldn't matter for performance since shapes are only created once
         let ab = (b - a).normalize();
         let ac = (c - a).normalize();
+        let (min_det_squared, min_distance) =
+            triangle_tolerances(a, b, c, DEGENERATE_TRIANGLE_RATIO);
+        let double_sided = double_sided_by_default(&material);
         Triangle {
             a,
             b,
@@ -202,8 +1213,14 @@ impl Triangle {
             uv_b,
             uv_c,
             normal: ab.cross(&ac).normalize(),
+            vertex_normals: None,
             material,
             node_index: 0,
+            object: ObjectId::default(),
+            epsilon: DEGENERATE_TRIANGLE_RATIO,
+            min_det_squared,
+            min_distance,
+            double_sided,
         }
     }
 
@@ -211,31 +1228,212 @@ impl Triangle {
         Self::new(c, b, a, material)
     }
 
-    pub fn transform(&self, matrix: &Matrix4<Float>) -> Self {
-        let a = matrix.transform_vector(&self.a);
-        let b = matrix.transform_vector(&self.b);
-        let c = matrix.transform_vector(&self.c);
-        Triangle::new_with_uv(
-            a,
-            b,
-            c,
-            self.uv_a,
-            self.uv_b,
-            self.uv_c,
-            self.material.clone(),
-        )
+    /// Like [`Triangle::new`], but fails on corners that aren't finite or are all on one line
+    pub fn try_new(
+        a: Point3,
+        b: Point3,
+        c: Point3,
+        material: Arc<Material>,
+    ) -> Result<Self, ShapeError> {
+        let triangle = Self::new(a, b, c, material);
+        triangle.validate()?;
+        Ok(triangle)
     }
 
-    pub fn shift(&self, shift: Vec3) -> Self {
-        Triangle::new_with_uv(
-            self.a + shift,
-            self.b + shift,
-            self.c + shift,
-            self.uv_a,
-            self.uv_b,
+    /// Like [`Triangle::new_with_uv`], but fails on corners that aren't finite or are all on one
+    /// line
+    pub fn try_new_with_uv(
+        a: Point3,
+        b: Point3,
+        c: Point3,
+        uv_a: Vec2,
+        uv_b: Vec2,
+        uv_c: Vec2,
+        material: Arc<Material>,
+    ) -> Result<Self, ShapeError> {
+        let triangle = Self::new_with_uv(a, b, c, uv_a, uv_b, uv_c, material);
+        triangle.validate()?;
+        Ok(triangle)
+    }
+
+    /// Checks that the corners are finite and not all on one line
+    pub fn validate(&self) -> Result<(), ShapeError> {
+        for corner in [&self.a, &self.b, &self.c] {
+            check_finite(corner, "triangle corner")?;
+        }
+        // A zero cross product normalizes to NaN
+        if !self.normal.iter().all(|x| x.is_finite()) {
+            return Err(ShapeError::Degenerate);
+        }
+        Ok(())
+    }
+
+    /// Moves the triangle by `matrix`, translation included, keeping the same side in front even
+    /// if `matrix` mirrors it. The face normal is found again from the moved corners, and vertex
+    /// normals go through [`normal_matrix`], so both stay right under non-uniform scaling.
+    pub fn transform(&self, matrix: &Matrix4<Float>) -> Self {
+        // `Point3` is a vector, so it has to be made a point for the translation to apply
+        let place = |point: &Point3| matrix.transform_point(&(*point).into()).coords;
+        let mut triangle = Triangle::new_with_uv(
+            place(&self.a),
+            place(&self.b),
+            place(&self.c),
+            self.uv_a,
+            self.uv_b,
+            self.uv_c,
+            self.material.clone(),
+        )
+        .with_object(self.object)
+        .with_epsilon(self.epsilon)
+        .with_double_sided(self.double_sided);
+        if let Some(normals) = self.vertex_normals {
+            let normal_matrix = normal_matrix(matrix);
+            triangle = triangle.with_vertex_normals(normals.map(|normal| normal_matrix * normal));
+        }
+        if matrix.fixed_view::<3, 3>(0, 0).determinant() < 0.0 {
+            triangle.flip();
+        }
+        triangle
+    }
+
+    pub fn shift(&self, shift: Vec3) -> Self {
+        let mut triangle = Triangle::new_with_uv(
+            self.a + shift,
+            self.b + shift,
+            self.c + shift,
+            self.uv_a,
+            self.uv_b,
             self.uv_c,
             self.material.clone(),
         )
+        .with_object(self.object)
+        .with_epsilon(self.epsilon)
+        .with_double_sided(self.double_sided);
+        triangle.vertex_normals = self.vertex_normals;
+        triangle
+    }
+
+    /// Shades the triangle smoothly by interpolating `normals` at a, b and c across it, instead
+    /// of flat with its face normal. They don't have to be unit length, but if any of them
+    /// can't be normalized the triangle stays flat.
+    pub fn with_vertex_normals(mut self, normals: [Vec3; 3]) -> Self {
+        let normals = normals.map(|normal| normal.normalize());
+        let usable = normals
+            .iter()
+            .all(|normal| normal.iter().all(|x| x.is_finite()));
+        self.vertex_normals = usable.then_some(normals);
+        self
+    }
+
+    pub fn vertex_normals(&self) -> Option<&[Vec3; 3]> {
+        self.vertex_normals.as_ref()
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    /// Sets the fraction of the triangle's size below which its intersection test treats things
+    /// as zero, [`DEGENERATE_TRIANGLE_RATIO`] by default. Rays closer to edge-on than this are
+    /// missed, as are hits this close to the ray's origin. Raising it hides sparkle on meshes
+    /// with nearly degenerate triangles, and lowering it keeps grazing hits on ones that lose
+    /// them.
+    pub fn with_epsilon(mut self, epsilon: Float) -> Self {
+        self.set_epsilon(epsilon);
+        self
+    }
+
+    fn set_epsilon(&mut self, epsilon: Float) {
+        self.epsilon = epsilon;
+        (self.min_det_squared, self.min_distance) =
+            triangle_tolerances(self.a, self.b, self.c, epsilon);
+    }
+
+    pub fn epsilon(&self) -> Float {
+        self.epsilon
+    }
+
+    /// Makes rays hit the triangle from behind as well as from the front, where they see its
+    /// normal turned toward them. By default only glass is, see [`double_sided_by_default`].
+    pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+
+    pub fn is_double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    /// Reverses the winding, which turns the triangle to face the other way
+    pub fn flip(&mut self) {
+        std::mem::swap(&mut self.b, &mut self.c);
+        std::mem::swap(&mut self.uv_b, &mut self.uv_c);
+        if let Some(normals) = &mut self.vertex_normals {
+            normals.swap(1, 2);
+        }
+        self.normal = -self.normal;
+    }
+
+    /// Unit normal of the triangle's front, the only side it can be hit from unless it's double
+    /// sided
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    pub fn area(&self) -> Float {
+        (self.b - self.a).cross(&(self.c - self.a)).norm() / 2.0
+    }
+
+    /// Maps `(s, t)` in the unit square to a point on the triangle, evenly by area, and returns
+    /// it with its normal and texture coordinates
+    pub fn surface_point(&self, s: Float, t: Float) -> (Point3, Vec3, Vec2) {
+        let root = s.sqrt();
+        let (u, v) = (root * (1.0 - t), root * t);
+        let point = self.a + (self.b - self.a) * u + (self.c - self.a) * v;
+        (
+            point,
+            self.normal,
+            uv_at(&[self.uv_a, self.uv_b, self.uv_c], u, v),
+        )
+    }
+
+    /// The `(s, t)` that [`Triangle::surface_point`] maps to `point` on the triangle, found from
+    /// its barycentric coordinates rather than by undoing that mapping, so points it spreads
+    /// unevenly come out uneven
+    pub fn surface_coordinates(&self, point: &Point3) -> (Float, Float) {
+        let (ab, ac, ap) = (self.b - self.a, self.c - self.a, point - self.a);
+        let (d00, d01, d11) = (ab.dot(&ab), ab.dot(&ac), ac.dot(&ac));
+        let (d20, d21) = (ap.dot(&ab), ap.dot(&ac));
+        let denominator = d00 * d11 - d01 * d01;
+        if denominator <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let u = (d11 * d20 - d01 * d21) / denominator;
+        let v = (d00 * d21 - d01 * d20) / denominator;
+        // Area grows with the square of the distance from `a`, and evenly across it
+        let across = u + v;
+        let t = if across > 0.0 { v / across } else { 0.0 };
+        (across * across, t)
+    }
+
+    /// Whether `point` lies on the triangle, give or take `tolerance`
+    pub fn contains(&self, point: &Point3, tolerance: Float) -> bool {
+        if (point - self.a).dot(&self.normal).abs() > tolerance {
+            return false;
+        }
+        // Barycentric coordinates, with the tolerance scaled to each edge's length
+        let (ab, ac, ap) = (self.b - self.a, self.c - self.a, point - self.a);
+        let (d00, d01, d11) = (ab.dot(&ab), ab.dot(&ac), ac.dot(&ac));
+        let (d20, d21) = (ap.dot(&ab), ap.dot(&ac));
+        let denominator = d00 * d11 - d01 * d01;
+        if denominator <= 0.0 {
+            return false;
+        }
+        let u = (d11 * d20 - d01 * d21) / denominator;
+        let v = (d00 * d21 - d01 * d20) / denominator;
+        let slack = tolerance / ab.norm().min(ac.norm()).max(Float::MIN_POSITIVE);
+        u >= -slack && v >= -slack && u + v <= 1.0 + slack
     }
 }
 
@@ -262,9 +1460,13 @@ pub struct Sphere {
     radius: Float,
     /// To determine the rotation of the sphere (for textures)
     front_direction: Vec3,
+    /// How textures are wrapped around the sphere
+    pub uv_mode: UvMode,
     pub material: Arc<Material>,
     /// For use in the BVH
     node_index: usize,
+    /// The scene object this sphere belongs to
+    pub object: ObjectId,
 }
 
 impl Sphere {
@@ -275,7 +1477,9 @@ impl Sphere {
             radius: radius.max(0.0),
             material,
             node_index: 0,
+            object: ObjectId::default(),
             front_direction: Vec3::x_axis().into_inner(),
+            uv_mode: UvMode::default(),
         }
     }
 
@@ -291,8 +1495,117 @@ impl Sphere {
             radius: radius.max(0.0),
             material,
             node_index: 0,
+            object: ObjectId::default(),
             front_direction: front_face,
+            uv_mode: UvMode::default(),
+        }
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn with_uv_mode(mut self, uv_mode: UvMode) -> Self {
+        self.uv_mode = uv_mode;
+        self
+    }
+
+    /// Like [`Sphere::new`], but fails on a center or radius that isn't finite or a radius that
+    /// isn't positive instead of clamping it to an invisible point
+    pub fn try_new(
+        center: Vec3,
+        radius: Float,
+        material: Arc<Material>,
+    ) -> Result<Self, ShapeError> {
+        // Unclamped, so the error has the radius that was asked for
+        let sphere = Sphere {
+            radius,
+            ..Self::new(center, radius, material)
+        };
+        sphere.validate()?;
+        Ok(sphere)
+    }
+
+    /// Checks that the center and radius are finite and the radius is positive
+    pub fn validate(&self) -> Result<(), ShapeError> {
+        check_finite(&self.center, "sphere center")?;
+        if !self.radius.is_finite() {
+            return Err(ShapeError::NotFinite("sphere radius"));
+        }
+        if self.radius <= 0.0 {
+            return Err(ShapeError::NonPositiveRadius(self.radius));
         }
+        Ok(())
+    }
+
+    pub fn center(&self) -> Point3 {
+        self.center
+    }
+
+    pub fn radius(&self) -> Float {
+        self.radius
+    }
+
+    pub fn area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    /// Maps `(s, t)` in the unit square to a point on the sphere, evenly by area, and returns it
+    /// with its outward normal and texture coordinates
+    pub fn surface_point(&self, s: Float, t: Float) -> (Point3, Vec3, Vec2) {
+        let z = 1.0 - 2.0 * s;
+        let ring = (1.0 - z * z).max(0.0).sqrt();
+        let phi = TAU * t;
+        let normal = Vec3::new(ring * phi.cos(), ring * phi.sin(), z);
+        let uv = self
+            .uv_mode
+            .uv(facing_rotation(self.front_direction) * normal);
+        (self.center + normal * self.radius, normal, uv)
+    }
+
+    /// The `(s, t)` that [`Sphere::surface_point`] maps to `point` on the sphere: the height
+    /// and the angle around it, which are both proportional to area
+    pub fn surface_coordinates(&self, point: &Point3) -> (Float, Float) {
+        let normal = (point - self.center).normalize();
+        let s = (1.0 - normal.z.clamp(-1.0, 1.0)) / 2.0;
+        let t = normal.y.atan2(normal.x).rem_euclid(TAU) / TAU;
+        (s, t)
+    }
+
+    /// Whether `point` lies on the sphere, give or take `tolerance`
+    pub fn contains(&self, point: &Point3, tolerance: Float) -> bool {
+        ((point - self.center).norm() - self.radius).abs() <= tolerance
+    }
+
+    /// How far the point at the unit `normal` moves per unit of u and of v. Found by differencing
+    /// the UV mapping, so it works for every mode and facing. Zero where the mapping has no
+    /// derivative, like at the poles of spherical UVs.
+    fn uv_tangents(&self, normal: &Vec3) -> (Vec3, Vec3) {
+        const STEP: Float = 1e-5;
+        let rotation = facing_rotation(self.front_direction);
+        let uv = |direction: Vec3| self.uv_mode.uv(rotation * direction.normalize());
+        let across = if normal.x.abs() < 0.9 {
+            Vec3::x()
+        } else {
+            Vec3::y()
+        };
+        let t1 = normal.cross(&across).normalize();
+        let t2 = normal.cross(&t1);
+        // Change in UV per unit of distance along each tangent, wrapped across seams
+        let derivative = |tangent: Vec3| {
+            let change = uv(normal + tangent * STEP) - uv(normal - tangent * STEP);
+            change.map(|c| c - c.round()) / (2.0 * STEP * self.radius)
+        };
+        let (d1, d2) = (derivative(t1), derivative(t2));
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < Float::EPSILON || !det.is_finite() {
+            return (Vec3::zeros(), Vec3::zeros());
+        }
+        // Inverts the Jacobian of the UVs over the tangent plane
+        let dpdu = (t1 * d2.y - t2 * d1.y) / det;
+        let dpdv = (t2 * d1.x - t1 * d2.x) / det;
+        (dpdu, dpdv)
     }
 }
 
@@ -316,7 +1629,7 @@ impl BHShape<Float, 3> for Sphere {
 }
 
 impl Hit for Sphere {
-    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection> {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
         let oc = self.center - ray.origin.coords;
         let a = ray.direction.norm_squared();
         let h = ray.direction.dot(&oc);
@@ -338,29 +1651,37 @@ impl Hit for Sphere {
         }
 
         let point_on_sphere = ray.at(t);
-        let mut normal = (point_on_sphere - self.center) / self.radius;
-        let is_front_face = Intersection::is_front_face(ray, &normal);
-        if !is_front_face {
-            // return None;
-            normal = -normal; // Set the normal to always face outward
-        }
-
-        let uv = unit_sphere_uv_facing(normal, self.front_direction);
+        let outward = (point_on_sphere - self.center) / self.radius;
 
-        if uv.x.is_nan() || uv.y.is_nan() {
-            // TODO: figure out how to avoid this
-            // println!("bang");
-            return None; // NaN occurs sometimes with glancing blows on the sphere
-        }
+        // Texture coordinates belong to the point on the surface, so they're found from the
+        // outward normal whichever side the ray came from
+        let uv = self
+            .uv_mode
+            .uv(facing_rotation(self.front_direction) * outward);
 
-        Some(Intersection::new(
-            point_on_sphere,
-            normal,
-            t,
-            &self.material,
-            is_front_face,
-            uv,
-        ))
+        // Only worked out for bump maps, since it takes four more UV lookups
+        let (dpdu, dpdv) = if self.material.has_bump() {
+            self.uv_tangents(&outward)
+        } else {
+            (Vec3::zeros(), Vec3::zeros())
+        };
+
+        let is_front_face = Intersection::is_front_face(ray, &outward);
+        // Faces the side the ray came from
+        let normal = if is_front_face { outward } else { -outward };
+
+        Some(
+            Intersection::new(
+                point_on_sphere,
+                normal,
+                t,
+                &self.material,
+                is_front_face,
+                uv,
+            )
+            .with_object(self.object)
+            .with_tangents(dpdu, dpdv),
+        )
     }
 }
 
@@ -387,125 +1708,520 @@ pub fn unit_sphere_uv(
     Vec2::new(u, v)
 }
 
-/// Returns the `(u, v)` coordinates of an `intersection_point` on the unit sphere centered at the
-/// origin with the texture facing toward `face_dir`
-fn unit_sphere_uv_facing(intersection_point: Point3, face_dir: Vec3) -> Vec2 {
+/// Returns the rotation that turns a sphere's texture to face toward `face_dir`, taking points on
+/// the sphere into the frame its [`UvMode`] lays out
+fn facing_rotation(face_dir: Vec3) -> nalgebra::Rotation3<Float> {
     let pitch = face_dir
         .z
         .atan2((face_dir.y * face_dir.y + face_dir.x * face_dir.x).sqrt());
     let yaw = face_dir.y.atan2(face_dir.x);
 
-    let rotation = 0.0;
-    unit_sphere_uv(intersection_point, pitch, yaw, rotation)
+    nalgebra::Rotation3::from_euler_angles(0.0, pitch, 0.0)
+        * nalgebra::Rotation3::from_euler_angles(0.0, 0.0, -yaw)
 }
 
-fn to_unit_spherical(point: Point3) -> (Float, Float) {
-    let theta = (-point.z).acos(); // acos is slow as balls
-    let phi = Float::atan2(point.y, point.x) + PI;
-    (theta, phi)
+impl Hit for Triangle {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        let (dist, u, v) = intersect_triangle(
+            ray,
+            range,
+            [&self.a, &self.b, &self.c],
+            self.min_det_squared,
+            self.min_distance,
+            self.double_sided,
+        )?;
+        let surface = TriangleSurface {
+            corners: [self.a, self.b, self.c],
+            uvs: [self.uv_a, self.uv_b, self.uv_c],
+            normal: self.normal,
+            vertex_normals: self.vertex_normals,
+            material: &self.material,
+            object: self.object,
+        };
+        Some(surface.intersection(ray, dist, u, v))
+    }
 }
 
-impl Hit for Triangle {
-    // https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-    // This is adapted from `intersects_triangle` in the BVH crate
-    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection> {
-        let a_to_b = self.b - self.a;
-        let a_to_c = self.c - self.a;
+/// Finds where `ray` crosses the triangle with `corners` a, b and c from its front, or from
+/// either side if it's `double_sided`, within `range`, see [`Triangle::with_epsilon`] for the
+/// tolerances. They hold for a ray direction of any length. Returns the distance along the ray,
+/// and how far the hit is toward b (u) and toward c (v).
+///
+/// The test is watertight: which side of an edge a ray passes is worked out from the edge's two
+/// corners alone, so triangles sharing the edge always agree on it, and rays right through an
+/// edge or corner hit only one of the triangles sharing it, see [`owns_seam_hit`].
+// Woop, Benthin and Wald, "Watertight Ray/Triangle Intersection", JCGT 2013
+pub(crate) fn intersect_triangle(
+    ray: &Ray,
+    range: &Range<Float>,
+    [a, b, c]: [&Point3; 3],
+    min_det_squared: Float,
+    min_distance: Float,
+    double_sided: bool,
+) -> Option<(Float, Float, Float)> {
+    let shear = RayShear::new(ray);
+    let [a, b, c] = [a, b, c].map(|corner| shear.apply(&(corner - ray.origin.coords)));
+
+    // Twice the signed areas, seen down the ray, of the triangles the ray makes with each edge.
+    // Each is the weight of the corner across from its edge, times the determinant.
+    let across_a = edge_function(&c, &b);
+    let across_b = edge_function(&a, &c);
+    let across_c = edge_function(&b, &a);
+    let weights = [across_a, across_b, across_c];
+    if weights.iter().any(|&w| w < 0.0) && weights.iter().any(|&w| w > 0.0) {
+        return None;
+    }
 
-        // Begin calculating determinant - also used to calculate u parameter
-        // u_vec lies in view plane
-        // length of a_to_c in view_plane = |u_vec| = |a_to_c|*sin(a_to_c, dir)
-        let u_vec = ray.direction.cross(&a_to_c);
+    // A negative determinant means the ray comes from behind, which single-sided triangles
+    // cull. Scaled by the direction's major axis it's the parallelepiped volume of the
+    // direction and the edges, so the threshold is relative to the triangle's edges and works
+    // the same at any scale, and it grows with the direction's length.
+    let det = across_a + across_b + across_c;
+    let volume = det * shear.major;
+    let direction_length_squared = ray.direction.norm_squared();
+    if (det <= 0.0 && !double_sided) || volume * volume < min_det_squared * direction_length_squared
+    {
+        return None;
+    }
 
-        // If determinant is near zero, ray lies in plane of triangle
-        // The determinant corresponds to the parallelepiped volume:
-        // det = 0 => [dir, a_to_b, a_to_c] not linearly independant
-        let det = a_to_b.dot(&u_vec);
+    let on_seam = [across_b == 0.0, across_c == 0.0, across_a == 0.0];
+    if on_seam.contains(&true) && !owns_seam_hit(&shear, [&a, &b, &c], det, on_seam) {
+        return None;
+    }
 
-        // Only testing positive bound, thus enabling backface culling
-        // If backface culling is not desired write:
-        // det < EPSILON && det > -EPSILON
-        if det < Float::EPSILON {
-            // TODO: add flag for backface culling on triangles
-            return None;
+    let inv_det = 1.0 / det;
+    let dist = (across_a * a.z + across_b * b.z + across_c * c.z) * shear.z_scale * inv_det;
+    if !range.contains(&dist) {
+        return None;
+    }
+
+    let (u, v) = (across_b * inv_det, across_c * inv_det);
+    (dist * dist * direction_length_squared > min_distance * min_distance).then_some((dist, u, v))
+}
+
+/// Shears space so a ray runs straight down its major axis from the origin, which then lies in
+/// the z slot, with the other two axes ordered so triangles keep their winding
+struct RayShear {
+    axes: [usize; 3],
+    /// How much x and y shift per unit of z
+    slope: [Float; 2],
+    /// The direction's major component, signed
+    major: Float,
+    /// What turns a sheared z into a distance along the ray
+    z_scale: Float,
+}
+
+impl RayShear {
+    fn new(ray: &Ray) -> Self {
+        let direction = &ray.direction;
+        let z = direction.iamax();
+        let (mut x, mut y) = ((z + 1) % 3, (z + 2) % 3);
+        if direction[z] < 0.0 {
+            std::mem::swap(&mut x, &mut y);
         }
+        let major = direction[z];
+        RayShear {
+            axes: [x, y, z],
+            slope: [direction[x] / major, direction[y] / major],
+            major: major.abs(),
+            z_scale: 1.0 / major,
+        }
+    }
 
-        let inv_det = 1.0 / det;
+    /// Where `offset`, relative to the ray's origin, ends up, with z left unsheared
+    fn apply(&self, offset: &Vec3) -> Vec3 {
+        let [x, y, z] = self.axes;
+        Vec3::new(
+            offset[x] - self.slope[0] * offset[z],
+            offset[y] - self.slope[1] * offset[z],
+            offset[z],
+        )
+    }
+}
 
-        // Vector from point a to ray origin
-        let a_to_origin = ray.origin - self.a;
+/// The sheared 2D cross product of the corners at the ends of an edge. Swapping the corners
+/// flips its sign exactly, so triangles sharing the edge agree on which side the ray passes.
+fn edge_function(from: &Vec3, to: &Vec3) -> Float {
+    from.x * to.y - from.y * to.x
+}
 
-        // Calculate u parameter
-        let u = a_to_origin.coords.dot(&u_vec) * inv_det;
+/// How much [`edge_function`] changes when the hit point moves by `nudge`, already sheared,
+/// exactly negated for the swapped corners like the function itself
+fn edge_function_change(from: &Vec3, to: &Vec3, nudge: &Vec3) -> Float {
+    nudge.x * (from.y - to.y) - nudge.y * (from.x - to.x)
+}
 
-        // Test bounds: u < 0 || u > 1 => outside of triangle
-        if !(0.0..=1.0).contains(&u) {
-            return None;
+/// Directions a hit right on a triangle's edge or corner is pretended to be nudged in, the
+/// second for edges the first runs along. Any two directions that don't line up with a scene's
+/// edges work.
+const SEAM_NUDGES: [[Float; 3]; 2] = [[0.31, 0.73, 0.61], [-0.53, 0.17, 0.83]];
+
+/// Whether a triangle with sheared `corners` a ray hits right on the edges or corner in
+/// `on_seam` (where u, v and 1 - u - v are 0) takes the hit, out of all the triangles sharing
+/// them. The hit goes to the one the point would be inside of if it were nudged a little in a
+/// fixed direction, which is exactly one of them when they cover the seam from all sides,
+/// however they're wound.
+fn owns_seam_hit(shear: &RayShear, [a, b, c]: [&Vec3; 3], det: Float, on_seam: [bool; 3]) -> bool {
+    for nudge in SEAM_NUDGES.map(Vec3::from) {
+        let nudge = shear.apply(&nudge);
+        let changes = [
+            edge_function_change(a, c, &nudge),
+            edge_function_change(b, a, &nudge),
+            edge_function_change(c, b, &nudge),
+        ];
+        let moved = on_seam.iter().zip(changes).filter(|(&on, _)| on);
+        if moved.clone().any(|(_, change)| change == 0.0) {
+            // The nudge runs along one of the edges, so it can't tell which side it's on
+            continue;
         }
+        return moved.into_iter().all(|(_, change)| change * det > 0.0);
+    }
+    false
+}
 
-        // Prepare to test v parameter
-        let v_vec = a_to_origin.coords.cross(&a_to_b);
+/// What shading a hit on a triangle takes, from a [`Triangle`] or a face of a [`Mesh`]
+pub(crate) struct TriangleSurface<'a> {
+    pub corners: [Point3; 3],
+    pub uvs: [Vec2; 3],
+    /// Unit normal of the triangle's front
+    pub normal: Vec3,
+    pub vertex_normals: Option<[Vec3; 3]>,
+    pub material: &'a Material,
+    pub object: ObjectId,
+}
 
-        // Calculate v parameter and test bound
-        let v = ray.direction.dot(&v_vec) * inv_det;
-        // The intersection lies outside of the triangle
-        if v < 0.0 || u + v > 1.0 {
-            return None;
-        }
+impl<'a> TriangleSurface<'a> {
+    /// The hit `dist` along `ray`, `u` of the way toward the second corner and `v` toward the
+    /// third, as found by [`intersect_triangle`]
+    pub fn intersection(&self, ray: &Ray, dist: Float, u: Float, v: Float) -> Intersection<'a> {
+        // TODO: verify this all. Much is handwaved and halfassed and untested
+        let intersection_point = ray.origin.coords + ray.direction * dist;
+        // Decided by the face normal even when shading smoothly, so which side a dielectric
+        // is entered from doesn't change across the triangle
+        let is_front_face = ray.direction.dot(&self.normal) <= 0.0;
+        // Double-sided triangles hit from behind face the ray, like spheres hit from inside
+        let normal = if is_front_face {
+            self.normal
+        } else {
+            -self.normal
+        };
+
+        // Interpolate the UV coordinates at the hit point
+        // let uv_no_map = Vec2::new(u, v);
+        let uv_hit = uv_at(&self.uvs, u, v);
+
+        // Texture coordinates run across the bounds of the corners' UVs, toward b in u and
+        // c in v, see `uv_at`. Only worked out for bump maps, like on spheres.
+        let (dpdu, dpdv) = if self.material.has_bump() {
+            let [a, b, c] = &self.corners;
+            let (uv_width, uv_height) = uv_extent(&self.uvs);
+            ((b - a) / uv_width, (c - a) / uv_height)
+        } else {
+            (Vec3::zeros(), Vec3::zeros())
+        };
+
+        Intersection::new(
+            intersection_point,
+            shading_normal(&normal, self.vertex_normals.as_ref(), u, v),
+            dist,
+            self.material,
+            is_front_face,
+            uv_hit,
+        )
+        .with_object(self.object)
+        .with_tangents(dpdu, dpdv)
+    }
+}
+
+/// Settings for loading meshes from files
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LoadOptions {
+    /// Fixes inconsistent winding with [`repair_orientation`], listing what it found in the
+    /// load report
+    pub repair_orientation: bool,
+    /// Overrides the [`Triangle::with_epsilon`] of every triangle, for meshes that lose grazing
+    /// hits or sparkle with the default
+    pub triangle_epsilon: Option<Float>,
+    /// Gives every node that uses a glTF mesh a copy of its triangles instead of an [`Instance`]
+    /// sharing them, e.g. to see how much memory instancing saves
+    pub copy_shared_meshes: bool,
+    /// Ignores the normals in the file and shades every triangle flat
+    pub flat_shading: bool,
+    /// Checks every mesh with [`analyze_mesh`], listing the ones that aren't watertight in the
+    /// load report
+    pub analyze: bool,
+    /// Analyzes every mesh and shades the glass on ones that aren't watertight as thin panes,
+    /// see [`Dielectric::thin`](crate::material::Dielectric::thin)
+    pub thin_open_glass: bool,
+    /// Makes every triangle double sided, see [`Triangle::with_double_sided`], for meshes with
+    /// faces that are seen from behind
+    pub double_sided: bool,
+}
 
-        let dist = a_to_c.dot(&v_vec) * inv_det;
-        if !range.contains(&dist) {
-            return None;
+impl LoadOptions {
+    /// Gives `mesh` the epsilon and sidedness asked for, and repairs and analyzes it if asked to,
+    /// recording the results under `name` if anything was wrong. Only repairs and analyses go
+    /// over whole [`Triangle`]s, so the mesh is only split up and welded back for those.
+    fn apply(&self, mut mesh: Mesh, name: &str, report: &mut LoadReport) -> Vec<Mesh> {
+        if let Some(epsilon) = self.triangle_epsilon {
+            mesh = mesh.with_epsilon(epsilon);
+        }
+        if self.double_sided {
+            mesh = mesh.with_double_sided(true);
         }
+        if !(self.repair_orientation || self.analyze || self.thin_open_glass) {
+            return vec![mesh];
+        }
+        let mut triangles: Vec<Triangle> = mesh.triangles().collect();
+        drop(mesh);
+        if self.repair_orientation {
+            let repair = repair_orientation(&mut triangles);
+            if repair.flipped > 0
+                || repair.open_components > 0
+                || repair.non_orientable_components > 0
+            {
+                report.mesh_repairs.push((name.to_string(), repair));
+            }
+        }
+        if self.analyze || self.thin_open_glass {
+            let mut analysis = analyze_mesh(&triangles, SELF_INTERSECTION_SAMPLES);
+            if !analysis.is_watertight() {
+                if self.thin_open_glass {
+                    analysis.thinned_glass = thin_glass(&mut triangles);
+                }
+                report.mesh_analyses.push((name.to_string(), analysis));
+            }
+        }
+        Mesh::from_triangles(&triangles)
+    }
+}
 
-        if dist > Float::EPSILON {
-            // TODO: verify this all. Much is handwaved and halfassed and untested
-            let intersection_point = ray.origin.coords + ray.direction * dist;
-            let is_front_face = ray.direction.dot(&self.normal) <= 0.0;
+/// How much geometry was shared between instances instead of copied
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstancingReport {
+    /// Meshes stored once and shared
+    pub shared_meshes: usize,
+    /// Placements of the shared meshes
+    pub instances: usize,
+    /// Triangles that would have been copies
+    pub triangles_saved: usize,
+}
 
-            // Interpolate the UV coordinates at the hit point
-            // let uv_no_map = Vec2::new(u, v);
+impl InstancingReport {
+    /// Records a mesh of `triangles` shared by `instances` placements
+    pub fn record(&mut self, triangles: usize, instances: usize) {
+        self.shared_meshes += 1;
+        self.instances += instances;
+        self.triangles_saved += triangles * instances.saturating_sub(1);
+    }
+
+    pub fn merge(&mut self, other: &InstancingReport) {
+        self.shared_meshes += other.shared_meshes;
+        self.instances += other.instances;
+        self.triangles_saved += other.triangles_saved;
+    }
+
+    /// Memory the copies would have taken in the world's shapes
+    pub fn bytes_saved(&self) -> usize {
+        self.triangles_saved * std::mem::size_of::<Shape>()
+    }
+}
 
-            let left = self.uv_a.x.min(self.uv_b.x).min(self.uv_c.x);
-            let right = self.uv_a.x.max(self.uv_b.x).max(self.uv_c.x);
+impl fmt::Display for InstancingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} instance(s) of {} shared mesh(es) saved copying {} triangles ({:.1} MB)",
+            self.instances,
+            self.shared_meshes,
+            self.triangles_saved,
+            self.bytes_saved() as f64 / 1e6
+        )
+    }
+}
 
-            let bot = self.uv_a.y.min(self.uv_b.y).min(self.uv_c.y);
-            let top = self.uv_a.y.max(self.uv_b.y).max(self.uv_c.y);
+/// What [`repair_orientation`] found and changed in a mesh
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepairReport {
+    pub triangles: usize,
+    pub flipped: usize,
+    /// Groups of triangles connected through shared edges
+    pub components: usize,
+    /// Components with edges that don't have exactly two triangles, so they have no inside and
+    /// were only oriented on a best-effort basis
+    pub open_components: usize,
+    /// Components like a Möbius strip, which can't be wound consistently at all
+    pub non_orientable_components: usize,
+}
 
-            let width = right - left;
-            let height = top - bot;
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "flipped {} of {} triangles in {} component(s)",
+            self.flipped, self.triangles, self.components
+        )?;
+        if self.open_components > 0 {
+            write!(
+                f,
+                ", {} open (oriented on a best-effort basis)",
+                self.open_components
+            )?;
+        }
+        if self.non_orientable_components > 0 {
+            write!(f, ", {} non-orientable", self.non_orientable_components)?;
+        }
+        Ok(())
+    }
+}
 
-            let u_mapped = left + width * u;
-            let v_mapped = bot + height * v;
+/// Identifies a vertex by its exact position, for matching up the corners of triangles that
+/// loaders store separately
+pub(crate) fn position_key(point: &Point3) -> [u64; 3] {
+    // Adding zero turns -0.0 into 0.0 so both get the same key
+    [point.x, point.y, point.z].map(|c| (c + 0.0).to_bits())
+}
 
-            let uv_hit = Vec2::new(u_mapped, v_mapped);
+/// Makes neighboring triangles agree on their winding and turns closed parts of the mesh to face
+/// outward. Backface culling needs this, since imported meshes often mix clockwise and
+/// counterclockwise triangles. Triangles are connected through edges whose ends are at exactly
+/// the same positions. Open and non-orientable parts keep the winding most of their area had.
+pub fn repair_orientation(triangles: &mut [Triangle]) -> RepairReport {
+    // Loaders repeat vertices per triangle, so they're matched up by position
+    let mut vertex_ids: HashMap<[u64; 3], usize> = HashMap::new();
+    let mut vertex_id = |point: &Point3| {
+        let next = vertex_ids.len();
+        *vertex_ids.entry(position_key(point)).or_insert(next)
+    };
+    let corners: Vec<[usize; 3]> = triangles
+        .iter()
+        .map(|tri| [vertex_id(&tri.a), vertex_id(&tri.b), vertex_id(&tri.c)])
+        .collect();
+
+    // The triangles on each edge, and whether they run along it from its lower vertex id
+    let mut edges: HashMap<(usize, usize), Vec<(usize, bool)>> = HashMap::new();
+    for (tri, corners) in corners.iter().enumerate() {
+        for k in 0..3 {
+            let (from, to) = (corners[k], corners[(k + 1) % 3]);
+            if from != to {
+                edges
+                    .entry((from.min(to), from.max(to)))
+                    .or_default()
+                    .push((tri, from < to));
+            }
+        }
+    }
+    // Each triangle's neighbors, and whether they run along the shared edge in the same
+    // direction, which means one of the two is wound the wrong way
+    let mut neighbors: Vec<Vec<(usize, bool)>> = vec![Vec::new(); triangles.len()];
+    let mut on_open_edge = vec![false; triangles.len()];
+    for uses in edges.values() {
+        if uses.len() != 2 {
+            for &(tri, _) in uses {
+                on_open_edge[tri] = true;
+            }
+        }
+        for (i, &(tri, forward)) in uses.iter().enumerate() {
+            for &(other, other_forward) in &uses[i + 1..] {
+                if tri != other {
+                    neighbors[tri].push((other, forward == other_forward));
+                    neighbors[other].push((tri, forward == other_forward));
+                }
+            }
+        }
+    }
 
-            Some(Intersection::new(
-                intersection_point,
-                self.normal,
-                dist,
-                &self.material,
-                is_front_face,
-                uv_hit,
-            ))
+    let mut report = RepairReport {
+        triangles: triangles.len(),
+        ..RepairReport::default()
+    };
+    let mut flips: Vec<Option<bool>> = vec![None; triangles.len()];
+    for start in 0..triangles.len() {
+        if flips[start].is_some() {
+            continue;
+        }
+        // Flood fills the component, flipping each triangle to agree with the one it's reached
+        // from. Every triangle is only visited once, so conflicts are counted instead of fixed.
+        report.components += 1;
+        flips[start] = Some(false);
+        let mut component = vec![start];
+        let mut stack = vec![start];
+        let mut consistent = true;
+        let mut closed = true;
+        while let Some(tri) = stack.pop() {
+            closed &= !on_open_edge[tri];
+            let flipped = flips[tri] == Some(true);
+            for &(other, same_direction) in &neighbors[tri] {
+                let wanted = flipped != same_direction;
+                match flips[other] {
+                    Some(other_flipped) => consistent &= other_flipped == wanted,
+                    None => {
+                        flips[other] = Some(wanted);
+                        component.push(other);
+                        stack.push(other);
+                    }
+                }
+            }
+        }
+        report.open_components += usize::from(!closed);
+        report.non_orientable_components += usize::from(!consistent);
+
+        let sign = |tri: usize| if flips[tri] == Some(true) { -1.0 } else { 1.0 };
+        let turn_over = if closed && consistent {
+            // Closed and consistent, so it encloses a volume that's negative if it faces inward
+            let origin = triangles[start].a;
+            let signed_volume: Float = component
+                .iter()
+                .map(|&tri| {
+                    let Triangle { a, b, c, .. } = &triangles[tri];
+                    sign(tri) * (a - origin).dot(&(b - origin).cross(&(c - origin)))
+                })
+                .sum();
+            signed_volume < 0.0
         } else {
-            None
+            // Keeps whichever winding covers more of the surface already
+            let area = |tri: &Triangle| (tri.b - tri.a).cross(&(tri.c - tri.a)).norm();
+            let kept_area: Float = component
+                .iter()
+                .map(|&tri| sign(tri) * area(&triangles[tri]))
+                .sum();
+            kept_area < 0.0
+        };
+        if turn_over {
+            for &tri in &component {
+                flips[tri] = flips[tri].map(|flipped| !flipped);
+            }
         }
     }
+
+    for (tri, flipped) in triangles.iter_mut().zip(flips) {
+        if flipped == Some(true) {
+            tri.flip();
+            report.flipped += 1;
+        }
+    }
+    report
 }
 
+/// Loads every model in an OBJ file as its own mesh. With `centered`, each model is moved so the
+/// mean of its vertices is at the origin, before `transform` places it. Each model is built
+/// straight from the file's buffers into a [`Mesh`], and only split into [`Triangle`]s if the
+/// load options repair or analyze it.
 pub fn load_obj(
     file_path: &str,
     mesh_material: Arc<Material>,
     transform: Option<Matrix4<Float>>,
     centered: bool,
-) -> Vec<Vec<Triangle>> {
+    load_options: &LoadOptions,
+) -> Result<(Vec<Mesh>, LoadReport), String> {
     let options = GPU_LOAD_OPTIONS;
 
     let (models, _materials) =
-        tobj::load_obj(file_path, &options).expect("Failed to OBJ load file");
+        tobj::load_obj(file_path, &options).map_err(|err| err.to_string())?;
 
-    let mut models_triangled = Vec::new();
+    let mut meshes = Vec::new();
+    let mut report = LoadReport::default();
 
     for model in models {
         let positions: Vec<Point3> = model
@@ -514,51 +2230,352 @@ pub fn load_obj(
             .par_chunks_exact(3)
             .map(|v| Point3::new(Float::from(v[0]), Float::from(v[1]), Float::from(v[2])))
             .collect();
+        // Indexed like the positions, since the load options ask for a single index
+        let normals: Vec<Vec3> = model
+            .mesh
+            .normals
+            .par_chunks_exact(3)
+            .map(|n| Vec3::new(Float::from(n[0]), Float::from(n[1]), Float::from(n[2])))
+            .collect();
+        let smooth = !load_options.flat_shading && normals.len() == positions.len();
+
+        let object = ObjectId::register(&model.name);
+
+        // Built where the file has them, then centered and placed
+        let mut rejects = RejectReport::default();
+        let mut mesh = Mesh::new_checked(
+            positions,
+            Vec::new(),
+            &model.mesh.indices,
+            mesh_material.clone(),
+            &mut rejects,
+        )
+        .with_object(object);
+        if smooth {
+            mesh = mesh.with_vertex_normals(normals);
+        }
+        report.record_rejects(&model.name, rejects);
 
-        let default = Matrix4::identity();
+        let mut placement = Matrix4::identity();
+        if centered && !mesh.positions().is_empty() {
+            let mean = mesh.positions().iter().sum::<Point3>() / mesh.positions().len() as Float;
+            placement = Matrix4::new_translation(&-mean);
+        }
+        if let Some(transform) = &transform {
+            placement = transform * placement;
+        }
+        for mesh in load_options.apply(mesh, &model.name, &mut report) {
+            if mesh.triangle_count() == 0 {
+                continue;
+            }
+            meshes.push(if placement == Matrix4::identity() {
+                mesh
+            } else {
+                mesh.transform(&placement)
+            });
+        }
+    }
 
-        let mut sum_pos = Vec3::zeros();
-        let triangles: Vec<Triangle> = model
-            .mesh
-            .indices
-            .chunks_exact(3)
-            .map(|idx| {
-                let a = positions[idx[0] as usize];
-                let b = positions[idx[1] as usize];
-                let c = positions[idx[2] as usize];
+    Ok((meshes, report))
+}
 
-                sum_pos += a + b + c;
+/// Fails if a face in the mesh `name` uses a vertex past the `count` the mesh has, which only a
+/// broken file does
+fn check_indices(name: &str, indices: &[u32], count: usize) -> Result<(), String> {
+    match indices.iter().find(|&&index| index as usize >= count) {
+        Some(index) => Err(format!(
+            "mesh '{}' uses vertex {} but only has {}",
+            name, index, count
+        )),
+        None => Ok(()),
+    }
+}
 
-                Triangle::new(a, b, c, mesh_material.clone())
-                    .transform(&transform.unwrap_or(default))
-            })
-            .collect();
+/// Meshes loaded from a file, placed where the file puts them
+#[derive(Default)]
+pub struct LoadedMeshes {
+    /// Meshes with triangles of their own, already in place
+    pub meshes: Vec<Mesh>,
+    /// Placements of meshes shared by several nodes, which all use one copy of the triangles
+    pub instances: Vec<Instance>,
+}
 
-        // TODO: centering doesn't work at all for some reason
-        if centered {
-            let mean_pos = sum_pos / (model.mesh.positions.len() / 3) as Float;
-            println!("mean_pos: {}", mean_pos);
-            let centered_tris: Vec<Triangle> =
-                triangles.iter().map(|tri| tri.shift(-mean_pos)).collect();
-            let new_center = centered_tris
+impl LoadedMeshes {
+    /// Moves everything by `transform`, e.g. to fit the whole file into a scene
+    pub fn place(self, transform: &Similarity3<Float>) -> Self {
+        let matrix = transform.to_homogeneous();
+        LoadedMeshes {
+            meshes: self
+                .meshes
                 .iter()
-                .fold(Vec3::zeros(), |sum, v| sum + v.a + v.b + v.c);
-            println!("new center: {} (not divided)", new_center);
-            models_triangled.push(centered_tris);
-        } else {
-            models_triangled.push(triangles);
+                .map(|mesh| mesh.transform(&matrix))
+                .collect(),
+            instances: self
+                .instances
+                .into_iter()
+                .map(|instance| {
+                    Instance::new(
+                        instance.prototype().clone(),
+                        transform * instance.transform(),
+                    )
+                    .with_object(instance.object)
+                })
+                .collect(),
         }
     }
 
-    models_triangled
+    /// Meshes that glow are split back into triangles, since only those are sampled as lights
+    pub fn into_shapes(self) -> Vec<Shape> {
+        let mut shapes = Vec::new();
+        for mesh in self.meshes {
+            if mesh.material.is_emissive() {
+                shapes.extend(mesh.triangles().map(Shape::from));
+            } else {
+                shapes.push(mesh.into());
+            }
+        }
+        shapes.extend(self.instances.into_iter().map(Shape::from));
+        shapes
+    }
 }
 
-pub fn load_gltf(file_path: &str, _mesh_material: Arc<Material>) -> Vec<Vec<Triangle>> {
-    let (gltf, buffers, images) = gltf::import(file_path)
-        .unwrap_or_else(|_| panic!("gltf loader failed to read {}", file_path));
-    let mut meshes = Vec::new();
+/// Finds meshes that are copies of each other moved somewhere else, as in OBJ files and
+/// flattened scenes that repeat geometry instead of referencing it, and keeps one copy of each
+/// for [`Instance`]s of it to share. Copies have to match triangle for triangle in the same
+/// order with the same uvs, normals and material, so this only catches meshes duplicated by
+/// translation.
+pub fn share_duplicates(meshes: Vec<Vec<Triangle>>) -> (LoadedMeshes, InstancingReport) {
+    let mut groups: HashMap<Vec<i64>, Vec<usize>> = HashMap::new();
+    for (i, mesh) in meshes.iter().enumerate() {
+        if let Some(key) = duplicate_key(mesh) {
+            groups.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut report = InstancingReport::default();
+    let mut loaded = LoadedMeshes::default();
+    let mut shared = vec![false; meshes.len()];
+    for copies in groups.values().filter(|copies| copies.len() > 1) {
+        let first = &meshes[copies[0]];
+        let origin = first[0].a;
+        let to_origin = Matrix4::new_translation(&-origin);
+        let at_origin: Vec<Triangle> = first.iter().map(|tri| tri.transform(&to_origin)).collect();
+        let prototype = Arc::new(Prototype::new(
+            Mesh::from_triangles(&at_origin)
+                .into_iter()
+                .map(Shape::from)
+                .collect(),
+        ));
+        for &copy in copies {
+            let mesh = &meshes[copy];
+            let translation = Translation3::from(mesh[0].a);
+            let placement = Similarity3::from_parts(translation, UnitQuaternion::identity(), 1.0);
+            loaded
+                .instances
+                .push(Instance::new(prototype.clone(), placement).with_object(mesh[0].object));
+            shared[copy] = true;
+        }
+        report.record(first.len(), copies.len());
+    }
+    loaded.meshes = meshes
+        .iter()
+        .zip(shared)
+        .filter(|(_, shared)| !shared)
+        .flat_map(|(mesh, _)| Mesh::from_triangles(mesh))
+        .collect();
+    (loaded, report)
+}
+
+/// Identifies `mesh` up to where it is, from its vertices relative to its first one rounded to
+/// a millionth of its size, or `None` if it's empty
+fn duplicate_key(mesh: &[Triangle]) -> Option<Vec<i64>> {
+    let origin = mesh.first()?.a;
+    let extent = mesh
+        .iter()
+        .flat_map(|tri| [tri.a, tri.b, tri.c])
+        .map(|point| (point - origin).amax())
+        .fold(Float::MIN_POSITIVE, Float::max);
+    // A power of two, so copies whose sizes differ by rounding still round the same way
+    let step = (2.0 as Float).powi(extent.log2().ceil() as i32 - 20);
+    let round = |value: Float| (value / step).round() as i64;
+    let mut key = Vec::with_capacity(mesh.len() * 16);
+    for tri in mesh {
+        key.push(Arc::as_ptr(&tri.material) as i64);
+        for point in [tri.a, tri.b, tri.c] {
+            key.extend((point - origin).iter().map(|&x| round(x)));
+        }
+        for uv in [tri.uv_a, tri.uv_b, tri.uv_c] {
+            key.extend(uv.iter().map(|&x| (x * 1e6).round() as i64));
+        }
+        for normal in tri.vertex_normals.iter().flatten() {
+            key.extend(normal.iter().map(|&x| (x * 1e6).round() as i64));
+        }
+    }
+    Some(key)
+}
+
+/// Where a node puts a glTF mesh
+#[derive(Clone)]
+struct Placement {
+    /// Path of the node, e.g. "scene/chassis/wheel_FL"
+    path: String,
+    /// From the mesh's space to the world's, through every node above this one
+    matrix: Matrix4<Float>,
+    /// The same transform, if it only rotates, scales uniformly and moves, which is all an
+    /// [`Instance`] can do
+    similarity: Option<Similarity3<Float>>,
+}
+
+/// glTF is Y-up and the world is Z-up. Files exported from Z-up tools usually undo this in their
+/// root node, so their meshes come out the way they were modeled.
+fn gltf_to_world() -> Similarity3<Float> {
+    Similarity3::from_parts(
+        Translation3::identity(),
+        UnitQuaternion::from_axis_angle(&Vec3::x_axis(), FRAC_PI_2),
+        1.0,
+    )
+}
+
+/// Returns `node`'s transform relative to its parent, and the same as a similarity if it is one
+fn node_transform(node: &gltf::Node) -> (Matrix4<Float>, Option<Similarity3<Float>>) {
+    let matrix = Matrix4::from(node.transform().matrix()).cast::<Float>();
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+    let (smallest, largest) = (
+        scale.iter().copied().fold(f32::INFINITY, f32::min),
+        scale.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+    );
+    let uniform = smallest > 0.0 && largest - smallest <= largest * 1e-5;
+    let similarity = uniform.then(|| {
+        Similarity3::from_parts(
+            Vector3::from(translation).cast::<Float>().into(),
+            UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z).cast::<Float>()),
+            Float::from(largest),
+        )
+    });
+    (matrix, similarity)
+}
+
+/// Every node in every scene of `gltf`, with where it is in the world
+fn placed_nodes(gltf: &gltf::Gltf) -> Vec<(gltf::Node<'_>, Placement)> {
+    let mut placed = Vec::new();
+    for scene in gltf.scenes() {
+        let scene_name = scene.name().unwrap_or("scene").to_string();
+        let root = gltf_to_world();
+        // Each node with where its parent is
+        let mut stack: Vec<(gltf::Node, Placement)> = scene
+            .nodes()
+            .map(|node| {
+                let parent = Placement {
+                    path: scene_name.clone(),
+                    matrix: root.to_homogeneous(),
+                    similarity: Some(root),
+                };
+                (node, parent)
+            })
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            let node_name = node
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("node_{}", node.index()));
+            let (matrix, similarity) = node_transform(&node);
+            let placement = Placement {
+                path: format!("{}/{}", parent.path, node_name),
+                matrix: parent.matrix * matrix,
+                similarity: parent.similarity.zip(similarity).map(|(p, s)| p * s),
+            };
+            stack.extend(node.children().map(|child| (child, placement.clone())));
+            placed.push((node, placement));
+        }
+    }
+    placed
+}
+
+/// Every node in every scene of `gltf`, with its path, like "scene/rig/camera", and the transform
+/// from its space to the world's
+pub(crate) fn gltf_node_transforms(
+    gltf: &gltf::Gltf,
+) -> Vec<(gltf::Node<'_>, String, Matrix4<Float>)> {
+    placed_nodes(gltf)
+        .into_iter()
+        .map(|(node, placement)| (node, placement.path, placement.matrix))
+        .collect()
+}
+
+/// Loads every mesh in a glTF file, placed by the nodes that use it. A mesh used by more than one
+/// node is loaded once and shared by an [`Instance`] per node, unless `load_options` asks for
+/// copies or a node scales it unevenly. Each texture image is decoded once and shared by every
+/// primitive using it, which the returned report counts. Textures that fail to load are
+/// replaced with the placeholder texture and listed in the report instead of failing the
+/// whole load, but a file or buffer that can't be read fails it.
+pub fn load_gltf(
+    file_path: &str,
+    _mesh_material: Arc<Material>,
+    load_options: &LoadOptions,
+) -> Result<(LoadedMeshes, LoadReport), String> {
+    let gltf = gltf::Gltf::open(file_path).map_err(|err| err.to_string())?;
+    let base = Path::new(file_path).parent();
+    let buffers = gltf::import_buffers(&gltf, base, gltf.blob.clone())
+        .map_err(|err| format!("its buffers can't be read: {}", err))?;
+    // Only base color textures are used. Each is decoded once however many primitives share it,
+    // all of them at the same time, and on its own so a bad one only affects the materials
+    // that use it.
+    let images: Vec<gltf::Image> = gltf.images().collect();
+    let base_colors: BTreeSet<usize> = gltf
+        .materials()
+        .filter_map(|material| material.pbr_metallic_roughness().base_color_texture())
+        .map(|info| info.texture().source().index())
+        .collect();
+    let textures: HashMap<usize, Result<Arc<Image>, String>> = base_colors
+        .into_par_iter()
+        .map(|index| {
+            let decoded = gltf::image::Data::from_source(images[index].source(), base, &buffers)
+                .map_err(|err| err.to_string())
+                .and_then(|data| {
+                    let format = format!("{:?}", data.format);
+                    let hash = ContentHash::of_pixels(
+                        data.width as usize,
+                        data.height as usize,
+                        &format,
+                        &data.pixels,
+                    );
+                    // Shared with every other file and scene using the same image
+                    TextureCache::global().get_or_insert_with(hash, || Image::try_from(&data))
+                });
+            (index, decoded)
+        })
+        .collect();
+    let mut references: HashMap<usize, usize> = HashMap::new();
+    let mut report = LoadReport::default();
+    let mut loaded = LoadedMeshes::default();
+
+    let mut placements: Vec<Vec<Placement>> = gltf.meshes().map(|_| Vec::new()).collect();
+    for (node, placement) in placed_nodes(&gltf) {
+        if let Some(mesh) = node.mesh() {
+            placements[mesh.index()].push(placement);
+        }
+    }
 
     for mesh in gltf.meshes() {
+        let mut placements = std::mem::take(&mut placements[mesh.index()]);
+        if placements.is_empty() {
+            // Not used by any node, so it goes where it was modeled
+            placements.push(Placement {
+                path: mesh
+                    .name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("mesh_{}", mesh.index())),
+                matrix: gltf_to_world().to_homogeneous(),
+                similarity: Some(gltf_to_world()),
+            });
+        }
+        // One name per node, and the triangles are named after the first node that uses them
+        let objects: Vec<ObjectId> = placements
+            .iter()
+            .map(|placement| ObjectId::register(&placement.path))
+            .collect();
+        let object = objects[0];
+        let mut primitives = Vec::new();
         // Note: gltf only supports triangles, which is why I only handle tris
         for triangle in mesh.primitives() {
             let reader = triangle.reader(|buffer| Some(&buffers[buffer.index()]));
@@ -568,66 +2585,1090 @@ pub fn load_gltf(file_path: &str, _mesh_material: Arc<Material>) -> Vec<Vec<Tria
             let mut texture_image = None;
 
             if let Some(texture_info) = material.pbr_metallic_roughness().base_color_texture() {
-                let texture = texture_info.texture();
-                let source = texture.source();
-                let image = &images[source.index()];
-                let im: crate::camera::Image = image.into();
-
-                texture_image = Some(im); // Replace `Image::from` with your image handling method
+                let source = texture_info.texture().source();
+                let decoded = textures[&source.index()].clone();
+                if decoded.is_ok() {
+                    *references.entry(source.index()).or_default() += 1;
+                }
+                texture_image = Some(decoded.unwrap_or_else(|reason| {
+                    let image_name = match (source.name(), source.source()) {
+                        (Some(name), _) => name.to_string(),
+                        (None, gltf::image::Source::Uri { uri, .. })
+                            if !uri.starts_with("data:") =>
+                        {
+                            uri.to_string()
+                        }
+                        _ => format!("image {}", source.index()),
+                    };
+                    report.record_texture_failure(TextureLoadFailure {
+                        source: format!("{} ({})", file_path, image_name),
+                        reason,
+                        material: material.name().map(str::to_string),
+                    });
+                    ImageTexture::placeholder().image
+                }));
             }
 
+            let gltf_double_sided = material.double_sided();
             let mesh_material = Arc::new(Material::from_gltf(material, texture_image));
+            // Glass is double sided either way
+            let double_sided = gltf_double_sided || double_sided_by_default(&mesh_material);
 
             if let (Some(indices), Some(positions)) =
                 (reader.read_indices(), reader.read_positions())
             {
-                let indices: Vec<u32> = indices.into_u32().collect(); // Convert indices to u32
-                let positions: Vec<[f32; 3]> = positions.collect(); // Collect positions
-                                                                    //
-                let tex_coords: Vec<[f32; 2]> = reader
+                let indices: Vec<u32> = indices.into_u32().collect();
+                let positions: Vec<Point3> = positions
+                    .map(|[x, y, z]| Point3::new(Float::from(x), Float::from(y), Float::from(z)))
+                    .collect();
+                check_indices(&object.name(), &indices, positions.len())?;
+                // Meshes without texture coordinates get each triangle's default ones
+                let tex_coords: Vec<Vec2> = reader
                     .read_tex_coords(0)
-                    .map(|coords| coords.into_f32().collect())
-                    .expect("no tex coords"); // Read texture coordinates
-
-                let tris: Vec<Triangle> = indices
-                    .par_chunks_exact(3)
-                    .map(|tri_indices| {
-                        let points: Vec<Point3> = tri_indices
-                            .iter()
-                            .map(|&index| {
-                                let pos = positions[index as usize]; // Get position by index
-                                Point3::new(
-                                    Float::from(pos[0]),
-                                    Float::from(pos[1]),
-                                    Float::from(pos[2]),
-                                )
-                            })
-                            .collect();
-
-                        let uvs: Vec<Vec2> = tri_indices
-                            .iter()
-                            .map(|&idx| {
-                                let uv = tex_coords[idx as usize];
-                                Vec2::new(uv[0] as Float, uv[1] as Float)
+                    .map(|coords| {
+                        coords
+                            .into_f32()
+                            .map(|[u, v]| Vec2::new(Float::from(u), Float::from(v)))
+                            .collect()
+                    })
+                    .filter(|coords: &Vec<Vec2>| coords.len() == positions.len())
+                    .unwrap_or_default();
+                let normals: Option<Vec<Vec3>> = reader
+                    .read_normals()
+                    .filter(|_| !load_options.flat_shading)
+                    .map(|normals| {
+                        normals
+                            .map(|[x, y, z]| {
+                                Vec3::new(Float::from(x), Float::from(y), Float::from(z))
                             })
-                            .collect();
-
-                        // TODO: make this use new instead of new_with_uv when None
-                        // though tbh it doesn't actually matter since textures shouldn't be used for a mesh with no texture map defined
-                        Triangle::new_with_uv(
-                            points[0],
-                            points[1],
-                            points[2],
-                            uvs[0],
-                            uvs[1],
-                            uvs[2],
-                            mesh_material.clone(),
-                        )
+                            .collect()
                     })
-                    .collect();
-                meshes.push(tris)
+                    .filter(|normals: &Vec<Vec3>| normals.len() == positions.len());
+
+                let mut rejects = RejectReport::default();
+                let mut mesh =
+                    Mesh::new_checked(positions, tex_coords, &indices, mesh_material, &mut rejects)
+                        .with_object(object)
+                        .with_double_sided(double_sided);
+                if let Some(normals) = normals {
+                    mesh = mesh.with_vertex_normals(normals);
+                }
+                report.record_rejects(&object.name(), rejects);
+                primitives.extend(
+                    load_options
+                        .apply(mesh, &object.name(), &mut report)
+                        .into_iter()
+                        .filter(|mesh| mesh.triangle_count() > 0),
+                );
+            }
+        }
+
+        let shared = placements.len() > 1
+            && !load_options.copy_shared_meshes
+            && placements
+                .iter()
+                .all(|placement| placement.similarity.is_some());
+        if shared {
+            let triangles = primitives.iter().map(Mesh::triangle_count).sum();
+            let shapes = primitives.into_iter().map(Shape::from).collect();
+            let prototype = Arc::new(Prototype::new(shapes));
+            report.instancing.record(triangles, placements.len());
+            for (placement, object) in placements.into_iter().zip(objects) {
+                let similarity = placement.similarity.expect("checked above");
+                loaded
+                    .instances
+                    .push(Instance::new(prototype.clone(), similarity).with_object(object));
+            }
+        } else {
+            for (placement, &object) in placements.iter().zip(&objects) {
+                for mesh in &primitives {
+                    loaded
+                        .meshes
+                        .push(mesh.transform(&placement.matrix).with_object(object));
+                }
+            }
+        }
+    }
+    for (index, count) in references {
+        if let Ok(image) = &textures[&index] {
+            report.textures.record(image, count);
+        }
+    }
+    Ok((loaded, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, scratch::scratch, texture::TextureEnum};
+
+    /// How many of `triangles` `ray` hits, each with the tolerances triangles get by default
+    fn hits(triangles: &[[Point3; 3]], ray: &Ray, double_sided: bool) -> usize {
+        triangles
+            .iter()
+            .filter(|[a, b, c]| {
+                let (min_det_squared, min_distance) =
+                    triangle_tolerances(*a, *b, *c, DEGENERATE_TRIANGLE_RATIO);
+                intersect_triangle(
+                    ray,
+                    &(0.0..Float::MAX),
+                    [a, b, c],
+                    min_det_squared,
+                    min_distance,
+                    double_sided,
+                )
+                .is_some()
+            })
+            .count()
+    }
+
+    /// A unit square on the ground facing up, split into four triangles around its center
+    fn fan() -> Vec<[Point3; 3]> {
+        let center = Vec3::new(0.5, 0.5, 0.0);
+        let corners = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        (0..4)
+            .map(|i| [center, corners[i], corners[(i + 1) % 4]])
+            .collect()
+    }
+
+    /// Rays from above through `point`, straight down and at a slant
+    fn rays_through(point: Point3) -> Vec<Ray> {
+        [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.25, -0.5, -1.0),
+            Vec3::new(-0.5, -0.25, -2.0),
+        ]
+        .into_iter()
+        .map(|direction| Ray::new((point - direction).into(), direction))
+        .collect()
+    }
+
+    /// Writes a glTF file with `meshes` meshes of the same triangle, which lies flat on the ground
+    /// once loaded, used by the scene's root `nodes`. Returns its path.
+    fn write_gltf(name: &str, nodes: &str, meshes: usize) -> String {
+        let directory = scratch(name);
+        write_gltf_in(
+            &directory,
+            name,
+            nodes,
+            &vec![0; meshes],
+            r#"{"doubleSided": true}"#,
+            "",
+        )
+    }
+
+    /// Like [`write_gltf`], into `directory`, with a mesh for each of `mesh_materials` using that
+    /// material out of `materials`, and `extra` top-level entries such as images
+    fn write_gltf_in(
+        directory: &Path,
+        name: &str,
+        nodes: &str,
+        mesh_materials: &[usize],
+        materials: &str,
+        extra: &str,
+    ) -> String {
+        let mut buffer: Vec<u8> = Vec::new();
+        for value in [-3.0f32, 0.0, -1.0, -1.0, 0.0, -1.0, -2.0, 0.0, 1.0] {
+            buffer.extend(value.to_le_bytes());
+        }
+        for value in [0.0f32, 0.0, 1.0, 0.0, 0.5, 1.0] {
+            buffer.extend(value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2, 0] {
+            buffer.extend(index.to_le_bytes());
+        }
+        std::fs::write(directory.join("parts.bin"), &buffer).unwrap();
+        let meshes: Vec<String> = mesh_materials
+            .iter()
+            .map(|material| {
+                format!(
+                    r#"{{"primitives": [{{"attributes": {{"POSITION": 0, "TEXCOORD_0": 1}},
+                        "indices": 2, "material": {material}}}]}}"#
+                )
+            })
+            .collect();
+        let meshes = meshes.join(", ");
+        let gltf = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [{nodes}],
+                "meshes": [{meshes}],
+                "materials": [{materials}],{extra}
+                "buffers": [{{"uri": "parts.bin", "byteLength": {length}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 24}},
+                    {{"buffer": 0, "byteOffset": 60, "byteLength": 6}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [-3, 0, -1], "max": [-1, 0, 1]}},
+                    {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2"}},
+                    {{"bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+                ]
+            }}"#,
+            length = buffer.len(),
+        );
+        let path = directory.join(format!("{}.gltf", name));
+        std::fs::write(&path, gltf).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Writes a glTF file with a triangle on a node called "chassis" and another on its child
+    /// "wheel_FL", four units along x. Returns its path.
+    fn write_two_node_gltf(name: &str) -> String {
+        let nodes = r#"
+            {"name": "chassis", "mesh": 0, "children": [1]},
+            {"name": "wheel_FL", "mesh": 1, "translation": [4, 0, 0]}
+        "#;
+        write_gltf(name, nodes, 2)
+    }
+
+    /// Writes a glTF file with one triangle used by four nodes under "row", four units apart
+    /// along x, the last twice the size, and with `uneven` scaling the third along y. Returns
+    /// its path.
+    fn write_four_node_gltf(name: &str, uneven: bool) -> String {
+        let third_scale = if uneven { "[1, 2, 1]" } else { "[1, 1, 1]" };
+        let nodes = format!(
+            r#"
+            {{"name": "row", "children": [1, 2, 3, 4]}},
+            {{"name": "a", "mesh": 0}},
+            {{"name": "b", "mesh": 0, "translation": [4, 0, 0]}},
+            {{"name": "c", "mesh": 0, "translation": [8, 0, 0], "scale": {third_scale}}},
+            {{"name": "d", "mesh": 0, "translation": [20, 0, 0], "scale": [2, 2, 2]}}
+            "#
+        );
+        write_gltf(name, &nodes, 1)
+    }
+
+    /// Loads the file at `path` with `options` and deletes its directory
+    fn load_and_remove(path: &str, options: &LoadOptions) -> (LoadedMeshes, LoadReport) {
+        let loaded = load_gltf(path, Arc::new(lambertian(0.5)), options).unwrap();
+        std::fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+        loaded
+    }
+
+    /// The node each ray straight down onto the four-node file's triangles hits, in order
+    fn four_node_hits(loaded: LoadedMeshes) -> Vec<String> {
+        let shapes = loaded
+            .meshes
+            .into_iter()
+            .map(Shape::from)
+            .chain(loaded.instances.into_iter().map(Shape::from))
+            .collect();
+        let world = World::build(shapes);
+        [-2.0, 2.0, 6.0, 16.0]
+            .into_iter()
+            .map(|x| {
+                let ray = Ray::new(Vec3::new(x, 0.3, 5.0).into(), -Vec3::z());
+                let hit = world.hit(&ray, &(0.0..Float::INFINITY)).unwrap();
+                hit.object.name().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nodes_sharing_a_gltf_mesh_share_one_copy_of_it() {
+        let path = write_four_node_gltf("shared", false);
+        let (loaded, report) = load_and_remove(&path, &LoadOptions::default());
+        assert!(loaded.meshes.is_empty());
+        assert_eq!(loaded.instances.len(), 4);
+        let prototype = loaded.instances[0].prototype();
+        assert_eq!(prototype.primitive_count(), 1);
+        assert!(loaded
+            .instances
+            .iter()
+            .all(|instance| Arc::ptr_eq(instance.prototype(), prototype)));
+        assert_eq!(
+            report.instancing,
+            InstancingReport {
+                shared_meshes: 1,
+                instances: 4,
+                triangles_saved: 3,
+            }
+        );
+        let nodes = ["scene/row/a", "scene/row/b", "scene/row/c", "scene/row/d"];
+        assert_eq!(four_node_hits(loaded), nodes);
+
+        // Asking for copies, or scaling one placement unevenly, gives each node its own
+        for (uneven, copy_shared_meshes) in [(false, true), (true, false)] {
+            let path = write_four_node_gltf("copied", uneven);
+            let options = LoadOptions {
+                copy_shared_meshes,
+                ..LoadOptions::default()
+            };
+            let (loaded, report) = load_and_remove(&path, &options);
+            assert!(loaded.instances.is_empty());
+            assert_eq!(loaded.meshes.len(), 4);
+            assert_eq!(report.instancing, InstancingReport::default());
+            assert_eq!(four_node_hits(loaded), nodes);
+        }
+    }
+
+    #[test]
+    fn gltf_meshes_without_texture_coordinates_load() {
+        // A node name of its own, since object names are shared with tests running alongside
+        let path = write_gltf("no_uvs", r#"{"name": "bare", "mesh": 0}"#, 1);
+        let gltf = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, gltf.replace(r#", "TEXCOORD_0": 1"#, "")).unwrap();
+        let (loaded, report) = load_and_remove(&path, &LoadOptions::default());
+        assert_eq!(loaded.meshes.len(), 1);
+        assert!(report.texture_failures.is_empty());
+        let world = World::build(loaded.meshes.into_iter().map(Shape::from).collect());
+        let ray = Ray::new(Vec3::new(-2.0, 0.3, 5.0).into(), -Vec3::z());
+        let hit = world.hit(&ray, &(0.0..Float::INFINITY)).unwrap();
+        assert!(
+            hit.uv.iter().all(|c| (0.0..=1.0).contains(c)),
+            "{:?}",
+            hit.uv
+        );
+    }
+
+    #[test]
+    fn broken_gltf_files_fail_to_load() {
+        let path = write_gltf("bad_indices", r#"{"name": "broken", "mesh": 0}"#, 1);
+        let parts = Path::new(&path).with_file_name("parts.bin");
+        let mut buffer = std::fs::read(&parts).unwrap();
+        // The last corner points past the three vertices
+        buffer[64..66].copy_from_slice(&7u16.to_le_bytes());
+        std::fs::write(&parts, &buffer).unwrap();
+        let indices = load_gltf(&path, Arc::new(lambertian(0.5)), &LoadOptions::default());
+        std::fs::write(&path, "{ not json").unwrap();
+        let syntax = load_gltf(&path, Arc::new(lambertian(0.5)), &LoadOptions::default());
+        std::fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        assert_eq!(
+            indices.err().unwrap(),
+            "mesh 'scene/broken' uses vertex 7 but only has 3"
+        );
+        assert!(syntax.is_err());
+    }
+
+    #[test]
+    fn gltf_textures_are_decoded_once_and_shared_by_every_material_using_them() {
+        let directory = scratch("shared_textures");
+        // Colors no other test's images have, so the global cache can't mix them up
+        for (file, color) in [("bricks.png", 0.123), ("tiles.png", 0.456)] {
+            let image = Image {
+                pixels: vec![Vec3::repeat(color); 4],
+                width: 2,
+                height: 2,
+                gamma: 1.0,
+                metadata: Vec::new(),
+                alpha: None,
+            };
+            std::fs::write(directory.join(file), image.encode_png().unwrap()).unwrap();
+        }
+        // Three materials using three textures, two of which show the same image, on six meshes
+        let nodes = r#"
+            {"name": "wall", "children": [1, 2, 3, 4, 5, 6]},
+            {"mesh": 0}, {"mesh": 1}, {"mesh": 2}, {"mesh": 3}, {"mesh": 4}, {"mesh": 5}
+        "#;
+        let material = |texture: usize| {
+            format!(
+                r#"{{"pbrMetallicRoughness": {{"baseColorTexture": {{"index": {texture}}},
+                    "metallicFactor": 0}}}}"#
+            )
+        };
+        let materials = [material(0), material(1), material(2)].join(", ");
+        let textures = r#"
+            "images": [{"uri": "bricks.png"}, {"uri": "tiles.png"}],
+            "textures": [{"source": 0}, {"source": 1}, {"source": 0}],"#;
+        let path = write_gltf_in(
+            &directory,
+            "shared_textures",
+            nodes,
+            &[0, 1, 2, 0, 1, 2],
+            &materials,
+            textures,
+        );
+        let (loaded, report) = load_and_remove(&path, &LoadOptions::default());
+        assert!(report.texture_failures.is_empty());
+        assert_eq!(report.textures.decoded, 2);
+        assert_eq!(report.textures.references, 6);
+        assert_eq!(report.textures.pixels_saved, 4 * 4);
+
+        let images: Vec<Arc<Image>> = loaded
+            .meshes
+            .iter()
+            .map(|mesh| match &*mesh.material {
+                Material::Lambertian(Lambertian {
+                    texture: TextureEnum::ImageTexture(texture),
+                    ..
+                }) => texture.image.clone(),
+                other => panic!("expected a textured Lambertian, got {:?}", other.name()),
+            })
+            .collect();
+        assert_eq!(images.len(), 6);
+        // Meshes are in node order, using materials 0, 1, 2, 0, 1, 2, and materials 0 and 2
+        // show the same image
+        for (a, b) in [(0, 2), (0, 3), (0, 5), (1, 4)] {
+            assert!(Arc::ptr_eq(&images[a], &images[b]), "{} and {}", a, b);
+        }
+        assert!(!Arc::ptr_eq(&images[0], &images[1]));
+    }
+
+    #[test]
+    fn hits_name_the_gltf_node_they_land_on() {
+        let path = write_two_node_gltf("names");
+        let (loaded, _) =
+            load_gltf(&path, Arc::new(lambertian(0.5)), &LoadOptions::default()).unwrap();
+        std::fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        let shapes = loaded
+            .meshes
+            .into_iter()
+            .map(Shape::from)
+            .chain(loaded.instances.into_iter().map(Shape::from))
+            .collect();
+        let world = World::build(shapes);
+        for (x, name) in [(-2.0, "scene/chassis"), (2.0, "scene/chassis/wheel_FL")] {
+            let ray = Ray::new(Vec3::new(x, 0.3, 5.0).into(), -Vec3::z());
+            let hit = world.hit(&ray, &(0.0..Float::INFINITY)).unwrap();
+            assert_eq!(&*hit.object.name(), name);
+            // Named once per node, however many triangles it has
+            assert_eq!(ObjectId::all_named(name).len(), 1);
+        }
+        assert!(world
+            .hit(
+                &Ray::new(Vec3::new(0.0, 0.3, 5.0).into(), -Vec3::z()),
+                &(0.0..Float::INFINITY)
+            )
+            .is_none());
+    }
+
+    fn lambertian(albedo: Float) -> Material {
+        crate::material::Lambertian::new(
+            crate::texture::SolidColor::new(Vec3::repeat(albedo)).into(),
+        )
+        .into()
+    }
+
+    /// A double-sided square of `material` two units above the origin, facing down
+    fn overhead_square(material: Material) -> Vec<Shape> {
+        let material = Arc::new(material);
+        let corners = [
+            Vec3::new(-1.0, -1.0, 2.0),
+            Vec3::new(1.0, -1.0, 2.0),
+            Vec3::new(1.0, 1.0, 2.0),
+            Vec3::new(-1.0, 1.0, 2.0),
+        ];
+        [[0, 1, 2], [0, 2, 3]]
+            .map(|[a, b, c]| {
+                Triangle::new(corners[a], corners[b], corners[c], material.clone())
+                    .with_double_sided(true)
+                    .into()
+            })
+            .into()
+    }
+
+    /// How much light comes down to a point near the origin from straight up
+    fn transmittance_from_above(shapes: Vec<Shape>) -> Vec3 {
+        let world = World::build(shapes);
+        let ray = Ray::new(Vec3::new(0.3, -0.2, 0.0).into(), Vec3::z());
+        world.transmittance(&ray, &(1e-6..Float::INFINITY))
+    }
+
+    #[test]
+    fn half_covered_surfaces_pass_half_the_light() {
+        let mask = crate::material::AlphaMask::new(
+            lambertian(0.5),
+            crate::texture::SolidColor::new(Vec3::repeat(0.5)).into(),
+        );
+        let transmittance = transmittance_from_above(overhead_square(mask.into()));
+        assert!(
+            (transmittance - Vec3::repeat(0.5)).norm() < 1e-9,
+            "{:?}",
+            transmittance
+        );
+    }
+
+    #[test]
+    fn opaque_surfaces_block_the_light() {
+        let transmittance = transmittance_from_above(overhead_square(lambertian(0.5)));
+        assert_eq!(transmittance, Vec3::zeros());
+    }
+
+    #[test]
+    fn glass_passes_what_both_of_its_surfaces_transmit() {
+        let glass = |material: crate::material::Dielectric| -> Vec<Shape> {
+            vec![Sphere::new(Vec3::new(0.3, -0.2, 3.0), 1.0, Arc::new(material.into())).into()]
+        };
+        // Head on, each surface reflects Schlick's r0 of 0.04 for an index of 1.5
+        let clear = transmittance_from_above(glass(crate::material::Dielectric::new(1.5)));
+        assert!(
+            (clear - Vec3::repeat(0.96 * 0.96)).norm() < 1e-9,
+            "{:?}",
+            clear
+        );
+
+        let tint = Vec3::new(1.0, 0.5, 0.25);
+        let tinted = transmittance_from_above(glass(crate::material::Dielectric::new_tinted(
+            1.5,
+            crate::texture::SolidColor::new(tint).into(),
+        )));
+        let expected = tint.component_mul(&tint) * 0.96 * 0.96;
+        assert!((tinted - expected).norm() < 1e-9, "{:?}", tinted);
+    }
+
+    #[test]
+    fn transmittance_multiplies_through_every_surface() {
+        let mask = |coverage: Float| -> Material {
+            crate::material::AlphaMask::new(
+                lambertian(0.5),
+                crate::texture::SolidColor::new(Vec3::repeat(coverage)).into(),
+            )
+            .into()
+        };
+        let mut shapes = overhead_square(mask(0.5));
+        let mut higher = overhead_square(mask(0.75));
+        for shape in &mut higher {
+            if let Shape::Triangle(triangle) = shape {
+                *triangle = triangle.shift(Vec3::z());
+            }
+        }
+        shapes.extend(higher);
+        let transmittance = transmittance_from_above(shapes);
+        assert!(
+            (transmittance - Vec3::repeat(0.5 * 0.25)).norm() < 1e-9,
+            "{:?}",
+            transmittance
+        );
+    }
+
+    /// The twelve triangles of a cube two units wide around the origin, wound to face outward
+    fn cube() -> Vec<Triangle> {
+        let material = Arc::new(lambertian(0.5));
+        let mut triangles = Vec::new();
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut normal = Vec3::zeros();
+                normal[axis] = sign;
+                let mut u = Vec3::zeros();
+                u[(axis + 1) % 3] = 1.0;
+                let v = normal.cross(&u);
+                let corner = |i: Float, j: Float| normal + u * i + v * j;
+                let [a, b, c, d] = [
+                    corner(-1.0, -1.0),
+                    corner(1.0, -1.0),
+                    corner(1.0, 1.0),
+                    corner(-1.0, 1.0),
+                ];
+                triangles.push(Triangle::new(a, b, c, material.clone()));
+                triangles.push(Triangle::new(a, c, d, material.clone()));
+            }
+        }
+        triangles
+    }
+
+    fn faces_outward(triangle: &Triangle) -> bool {
+        let centroid = (triangle.a + triangle.b + triangle.c) / 3.0;
+        triangle.normal().dot(&centroid) > 0.0
+    }
+
+    #[test]
+    fn half_reversed_cubes_come_out_facing_outward() {
+        let mut triangles = cube();
+        assert!(triangles.iter().all(faces_outward));
+        for triangle in triangles.iter_mut().step_by(2) {
+            triangle.flip();
+        }
+        let report = repair_orientation(&mut triangles);
+        assert!(triangles.iter().all(faces_outward));
+        assert_eq!(report.flipped, 6);
+        assert_eq!(report.components, 1);
+        assert_eq!(report.open_components, 0);
+        assert_eq!(report.non_orientable_components, 0);
+    }
+
+    #[test]
+    fn inside_out_cubes_are_turned_over() {
+        let mut triangles = cube();
+        for triangle in &mut triangles {
+            triangle.flip();
+        }
+        let report = repair_orientation(&mut triangles);
+        assert!(triangles.iter().all(faces_outward));
+        assert_eq!(report.flipped, 12);
+    }
+
+    #[test]
+    fn open_surfaces_are_made_consistent_and_reported() {
+        // Two opposite faces of the cube, which don't touch, each with one triangle reversed
+        let mut triangles = cube();
+        triangles.truncate(4);
+        triangles[1].flip();
+        triangles[2].flip();
+        let report = repair_orientation(&mut triangles);
+        assert_eq!(faces_outward(&triangles[0]), faces_outward(&triangles[1]));
+        assert_eq!(faces_outward(&triangles[2]), faces_outward(&triangles[3]));
+        assert_eq!(report.components, 2);
+        assert_eq!(report.open_components, 2);
+        assert_eq!(report.non_orientable_components, 0);
+    }
+
+    #[test]
+    fn mobius_strips_are_reported_as_non_orientable() {
+        let material = Arc::new(lambertian(0.5));
+        let segments = 24;
+        // Pairs of points across the strip, which turns half a turn on its way around
+        let rungs: Vec<[Point3; 2]> = (0..segments)
+            .map(|i| {
+                let angle = i as Float / segments as Float * std::f64::consts::TAU;
+                let center = Vec3::new(angle.cos(), angle.sin(), 0.0) * 2.0;
+                let across = Vec3::new(angle.cos(), angle.sin(), 0.0) * (angle / 2.0).cos()
+                    + Vec3::z() * (angle / 2.0).sin();
+                [center - across * 0.3, center + across * 0.3]
+            })
+            .collect();
+        let mut triangles = Vec::new();
+        for i in 0..segments {
+            let [a, b] = rungs[i];
+            // The last rung meets the first one upside down
+            let [c, d] = if i + 1 == segments {
+                let [first, second] = rungs[0];
+                [second, first]
+            } else {
+                rungs[i + 1]
+            };
+            triangles.push(Triangle::new(a, c, d, material.clone()));
+            triangles.push(Triangle::new(a, d, b, material.clone()));
+        }
+        let report = repair_orientation(&mut triangles);
+        assert_eq!(report.components, 1);
+        assert_eq!(report.non_orientable_components, 1);
+        assert_eq!(report.open_components, 1);
+    }
+
+    #[test]
+    fn rays_through_shared_edges_hit_one_triangle() {
+        let quad = [
+            [
+                Vec3::zeros(),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+            ],
+            [
+                Vec3::zeros(),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+        ];
+        for t in [0.125, 0.25, 0.5, 0.75] {
+            for ray in rays_through(Vec3::new(t, t, 0.0)) {
+                assert_eq!(hits(&quad, &ray, false), 1, "{:?}", ray);
+            }
+        }
+    }
+
+    #[test]
+    fn rays_at_an_irregular_edge_never_slip_through() {
+        use rand::Rng;
+        // A skewed quad off the axes, so points along its diagonal don't land on exact values
+        let corners = [
+            Vec3::new(0.1, -0.3, 0.7),
+            Vec3::new(2.3, 0.2, 1.1),
+            Vec3::new(1.9, 1.7, -0.4),
+            Vec3::new(-0.2, 1.3, 0.3),
+        ];
+        let quad = [
+            [corners[0], corners[1], corners[2]],
+            [corners[0], corners[2], corners[3]],
+        ];
+        let normal = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+        let mut rng = crate::rng::SampleRng::seeded(1, 0, 0, 0);
+        for _ in 0..10_000 {
+            let point = corners[0].lerp(&corners[2], rng.gen_range(0.01..0.99));
+            let origin = point + normal + Vec3::new(rng.gen(), rng.gen(), rng.gen());
+            let ray = Ray::new(origin.into(), point - origin);
+            assert_eq!(hits(&quad, &ray, false), 1, "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn rays_through_shared_corners_hit_one_triangle() {
+        for ray in rays_through(Vec3::new(0.5, 0.5, 0.0)) {
+            assert_eq!(hits(&fan(), &ray, false), 1, "{:?}", ray);
+        }
+        // The edges between neighbors too, out from the center
+        for point in [Vec3::new(0.25, 0.25, 0.0), Vec3::new(0.75, 0.25, 0.0)] {
+            for ray in rays_through(point) {
+                assert_eq!(hits(&fan(), &ray, false), 1, "{:?}", ray);
+            }
+        }
+    }
+
+    #[test]
+    fn seams_stay_watertight_with_mixed_winding() {
+        let mut fan = fan();
+        let [a, b, c] = fan[1];
+        fan[1] = [a, c, b];
+        for ray in rays_through(Vec3::new(0.5, 0.5, 0.0)) {
+            assert_eq!(hits(&fan, &ray, true), 1, "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn rays_past_the_outer_edges_miss() {
+        for point in [Vec3::new(-0.01, 0.5, 0.0), Vec3::new(0.5, 1.01, 0.0)] {
+            for ray in rays_through(point) {
+                assert_eq!(hits(&fan(), &ray, false), 0, "{:?}", ray);
+            }
+        }
+    }
+
+    #[test]
+    fn tolerances_ignore_the_direction_length() {
+        let triangle = [[
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ]];
+        for scale in [1e-9, 1.0, 1e13] {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0).into(), Vec3::new(0.0, 0.0, -scale));
+            assert_eq!(
+                hits(&triangle, &ray, false),
+                1,
+                "direction length {}",
+                scale
+            );
+        }
+        // Edge-on no matter how long the direction is
+        for scale in [1e-9, 1.0, 1e13] {
+            let ray = Ray::new(
+                Vec3::new(-2.0, 0.0, 1e-20).into(),
+                Vec3::new(scale, 0.0, 0.0),
+            );
+            assert_eq!(hits(&triangle, &ray, true), 0, "direction length {}", scale);
+        }
+    }
+
+    #[test]
+    fn huge_triangles_keep_grazing_hits() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        // A ground plane 10000 units across, seen less than a degree above its surface
+        let ground = Triangle::new(
+            Vec3::new(-5000.0, -5000.0, 0.0),
+            Vec3::new(5000.0, -5000.0, 0.0),
+            Vec3::new(0.0, 5000.0, 0.0),
+            Arc::new(lambertian(0.5)),
+        );
+        let mut rng = StdRng::seed_from_u64(4);
+        for degrees in [0.05, 0.2, 0.5, 0.9] {
+            let angle = Float::to_radians(degrees);
+            for _ in 0..1000 {
+                let target = Vec3::new(
+                    rng.gen_range(-2000.0..2000.0),
+                    rng.gen_range(-4000.0..0.0),
+                    0.0,
+                );
+                let heading = rng.gen_range(0.0..std::f64::consts::TAU);
+                let direction = Vec3::new(
+                    angle.cos() * heading.cos(),
+                    angle.cos() * heading.sin(),
+                    -angle.sin(),
+                );
+                let ray = Ray::new((target - direction * 500.0).into(), direction);
+                let hit = ground
+                    .hit(&ray, &(0.0..Float::MAX))
+                    .unwrap_or_else(|| panic!("missed {:?} at {} degrees", target, degrees));
+                assert!((hit.point - target).norm() < 1e-6, "{:?}", hit.point);
+                assert_eq!(hit.normal, Vec3::z());
+            }
+        }
+    }
+
+    #[test]
+    fn zero_area_triangles_are_never_hit_and_tiny_ones_are() {
+        let material = Arc::new(lambertian(0.5));
+        let down = Ray::new(Vec3::new(0.25, 0.0, 1.0).into(), -Vec3::z());
+        // Made without the checks `Triangle::try_new` does, as a corrupt mesh might be
+        let flat = Triangle::new(Vec3::zeros(), Vec3::x(), Vec3::x() * 2.0, material.clone());
+        assert!(flat.hit(&down, &(0.0..Float::MAX)).is_none());
+
+        let scale = 1e-4;
+        let tiny = Triangle::new(
+            Vec3::zeros(),
+            Vec3::x() * scale,
+            Vec3::y() * scale,
+            material,
+        );
+        let ray = Ray::new(
+            Vec3::new(0.25 * scale, 0.25 * scale, scale).into(),
+            -Vec3::z(),
+        );
+        let hit = tiny.hit(&ray, &(0.0..Float::MAX)).unwrap();
+        assert!((hit.t - scale).abs() < 1e-12, "{}", hit.t);
+        assert_eq!(hit.normal, Vec3::z());
+        let beside = Ray::new(
+            Vec3::new(0.75 * scale, 0.5 * scale, scale).into(),
+            -Vec3::z(),
+        );
+        assert!(tiny.hit(&beside, &(0.0..Float::MAX)).is_none());
+    }
+
+    #[test]
+    fn degenerate_spheres_are_rejected_with_why() {
+        let material = Arc::new(lambertian(0.5));
+        let sphere =
+            |center: Vec3, radius: Float| Sphere::try_new(center, radius, material.clone()).err();
+        assert!(sphere(Vec3::new(1.0, 2.0, 3.0), 0.5).is_none());
+        assert_eq!(
+            sphere(Vec3::new(Float::NAN, 0.0, 0.0), 1.0),
+            Some(ShapeError::NotFinite("sphere center"))
+        );
+        assert_eq!(
+            sphere(Vec3::new(0.0, Float::INFINITY, 0.0), 1.0),
+            Some(ShapeError::NotFinite("sphere center"))
+        );
+        assert_eq!(
+            sphere(Vec3::zeros(), Float::INFINITY),
+            Some(ShapeError::NotFinite("sphere radius"))
+        );
+        assert_eq!(
+            sphere(Vec3::zeros(), 0.0),
+            Some(ShapeError::NonPositiveRadius(0.0))
+        );
+        // The radius that was asked for, not the one it would have been clamped to
+        assert_eq!(
+            sphere(Vec3::zeros(), -2.0),
+            Some(ShapeError::NonPositiveRadius(-2.0))
+        );
+    }
+
+    #[test]
+    fn degenerate_triangles_are_rejected_with_why() {
+        let material = Arc::new(lambertian(0.5));
+        let triangle =
+            |a: Point3, b: Point3, c: Point3| Triangle::try_new(a, b, c, material.clone()).err();
+        let (a, b, c) = (Vec3::zeros(), Vec3::x(), Vec3::y());
+        assert!(triangle(a, b, c).is_none());
+        assert_eq!(
+            triangle(a, Vec3::new(Float::INFINITY, 0.0, 0.0), c),
+            Some(ShapeError::NotFinite("triangle corner"))
+        );
+        assert_eq!(
+            triangle(a, b, Vec3::new(0.0, Float::NAN, 0.0)),
+            Some(ShapeError::NotFinite("triangle corner"))
+        );
+        // Corners on one line, and two corners in the same place
+        assert_eq!(triangle(a, b, b * 3.0), Some(ShapeError::Degenerate));
+        assert_eq!(triangle(a, b, b), Some(ShapeError::Degenerate));
+        assert_eq!(
+            Triangle::try_new_with_uv(a, a, c, Vec2::zeros(), Vec2::x(), Vec2::y(), material).err(),
+            Some(ShapeError::Degenerate)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "worlds are only validated in debug builds"
+    )]
+    fn worlds_leave_out_and_count_corrupted_shapes() {
+        use rand::Rng;
+        let material = Arc::new(lambertian(0.5));
+        let mut rng = crate::rng::SampleRng::seeded(3, 0, 0, 0);
+        let mut expected = RejectReport::default();
+        let mut shapes: Vec<Shape> = Vec::new();
+        for _ in 0..500 {
+            let center = Vec3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            let (a, b, c) = (center, center + Vec3::x(), center + Vec3::y());
+            let shape: Shape = match rng.gen_range(0..8) {
+                0 => {
+                    expected.not_finite += 1;
+                    Sphere::new(center.map(|x| x * Float::NAN), 1.0, material.clone()).into()
+                }
+                1 => {
+                    expected.not_finite += 1;
+                    Sphere::new(center, Float::INFINITY, material.clone()).into()
+                }
+                2 => {
+                    expected.non_positive_radius += 1;
+                    Sphere::new(center, -rng.gen::<Float>(), material.clone()).into()
+                }
+                3 => {
+                    expected.not_finite += 1;
+                    Triangle::new(a, b, c.map(|x| x / 0.0), material.clone()).into()
+                }
+                4 => {
+                    expected.degenerate += 1;
+                    Triangle::new(a, b, a.lerp(&b, rng.gen()), material.clone()).into()
+                }
+                5 => Sphere::new(center, rng.gen_range(0.1..1.0), material.clone()).into(),
+                _ => Triangle::new(a, b, c, material.clone()).into(),
+            };
+            shapes.push(shape);
+        }
+        let kept = shapes.len() - expected.total();
+        assert!(expected.not_finite > 0 && expected.non_positive_radius > 0);
+        assert!(expected.degenerate > 0 && kept > 0);
+
+        let world = World::build(shapes);
+        assert_eq!(world.rejected, expected);
+        // What's left is hittable without NaN creeping in from the rejected shapes
+        for _ in 0..1000 {
+            let direction = Vec3::new(rng.gen(), rng.gen(), rng.gen()) - Vec3::repeat(0.5);
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.5).into(), direction);
+            if let Some(hit) = world.hit(&ray, &(1e-6..Float::INFINITY)) {
+                assert!(hit
+                    .point
+                    .iter()
+                    .chain(hit.normal.iter())
+                    .all(|x| x.is_finite()));
+            }
+        }
+    }
+
+    #[test]
+    fn obj_loads_report_the_faces_they_leave_out() {
+        let directory = scratch("rejects");
+        let path = directory.join("panel.obj");
+        // A good face, a face along a line and a face with an infinite corner
+        std::fs::write(
+            &path,
+            "o panel\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nv 0 inf 0\nf 1 2 3\nf 1 2 4\nf 1 2 5\n",
+        )
+        .unwrap();
+        let (meshes, report) = load_obj(
+            path.to_str().unwrap(),
+            Arc::new(lambertian(0.5)),
+            None,
+            false,
+            &LoadOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(meshes.iter().map(Mesh::triangle_count).sum::<usize>(), 1);
+        assert_eq!(
+            report.rejected_shapes,
+            [(
+                "panel".to_string(),
+                RejectReport {
+                    not_finite: 1,
+                    degenerate: 1,
+                    ..RejectReport::default()
+                }
+            )]
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn sphere_hits_from_inside_get_the_uvs_of_the_outside() {
+        use rand::Rng;
+        let mut rng = crate::rng::SampleRng::seeded(7, 0, 0, 0);
+        for mode in [UvMode::Spherical, UvMode::EqualArea, UvMode::CubeMap] {
+            let center = Vec3::new(0.3, -1.7, 2.9);
+            let sphere = Sphere::new_facing(
+                center,
+                1.3,
+                Arc::new(lambertian(0.5)),
+                Vec3::new(1.0, 2.0, -0.5).normalize(),
+            )
+            .with_uv_mode(mode);
+            for _ in 0..1000 {
+                let direction = Vec3::random_unit(&mut rng);
+                let point = center + direction * 1.3;
+                // From outside toward the center, and from near the center out
+                let from_outside = Ray::new((point + direction * 5.0).into(), -direction);
+                let offset = Vec3::random_unit(&mut rng) * rng.gen_range(0.0..0.5);
+                let inside = center + offset;
+                let from_inside = Ray::new(inside.into(), point - inside);
+                let range = 1e-6..Float::INFINITY;
+                let outside_hit = sphere.hit(&from_outside, &range).unwrap();
+                let inside_hit = sphere.hit(&from_inside, &range).unwrap();
+                assert!(outside_hit.is_front_face && !inside_hit.is_front_face);
+                assert!((outside_hit.point - inside_hit.point).norm() < 1e-9);
+                assert!((outside_hit.normal + inside_hit.normal).norm() < 1e-9);
+                let difference = outside_hit.uv - inside_hit.uv;
+                assert!(
+                    difference.map(|c| c - c.round()).norm() < 1e-6,
+                    "{:?}: {:?} outside, {:?} inside",
+                    mode,
+                    outside_hit.uv,
+                    inside_hit.uv
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grazing_rays_keep_their_sphere_hits() {
+        use rand::Rng;
+        let mut rng = crate::rng::SampleRng::seeded(6, 0, 0, 0);
+        for mode in [UvMode::Spherical, UvMode::EqualArea, UvMode::CubeMap] {
+            let center = Vec3::new(0.3, -1.7, 2.9);
+            let sphere = Sphere::new(center, 1.3, Arc::new(lambertian(0.5))).with_uv_mode(mode);
+            for _ in 0..5000 {
+                // Just inside the silhouette seen along a random direction
+                let direction = Vec3::random_unit(&mut rng);
+                let across = direction.cross(&Vec3::random_unit(&mut rng)).normalize();
+                let inset = rng.gen_range(1e-12..1e-6);
+                let origin = center + across * 1.3 * (1.0 - inset) - direction * 10.0;
+                let ray = Ray::new(origin.into(), direction);
+                let hit = sphere
+                    .hit(&ray, &(1e-6..Float::INFINITY))
+                    .unwrap_or_else(|| panic!("{:?} missed {:?}", mode, ray));
+                assert!(
+                    hit.uv.iter().all(|c| (0.0..=1.0).contains(c)),
+                    "{:?} {:?}",
+                    mode,
+                    hit.uv
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_triangles_move_their_corners_and_keep_unit_normals() {
+        let (a, b, c) = (Vec3::zeros(), Vec3::x(), Vec3::y());
+        let bent = [
+            Vec3::new(0.2, 0.0, 1.0),
+            Vec3::new(0.0, 0.2, 1.0),
+            Vec3::z(),
+        ];
+        let triangle = Triangle::new(a, b, c, Arc::new(lambertian(0.5))).with_vertex_normals(bent);
+        let translation = Matrix4::new_translation(&Vec3::new(3.0, -2.0, 5.0));
+        let turned = translation
+            * Matrix4::from_axis_angle(&Vec3::x_axis(), FRAC_PI_2)
+            * Matrix4::new_nonuniform_scaling(&Vec3::new(2.0, 0.5, 1.0));
+        let mirrored = translation * Matrix4::new_nonuniform_scaling(&Vec3::new(-1.0, 1.0, 1.0));
+        for matrix in [translation, turned, mirrored] {
+            let moved = triangle.transform(&matrix);
+            let place = |point: Vec3| matrix.transform_point(&point.into()).coords;
+            let turn = |normal: Vec3| (normal_matrix(&matrix) * normal).normalize();
+            // Mirroring swaps two corners to keep the same side in front
+            let order = if matrix == mirrored {
+                [0, 2, 1]
+            } else {
+                [0, 1, 2]
+            };
+            let corners = order.map(|i| place([a, b, c][i]));
+            for (moved, expected) in [moved.a, moved.b, moved.c].iter().zip(corners) {
+                assert!(
+                    (moved - expected).norm() < 1e-12,
+                    "{:?} for {:?}",
+                    moved,
+                    expected
+                );
+            }
+            assert!((moved.normal.norm() - 1.0).abs() < 1e-12);
+            assert!((moved.normal - turn(triangle.normal)).norm() < 1e-12);
+            let normals = moved.vertex_normals.unwrap();
+            for (normal, original) in normals.iter().zip(order.map(|i| bent[i])) {
+                assert!((normal.norm() - 1.0).abs() < 1e-12);
+                assert!((normal - turn(original)).norm() < 1e-12);
+                assert!(normal.dot(&moved.normal) > 0.0);
             }
         }
     }
-    meshes
 }
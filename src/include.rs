@@ -0,0 +1,297 @@
+use crate::assets::AssetResolver;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// One line of a text file like a render job or material library, with where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    /// The file the line is in, or `None` for text that didn't come from a file
+    pub file: Option<PathBuf>,
+    /// 1-based
+    pub number: usize,
+    pub text: String,
+}
+
+impl SourceLine {
+    /// Splits text that didn't come from a file into lines
+    pub fn from_source(source: &str) -> Vec<SourceLine> {
+        source
+            .lines()
+            .enumerate()
+            .map(|(i, text)| SourceLine {
+                file: None,
+                number: i + 1,
+                text: text.to_string(),
+            })
+            .collect()
+    }
+
+    /// Where the line is, for error messages
+    pub fn location(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{}:{}", file.display(), self.number),
+            None => format!("line {}", self.number),
+        }
+    }
+
+    /// The directory relative paths on this line are resolved against, `default` if the line
+    /// didn't come from a file
+    pub fn directory<'a>(&'a self, default: &'a Path) -> &'a Path {
+        self.file
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(default)
+    }
+}
+
+#[derive(Debug)]
+pub enum IncludeError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// Files that include each other in a loop, from the first one read back around to it
+    Cycle(Vec<PathBuf>),
+    /// An `include` line without a path
+    Malformed(String),
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncludeError::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            IncludeError::Cycle(chain) => {
+                let chain: Vec<String> = chain.iter().map(|p| p.display().to_string()).collect();
+                write!(
+                    f,
+                    "files include each other in a loop: {}",
+                    chain.join(" -> ")
+                )
+            }
+            IncludeError::Malformed(location) => write!(f, "{}: include needs a path", location),
+        }
+    }
+}
+
+impl std::error::Error for IncludeError {}
+
+/// Reads the files in `paths` one after the other, replacing every `include <path>` line with
+/// the lines of the file it names, relative to the file the include is in or found by an
+/// [`AssetResolver`] from there. Lines come out in the order they're read, so when a format
+/// lets a later setting replace an earlier one, files override the files and includes before
+/// them.
+pub fn read_with_includes(paths: &[impl AsRef<Path>]) -> Result<Vec<SourceLine>, IncludeError> {
+    let mut lines = Vec::new();
+    for path in paths {
+        expand(path.as_ref(), &mut Vec::new(), &mut lines)?;
+    }
+    Ok(lines)
+}
+
+/// Appends the lines of `path` to `lines`, expanding its includes. `chain` holds the files
+/// that included this one, which it mustn't include again.
+fn expand(
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), IncludeError> {
+    enter(path, chain)?;
+    let source = fs::read_to_string(path).map_err(|error| IncludeError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    for (i, text) in source.lines().enumerate() {
+        let line = SourceLine {
+            file: Some(path.to_path_buf()),
+            number: i + 1,
+            text: text.to_string(),
+        };
+        let trimmed = line.text.trim();
+        if trimmed != "include" && !trimmed.starts_with("include ") {
+            lines.push(line);
+            continue;
+        }
+        let included = trimmed["include".len()..].trim().trim_matches('"');
+        if included.is_empty() {
+            return Err(IncludeError::Malformed(line.location()));
+        }
+        let included = resolve(line.directory(Path::new("")), Path::new(included));
+        expand(&included, chain, lines)?;
+    }
+    chain.pop();
+    Ok(())
+}
+
+/// Where the file `included` from a file in `directory` is. Included files that aren't where
+/// the path says are looked for like any other asset.
+fn resolve(directory: &Path, included: &Path) -> PathBuf {
+    let included = directory.join(included);
+    match AssetResolver::from_env(directory).resolve_file(&included) {
+        Ok(file) => file.path,
+        Err(_) => included,
+    }
+}
+
+/// Adds `path` to `chain`, the files that included it, or fails if it's already in there
+fn enter(path: &Path, chain: &mut Vec<PathBuf>) -> Result<(), IncludeError> {
+    // The same file can be reached through different relative paths
+    let canonical = fs::canonicalize(path).map_err(|error| IncludeError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    if let Some(start) = chain.iter().position(|file| *file == canonical) {
+        let mut cycle = chain[start..].to_vec();
+        cycle.push(canonical);
+        return Err(IncludeError::Cycle(cycle));
+    }
+    chain.push(canonical);
+    Ok(())
+}
+
+/// Reads the files in `paths` one after the other like [`read_with_includes`], for formats
+/// that list the files they include among their own settings rather than on lines of their
+/// own. `parse` turns a file's path and text into what it describes and the paths it includes,
+/// relative to it. Each file comes out after the files it includes, so a format that lets later
+/// descriptions replace earlier ones lets files override what they include.
+pub fn read_descriptions<T, E: From<IncludeError>>(
+    paths: &[impl AsRef<Path>],
+    mut parse: impl FnMut(&Path, &str) -> Result<(T, Vec<PathBuf>), E>,
+) -> Result<Vec<(PathBuf, T)>, E> {
+    let mut descriptions = Vec::new();
+    for path in paths {
+        describe(
+            path.as_ref(),
+            &mut parse,
+            &mut Vec::new(),
+            &mut descriptions,
+        )?;
+    }
+    Ok(descriptions)
+}
+
+/// Appends what `path` and the files it includes describe to `descriptions`, with `chain`
+/// like [`expand`]'s
+fn describe<T, E: From<IncludeError>>(
+    path: &Path,
+    parse: &mut impl FnMut(&Path, &str) -> Result<(T, Vec<PathBuf>), E>,
+    chain: &mut Vec<PathBuf>,
+    descriptions: &mut Vec<(PathBuf, T)>,
+) -> Result<(), E> {
+    enter(path, chain)?;
+    let source = fs::read_to_string(path).map_err(|error| IncludeError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let (description, includes) = parse(path, &source)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    for included in includes {
+        describe(&resolve(directory, &included), parse, chain, descriptions)?;
+    }
+    descriptions.push((path.to_path_buf(), description));
+    chain.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{scratch, write};
+
+    fn texts(lines: &[SourceLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn nested_includes_resolve_against_their_own_files() {
+        let directory = scratch("nested");
+        write(&directory.join("main.job"), "a\ninclude sub/one.job\nd");
+        write(
+            &directory.join("sub/one.job"),
+            "b\ninclude \"deeper/two.job\"",
+        );
+        write(&directory.join("sub/deeper/two.job"), "c");
+        let lines = read_with_includes(&[directory.join("main.job")]).unwrap();
+        assert_eq!(texts(&lines), ["a", "b", "c", "d"]);
+        // Every line remembers where it was written
+        assert_eq!(
+            lines[2].file.as_deref(),
+            Some(&*directory.join("sub/deeper/two.job"))
+        );
+        assert_eq!(lines[3].number, 3);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn later_files_come_after_earlier_ones() {
+        let directory = scratch("order");
+        write(&directory.join("base.job"), "base");
+        write(&directory.join("night.job"), "include base.job\nnight");
+        let lines =
+            read_with_includes(&[directory.join("base.job"), directory.join("night.job")]).unwrap();
+        // Including a file twice through different branches isn't a cycle
+        assert_eq!(texts(&lines), ["base", "base", "night"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn cycles_are_reported_with_the_chain() {
+        let directory = scratch("cycle");
+        write(&directory.join("a.job"), "include common/b.job");
+        write(&directory.join("common/b.job"), "include ../a.job");
+        let error = read_with_includes(&[directory.join("a.job")]).unwrap_err();
+        let IncludeError::Cycle(chain) = &error else {
+            panic!("expected a cycle, got {}", error);
+        };
+        let names: Vec<_> = chain
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["a.job", "b.job", "a.job"]);
+        assert!(error.to_string().contains(" -> "));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn includes_without_a_path_or_file_are_errors() {
+        let directory = scratch("errors");
+        write(&directory.join("empty.job"), "x\ninclude");
+        let error = read_with_includes(&[directory.join("empty.job")]).unwrap_err();
+        assert!(matches!(&error, IncludeError::Malformed(location) if location.ends_with(":2")));
+        write(&directory.join("missing.job"), "include nowhere.job");
+        let error = read_with_includes(&[directory.join("missing.job")]).unwrap_err();
+        assert!(matches!(error, IncludeError::Io { .. }));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// Reads descriptions that list their includes on their first line, naming each by its
+    /// second
+    fn describe_all(paths: &[PathBuf]) -> Result<Vec<(PathBuf, String)>, IncludeError> {
+        read_descriptions(paths, |_, source| {
+            let (includes, name) = source.split_once('\n').unwrap_or((source, ""));
+            let includes = includes.split_whitespace().map(PathBuf::from).collect();
+            Ok((name.to_string(), includes))
+        })
+    }
+
+    #[test]
+    fn descriptions_come_after_the_ones_they_include() {
+        let directory = scratch("descriptions");
+        write(&directory.join("main"), "sub/one two\nmain");
+        write(&directory.join("sub/one"), "deeper/three\none");
+        write(&directory.join("sub/deeper/three"), "\nthree");
+        write(&directory.join("two"), "\ntwo");
+        let descriptions = describe_all(&[directory.join("main")]).unwrap();
+        let names: Vec<&str> = descriptions.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, ["three", "one", "two", "main"]);
+        assert_eq!(descriptions[1].0, directory.join("sub/one"));
+
+        write(&directory.join("sub/deeper/three"), "../../main\nthree");
+        let error = describe_all(&[directory.join("main")]).unwrap_err();
+        assert!(matches!(&error, IncludeError::Cycle(chain) if chain.len() == 4));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
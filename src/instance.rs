@@ -0,0 +1,196 @@
+use crate::{
+    camera::Float,
+    hittable::{Hit, Shape},
+    intersection::Intersection,
+    object::ObjectId,
+    vec3::{Point3, Ray, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+    bvh::Bvh,
+};
+use nalgebra::Similarity3;
+use std::{ops::Range, sync::Arc};
+
+/// A group of shapes with its own `BVH`, built once and shared by every [`Instance`] of it.
+/// This is the bottom level of a two-level `BVH`: the world's `BVH` only sees the instances.
+pub struct Prototype {
+    shapes: Vec<Shape>,
+    bvh: Bvh<Float, 3>,
+    bounds: Aabb<Float, 3>,
+}
+
+impl Prototype {
+    pub fn new(mut shapes: Vec<Shape>) -> Self {
+        let bvh = Bvh::build(&mut shapes);
+        let bounds = shapes
+            .iter()
+            .fold(Aabb::empty(), |bounds, shape| bounds.join(&shape.aabb()));
+        Prototype {
+            shapes,
+            bvh,
+            bounds,
+        }
+    }
+
+    pub fn shapes(&self) -> &[Shape] {
+        &self.shapes
+    }
+
+    /// Returns how many primitives this prototype stands for once every nested instance is expanded
+    pub fn primitive_count(&self) -> usize {
+        self.shapes.iter().map(primitive_count).sum()
+    }
+}
+
+impl Hit for Prototype {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        let mut nearest_hit_dist = range.end;
+        let mut nearest_hit = None;
+        for shape in self.bvh.nearest_traverse_iterator(ray, &self.shapes) {
+            if let Some(intersection) = shape.hit(ray, &(range.start..nearest_hit_dist)) {
+                nearest_hit_dist = intersection.t;
+                nearest_hit = Some(intersection);
+            }
+        }
+        nearest_hit
+    }
+}
+
+/// A [`Prototype`] placed in the scene with a rotation, uniform scale, and translation.
+/// Instances only store the transform, so memory grows with the number of unique prototypes
+/// rather than with the number of primitives they add up to.
+pub struct Instance {
+    prototype: Arc<Prototype>,
+    /// Maps the prototype's local space into its parent's space
+    transform: Similarity3<Float>,
+    inverse: Similarity3<Float>,
+    bounds: Aabb<Float, 3>,
+    /// The transform the instance was created with, which animations are applied on top of
+    rest: Similarity3<Float>,
+    node_index: usize,
+    /// The scene object this instance places, which animations refer to it by. Hits on the
+    /// instance name it rather than whatever its prototype's shapes are named, unless it's
+    /// unnamed.
+    pub object: ObjectId,
+}
+
+/// Returns bounds containing `local` once it's moved by `transform`
+fn transformed_bounds(local: &Aabb<Float, 3>, transform: &Similarity3<Float>) -> Aabb<Float, 3> {
+    // The transformed corners of the local bounds contain everything inside them
+    (0..8).fold(Aabb::empty(), |bounds, corner| {
+        let point = Vec3::from_fn(|axis, _| {
+            if (corner >> axis) & 1 == 0 {
+                local.min[axis]
+            } else {
+                local.max[axis]
+            }
+        });
+        bounds.grow(&(transform * nalgebra::Point3::from(point)))
+    })
+}
+
+impl Instance {
+    pub fn new(prototype: Arc<Prototype>, transform: Similarity3<Float>) -> Self {
+        let bounds = transformed_bounds(&prototype.bounds, &transform);
+        Instance {
+            prototype,
+            transform,
+            inverse: transform.inverse(),
+            bounds,
+            rest: transform,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn prototype(&self) -> &Arc<Prototype> {
+        &self.prototype
+    }
+
+    pub fn transform(&self) -> &Similarity3<Float> {
+        &self.transform
+    }
+
+    pub fn rest_transform(&self) -> &Similarity3<Float> {
+        &self.rest
+    }
+
+    /// Where the instance's bounds are at rest, whatever it's been moved to since
+    pub fn rest_bounds(&self) -> Aabb<Float, 3> {
+        transformed_bounds(&self.prototype.bounds, &self.rest)
+    }
+
+    /// Moves the instance without touching its prototype. The world's `BVH` has to be refit
+    /// with [`crate::hittable::World::refit`] afterwards.
+    pub fn set_transform(&mut self, transform: Similarity3<Float>) {
+        self.transform = transform;
+        self.inverse = transform.inverse();
+        self.bounds = transformed_bounds(&self.prototype.bounds, &transform);
+    }
+}
+
+impl Hit for Instance {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        // Rays are normalized, so distances in local space are shorter by the instance's scale
+        let scale = self.transform.scaling();
+        let local_ray = Ray::new(
+            self.inverse * ray.origin,
+            self.inverse.transform_vector(&ray.direction),
+        );
+        let local_range = range.start / scale..range.end / scale;
+        let mut hit = self.prototype.hit(&local_ray, &local_range)?;
+
+        hit.t *= scale;
+        hit.point = (self.transform * nalgebra::Point3::from(hit.point)).coords;
+        hit.normal = (self.transform.isometry.rotation * hit.normal).normalize();
+        hit.dpdu = self.transform.transform_vector(&hit.dpdu);
+        hit.dpdv = self.transform.transform_vector(&hit.dpdv);
+        if self.object != ObjectId::default() {
+            hit.object = self.object;
+        }
+        Some(hit)
+    }
+}
+
+impl Bounded<Float, 3> for Instance {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        self.bounds
+    }
+}
+
+impl BHShape<Float, 3> for Instance {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// Returns how many primitives `shape` stands for, counting everything inside instances
+pub fn primitive_count(shape: &Shape) -> usize {
+    match shape {
+        Shape::Instance(instance) => instance.prototype.primitive_count(),
+        Shape::Mesh(mesh) => mesh.triangle_count(),
+        _ => 1,
+    }
+}
+
+/// Returns the similarity transform that scales by `scale`, turns local +Z to point along
+/// `direction`, and then moves the origin to `translation`
+pub fn place(translation: Point3, direction: Vec3, scale: Float) -> Similarity3<Float> {
+    let rotation = nalgebra::UnitQuaternion::rotation_between(&Vec3::z(), &direction)
+        // Only fails when `direction` is straight down
+        .unwrap_or_else(|| {
+            nalgebra::UnitQuaternion::from_axis_angle(&Vec3::x_axis(), std::f64::consts::PI)
+        });
+    Similarity3::from_parts(translation.into(), rotation, scale)
+}
@@ -1,6 +1,7 @@
 use crate::{
     camera::Float,
-    material::Material,
+    material::{Material, Scatter},
+    object::ObjectId,
     vec3::{Point3, Ray, Vec2, Vec3},
 };
 
@@ -12,6 +13,12 @@ pub struct Intersection<'a> {
     pub t: Float,
     pub is_front_face: bool,
     pub uv: Vec2,
+    /// The scene object the hit shape belongs to
+    pub object: ObjectId,
+    /// How far the hit point moves along the surface per unit of `uv.x` and of `uv.y`. Zero on
+    /// shapes that don't work them out, which leaves their bump maps flat.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
 }
 
 impl<'a> Intersection<'a> {
@@ -30,9 +37,30 @@ impl<'a> Intersection<'a> {
             t,
             is_front_face,
             uv,
+            object: ObjectId::default(),
+            dpdu: Vec3::zeros(),
+            dpdv: Vec3::zeros(),
         }
     }
 
+    pub fn with_tangents(mut self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    /// Returns the hit with its normal perturbed by its material's bump map, if it has one
+    pub fn bumped(mut self) -> Self {
+        let material = self.material;
+        material.bump(&mut self);
+        self
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
     pub fn is_front_face(ray: &Ray, outward_normal: &Vec3) -> bool {
         ray.direction.dot(outward_normal) < 0.0
     }
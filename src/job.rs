@@ -0,0 +1,1266 @@
+use crate::{
+    animation::{Animation, AnimationError},
+    bracket::{ev_label, Bracket},
+    camera::{
+        Camera, Float, Image, Integrator, RenderFidelity, DEFAULT_ROULETTE_MIN_DEPTH,
+        DEFAULT_THROUGHPUT_THRESHOLD,
+    },
+    camera_path::CameraPath,
+    convergence::{self, StopCriterion},
+    denoise::{self, Guides},
+    gpu::GpuPrimary,
+    hittable::World,
+    include::{self, IncludeError, SourceLine},
+    multiview::{self, MultiSettings},
+    perf::{self, PerfLog, SessionHeader, SweepRecord},
+    postprocess::{FilmGrain, GrainStage, LensFlare, PostProcess},
+    shadow_matte::{ShadowCatcher, ShadowMatte, DEFAULT_SUN_ANGLE, DEFAULT_SUN_FRACTION},
+    streaming::{self, StreamError},
+    technical,
+    tiles::TileRenderer,
+    tonemap::Tonemap,
+    vec3::Vec3,
+};
+use std::{
+    fmt, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// What the preview does when asked to hand off to a final render
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandoffAction {
+    /// Closes the preview and renders the job right away without a window
+    RenderNow,
+    /// Closes the preview and writes the job to this path for `rt render <job>` to pick up later
+    WriteJob(String),
+}
+
+/// Quality settings for final renders handed off from the preview
+#[derive(Debug, Clone)]
+pub struct HandoffSettings {
+    pub samples_per_pixel: usize,
+    /// Multiplies the preview's resolution
+    pub resolution_scale: Float,
+    pub output_path: String,
+    pub action: HandoffAction,
+    /// Exposures to write the final render at, around the one the preview showed
+    pub bracket: Option<Bracket>,
+}
+
+impl Default for HandoffSettings {
+    fn default() -> Self {
+        HandoffSettings {
+            samples_per_pixel: 1024,
+            resolution_scale: 2.0,
+            output_path: "final_out.ppm".to_string(),
+            action: HandoffAction::RenderNow,
+            bracket: None,
+        }
+    }
+}
+
+/// Returns where the denoised version of an image at `path` goes: next to it, with `_denoised`
+/// added before the extension
+pub fn denoised_path(path: &str) -> String {
+    suffixed_path(path, "_denoised")
+}
+
+/// Returns where the shadow matte of an image at `path` goes: next to it, with `_shadow` added
+/// before the extension
+pub fn shadow_matte_path(path: &str) -> String {
+    suffixed_path(path, "_shadow")
+}
+
+/// Returns where the image at `path` goes when it's written at `ev` stops from the job's
+/// exposure as part of a bracket: next to it, with e.g. `_ev+1` or `_ev-0.5` added before the
+/// extension. A bracket never has the same EV twice, so each one gets a file of its own.
+pub fn bracket_path(path: &str, ev: Float) -> String {
+    suffixed_path(path, &format!("_ev{}", ev_label(ev)))
+}
+
+/// Returns where the view named `view` of a multi-view render whose output is `path` goes: next
+/// to it, with e.g. `_left` added before the extension
+pub fn view_path(path: &str, view: &str) -> String {
+    suffixed_path(path, &format!("_{}", view))
+}
+
+fn suffixed_path(path: &str, suffix: &str) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => {
+            format!("{}{}.{}", stem, suffix, extension)
+        }
+        _ => format!("{}{}", path, suffix),
+    }
+}
+
+/// Everything needed to reproduce a render of a scene, apart from the scene itself.
+/// The scene's fingerprint is recorded so a job run against a different scene gets noticed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderJob {
+    pub center: Vec3,
+    pub lookat: Vec3,
+    pub up: Vec3,
+    pub vertical_fov: Float,
+    pub focus_distance: Float,
+    pub defocus_angle: Float,
+    pub near: Float,
+    pub far: Float,
+    pub width: usize,
+    pub height: usize,
+    /// The most samples per pixel an `auto_stop` render may take
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub fidelity: RenderFidelity,
+    pub integrator: Integrator,
+    /// Stops rendering once the image is this accurate instead of always taking
+    /// `samples_per_pixel`
+    pub auto_stop: Option<StopCriterion>,
+    /// See [`Camera::throughput_threshold`]
+    pub throughput_threshold: Float,
+    /// See [`Camera::roulette_min_depth`]
+    pub roulette_min_depth: usize,
+    /// See [`Camera::sun_sampling`]
+    pub sun_sampling: bool,
+    pub gamma: Float,
+    pub output_path: String,
+    /// Output tonemaps that are LUTs aren't saved with the job
+    pub post_process: PostProcess,
+    /// From [`crate::snapshot::SceneSnapshot::fingerprint`]
+    pub scene_fingerprint: u64,
+    /// Makes every sample reproducible, see [`Camera::seed`]
+    pub seed: Option<u64>,
+    /// Moves objects in the scene depending on the frame
+    pub animation: Animation,
+    /// Flies the camera through the scene depending on the frame, instead of leaving it at
+    /// `center` looking at `lookat`
+    pub camera_path: CameraPath,
+    /// Also renders the shadows on a catcher as a matte, written to [`shadow_matte_path`]
+    pub shadow_matte: Option<ShadowMatte>,
+    /// Writes the render at each of these exposures, to [`bracket_path`], instead of once
+    pub bracket: Option<Bracket>,
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    Io(io::Error),
+    /// A line that couldn't be parsed, with where it is
+    Malformed {
+        location: String,
+        message: String,
+    },
+    Missing(&'static str),
+    /// The job's files couldn't be read, or include each other in a loop
+    Include(IncludeError),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Io(err) => write!(f, "failed to read render job: {}", err),
+            JobError::Malformed { location, message } => write!(f, "{}: {}", location, message),
+            JobError::Missing(key) => write!(f, "render job has no '{}'", key),
+            JobError::Include(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl From<io::Error> for JobError {
+    fn from(err: io::Error) -> Self {
+        JobError::Io(err)
+    }
+}
+
+fn parse_values<T: std::str::FromStr>(
+    words: &[&str],
+    count: usize,
+    location: &str,
+) -> Result<Vec<T>, JobError> {
+    let malformed = |message: String| JobError::Malformed {
+        location: location.to_string(),
+        message,
+    };
+    if words.len() != count {
+        return Err(malformed(format!(
+            "expected {} value(s) but found {}",
+            count,
+            words.len()
+        )));
+    }
+    words
+        .iter()
+        .map(|word| {
+            word.parse()
+                .map_err(|_| malformed(format!("'{}' is not a valid value", word)))
+        })
+        .collect()
+}
+
+impl RenderJob {
+    /// Describes a final render of what `camera` sees, at the quality asked for by `settings`
+    pub fn from_camera(camera: &Camera, world: &World, settings: &HandoffSettings) -> Self {
+        let scale = settings.resolution_scale.max(0.0);
+        let range = camera.t_range();
+        RenderJob {
+            center: camera.center,
+            lookat: camera.lookat,
+            up: camera.up,
+            vertical_fov: camera.vertical_fov,
+            focus_distance: camera.focus_distance,
+            defocus_angle: camera.defocus_angle(),
+            near: range.start,
+            far: range.end,
+            width: ((camera.image_width as Float * scale).round() as usize).max(1),
+            height: ((camera.image_height as Float * scale).round() as usize).max(1),
+            samples_per_pixel: settings.samples_per_pixel,
+            max_depth: camera.max_depth(),
+            fidelity: camera.fidelity,
+            integrator: camera.integrator,
+            auto_stop: None,
+            throughput_threshold: camera.throughput_threshold,
+            roulette_min_depth: camera.roulette_min_depth,
+            sun_sampling: camera.sun_sampling,
+            gamma: camera.gamma,
+            output_path: settings.output_path.clone(),
+            post_process: camera.post_process.clone(),
+            scene_fingerprint: world.snapshot().fingerprint(),
+            seed: camera.seed,
+            animation: Animation::default(),
+            camera_path: CameraPath::default(),
+            shadow_matte: None,
+            bracket: settings.bracket.clone(),
+        }
+    }
+
+    /// Moves the objects in `world` to where the job's animation, played over the world's own,
+    /// puts them on its frame, and checks that its camera path has everything it tracks.
+    /// Returns how many moved.
+    pub fn animate(&self, world: &mut World) -> Result<usize, AnimationError> {
+        let moved = self
+            .animation
+            .over(&world.animation)
+            .apply(world, self.post_process.frame as Float)?;
+        self.camera_in(world)?;
+        Ok(moved)
+    }
+
+    /// The job's camera where it's set, ignoring any camera path
+    pub fn camera(&self) -> Camera {
+        self.camera_looking(
+            self.center,
+            self.lookat,
+            self.up,
+            self.focus_distance,
+            self.vertical_fov,
+        )
+    }
+
+    /// The job's camera on its frame, moved along its camera path if it has one, looking at
+    /// tracked objects where they are in `world`. A camera on a path stays focused on what
+    /// it's looking at.
+    pub fn camera_in(&self, world: &World) -> Result<Camera, AnimationError> {
+        let view = self.camera_path.view_at(
+            self.post_process.frame as Float,
+            world,
+            self.vertical_fov,
+            self.up,
+        )?;
+        Ok(match view {
+            Some(view) => self.camera_looking(
+                view.center,
+                view.lookat,
+                view.up,
+                (view.lookat - view.center).norm(),
+                view.vertical_fov,
+            ),
+            None => self.camera(),
+        })
+    }
+
+    /// Like [`RenderJob::camera_in`], falling back to where the job set the camera with a
+    /// warning
+    fn camera_or_warn(&self, world: &World) -> Camera {
+        self.camera_in(world).unwrap_or_else(|err| {
+            println!("Warning: {}, so the camera stays where the job set it", err);
+            self.camera()
+        })
+    }
+
+    /// The job with its camera replaced by `view`'s: where it is, where it looks, its lens,
+    /// its resolution and its camera path. Everything else, like sampling and the output path,
+    /// stays the job's.
+    pub fn viewed_from(&self, view: &RenderJob) -> RenderJob {
+        RenderJob {
+            center: view.center,
+            lookat: view.lookat,
+            up: view.up,
+            vertical_fov: view.vertical_fov,
+            focus_distance: view.focus_distance,
+            defocus_angle: view.defocus_angle,
+            width: view.width,
+            height: view.height,
+            camera_path: view.camera_path.clone(),
+            ..self.clone()
+        }
+    }
+
+    fn camera_looking(
+        &self,
+        center: Vec3,
+        lookat: Vec3,
+        up: Vec3,
+        focus_distance: Float,
+        vertical_fov: Float,
+    ) -> Camera {
+        let mut camera = Camera::new(
+            center,
+            lookat,
+            up,
+            focus_distance,
+            self.defocus_angle,
+            self.width,
+            self.height,
+            self.samples_per_pixel,
+            self.max_depth,
+            vertical_fov,
+            self.near..self.far,
+        );
+        camera.fidelity = self.fidelity;
+        camera.integrator = self.integrator;
+        camera.throughput_threshold = self.throughput_threshold;
+        camera.roulette_min_depth = self.roulette_min_depth;
+        camera.sun_sampling = self.sun_sampling;
+        camera.gamma = self.gamma;
+        camera.post_process = self.post_process.clone();
+        camera.seed = self.seed;
+        if self.integrator == Integrator::Technical {
+            technical::preset(&mut camera);
+        }
+        camera
+    }
+
+    /// Renders the job without a window, with its post-processing applied
+    pub fn render(&self, world: &World) -> Image {
+        self.render_on(world, None)
+    }
+
+    /// Like [`RenderJob::render`], but on `tiles` instead of the global thread pool if given
+    pub fn render_on(&self, world: &World, tiles: Option<&TileRenderer>) -> Image {
+        self.post_process(self.render_linear(world, tiles))
+    }
+
+    /// Renders the job without its post-processing, so the colors are still linear
+    fn render_linear(&self, world: &World, tiles: Option<&TileRenderer>) -> Image {
+        self.render_linear_with(world, |camera| match (&self.auto_stop, tiles) {
+            (Some(criterion), _) => {
+                let (image, report) =
+                    convergence::render_until_converged(camera, world, criterion, tiles);
+                println!("Auto stop {}", report);
+                image
+            }
+            (None, Some(tiles)) => tiles.render_image(camera, world),
+            (None, None) => camera.render_image(world),
+        })
+    }
+
+    /// Like [`RenderJob::render_linear`], with the job's camera rendered by `render`
+    fn render_linear_with(&self, world: &World, render: impl FnOnce(&Camera) -> Image) -> Image {
+        let fingerprint = self.check_scene(world);
+        let camera = self.camera_or_warn(world);
+        let render_start = Instant::now();
+        let traced_before = camera.watchdog.path_stats().traced_rays;
+        let mut image = render(&camera);
+        camera.add_alpha(world, &mut image);
+        println!("Paths: {}", camera.watchdog.path_stats());
+        // Auto stop logs each of its passes instead
+        if let (Some(log), None) = (PerfLog::global(), &self.auto_stop) {
+            let samples = camera.samples_per_pixel();
+            log.sweep(&SweepRecord {
+                render: log.begin_render(),
+                sweep: 1,
+                samples_added: samples,
+                total_samples: samples,
+                seconds: render_start.elapsed().as_secs_f64(),
+                camera_rays: (samples * camera.image_width * camera.image_height) as u64,
+                traced_rays: perf::traced_since(&camera.watchdog, traced_before),
+                converged_fraction: None,
+            });
+        }
+        image
+            .metadata
+            .push(format!("scene fingerprint: {:016x}", fingerprint));
+        image
+            .metadata
+            .push(format!("render job: {:016x}", self.fingerprint()));
+        image
+    }
+
+    /// Returns the fingerprint of `world`, warning if the job was made for another scene
+    fn check_scene(&self, world: &World) -> u64 {
+        let fingerprint = world.snapshot().fingerprint();
+        if fingerprint != self.scene_fingerprint {
+            println!(
+                "Warning: render job was made for scene {:016x} but this scene is {:016x}",
+                self.scene_fingerprint, fingerprint
+            );
+        }
+        fingerprint
+    }
+
+    fn post_process(&self, image: Image) -> Image {
+        if self.post_process.is_enabled() {
+            self.post_process.apply(&image)
+        } else {
+            image
+        }
+    }
+
+    /// The header of a performance log of rendering the job in `world` on `threads` threads
+    pub fn perf_session(&self, world: &World, threads: usize) -> SessionHeader {
+        SessionHeader {
+            scene_fingerprint: world.snapshot().fingerprint(),
+            job_fingerprint: Some(self.fingerprint()),
+            width: self.width,
+            height: self.height,
+            samples_per_pixel: Some(self.samples_per_pixel),
+            max_depth: self.max_depth,
+            integrator: self.integrator.name().to_string(),
+            fidelity: self.fidelity.name().to_string(),
+            threads,
+            ..SessionHeader::here("batch")
+        }
+    }
+
+    /// Renders the job without a window and writes the image to its output path
+    pub fn run(&self, world: &World) -> io::Result<()> {
+        self.run_on(world, None)
+    }
+
+    /// Like [`RenderJob::run`], but on `tiles` instead of the global thread pool if given
+    pub fn run_on(&self, world: &World, tiles: Option<&TileRenderer>) -> io::Result<()> {
+        let render_start = Instant::now();
+        let image = self.render_linear(world, tiles);
+        self.write(image, render_start)?;
+        self.write_shadow_matte(world)
+    }
+
+    /// Like [`RenderJob::run`], with the camera rays' first hits found on `gpu`
+    pub fn run_on_gpu(&self, world: &World, gpu: &GpuPrimary) -> io::Result<()> {
+        let render_start = Instant::now();
+        let image = self.render_linear_with(world, |camera| gpu.render_image(camera, world));
+        self.write(image, render_start)?;
+        self.write_shadow_matte(world)
+    }
+
+    /// Renders every named view of one world, see [`multiview::render_multi`], and writes each
+    /// like [`RenderJob::run_on`] would to its output path's [`view_path`]. Every view is a job
+    /// of its own, usually [`RenderJob::viewed_from`] the same one. When cancelled, the views
+    /// that were started are still written.
+    pub fn run_views_on(
+        views: &[(String, RenderJob)],
+        world: &World,
+        settings: &MultiSettings,
+        tiles: Option<&TileRenderer>,
+    ) -> io::Result<()> {
+        let views: Vec<(String, RenderJob)> = views
+            .iter()
+            .map(|(name, job)| {
+                let output_path = view_path(&job.output_path, name);
+                let job = RenderJob {
+                    output_path,
+                    ..job.clone()
+                };
+                (name.clone(), job)
+            })
+            .collect();
+        let cameras: Vec<Camera> = views
+            .iter()
+            .map(|(_, job)| job.camera_or_warn(world))
+            .collect();
+        // Views usually come from the same job, so each scene they were made for is checked once
+        let mut checked = Vec::new();
+        for (_, job) in &views {
+            if !checked.contains(&job.scene_fingerprint) {
+                checked.push(job.scene_fingerprint);
+                job.check_scene(world);
+            }
+        }
+        let fingerprint = world.snapshot().fingerprint();
+        let render_start = Instant::now();
+        let (images, report) = multiview::render_multi(&cameras, world, settings, tiles);
+        println!("{}", report);
+        for ((name, job), mut image) in views.iter().zip(images) {
+            image.metadata.extend([
+                format!("view name: {}", name),
+                format!("scene fingerprint: {:016x}", fingerprint),
+                format!("render job: {:016x}", job.fingerprint()),
+            ]);
+            job.write(image, render_start)?;
+            job.write_shadow_matte(world)?;
+        }
+        Ok(())
+    }
+
+    /// Renders the job's shadow matte if it has one and writes it to [`shadow_matte_path`].
+    /// The matte isn't post-processed, since it's meant to be multiplied over footage. A
+    /// catcher or caster the scene doesn't have is only a warning, as the render is done.
+    fn write_shadow_matte(&self, world: &World) -> io::Result<()> {
+        let Some(matte) = &self.shadow_matte else {
+            return Ok(());
+        };
+        let camera = self.camera_in(world).unwrap_or_else(|_| self.camera());
+        let start = Instant::now();
+        match matte.render(&camera, world) {
+            Ok(mut image) => {
+                image
+                    .metadata
+                    .push(format!("render job: {:016x}", self.fingerprint()));
+                let path = shadow_matte_path(&self.output_path);
+                Camera::save_image(image, Path::new(&path))?;
+                println!(
+                    "Rendered the shadow matte into {} in {:.1} seconds",
+                    path,
+                    start.elapsed().as_secs_f64()
+                );
+            }
+            Err(err) => println!("Warning: no shadow matte, {}", err),
+        }
+        Ok(())
+    }
+
+    /// Like [`RenderJob::run_on`], but renders a tile at a time and writes each one out as
+    /// soon as it's done, so images too large to hold in memory can be rendered. See
+    /// [`streaming::render_streaming`] for what it can't do.
+    pub fn run_streaming_on(
+        &self,
+        world: &World,
+        tiles: Option<&TileRenderer>,
+    ) -> Result<(), StreamError> {
+        if self.auto_stop.is_some() {
+            return Err(StreamError::NeedsWholeFrame("auto stop"));
+        }
+        if self.bracket.is_some() {
+            return Err(StreamError::NeedsWholeFrame("exposure bracketing"));
+        }
+        let camera = self.camera_or_warn(world);
+        let metadata = vec![
+            format!("scene fingerprint: {:016x}", world.snapshot().fingerprint()),
+            format!("render job: {:016x}", self.fingerprint()),
+        ];
+        let report = streaming::render_streaming(
+            &camera,
+            world,
+            &self.post_process,
+            metadata,
+            Path::new(&self.output_path),
+            tiles,
+        )?;
+        println!("Rendered {}, {}", self.output_path, report);
+        if let Some(log) = PerfLog::global() {
+            let samples = camera.samples_per_pixel();
+            log.sweep(&SweepRecord {
+                render: log.begin_render(),
+                sweep: 1,
+                samples_added: samples,
+                total_samples: samples,
+                seconds: report.elapsed.as_secs_f64(),
+                camera_rays: (samples * camera.image_width * camera.image_height) as u64,
+                traced_rays: perf::traced_since(&camera.watchdog, 0),
+                converged_fraction: None,
+            });
+        }
+        self.write_shadow_matte(world)?;
+        Ok(())
+    }
+
+    /// Post-processes a linear render started at `render_start` and writes it to the job's
+    /// output path, or each of its bracket's exposures next to it
+    fn write(&self, image: Image, render_start: Instant) -> io::Result<()> {
+        let paths = self.write_processed(image, &self.output_path)?;
+        println!(
+            "Rendered {} in {:.1} seconds",
+            paths.join(", "),
+            render_start.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    /// Post-processes the linear `image` and writes it to `path`, or once for every exposure
+    /// in the job's bracket to its [`bracket_path`], returning the paths written. The bracket
+    /// only changes the exposure, so all of its images come from the same samples.
+    fn write_processed(&self, image: Image, path: &str) -> io::Result<Vec<String>> {
+        let Some(bracket) = &self.bracket else {
+            Camera::save_image(self.post_process(image), Path::new(path))?;
+            return Ok(vec![path.to_string()]);
+        };
+        let mut paths = Vec::new();
+        for &ev in bracket.evs() {
+            let post_process = PostProcess {
+                exposure: self.post_process.exposure + ev,
+                ..self.post_process.clone()
+            };
+            let mut processed = post_process.apply(&image);
+            processed
+                .metadata
+                .push(format!("bracket: {} EV", ev_label(ev)));
+            let path = bracket_path(path, ev);
+            Camera::save_image(processed, Path::new(&path))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Like [`RenderJob::run_on`], but also denoises the render and writes it next to the raw
+    /// one, to [`denoised_path`]. The raw render is written either way, and if denoising fails
+    /// that's only a warning.
+    pub fn run_denoised_on(&self, world: &World, tiles: Option<&TileRenderer>) -> io::Result<()> {
+        let render_start = Instant::now();
+        let mut raw = self.render_linear(world, tiles);
+        let camera = self.camera_in(world).unwrap_or_else(|_| self.camera());
+        let guides = Guides::render(&camera, world);
+        // Denoised before post-processing, which is nonlinear and can add grain
+        let denoised = denoise::denoise(&raw, &guides);
+        raw.metadata.push("denoised: no".to_string());
+        self.write(raw, render_start)?;
+        match denoised {
+            Ok(denoised) => {
+                let paths = self.write_processed(denoised, &denoised_path(&self.output_path))?;
+                println!("Denoised it into {}", paths.join(", "));
+            }
+            Err(err) => println!("Warning: only wrote the raw render, {}", err),
+        }
+        self.write_shadow_matte(world)
+    }
+
+    /// Returns a hash of every setting in the job, which renders record in their metadata so
+    /// an image can be matched to the exact job that made it
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.to_job_string().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, JobError> {
+        RenderJob::load_all(&[path])
+    }
+
+    /// Loads a job made of several files, in order, along with the files they `include`.
+    /// Settings in later files replace those in earlier ones, while `animate` keyframes add up
+    /// unless a later file clears an object's with `unset animate <object>`.
+    pub fn load_all(paths: &[impl AsRef<Path>]) -> Result<Self, JobError> {
+        let lines = include::read_with_includes(paths).map_err(JobError::Include)?;
+        RenderJob::parse_lines(&lines)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_job_string())
+    }
+
+    /// Serializes the job as one `key value...` line per setting
+    pub fn to_job_string(&self) -> String {
+        let vector = |v: &Vec3| format!("{} {} {}", v.x, v.y, v.z);
+        let post = &self.post_process;
+        let tonemap = match &post.tonemap {
+            None => None,
+            Some(Tonemap::Clamp) => Some("output_tonemap clamp".to_string()),
+            Some(Tonemap::Uncharted2 { exposure_bias }) => {
+                Some(format!("output_tonemap uncharted2 {}", exposure_bias))
+            }
+            Some(Tonemap::Lut(_)) => {
+                Some("# output tonemap is a LUT, which isn't saved".to_string())
+            }
+        };
+        let grain = post.grain.as_ref().map(|grain| {
+            format!(
+                "grain {} {} {} {} {}",
+                grain.iso,
+                grain.read_noise,
+                grain.shot_noise,
+                grain.fixed_pattern,
+                grain.stage.name()
+            )
+        });
+        let flare = post.flare.as_ref().map(|flare| {
+            format!(
+                "flare {} {} {} {} {} {} {} {}",
+                flare.threshold,
+                flare.ghost_count,
+                flare.ghost_spacing,
+                flare.intensity,
+                flare.ghost_falloff,
+                flare.streak_count,
+                flare.streak_length,
+                flare.streak_intensity
+            )
+        });
+        let lines = [
+            "# rt render job".to_string(),
+            format!("center {}", vector(&self.center)),
+            format!("lookat {}", vector(&self.lookat)),
+            format!("up {}", vector(&self.up)),
+            format!("vertical_fov {}", self.vertical_fov),
+            format!("focus_distance {}", self.focus_distance),
+            format!("defocus_angle {}", self.defocus_angle),
+            format!("t_range {:e} {:e}", self.near, self.far),
+            format!("resolution {} {}", self.width, self.height),
+            format!("samples_per_pixel {}", self.samples_per_pixel),
+            format!("max_depth {}", self.max_depth),
+            format!("fidelity {}", self.fidelity.name()),
+            format!("integrator {}", self.integrator.name()),
+            format!("throughput_threshold {}", self.throughput_threshold),
+            format!("gamma {}", self.gamma),
+            format!("output {}", self.output_path),
+            format!("scene_fingerprint {:016x}", self.scene_fingerprint),
+            format!("frame {}", post.frame),
+        ];
+        let seed = self.seed.map(|seed| format!("seed {}", seed));
+        // Only written when off, so jobs from before there was a choice keep their fingerprint
+        let sun_sampling = (!self.sun_sampling).then(|| "sun_sampling off".to_string());
+        let roulette_min_depth = (self.roulette_min_depth != DEFAULT_ROULETTE_MIN_DEPTH)
+            .then(|| format!("roulette_min_depth {}", self.roulette_min_depth));
+        // Only written when set, so jobs from before there was an exposure keep their fingerprint
+        let exposure = (post.exposure != 0.0).then(|| format!("exposure {}", post.exposure));
+        let bracket = self
+            .bracket
+            .as_ref()
+            .map(|bracket| format!("bracket {}", bracket.to_list()));
+        let auto_stop = self.auto_stop.as_ref().map(|stop| {
+            let time = stop
+                .max_time
+                .map_or(String::new(), |max| format!(" {}", max.as_secs_f64()));
+            format!(
+                "auto_stop {} {} {} {} {}{}",
+                stop.relative_error,
+                stop.confidence,
+                stop.pixel_fraction,
+                stop.outlier_fraction,
+                stop.luminance_floor,
+                time
+            )
+        });
+        let shadow_matte = self.shadow_matte.iter().flat_map(|matte| {
+            let catcher = match &matte.catcher {
+                ShadowCatcher::Plane { point, normal } => {
+                    format!("shadow_catcher plane {} {}", vector(point), vector(normal))
+                }
+                ShadowCatcher::Objects(names) => {
+                    format!("shadow_catcher objects {}", names.join(" "))
+                }
+            };
+            let casters = matte
+                .casters
+                .as_ref()
+                .map(|names| format!("shadow_casters {}", names.join(" ")));
+            let sun = format!("shadow_sun {} {}", matte.sun_angle, matte.sun_fraction);
+            std::iter::once(catcher).chain(casters).chain([sun])
+        });
+        lines
+            .into_iter()
+            .chain(seed)
+            .chain(sun_sampling)
+            .chain(roulette_min_depth)
+            .chain(auto_stop)
+            .chain(exposure)
+            .chain(tonemap)
+            .chain(grain)
+            .chain(flare)
+            .chain(shadow_matte)
+            .chain(bracket)
+            .chain(self.camera_path.to_lines())
+            .chain(self.animation.to_lines())
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    pub fn parse(source: &str) -> Result<Self, JobError> {
+        RenderJob::parse_lines(&SourceLine::from_source(source))
+    }
+
+    fn parse_lines(lines: &[SourceLine]) -> Result<Self, JobError> {
+        let mut center = None;
+        let mut lookat = None;
+        let mut up = None;
+        let mut vertical_fov = None;
+        let mut focus_distance = None;
+        let mut defocus_angle = None;
+        let mut t_range = None;
+        let mut resolution = None;
+        let mut samples_per_pixel = None;
+        let mut max_depth = None;
+        let mut fidelity = None;
+        // Jobs from before there was a choice of integrator all used the path tracer
+        let mut integrator = Integrator::default();
+        // Older jobs culled paths by attenuation instead, but this is the closest match
+        let mut throughput_threshold = DEFAULT_THROUGHPUT_THRESHOLD;
+        let mut roulette_min_depth = DEFAULT_ROULETTE_MIN_DEPTH;
+        let mut sun_sampling = true;
+        let mut gamma = None;
+        let mut output_path = None;
+        let mut scene_fingerprint = None;
+        let mut seed = None;
+        let mut auto_stop = None;
+        let mut post_process = PostProcess::default();
+        let mut animation = Animation::default();
+        let mut camera_path = CameraPath::default();
+        let mut shadow_catcher = None;
+        let mut shadow_casters = None;
+        let mut shadow_sun = None;
+        let mut bracket = None;
+
+        for line in lines {
+            let location = line.location();
+            let line = line.text.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            let malformed = |message: String| JobError::Malformed {
+                location: location.clone(),
+                message,
+            };
+            let vector = |words: &[&str]| -> Result<Vec3, JobError> {
+                let v = parse_values::<Float>(words, 3, &location)?;
+                Ok(Vec3::new(v[0], v[1], v[2]))
+            };
+            let float = |words: &[&str]| -> Result<Float, JobError> {
+                Ok(parse_values::<Float>(words, 1, &location)?[0])
+            };
+
+            match key {
+                "center" => center = Some(vector(&words)?),
+                "lookat" => lookat = Some(vector(&words)?),
+                "up" => up = Some(vector(&words)?),
+                "vertical_fov" => vertical_fov = Some(float(&words)?),
+                "focus_distance" => focus_distance = Some(float(&words)?),
+                "defocus_angle" => defocus_angle = Some(float(&words)?),
+                "t_range" => {
+                    let v = parse_values::<Float>(&words, 2, &location)?;
+                    t_range = Some((v[0], v[1]));
+                }
+                "resolution" => {
+                    let v = parse_values::<usize>(&words, 2, &location)?;
+                    resolution = Some((v[0], v[1]));
+                }
+                "samples_per_pixel" => {
+                    samples_per_pixel = Some(parse_values::<usize>(&words, 1, &location)?[0])
+                }
+                "max_depth" => max_depth = Some(parse_values::<usize>(&words, 1, &location)?[0]),
+                "fidelity" => {
+                    fidelity = Some(
+                        RenderFidelity::from_name(rest.trim())
+                            .ok_or_else(|| malformed(format!("unknown fidelity '{}'", rest)))?,
+                    )
+                }
+                "integrator" => {
+                    integrator = Integrator::from_name(rest.trim())
+                        .ok_or_else(|| malformed(format!("unknown integrator '{}'", rest)))?
+                }
+                "throughput_threshold" => throughput_threshold = float(&words)?,
+                "roulette_min_depth" => {
+                    roulette_min_depth = parse_values::<usize>(&words, 1, &location)?[0]
+                }
+                "sun_sampling" => {
+                    sun_sampling = match words.as_slice() {
+                        ["on"] => true,
+                        ["off"] => false,
+                        _ => return Err(malformed("sun_sampling is 'on' or 'off'".to_string())),
+                    }
+                }
+                "gamma" => gamma = Some(float(&words)?),
+                "output" => output_path = Some(rest.trim().to_string()),
+                "scene_fingerprint" => {
+                    scene_fingerprint =
+                        Some(u64::from_str_radix(rest.trim(), 16).map_err(|_| {
+                            malformed(format!("'{}' is not a hexadecimal fingerprint", rest))
+                        })?)
+                }
+                "seed" => seed = Some(parse_values::<u64>(&words, 1, &location)?[0]),
+                "auto_stop" => {
+                    // The time limit at the end is optional
+                    let count = words.len().clamp(5, 6);
+                    let v = parse_values::<Float>(&words, count, &location)?;
+                    let bad_time = v
+                        .get(5)
+                        .is_some_and(|seconds| !(seconds.is_finite() && *seconds >= 0.0));
+                    if v[0] <= 0.0 || !(0.0..1.0).contains(&v[1]) || bad_time {
+                        return Err(malformed(
+                            "auto_stop needs a positive error, a confidence below 1 and a time \
+                             limit that isn't negative"
+                                .to_string(),
+                        ));
+                    }
+                    auto_stop = Some(StopCriterion {
+                        relative_error: v[0],
+                        confidence: v[1],
+                        pixel_fraction: v[2],
+                        outlier_fraction: v[3],
+                        luminance_floor: v[4],
+                        max_time: v.get(5).map(|&seconds| Duration::from_secs_f64(seconds)),
+                    });
+                }
+                "frame" => post_process.frame = parse_values::<u64>(&words, 1, &location)?[0],
+                "exposure" => post_process.exposure = float(&words)?,
+                "bracket" => {
+                    bracket = Some(
+                        Bracket::parse(rest.trim()).map_err(|err| malformed(err.to_string()))?,
+                    )
+                }
+                "output_tonemap" => {
+                    post_process.tonemap = Some(match words.first() {
+                        Some(&"clamp") if words.len() == 1 => Tonemap::Clamp,
+                        Some(&"uncharted2") => Tonemap::Uncharted2 {
+                            exposure_bias: float(&words[1..])?,
+                        },
+                        _ => return Err(malformed(format!("unknown output tonemap '{}'", rest))),
+                    })
+                }
+                "grain" => {
+                    let (stage, values) = words
+                        .split_last()
+                        .ok_or_else(|| malformed("grain needs 5 values".to_string()))?;
+                    let v = parse_values::<Float>(values, 4, &location)?;
+                    post_process.grain = Some(FilmGrain {
+                        iso: v[0],
+                        read_noise: v[1],
+                        shot_noise: v[2],
+                        fixed_pattern: v[3],
+                        stage: GrainStage::from_name(stage)
+                            .ok_or_else(|| malformed(format!("unknown grain stage '{}'", stage)))?,
+                    });
+                }
+                "flare" => {
+                    let v = parse_values::<Float>(&words, 8, &location)?;
+                    let count = |value: Float| {
+                        if value >= 0.0 && value.fract() == 0.0 {
+                            Ok(value as usize)
+                        } else {
+                            Err(malformed(format!("'{}' is not a count", value)))
+                        }
+                    };
+                    post_process.flare = Some(LensFlare {
+                        threshold: v[0],
+                        ghost_count: count(v[1])?,
+                        ghost_spacing: v[2],
+                        intensity: v[3],
+                        ghost_falloff: v[4],
+                        streak_count: count(v[5])?,
+                        streak_length: count(v[6])?,
+                        streak_intensity: v[7],
+                    });
+                }
+                "shadow_catcher" => {
+                    shadow_catcher = Some(match words.split_first() {
+                        Some((&"plane", values)) => {
+                            let v = parse_values::<Float>(values, 6, &location)?;
+                            let normal = Vec3::new(v[3], v[4], v[5]);
+                            if normal == Vec3::zeros() {
+                                return Err(malformed(
+                                    "the shadow catcher plane's normal is zero".to_string(),
+                                ));
+                            }
+                            ShadowCatcher::Plane {
+                                point: Vec3::new(v[0], v[1], v[2]),
+                                normal,
+                            }
+                        }
+                        Some((&"objects", names)) if !names.is_empty() => {
+                            ShadowCatcher::Objects(names.iter().map(|s| s.to_string()).collect())
+                        }
+                        _ => {
+                            return Err(malformed(
+                                "shadow_catcher needs 'plane' and a point and normal, or \
+                                 'objects' and their names"
+                                    .to_string(),
+                            ))
+                        }
+                    })
+                }
+                "shadow_casters" => {
+                    if words.is_empty() {
+                        return Err(malformed("shadow_casters needs object names".to_string()));
+                    }
+                    shadow_casters = Some(words.iter().map(|s| s.to_string()).collect());
+                }
+                "shadow_sun" => {
+                    let v = parse_values::<Float>(&words, 2, &location)?;
+                    if !(0.0..180.0).contains(&v[0]) || !(0.0..=1.0).contains(&v[1]) {
+                        return Err(malformed(
+                            "shadow_sun needs an angle under 180 degrees and a fraction from 0 \
+                             to 1"
+                                .to_string(),
+                        ));
+                    }
+                    shadow_sun = Some((v[0], v[1]));
+                }
+                "animate" => animation
+                    .parse_line(&words)
+                    .map_err(|err| malformed(err.to_string()))?,
+                "camera_path" | "camera_key" => camera_path
+                    .parse_line(key, &words)
+                    .map_err(|err| malformed(err.to_string()))?,
+                // Lets a job that includes another take back what that one set
+                "unset" => match words.as_slice() {
+                    ["seed"] => seed = None,
+                    ["auto_stop"] => auto_stop = None,
+                    ["output_tonemap"] => post_process.tonemap = None,
+                    ["grain"] => post_process.grain = None,
+                    ["flare"] => post_process.flare = None,
+                    ["animate", object] => animation.tracks.retain(|track| track.object != *object),
+                    ["camera_path"] => camera_path = CameraPath::default(),
+                    ["shadow_catcher"] => {
+                        shadow_catcher = None;
+                        shadow_casters = None;
+                        shadow_sun = None;
+                    }
+                    ["shadow_casters"] => shadow_casters = None,
+                    ["bracket"] => bracket = None,
+                    _ => {
+                        return Err(malformed(format!(
+                            "can't unset '{}', only seed, auto_stop, output_tonemap, grain, \
+                             flare, animate <object>, camera_path, shadow_catcher, \
+                             shadow_casters or bracket",
+                            rest
+                        )))
+                    }
+                },
+                _ => return Err(malformed(format!("unknown setting '{}'", key))),
+            }
+        }
+
+        if shadow_catcher.is_none() && (shadow_casters.is_some() || shadow_sun.is_some()) {
+            return Err(JobError::Missing("shadow_catcher"));
+        }
+        let shadow_matte = shadow_catcher.map(|catcher| {
+            let (sun_angle, sun_fraction) =
+                shadow_sun.unwrap_or((DEFAULT_SUN_ANGLE, DEFAULT_SUN_FRACTION));
+            ShadowMatte {
+                catcher,
+                casters: shadow_casters,
+                sun_angle,
+                sun_fraction,
+            }
+        });
+        let (near, far) = t_range.ok_or(JobError::Missing("t_range"))?;
+        let (width, height) = resolution.ok_or(JobError::Missing("resolution"))?;
+        if integrator == Integrator::Technical {
+            post_process = technical::post_process(&post_process);
+        }
+        Ok(RenderJob {
+            center: center.ok_or(JobError::Missing("center"))?,
+            lookat: lookat.ok_or(JobError::Missing("lookat"))?,
+            up: up.ok_or(JobError::Missing("up"))?,
+            vertical_fov: vertical_fov.ok_or(JobError::Missing("vertical_fov"))?,
+            focus_distance: focus_distance.ok_or(JobError::Missing("focus_distance"))?,
+            defocus_angle: defocus_angle.ok_or(JobError::Missing("defocus_angle"))?,
+            near,
+            far,
+            width,
+            height,
+            samples_per_pixel: samples_per_pixel.ok_or(JobError::Missing("samples_per_pixel"))?,
+            max_depth: max_depth.ok_or(JobError::Missing("max_depth"))?,
+            fidelity: fidelity.ok_or(JobError::Missing("fidelity"))?,
+            integrator,
+            auto_stop,
+            throughput_threshold,
+            roulette_min_depth,
+            sun_sampling,
+            gamma: gamma.ok_or(JobError::Missing("gamma"))?,
+            output_path: output_path.ok_or(JobError::Missing("output"))?,
+            post_process,
+            scene_fingerprint: scene_fingerprint.ok_or(JobError::Missing("scene_fingerprint"))?,
+            seed,
+            animation,
+            camera_path,
+            shadow_matte,
+            bracket,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{scratch, write};
+    use crate::{
+        hittable::{Background, Sphere},
+        material::Lambertian,
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    fn ball() -> World {
+        let material = Lambertian::new(SolidColor::new(Vec3::new(0.7, 0.3, 0.2)).into());
+        let ball = Sphere::new(Vec3::zeros(), 1.0, Arc::new(material.into())).into();
+        let mut world = World::build(vec![ball]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::repeat(0.2),
+            top: Vec3::new(0.4, 0.6, 1.0),
+        };
+        world
+    }
+
+    /// A small seeded job of [`ball`]
+    fn base_job(world: &World) -> RenderJob {
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -6.0, 2.0))
+            .with_look_at(Vec3::zeros())
+            .with_resolution(8, 8)
+            .with_max_depth(3)
+            .build()
+            .unwrap();
+        camera.seed = Some(5);
+        let settings = HandoffSettings {
+            samples_per_pixel: 2,
+            resolution_scale: 1.0,
+            ..HandoffSettings::default()
+        };
+        RenderJob::from_camera(&camera, world, &settings)
+    }
+
+    /// The linear value of each channel of a binary PPM with gamma 2.2, and its header
+    fn read_ppm(path: &Path, width: usize, height: usize) -> (Vec<Float>, String) {
+        let bytes = fs::read(path).unwrap();
+        let (header, pixels) = bytes.split_at(bytes.len() - width * height * 3);
+        let linear = pixels
+            .iter()
+            .map(|&byte| (byte as Float / 255.0).powf(2.2))
+            .collect();
+        (linear, String::from_utf8(header.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn brackets_write_each_exposure_from_the_same_samples() {
+        let directory = scratch("bracket");
+        let world = ball();
+        let mut job = base_job(&world);
+        job.post_process.exposure = -0.5;
+        job.bracket = Some(Bracket::parse("-1..=1").unwrap());
+        let path = directory.join("ball.ppm");
+        let paths = job
+            .write_processed(job.render_linear(&world, None), path.to_str().unwrap())
+            .unwrap();
+        let expected = ["ball_ev-1.ppm", "ball_ev0.ppm", "ball_ev+1.ppm"]
+            .map(|name| directory.join(name).to_str().unwrap().to_string());
+        assert_eq!(paths, expected);
+        assert!(!path.exists());
+
+        let [under, even, over] = expected.map(|path| read_ppm(Path::new(&path), 8, 8));
+        for (ev, exposure, (_, header)) in [
+            ("-1", "-1.5", &under),
+            ("0", "-0.5", &even),
+            ("+1", "+0.5", &over),
+        ] {
+            assert!(
+                header.contains(&format!("# bracket: {} EV\n", ev)),
+                "{}",
+                header
+            );
+            assert!(
+                header.contains(&format!("# exposure: {} EV\n", exposure)),
+                "{}",
+                header
+            );
+        }
+        // A stop either way doubles or halves the light, up to the 8-bit rounding, on every
+        // channel bright enough to measure and not clipped
+        let mut checked = 0;
+        for ((&under, &even), &over) in under.0.iter().zip(&even.0).zip(&over.0) {
+            if even > 0.02 && over < 0.95 {
+                assert!((over / even - 2.0).abs() < 0.15, "{} then {}", even, over);
+                assert!((under / even - 0.5).abs() < 0.05, "{} then {}", even, under);
+                checked += 1;
+            }
+        }
+        assert!(checked > 100, "only {} channels", checked);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn later_files_override_settings_through_nested_includes() {
+        let directory = scratch("override");
+        let base = base_job(&ball());
+        write(
+            &directory.join("base.job"),
+            &(base.to_job_string() + "animate ball translate 0 0 0 0\n"),
+        );
+        write(
+            &directory.join("variants/night.job"),
+            "include common/dark.job\nsamples_per_pixel 3\nanimate ball translate 10 1 0 0\n",
+        );
+        write(
+            &directory.join("variants/common/dark.job"),
+            "exposure -1\nsamples_per_pixel 9\n",
+        );
+        let night = RenderJob::load_all(&[
+            directory.join("base.job"),
+            directory.join("variants/night.job"),
+        ])
+        .unwrap();
+        assert_eq!(night.post_process.exposure, -1.0);
+        // Set after the include, so it wins over it
+        assert_eq!(night.samples_per_pixel, 3);
+        // Keyframes from both files add up
+        assert_eq!(night.animation.tracks[0].translation.keyframes().len(), 2);
+        // Everything else is the base's
+        assert_eq!(
+            RenderJob {
+                samples_per_pixel: base.samples_per_pixel,
+                post_process: base.post_process.clone(),
+                animation: Animation::default(),
+                ..night.clone()
+            },
+            base
+        );
+
+        write(
+            &directory.join("replace.job"),
+            "unset animate ball\nanimate ball scale 0 2\n",
+        );
+        let replaced =
+            RenderJob::load_all(&[directory.join("base.job"), directory.join("replace.job")])
+                .unwrap();
+        let track = &replaced.animation.tracks[0];
+        assert!(track.translation.keyframes().is_empty());
+        assert_eq!(track.scale.keyframes().len(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn errors_point_at_the_file_they_are_in() {
+        let directory = scratch("location");
+        write(
+            &directory.join("base.job"),
+            &base_job(&ball()).to_job_string(),
+        );
+        write(&directory.join("bad.job"), "\nmax_depth lots\n");
+        let error = RenderJob::load_all(&[directory.join("base.job"), directory.join("bad.job")])
+            .unwrap_err();
+        assert!(
+            matches!(&error, JobError::Malformed { location, .. } if location.ends_with("bad.job:2")),
+            "{}",
+            error
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn overrides_only_change_what_they_set() {
+        let world = ball();
+        let base = base_job(&world);
+        let brighter = RenderJob::parse(&(base.to_job_string() + "exposure 1\n")).unwrap();
+        let (before, after) = (base.render(&world), brighter.render(&world));
+        // The same samples, only twice as bright
+        let doubled: Vec<Vec3> = before.pixels.iter().map(|color| color * 2.0).collect();
+        assert_eq!(after.pixels, doubled);
+        assert_eq!(
+            (after.width, after.height, after.gamma),
+            (before.width, before.height, before.gamma)
+        );
+    }
+}
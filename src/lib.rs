@@ -1,8 +1,74 @@
+pub mod accel;
+pub mod animation;
+pub mod assets;
+pub mod benchmark;
+pub mod bidirectional;
+pub mod boxes;
+pub mod bracket;
 pub mod camera;
+pub mod camera_path;
+pub mod colormap;
+pub mod compare;
+pub mod controls;
+pub mod convergence;
+pub mod cost;
+pub mod denoise;
+pub mod display;
+pub mod draft;
+pub mod environment;
+pub mod estimate;
+pub mod gltf_scene;
+pub mod glyphs;
+pub mod gpu;
 pub mod hittable;
+pub mod include;
+pub mod instance;
 pub mod intersection;
+pub mod job;
+pub mod lights;
 pub mod material;
+pub mod material_inference;
+pub mod material_library;
+pub mod material_override;
+pub mod medium;
+pub mod mesh;
+pub mod mesh_analysis;
+pub mod multiview;
+pub mod numeric;
+pub mod object;
+pub mod perf;
+pub mod perlin;
+pub mod postprocess;
+pub mod presenter;
+pub mod proxy;
+pub mod raster;
+pub mod rng;
+pub mod ron_format;
+pub mod scene_file;
+pub mod scene_lights;
 pub mod scenes;
+pub mod scopes;
+#[cfg(test)]
+mod scratch;
+pub mod sequence;
+pub mod session;
+pub mod shading;
+pub mod shadow_matte;
+pub mod sky_cache;
+pub mod sky_harmonics;
+pub mod sky_importance;
+pub mod snapshot;
+pub mod spatial_split;
+pub mod splat;
+pub mod streaming;
+pub mod technical;
 pub mod texture;
+pub mod texture_cache;
+pub mod tiles;
+pub mod tonemap;
+pub mod uv;
+pub mod validation;
+pub mod variations;
 pub mod vec3;
+pub mod watchdog;
 pub mod window;
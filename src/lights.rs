@@ -0,0 +1,713 @@
+use crate::{
+    camera::Float,
+    hittable::Shape,
+    intersection::Intersection,
+    material::{Material, Scatter},
+    scene_lights::{RectLight, SceneLight},
+    sky_importance::luminance,
+    vec3::{Point3, Vec2, Vec3},
+};
+use bvh::aabb::{Aabb, Bounded};
+use rand::Rng;
+use std::f64::consts::{PI, TAU};
+
+/// What light selection needs to know about one emitter
+#[derive(Debug, Clone)]
+pub struct LightBounds {
+    pub bounds: Aabb<Float, 3>,
+    /// Total power the light gives off, e.g. its luminance times its area
+    pub power: Float,
+}
+
+/// Index of a light in the list a [`LightSampler`] was built from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightRef(pub usize);
+
+/// How [`LightSampler::sample_light_for`] picks which light a shading point samples
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LightSelection {
+    /// Every light equally often
+    Uniform,
+    /// In proportion to each light's power, wherever the shading point is
+    Power,
+    /// Walks a tree of lights, preferring branches that are bright, close and in front of the
+    /// shading point. A simplified version of the light tree in Conty Estevez and Kulla's
+    /// "Importance Sampling of Many Lights with Adaptive Tree Splitting".
+    #[default]
+    Tree,
+}
+
+impl LightSelection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightSelection::Uniform => "uniform",
+            LightSelection::Power => "power",
+            LightSelection::Tree => "tree",
+        }
+    }
+
+    /// The inverse of [`LightSelection::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "uniform" => Some(LightSelection::Uniform),
+            "power" => Some(LightSelection::Power),
+            "tree" => Some(LightSelection::Tree),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum LightNode {
+    Leaf {
+        light: usize,
+        bounds: Aabb<Float, 3>,
+        power: Float,
+    },
+    Interior {
+        bounds: Aabb<Float, 3>,
+        power: Float,
+        children: [usize; 2],
+    },
+}
+
+impl LightNode {
+    fn bounds(&self) -> &Aabb<Float, 3> {
+        match self {
+            LightNode::Leaf { bounds, .. } | LightNode::Interior { bounds, .. } => bounds,
+        }
+    }
+
+    fn power(&self) -> Float {
+        match self {
+            LightNode::Leaf { power, .. } | LightNode::Interior { power, .. } => *power,
+        }
+    }
+
+    /// Estimates how much light everything under the node sends to a point with `normal`, or
+    /// to a point in a medium if `normal` is `None`. Never zero for lights that could reach it.
+    fn importance(&self, point: &Point3, normal: Option<&Vec3>) -> Float {
+        let bounds = self.bounds();
+        let center = bounds.center().coords;
+        let radius = bounds.half_size().norm();
+        let to_center = center - point;
+        let distance = to_center.norm();
+        if distance <= radius {
+            // Inside the bounds, where any direction could reach a light
+            return self.power() / (radius * radius).max(Float::MIN_POSITIVE);
+        }
+        let cos_term = match normal {
+            Some(normal) => {
+                // The bounds cover every direction within this angle of the one to their center
+                let spread = (radius / distance).asin();
+                let angle = (normal.dot(&to_center) / distance).clamp(-1.0, 1.0).acos();
+                (angle - spread).max(0.0).cos().max(0.0)
+            }
+            None => 1.0,
+        };
+        self.power() * cos_term / (distance * distance)
+    }
+}
+
+/// Picks which of many lights a shading point should sample, so scenes with hundreds of small
+/// emitters spend their shadow rays on the few that matter. Rebuild it whenever the lights change.
+#[derive(Debug, Clone)]
+pub struct LightSampler {
+    pub selection: LightSelection,
+    lights: Vec<LightBounds>,
+    /// Running total of the lights' power, for [`LightSelection::Power`]
+    power_cdf: Vec<Float>,
+    /// The root is the last node
+    nodes: Vec<LightNode>,
+    /// Which children each light's leaf is reached through, root first
+    paths: Vec<Vec<usize>>,
+}
+
+impl LightSampler {
+    pub fn new(lights: Vec<LightBounds>, selection: LightSelection) -> Self {
+        let mut total = 0.0;
+        let power_cdf = lights
+            .iter()
+            .map(|light| {
+                total += light.power.max(0.0);
+                total
+            })
+            .collect();
+        let mut sampler = LightSampler {
+            selection,
+            lights,
+            power_cdf,
+            nodes: Vec::new(),
+            paths: Vec::new(),
+        };
+        let mut indices: Vec<usize> = (0..sampler.lights.len()).collect();
+        if !indices.is_empty() {
+            sampler.build_node(&mut indices);
+            sampler.paths = vec![Vec::new(); sampler.lights.len()];
+            sampler.record_paths(sampler.nodes.len() - 1, &mut Vec::new());
+        }
+        sampler
+    }
+
+    pub fn lights(&self) -> &[LightBounds] {
+        &self.lights
+    }
+
+    /// Builds the subtree over `indices` by splitting their centers in half along the widest
+    /// axis, and returns the index of its root
+    fn build_node(&mut self, indices: &mut [usize]) -> usize {
+        if let [light] = indices {
+            let light = *light;
+            self.nodes.push(LightNode::Leaf {
+                light,
+                bounds: self.lights[light].bounds,
+                power: self.lights[light].power.max(0.0),
+            });
+            return self.nodes.len() - 1;
+        }
+        let centers = indices.iter().fold(Aabb::empty(), |centers, &light| {
+            centers.grow(&self.lights[light].bounds.center())
+        });
+        let axis = centers.largest_axis();
+        let middle = indices.len() / 2;
+        indices.select_nth_unstable_by(middle, |&a, &b| {
+            let center = |light: usize| self.lights[light].bounds.center()[axis];
+            center(a).total_cmp(&center(b))
+        });
+        let (left, right) = indices.split_at_mut(middle);
+        let children = [self.build_node(left), self.build_node(right)];
+        let bounds = self.nodes[children[0]]
+            .bounds()
+            .join(self.nodes[children[1]].bounds());
+        let power = self.nodes[children[0]].power() + self.nodes[children[1]].power();
+        self.nodes.push(LightNode::Interior {
+            bounds,
+            power,
+            children,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn record_paths(&mut self, node: usize, path: &mut Vec<usize>) {
+        match self.nodes[node] {
+            LightNode::Leaf { light, .. } => self.paths[light] = path.clone(),
+            LightNode::Interior { children, .. } => {
+                for (side, child) in children.into_iter().enumerate() {
+                    path.push(side);
+                    self.record_paths(child, path);
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    /// Probabilities of descending into each child of `children` from a shading point
+    fn child_probabilities(
+        &self,
+        children: [usize; 2],
+        point: &Point3,
+        normal: Option<&Vec3>,
+    ) -> [Float; 2] {
+        let importance = children.map(|child| self.nodes[child].importance(point, normal));
+        let total = importance[0] + importance[1];
+        if total > 0.0 {
+            return importance.map(|importance| importance / total);
+        }
+        // Nothing here can light the point, but a light must still be picked with some chance
+        let power = children.map(|child| self.nodes[child].power());
+        let total = power[0] + power[1];
+        if total > 0.0 {
+            power.map(|power| power / total)
+        } else {
+            [0.5, 0.5]
+        }
+    }
+
+    /// Picks a light for the shading point at `point` with `normal` (`None` inside media) and
+    /// returns it with the probability it had of being picked, which the light's own sampling
+    /// pdf has to be multiplied by. Returns `None` if there are no lights.
+    pub fn sample_light_for<R: Rng + ?Sized>(
+        &self,
+        point: &Point3,
+        normal: Option<&Vec3>,
+        rng: &mut R,
+    ) -> Option<(LightRef, Float)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        match self.selection {
+            // Neither depends on where the shading point is
+            LightSelection::Uniform | LightSelection::Power => self.sample_light(rng),
+            LightSelection::Tree => {
+                let mut node = self.nodes.len() - 1;
+                let mut pdf = 1.0;
+                loop {
+                    match self.nodes[node] {
+                        LightNode::Leaf { light, .. } => return Some((LightRef(light), pdf)),
+                        LightNode::Interior { children, .. } => {
+                            let probabilities = self.child_probabilities(children, point, normal);
+                            let side = usize::from(rng.gen::<Float>() >= probabilities[0]);
+                            pdf *= probabilities[side];
+                            node = children[side];
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Picks a light with no shading point to go by, e.g. one for a path to start from:
+    /// uniformly with [`LightSelection::Uniform`] and in proportion to power otherwise. Returns
+    /// it with the probability it had of being picked, or `None` if there are no lights.
+    pub fn sample_light<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(LightRef, Float)> {
+        let count = self.lights.len();
+        let total = *self.power_cdf.last()?;
+        if self.selection == LightSelection::Uniform || total <= 0.0 {
+            let light = LightRef(rng.gen_range(0..count));
+            return Some((light, self.light_pdf(light)));
+        }
+        let u = rng.gen::<Float>() * total;
+        let light = self
+            .power_cdf
+            .partition_point(|&running| running <= u)
+            .min(count - 1);
+        Some((LightRef(light), self.light_pdf(LightRef(light))))
+    }
+
+    /// Returns the probability [`LightSampler::sample_light`] has of picking `light`
+    pub fn light_pdf(&self, light: LightRef) -> Float {
+        let count = self.lights.len();
+        if light.0 >= count {
+            return 0.0;
+        }
+        let total = self.power_cdf[count - 1];
+        if self.selection == LightSelection::Uniform || total <= 0.0 {
+            1.0 / count as Float
+        } else {
+            self.lights[light.0].power.max(0.0) / total
+        }
+    }
+
+    /// Returns the probability [`LightSampler::sample_light_for`] has of picking `light` for
+    /// the same shading point, for weighting light samples against other strategies
+    pub fn selection_pdf(&self, point: &Point3, normal: Option<&Vec3>, light: LightRef) -> Float {
+        let count = self.lights.len();
+        if light.0 >= count {
+            return 0.0;
+        }
+        match self.selection {
+            LightSelection::Uniform | LightSelection::Power => self.light_pdf(light),
+            LightSelection::Tree => {
+                let mut node = self.nodes.len() - 1;
+                let mut pdf = 1.0;
+                for &side in &self.paths[light.0] {
+                    let LightNode::Interior { children, .. } = self.nodes[node] else {
+                        unreachable!("light paths only pass through interior nodes");
+                    };
+                    pdf *= self.child_probabilities(children, point, normal)[side];
+                    node = children[side];
+                }
+                pdf
+            }
+        }
+    }
+}
+
+/// Points per side of the grid an area light's radiance is averaged over to estimate its power
+const POWER_ESTIMATE_GRID: usize = 4;
+
+/// A point picked on an area light by [`AreaLights::sample`]
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterSample {
+    pub light: LightRef,
+    pub point: Point3,
+    /// Unit normal on the side the light gives off light from
+    pub normal: Vec3,
+    /// Radiance given off from `point` toward the side `normal` points to
+    pub radiance: Vec3,
+    /// Probability density of picking `point`, per unit area, including picking its light
+    pub pdf: Float,
+}
+
+/// The emissive spheres and triangles of a world, and its visible rect lights, which the
+/// bidirectional path tracer starts light paths from. There's no shading point yet when a light
+/// path starts, so lights are picked with [`LightSampler::sample_light`], in proportion to their
+/// power unless the selection is uniform.
+///
+/// Emissive surfaces inside instances, split into fragments or on other shapes aren't included.
+/// Paths that hit them still pick up their light, but they're never sampled. Neither are spot
+/// lights and hidden rect lights, which only light the path tracer's renders.
+#[derive(Debug, Clone)]
+pub struct AreaLights {
+    /// Index of each light's shape among the world's shapes
+    shapes: Vec<usize>,
+    areas: Vec<Float>,
+    sampler: LightSampler,
+}
+
+/// The emissive sphere, triangle or visible rect light `shape` is, with its area
+fn emitter(shape: &Shape) -> Option<(&Material, Float)> {
+    let (material, area) = match shape {
+        Shape::Sphere(sphere) => (&*sphere.material, sphere.area()),
+        Shape::Triangle(triangle) => (&*triangle.material, triangle.area()),
+        Shape::RectLight(light) if light.visible => (&*light.material, light.area()),
+        _ => return None,
+    };
+    (material.is_emissive() && area > 0.0).then_some((material, area))
+}
+
+/// Maps `(s, t)` in the unit square to a point on the emitter `shape`, see
+/// [`crate::hittable::Sphere::surface_point`]
+fn surface_point(shape: &Shape, s: Float, t: Float) -> (Point3, Vec3, Vec2) {
+    match shape {
+        Shape::Sphere(sphere) => sphere.surface_point(s, t),
+        Shape::Triangle(triangle) => triangle.surface_point(s, t),
+        Shape::RectLight(light) => light.surface_point(s, t),
+        _ => unreachable!("only spheres, triangles and rect lights are area lights"),
+    }
+}
+
+/// The `(s, t)` that [`surface_point`] maps to `point` on the emitter `shape`
+#[cfg(feature = "diagnostics")]
+fn surface_coordinates(shape: &Shape, point: &Point3) -> (Float, Float) {
+    match shape {
+        Shape::Sphere(sphere) => sphere.surface_coordinates(point),
+        Shape::Triangle(triangle) => triangle.surface_coordinates(point),
+        Shape::RectLight(light) => light.surface_coordinates(point),
+        _ => unreachable!("only spheres, triangles and rect lights are area lights"),
+    }
+}
+
+/// Radiance `material` gives off from `point` on its front, toward `normal`
+fn radiance(material: &Material, point: Point3, normal: Vec3, uv: Vec2) -> Vec3 {
+    material.emitted(&Intersection::new(point, normal, 0.0, material, true, uv))
+}
+
+impl AreaLights {
+    pub fn new(shapes: &[Shape], selection: LightSelection) -> Self {
+        let mut lights = AreaLights {
+            shapes: Vec::new(),
+            areas: Vec::new(),
+            sampler: LightSampler::new(Vec::new(), selection),
+        };
+        let mut bounds = Vec::new();
+        for (index, shape) in shapes.iter().enumerate() {
+            let Some((material, area)) = emitter(shape) else {
+                continue;
+            };
+            let cells = (0..POWER_ESTIMATE_GRID * POWER_ESTIMATE_GRID).map(|cell| {
+                let s = (cell / POWER_ESTIMATE_GRID) as Float + 0.5;
+                let t = (cell % POWER_ESTIMATE_GRID) as Float + 0.5;
+                let grid = POWER_ESTIMATE_GRID as Float;
+                let (point, normal, uv) = surface_point(shape, s / grid, t / grid);
+                luminance(&radiance(material, point, normal, uv))
+            });
+            let average =
+                cells.sum::<Float>() / (POWER_ESTIMATE_GRID * POWER_ESTIMATE_GRID) as Float;
+            lights.shapes.push(index);
+            lights.areas.push(area);
+            bounds.push(LightBounds {
+                bounds: shape.aabb(),
+                power: average * area,
+            });
+        }
+        lights.sampler = LightSampler::new(bounds, selection);
+        lights
+    }
+
+    pub fn len(&self) -> usize {
+        self.shapes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    /// Picks a light and a point on it evenly by area. `shapes` are the shapes the lights were
+    /// found among. Returns `None` if there are no lights.
+    pub fn sample<R: Rng + ?Sized>(&self, shapes: &[Shape], rng: &mut R) -> Option<EmitterSample> {
+        let (light, selection_pdf) = self.sampler.sample_light(rng)?;
+        let shape = &shapes[self.shapes[light.0]];
+        let (material, _) = emitter(shape)?;
+        let (point, normal, uv) = surface_point(shape, rng.gen(), rng.gen());
+        #[cfg(feature = "diagnostics")]
+        {
+            let (s, t) = surface_coordinates(shape, &point);
+            crate::splat::record_light(crate::splat::LightSplat {
+                light: crate::splat::SplatLight::Area(light.0),
+                s,
+                t,
+            });
+        }
+        Some(EmitterSample {
+            light,
+            point,
+            normal,
+            radiance: radiance(material, point, normal, uv),
+            pdf: selection_pdf / self.areas[light.0],
+        })
+    }
+
+    /// Returns the probability density of [`AreaLights::sample`] picking a point on `light`,
+    /// per unit area
+    pub fn pdf(&self, light: LightRef) -> Float {
+        self.sampler.light_pdf(light) / self.areas[light.0]
+    }
+
+    /// Finds the light that `point`, on a surface with `material`, lies on. `None` for points
+    /// on emissive surfaces that aren't sampled.
+    pub fn light_at(
+        &self,
+        shapes: &[Shape],
+        point: &Point3,
+        material: &Material,
+        tolerance: Float,
+    ) -> Option<LightRef> {
+        self.shapes
+            .iter()
+            .position(|&index| match &shapes[index] {
+                Shape::Sphere(sphere) => {
+                    std::ptr::eq(&*sphere.material, material) && sphere.contains(point, tolerance)
+                }
+                Shape::Triangle(triangle) => {
+                    std::ptr::eq(&*triangle.material, material)
+                        && triangle.contains(point, tolerance)
+                }
+                Shape::RectLight(light) => {
+                    std::ptr::eq(&*light.material, material) && light.contains(point, tolerance)
+                }
+                _ => false,
+            })
+            .map(LightRef)
+    }
+}
+
+/// The spot and rect lights of a world, which the path tracer samples from diffuse hits. Each hit
+/// samples one of them, picked by a [`LightSampler`], so scenes with hundreds of lights don't
+/// trace hundreds of shadow rays per hit.
+///
+/// Lights inside instances aren't included.
+#[derive(Debug, Clone)]
+pub struct SceneLights {
+    /// Index of each light's shape among the world's shapes
+    shapes: Vec<usize>,
+    sampler: LightSampler,
+}
+
+impl SceneLights {
+    pub fn new(shapes: &[Shape], selection: LightSelection) -> Self {
+        let mut indices = Vec::new();
+        let mut bounds = Vec::new();
+        for (index, shape) in shapes.iter().enumerate() {
+            let power = match shape {
+                Shape::RectLight(light) => {
+                    let sides = if light.double_sided { 2.0 } else { 1.0 };
+                    luminance(&light.radiance()) * light.area() * PI * sides
+                }
+                // The solid angle of its cone, ignoring the falloff and profile inside it
+                Shape::SpotLight(light) => {
+                    luminance(&light.intensity()) * TAU * (1.0 - light.cone()[1])
+                }
+                _ => continue,
+            };
+            indices.push(index);
+            bounds.push(LightBounds {
+                bounds: shape.aabb(),
+                power,
+            });
+        }
+        SceneLights {
+            shapes: indices,
+            sampler: LightSampler::new(bounds, selection),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shapes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    /// The light `light` is, among `shapes`, the shapes the lights were found among
+    pub fn get<'a>(&self, shapes: &'a [Shape], light: LightRef) -> SceneLight<'a> {
+        match &shapes[self.shapes[light.0]] {
+            Shape::RectLight(light) => SceneLight::Rect(light),
+            Shape::SpotLight(light) => SceneLight::Spot(light),
+            _ => unreachable!("only spot and rect lights are scene lights"),
+        }
+    }
+
+    /// Picks the light the diffuse hit at `point` with `normal` samples, see
+    /// [`LightSampler::sample_light_for`]
+    pub fn sample_for<'a, R: Rng + ?Sized>(
+        &self,
+        shapes: &'a [Shape],
+        point: &Point3,
+        normal: &Vec3,
+        rng: &mut R,
+    ) -> Option<(SceneLight<'a>, Float)> {
+        let (light, pdf) = self.sampler.sample_light_for(point, Some(normal), rng)?;
+        Some((self.get(shapes, light), pdf))
+    }
+
+    /// Returns the probability [`SceneLights::sample_for`] has of picking `light` for the same
+    /// hit
+    pub fn selection_pdf(&self, point: &Point3, normal: &Vec3, light: LightRef) -> Float {
+        self.sampler.selection_pdf(point, Some(normal), light)
+    }
+
+    /// The rect light whose surface has `material`, if there is one
+    pub fn rect_with<'a>(
+        &self,
+        shapes: &'a [Shape],
+        material: &Material,
+    ) -> Option<(LightRef, &'a RectLight)> {
+        (0..self.len()).find_map(|light| match self.get(shapes, LightRef(light)) {
+            SceneLight::Rect(rect) if std::ptr::eq(&*rect.material, material) => {
+                Some((LightRef(light), rect))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Small panels facing down from a ceiling, of very different power, with a few more under
+    /// the floor that shading points on it can't see
+    fn panels(count: usize, rng: &mut StdRng) -> Vec<(Point3, Float)> {
+        (0..count)
+            .map(|i| {
+                let height = if i % 10 == 0 {
+                    -2.0
+                } else {
+                    4.0 + rng.gen::<Float>()
+                };
+                let center = Vec3::new(
+                    rng.gen_range(-20.0..20.0),
+                    rng.gen_range(-20.0..20.0),
+                    height,
+                );
+                (center, (rng.gen::<Float>() * 6.0).exp() * 0.01)
+            })
+            .collect()
+    }
+
+    /// Light from `panel` at a point on the floor facing up, as if it were a point light
+    fn contribution(point: &Point3, (center, power): &(Point3, Float)) -> Float {
+        let to_light = center - point;
+        let distance_squared = to_light.norm_squared();
+        // Both the floor and the panel face along Z
+        let cos = (to_light.z / distance_squared.sqrt()).max(0.0);
+        power * cos * cos / distance_squared
+    }
+
+    fn sampler(panels: &[(Point3, Float)], selection: LightSelection) -> LightSampler {
+        let bounds = panels
+            .iter()
+            .map(|(center, power)| LightBounds {
+                bounds: Aabb::with_bounds(
+                    (center - Vec3::new(0.1, 0.1, 0.0)).into(),
+                    (center + Vec3::new(0.1, 0.1, 0.0)).into(),
+                ),
+                power: *power,
+            })
+            .collect();
+        LightSampler::new(bounds, selection)
+    }
+
+    #[test]
+    fn selection_pdfs_match_sampling_and_sum_to_one() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let panels = panels(37, &mut rng);
+        let point = Vec3::new(1.0, -2.0, 0.0);
+        for selection in [
+            LightSelection::Uniform,
+            LightSelection::Power,
+            LightSelection::Tree,
+        ] {
+            let sampler = sampler(&panels, selection);
+            let total: Float = (0..panels.len())
+                .map(|light| sampler.selection_pdf(&point, Some(&Vec3::z()), LightRef(light)))
+                .sum();
+            assert!(
+                (total - 1.0).abs() < 1e-9,
+                "{:?} pdfs sum to {}",
+                selection,
+                total
+            );
+            for _ in 0..100 {
+                let (light, pdf) = sampler
+                    .sample_light_for(&point, Some(&Vec3::z()), &mut rng)
+                    .unwrap();
+                let expected = sampler.selection_pdf(&point, Some(&Vec3::z()), light);
+                assert!((pdf - expected).abs() < 1e-12, "{:?}", selection);
+            }
+        }
+        assert!(sampler(&[], LightSelection::Tree)
+            .sample_light_for(&point, None, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn aware_selection_needs_fewer_samples_than_uniform() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let panels = panels(500, &mut rng);
+        let points: Vec<Point3> = (0..20)
+            .map(|_| Vec3::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), 0.0))
+            .collect();
+        // Variance of one-light estimates of the light reaching each point, summed over them
+        let variance = |selection| {
+            let sampler = sampler(&panels, selection);
+            let mut rng = StdRng::seed_from_u64(3);
+            let mut total = 0.0;
+            for point in &points {
+                let exact: Float = panels.iter().map(|panel| contribution(point, panel)).sum();
+                let samples = 4000;
+                let (mut sum, mut sum_squared) = (0.0, 0.0);
+                for _ in 0..samples {
+                    let (light, pdf) = sampler
+                        .sample_light_for(point, Some(&Vec3::z()), &mut rng)
+                        .unwrap();
+                    let estimate = contribution(point, &panels[light.0]) / pdf;
+                    sum += estimate;
+                    sum_squared += estimate * estimate;
+                }
+                let mean = sum / samples as Float;
+                let variance = sum_squared / samples as Float - mean * mean;
+                // Every strategy is unbiased, so they only differ in noise
+                let error = (variance / samples as Float).sqrt();
+                assert!(
+                    (mean - exact).abs() < 5.0 * error + 1e-9,
+                    "{:?} estimated {} instead of {}",
+                    selection,
+                    mean,
+                    exact
+                );
+                total += variance / (exact * exact);
+            }
+            total
+        };
+        let uniform = variance(LightSelection::Uniform);
+        let power = variance(LightSelection::Power);
+        let tree = variance(LightSelection::Tree);
+        assert!(
+            power < uniform,
+            "power {} against uniform {}",
+            power,
+            uniform
+        );
+        // The samples needed for the same noise go with the variance
+        assert!(
+            tree * 4.0 < uniform,
+            "tree {} against uniform {}",
+            tree,
+            uniform
+        );
+    }
+}
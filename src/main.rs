@@ -1,36 +1,1180 @@
 #![allow(unused)]
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
+
+use rand::{rngs::StdRng, SeedableRng};
 
 use scenes::sponza;
 
 use crate::{
-    hittable::World,
+    accel::{BvhLayout, LayoutComparison},
+    assets::{AssetReference, AssetResolver},
+    bracket::Bracket,
+    camera::{Camera, Float, Integrator},
+    compare::Comparison,
+    convergence::StopCriterion,
+    cost::{CostMap, CostMetric, CostScale},
+    denoise::DenoiseParams,
+    estimate::{CostLimits, Decision},
+    gpu::GpuPrimary,
+    hittable::{LoadOptions, Shape, Surroundings, World},
+    job::{HandoffSettings, RenderJob},
     material::Lambertian,
     material::{Dielectric, Material, Metal},
+    multiview::{MultiMode, MultiSettings},
+    perf::{PerfLog, PerfReport},
+    scene_file::{SceneError, SceneFile},
+    sequence::SequenceOptions,
+    session::{Session, SessionRecorder, SessionStart},
+    splat::{Heatmap, SplatLight},
     texture::{CheckerTexture, SolidColor},
+    texture_cache::TextureCache,
+    tiles::{ExecutionOptions, TileRenderer},
     vec3::Vec3,
+    window::{PreviewScene, PreviewSession},
 };
 
+pub mod accel;
+pub mod animation;
+pub mod assets;
+pub mod benchmark;
+pub mod bidirectional;
+pub mod boxes;
+pub mod bracket;
 pub mod camera;
+pub mod camera_path;
+pub mod colormap;
+pub mod compare;
+pub mod controls;
+pub mod convergence;
+pub mod cost;
+pub mod denoise;
+pub mod display;
+pub mod draft;
+pub mod environment;
+pub mod estimate;
+pub mod gltf_scene;
+pub mod glyphs;
+pub mod gpu;
 pub mod hittable;
+pub mod include;
+pub mod instance;
 pub mod intersection;
+pub mod job;
+pub mod lights;
 pub mod material;
+pub mod material_inference;
+pub mod material_library;
+pub mod material_override;
+pub mod medium;
+pub mod mesh;
+pub mod mesh_analysis;
+pub mod multiview;
+pub mod numeric;
+pub mod object;
+pub mod perf;
+pub mod perlin;
+pub mod postprocess;
+pub mod presenter;
+pub mod proxy;
+pub mod raster;
+pub mod rng;
+pub mod ron_format;
+pub mod scene_file;
+pub mod scene_lights;
 pub mod scenes;
+pub mod scopes;
+#[cfg(test)]
+mod scratch;
+pub mod sequence;
+pub mod session;
+pub mod shading;
+pub mod shadow_matte;
+pub mod sky_cache;
+pub mod sky_harmonics;
+pub mod sky_importance;
+pub mod snapshot;
+pub mod spatial_split;
+pub mod splat;
+pub mod streaming;
+pub mod technical;
 pub mod texture;
+pub mod texture_cache;
+pub mod tiles;
+pub mod tonemap;
+pub mod uv;
+pub mod validation;
+pub mod variations;
 pub mod vec3;
+pub mod watchdog;
 pub mod window;
 
 fn main() {
     env_logger::init();
     std::env::set_var("RUST_BACKTRACE", "FULL");
 
+    // See the usage section of the README for the commands and flags
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, rest @ ..] = args.as_slice() {
+        let first_flag = rest.iter().position(|arg| arg.starts_with("--"));
+        let (job_paths, flags) = rest.split_at(first_flag.unwrap_or(rest.len()));
+        let result = match command.as_str() {
+            "perf-report" => Some(perf_report(job_paths)),
+            "mesh-check" => Some(mesh_check(job_paths)),
+            "bvh-bench" if job_paths.is_empty() => Some(bvh_bench(flags)),
+            "assets" if job_paths.is_empty() => Some(list_assets(flags)),
+            "light-splats" if job_paths.is_empty() => Some(light_splats(flags)),
+            "variations" if job_paths.is_empty() => Some(render_variations(flags)),
+            _ if job_paths.is_empty() => None,
+            "render" => Some(render_job(job_paths, flags)),
+            "debug-pixel" => Some(debug_pixel(job_paths, flags)),
+            _ => None,
+        };
+        if let Some(result) = result {
+            if command == "render" {
+                report_validation();
+            }
+            match result {
+                Ok(()) => return,
+                Err(err) if err.is::<Declined>() => {
+                    println!("{}", err);
+                    std::process::exit(estimate::DECLINED_EXIT_CODE);
+                }
+                Err(err) => {
+                    println!("Err: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    if args[1..].iter().any(|arg| arg == "--headless") {
+        let result = headless(&args[1..]);
+        report_validation();
+        if let Err(err) = result {
+            println!("Err: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(err) = preview(&args[1..]) {
+        println!("Err: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Prints the color pipeline's violations during a render, in builds that check for them
+fn report_validation() {
+    if validation::ENABLED {
+        println!("{}", validation::take_report());
+    }
+}
+
+/// Renders the scene with its camera's settings, or the ones given, and writes it out
+fn headless(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut width = None;
+    let mut height = None;
+    let mut samples = None;
+    let mut max_depth = None;
+    let mut seed = None;
+    let mut output = "final_out.ppm".to_string();
+    let mut scene = None;
+    let mut replay = None;
+    let mut expect = None;
+    let mut cost = None;
+    let mut cost_metric = CostMetric::Time;
+    let mut cost_scale = CostScale::default();
+    let mut technical = false;
+    let mut quick_denoise = None;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut count = || -> Result<usize, String> {
+            let value = flags.next().ok_or(format!("{} needs a value", flag))?;
+            value
+                .parse()
+                .ok()
+                .filter(|count| *count > 0)
+                .ok_or_else(|| format!("'{}' is not a positive whole number", value))
+        };
+        match flag.as_str() {
+            "--headless" => {}
+            "--width" => width = Some(count()?),
+            "--height" => height = Some(count()?),
+            "--samples" => samples = Some(count()?),
+            "--max-depth" => max_depth = Some(count()?),
+            "--seed" => seed = Some(seed_flag(flags.next())?),
+            "--output" => output = flags.next().ok_or("--output needs a path")?.clone(),
+            "--scene" => scene = Some(flags.next().ok_or("--scene needs a name or path")?.clone()),
+            "--replay" => replay = Some(flags.next().ok_or("--replay needs a session")?),
+            "--expect" => {
+                let value = flags.next().ok_or("--expect needs a fingerprint")?;
+                let fingerprint = u64::from_str_radix(value, 16)
+                    .map_err(|_| format!("'{}' is not a fingerprint, which is hex", value))?;
+                expect = Some(fingerprint);
+            }
+            "--cost" => cost = Some(flags.next().ok_or("--cost needs a path")?),
+            "--preset" => technical = preset_flag(flags.next())?,
+            "--quick-denoise" => quick_denoise = Some(quick_denoise_flag(flags.next())?),
+            _ if cost_flag(flag, &mut flags, &mut cost_metric, &mut cost_scale)? => {}
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    if expect.is_some() && replay.is_none() {
+        return Err("--expect only checks the fingerprint of a --replay".into());
+    }
+    let session = replay.map(Session::load).transpose()?;
+    let scene = scene.or_else(|| {
+        session
+            .as_ref()
+            .and_then(|session| session.start.scene.clone())
+    });
+    let output = Path::new(&output);
+    // Replays lay the scene out as the preview they were recorded in did
+    let layout_seed = seed.filter(|_| session.is_none());
+    let (camera, shapes, surroundings) = scene_shapes(scene.as_ref(), layout_seed)?;
+    let world = surroundings.build(shapes);
+    let camera = match &session {
+        Some(session) => {
+            session.check_scene(&world)?;
+            let replayed = session::replay_headless(session, &camera);
+            println!(
+                "Replayed {} recorded edits, ending at {}",
+                session.entries.len(),
+                multiview::describe_camera(&replayed)
+            );
+            replayed
+        }
+        None => camera,
+    };
+    let mut camera = camera
+        .with_resolution(
+            width.unwrap_or(camera.image_width),
+            height.unwrap_or(camera.image_height),
+        )
+        .with_sampling(
+            samples.unwrap_or(camera.samples_per_pixel()),
+            max_depth.unwrap_or(camera.max_depth()),
+        );
+    camera.seed = seed.or(camera.seed);
+    if technical {
+        technical::preset(&mut camera);
+    }
+    camera.quick_denoise = quick_denoise;
+    // Found out before rendering rather than after, without losing what's there already
+    Camera::claim_image_path(output)
+        .map_err(|err| format!("can't write to '{}': {}", output.display(), err))?;
+    println!(
+        "Rendering {}x{} at {} samples per pixel",
+        camera.image_width,
+        camera.image_height,
+        camera.samples_per_pixel()
+    );
+    let costs = cost.map(|_| CostMap::new(camera.image_width, camera.image_height));
+    let image = match &costs {
+        Some(costs) => camera.render_image_with_cost(&world, costs),
+        None => camera.render_image(&world),
+    };
+    let fingerprint = session::image_fingerprint(&image);
+    Camera::replace_image(image, output)
+        .map_err(|err| format!("can't write to '{}': {}", output.display(), err))?;
+    println!("Wrote {}", output.display());
+    if let (Some(path), Some(costs)) = (cost, &costs) {
+        let heatmap = costs.to_image(cost_metric, cost_scale);
+        let described = heatmap.metadata[0].clone();
+        Camera::save_image(heatmap, Path::new(path))
+            .map_err(|err| format!("can't write to '{}': {}", path, err))?;
+        println!("Wrote {} ({})", path, described);
+    }
+    if session.is_some() {
+        println!("Final accumulation fingerprint: {:016x}", fingerprint);
+    }
+    match expect {
+        Some(expected) if expected != fingerprint => Err(format!(
+            "the replay's fingerprint is {:016x}, but {:016x} was expected",
+            fingerprint, expected
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn preview(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reference = None;
+    let mut execution = ExecutionOptions::default();
+    let mut tiled = false;
+    let mut gpu_primary = false;
+    let mut scene = None;
+    let mut bracket = None;
+    let mut record = None;
+    let mut seed = None;
+    let mut replay = None;
+    let mut replay_speed = None;
+    let mut cost_scale = CostScale::default();
+    let mut technical = false;
+    let mut quick_denoise = None;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--gpu-primary" => gpu_primary = true,
+            "--preset" => technical = preset_flag(flags.next())?,
+            "--quick-denoise" => quick_denoise = Some(quick_denoise_flag(flags.next())?),
+            "--scene" => scene = Some(flags.next().ok_or("--scene needs a path")?.clone()),
+            "--perf-log" => PerfLog::install(flags.next().ok_or("--perf-log needs a path")?)?,
+            "--reference" => {
+                reference = Some(flags.next().ok_or("--reference needs an image")?);
+            }
+            "--bracket" => bracket = Some(bracket_flag(flags.next())?),
+            "--record" => record = Some(flags.next().ok_or("--record needs a path")?),
+            "--seed" => seed = Some(seed_flag(flags.next())?),
+            "--replay" => replay = Some(flags.next().ok_or("--replay needs a session")?),
+            "--replay-speed" => {
+                let value = flags.next().ok_or("--replay-speed needs a speed")?;
+                let speed = value
+                    .parse()
+                    .ok()
+                    .filter(|speed: &Float| *speed > 0.0)
+                    .ok_or_else(|| format!("'{}' is not a positive speed", value))?;
+                replay_speed = Some(speed);
+            }
+            _ if execution_flag(flag, &mut flags, &mut execution)? => tiled = true,
+            // The metric is picked with H in the window
+            _ if cost_flag(flag, &mut flags, &mut CostMetric::Time, &mut cost_scale)? => {}
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    if gpu_primary && tiled {
+        return Err("--gpu-primary doesn't work with --threads yet".into());
+    }
+    if record.is_some() && replay.is_some() {
+        return Err("--record and --replay can't be used together".into());
+    }
+    if seed.is_some() && record.is_none() {
+        return Err(
+            "the preview isn't seeded, --seed only sets the seed a --record replays \
+                    with"
+                .into(),
+        );
+    }
+    if replay_speed.is_some() && replay.is_none() {
+        return Err("--replay-speed needs a --replay".into());
+    }
+    let tiles = tiled.then(|| TileRenderer::new(&execution)).transpose()?;
+
+    let session = replay.map(Session::load).transpose()?;
+    let scene = scene.or_else(|| {
+        session
+            .as_ref()
+            .and_then(|session| session.start.scene.clone())
+    });
+    let (mut camera, shapes, surroundings) = scene_shapes(scene.as_ref(), None)?;
+    if technical {
+        technical::preset(&mut camera);
+    }
+    camera.quick_denoise = quick_denoise;
+    let (camera, scene, session) = match session {
+        // Replays only start once the scene is known to be the one that was recorded
+        Some(session) => {
+            let world = surroundings.build(shapes);
+            session.check_scene(&world)?;
+            let camera = session.start.camera(&camera);
+            let speed = replay_speed.unwrap_or(1.0);
+            let session = PreviewSession::Replay {
+                session: Box::new(session),
+                speed,
+            };
+            (camera, PreviewScene::Ready(Box::new(world)), session)
+        }
+        // The window opens on a blocky proxy of the scene while its BVH builds
+        None => {
+            let session = match record {
+                Some(path) => {
+                    let start = SessionStart::new(&camera, scene.as_deref(), seed);
+                    PreviewSession::Record(SessionRecorder::create(path, &start)?)
+                }
+                None => PreviewSession::Off,
+            };
+            (
+                camera,
+                PreviewScene::Loading(shapes, Box::new(surroundings)),
+                session,
+            )
+        }
+    };
+    let comparison = reference
+        .map(|path| Comparison::load(path, camera.image_width, camera.image_height, camera.gamma))
+        .transpose()?;
+    let handoff = HandoffSettings {
+        bracket,
+        ..HandoffSettings::default()
+    };
+    Ok(window::render_with_handoff(
+        camera,
+        scene,
+        handoff,
+        comparison,
+        tiles,
+        gpu_primary,
+        session,
+        cost_scale,
+    )?)
+}
+
+/// Handles `--threads <n>` and `--affinity`, returning whether `flag` was one of them
+fn execution_flag<'a>(
+    flag: &str,
+    flags: &mut impl Iterator<Item = &'a String>,
+    execution: &mut ExecutionOptions,
+) -> Result<bool, String> {
+    match flag {
+        "--threads" => {
+            let value = flags.next().ok_or("--threads needs a thread count")?;
+            let threads = value
+                .parse()
+                .map_err(|_| format!("'{}' is not a thread count", value))?;
+            execution.threads = Some(threads);
+        }
+        "--affinity" => execution.affinity = true,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Handles `--cost-metric <time|rays>`, `--cost-max <cost>` and `--cost-percentile <p>`, which
+/// set how cost heatmaps are drawn, returning whether `flag` was one of them
+fn cost_flag<'a>(
+    flag: &str,
+    flags: &mut impl Iterator<Item = &'a String>,
+    metric: &mut CostMetric,
+    scale: &mut CostScale,
+) -> Result<bool, String> {
+    let mut value = || flags.next().ok_or(format!("{} needs a value", flag));
+    match flag {
+        "--cost-metric" => {
+            let name = value()?;
+            *metric = CostMetric::from_name(name)
+                .ok_or_else(|| format!("'{}' is not a cost metric, try time or rays", name))?;
+        }
+        "--cost-max" => {
+            let max = value()?;
+            *scale = CostScale::Absolute(
+                max.parse()
+                    .ok()
+                    .filter(|max: &Float| *max > 0.0)
+                    .ok_or_else(|| format!("'{}' is not a positive cost", max))?,
+            );
+        }
+        "--cost-percentile" => {
+            let percentile = value()?;
+            *scale = CostScale::Percentile(
+                percentile
+                    .parse()
+                    .ok()
+                    .filter(|percentile: &Float| (0.0..=100.0).contains(percentile))
+                    .ok_or_else(|| format!("'{}' is not a percentile", percentile))?,
+            );
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// `rt render` didn't start because the render was over its cost limits and wasn't confirmed
+#[derive(Debug)]
+struct Declined;
+
+impl std::fmt::Display for Declined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not rendering")
+    }
+}
+
+impl std::error::Error for Declined {}
+
+fn render_job(job_paths: &[String], flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut job = RenderJob::load_all(job_paths)?;
+    let mut frames = None;
+    let mut fail_fast = false;
+    let mut resume = false;
+    let mut dry_run = false;
+    let mut denoise = false;
+    let mut assume_yes = false;
+    let mut limits = CostLimits::default();
+    let mut execution = ExecutionOptions::default();
+    let mut tiled = false;
+    let mut gpu_primary = false;
+    let mut stream = false;
+    let mut perf_log = None;
+    let mut scene = None;
+    let mut bvh_layout = BvhLayout::default();
+    let mut view_paths = Vec::new();
+    let mut mode = MultiMode::Sequential;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--frames" => {
+                let mut frame = || -> Result<u64, String> {
+                    let value = flags
+                        .next()
+                        .ok_or("--frames needs a first and last frame")?;
+                    value
+                        .parse()
+                        .map_err(|_| format!("'{}' is not a frame number", value))
+                };
+                let (first, last) = (frame()?, frame()?);
+                frames = Some(first..last + 1);
+            }
+            "--out" => job.output_path = flags.next().ok_or("--out needs a path")?.clone(),
+            "--fail-fast" => fail_fast = true,
+            "--resume" => resume = true,
+            "--dry-run" => dry_run = true,
+            "--auto-stop" => {
+                let value = flags.next().ok_or("--auto-stop needs a relative error")?;
+                let relative_error: Float = value
+                    .parse()
+                    .ok()
+                    .filter(|error| *error > 0.0)
+                    .ok_or_else(|| format!("'{}' is not a relative error", value))?;
+                job.auto_stop = Some(StopCriterion {
+                    relative_error,
+                    ..job.auto_stop.unwrap_or_default()
+                });
+            }
+            "--denoise" => denoise = true,
+            "--gpu-primary" => gpu_primary = true,
+            "--stream" => stream = true,
+            "--perf-log" => perf_log = Some(flags.next().ok_or("--perf-log needs a path")?),
+            "--scene" => scene = Some(flags.next().ok_or("--scene needs a path")?),
+            "--bvh" => bvh_layout = bvh_layout_flag(flags.next())?,
+            "--bracket" => job.bracket = Some(bracket_flag(flags.next())?),
+            "--seed" => job.seed = Some(seed_flag(flags.next())?),
+            "--preset" => {
+                preset_flag(flags.next())?;
+                job.integrator = Integrator::Technical;
+                job.post_process = technical::post_process(&job.post_process);
+            }
+            "--view" => view_paths.push(flags.next().ok_or("--view needs a job file")?),
+            "--interleave" => {
+                let value = flags.next().ok_or("--interleave needs samples per sweep")?;
+                let sweep_samples = value
+                    .parse()
+                    .ok()
+                    .filter(|samples| *samples > 0)
+                    .ok_or_else(|| format!("'{}' is not a positive whole number", value))?;
+                mode = MultiMode::Interleaved { sweep_samples };
+            }
+            "--yes" => assume_yes = true,
+            "--max-hours" | "--max-disk-gb" => {
+                let value = flags.next().ok_or(format!("{} needs a value", flag))?;
+                let value: Float = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number", value))?;
+                if flag == "--max-hours" {
+                    limits.max_seconds = value * 3600.0;
+                } else {
+                    limits.max_output_bytes = (value * (1u64 << 30) as Float) as u64;
+                }
+            }
+            _ if execution_flag(flag, &mut flags, &mut execution)? => tiled = true,
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    let tiles = tiled.then(|| TileRenderer::new(&execution)).transpose()?;
+
+    if denoise && frames.is_some() {
+        return Err("--denoise only works on single frames so far".into());
+    }
+    if stream && (frames.is_some() || denoise || gpu_primary) {
+        return Err(
+            "--stream only works on single frames without --denoise or --gpu-primary".into(),
+        );
+    }
+    if job.bracket.is_some() && (frames.is_some() || stream) {
+        return Err("--bracket only works on single frames without --stream so far".into());
+    }
+    if mode != MultiMode::Sequential && view_paths.is_empty() {
+        return Err("--interleave needs views to interleave, given with --view".into());
+    }
+    if !view_paths.is_empty()
+        && (frames.is_some() || denoise || stream || gpu_primary || job.auto_stop.is_some())
+    {
+        return Err(
+            "--view only works on single frames without --denoise, --stream, --gpu-primary or \
+             auto stop so far"
+                .into(),
+        );
+    }
+    if gpu_primary && (frames.is_some() || denoise || tiled || job.auto_stop.is_some()) {
+        return Err(
+            "--gpu-primary only works on single frames without --denoise, --threads or \
+             auto stop so far"
+                .into(),
+        );
+    }
+
+    let mut views: Vec<(String, RenderJob)> = Vec::new();
+    for path in view_paths {
+        let name = Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("'{}' doesn't name a view", path))?
+            .to_string();
+        if views.iter().any(|(other, _)| *other == name) {
+            return Err(format!(
+                "two views are named '{}', which would write the same file",
+                name
+            )
+            .into());
+        }
+        let view = RenderJob::load_all(&[path])?;
+        views.push((name, job.viewed_from(&view)));
+    }
+
+    let (_camera, mut world) = build_scene(scene)?;
+    world.set_bvh_layout(bvh_layout)?;
+    job.animate(&mut world)?;
+    // Every view costs about as much as a frame of its own
+    let frame_count = frames
+        .as_ref()
+        .map_or(views.len().max(1), |frames| frames.clone().count());
+    let cost = estimate::estimate(&job, &world, tiles.as_ref(), frame_count);
+    println!("Estimated cost:\n{}", cost);
+    if dry_run {
+        return Ok(());
+    }
+    if estimate::decide(&cost, &limits, assume_yes, estimate::confirm_on_terminal)
+        == Decision::Decline
+    {
+        return Err(Declined.into());
+    }
+    if let Some(path) = perf_log {
+        PerfLog::install(path)?;
+        let threads = tiles
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, TileRenderer::threads);
+        let log = PerfLog::global().expect("the log was just installed");
+        log.session(&job.perf_session(&world, threads));
+    }
+
+    let Some(frames) = frames else {
+        job.animate(&mut world)?;
+        if !views.is_empty() {
+            let settings = MultiSettings {
+                mode,
+                cancel: Some(sequence::cancel_on_interrupt()),
+            };
+            return Ok(RenderJob::run_views_on(
+                &views,
+                &world,
+                &settings,
+                tiles.as_ref(),
+            )?);
+        }
+        if denoise {
+            return Ok(job.run_denoised_on(&world, tiles.as_ref())?);
+        }
+        if stream {
+            return Ok(job.run_streaming_on(&world, tiles.as_ref())?);
+        }
+        if let Some(gpu) = GpuPrimary::try_new(&world, gpu_primary) {
+            let (gpu_time, cpu_time) = gpu.benchmark(&job.camera(), &world)?;
+            println!(
+                "First hits of one ray per pixel took {:.1} ms on the GPU and {:.1} ms on the \
+                 CPU ({:.2}x)",
+                gpu_time.as_secs_f64() * 1000.0,
+                cpu_time.as_secs_f64() * 1000.0,
+                cpu_time.as_secs_f64() / gpu_time.as_secs_f64()
+            );
+            return Ok(job.run_on_gpu(&world, &gpu)?);
+        }
+        return Ok(job.run_on(&world, tiles.as_ref())?);
+    };
+    let options = SequenceOptions {
+        fail_fast,
+        resume,
+        // Next to the last job file, which is the one that makes this render what it is
+        ..SequenceOptions::new(job_paths.last().expect("there's a job file"), frames)
+    };
+    let cancel = sequence::cancel_on_interrupt();
+    let report = sequence::render_sequence(&job, &options, cancel, |frame_job| {
+        frame_job
+            .animate(&mut world)
+            .map_err(|err| err.to_string())?;
+        Ok(frame_job.render_on(&world, tiles.as_ref()))
+    })?;
+    println!(
+        "{} of {} frames failed, {} never started{}. Report written to {}",
+        report.failed().count(),
+        options.frames.end - options.frames.start,
+        report.pending.len(),
+        if report.cancelled { " (cancelled)" } else { "" },
+        options.report_path
+    );
+    Ok(())
+}
+
+fn seed_flag(value: Option<&String>) -> Result<u64, String> {
+    let value = value.ok_or("--seed needs a seed")?;
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a seed, which is a whole number", value))
+}
+
+/// Checks the name after `--preset`, returning whether it's the technical preset, the only one
+/// so far
+fn preset_flag(value: Option<&String>) -> Result<bool, String> {
+    match value.map(String::as_str) {
+        Some("technical") => Ok(true),
+        Some(name) => Err(format!("'{}' is not a preset, try technical", name)),
+        None => Err("--preset needs a name".to_string()),
+    }
+}
+
+fn quick_denoise_flag(value: Option<&String>) -> Result<DenoiseParams, String> {
+    let value = value.ok_or("--quick-denoise needs a strength, e.g. 1")?;
+    value
+        .parse()
+        .ok()
+        .filter(|strength: &Float| *strength >= 0.0)
+        .map(DenoiseParams::new)
+        .ok_or_else(|| format!("'{}' is not a strength, which is 0 or more", value))
+}
+
+fn bracket_flag(value: Option<&String>) -> Result<Bracket, String> {
+    let value = value.ok_or("--bracket needs exposures, e.g. -2..=2:1")?;
+    Bracket::parse(value).map_err(|err| err.to_string())
+}
+
+fn bvh_layout_flag(value: Option<&String>) -> Result<BvhLayout, String> {
+    let value = value.ok_or("--bvh needs a layout")?;
+    BvhLayout::from_name(value)
+        .ok_or_else(|| format!("unknown BVH layout '{}', try binary or compressed", value))
+}
+
+fn bvh_bench(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut scene = None;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--scene" => scene = Some(flags.next().ok_or("--scene needs a path")?),
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    let (camera, mut world) = build_scene(scene)?;
+    let rays = accel::sample_rays(&camera, &world);
+    let comparison = LayoutComparison::measure(&mut world, &rays)?;
+    println!("{}", comparison);
+    if comparison.missed > 0 {
+        return Err("the compressed BVH missed shapes the binary one found".into());
+    }
+    Ok(())
+}
+
+fn list_assets(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut scene = None;
+    let mut bundle_dir = None;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--scene" => scene = Some(flags.next().ok_or("--scene needs a name or path")?),
+            "--bundle" => bundle_dir = Some(flags.next().ok_or("--bundle needs a directory")?),
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    let scene = scene.ok_or("--scene is required")?;
+    let path = Path::new(scene);
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let mesh_kind = |path: &str| {
+        if path.ends_with(".obj") {
+            "mesh"
+        } else {
+            "gltf"
+        }
+    };
+    // Bundles keep each file where it is relative to `directory`
+    let (references, resolver, directory) =
+        if let Some(files) = scenes::built_in_scene_assets(scene) {
+            let references = files
+                .iter()
+                .map(|file| AssetReference {
+                    location: format!("built-in scene {}", scene),
+                    kind: mesh_kind(file),
+                    path: file.into(),
+                })
+                .collect();
+            let resolver = AssetResolver::from_env(Path::new(""));
+            (references, resolver, Path::new(assets::PROJECT_ASSETS))
+        } else if matches!(extension, Some("gltf" | "glb")) {
+            let reference = AssetReference {
+                location: "scene".to_string(),
+                kind: "gltf",
+                path: path.to_path_buf(),
+            };
+            let resolver = AssetResolver::from_env(Path::new(""));
+            (
+                vec![reference],
+                resolver,
+                path.parent().unwrap_or(Path::new("")),
+            )
+        } else {
+            let scene_file = SceneFile::load(path)?;
+            let references = scene_file.asset_references();
+            (
+                references,
+                scene_file.assets,
+                path.parent().unwrap_or(Path::new("")),
+            )
+        };
+
+    if references.is_empty() {
+        println!("{} doesn't use any files", scene);
+    }
+    let mut missing = 0;
+    for reference in &references {
+        let found = match resolver.resolve(&reference.path) {
+            Ok(asset) => asset.to_string(),
+            Err(err) => {
+                missing += 1;
+                format!("MISSING (looked in: {})", err.looked_in.join(", "))
+            }
+        };
+        println!(
+            "{}: {} {} -> {}",
+            reference.location,
+            reference.kind,
+            reference.path.display(),
+            found
+        );
+    }
+    if missing > 0 {
+        return Err(format!("{} of {} assets are missing", missing, references.len()).into());
+    }
+    if let Some(bundle_dir) = bundle_dir {
+        let copied = assets::bundle(&references, &resolver, directory, Path::new(bundle_dir))?;
+        println!("Copied {} files into {}", copied.len(), bundle_dir);
+    }
+    Ok(())
+}
+
+fn light_splats(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if !cfg!(feature = "diagnostics") {
+        return Err("light-splats needs a build with the `diagnostics` feature".into());
+    }
+    let mut scene = None;
+    let mut samples = 4;
+    let mut integrator = None;
+    let mut chosen_light = None;
+    let mut out_dir = "splats".to_string();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut value = || flags.next().ok_or(format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--scene" => scene = Some(value()?),
+            "--samples" => samples = value()?.parse()?,
+            "--integrator" => {
+                let name = value()?;
+                integrator = Some(
+                    Integrator::from_name(name)
+                        .ok_or_else(|| format!("unknown integrator '{}'", name))?,
+                );
+            }
+            "--light" => {
+                let name = value()?;
+                chosen_light = Some(match name.as_str() {
+                    "sun" => SplatLight::Sun,
+                    index => SplatLight::Area(
+                        index
+                            .parse()
+                            .map_err(|_| format!("'{}' is not a light index or sun", index))?,
+                    ),
+                });
+            }
+            "--out" => out_dir = value()?.clone(),
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    let (camera, world) = build_scene(scene)?;
+    let mut camera = camera.with_sampling(samples, camera.max_depth());
+    if let Some(integrator) = integrator {
+        camera.integrator = integrator;
+    }
+    let out_dir = Path::new(&out_dir);
+    std::fs::create_dir_all(out_dir)?;
+
+    println!(
+        "Rendering {}x{} at {} samples per pixel while recording light sampling",
+        camera.image_width, camera.image_height, samples
+    );
+    splat::start_recording();
+    let mut image = camera.render_image(&world);
+    let splats = splat::stop_recording();
+
+    let write = |image: camera::Image, name: &str| -> Result<(), Box<dyn std::error::Error>> {
+        let path = out_dir.join(name);
+        Camera::write_png(image, &path)
+            .map_err(|err| format!("can't write to '{}': {}", path.display(), err))?;
+        println!("Wrote {}", path.display());
+        Ok(())
+    };
+    let describe = |name: &str, samples: u64, heatmap: &Heatmap| {
+        let verdict = match heatmap.clustered() {
+            Some(true) => "CLUSTERED",
+            Some(false) => "spread as expected",
+            None => "too few samples to judge",
+        };
+        let uniformity = heatmap
+            .uniformity()
+            .map_or("-".to_string(), |uniformity| format!("{:.3}", uniformity));
+        println!(
+            "{}: {} samples, uniformity {} (1 is ideal), {}",
+            name, samples, uniformity, verdict
+        );
+    };
+
+    let sampled = splats.sampled_lights();
+    match chosen_light.or_else(|| sampled.first().map(|(light, _)| *light)) {
+        Some(light) => {
+            let points = splats
+                .lights
+                .items()
+                .iter()
+                .filter(|splat| splat.light == light)
+                .map(|splat| (splat.s, splat.t));
+            let heatmap = Heatmap::uniform(points, splat::LIGHT_CELLS);
+            let seen = sampled
+                .iter()
+                .find(|(sampled, _)| *sampled == light)
+                .map_or(0, |(_, count)| *count as u64);
+            describe(&light.to_string(), seen, &heatmap);
+            write(
+                heatmap.to_image(),
+                &format!("{}.png", light).replace(' ', "_"),
+            )?;
+        }
+        None => println!("No lights were sampled"),
+    }
+    if splats.sky.items().is_empty() {
+        println!("The sky wasn't sampled");
+    } else {
+        let heatmap = Heatmap::sky(splats.sky.items());
+        describe("sky", splats.sky.seen(), &heatmap);
+        write(heatmap.to_image(), "sky_directions.png")?;
+    }
+
+    let self_hit_distance = world.numeric.zero_advance_distance;
+    let shadows = splats.shadows.items();
+    let self_hits = shadows
+        .iter()
+        .filter(|shadow| shadow.distance < self_hit_distance)
+        .count();
+    println!(
+        "shadow rays: {} blocked, and {} of the {} kept stopped within {} of their origin \
+         (marked red)",
+        splats.shadows.seen(),
+        self_hits,
+        shadows.len(),
+        self_hit_distance
+    );
+    splat::shadow_overlay(&mut image, &camera, shadows, self_hit_distance);
+    write(image, "shadow_rays.png")?;
+    Ok(())
+}
+
+fn mesh_check(mesh_paths: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if mesh_paths.is_empty() {
+        return Err("mesh-check needs at least one OBJ or glTF file".into());
+    }
+    let options = LoadOptions {
+        analyze: true,
+        ..LoadOptions::default()
+    };
+    let material: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    for path in mesh_paths {
+        let report = if path.ends_with(".obj") {
+            hittable::load_obj(path, material.clone(), None, false, &options)
+                .map(|(_, report)| report)
+        } else {
+            hittable::load_gltf(path, material.clone(), &options).map(|(_, report)| report)
+        }
+        .map_err(|err| format!("{}: {}", path, err))?;
+        if report.mesh_analyses.is_empty() {
+            println!("{}: every mesh is watertight", path);
+        }
+        for (mesh, analysis) in &report.mesh_analyses {
+            println!("{}: {}: {}", path, mesh, analysis);
+        }
+    }
+    Ok(())
+}
+
+fn perf_report(log_paths: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if log_paths.is_empty() {
+        return Err("perf-report needs at least one performance log".into());
+    }
+    print!("{}", PerfReport::load(log_paths)?);
+    Ok(())
+}
+
+/// Renders a procedural scene at many seeds onto a contact sheet to pick a layout from
+fn render_variations(flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut scene = None;
+    let mut count = variations::DEFAULT_COUNT;
+    let mut first_seed = 0;
+    let mut width = variations::DEFAULT_THUMBNAIL_WIDTH;
+    let mut samples = variations::DEFAULT_SAMPLES;
+    let mut out = "variations.png".to_string();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut value = || flags.next().ok_or(format!("{} needs a value", flag));
+        let positive = |value: &String| {
+            value
+                .parse()
+                .ok()
+                .filter(|count| *count > 0)
+                .ok_or_else(|| format!("'{}' is not a positive whole number", value))
+        };
+        match flag.as_str() {
+            "--scene" => scene = Some(value()?),
+            "--count" => count = positive(value()?)?,
+            "--first-seed" => first_seed = seed_flag(flags.next())?,
+            "--width" => width = positive(value()?)?,
+            "--samples" => samples = positive(value()?)?,
+            "--out" => out = value()?.clone(),
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    let scene = scene.ok_or_else(|| {
+        format!(
+            "--scene is required, one of {}",
+            scenes::PROCEDURAL_SCENES.join(", ")
+        )
+    })?;
+    // Found out before rendering rather than after, without losing what's there already
+    let partial = Camera::claim_image_path(Path::new(&out))
+        .map_err(|err| format!("can't write to '{}': {}", out, err))?;
+
+    let seeds: Vec<u64> = (0..count as u64)
+        .map(|k| first_seed.wrapping_add(k))
+        .collect();
+    let variations = match variations::render_variations(scene, &seeds, width, samples) {
+        Ok(variations) => variations,
+        Err(err) => {
+            let _ = std::fs::remove_file(partial);
+            return Err(err.into());
+        }
+    };
+    let sheet = variations::contact_sheet(&variations).ok_or("there were no seeds to render")?;
+    Camera::replace_image(sheet, Path::new(&out))
+        .map_err(|err| format!("can't write to '{}': {}", out, err))?;
+    println!("Wrote {}", out);
+    println!("Textures: {}", TextureCache::global().stats());
+    let first = &variations[0].image;
+    println!(
+        "Render one again with `rt --headless --scene {} --seed <seed> --width {} --height {} \
+         --samples {}`",
+        scene, first.width, first.height, samples
+    );
+    Ok(())
+}
+
+fn debug_pixel(job_paths: &[String], flags: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let job = RenderJob::load_all(job_paths)?;
+    let mut pixel = None;
+    let mut sample = 0;
+    let mut seed = job.seed;
+    let mut verbose = false;
+    let mut scene = None;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut value = || flags.next().ok_or(format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--pixel" => {
+                let value = value()?;
+                let parsed = value
+                    .split_once(',')
+                    .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+                pixel = Some(parsed.ok_or(format!("'{}' is not a pixel like 412,217", value))?);
+            }
+            "--sample" => sample = value()?.parse()?,
+            "--seed" => seed = Some(value()?.parse()?),
+            "--verbose" => verbose = true,
+            "--scene" => scene = Some(value()?),
+            _ => return Err(format!("unknown flag '{}'", flag).into()),
+        }
+    }
+    let (x, y) = pixel.ok_or("--pixel is required")?;
+    if x >= job.width || y >= job.height {
+        return Err(format!(
+            "pixel ({}, {}) is outside the {}x{} image",
+            x, y, job.width, job.height
+        )
+        .into());
+    }
+    if seed.is_none() {
+        println!("Warning: the job has no seed, so this won't match any earlier render");
+    }
+
+    let (_camera, mut world) = build_scene(scene)?;
+    job.animate(&mut world)?;
+    let mut camera = job.camera_in(&world)?;
+    camera.seed = seed;
+    let trace = camera.trace_sample(&world, x, y, sample);
+    if verbose {
+        println!("{}", trace);
+    } else {
+        let radiance = trace.radiance;
+        println!(
+            "Sample {} of pixel ({}, {}): {} bounces, radiance ({}, {}, {})",
+            sample,
+            x,
+            y,
+            trace
+                .events
+                .iter()
+                .filter(|event| matches!(event, camera::PathEvent::Bounce(_)))
+                .count(),
+            radiance.x,
+            radiance.y,
+            radiance.z
+        );
+    }
+    Ok(())
+}
+
+/// Builds the scene built in under the name `scene` or in the file at `scene`, or the one below
+/// if there isn't one
+fn build_scene(scene: Option<&String>) -> Result<(Camera, World), SceneError> {
+    let (camera, shapes, surroundings) = scene_shapes(scene, None)?;
+    Ok((camera, surroundings.build(shapes)))
+}
+
+/// Like [`build_scene`], laying the built-in procedural scenes out by `seed` if there is one,
+/// rather than by [`scenes::BUILT_IN_COVER_SEED`], and leaving the world to be built from the
+/// shapes in the surroundings
+fn scene_shapes(
+    scene: Option<&String>,
+    seed: Option<u64>,
+) -> Result<(Camera, Vec<Shape>, Surroundings), SceneError> {
+    let seed = seed.unwrap_or(scenes::BUILT_IN_COVER_SEED);
+    if let Some(built_in) = scene.and_then(|name| scenes::built_in_scene(name, seed)) {
+        return Ok(built_in);
+    }
+    match scene {
+        Some(path) => scenes::load_scene(Path::new(path)),
+        None => {
+            let (camera, shapes) = default_scene_shapes();
+            Ok((camera, shapes, Surroundings::default()))
+        }
+    }
+}
+
+fn default_scene_shapes() -> (Camera, Vec<Shape>) {
     let camera = scenes::cam1();
 
     let mut shapes = Vec::new();
 
     let even_texture = SolidColor::new(Vec3::new(0.1, 0.1, 0.1)).into();
     let odd_texture = SolidColor::new(Vec3::new(0.95, 0.95, 0.95)).into();
-    let checker_tex = CheckerTexture::new(3.0, even_texture, odd_texture).into();
+    let checker_tex = CheckerTexture::new_filtered(3.0, even_texture, odd_texture).into();
     let checker_mat: Arc<Material> = Arc::new(Lambertian::new(checker_tex).into());
     let frosty_glass: Arc<Material> = Arc::new(Dielectric::new_frosted(1.5, 0.05).into());
 
@@ -49,14 +1193,19 @@ fn main() {
     shapes.append(&mut ground);
     // shapes.append(&mut scenes::triangle_scene());
     // shapes.append(&mut scenes::mesh_scene());
-    shapes.append(&mut scenes::cover_scene(300, 300, &camera, ground_height));
+    shapes.append(&mut scenes::cover_scene(
+        300,
+        300,
+        &camera,
+        ground_height,
+        &mut StdRng::seed_from_u64(scenes::BUILT_IN_COVER_SEED),
+    ));
     // shapes.append(&mut scenes::triangle_scene());
-    shapes.append(&mut scenes::gltf_test());
-    // shapes.append(&mut sponza());
+    let (mut gltf_shapes, load_report) = scenes::gltf_test();
+    shapes.append(&mut gltf_shapes);
+    // shapes.append(&mut sponza().0);
+    println!("{}", load_report);
+    println!("Textures: {}", TextureCache::global().stats());
     println!("Rendering a scene with {} shapes", shapes.len());
-    let world = World::build(shapes);
-
-    if let Err(err) = window::render_with_preview(camera, world) {
-        println!("Err: {}", err);
-    }
+    (camera, shapes)
 }
@@ -1,11 +1,23 @@
 use crate::{
     camera::{Float, Image},
     intersection::Intersection,
+    shading::ShadingContext,
     texture::{ImageTexture, SolidColor, Texture, TextureEnum},
     vec3::{Ray, Vec3, Vec3Ext},
 };
 use enum_dispatch::enum_dispatch;
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use std::sync::Arc;
+
+/// Transmission factor from which a glTF material is shaded as glass, see
+/// [`Material::from_gltf`]
+const GLTF_MIN_TRANSMISSION: Float = 0.5;
+/// Index of refraction glTF gives materials without `KHR_materials_ior`
+const GLTF_DEFAULT_IOR: Float = 1.5;
+/// glTF materials with a metallic factor up to this and a roughness from
+/// [`GLTF_MIN_DIFFUSE_ROUGHNESS`] are shaded as Lambertians, like cloth, skin and stone
+const GLTF_MAX_DIELECTRIC_METALLIC: Float = 0.05;
+const GLTF_MIN_DIFFUSE_ROUGHNESS: Float = 0.5;
 
 #[enum_dispatch]
 #[derive(Debug)]
@@ -13,23 +25,75 @@ pub enum Material {
     Lambertian,
     Metal,
     Dielectric,
+    AlphaMask,
+    Volumetric,
+    DiffuseLight,
+    ShadowCatcher,
 }
 
 impl Material {
-    // TODO: figure out materials
-    pub fn from_gltf(gltf_mat: gltf::Material, image: Option<Image>) -> Self {
-        let pbr = gltf_mat.pbr_metallic_roughness();
-        let fuzz = pbr.roughness_factor().into();
-        let color = pbr.base_color_factor().map(|x| x.into());
+    pub fn name(&self) -> &'static str {
+        match self {
+            Material::Lambertian(_) => "lambertian",
+            Material::Metal(_) => "metal",
+            Material::Dielectric(_) => "dielectric",
+            Material::AlphaMask(_) => "alpha mask",
+            Material::Volumetric(_) => "volumetric",
+            Material::DiffuseLight(_) => "diffuse light",
+            Material::ShadowCatcher(_) => "shadow catcher",
+        }
+    }
 
-        if let Some(image) = image {
-            let tex = ImageTexture::new(image).into();
-            return Metal::new(tex, Some(fuzz)).into();
+    /// One color standing in for how much light the material gives back, like for previews
+    /// too coarse to see its texture. Glass counts as its tint, and lights as what they give off.
+    pub fn average_albedo(&self) -> Vec3 {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.texture.average(),
+            Material::Metal(metal) => metal.texture.average(),
+            Material::Dielectric(dielectric) => dielectric
+                .tint
+                .as_ref()
+                .map_or(Vec3::ONE, |tint| tint.average()),
+            Material::AlphaMask(mask) => mask.base.average_albedo(),
+            Material::Volumetric(volumetric) => volumetric.albedo,
+            Material::DiffuseLight(light) => light.texture.average() * light.intensity,
+            Material::ShadowCatcher(_) => Vec3::ONE,
         }
+    }
 
-        let color = Vec3::new(color[0], color[1], color[2]);
+    /// Picks a material for a glTF one from its extensions and metallic-roughness factors: glass
+    /// with the `KHR_materials_ior` index and frosted by the roughness when it's mostly
+    /// transmissive, a Lambertian when it's a rough non-metal, and a metal otherwise. The base
+    /// color texture, or factor without one, is the glass's tint and the others' albedo.
+    pub fn from_gltf(gltf_mat: gltf::Material, image: Option<Arc<Image>>) -> Self {
+        let pbr = gltf_mat.pbr_metallic_roughness();
+        let roughness: Float = pbr.roughness_factor().into();
+        let metallic: Float = pbr.metallic_factor().into();
+        let texture: TextureEnum = match image {
+            Some(image) => ImageTexture::shared(image).into(),
+            None => {
+                let color = pbr.base_color_factor().map(Float::from);
+                SolidColor::new(Vec3::new(color[0], color[1], color[2])).into()
+            }
+        };
 
-        Metal::new_solid(color, Some(fuzz)).into()
+        // Blended or transmissive materials are usually glass, stained when textured
+        let is_transmissive = gltf_mat.alpha_mode() == gltf::material::AlphaMode::Blend
+            || gltf_mat.transmission().is_some_and(|transmission| {
+                Float::from(transmission.transmission_factor()) >= GLTF_MIN_TRANSMISSION
+            });
+        if is_transmissive {
+            let refractive_index = gltf_mat.ior().map_or(GLTF_DEFAULT_IOR, Float::from);
+            return Dielectric {
+                fuzz: (roughness > 0.0).then_some(roughness),
+                ..Dielectric::new_tinted(refractive_index, texture)
+            }
+            .into();
+        }
+        if metallic <= GLTF_MAX_DIELECTRIC_METALLIC && roughness >= GLTF_MIN_DIFFUSE_ROUGHNESS {
+            return Lambertian::new(texture).into();
+        }
+        Metal::new(texture, Some(roughness)).into()
     }
 }
 // TODO: change out uses of Vec3 for a Color type where applicable. Make said Color type.
@@ -40,10 +104,127 @@ pub trait Scatter: Send + Sync {
     // TODO: I don't think this needs to be an option type?
     // At the very least, between Lambertian, Dielectric, and Metal's `Scatter` implementations,
     // there is not one instance in which `None` is returned
-    fn scatter(&self, ray_in: &Ray, record: &Intersection) -> Option<(Vec3, Ray)>;
+    /// Returns how much of the light coming back along the scattered ray is kept, and the ray,
+    /// drawing any random numbers from `context`'s sampler
+    fn scatter(&self, context: &mut ShadingContext) -> Option<(Vec3, Ray)>;
+
+    /// Returns the surface's coverage at the intersection, with 0.0 being fully transparent
+    fn alpha(&self, _record: &Intersection) -> Float {
+        1.0
+    }
+
+    /// Whether the surface scatters like a Lambertian: in a cosine-weighted direction around the
+    /// normal, with `scatter`'s attenuation as the albedo. Lights can be sampled directly for these.
+    fn is_diffuse(&self) -> bool {
+        false
+    }
+
+    /// Returns the fraction of light a shadow ray keeps going straight through the surface at
+    /// `record`, per channel. Opaque surfaces return black.
+    fn shadow_transmittance(&self, _ray_in: &Ray, _record: &Intersection) -> Vec3 {
+        Vec3::zeros()
+    }
+
+    /// Returns the radiance the surface gives off at `record` back toward where the ray came from
+    fn emitted(&self, _record: &Intersection) -> Vec3 {
+        Vec3::zeros()
+    }
+
+    /// Whether [`Scatter::emitted`] can return anything but black, so the surface can be sampled
+    /// as a light
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Whether [`Scatter::bump`] can change anything, so shapes know to work out the tangents
+    /// it needs
+    fn has_bump(&self) -> bool {
+        false
+    }
+
+    /// Perturbs the normal at `record` to match the surface's fine detail. Runs once a hit is
+    /// known to be the nearest, before anything shades it.
+    fn bump(&self, _record: &mut Intersection) {}
+}
+
+/// Step in texture coordinates to difference height textures over when they aren't images
+const BUMP_STEP: Float = 1e-3;
+
+/// Below this cosine between a bumped normal and the surface's own, the bumped one is tilted
+/// back, so steep bumps never face into the surface
+const MIN_BUMP_COSINE: Float = 0.1;
+
+/// Fine detail on a surface from a grayscale height texture, which tilts the normal without
+/// moving the surface
+#[derive(Debug)]
+pub struct BumpMap {
+    /// Height at each point, as the average of the texture's channels
+    pub height: TextureEnum,
+    /// Distance in world units the surface would rise by where the height goes from 0 to 1
+    pub strength: Float,
 }
 
-fn reflect(incoming_direction: Vec3, surface_normal: Vec3) -> Vec3 {
+impl BumpMap {
+    pub fn new(height: TextureEnum, strength: Float) -> Self {
+        BumpMap { height, strength }
+    }
+
+    /// Height at `(u, v)` near the hit, looked up at the point that far along the tangents too
+    /// so solid textures, which ignore UVs, bump as well
+    fn height_at(&self, u: Float, v: Float, record: &Intersection) -> Float {
+        let point =
+            record.point + record.dpdu * (u - record.uv.x) + record.dpdv * (v - record.uv.y);
+        self.height.value(u, v, point).sum() / 3.0
+    }
+
+    /// Steps in u and v to difference the height over: one texel for images, which are looked
+    /// up without filtering, so any less would only see the steps between texels
+    fn steps(&self) -> (Float, Float) {
+        match &self.height {
+            TextureEnum::ImageTexture(texture) => (
+                1.0 / (texture.image.width.max(2) - 1) as Float,
+                1.0 / (texture.image.height.max(2) - 1) as Float,
+            ),
+            _ => (BUMP_STEP, BUMP_STEP),
+        }
+    }
+
+    /// Tilts the normal at `record` as if the surface were raised by the height along it. Does
+    /// nothing where the shape gave no tangents.
+    pub fn perturb(&self, record: &mut Intersection) {
+        let normal = record.normal;
+        let tangents = record.dpdu.cross(&record.dpdv);
+        if tangents.norm_squared() == 0.0 || !tangents.iter().all(|c| c.is_finite()) {
+            return;
+        }
+        let (u, v) = (record.uv.x, record.uv.y);
+        let (du, dv) = self.steps();
+        let slope_u = (self.height_at(u + du, v, record) - self.height_at(u - du, v, record))
+            / (2.0 * du)
+            * self.strength;
+        let slope_v = (self.height_at(u, v + dv, record) - self.height_at(u, v - dv, record))
+            / (2.0 * dv)
+            * self.strength;
+        // Tangents of the surface raised along the normal, ignoring how the normal itself turns
+        let bumped_u = record.dpdu + normal * slope_u;
+        let bumped_v = record.dpdv + normal * slope_v;
+        let mut bumped = bumped_u.cross(&bumped_v).normalize();
+        if bumped.dot(&normal) < 0.0 {
+            bumped = -bumped;
+        }
+        let along = bumped.dot(&normal);
+        if along < MIN_BUMP_COSINE {
+            let across = (bumped - normal * along).normalize();
+            bumped = normal * MIN_BUMP_COSINE
+                + across * (1.0 - MIN_BUMP_COSINE * MIN_BUMP_COSINE).sqrt();
+        }
+        if bumped.iter().all(|c| c.is_finite()) {
+            record.normal = bumped;
+        }
+    }
+}
+
+pub(crate) fn reflect(incoming_direction: Vec3, surface_normal: Vec3) -> Vec3 {
     // Scale normal by length of incoming ray's direction projected onto the normal
     // Then reflect the ray by subtracting twice its height relative to the surface
     let scaled_normal = surface_normal * incoming_direction.dot(&surface_normal);
@@ -62,11 +243,21 @@ fn refract(incoming_direction: Vec3, surface_normal: Vec3, refractive_ratio: Flo
 #[derive(Debug)]
 pub struct Lambertian {
     pub texture: TextureEnum,
+    pub bump: Option<BumpMap>,
 }
 
 impl Lambertian {
     pub fn new(texture: TextureEnum) -> Self {
-        Lambertian { texture }
+        Lambertian {
+            texture,
+            bump: None,
+        }
+    }
+
+    /// Adds a bump map raising the surface by up to `strength` where `height` is white
+    pub fn with_bump(mut self, height: TextureEnum, strength: Float) -> Self {
+        self.bump = Some(BumpMap::new(height, strength));
+        self
     }
 
     pub fn new_rgb_solid(r: Float, g: Float, b: Float) -> Self {
@@ -79,6 +270,7 @@ impl Lambertian {
 pub struct Metal {
     pub texture: TextureEnum,
     pub fuzz: Option<Float>,
+    pub bump: Option<BumpMap>,
 }
 
 impl Metal {
@@ -87,44 +279,84 @@ impl Metal {
         Metal::new(solid_texture, fuzz)
     }
     pub fn new(texture: TextureEnum, fuzz: Option<Float>) -> Self {
-        Metal { texture, fuzz }
+        Metal {
+            texture,
+            fuzz,
+            bump: None,
+        }
+    }
+
+    /// Adds a bump map raising the surface by up to `strength` where `height` is white
+    pub fn with_bump(mut self, height: TextureEnum, strength: Float) -> Self {
+        self.bump = Some(BumpMap::new(height, strength));
+        self
     }
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, ray_in: &Ray, intersection: &Intersection) -> Option<(Vec3, Ray)> {
+    fn scatter(&self, context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
         let reflected_dir = if let Some(fuzz) = self.fuzz {
-            reflect(ray_in.direction, intersection.normal)
-                + Vec3::random_unit(&mut thread_rng()) * fuzz
+            reflect(context.incoming(), context.normal())
+                + Vec3::random_unit(context.sampler()) * fuzz
         } else {
-            reflect(ray_in.direction, intersection.normal)
+            reflect(context.incoming(), context.normal())
         };
-        let scattered = Ray::new(intersection.point.into(), reflected_dir);
-        let attenuation =
-            self.texture
-                .value(intersection.uv.x, intersection.uv.y, intersection.point);
+        let scattered = Ray::new(context.point().into(), reflected_dir);
+        let attenuation = context.texture(&self.texture);
         Some((attenuation, scattered))
     }
+
+    fn has_bump(&self) -> bool {
+        self.bump.is_some()
+    }
+
+    fn bump(&self, record: &mut Intersection) {
+        if let Some(bump) = &self.bump {
+            bump.perturb(record);
+        }
+    }
 }
 
 impl Scatter for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, hit: &Intersection) -> Option<(Vec3, Ray)> {
-        let mut scatter_dir = hit.normal + Vec3::random_unit(&mut thread_rng());
+    fn scatter(&self, context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
+        let normal = context.normal();
+        let mut scatter_dir = normal + Vec3::random_unit(context.sampler());
         if scatter_dir.near_zero() {
-            scatter_dir = hit.normal;
+            scatter_dir = normal;
         }
-        let scattered = Ray::new(hit.point.into(), scatter_dir);
-        let attenuation = self.texture.value(hit.uv.x, hit.uv.y, hit.point);
+        let scattered = Ray::new(context.point().into(), scatter_dir);
+        let attenuation = context.texture(&self.texture);
         Some((attenuation, scattered))
     }
+
+    fn is_diffuse(&self) -> bool {
+        true
+    }
+
+    fn has_bump(&self) -> bool {
+        self.bump.is_some()
+    }
+
+    fn bump(&self, record: &mut Intersection) {
+        if let Some(bump) = &self.bump {
+            bump.perturb(record);
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug)]
 pub struct Dielectric {
     /// Refractive index in vacuum or air, or the ratio of the material's RI over the RI of the enclosing medium
     pub refractive_index: Float,
     /// Controls the amount of "fuzz" on the surface. Higher values make the glass look frosted
     pub fuzz: Option<Float>,
+    /// Tints light refracting through the surface, like a thin pane of stained glass. Shared so
+    /// a [`Dielectric::thin_copy`] can keep it.
+    pub tint: Option<Arc<TextureEnum>>,
+    /// Treats each surface as a pane with nothing inside it: light either reflects off it or
+    /// passes straight through, never bending. For glass on meshes that don't enclose a volume,
+    /// where which side a ray hit from says nothing about whether it's in the glass.
+    pub thin: bool,
 }
 
 impl Dielectric {
@@ -132,6 +364,8 @@ impl Dielectric {
         Dielectric {
             refractive_index,
             fuzz: None,
+            tint: None,
+            thin: false,
         }
     }
 
@@ -139,48 +373,481 @@ impl Dielectric {
         Dielectric {
             refractive_index,
             fuzz: Some(fuzz),
+            tint: None,
+            thin: false,
+        }
+    }
+
+    pub fn new_tinted(refractive_index: Float, tint: TextureEnum) -> Self {
+        Dielectric {
+            refractive_index,
+            fuzz: None,
+            tint: Some(Arc::new(tint)),
+            thin: false,
         }
     }
 
     pub fn new_inside_other(material_index: Float, container_index: Float) -> Self {
         Dielectric::new(material_index / container_index)
     }
+
+    /// The same glass as `glass`, shaded as thin panes
+    pub fn thin_copy(glass: &Dielectric) -> Self {
+        Dielectric {
+            refractive_index: glass.refractive_index,
+            fuzz: glass.fuzz,
+            tint: glass.tint.clone(),
+            thin: true,
+        }
+    }
+
+    /// Fraction of light a thin pane reflects, counting the light bouncing back and forth between
+    /// its two faces, or `None` for solid glass
+    fn pane_reflectance(&self, cos_theta: Float) -> Option<Float> {
+        if !self.thin {
+            return None;
+        }
+        // Light always enters a pane from outside, whichever side it hits
+        let once = reflectance(cos_theta.abs(), 1.0 / self.refractive_index);
+        Some(2.0 * once / (1.0 + once))
+    }
+
+    /// Reflects off a thin pane, or passes through it, picking up the tint, without bending
+    fn scatter_pane(&self, context: &mut ShadingContext, pane_reflectance: Float) -> (Vec3, Ray) {
+        let incoming_direction = context.incoming().normalize();
+        let reflects = context.sampler().gen_range(0.0..=1.0) < pane_reflectance;
+        let mut direction = if reflects {
+            reflect(incoming_direction, context.normal())
+        } else {
+            incoming_direction
+        };
+        if let Some(surface_fuzz) = self.fuzz {
+            direction += Vec3::random_unit(context.sampler()) * surface_fuzz;
+        }
+        let attenuation = match &self.tint {
+            Some(tint) if !reflects => context.texture(tint),
+            _ => Vec3::ONE,
+        };
+        let ray = Ray::new(context.point().into(), direction.normalize());
+        (attenuation, ray)
+    }
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, ray_in: &Ray, record: &Intersection) -> Option<(Vec3, Ray)> {
-        let ri = if record.is_front_face {
+    fn scatter(&self, context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
+        let ri = if context.is_front_face() {
             1.0 / self.refractive_index
         } else {
             self.refractive_index
         };
 
-        let incoming_direction = ray_in.direction.normalize();
+        let incoming_direction = context.incoming().normalize();
+        let normal = context.normal();
 
-        let cos_theta = (-incoming_direction.dot(&record.normal)).min(1.0);
+        let cos_theta = (-incoming_direction.dot(&normal)).min(1.0);
+        if let Some(pane_reflectance) = self.pane_reflectance(cos_theta) {
+            return Some(self.scatter_pane(context, pane_reflectance));
+        }
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt(); // sin^2(x) + cos^2(x) = 1
         let cannot_refract = ri * sin_theta > 1.0;
 
-        let noise = thread_rng().gen_range(0.0..=1.0);
+        let noise = context.sampler().gen_range(0.0..=1.0);
 
-        let direction = if cannot_refract || reflectance(cos_theta, ri) > noise {
-            reflect(incoming_direction, record.normal)
+        let reflects = cannot_refract || reflectance(cos_theta, ri) > noise;
+        let direction = if reflects {
+            reflect(incoming_direction, normal)
         } else if let Some(surface_fuzz) = self.fuzz {
-            refract(incoming_direction, record.normal, ri)
-                + Vec3::random_unit(&mut thread_rng()) * surface_fuzz
+            refract(incoming_direction, normal, ri)
+                + Vec3::random_unit(context.sampler()) * surface_fuzz
         } else {
-            refract(incoming_direction, record.normal, ri)
+            refract(incoming_direction, normal, ri)
+        };
+
+        // Only light passing through the surface picks up the tint
+        let attenuation = match &self.tint {
+            Some(tint) if !reflects => context.texture(tint),
+            _ => Vec3::ONE,
         };
         Some((
-            Vec3::ONE,
-            Ray::new(record.point.into(), direction.normalize()),
+            attenuation,
+            Ray::new(context.point().into(), direction.normalize()),
         ))
     }
+
+    /// The light refracted through the surface, as if it kept going straight. Ignoring the bend
+    /// lets glass cast light, tinted shadows without having to find caustics.
+    fn shadow_transmittance(&self, ray_in: &Ray, record: &Intersection) -> Vec3 {
+        let cos_theta = ray_in.direction.normalize().dot(&record.normal);
+        if let Some(pane_reflectance) = self.pane_reflectance(cos_theta) {
+            let transmitted = 1.0 - pane_reflectance;
+            return match &self.tint {
+                Some(tint) => tint.value(record.uv.x, record.uv.y, record.point) * transmitted,
+                None => Vec3::repeat(transmitted),
+            };
+        }
+        let ri = if record.is_front_face {
+            1.0 / self.refractive_index
+        } else {
+            self.refractive_index
+        };
+        let cos_theta = (-ray_in.direction.normalize().dot(&record.normal)).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        if ri * sin_theta > 1.0 {
+            return Vec3::zeros();
+        }
+        let transmitted = 1.0 - reflectance(cos_theta, ri);
+        match &self.tint {
+            Some(tint) => tint.value(record.uv.x, record.uv.y, record.point) * transmitted,
+            None => Vec3::repeat(transmitted),
+        }
+    }
 }
 
 /// Returns Schlick's approximation for reflectance at a given angle.
-fn reflectance(cosine: Float, refractive_index: Float) -> Float {
+pub(crate) fn reflectance(cosine: Float, refractive_index: Float) -> Float {
     let r0 = (1.0 - refractive_index) / (1.0 + refractive_index);
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
+
+/// Wraps another material with a coverage mask, e.g. for leaves on a quad.
+/// The red channel of `coverage` is the probability a ray hits the surface instead of passing through.
+#[derive(Debug)]
+pub struct AlphaMask {
+    pub base: Box<Material>,
+    pub coverage: TextureEnum,
+}
+
+impl AlphaMask {
+    pub fn new(base: Material, coverage: TextureEnum) -> Self {
+        AlphaMask {
+            base: Box::new(base),
+            coverage,
+        }
+    }
+}
+
+impl Scatter for AlphaMask {
+    fn scatter(&self, context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
+        self.base.scatter(context)
+    }
+
+    fn is_diffuse(&self) -> bool {
+        self.base.is_diffuse()
+    }
+
+    fn emitted(&self, record: &Intersection) -> Vec3 {
+        self.base.emitted(record)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn has_bump(&self) -> bool {
+        self.base.has_bump()
+    }
+
+    fn bump(&self, record: &mut Intersection) {
+        self.base.bump(record)
+    }
+
+    fn alpha(&self, record: &Intersection) -> Float {
+        self.coverage
+            .value(record.uv.x, record.uv.y, record.point)
+            .x
+            .clamp(0.0, 1.0)
+    }
+
+    /// Light passes the uncovered part freely and the covered part like the base material
+    fn shadow_transmittance(&self, ray_in: &Ray, record: &Intersection) -> Vec3 {
+        let alpha = self.alpha(record);
+        Vec3::repeat(1.0 - alpha) + self.base.shadow_transmittance(ray_in, record) * alpha
+    }
+}
+
+/// Scatters light inside participating media like smoke and fog, with a Henyey-Greenstein
+/// phase function deciding how far rays turn
+#[derive(Debug)]
+pub struct Volumetric {
+    pub albedo: Vec3,
+    /// From -1.0 (scatters back the way light came) through 0.0 (every direction equally) to
+    /// 1.0 (keeps going forward)
+    pub anisotropy: Float,
+}
+
+impl Volumetric {
+    pub fn new(albedo: Vec3, anisotropy: Float) -> Self {
+        Volumetric { albedo, anisotropy }
+    }
+
+    /// Scatters equally in every direction
+    pub fn isotropic(albedo: Vec3) -> Self {
+        Volumetric::new(albedo, 0.0)
+    }
+}
+
+impl Scatter for Volumetric {
+    fn scatter(&self, context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
+        let rng = context.sampler();
+        let g = self.anisotropy.clamp(-0.99, 0.99);
+        let u = rng.gen::<Float>();
+        // Inverts the Henyey-Greenstein distribution of the cosine to the incoming direction
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = std::f64::consts::TAU * rng.gen::<Float>();
+
+        let forward = context.incoming().normalize();
+        let helper = if forward.x.abs() > 0.9 {
+            Vec3::y()
+        } else {
+            Vec3::x()
+        };
+        let side = forward.cross(&helper).normalize();
+        let up = forward.cross(&side);
+        let direction = forward * cos_theta + (side * phi.cos() + up * phi.sin()) * sin_theta;
+        Some((self.albedo, Ray::new(context.point().into(), direction)))
+    }
+}
+
+/// Gives off light from its front face, evenly in every direction, and absorbs everything that
+/// hits it
+#[derive(Debug)]
+pub struct DiffuseLight {
+    /// Color of the light given off, which can be well above 1.0
+    pub texture: TextureEnum,
+    /// Scales the texture, so a light can be made brighter without changing its color
+    pub intensity: Float,
+}
+
+impl DiffuseLight {
+    pub fn new(texture: TextureEnum) -> Self {
+        DiffuseLight {
+            texture,
+            intensity: 1.0,
+        }
+    }
+
+    pub fn new_rgb_solid(r: Float, g: Float, b: Float) -> Self {
+        DiffuseLight::new(SolidColor::new_rgb(r, g, b).into())
+    }
+
+    pub fn with_intensity(mut self, intensity: Float) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn scatter(&self, _context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
+        None
+    }
+
+    fn emitted(&self, record: &Intersection) -> Vec3 {
+        if record.is_front_face {
+            self.texture.value(record.uv.x, record.uv.y, record.point) * self.intensity
+        } else {
+            Vec3::zeros()
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Ground that only shows the shadows the rest of the scene casts on it, for compositing CG
+/// objects over a photo or another render. Camera paths that hit it see the background behind
+/// it, darkened by how much of the sun and sky is blocked there, and renders of worlds with one
+/// get an alpha channel that's transparent where it's fully lit, see
+/// [`crate::shadow_matte::caught_light`]. Only the path tracer shows the shadows: drafts show
+/// the background through it, and the other integrators a black surface.
+#[derive(Debug, Default)]
+pub struct ShadowCatcher;
+
+impl Scatter for ShadowCatcher {
+    fn scatter(&self, _context: &mut ShadingContext) -> Option<(Vec3, Ray)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{Background, Triangle, World},
+        texture::CheckerTexture,
+        vec3::Vec2,
+    };
+
+    /// A `width` by `height` height map rising from 0 to 1 along u, or along v if `along_v`
+    fn ramp(width: usize, height: usize, along_v: bool) -> TextureEnum {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let value = if along_v {
+                    y as Float / (height - 1) as Float
+                } else {
+                    x as Float / (width - 1) as Float
+                };
+                Vec3::repeat(value)
+            })
+            .collect();
+        ImageTexture::new(Image {
+            pixels,
+            width,
+            height,
+            gamma: 1.0,
+            metadata: Vec::new(),
+            alpha: None,
+        })
+        .into()
+    }
+
+    /// The normal `bump` gives a flat surface facing +z at `uv`, with `dpdu` along +x and
+    /// `dpdv` along +y
+    fn bumped_normal(bump: &BumpMap, uv: Vec2, dpdu: Vec3, dpdv: Vec3) -> Vec3 {
+        let material = Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into();
+        let mut record = Intersection::new(Vec3::zeros(), Vec3::z(), 1.0, &material, true, uv)
+            .with_tangents(dpdu, dpdv);
+        bump.perturb(&mut record);
+        record.normal
+    }
+
+    #[test]
+    fn linear_ramps_tilt_the_normal_by_their_slope() {
+        // In the middle of a texel, so the differences a texel either way are exact
+        let uv = Vec2::new(0.505, 0.505);
+        for (strength, stretch) in [(0.5, 1.0), (0.5, 2.0), (2.0, 1.0)] {
+            // The surface rises `strength` over the ramp, which is `stretch` long
+            let expected = Float::atan(strength / stretch);
+            let bump = BumpMap::new(ramp(101, 1, false), strength);
+            let normal = bumped_normal(&bump, uv, Vec3::x() * stretch, Vec3::y());
+            let tilt = normal.dot(&Vec3::z()).acos();
+            assert!(
+                (tilt - expected).abs() < 1e-9,
+                "{} over {}: {} rather than {}",
+                strength,
+                stretch,
+                tilt.to_degrees(),
+                expected.to_degrees()
+            );
+            // Leaning away from the rise, in the plane of the ramp
+            assert!(normal.x < 0.0 && normal.y.abs() < 1e-12);
+
+            let bump = BumpMap::new(ramp(1, 101, true), strength);
+            let normal = bumped_normal(&bump, uv, Vec3::x(), Vec3::y() * stretch);
+            assert!((normal.dot(&Vec3::z()).acos() - expected).abs() < 1e-9);
+            assert!(normal.y < 0.0 && normal.x.abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn flat_heights_and_missing_tangents_leave_the_normal_alone() {
+        let uv = Vec2::new(0.505, 0.505);
+        let flat = BumpMap::new(SolidColor::new(Vec3::repeat(0.7)).into(), 3.0);
+        assert_eq!(bumped_normal(&flat, uv, Vec3::x(), Vec3::y()), Vec3::z());
+        let ramp = BumpMap::new(ramp(101, 1, false), 3.0);
+        assert_eq!(
+            bumped_normal(&ramp, uv, Vec3::zeros(), Vec3::zeros()),
+            Vec3::z()
+        );
+    }
+
+    #[test]
+    fn steep_ramps_stop_short_of_the_surface() {
+        let bump = BumpMap::new(ramp(101, 1, false), 1000.0);
+        let normal = bumped_normal(&bump, Vec2::new(0.505, 0.5), Vec3::x(), Vec3::y());
+        assert!((normal.z - MIN_BUMP_COSINE).abs() < 1e-12);
+        assert!((normal.norm() - 1.0).abs() < 1e-12);
+    }
+
+    /// Stained glass that's blue left of x = 0 and red right of it, like the pane in
+    /// [`crate::scenes::stained_glass`], which stands at y = 0.5
+    fn half_red_half_blue() -> Dielectric {
+        let red = SolidColor::new_rgb(0.9, 0.1, 0.1).into();
+        let blue = SolidColor::new_rgb(0.1, 0.2, 0.9).into();
+        Dielectric::new_tinted(1.0, CheckerTexture::new(100.0, red, blue).into())
+    }
+
+    #[test]
+    fn stained_glass_tints_light_by_where_it_passes_through() {
+        let glass: Arc<Material> = Arc::new(half_red_half_blue().into());
+        let (a, b) = (Vec3::new(-1.0, 0.5, 0.0), Vec3::new(1.0, 0.5, 0.0));
+        let (c, d) = (Vec3::new(1.0, 0.5, 2.0), Vec3::new(-1.0, 0.5, 2.0));
+        let mut world = World::build(vec![
+            Triangle::new(a, b, c, glass.clone()).into(),
+            Triangle::new(a, c, d, glass.clone()).into(),
+        ]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::ONE,
+            top: Vec3::ONE,
+        };
+        let mut sky = World::build(Vec::new());
+        sky.background = world.background.clone();
+        // The pane fills the view, with +x to the right
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -4.0, 1.0))
+            .with_look_at(Vec3::new(0.0, 0.0, 1.0))
+            .with_vertical_fov(20.0)
+            .with_resolution(8, 8)
+            .with_samples(4)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(1);
+        let (image, open) = (camera.render_image(&world), camera.render_image(&sky));
+        let (blue, red) = (Vec3::new(0.1, 0.2, 0.9), Vec3::new(0.9, 0.1, 0.1));
+        for y in 0..8 {
+            // Leaving out the two columns the split runs between, which see a little of both
+            for (x, tint) in [
+                (0, blue),
+                (1, blue),
+                (2, blue),
+                (5, red),
+                (6, red),
+                (7, red),
+            ] {
+                let expected = open[(x, y)].component_mul(&tint);
+                let color = image[(x, y)];
+                assert!(
+                    (color - expected).abs().max() < 1e-3,
+                    "{} rather than {} at {:?}",
+                    color,
+                    expected,
+                    (x, y)
+                );
+            }
+        }
+
+        // Shadow rays through either half pick up its color, but only what isn't reflected
+        let pane = half_red_half_blue();
+        for (x, expected) in [(-0.5, blue), (0.5, red)] {
+            let point = Vec3::new(x, 0.5, 1.0);
+            let record = Intersection::new(point, -Vec3::y(), 1.0, &glass, true, Vec2::zeros());
+            let straight = Ray::new((point - Vec3::y()).into(), Vec3::y());
+            assert!(
+                (pane.shadow_transmittance(&straight, &record) - expected)
+                    .abs()
+                    .max()
+                    < 1e-9
+            );
+            let grazing = Ray::new(
+                (point - Vec3::new(1.0, 0.2, 0.0)).into(),
+                Vec3::new(1.0, 0.2, 0.0),
+            );
+            let transmitted = pane.shadow_transmittance(&grazing, &record);
+            assert!(transmitted
+                .iter()
+                .zip(expected.iter())
+                .all(|(t, e)| *t < *e && *t > 0.0));
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use crate::{
+    camera::{Float, Image},
+    material::{Lambertian, Material, Metal},
+    sky_importance::luminance,
+    texture::{ImageTexture, TextureEnum},
+    vec3::Vec3,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Images darker than this on average are kept matte, since a dark glossy surface only shows
+/// its reflections and none of its texture
+const DARK_LUMINANCE: Float = 0.03;
+
+/// Images brighter than this on average are scaled down to it. A surface giving back nearly all
+/// the light that reaches it reads as glowing, and paths take many bounces to settle on it.
+const BRIGHT_LUMINANCE: Float = 0.85;
+
+/// Images without companion maps this pale and gray are usually metal, plastic or polished
+/// stone, and get a blurry sheen instead of being matte
+const GLOSSY_MAX_SATURATION: Float = 0.15;
+const GLOSSY_MIN_LUMINANCE: Float = 0.35;
+
+/// Fuzz of the sheen pale gray images get when there's no roughness map to say
+const GLOSSY_FUZZ: Float = 0.5;
+
+/// Non-metals with a roughness map smoother than this on average get a sheen, rougher ones are
+/// matte
+const GLOSSY_MAX_ROUGHNESS: Float = 0.5;
+
+/// Metalness maps brighter than this on average make the surface a metal
+const METAL_MIN_METALNESS: Float = 0.5;
+
+/// Distance in world units a height map raises the surface by where it's white, about right for
+/// fine detail on an object a couple of units across
+const HEIGHT_MAP_STRENGTH: Float = 0.005;
+
+/// Suffixes a base color map may have, which its companions' names leave out
+const COLOR_SUFFIXES: [&str; 7] = [
+    "color",
+    "col",
+    "albedo",
+    "diffuse",
+    "diff",
+    "basecolor",
+    "base_color",
+];
+
+/// Where the image to infer a material from comes from
+#[derive(Debug, Clone, Copy)]
+pub enum ImageSource<'a> {
+    /// A file, which companion maps are looked for next to
+    Path(&'a Path),
+    /// An image file held in memory, which has no companions
+    Bytes(&'a [u8]),
+}
+
+/// A map in a set of PBR textures, found next to the base color map by its name, see
+/// [`companion_maps`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapKind {
+    Roughness,
+    Metalness,
+    Height,
+    Normal,
+}
+
+impl MapKind {
+    pub const ALL: [MapKind; 4] = [
+        MapKind::Roughness,
+        MapKind::Metalness,
+        MapKind::Height,
+        MapKind::Normal,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapKind::Roughness => "roughness",
+            MapKind::Metalness => "metalness",
+            MapKind::Height => "height",
+            MapKind::Normal => "normal",
+        }
+    }
+
+    /// What the map's file name ends in after the base color map's, following the usual PBR
+    /// naming conventions
+    fn suffixes(&self) -> &'static [&'static str] {
+        match self {
+            MapKind::Roughness => &["rough", "roughness", "rgh"],
+            MapKind::Metalness => &["metal", "metallic", "metalness", "mtl"],
+            MapKind::Height => &["height", "bump", "disp", "displacement"],
+            MapKind::Normal => &["normal", "nor", "nrm", "normalgl", "normaldx", "nor_gl"],
+        }
+    }
+}
+
+/// What an image looks like on average, in the raw channel values textures are looked up as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageStats {
+    pub mean: Vec3,
+    pub luminance: Float,
+    /// Average of every pixel's saturation, with 0 for gray and 1 for pure colors
+    pub saturation: Float,
+}
+
+impl ImageStats {
+    pub fn of(image: &Image) -> Self {
+        let count = image.pixels.len().max(1) as Float;
+        let mean = image.pixels.iter().sum::<Vec3>() / count;
+        let saturation = image
+            .pixels
+            .iter()
+            .map(|color| {
+                let (max, min) = (color.max(), color.min());
+                if max > 0.0 {
+                    (max - min) / max
+                } else {
+                    0.0
+                }
+            })
+            .sum::<Float>()
+            / count;
+        ImageStats {
+            mean,
+            luminance: luminance(&mean),
+            saturation,
+        }
+    }
+}
+
+/// A material made from an image by [`Material::infer_from_image`], with what went into it
+#[derive(Debug)]
+pub struct InferredMaterial {
+    pub material: Material,
+    /// The companion maps it uses
+    pub maps: Vec<(MapKind, PathBuf)>,
+    /// Companion maps that were found and left out, and adjustments made to the image, for
+    /// telling whoever asked
+    pub notes: Vec<String>,
+}
+
+/// The part of a base color map's file name its companions share, lowercased, like `brick` for
+/// `Brick_Color.png`
+fn base_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    for suffix in COLOR_SUFFIXES {
+        for separator in ['_', '-'] {
+            if let Some(base) = stem.strip_suffix(&format!("{}{}", separator, suffix)) {
+                if !base.is_empty() {
+                    return Some(base.to_string());
+                }
+            }
+        }
+    }
+    Some(stem)
+}
+
+/// Finds the maps next to the base color map at `path` named after it, like `brick_rough.jpg`
+/// or `brick-normal.png` for `brick.png` or `brick_color.png`. Names are compared ignoring
+/// case, and any image format goes. When there's more than one map of a kind the first by name
+/// is used.
+pub fn companion_maps(path: &Path) -> Vec<(MapKind, PathBuf)> {
+    let Some(base) = base_name(path) else {
+        return Vec::new();
+    };
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| image::ImageFormat::from_path(name).is_ok())
+        .collect();
+    names.sort();
+    let kind_of = |name: &str| {
+        let stem = Path::new(name).file_stem()?.to_str()?.to_lowercase();
+        let suffix = stem.strip_prefix(&base)?.strip_prefix(['_', '-'])?;
+        MapKind::ALL
+            .into_iter()
+            .find(|kind| kind.suffixes().contains(&suffix))
+    };
+    MapKind::ALL
+        .into_iter()
+        .filter_map(|kind| {
+            let name = names.iter().find(|name| kind_of(name) == Some(kind))?;
+            Some((kind, path.with_file_name(name)))
+        })
+        .filter(|(_, file)| file.is_file())
+        .collect()
+}
+
+/// `image` with every pixel scaled by `factor`
+fn scaled(image: &Image, factor: Float) -> Image {
+    Image {
+        pixels: image.pixels.iter().map(|color| color * factor).collect(),
+        width: image.width,
+        height: image.height,
+        gamma: image.gamma,
+        metadata: image.metadata.clone(),
+        alpha: None,
+    }
+}
+
+/// Whether `map` covers the same texture coordinates as `base` the same way, so they line up.
+/// Only the aspect ratio has to match, since textures are looked up by texture coordinates
+/// whatever their size.
+fn lines_up(base: &Image, map: &Image) -> bool {
+    base.width * map.height == map.width * base.height
+}
+
+impl Material {
+    /// Makes a plausible material out of a single image, for looking at a texture without
+    /// writing a material for it. Companion maps named after a file, see [`companion_maps`],
+    /// decide what it's made of: a metalness map makes it a metal, a roughness map sets how
+    /// blurry its reflections are, and a height map bumps it. Without them, pale gray images
+    /// get a blurry sheen and everything else is matte. Very bright images are dimmed, and
+    /// companions that can't be loaded or don't line up with the image are left out with a
+    /// note.
+    pub fn infer_from_image(source: ImageSource) -> Result<InferredMaterial, String> {
+        let (base, companions) = match source {
+            ImageSource::Path(path) => (ImageTexture::open(path)?, companion_maps(path)),
+            ImageSource::Bytes(bytes) => (ImageTexture::from_bytes(bytes)?, Vec::new()),
+        };
+        let mut notes = Vec::new();
+        let mut maps = Vec::new();
+        let mut roughness = None;
+        let mut metalness = None;
+        let mut height = None;
+        for (kind, path) in companions {
+            let map = match ImageTexture::open(&path) {
+                Ok(map) => map,
+                Err(err) => {
+                    notes.push(format!(
+                        "left out {} map {}: {}",
+                        kind.name(),
+                        path.display(),
+                        err
+                    ));
+                    continue;
+                }
+            };
+            if !lines_up(&base.image, &map.image) {
+                notes.push(format!(
+                    "left out {} map {}: it's {}x{} and doesn't line up with the {}x{} image",
+                    kind.name(),
+                    path.display(),
+                    map.image.width,
+                    map.image.height,
+                    base.image.width,
+                    base.image.height
+                ));
+                continue;
+            }
+            // The maps are gray, so any channel's average is the map's
+            let average = ImageStats::of(&map.image).luminance;
+            match kind {
+                MapKind::Roughness => roughness = Some(average),
+                MapKind::Metalness => metalness = Some(average),
+                MapKind::Height => height = Some(map),
+                MapKind::Normal => {
+                    notes.push(format!(
+                        "left out normal map {}: only height maps can bump surfaces",
+                        path.display()
+                    ));
+                    continue;
+                }
+            }
+            maps.push((kind, path));
+        }
+
+        let stats = ImageStats::of(&base.image);
+        let texture: TextureEnum = if stats.luminance > BRIGHT_LUMINANCE {
+            notes.push(format!(
+                "dimmed the image from an average brightness of {:.2} to {:.2}",
+                stats.luminance, BRIGHT_LUMINANCE
+            ));
+            let dimmed = scaled(&base.image, BRIGHT_LUMINANCE / stats.luminance);
+            ImageTexture {
+                image: Arc::new(dimmed),
+                source: base.source,
+            }
+            .into()
+        } else {
+            base.into()
+        };
+
+        let fuzz = match (metalness, roughness) {
+            (Some(metalness), roughness) if metalness >= METAL_MIN_METALNESS => {
+                Some(roughness.unwrap_or(GLOSSY_FUZZ))
+            }
+            (Some(_), _) => None,
+            (None, Some(roughness)) => (roughness < GLOSSY_MAX_ROUGHNESS).then_some(roughness),
+            (None, None) => (stats.luminance >= GLOSSY_MIN_LUMINANCE
+                && stats.saturation <= GLOSSY_MAX_SATURATION)
+                .then_some(GLOSSY_FUZZ),
+        };
+        let fuzz = if fuzz.is_some() && stats.luminance < DARK_LUMINANCE {
+            notes.push("kept the nearly black image matte".to_string());
+            None
+        } else {
+            fuzz
+        };
+
+        let material = match (fuzz, height) {
+            (Some(fuzz), Some(height)) => Metal::new(texture, Some(fuzz))
+                .with_bump(height.into(), HEIGHT_MAP_STRENGTH)
+                .into(),
+            (Some(fuzz), None) => Metal::new(texture, Some(fuzz)).into(),
+            (None, Some(height)) => Lambertian::new(texture)
+                .with_bump(height.into(), HEIGHT_MAP_STRENGTH)
+                .into(),
+            (None, None) => Lambertian::new(texture).into(),
+        };
+        Ok(InferredMaterial {
+            material,
+            maps,
+            notes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The fixture texture at `name` under `tests/fixtures/textures`
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/textures")
+            .join(name)
+    }
+
+    fn infer(name: &str) -> InferredMaterial {
+        Material::infer_from_image(ImageSource::Path(&fixture(name))).unwrap()
+    }
+
+    fn kinds(inferred: &InferredMaterial) -> Vec<MapKind> {
+        inferred.maps.iter().map(|(kind, _)| *kind).collect()
+    }
+
+    #[test]
+    fn companions_are_found_by_name_ignoring_case_and_separator() {
+        let names: Vec<_> = companion_maps(&fixture("metal/brick_color.png"))
+            .into_iter()
+            .map(|(kind, path)| (kind, path.file_name().unwrap().to_owned()))
+            .collect();
+        // bricks_rough.png shares the start of the name, but isn't brick's
+        assert_eq!(
+            names,
+            [
+                (MapKind::Roughness, "BRICK_ROUGH.png".into()),
+                (MapKind::Metalness, "brick_metal.png".into()),
+                (MapKind::Height, "brick_height.png".into()),
+                (MapKind::Normal, "brick-nrm.png".into()),
+            ]
+        );
+        assert_eq!(base_name(Path::new("Tile_Albedo.png")).unwrap(), "tile");
+        assert_eq!(base_name(Path::new("stone-diff.png")).unwrap(), "stone");
+        assert_eq!(base_name(Path::new("_color.png")).unwrap(), "_color");
+        assert!(companion_maps(&fixture("plain/terracotta.png")).is_empty());
+    }
+
+    #[test]
+    fn a_lone_color_texture_is_lambertian() {
+        let inferred = infer("plain/terracotta.png");
+        assert!(matches!(
+            inferred.material,
+            Material::Lambertian(Lambertian { bump: None, .. })
+        ));
+        assert!(inferred.maps.is_empty() && inferred.notes.is_empty());
+
+        // Bytes have nowhere to look for companions
+        let bytes = fs::read(fixture("metal/brick_color.png")).unwrap();
+        let inferred = Material::infer_from_image(ImageSource::Bytes(&bytes)).unwrap();
+        assert!(matches!(inferred.material, Material::Lambertian(_)));
+        assert!(inferred.maps.is_empty());
+    }
+
+    #[test]
+    fn metalness_and_roughness_maps_make_a_metal() {
+        let inferred = infer("metal/brick_color.png");
+        let Material::Metal(Metal {
+            fuzz: Some(fuzz),
+            bump: None,
+            ..
+        }) = inferred.material
+        else {
+            panic!("not a bumpless metal: {:?}", inferred.material.name());
+        };
+        assert!((fuzz - 77.0 / 255.0).abs() < 1e-9, "{}", fuzz);
+        assert_eq!(kinds(&inferred), [MapKind::Roughness, MapKind::Metalness]);
+        // The normal map isn't supported, and the height map is half as wide
+        assert_eq!(inferred.notes.len(), 2, "{:?}", inferred.notes);
+        assert!(inferred.notes[0].starts_with("left out height map"));
+        assert!(inferred.notes[0].ends_with("it's 2x4 and doesn't line up with the 4x4 image"));
+        assert!(inferred.notes[1].starts_with("left out normal map"));
+    }
+
+    #[test]
+    fn smooth_non_metals_are_glossy_and_rough_ones_matte() {
+        // The height map is twice the size, but lines up
+        let tile = infer("tile/tile_albedo.png");
+        let Material::Metal(Metal {
+            fuzz: Some(fuzz),
+            bump: Some(bump),
+            ..
+        }) = &tile.material
+        else {
+            panic!("not a bumped metal: {:?}", tile.material.name());
+        };
+        assert!((fuzz - 0.2).abs() < 1e-9, "{}", fuzz);
+        assert_eq!(bump.strength, HEIGHT_MAP_STRENGTH);
+        assert_eq!(kinds(&tile), [MapKind::Roughness, MapKind::Height]);
+        assert!(tile.notes.is_empty(), "{:?}", tile.notes);
+
+        let stone = infer("stone/stone-diff.png");
+        assert!(matches!(
+            stone.material,
+            Material::Lambertian(Lambertian { bump: None, .. })
+        ));
+        assert_eq!(kinds(&stone), [MapKind::Roughness]);
+    }
+
+    #[test]
+    fn bright_images_are_dimmed_and_black_ones_stay_matte() {
+        let snow = infer("snow/snow.png");
+        let Material::Metal(Metal {
+            texture: TextureEnum::ImageTexture(texture),
+            fuzz: Some(fuzz),
+            ..
+        }) = &snow.material
+        else {
+            panic!("not a glossy image: {:?}", snow.material.name());
+        };
+        assert_eq!(*fuzz, GLOSSY_FUZZ);
+        assert!((ImageStats::of(&texture.image).luminance - BRIGHT_LUMINANCE).abs() < 1e-9);
+        assert_eq!(
+            snow.notes,
+            ["dimmed the image from an average brightness of 1.00 to 0.85"]
+        );
+
+        // The metalness map would make it a metal, and the bump map can't be read
+        let coal = infer("coal/coal.png");
+        assert!(matches!(
+            coal.material,
+            Material::Lambertian(Lambertian { bump: None, .. })
+        ));
+        assert_eq!(kinds(&coal), [MapKind::Metalness]);
+        assert_eq!(coal.notes.len(), 2, "{:?}", coal.notes);
+        assert!(coal.notes[0].starts_with("left out height map"));
+        assert_eq!(coal.notes[1], "kept the nearly black image matte");
+    }
+}
@@ -0,0 +1,948 @@
+use crate::{
+    assets::{Asset, AssetResolver},
+    camera::Float,
+    include::{self, IncludeError},
+    material::{
+        AlphaMask, Dielectric, DiffuseLight, Lambertian, Material, Metal, ShadowCatcher, Volumetric,
+    },
+    ron_format,
+    texture::{
+        CheckerSpace, CheckerTexture, ImageTexture, LoadReport, SolidColor, TextureEnum,
+        TextureLoadFailure,
+    },
+    vec3::Vec3,
+};
+use ron::error::SpannedError;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Version of the library format that [`MaterialLibrary::to_library_string`] writes
+pub const LIBRARY_VERSION: u32 = 1;
+
+/// A texture as it's written in a material library
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TextureSpec {
+    Solid(Vec3),
+    Checker {
+        scale: Float,
+        #[serde(default)]
+        space: CheckerSpace,
+        #[serde(default, skip_serializing_if = "is_false")]
+        filtered: bool,
+        even: Box<TextureSpec>,
+        odd: Box<TextureSpec>,
+    },
+    /// An image file. Relative paths in a library file are relative to the library, and are
+    /// resolved when it's loaded.
+    Image(PathBuf),
+}
+
+/// A material as it's written in a material library, which can be compared and edited
+/// unlike the [`Material`] it builds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MaterialSpec {
+    Lambertian {
+        texture: TextureSpec,
+    },
+    Metal {
+        texture: TextureSpec,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fuzz: Option<Float>,
+    },
+    Dielectric {
+        refractive_index: Float,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fuzz: Option<Float>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tint: Option<TextureSpec>,
+    },
+    /// Wraps the library material named `base`
+    AlphaMask {
+        base: String,
+        coverage: TextureSpec,
+    },
+    Volumetric {
+        albedo: Vec3,
+        #[serde(default)]
+        anisotropy: Float,
+    },
+    DiffuseLight {
+        texture: TextureSpec,
+        #[serde(default = "full_intensity")]
+        intensity: Float,
+    },
+    ShadowCatcher,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn full_intensity() -> Float {
+    1.0
+}
+
+impl MaterialSpec {
+    /// Every texture the material is made of
+    fn textures_mut(&mut self) -> Vec<&mut TextureSpec> {
+        match self {
+            MaterialSpec::Lambertian { texture }
+            | MaterialSpec::Metal { texture, .. }
+            | MaterialSpec::DiffuseLight { texture, .. } => vec![texture],
+            MaterialSpec::Dielectric { tint, .. } => tint.iter_mut().collect(),
+            MaterialSpec::AlphaMask { coverage, .. } => vec![coverage],
+            MaterialSpec::Volumetric { .. } | MaterialSpec::ShadowCatcher => Vec::new(),
+        }
+    }
+
+    /// Makes the material's relative image paths relative to `directory` instead of to
+    /// wherever they're read from, as files written in `directory` mean them
+    pub(crate) fn resolve_images(&mut self, directory: &Path) {
+        for texture in self.textures_mut() {
+            texture.map_images(&mut |path| directory.join(path));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Io(io::Error),
+    /// A file that couldn't be parsed, with where the problem is
+    Malformed {
+        location: String,
+        message: String,
+    },
+    /// A material that isn't in the library
+    UnknownMaterial(String),
+    /// An alpha mask that is, through its bases, its own base
+    CyclicBase(String),
+    /// The library's files couldn't be read, or include each other in a loop
+    Include(IncludeError),
+    /// A material that can't be written out, e.g. because its image didn't come from a file
+    Unsaveable {
+        material: String,
+        reason: String,
+    },
+}
+
+impl LibraryError {
+    /// A RON error in the file at `file`, or in text that didn't come from one
+    fn parse(file: Option<&Path>, err: SpannedError) -> Self {
+        LibraryError::Malformed {
+            location: ron_format::location(file, err.span.start),
+            message: err.code.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryError::Io(err) => write!(f, "failed to read material library: {}", err),
+            LibraryError::Malformed { location, message } => {
+                write!(f, "{}: {}", location, message)
+            }
+            LibraryError::UnknownMaterial(name) => write!(f, "no material named '{}'", name),
+            LibraryError::Include(err) => write!(f, "{}", err),
+            LibraryError::CyclicBase(name) => {
+                write!(f, "alpha mask '{}' ends up being its own base", name)
+            }
+            LibraryError::Unsaveable { material, reason } => {
+                write!(f, "can't save material '{}': {}", material, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+impl From<io::Error> for LibraryError {
+    fn from(err: io::Error) -> Self {
+        LibraryError::Io(err)
+    }
+}
+
+impl From<IncludeError> for LibraryError {
+    fn from(err: IncludeError) -> Self {
+        LibraryError::Include(err)
+    }
+}
+
+/// Named materials kept in a human-editable sidecar file, so a project's materials can be tweaked
+/// without recompiling. A library is a [RON](https://github.com/ron-rs/ron) struct mapping each
+/// material's name to its settings:
+///
+/// ```text
+/// // rt material library
+/// (
+///     version: 1,
+///     include: ["../shared/walls.ron"],
+///     materials: {
+///         "brick": Lambertian(
+///             texture: Checker(
+///                 scale: 0.5,
+///                 even: Solid((0.6, 0.2, 0.1)),
+///                 odd: Image("bricks/old mortar.png"),
+///             ),
+///         ),
+///         "window": Dielectric(refractive_index: 1.5, tint: Solid((0.8, 0.9, 1.0))),
+///         "ground": ShadowCatcher,
+///     },
+/// )
+/// ```
+///
+/// Materials are [`MaterialSpec`]s and textures are [`TextureSpec`]s, written by their variant
+/// names. Colors are `(r, g, b)`, a checker's `space` is `World` by default or `Uv` for checks
+/// over the surface's UVs, and `filtered: true` fades its checks to gray far away, see
+/// [`CheckerTexture::new_filtered`]. Optional settings can be left out, and are written without
+/// `Some`. Settings a version doesn't know about are skipped with a warning, so libraries
+/// written by newer versions still load.
+///
+/// `include` pulls in other libraries first, relative to the file it's in. Materials defined
+/// later replace earlier ones with the same name, so a variant can include a base library and
+/// redefine only what it changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaterialLibrary {
+    /// Materials in the order they're written
+    pub materials: Vec<(String, MaterialSpec)>,
+    /// Settings that were skipped while loading, e.g. ones added in a newer version
+    pub warnings: Vec<String>,
+}
+
+/// One library file as it's written
+#[derive(Serialize, Deserialize)]
+struct LibraryFile {
+    #[serde(default = "first_version")]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<PathBuf>,
+    #[serde(default, with = "ron_format::named")]
+    materials: Vec<(String, MaterialSpec)>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl TextureSpec {
+    /// Replaces every image path in the texture with `map` of it
+    fn map_images(&mut self, map: &mut impl FnMut(&Path) -> PathBuf) {
+        match self {
+            TextureSpec::Solid(_) => {}
+            TextureSpec::Checker { even, odd, .. } => {
+                even.map_images(map);
+                odd.map_images(map);
+            }
+            TextureSpec::Image(path) => *path = map(path),
+        }
+    }
+
+    fn describe(texture: &TextureEnum) -> Result<Self, String> {
+        Ok(match texture {
+            TextureEnum::SolidColor(solid) => TextureSpec::Solid(solid.color),
+            TextureEnum::CheckerTexture(checker) => TextureSpec::Checker {
+                scale: checker.scale(),
+                space: checker.space(),
+                filtered: checker.is_filtered(),
+                even: Box::new(TextureSpec::describe(checker.even_texture())?),
+                odd: Box::new(TextureSpec::describe(checker.odd_texture())?),
+            },
+            TextureEnum::ImageTexture(image) => TextureSpec::Image(
+                image
+                    .source
+                    .clone()
+                    .ok_or("its image wasn't loaded from a file")?,
+            ),
+            TextureEnum::NoiseTexture(_) => return Err("it's procedural noise".into()),
+        })
+    }
+
+    /// Appends the image files the texture uses to `paths`
+    fn images<'a>(&'a self, paths: &mut Vec<&'a Path>) {
+        match self {
+            TextureSpec::Solid(_) => {}
+            TextureSpec::Checker { even, odd, .. } => {
+                even.images(paths);
+                odd.images(paths);
+            }
+            TextureSpec::Image(path) => paths.push(path),
+        }
+    }
+
+    /// Builds the texture, with images found by `assets` and replaced with the placeholder if
+    /// they fail to load
+    fn build(
+        &self,
+        material: &str,
+        assets: &AssetResolver,
+        report: &mut LoadReport,
+    ) -> TextureEnum {
+        match self {
+            TextureSpec::Solid(color) => SolidColor::new(*color).into(),
+            TextureSpec::Checker {
+                scale,
+                space,
+                filtered,
+                even,
+                odd,
+            } => {
+                let even = even.build(material, assets, report);
+                let odd = odd.build(material, assets, report);
+                let checker = match space {
+                    CheckerSpace::World => CheckerTexture::new(*scale, even, odd),
+                    CheckerSpace::Uv => CheckerTexture::new_uv(*scale, even, odd),
+                };
+                checker.with_filtering(*filtered).into()
+            }
+            TextureSpec::Image(path) => match open_image(path, assets) {
+                Ok(texture) => texture.into(),
+                Err(reason) => {
+                    report.record_texture_failure(TextureLoadFailure {
+                        source: path.display().to_string(),
+                        reason,
+                        material: Some(material.to_string()),
+                    });
+                    let mut placeholder = ImageTexture::placeholder();
+                    placeholder.source = Some(path.clone());
+                    placeholder.into()
+                }
+            },
+        }
+    }
+}
+
+/// Loads the image at `path` wherever `assets` finds it, remembering `path` as where images
+/// compiled into the binary came from so libraries still save them the way they were written
+fn open_image(path: &Path, assets: &AssetResolver) -> Result<ImageTexture, String> {
+    match assets.resolve(path).map_err(|err| err.to_string())? {
+        Asset::File(file) => ImageTexture::open(file.path),
+        Asset::Embedded { bytes, .. } => Ok(ImageTexture {
+            source: Some(path.to_path_buf()),
+            ..ImageTexture::from_bytes(bytes)?
+        }),
+    }
+}
+
+impl MaterialLibrary {
+    pub fn get(&self, name: &str) -> Option<&MaterialSpec> {
+        self.materials
+            .iter()
+            .find(|(material, _)| material == name)
+            .map(|(_, spec)| spec)
+    }
+
+    /// Adds `spec` as `name`, replacing any material that already has that name
+    pub fn set(&mut self, name: &str, spec: MaterialSpec) {
+        match self
+            .materials
+            .iter_mut()
+            .find(|(material, _)| material == name)
+        {
+            Some((_, existing)) => *existing = spec,
+            None => self.materials.push((name.to_string(), spec)),
+        }
+    }
+
+    /// Adds a description of `material` as `name`. An alpha mask's base material is added too,
+    /// as `<name>.base`.
+    pub fn insert(&mut self, name: &str, material: &Material) -> Result<(), LibraryError> {
+        let unsaveable = |reason: String| LibraryError::Unsaveable {
+            material: name.to_string(),
+            reason,
+        };
+        let spec = match material {
+            Material::Lambertian(lambertian) => MaterialSpec::Lambertian {
+                texture: TextureSpec::describe(&lambertian.texture).map_err(unsaveable)?,
+            },
+            Material::Metal(metal) => MaterialSpec::Metal {
+                texture: TextureSpec::describe(&metal.texture).map_err(unsaveable)?,
+                fuzz: metal.fuzz,
+            },
+            Material::Dielectric(dielectric) => MaterialSpec::Dielectric {
+                refractive_index: dielectric.refractive_index,
+                fuzz: dielectric.fuzz,
+                tint: dielectric
+                    .tint
+                    .as_ref()
+                    .map(|tint| TextureSpec::describe(tint))
+                    .transpose()
+                    .map_err(unsaveable)?,
+            },
+            Material::AlphaMask(mask) => {
+                let base = format!("{}.base", name);
+                self.insert(&base, &mask.base)?;
+                MaterialSpec::AlphaMask {
+                    base,
+                    coverage: TextureSpec::describe(&mask.coverage).map_err(unsaveable)?,
+                }
+            }
+            Material::Volumetric(volumetric) => MaterialSpec::Volumetric {
+                albedo: volumetric.albedo,
+                anisotropy: volumetric.anisotropy,
+            },
+            Material::DiffuseLight(light) => MaterialSpec::DiffuseLight {
+                texture: TextureSpec::describe(&light.texture).map_err(unsaveable)?,
+                intensity: light.intensity,
+            },
+            Material::ShadowCatcher(_) => MaterialSpec::ShadowCatcher,
+        };
+        self.set(name, spec);
+        Ok(())
+    }
+
+    /// Builds the material called `name`, with its images found by `assets`. Images that fail to
+    /// load are replaced with the placeholder and recorded in `report`.
+    pub fn build(
+        &self,
+        name: &str,
+        assets: &AssetResolver,
+        report: &mut LoadReport,
+    ) -> Result<Material, LibraryError> {
+        self.build_checked(name, assets, report, &mut Vec::new())
+    }
+
+    /// Builds `name`, with `building` holding the alpha masks whose bases are being built so a
+    /// mask that's its own base is caught instead of recursing forever
+    fn build_checked(
+        &self,
+        name: &str,
+        assets: &AssetResolver,
+        report: &mut LoadReport,
+        building: &mut Vec<String>,
+    ) -> Result<Material, LibraryError> {
+        let spec = self
+            .get(name)
+            .ok_or_else(|| LibraryError::UnknownMaterial(name.to_string()))?;
+        Ok(match spec {
+            MaterialSpec::Lambertian { texture } => {
+                Lambertian::new(texture.build(name, assets, report)).into()
+            }
+            MaterialSpec::Metal { texture, fuzz } => {
+                Metal::new(texture.build(name, assets, report), *fuzz).into()
+            }
+            MaterialSpec::Dielectric {
+                refractive_index,
+                fuzz,
+                tint,
+            } => {
+                let mut dielectric = Dielectric::new(*refractive_index);
+                dielectric.fuzz = *fuzz;
+                dielectric.tint = tint
+                    .as_ref()
+                    .map(|tint| Arc::new(tint.build(name, assets, report)));
+                dielectric.into()
+            }
+            MaterialSpec::AlphaMask { base, coverage } => {
+                if building.iter().any(|material| material == name) {
+                    return Err(LibraryError::CyclicBase(name.to_string()));
+                }
+                building.push(name.to_string());
+                let base_material = self.build_checked(base, assets, report, building)?;
+                building.pop();
+                AlphaMask::new(base_material, coverage.build(name, assets, report)).into()
+            }
+            MaterialSpec::Volumetric { albedo, anisotropy } => {
+                Volumetric::new(*albedo, *anisotropy).into()
+            }
+            MaterialSpec::DiffuseLight { texture, intensity } => {
+                DiffuseLight::new(texture.build(name, assets, report))
+                    .with_intensity(*intensity)
+                    .into()
+            }
+            MaterialSpec::ShadowCatcher => ShadowCatcher.into(),
+        })
+    }
+
+    /// Builds every material in the library, keyed by name
+    pub fn build_all(
+        &self,
+        assets: &AssetResolver,
+        report: &mut LoadReport,
+    ) -> Result<HashMap<String, Arc<Material>>, LibraryError> {
+        self.materials
+            .iter()
+            .map(|(name, _)| Ok((name.clone(), Arc::new(self.build(name, assets, report)?))))
+            .collect()
+    }
+
+    /// The image files each material uses, by material name
+    pub fn images(&self) -> Vec<(&str, &Path)> {
+        let mut images = Vec::new();
+        for (name, spec) in &self.materials {
+            let textures = match spec {
+                MaterialSpec::Lambertian { texture }
+                | MaterialSpec::Metal { texture, .. }
+                | MaterialSpec::DiffuseLight { texture, .. } => vec![texture],
+                MaterialSpec::Dielectric { tint, .. } => tint.iter().collect(),
+                MaterialSpec::AlphaMask { coverage, .. } => vec![coverage],
+                MaterialSpec::Volumetric { .. } | MaterialSpec::ShadowCatcher => Vec::new(),
+            };
+            let mut paths = Vec::new();
+            for texture in textures {
+                texture.images(&mut paths);
+            }
+            images.extend(paths.into_iter().map(|path| (name.as_str(), path)));
+        }
+        images
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LibraryError> {
+        MaterialLibrary::load_all(&[path])
+    }
+
+    /// Loads a library made of several files, in order, along with the files they `include`.
+    /// A material in a later file replaces one with the same name from an earlier file, and
+    /// image paths are relative to the file they're written in.
+    pub fn load_all(paths: &[impl AsRef<Path>]) -> Result<Self, LibraryError> {
+        MaterialLibrary::load_all_with_sources(paths).map(|(library, _)| library)
+    }
+
+    /// Like [`MaterialLibrary::load_all`], also returning every file that was read, each after
+    /// the files it includes
+    pub fn load_all_with_sources(
+        paths: &[impl AsRef<Path>],
+    ) -> Result<(Self, Vec<PathBuf>), LibraryError> {
+        let files = include::read_descriptions(paths, |path, source| {
+            let mut skipped = Vec::new();
+            let file: LibraryFile = ron_format::parse(source, |setting| skipped.push(setting))
+                .map_err(|err| LibraryError::parse(Some(path), err))?;
+            let includes = file.include.clone();
+            Ok::<_, LibraryError>(((file, skipped), includes))
+        })?;
+        let mut library = MaterialLibrary::default();
+        let mut sources = Vec::new();
+        for (path, (file, skipped)) in files {
+            let directory = path.parent().unwrap_or(Path::new(""));
+            library.add_file(file, &path.display().to_string(), directory, skipped);
+            sources.push(path);
+        }
+        Ok((library, sources))
+    }
+
+    /// Adds the materials in `file`, which is at `location` and has image paths relative to
+    /// `directory`, along with warnings about its version and the `skipped` settings in it
+    fn add_file(
+        &mut self,
+        file: LibraryFile,
+        location: &str,
+        directory: &Path,
+        skipped: Vec<String>,
+    ) {
+        if file.version > LIBRARY_VERSION {
+            self.warnings.push(format!(
+                "{}: library is version {} but only version {} is understood, so newer settings \
+                 will be skipped",
+                location, file.version, LIBRARY_VERSION
+            ));
+        }
+        self.warnings.extend(
+            skipped
+                .into_iter()
+                .map(|setting| format!("{}: skipped unknown setting '{}'", location, setting)),
+        );
+        for (name, mut spec) in file.materials {
+            spec.resolve_images(directory);
+            self.set(&name, spec);
+        }
+    }
+
+    /// Writes the library to `path`, with image paths relative to its directory where possible
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or(Path::new(""));
+        fs::write(path, self.to_library_string(directory))
+    }
+
+    /// Serializes the library, writing image paths relative to `directory` where possible
+    pub fn to_library_string(&self, directory: &Path) -> String {
+        let mut materials = self.materials.clone();
+        for (_, spec) in &mut materials {
+            for texture in spec.textures_mut() {
+                texture.map_images(&mut |path| {
+                    path.strip_prefix(directory).unwrap_or(path).to_path_buf()
+                });
+            }
+        }
+        let file = LibraryFile {
+            version: LIBRARY_VERSION,
+            include: Vec::new(),
+            materials,
+        };
+        format!("// rt material library\n{}\n", ron_format::to_string(&file))
+    }
+
+    /// Parses a library, resolving relative image paths and includes against `directory`
+    pub fn parse(source: &str, directory: &Path) -> Result<Self, LibraryError> {
+        let mut skipped = Vec::new();
+        let file: LibraryFile = ron_format::parse(source, |setting| skipped.push(setting))
+            .map_err(|err| LibraryError::parse(None, err))?;
+        let includes: Vec<PathBuf> = file
+            .include
+            .iter()
+            .map(|included| directory.join(included))
+            .collect();
+        let mut library = MaterialLibrary::load_all(&includes)?;
+        library.add_file(file, "library", directory, skipped);
+        Ok(library)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{scratch, write};
+    use crate::{
+        camera::Camera,
+        hittable::{Sphere, World},
+    };
+    use std::path::PathBuf;
+
+    #[test]
+    fn later_materials_replace_earlier_ones_in_place() {
+        let directory = scratch("replace");
+        write(
+            &directory.join("base.ron"),
+            r#"(materials: {
+                "floor": Lambertian(texture: Solid((0.5, 0.5, 0.5))),
+                "trim": Metal(texture: Solid((0.9, 0.9, 0.9)), fuzz: 0.1),
+            })"#,
+        );
+        write(
+            &directory.join("night.ron"),
+            r#"(
+                include: ["base.ron"],
+                materials: {"floor": Lambertian(texture: Solid((0.1, 0.1, 0.2)))},
+            )"#,
+        );
+        let (library, sources) =
+            MaterialLibrary::load_all_with_sources(&[directory.join("night.ron")]).unwrap();
+        assert_eq!(
+            sources,
+            [directory.join("base.ron"), directory.join("night.ron")]
+        );
+        let names: Vec<&str> = library
+            .materials
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["floor", "trim"]);
+        assert_eq!(
+            library.get("floor"),
+            Some(&MaterialSpec::Lambertian {
+                texture: TextureSpec::Solid(Vec3::new(0.1, 0.1, 0.2))
+            })
+        );
+        assert_eq!(
+            library.get("trim"),
+            Some(&MaterialSpec::Metal {
+                texture: TextureSpec::Solid(Vec3::repeat(0.9)),
+                fuzz: Some(0.1)
+            })
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn image_paths_are_relative_to_the_file_they_are_in() {
+        let directory = scratch("images");
+        write(
+            &directory.join("shared/walls.ron"),
+            r#"(materials: {"brick": Lambertian(texture: Image("textures/brick.png"))})"#,
+        );
+        write(
+            &directory.join("scene.ron"),
+            r#"(
+                include: ["shared/walls.ron"],
+                materials: {"sign": Lambertian(texture: Image("signs/open sign.png"))},
+            )"#,
+        );
+        let library = MaterialLibrary::load(directory.join("scene.ron")).unwrap();
+        assert_eq!(
+            library.images(),
+            [
+                (
+                    "brick",
+                    directory.join("shared/textures/brick.png").as_path()
+                ),
+                ("sign", directory.join("signs/open sign.png").as_path()),
+            ]
+        );
+        // Saving next to the scene writes them relative to it
+        let saved = library.to_library_string(&directory);
+        assert!(
+            saved.contains(r#"Image("shared/textures/brick.png")"#),
+            "{}",
+            saved
+        );
+        assert!(
+            saved.contains(r#"Image("signs/open sign.png")"#),
+            "{}",
+            saved
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn mistakes_say_where_they_are() {
+        let directory = scratch("mistakes");
+        let path = directory.join("broken.ron");
+        let error = |text: &str| {
+            write(&path, text);
+            MaterialLibrary::load(&path).unwrap_err().to_string()
+        };
+        let syntax = error("(materials: {\n  \"glass\": Dielectric(refractive_index: 1.5\n})");
+        let kind = error("(materials: {\n  \"floor\": Plastic(texture: Solid((1, 1, 1))),\n})");
+        let missing = error("(materials: {\n  \"glass\": Dielectric(fuzz: 0.1),\n})");
+        let included = error("(include: [\"nowhere.ron\"])");
+        fs::remove_dir_all(&directory).unwrap();
+
+        let at = |position: &str| format!("{}:{}: ", path.display(), position);
+        assert_eq!(syntax, at("3:1") + "Expected comma");
+        assert!(
+            kind.starts_with(&(at("2:12") + "Unexpected variant named `Plastic`")),
+            "{}",
+            kind
+        );
+        assert_eq!(
+            missing,
+            at("2:32") + "Unexpected missing field named `refractive_index` in `Dielectric`"
+        );
+        assert!(included.contains("nowhere.ron"), "{}", included);
+
+        let error = MaterialLibrary::parse("(materials: [])", Path::new("")).unwrap_err();
+        assert_eq!(error.to_string(), "line 1, column 12: Expected opening `{`");
+    }
+
+    #[test]
+    fn broken_images_are_replaced_with_the_placeholder_and_reported() {
+        let directory = scratch("broken");
+        let mut png = Vec::new();
+        image::ImageEncoder::write_image(
+            image::codecs::png::PngEncoder::new(&mut png),
+            &[200; 16 * 16 * 3],
+            16,
+            16,
+            image::ExtendedColorType::Rgb8,
+        )
+        .unwrap();
+        fs::write(directory.join("truncated.png"), &png[..png.len() / 2]).unwrap();
+        write(&directory.join("notes.xyz"), "not an image in any format");
+        write(
+            &directory.join("scene.ron"),
+            r#"(materials: {
+                "wall": Lambertian(texture: Image("truncated.png")),
+                "sign": Lambertian(texture: Image("notes.xyz")),
+            })"#,
+        );
+        let library = MaterialLibrary::load(directory.join("scene.ron")).unwrap();
+        let mut report = LoadReport::default();
+        let materials = library
+            .build_all(&AssetResolver::new(vec![directory.clone()]), &mut report)
+            .unwrap();
+
+        let failed: Vec<(&str, Option<&str>)> = report
+            .texture_failures
+            .iter()
+            .map(|failure| (failure.source.as_str(), failure.material.as_deref()))
+            .collect();
+        let truncated = directory.join("truncated.png").display().to_string();
+        let notes = directory.join("notes.xyz").display().to_string();
+        assert_eq!(
+            failed,
+            [
+                (truncated.as_str(), Some("wall")),
+                (notes.as_str(), Some("sign"))
+            ]
+        );
+        let placeholder = ImageTexture::placeholder();
+        for name in ["wall", "sign"] {
+            let Material::Lambertian(Lambertian {
+                texture: TextureEnum::ImageTexture(texture),
+                ..
+            }) = &*materials[name]
+            else {
+                panic!("{} isn't an image texture", name);
+            };
+            assert!(texture.image.pixels == placeholder.image.pixels, "{}", name);
+        }
+
+        // The scene still renders, with the placeholder's magenta where the textures were
+        let world = World::build(vec![Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            materials["wall"].clone(),
+        )
+        .into()]);
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -4.0, 0.0))
+            .with_look_at(Vec3::zeros())
+            .with_resolution(8, 8)
+            .with_samples(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(3);
+        let image = camera.render_image(&world);
+        assert!(image
+            .pixels
+            .iter()
+            .all(|pixel| pixel.iter().all(|c| c.is_finite())));
+        assert!(image
+            .pixels
+            .iter()
+            .any(|pixel| pixel.x > 2.0 * pixel.y && pixel.z > 2.0 * pixel.y));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// One of every kind of material and texture, with images in `directory`
+    fn every_kind(directory: &Path) -> MaterialLibrary {
+        let image = |name: &str| TextureSpec::Image(directory.join("textures").join(name));
+        let mut library = MaterialLibrary::default();
+        library.set(
+            "brick",
+            MaterialSpec::Lambertian {
+                texture: TextureSpec::Checker {
+                    scale: 0.5,
+                    space: CheckerSpace::Uv,
+                    filtered: true,
+                    even: Box::new(TextureSpec::Solid(Vec3::new(0.6, 0.2, 0.1))),
+                    odd: Box::new(image("old mortar.png")),
+                },
+            },
+        );
+        library.set(
+            "trim",
+            MaterialSpec::Metal {
+                texture: TextureSpec::Solid(Vec3::repeat(0.9)),
+                fuzz: Some(0.25),
+            },
+        );
+        library.set(
+            "window",
+            MaterialSpec::Dielectric {
+                refractive_index: 1.5,
+                fuzz: None,
+                tint: Some(TextureSpec::Solid(Vec3::new(0.8, 0.9, 1.0))),
+            },
+        );
+        library.set(
+            "leaves",
+            MaterialSpec::AlphaMask {
+                base: "brick".to_string(),
+                coverage: image("leaf.png"),
+            },
+        );
+        library.set(
+            "fog",
+            MaterialSpec::Volumetric {
+                albedo: Vec3::repeat(0.8),
+                anisotropy: 0.3,
+            },
+        );
+        library.set(
+            "lamp",
+            MaterialSpec::DiffuseLight {
+                texture: TextureSpec::Solid(Vec3::new(1.0, 0.9, 0.7)),
+                intensity: 4.0,
+            },
+        );
+        library.set("ground", MaterialSpec::ShadowCatcher);
+        library
+    }
+
+    #[test]
+    fn saving_and_loading_gives_back_the_same_materials() {
+        let directory = scratch("round-trip");
+        let library = every_kind(&directory.join("project"));
+        let path = directory.join("project/materials.ron");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        library.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        // Images next to the library are written relative to it
+        assert!(
+            saved.contains(r#"Image("textures/old mortar.png")"#),
+            "{}",
+            saved
+        );
+        assert_eq!(MaterialLibrary::load(&path).unwrap(), library);
+
+        // Included from elsewhere, the paths still resolve against the library's own directory
+        write(
+            &directory.join("scenes/night.ron"),
+            r#"(include: ["../project/materials.ron"])"#,
+        );
+        for name in ["old mortar.png", "leaf.png"] {
+            write(&directory.join("project/textures").join(name), "");
+        }
+        let included = MaterialLibrary::load(directory.join("scenes/night.ron")).unwrap();
+        let files = |library: &MaterialLibrary| -> Vec<PathBuf> {
+            let images = library.images().into_iter();
+            images
+                .map(|(_, path)| fs::canonicalize(path).unwrap())
+                .collect()
+        };
+        assert_eq!(files(&included), files(&library));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn old_and_newer_libraries_still_load() {
+        // Written before checker filtering and light intensity were added
+        let version_1 = r#"(
+            version: 1,
+            materials: {
+                "floor": Lambertian(
+                    texture: Checker(scale: 2, even: Solid((0, 0, 0)), odd: Solid((1, 1, 1))),
+                ),
+                "lamp": DiffuseLight(texture: Solid((4, 4, 4))),
+            },
+        )"#;
+        let library = MaterialLibrary::parse(version_1, Path::new("")).unwrap();
+        assert!(library.warnings.is_empty());
+        assert_eq!(
+            library.get("floor"),
+            Some(&MaterialSpec::Lambertian {
+                texture: TextureSpec::Checker {
+                    scale: 2.0,
+                    space: CheckerSpace::World,
+                    filtered: false,
+                    even: Box::new(TextureSpec::Solid(Vec3::zeros())),
+                    odd: Box::new(TextureSpec::Solid(Vec3::repeat(1.0))),
+                }
+            })
+        );
+        assert_eq!(
+            library.get("lamp"),
+            Some(&MaterialSpec::DiffuseLight {
+                texture: TextureSpec::Solid(Vec3::repeat(4.0)),
+                intensity: 1.0
+            })
+        );
+
+        // Settings from a newer version are skipped with a warning
+        let newer = format!(
+            "(version: {}, materials: {{\"trim\": Metal(texture: Solid((1, 1, 1)), roughness: 0.3)}})",
+            LIBRARY_VERSION + 1
+        );
+        let library = MaterialLibrary::parse(&newer, Path::new("")).unwrap();
+        assert_eq!(
+            library.get("trim"),
+            Some(&MaterialSpec::Metal {
+                texture: TextureSpec::Solid(Vec3::repeat(1.0)),
+                fuzz: None
+            })
+        );
+        assert_eq!(
+            library.warnings,
+            [
+                "library: library is version 2 but only version 1 is understood, so newer \
+                 settings will be skipped",
+                "library: skipped unknown setting 'materials.trim.roughness'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use crate::{
+    camera::Float,
+    intersection::Intersection,
+    material::{Lambertian, Material, Scatter},
+    texture::SolidColor,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+/// Albedo of [`MaterialOverride::clay`], a middle grey
+pub const CLAY_ALBEDO: Float = 0.5;
+
+/// Shades every surface a camera sees with one material in place of its own, as the hits come
+/// back from the world, so the world itself is left alone and other cameras still see its
+/// materials. Lights keep theirs, so a clay render is still lit by them. Alpha masks have already
+/// cut their holes by the time a hit comes back, so they keep doing so.
+#[derive(Debug, Clone)]
+pub struct MaterialOverride {
+    pub material: Arc<Material>,
+}
+
+impl MaterialOverride {
+    pub fn new(material: Material) -> Self {
+        MaterialOverride {
+            material: Arc::new(material),
+        }
+    }
+
+    /// A matte grey, for looking at a scene's shapes and lighting without its materials
+    pub fn clay() -> Self {
+        let grey = SolidColor::new(Vec3::repeat(CLAY_ALBEDO));
+        MaterialOverride::new(Lambertian::new(grey.into()).into())
+    }
+
+    /// `hit` with the override's material, unless it hit a light
+    pub fn apply<'a>(&'a self, hit: Intersection<'a>) -> Intersection<'a> {
+        if hit.material.is_emissive() {
+            return hit;
+        }
+        Intersection {
+            material: &self.material,
+            ..hit
+        }
+    }
+}
@@ -0,0 +1,586 @@
+use crate::{
+    camera::Float,
+    hittable::Hit,
+    intersection::Intersection,
+    material::Material,
+    object::ObjectId,
+    rng::HitRng,
+    vec3::{Point3, Ray, RayExt, Vec2, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+};
+use rand::Rng;
+use std::{
+    fmt, fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Voxels per side of the blocks that empty space is skipped in
+const BLOCK_SIZE: usize = 8;
+
+/// Per-block density bounds over a [`VoxelGrid`], one level of a min/max mip. Blocks that are
+/// empty get skipped and blocks with constant density are crossed in one step.
+struct BlockBounds {
+    blocks: [usize; 3],
+    /// `(min, max)` density of each block, x varying fastest
+    bounds: Vec<(Float, Float)>,
+}
+
+/// A dense 3D array of densities filling an axis-aligned box, such as one frame of a smoke
+/// simulation. Densities are extinction coefficients per unit of world distance, and are
+/// interpolated between voxel centers.
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    min: Point3,
+    max: Point3,
+    /// One density per voxel, x varying fastest and then y
+    densities: Vec<f32>,
+    blocks: BlockBounds,
+}
+
+#[derive(Debug)]
+pub enum VoxelGridError {
+    Io(io::Error),
+    /// A header line that couldn't be parsed, with its 1-based line number
+    Malformed {
+        line: usize,
+        message: String,
+    },
+    Missing(&'static str),
+    /// The raw data doesn't have one `f32` for each voxel in the header's dimensions
+    WrongSize {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for VoxelGridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxelGridError::Io(err) => write!(f, "failed to read voxel grid: {}", err),
+            VoxelGridError::Malformed { line, message } => {
+                write!(f, "line {}: {}", line, message)
+            }
+            VoxelGridError::Missing(key) => write!(f, "voxel grid header has no '{}'", key),
+            VoxelGridError::WrongSize { expected, found } => write!(
+                f,
+                "voxel grid data should be {} bytes but is {} bytes",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VoxelGridError {}
+
+impl From<io::Error> for VoxelGridError {
+    fn from(err: io::Error) -> Self {
+        VoxelGridError::Io(err)
+    }
+}
+
+impl VoxelGrid {
+    /// Returns a grid with `dims` voxels spanning the box from `min` to `max`.
+    /// Panics if any dimension is zero or `densities` doesn't have one value per voxel.
+    pub fn new(dims: [usize; 3], min: Point3, max: Point3, densities: Vec<f32>) -> Self {
+        assert!(dims.iter().all(|&d| d > 0), "voxel grid can't be empty");
+        assert_eq!(
+            densities.len(),
+            dims.iter().product::<usize>(),
+            "voxel grid needs one density per voxel"
+        );
+        let mut grid = VoxelGrid {
+            dims,
+            min: min.inf(&max),
+            max: min.sup(&max),
+            densities,
+            blocks: BlockBounds {
+                blocks: [0; 3],
+                bounds: Vec::new(),
+            },
+        };
+        grid.blocks = grid.block_bounds();
+        grid
+    }
+
+    /// Returns a grid whose voxels are set to `density` evaluated at their centers
+    pub fn from_fn(
+        dims: [usize; 3],
+        min: Point3,
+        max: Point3,
+        density: impl Fn(Point3) -> Float,
+    ) -> Self {
+        let voxel_size = (max - min).component_div(&Vec3::from_fn(|i, _| dims[i] as Float));
+        let mut densities = Vec::with_capacity(dims.iter().product());
+        for z in 0..dims[2] {
+            for y in 0..dims[1] {
+                for x in 0..dims[0] {
+                    let index = Vec3::new(x as Float, y as Float, z as Float).add_scalar(0.5);
+                    densities.push(density(min + index.component_mul(&voxel_size)) as f32);
+                }
+            }
+        }
+        VoxelGrid::new(dims, min, max, densities)
+    }
+
+    /// Loads a grid from a small text header with `dims x y z`, `bbox min_x min_y min_z max_x
+    /// max_y max_z`, and `data <path>` lines. The data file holds the densities as raw
+    /// little-endian `f32`s with x varying fastest, and its path is relative to the header.
+    pub fn load(header_path: impl AsRef<Path>) -> Result<Self, VoxelGridError> {
+        let header_path = header_path.as_ref();
+        let mut dims = None;
+        let mut bbox = None;
+        let mut data_path = None;
+        for (i, line) in fs::read_to_string(header_path)?.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let malformed = |message: String| VoxelGridError::Malformed {
+                line: line_number,
+                message,
+            };
+            match key {
+                "dims" => {
+                    let values: Vec<usize> = rest
+                        .split_whitespace()
+                        .map(|word| word.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| malformed(format!("invalid dimensions '{}'", rest)))?;
+                    if values.len() != 3 || values.contains(&0) {
+                        return Err(malformed("dims needs 3 values above zero".to_string()));
+                    }
+                    dims = Some([values[0], values[1], values[2]]);
+                }
+                "bbox" => {
+                    let values: Vec<Float> = rest
+                        .split_whitespace()
+                        .map(|word| word.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| malformed(format!("invalid bounding box '{}'", rest)))?;
+                    if values.len() != 6 {
+                        return Err(malformed("bbox needs 6 values".to_string()));
+                    }
+                    bbox = Some((
+                        Point3::new(values[0], values[1], values[2]),
+                        Point3::new(values[3], values[4], values[5]),
+                    ));
+                }
+                "data" => data_path = Some(PathBuf::from(rest.trim())),
+                _ => return Err(malformed(format!("unknown setting '{}'", key))),
+            }
+        }
+
+        let dims = dims.ok_or(VoxelGridError::Missing("dims"))?;
+        let (min, max) = bbox.ok_or(VoxelGridError::Missing("bbox"))?;
+        let data_path = data_path.ok_or(VoxelGridError::Missing("data"))?;
+        let data_path = header_path
+            .parent()
+            .map_or(data_path.clone(), |dir| dir.join(&data_path));
+
+        let bytes = fs::read(data_path)?;
+        let expected = dims.iter().product::<usize>() * size_of::<f32>();
+        if bytes.len() != expected {
+            return Err(VoxelGridError::WrongSize {
+                expected,
+                found: bytes.len(),
+            });
+        }
+        let densities = bytes
+            .chunks_exact(size_of::<f32>())
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Ok(VoxelGrid::new(dims, min, max, densities))
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    /// Size of one voxel along each axis in world units
+    pub fn voxel_size(&self) -> Vec3 {
+        (self.max - self.min).component_div(&Vec3::from_fn(|i, _| self.dims[i] as Float))
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> Float {
+        self.densities[x + self.dims[0] * (y + self.dims[1] * z)].into()
+    }
+
+    /// Returns the density at `point`, trilinearly interpolated between voxel centers.
+    /// Points outside the grid have no density.
+    pub fn density(&self, point: &Point3) -> Float {
+        if (0..3).any(|i| point[i] < self.min[i] || point[i] > self.max[i]) {
+            return 0.0;
+        }
+        // Position in voxel units, relative to the center of the first voxel
+        let local = (point - self.min)
+            .component_div(&self.voxel_size())
+            .add_scalar(-0.5);
+        let mut corner = [0; 3];
+        let mut fraction = Vec3::zeros();
+        for i in 0..3 {
+            let last = self.dims[i] - 1;
+            let clamped = local[i].clamp(0.0, last as Float);
+            corner[i] = (clamped.floor() as usize).min(last.saturating_sub(1));
+            fraction[i] = clamped - corner[i] as Float;
+        }
+        let mut density = 0.0;
+        for offset in 0..8 {
+            let mut weight = 1.0;
+            let mut index = corner;
+            for i in 0..3 {
+                let upper = (offset >> i) & 1 == 1;
+                // Grids one voxel thick have no upper neighbor along that axis
+                if upper && self.dims[i] > 1 {
+                    index[i] += 1;
+                }
+                weight *= if upper {
+                    fraction[i]
+                } else {
+                    1.0 - fraction[i]
+                };
+            }
+            if weight > 0.0 {
+                density += weight * self.voxel(index[0], index[1], index[2]);
+            }
+        }
+        density
+    }
+
+    /// Computes the density bounds of each block. Interpolation reaches one voxel past a block's
+    /// edges, so each block's bounds include that border.
+    fn block_bounds(&self) -> BlockBounds {
+        let blocks = self.dims.map(|d| d.div_ceil(BLOCK_SIZE));
+        let mut bounds = Vec::with_capacity(blocks.iter().product());
+        for bz in 0..blocks[2] {
+            for by in 0..blocks[1] {
+                for bx in 0..blocks[0] {
+                    let block = [bx, by, bz];
+                    let range = |i: usize| {
+                        let start = (block[i] * BLOCK_SIZE).saturating_sub(1);
+                        let end = ((block[i] + 1) * BLOCK_SIZE + 1).min(self.dims[i]);
+                        start..end
+                    };
+                    let (mut min, mut max) = (Float::INFINITY, Float::NEG_INFINITY);
+                    for z in range(2) {
+                        for y in range(1) {
+                            for x in range(0) {
+                                let density = self.voxel(x, y, z);
+                                min = min.min(density);
+                                max = max.max(density);
+                            }
+                        }
+                    }
+                    bounds.push((min, max));
+                }
+            }
+        }
+        BlockBounds { blocks, bounds }
+    }
+
+    /// Returns the world-space box of the block containing `point` and its density bounds
+    fn block_at(&self, point: &Point3) -> (Point3, Point3, (Float, Float)) {
+        let block_size = self.voxel_size() * BLOCK_SIZE as Float;
+        let index = Vec3::from_fn(|i, _| {
+            let block = ((point[i] - self.min[i]) / block_size[i]).floor().max(0.0);
+            block.min((self.blocks.blocks[i] - 1) as Float)
+        });
+        let block_min = self.min + index.component_mul(&block_size);
+        let block_max = (block_min + block_size).inf(&self.max);
+        let [bx, by, bz] = [0, 1, 2].map(|i| index[i] as usize);
+        let blocks = self.blocks.blocks;
+        let bounds = self.blocks.bounds[bx + blocks[0] * (by + blocks[1] * bz)];
+        (block_min, block_max, bounds)
+    }
+}
+
+/// Returns the distance along `ray` to where it leaves the box from `min` to `max`
+fn box_exit(ray: &Ray, min: &Point3, max: &Point3) -> Float {
+    (0..3)
+        .filter(|&i| ray.direction[i] != 0.0)
+        .map(|i| {
+            let t0 = (min[i] - ray.origin[i]) / ray.direction[i];
+            let t1 = (max[i] - ray.origin[i]) / ray.direction[i];
+            t0.max(t1)
+        })
+        .fold(Float::INFINITY, Float::min)
+}
+
+/// Returns the `(entry, exit)` distances of `ray` through the box from `min` to `max`
+fn box_interval(ray: &Ray, min: &Point3, max: &Point3) -> Option<(Float, Float)> {
+    let (mut entry, mut exit) = (Float::NEG_INFINITY, Float::INFINITY);
+    for i in 0..3 {
+        if ray.direction[i] == 0.0 {
+            if ray.origin[i] < min[i] || ray.origin[i] > max[i] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min[i] - ray.origin[i]) / ray.direction[i];
+        let t1 = (max[i] - ray.origin[i]) / ray.direction[i];
+        entry = entry.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+    }
+    (entry <= exit).then_some((entry, exit))
+}
+
+/// Smoke, fog, or anything else whose density varies through space, defined by a [`VoxelGrid`].
+/// Rays scatter inside it after passing through a random amount of density, which is found by
+/// marching along the ray in fixed steps. The `BVH` only sees the grid's bounding box.
+pub struct HeterogeneousMedium {
+    grid: Arc<VoxelGrid>,
+    /// Distance between density samples while marching. Smaller steps follow the density more
+    /// closely at the cost of more lookups.
+    pub step_size: Float,
+    /// Decides which way rays scatter, usually a [`crate::material::Volumetric`]
+    pub material: Arc<Material>,
+    node_index: usize,
+    /// The scene object this medium belongs to
+    pub object: ObjectId,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(grid: Arc<VoxelGrid>, step_size: Float, material: Arc<Material>) -> Self {
+        HeterogeneousMedium {
+            grid,
+            step_size,
+            material,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn grid(&self) -> &Arc<VoxelGrid> {
+        &self.grid
+    }
+
+    /// Marches `ray` from `start` to `end` and returns where the optical depth reaches `target`.
+    /// Distances along the ray are in units of its direction's length, which isn't always one,
+    /// so densities and the step size are converted with it to stay per unit of world distance.
+    fn march(&self, ray: &Ray, start: Float, end: Float, target: Float) -> Option<Float> {
+        let grid = &self.grid;
+        let speed = ray.direction.norm();
+        let step_size = self.step_size / speed;
+        // Nudges past block boundaries so the next lookup lands in the next block
+        let nudge = grid.voxel_size().min() * 1e-6 / speed;
+        let mut optical_depth = 0.0;
+        let mut t = start;
+        while t < end {
+            let (block_min, block_max, (min, max)) = grid.block_at(&ray.at(t + nudge));
+            let block_end = box_exit(ray, &block_min, &block_max)
+                .min(end)
+                .max(t + nudge);
+            if max <= 0.0 {
+                t = block_end;
+                continue;
+            }
+            if min == max {
+                // Constant density, so the optical depth across the block is exact
+                let density = max * speed;
+                let depth = density * (block_end - t);
+                if optical_depth + depth >= target {
+                    return Some(t + (target - optical_depth) / density);
+                }
+                optical_depth += depth;
+                t = block_end;
+                continue;
+            }
+            while t < block_end {
+                let step = step_size.min(block_end - t);
+                let density = grid.density(&ray.at(t + step / 2.0)) * speed;
+                let depth = density * step;
+                if optical_depth + depth >= target && density > 0.0 {
+                    return Some(t + (target - optical_depth) / density);
+                }
+                optical_depth += depth;
+                t += step;
+            }
+        }
+        None
+    }
+}
+
+impl Hit for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        let (entry, exit) = box_interval(ray, &self.grid.min, &self.grid.max)?;
+        let (start, end) = (entry.max(range.start), exit.min(range.end));
+        if start >= end {
+            return None;
+        }
+        // Light makes it through an optical depth of `target` with probability e^-target
+        let target = -(1.0 - HitRng.gen::<Float>()).ln();
+        let t = self.march(ray, start, end, target)?;
+        // Media have no surface, so the normal just faces back along the ray
+        Some(
+            Intersection::new(
+                ray.at(t),
+                -ray.direction,
+                t,
+                &self.material,
+                true,
+                Vec2::zeros(),
+            )
+            .with_object(self.object),
+        )
+    }
+}
+
+impl Bounded<Float, 3> for HeterogeneousMedium {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        Aabb::with_bounds(self.grid.min.into(), self.grid.max.into())
+    }
+}
+
+impl BHShape<Float, 3> for HeterogeneousMedium {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Volumetric;
+
+    /// A ball of density falling smoothly from `peak` at its center to nothing at a radius of
+    /// one, in a grid of `resolution` voxels per side, marched in steps of `step_size`
+    fn smoke_ball(resolution: usize, peak: Float, step_size: Float) -> HeterogeneousMedium {
+        let grid = VoxelGrid::from_fn(
+            [resolution; 3],
+            Vec3::repeat(-1.25),
+            Vec3::repeat(1.25),
+            |point| {
+                let falloff = (1.0 - point.norm_squared()).max(0.0);
+                peak * falloff * falloff
+            },
+        );
+        let material = Volumetric::new(Vec3::repeat(0.9), 0.0).into();
+        HeterogeneousMedium::new(Arc::new(grid), step_size, Arc::new(material))
+    }
+
+    /// A ray from `origin` along `direction` exactly as given, without normalizing it
+    fn unnormalized(origin: Point3, direction: Vec3) -> Ray {
+        Ray {
+            origin: origin.into(),
+            direction,
+            inv_direction: direction.map(|c| 1.0 / c),
+        }
+    }
+
+    /// Where the optical depth along `ray` reaches `target` inside the grid, like
+    /// [`HeterogeneousMedium::hit`] without the random target
+    fn march_through(medium: &HeterogeneousMedium, ray: &Ray, target: Float) -> Option<Float> {
+        let (entry, exit) = box_interval(ray, &medium.grid.min, &medium.grid.max)?;
+        medium.march(ray, entry.max(0.0), exit, target)
+    }
+
+    /// Light getting through the ball along rays straight across it on a `size` by `size` grid,
+    /// estimated from the fraction of evenly spread optical depths a march doesn't reach
+    fn transmittance_image(medium: &HeterogeneousMedium, size: usize) -> Vec<Float> {
+        const DEPTHS: usize = 32;
+        let targets: Vec<Float> = (0..DEPTHS)
+            .map(|i| -(1.0 - (i as Float + 0.5) / DEPTHS as Float).ln())
+            .collect();
+        let mut image = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let across = |i: usize| (i as Float + 0.5) / size as Float * 2.5 - 1.25;
+                let ray = Ray::new(Vec3::new(across(x), across(y), -3.0).into(), Vec3::z());
+                let passed = targets
+                    .iter()
+                    .filter(|&&target| march_through(medium, &ray, target).is_none())
+                    .count();
+                image.push(passed as Float / DEPTHS as Float);
+            }
+        }
+        image
+    }
+
+    fn mean_difference(a: &[Float], b: &[Float]) -> Float {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<Float>() / a.len() as Float
+    }
+
+    #[test]
+    fn density_is_per_unit_of_world_distance() {
+        let medium = smoke_ball(32, 3.0, 0.02);
+        let origin = Vec3::new(0.1, -0.2, -3.0);
+        let unit = Ray::new(origin.into(), Vec3::z());
+        for length in [0.25, 3.0, 40.0] {
+            let long = unnormalized(origin, Vec3::z() * length);
+            for target in [0.1, 0.5, 1.5] {
+                let t_unit = march_through(&medium, &unit, target).unwrap();
+                let t_long = march_through(&medium, &long, target).unwrap();
+                // The same place in the world, however long the direction is
+                assert!(
+                    (t_long * length - t_unit).abs() < 1e-6,
+                    "length {}: {} vs {}",
+                    length,
+                    t_long * length,
+                    t_unit
+                );
+            }
+        }
+        // Past empty blocks and constant ones alike
+        let empty_then_constant = HeterogeneousMedium::new(
+            Arc::new(VoxelGrid::from_fn(
+                [16, 16, 16],
+                Vec3::zeros(),
+                Vec3::repeat(2.0),
+                |point| if point.z > 1.0 { 2.0 } else { 0.0 },
+            )),
+            0.05,
+            Arc::new(Volumetric::new(Vec3::repeat(0.9), 0.0).into()),
+        );
+        let origin = Vec3::new(0.5, 0.5, -1.0);
+        let unit = Ray::new(origin.into(), Vec3::z());
+        let long = unnormalized(origin, Vec3::z() * 5.0);
+        let t_unit = march_through(&empty_then_constant, &unit, 1.0).unwrap();
+        let t_long = march_through(&empty_then_constant, &long, 1.0).unwrap();
+        assert!((t_long * 5.0 - t_unit).abs() < 1e-6);
+        // Entering at z = 1 past the interpolated edge, it takes about half a unit at density 2
+        assert!((t_unit - 2.5).abs() < 0.1, "{}", t_unit);
+    }
+
+    #[test]
+    fn halving_the_step_barely_changes_the_image() {
+        let size = 12;
+        let voxel = 2.5 / 24.0;
+        let images: Vec<Vec<Float>> = [2.0, 1.0, 0.5, 0.125]
+            .iter()
+            .map(|steps| transmittance_image(&smoke_ball(24, 4.0, voxel * steps), size))
+            .collect();
+        let reference = &images[3];
+        // The ball shows, with a soft edge rather than a hard one
+        assert!(reference.iter().any(|&t| t < 0.2));
+        assert!(reference.iter().any(|&t| t > 0.2 && t < 0.8));
+        for halved in images[..3].windows(2) {
+            let change = mean_difference(&halved[0], &halved[1]);
+            assert!(
+                change < 0.01,
+                "halving the step changed the image by {}",
+                change
+            );
+        }
+        // And each halving gets closer to the finely stepped image
+        let errors: Vec<Float> = images[..3]
+            .iter()
+            .map(|image| mean_difference(image, reference))
+            .collect();
+        assert!(errors[2] <= errors[0], "{:?}", errors);
+    }
+}
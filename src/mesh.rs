@@ -0,0 +1,523 @@
+use crate::{
+    camera::Float,
+    hittable::{
+        double_sided_by_default, intersect_triangle, normal_matrix, triangle_tolerances, Hit,
+        RejectReport, ShapeError, Triangle, TriangleSurface, DEFAULT_TRIANGLE_UVS,
+    },
+    intersection::Intersection,
+    material::Material,
+    numeric::DEGENERATE_TRIANGLE_RATIO,
+    object::ObjectId,
+    vec3::{Point3, Ray, Vec2, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+    bvh::Bvh,
+};
+use nalgebra::Matrix4;
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+/// A triangle of a [`Mesh`], as the indices of its corners among the mesh's vertices
+struct Face {
+    vertices: [u32; 3],
+    bounds: Aabb<Float, 3>,
+    node_index: usize,
+}
+
+impl Bounded<Float, 3> for Face {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        self.bounds
+    }
+}
+
+impl BHShape<Float, 3> for Face {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// Bounds of the triangle with the corners `vertices` among `positions`
+fn face_bounds(positions: &[Point3], vertices: &[u32; 3]) -> Aabb<Float, 3> {
+    let [a, b, c] = vertices.map(|i| positions[i as usize]);
+    Aabb::with_bounds(a.inf(&b).inf(&c).into(), a.sup(&b).sup(&c).into())
+}
+
+/// Normal of the triangle with `corners`, found the way `Triangle::new` finds it so faces shade
+/// exactly like triangles. It's NaN if the corners are all on one line.
+fn face_normal([a, b, c]: [Point3; 3]) -> Vec3 {
+    (b - a).normalize().cross(&(c - a).normalize()).normalize()
+}
+
+/// Triangles that share their vertices and one material, with a `BVH` of their own like a
+/// [`crate::instance::Prototype`], so the world's `BVH` only sees the mesh. Each triangle is
+/// three indices instead of a whole [`Triangle`], which is what lets scenes of millions of
+/// triangles fit in memory. Faces hit and shade exactly like the triangles they'd otherwise be.
+pub struct Mesh {
+    positions: Vec<Point3>,
+    /// Indexed like the positions, or empty to give every face the texture coordinates
+    /// [`Triangle::new`] gives a triangle
+    uvs: Vec<Vec2>,
+    /// Unit normals at the vertices, interpolated across faces to shade them smoothly. `None`
+    /// shades every face flat. Faces shaded flat among smooth ones have NaN at their vertices,
+    /// which interpolates to NaN and falls back to the face normal the way it does on triangles.
+    normals: Option<Vec<Vec3>>,
+    faces: Vec<Face>,
+    bvh: Bvh<Float, 3>,
+    bounds: Aabb<Float, 3>,
+    pub material: Arc<Material>,
+    /// See [`Triangle::with_epsilon`]
+    epsilon: Float,
+    /// See [`Triangle::with_double_sided`]
+    double_sided: bool,
+    node_index: usize,
+    /// The scene object this mesh is part of
+    pub object: ObjectId,
+}
+
+impl Mesh {
+    /// Makes a mesh of the triangles `indices` picks out of `positions`, which are wound
+    /// counterclockwise seen from their front. `uvs` are indexed like the positions.
+    pub fn new(
+        positions: Vec<Point3>,
+        uvs: Vec<Vec2>,
+        indices: Vec<[u32; 3]>,
+        material: Arc<Material>,
+    ) -> Self {
+        let faces = indices
+            .into_iter()
+            .map(|vertices| Face {
+                bounds: face_bounds(&positions, &vertices),
+                vertices,
+                node_index: 0,
+            })
+            .collect();
+        Self::build(positions, uvs, None, faces, material)
+    }
+
+    /// Like [`Mesh::new`] with `indices` three to a face and `uvs` possibly empty, but leaves
+    /// out the faces [`Triangle::try_new`] would reject, counting them in `rejects`. Loaders
+    /// build meshes straight from the buffers in the file this way, without ever making a
+    /// [`Triangle`] of each face.
+    pub fn new_checked(
+        positions: Vec<Point3>,
+        uvs: Vec<Vec2>,
+        indices: &[u32],
+        material: Arc<Material>,
+        rejects: &mut RejectReport,
+    ) -> Self {
+        let faces = rejects.collect(
+            indices
+                .par_chunks_exact(3)
+                .map(|chunk| {
+                    let vertices = [chunk[0], chunk[1], chunk[2]];
+                    let corners = vertices.map(|i| positions[i as usize]);
+                    if !corners
+                        .iter()
+                        .flat_map(|corner| corner.iter())
+                        .all(|x| x.is_finite())
+                    {
+                        return Err(ShapeError::NotFinite("triangle corner"));
+                    }
+                    if !face_normal(corners).iter().all(|x| x.is_finite()) {
+                        return Err(ShapeError::Degenerate);
+                    }
+                    Ok(Face {
+                        bounds: face_bounds(&positions, &vertices),
+                        vertices,
+                        node_index: 0,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        );
+        Self::build(positions, uvs, None, faces, material)
+    }
+
+    fn build(
+        positions: Vec<Point3>,
+        uvs: Vec<Vec2>,
+        normals: Option<Vec<Vec3>>,
+        mut faces: Vec<Face>,
+        material: Arc<Material>,
+    ) -> Self {
+        let bvh = Bvh::build(&mut faces);
+        let bounds = faces
+            .iter()
+            .fold(Aabb::empty(), |bounds, face| bounds.join(&face.bounds));
+        Mesh {
+            positions,
+            uvs,
+            normals,
+            faces,
+            bvh,
+            bounds,
+            double_sided: double_sided_by_default(&material),
+            material,
+            epsilon: DEGENERATE_TRIANGLE_RATIO,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    /// Stores `triangles` as meshes, one for each material, object, epsilon and sidedness among
+    /// them in the order they first come up. Corners at exactly the same place with the same texture
+    /// coordinates and normal become one vertex.
+    pub fn from_triangles(triangles: &[Triangle]) -> Vec<Mesh> {
+        let mut groups: Vec<Vec<&Triangle>> = Vec::new();
+        let mut group_ids: HashMap<(*const Material, ObjectId, u64, bool), usize> = HashMap::new();
+        for triangle in triangles {
+            let key = (
+                Arc::as_ptr(&triangle.material),
+                triangle.object,
+                triangle.epsilon().to_bits(),
+                triangle.is_double_sided(),
+            );
+            let group = *group_ids.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(triangle);
+        }
+        groups.iter().map(|group| Self::weld(group)).collect()
+    }
+
+    /// Makes one mesh of `triangles`, which all have the same material, object, epsilon and
+    /// sidedness, and of which there's at least one
+    fn weld(triangles: &[&Triangle]) -> Self {
+        let smooth = triangles
+            .iter()
+            .any(|triangle| triangle.vertex_normals().is_some());
+        let mut vertex_ids: HashMap<[u64; 8], u32> = HashMap::new();
+        let (mut positions, mut uvs, mut normals) = (Vec::new(), Vec::new(), Vec::new());
+        let mut faces = Vec::with_capacity(triangles.len());
+        for triangle in triangles {
+            let corner_normals = triangle
+                .vertex_normals()
+                .copied()
+                .unwrap_or([Vec3::repeat(Float::NAN); 3]);
+            let corners = [
+                (triangle.a, triangle.uv_a),
+                (triangle.b, triangle.uv_b),
+                (triangle.c, triangle.uv_c),
+            ];
+            let vertices = [0, 1, 2].map(|k| {
+                let (position, uv) = corners[k];
+                let normal = corner_normals[k];
+                let mut key = [0; 8];
+                for (bits, value) in key.iter_mut().zip(
+                    position
+                        .iter()
+                        .chain(uv.iter())
+                        .chain(normal.iter().filter(|_| smooth)),
+                ) {
+                    *bits = value.to_bits();
+                }
+                *vertex_ids.entry(key).or_insert_with(|| {
+                    positions.push(position);
+                    uvs.push(uv);
+                    normals.push(normal);
+                    u32::try_from(positions.len() - 1).expect("too many vertices for one mesh")
+                })
+            });
+            faces.push(Face {
+                vertices,
+                bounds: triangle.aabb(),
+                node_index: 0,
+            });
+        }
+        let first = triangles[0];
+        Self::build(
+            positions,
+            uvs,
+            smooth.then_some(normals),
+            faces,
+            first.material.clone(),
+        )
+        .with_object(first.object)
+        .with_epsilon(first.epsilon())
+        .with_double_sided(first.is_double_sided())
+    }
+
+    /// Shades the mesh smoothly by interpolating `normals`, indexed like the positions, across
+    /// each face. Faces with a normal that can't be normalized stay flat.
+    pub fn with_vertex_normals(mut self, normals: Vec<Vec3>) -> Self {
+        self.normals = Some(normals.iter().map(|normal| normal.normalize()).collect());
+        self
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    /// See [`Triangle::with_epsilon`]
+    pub fn with_epsilon(mut self, epsilon: Float) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// See [`Triangle::with_double_sided`]
+    pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn positions(&self) -> &[Point3] {
+        &self.positions
+    }
+
+    /// Every face's corners, without building whole triangles
+    pub fn face_corners(&self) -> impl Iterator<Item = [Point3; 3]> + '_ {
+        self.faces
+            .iter()
+            .map(|face| face.vertices.map(|i| self.positions[i as usize]))
+    }
+
+    /// Moves the mesh by `matrix` like [`Triangle::transform`] moves each of its triangles
+    pub fn transform(&self, matrix: &Matrix4<Float>) -> Self {
+        // `Point3` is a vector, so it has to be made a point for the translation to apply
+        let positions: Vec<Point3> = self
+            .positions
+            .iter()
+            .map(|point| matrix.transform_point(&(*point).into()).coords)
+            .collect();
+        let normals = self.normals.as_ref().map(|normals| {
+            let normal_matrix = normal_matrix(matrix);
+            normals
+                .iter()
+                .map(|normal| (normal_matrix * normal).normalize())
+                .collect()
+        });
+        // Mirroring turns every face over, so they're wound the other way to keep their fronts
+        let mirrored = matrix.fixed_view::<3, 3>(0, 0).determinant() < 0.0;
+        let faces = self
+            .faces
+            .iter()
+            .map(|face| {
+                let [a, b, c] = face.vertices;
+                let vertices = if mirrored { [a, c, b] } else { [a, b, c] };
+                Face {
+                    bounds: face_bounds(&positions, &vertices),
+                    vertices,
+                    node_index: 0,
+                }
+            })
+            .collect();
+        Self::build(
+            positions,
+            self.uvs.clone(),
+            normals,
+            faces,
+            self.material.clone(),
+        )
+        .with_object(self.object)
+        .with_epsilon(self.epsilon)
+        .with_double_sided(self.double_sided)
+    }
+
+    /// Texture coordinates at `face`'s corners
+    fn face_uvs(&self, face: &Face) -> [Vec2; 3] {
+        if self.uvs.is_empty() {
+            DEFAULT_TRIANGLE_UVS
+        } else {
+            face.vertices.map(|i| self.uvs[i as usize])
+        }
+    }
+
+    /// What shading a hit on `face` takes
+    fn surface(&self, face: &Face) -> TriangleSurface<'_> {
+        let corners = face.vertices.map(|i| self.positions[i as usize]);
+        TriangleSurface {
+            corners,
+            uvs: self.face_uvs(face),
+            normal: face_normal(corners),
+            vertex_normals: self
+                .normals
+                .as_ref()
+                .map(|normals| face.vertices.map(|i| normals[i as usize])),
+            material: &self.material,
+            object: self.object,
+        }
+    }
+
+    /// Every face as a triangle of its own, e.g. for the GPU or to sample an emissive mesh
+    pub fn triangles(&self) -> impl Iterator<Item = Triangle> + '_ {
+        self.faces.iter().map(|face| {
+            let [a, b, c] = face.vertices.map(|i| self.positions[i as usize]);
+            let [uv_a, uv_b, uv_c] = self.face_uvs(face);
+            let triangle = Triangle::new_with_uv(a, b, c, uv_a, uv_b, uv_c, self.material.clone())
+                .with_object(self.object)
+                .with_epsilon(self.epsilon)
+                .with_double_sided(self.double_sided);
+            match &self.normals {
+                Some(normals) => {
+                    triangle.with_vertex_normals(face.vertices.map(|i| normals[i as usize]))
+                }
+                None => triangle,
+            }
+        })
+    }
+}
+
+impl Hit for Mesh {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        let mut nearest_hit_dist = range.end;
+        let mut nearest_hit = None;
+        for face in self.bvh.nearest_traverse_iterator(ray, &self.faces) {
+            let [a, b, c] = face.vertices.map(|i| &self.positions[i as usize]);
+            let (min_det_squared, min_distance) = triangle_tolerances(*a, *b, *c, self.epsilon);
+            if let Some((dist, u, v)) = intersect_triangle(
+                ray,
+                &(range.start..nearest_hit_dist),
+                [a, b, c],
+                min_det_squared,
+                min_distance,
+                self.double_sided,
+            ) {
+                nearest_hit_dist = dist;
+                nearest_hit = Some((face, dist, u, v));
+            }
+        }
+        // Only the nearest face is shaded, since that takes a few normalizations
+        let (face, dist, u, v) = nearest_hit?;
+        Some(self.surface(face).intersection(ray, dist, u, v))
+    }
+}
+
+impl Bounded<Float, 3> for Mesh {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        self.bounds
+    }
+}
+
+impl BHShape<Float, 3> for Mesh {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{Shape, Sphere, World},
+        material::Lambertian,
+    };
+
+    /// A square pyramid without a floor, its four sides sharing the apex and their base corners
+    fn pyramid(material: Arc<Material>) -> Vec<Triangle> {
+        let apex = Point3::new(0.0, 0.0, 1.5);
+        let base = [
+            Point3::new(-1.0, -1.0, 0.0),
+            Point3::new(1.0, -1.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(-1.0, 1.0, 0.0),
+        ];
+        (0..4)
+            .map(|i| Triangle::new(base[i], base[(i + 1) % 4], apex, material.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn corners_in_the_same_place_are_welded_into_one_vertex() {
+        let gray: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let triangles = pyramid(gray);
+        let meshes = Mesh::from_triangles(&triangles);
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0];
+        assert_eq!(mesh.triangle_count(), 4);
+        // Each triangle has the default uvs, so only corners with the same uv are the same
+        // vertex: the apex is always at (0.5, 1), and each base corner is the second corner of
+        // one side and the first of the next
+        assert_eq!(mesh.positions().len(), 9);
+        let corners: Vec<[Point3; 3]> = mesh.face_corners().collect();
+        let expected: Vec<[Point3; 3]> = triangles.iter().map(|t| [t.a, t.b, t.c]).collect();
+        assert_eq!(corners, expected);
+    }
+
+    #[test]
+    fn faces_are_indices_into_the_vertices() {
+        let gray: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let positions = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(2.0, 2.0, 0.0),
+            Point3::new(Float::NAN, 0.0, 0.0),
+        ];
+        // A square of two faces, then one with its corners on a line and one with a NaN corner
+        let indices = [0, 1, 2, 0, 2, 3, 0, 2, 4, 0, 1, 5];
+        let mut rejects = RejectReport::default();
+        let mesh = Mesh::new_checked(positions, Vec::new(), &indices, gray, &mut rejects);
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(rejects.degenerate, 1);
+        assert_eq!(rejects.not_finite, 1);
+        let corners: Vec<[Point3; 3]> = mesh.face_corners().collect();
+        assert_eq!(corners[1][2], Point3::new(0.0, 1.0, 0.0));
+        // Without uvs each face gets a triangle's default ones
+        for triangle in mesh.triangles() {
+            assert_eq!(
+                [triangle.uv_a, triangle.uv_b, triangle.uv_c],
+                DEFAULT_TRIANGLE_UVS
+            );
+        }
+        let hit = mesh
+            .hit(
+                &Ray::new(Point3::new(0.25, 0.75, 1.0).into(), -Vec3::z()),
+                &(0.0..Float::MAX),
+            )
+            .expect("the ray goes through the second face");
+        assert!((hit.t - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn scenes_render_the_same_with_triangles_as_a_mesh() {
+        let gray: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.6, 0.5, 0.4).into());
+        let ground_material: Arc<Material> =
+            Arc::new(Lambertian::new_rgb_solid(0.3, 0.3, 0.3).into());
+        let ground = || -> Shape {
+            Sphere::new(
+                Point3::new(0.0, 0.0, -100.0),
+                100.0,
+                ground_material.clone(),
+            )
+            .into()
+        };
+        let mut separate: Vec<Shape> = pyramid(gray.clone()).into_iter().map(Shape::from).collect();
+        separate.push(ground());
+        let mut meshed: Vec<Shape> = Mesh::from_triangles(&pyramid(gray))
+            .into_iter()
+            .map(Shape::from)
+            .collect();
+        meshed.push(ground());
+
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(2.0, -5.0, 3.0))
+            .with_look_at(Vec3::new(0.0, 0.0, 0.5))
+            .with_vertical_fov(40.0)
+            .with_resolution(16, 12)
+            .with_samples(4)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(3);
+        let separate = camera.render_image(&World::build(separate));
+        let meshed = camera.render_image(&World::build(meshed));
+        assert!(separate.pixels == meshed.pixels);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{
+    camera::Float,
+    hittable::{position_key, Hit, Triangle},
+    material::{Dielectric, Material},
+    vec3::Ray,
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+    bvh::Bvh,
+};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Triangles tested for crossing the rest of the mesh, spread evenly over it. Every triangle is
+/// tested in meshes with fewer.
+pub const SELF_INTERSECTION_SAMPLES: usize = 1024;
+
+/// Fraction of an edge at either end that doesn't count as crossing another triangle, so
+/// triangles meeting at a corner aren't mistaken for ones passing through each other
+const EDGE_MARGIN: Float = 1e-6;
+
+/// What [`analyze_mesh`] found wrong with a mesh's topology. Glass needs a closed mesh whose
+/// surfaces don't cross, since a ray only knows it's inside from which side of a triangle it hit:
+/// through an open edge or a self-intersection it ends up refracting as if it were entering
+/// glass it's already in, or leaving glass it never entered.
+///
+/// Rays don't carry a stack of the media they're in, so there's no stack depth to cap and no
+/// unmatched enters and exits to count in the render stats. Those limits are left for when
+/// nested media are added. Until then, shading flagged glass as thin panes is the whole
+/// mitigation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshAnalysis {
+    pub triangles: usize,
+    /// Edges used by only one triangle, along holes and the boundaries of open surfaces
+    pub open_edges: usize,
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: usize,
+    /// Triangles tested for self-intersections
+    pub sampled_triangles: usize,
+    /// Sampled triangles with an edge passing through another triangle of the mesh
+    pub self_intersecting: usize,
+    /// Glass triangles shaded as thin panes because of what was found, see [`thin_glass`]
+    pub thinned_glass: usize,
+}
+
+impl MeshAnalysis {
+    /// Triangles crossing another one, scaled up from the sample to the whole mesh
+    pub fn estimated_self_intersections(&self) -> usize {
+        if self.sampled_triangles == 0 {
+            return 0;
+        }
+        let fraction = self.self_intersecting as Float / self.sampled_triangles as Float;
+        (fraction * self.triangles as Float).round() as usize
+    }
+
+    /// Whether the mesh encloses a volume glass can be shaded inside of
+    pub fn is_watertight(&self) -> bool {
+        self.open_edges == 0 && self.non_manifold_edges == 0 && self.self_intersecting == 0
+    }
+}
+
+impl fmt::Display for MeshAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} triangles, {} open edge(s), {} non-manifold edge(s), ~{} self-intersecting \
+             triangle(s) ({} of {} sampled)",
+            self.triangles,
+            self.open_edges,
+            self.non_manifold_edges,
+            self.estimated_self_intersections(),
+            self.self_intersecting,
+            self.sampled_triangles
+        )?;
+        if self.thinned_glass > 0 {
+            write!(
+                f,
+                ", {} glass triangle(s) shaded as thin panes",
+                self.thinned_glass
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A triangle's bounds and its index in the mesh, for the `BVH` that finds which triangles
+/// another one's edges might cross
+struct IndexedBounds {
+    index: usize,
+    bounds: Aabb<Float, 3>,
+    node_index: usize,
+}
+
+impl Bounded<Float, 3> for IndexedBounds {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        self.bounds
+    }
+}
+
+impl BHShape<Float, 3> for IndexedBounds {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// Counts the open and non-manifold edges of `triangles`, matching corners up by position, and
+/// tests up to `samples` of them for passing through the rest of the mesh. A sampled triangle
+/// counts as self-intersecting when one of its edges goes through a triangle it shares no corner
+/// with, which finds most crossings but not ones where only the other triangle's edges cross it.
+pub fn analyze_mesh(triangles: &[Triangle], samples: usize) -> MeshAnalysis {
+    let mut vertex_ids: HashMap<[u64; 3], usize> = HashMap::new();
+    let mut vertex_id = |point| {
+        let next = vertex_ids.len();
+        *vertex_ids.entry(position_key(point)).or_insert(next)
+    };
+    let corners: Vec<[usize; 3]> = triangles
+        .iter()
+        .map(|tri| [vertex_id(&tri.a), vertex_id(&tri.b), vertex_id(&tri.c)])
+        .collect();
+
+    let mut edge_uses: HashMap<(usize, usize), usize> = HashMap::new();
+    for corners in &corners {
+        for k in 0..3 {
+            let (from, to) = (corners[k], corners[(k + 1) % 3]);
+            if from != to {
+                *edge_uses.entry((from.min(to), from.max(to))).or_default() += 1;
+            }
+        }
+    }
+    let mut analysis = MeshAnalysis {
+        triangles: triangles.len(),
+        open_edges: edge_uses.values().filter(|&&uses| uses == 1).count(),
+        non_manifold_edges: edge_uses.values().filter(|&&uses| uses > 2).count(),
+        ..MeshAnalysis::default()
+    };
+    if triangles.is_empty() || samples == 0 {
+        return analysis;
+    }
+
+    let mut bounds: Vec<IndexedBounds> = triangles
+        .iter()
+        .enumerate()
+        .map(|(index, tri)| IndexedBounds {
+            index,
+            bounds: tri.aabb(),
+            node_index: 0,
+        })
+        .collect();
+    let bvh = Bvh::build(&mut bounds);
+    let stride = triangles.len().div_ceil(samples).max(1);
+    for i in (0..triangles.len()).step_by(stride) {
+        analysis.sampled_triangles += 1;
+        let tri = &triangles[i];
+        let crosses = [(tri.a, tri.b), (tri.b, tri.c), (tri.c, tri.a)]
+            .into_iter()
+            .any(|(from, to)| {
+                // Triangles only get hit from the front, so each edge is traced both ways
+                [(from, to), (to, from)].into_iter().any(|(from, to)| {
+                    // Rays have unit directions, so the edge ends its length along
+                    let (edge, length) = (Ray::new(from.into(), to - from), (to - from).norm());
+                    let along = EDGE_MARGIN * length..(1.0 - EDGE_MARGIN) * length;
+                    bvh.traverse_iterator(&edge, &bounds).any(|other| {
+                        let shares_corner =
+                            corners[other.index].iter().any(|c| corners[i].contains(c));
+                        !shares_corner && triangles[other.index].hit(&edge, &along).is_some()
+                    })
+                })
+            });
+        analysis.self_intersecting += usize::from(crosses);
+    }
+    analysis
+}
+
+/// Gives the glass in `triangles` thin surfaces instead, see [`Dielectric::thin`], for meshes
+/// [`analyze_mesh`] found aren't watertight. Returns how many triangles changed.
+pub fn thin_glass(triangles: &mut [Triangle]) -> usize {
+    // Triangles share their materials, so each is only copied once
+    let mut thinned: HashMap<*const Material, Arc<Material>> = HashMap::new();
+    let mut changed = 0;
+    for triangle in triangles.iter_mut() {
+        let Material::Dielectric(glass) = &*triangle.material else {
+            continue;
+        };
+        if glass.thin {
+            continue;
+        }
+        let thin = thinned
+            .entry(Arc::as_ptr(&triangle.material))
+            .or_insert_with(|| Arc::new(Dielectric::thin_copy(glass).into()))
+            .clone();
+        triangle.material = thin;
+        changed += 1;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{load_obj, Background, LoadOptions, World},
+        material::Lambertian,
+        vec3::{Point3, Vec3},
+    };
+
+    fn gray() -> Arc<Material> {
+        Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into())
+    }
+
+    fn triangles(corners: &[[Point3; 3]], material: Arc<Material>) -> Vec<Triangle> {
+        corners
+            .iter()
+            .map(|&[a, b, c]| Triangle::new(a, b, c, material.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn fixture_meshes_report_their_open_edges() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/open_box.obj");
+        let options = LoadOptions {
+            analyze: true,
+            ..LoadOptions::default()
+        };
+        let (meshes, report) = load_obj(path, gray(), None, false, &options).unwrap();
+        assert_eq!(meshes.len(), 2);
+        // Only the box that isn't watertight is reported
+        let [(name, analysis)] = &report.mesh_analyses[..] else {
+            panic!("{:?}", report.mesh_analyses);
+        };
+        assert_eq!(name, "open_box");
+        assert_eq!(
+            *analysis,
+            MeshAnalysis {
+                triangles: 10,
+                open_edges: 4,
+                non_manifold_edges: 0,
+                sampled_triangles: 10,
+                self_intersecting: 0,
+                thinned_glass: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn fins_and_crossings_are_found() {
+        let p = |x: Float, y: Float, z: Float| Vec3::new(x, y, z);
+        // Three triangles sharing the edge along x
+        let fin = triangles(
+            &[
+                [p(0.0, 0.0, 0.0), p(1.0, 0.0, 0.0), p(0.5, 1.0, 0.0)],
+                [p(0.0, 0.0, 0.0), p(1.0, 0.0, 0.0), p(0.5, -1.0, 0.0)],
+                [p(0.0, 0.0, 0.0), p(1.0, 0.0, 0.0), p(0.5, 0.0, 1.0)],
+            ],
+            gray(),
+        );
+        let analysis = analyze_mesh(&fin, SELF_INTERSECTION_SAMPLES);
+        assert_eq!((analysis.open_edges, analysis.non_manifold_edges), (6, 1));
+        assert_eq!(analysis.self_intersecting, 0);
+
+        // The second pierces the first through its middle
+        let crossing = triangles(
+            &[
+                [p(-1.0, -1.0, 0.0), p(1.0, -1.0, 0.0), p(0.0, 1.0, 0.0)],
+                [p(0.0, 0.0, -1.0), p(0.2, 0.0, 1.0), p(-0.2, 0.0, 1.0)],
+            ],
+            gray(),
+        );
+        let analysis = analyze_mesh(&crossing, SELF_INTERSECTION_SAMPLES);
+        assert_eq!(analysis.sampled_triangles, 2);
+        assert_eq!(analysis.self_intersecting, 1);
+        assert_eq!(analysis.estimated_self_intersections(), 1);
+        assert!(!analysis.is_watertight());
+    }
+
+    #[test]
+    fn open_glass_does_not_darken_as_samples_add_up() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/open_box.obj");
+        let glass = || Arc::new(Dielectric::new(1.5).into());
+        let sky = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::repeat(0.5),
+            top: Vec3::repeat(0.5),
+        };
+        let world = |thin_open_glass: bool| {
+            let options = LoadOptions {
+                thin_open_glass,
+                ..LoadOptions::default()
+            };
+            let (meshes, report) = load_obj(path, glass(), None, false, &options).unwrap();
+            let thinned = report.mesh_analyses.iter().map(|(_, a)| a.thinned_glass);
+            assert_eq!(thinned.sum::<usize>(), if thin_open_glass { 10 } else { 0 });
+            let mut world = World::build(meshes.into_iter().map(Into::into).collect());
+            world.background = sky.clone();
+            world
+        };
+        // Looking down into the open box, through its walls from the inside
+        let mean = |world: &World, samples: usize, seed: u64| {
+            let mut camera = Camera::builder()
+                .with_look_from(Vec3::new(0.5, -1.0, 2.5))
+                .with_look_at(Vec3::new(0.5, 0.6, 0.3))
+                .with_vertical_fov(15.0)
+                .with_resolution(16, 16)
+                .with_samples(samples)
+                .with_max_depth(16)
+                .build()
+                .unwrap();
+            camera.seed = Some(seed);
+            let image = camera.render_image(world);
+            image.pixels.iter().map(|pixel| pixel.x).sum::<Float>() / image.pixels.len() as Float
+        };
+        let mut empty = World::build(Vec::new());
+        empty.background = sky.clone();
+        let open_sky = mean(&empty, 1, 1);
+        // Only thinned when asked to
+        world(false);
+        let thin = world(true);
+        let (early, late) = (mean(&thin, 4, 1), mean(&thin, 64, 2));
+        assert!((late / early - 1.0).abs() < 0.02, "{} then {}", early, late);
+        assert!(
+            (late / open_sky - 1.0).abs() < 0.02,
+            "{} of {}",
+            late,
+            open_sky
+        );
+    }
+}
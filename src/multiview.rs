@@ -0,0 +1,467 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    hittable::World,
+    perf::{self, PerfLog, SweepRecord},
+    tiles::{Accumulation, TileRenderer},
+    vec3::Vec3,
+};
+use rayon::prelude::*;
+use std::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+/// How [`render_multi`] shares its time between the views
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiMode {
+    /// Renders each view to completion before starting the next, exactly like rendering it on
+    /// its own, only without loading the scene again
+    Sequential,
+    /// Renders a sweep of `sweep_samples` samples per pixel of every view in turn, so all the
+    /// views converge together and stopping early leaves a draft of each
+    Interleaved { sweep_samples: usize },
+}
+
+impl MultiMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MultiMode::Sequential => "sequential",
+            MultiMode::Interleaved { .. } => "interleaved",
+        }
+    }
+}
+
+/// Settings for rendering several cameras' views of one world with [`render_multi`]
+#[derive(Debug, Clone, Copy)]
+pub struct MultiSettings {
+    pub mode: MultiMode,
+    /// Stops the render once set, at the end of the view being rendered when sequential, and
+    /// at the end of the sweep being rendered when interleaved
+    pub cancel: Option<&'static AtomicBool>,
+}
+
+impl Default for MultiSettings {
+    fn default() -> Self {
+        MultiSettings {
+            mode: MultiMode::Sequential,
+            cancel: None,
+        }
+    }
+}
+
+/// How rendering one of the views went
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewStats {
+    pub samples_per_pixel: usize,
+    pub sweeps: usize,
+    /// Time spent rendering this view, not counting the others' sweeps in between
+    pub seconds: Float,
+    pub camera_rays: u64,
+}
+
+impl ViewStats {
+    /// Millions of camera rays per second
+    pub fn mrays_per_second(&self) -> Float {
+        self.camera_rays as Float / 1e6 / self.seconds.max(Float::MIN_POSITIVE)
+    }
+}
+
+/// How a [`render_multi`] went, for every view and for all of them together
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiReport {
+    pub mode: MultiMode,
+    /// One for every view that was started, in the order of the cameras
+    pub views: Vec<ViewStats>,
+    /// Time from the start of the first view to the end of the last
+    pub seconds: Float,
+    pub cancelled: bool,
+}
+
+impl MultiReport {
+    pub fn camera_rays(&self) -> u64 {
+        self.views.iter().map(|view| view.camera_rays).sum()
+    }
+
+    /// Millions of camera rays per second over all the views
+    pub fn mrays_per_second(&self) -> Float {
+        self.camera_rays() as Float / 1e6 / self.seconds.max(Float::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for MultiReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, view) in self.views.iter().enumerate() {
+            writeln!(
+                f,
+                "view {}: {} samples per pixel in {} sweep(s), {:.1} seconds, {:.2} Mrays/s",
+                index,
+                view.samples_per_pixel,
+                view.sweeps,
+                view.seconds,
+                view.mrays_per_second()
+            )?;
+        }
+        write!(
+            f,
+            "all {} views ({}): {:.1} seconds, {:.2} Mrays/s{}",
+            self.views.len(),
+            self.mode.name(),
+            self.seconds,
+            self.mrays_per_second(),
+            if self.cancelled { " (cancelled)" } else { "" }
+        )
+    }
+}
+
+/// Where the camera is and how it sees, for recording in the metadata of its view
+pub fn describe_camera(camera: &Camera) -> String {
+    let vector = |v: &Vec3| format!("{} {} {}", v.x, v.y, v.z);
+    format!(
+        "center {}, lookat {}, up {}, vertical fov {}, focus distance {}, defocus angle {}, \
+         resolution {}x{}",
+        vector(&camera.center),
+        vector(&camera.lookat),
+        vector(&camera.up),
+        camera.vertical_fov,
+        camera.focus_distance,
+        camera.defocus_angle(),
+        camera.image_width,
+        camera.image_height
+    )
+}
+
+/// Renders every camera's view of `world`, on `tiles` if given, reusing the world and its BVH
+/// for all of them. Returns an image for every view that was started, in the order of the
+/// cameras, each with its camera's settings as metadata. Sequential views render exactly like
+/// [`TileRenderer::render_image`] or [`Camera::render_image`] would on their own. With a seed,
+/// interleaved views take the very same samples too, only summed in a different order.
+pub fn render_multi(
+    cameras: &[Camera],
+    world: &World,
+    settings: &MultiSettings,
+    tiles: Option<&TileRenderer>,
+) -> (Vec<Image>, MultiReport) {
+    let start = Instant::now();
+    let cancelled = || {
+        settings
+            .cancel
+            .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+    };
+    let mut images = Vec::new();
+    let mut views = Vec::new();
+    let mut stopped = false;
+    match settings.mode {
+        MultiMode::Sequential => {
+            for camera in cameras {
+                if cancelled() {
+                    stopped = true;
+                    break;
+                }
+                let view_start = Instant::now();
+                let traced_before = camera.watchdog.path_stats().traced_rays;
+                let image = match tiles {
+                    Some(tiles) => tiles.render_image(camera, world),
+                    None => camera.render_image(world),
+                };
+                let samples = camera.samples_per_pixel();
+                let stats = ViewStats {
+                    samples_per_pixel: samples,
+                    sweeps: 1,
+                    seconds: view_start.elapsed().as_secs_f64(),
+                    camera_rays: (samples * camera.image_width * camera.image_height) as u64,
+                };
+                if let Some(log) = PerfLog::global() {
+                    log.sweep(&SweepRecord {
+                        render: log.begin_render(),
+                        sweep: 1,
+                        samples_added: samples,
+                        total_samples: samples,
+                        seconds: stats.seconds,
+                        camera_rays: stats.camera_rays,
+                        traced_rays: perf::traced_since(&camera.watchdog, traced_before),
+                        converged_fraction: None,
+                    });
+                }
+                images.push(image);
+                views.push(stats);
+            }
+        }
+        MultiMode::Interleaved { sweep_samples } => {
+            let sweep_samples = sweep_samples.max(1);
+            let mut accumulations: Vec<Accumulation> = cameras
+                .iter()
+                .map(|camera| Accumulation::new(camera.image_width, camera.image_height))
+                .collect();
+            views = vec![
+                ViewStats {
+                    samples_per_pixel: 0,
+                    sweeps: 0,
+                    seconds: 0.0,
+                    camera_rays: 0,
+                };
+                cameras.len()
+            ];
+            let perf_renders: Vec<Option<usize>> = cameras
+                .iter()
+                .map(|_| PerfLog::global().map(PerfLog::begin_render))
+                .collect();
+            loop {
+                let mut rendered_any = false;
+                for (index, camera) in cameras.iter().enumerate() {
+                    let stats = &mut views[index];
+                    let total = camera.samples_per_pixel();
+                    if stats.samples_per_pixel >= total {
+                        continue;
+                    }
+                    let samples = stats.samples_per_pixel
+                        ..(stats.samples_per_pixel + sweep_samples).min(total);
+                    let added = samples.len();
+                    let sweep_start = Instant::now();
+                    let traced_before = camera.watchdog.path_stats().traced_rays;
+                    render_samples(camera, world, &mut accumulations[index], samples, tiles);
+                    let seconds = sweep_start.elapsed().as_secs_f64();
+                    let camera_rays = (added * camera.image_width * camera.image_height) as u64;
+                    stats.samples_per_pixel += added;
+                    stats.sweeps += 1;
+                    stats.seconds += seconds;
+                    stats.camera_rays += camera_rays;
+                    if let (Some(log), Some(render)) = (PerfLog::global(), perf_renders[index]) {
+                        log.sweep(&SweepRecord {
+                            render,
+                            sweep: stats.sweeps,
+                            samples_added: added,
+                            total_samples: stats.samples_per_pixel,
+                            seconds,
+                            camera_rays,
+                            traced_rays: perf::traced_since(&camera.watchdog, traced_before),
+                            converged_fraction: None,
+                        });
+                    }
+                    rendered_any = true;
+                }
+                if !rendered_any {
+                    break;
+                }
+                // Only between sweeps, so every view has had as many as the others
+                if cancelled() {
+                    stopped = true;
+                    break;
+                }
+            }
+            images = cameras
+                .iter()
+                .zip(&accumulations)
+                .zip(&views)
+                .map(|((camera, accumulation), stats)| {
+                    let mut image = camera.image_from_pixels(accumulation.colors());
+                    if stats.samples_per_pixel < camera.samples_per_pixel() {
+                        image.metadata.push(format!(
+                            "stopped early at {} samples per pixel",
+                            stats.samples_per_pixel
+                        ));
+                    }
+                    if let Some(tiles) = tiles {
+                        image.metadata.push(format!("threads: {}", tiles.threads()));
+                    }
+                    image
+                })
+                .collect();
+        }
+    }
+
+    let mode = match settings.mode {
+        MultiMode::Sequential => "sequential".to_string(),
+        MultiMode::Interleaved { sweep_samples } => {
+            format!("interleaved, {} samples per pixel per sweep", sweep_samples)
+        }
+    };
+    for (index, (image, camera)) in images.iter_mut().zip(cameras).enumerate() {
+        image.metadata.extend([
+            format!("view: {} of {} ({})", index, cameras.len(), mode),
+            format!("view camera: {}", describe_camera(camera)),
+        ]);
+    }
+    let report = MultiReport {
+        mode: settings.mode,
+        views,
+        seconds: start.elapsed().as_secs_f64(),
+        cancelled: stopped,
+    };
+    (images, report)
+}
+
+/// Adds samples `samples` of every pixel of the camera's view to `accumulation`
+fn render_samples(
+    camera: &Camera,
+    world: &World,
+    accumulation: &mut Accumulation,
+    samples: Range<usize>,
+    tiles: Option<&TileRenderer>,
+) {
+    let count = samples.len();
+    let render_pixel = |x: usize, y: usize| {
+        let mut color = Vec3::zeros();
+        for i in samples.clone() {
+            camera.watchdog.begin_sample(x, y, i);
+            color += camera.render_sample(world, x, y, i);
+            camera.watchdog.end_sample();
+        }
+        color / count as Float
+    };
+    match tiles {
+        Some(tiles) => tiles.render_sweep(accumulation, count, |x, y| Some(render_pixel(x, y))),
+        None => {
+            let width = accumulation.width;
+            let colors: Vec<Vec3> = (0..width * accumulation.height)
+                .into_par_iter()
+                .map(|k| render_pixel(k % width, k / width))
+                .collect();
+            for (k, color) in colors.into_iter().enumerate() {
+                accumulation.add(k % width, k / width, color, count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Sphere,
+        material::{Lambertian, Metal},
+        tiles::ExecutionOptions,
+    };
+    use std::sync::Arc;
+
+    /// A matte ball and a mirror ball, from the front and from above at different sizes, seeded
+    fn views() -> (Vec<Camera>, World) {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let mirror = Arc::new(Metal::new_solid(Vec3::new(0.8, 0.7, 0.6), Some(0.1)).into());
+        let world = World::build(vec![
+            Sphere::new(Vec3::new(-0.6, 0.0, 0.0), 0.5, gray).into(),
+            Sphere::new(Vec3::new(0.6, 0.0, 0.0), 0.5, mirror).into(),
+        ]);
+        let camera = |from: Vec3, width: usize, height: usize, seed: u64| {
+            let mut camera = Camera::builder()
+                .with_look_from(from)
+                .with_look_at(Vec3::zeros())
+                .with_vertical_fov(40.0)
+                .with_resolution(width, height)
+                .with_samples(6)
+                .with_max_depth(4)
+                .build()
+                .unwrap();
+            camera.seed = Some(seed);
+            camera
+        };
+        let cameras = vec![
+            camera(Vec3::new(0.0, -4.0, 0.5), 24, 16, 1),
+            camera(Vec3::new(0.1, -0.5, 4.0), 12, 12, 2),
+        ];
+        (cameras, world)
+    }
+
+    fn interleaved(sweep_samples: usize, cancel: Option<&'static AtomicBool>) -> MultiSettings {
+        MultiSettings {
+            mode: MultiMode::Interleaved { sweep_samples },
+            cancel,
+        }
+    }
+
+    #[test]
+    fn sequential_views_are_pixel_identical_to_rendering_each_alone() {
+        let (cameras, world) = views();
+        let tiles = TileRenderer::new(&ExecutionOptions {
+            threads: Some(2),
+            ..ExecutionOptions::default()
+        })
+        .unwrap();
+        for tiles in [None, Some(&tiles)] {
+            let (images, report) = render_multi(&cameras, &world, &MultiSettings::default(), tiles);
+            assert_eq!(images.len(), 2);
+            assert!(!report.cancelled);
+            for (index, (image, camera)) in images.iter().zip(&cameras).enumerate() {
+                let alone = match tiles {
+                    Some(tiles) => tiles.render_image(camera, &world),
+                    None => camera.render_image(&world),
+                };
+                assert_eq!((image.width, image.height), (alone.width, alone.height));
+                assert!(image.pixels == alone.pixels, "view {} differs", index);
+                assert!(image
+                    .metadata
+                    .contains(&format!("view camera: {}", describe_camera(camera))));
+            }
+            let stats = &report.views[1];
+            assert_eq!((stats.samples_per_pixel, stats.sweeps), (6, 1));
+            assert_eq!(report.camera_rays(), 6 * (24 * 16 + 12 * 12));
+        }
+    }
+
+    #[test]
+    fn interleaved_views_take_the_same_samples() {
+        let (cameras, world) = views();
+        // Three sweeps of two samples each
+        let (images, report) = render_multi(&cameras, &world, &interleaved(2, None), None);
+        assert!(!report.cancelled);
+        for (index, (image, camera)) in images.iter().zip(&cameras).enumerate() {
+            assert_eq!(report.views[index].sweeps, 3);
+            let alone = camera.render_image(&world);
+            for (a, b) in image.pixels.iter().zip(&alone.pixels) {
+                // Only summed in a different order
+                assert!(
+                    (a - b).abs().max() < 1e-12,
+                    "view {}: {} vs {}",
+                    index,
+                    a,
+                    b
+                );
+            }
+            assert!(!image
+                .metadata
+                .iter()
+                .any(|line| line.starts_with("stopped early")));
+        }
+    }
+
+    #[test]
+    fn interleaved_views_stopped_early_are_each_a_complete_draft() {
+        static CANCEL: AtomicBool = AtomicBool::new(false);
+        let (cameras, world) = views();
+        // Only checked between sweeps, so every view gets the first sweep
+        CANCEL.store(true, Ordering::SeqCst);
+        let (images, report) = render_multi(&cameras, &world, &interleaved(2, Some(&CANCEL)), None);
+        assert!(report.cancelled);
+        assert_eq!(images.len(), 2);
+        for (index, (image, camera)) in images.iter().zip(&cameras).enumerate() {
+            let stats = &report.views[index];
+            assert_eq!((stats.samples_per_pixel, stats.sweeps), (2, 1));
+            assert!(image
+                .metadata
+                .contains(&"stopped early at 2 samples per pixel".to_string()));
+            // The same as asking for two samples in the first place
+            let draft = camera
+                .with_sampling(2, camera.max_depth())
+                .render_image(&world);
+            for (a, b) in image.pixels.iter().zip(&draft.pixels) {
+                assert!(
+                    (a - b).abs().max() < 1e-12,
+                    "view {}: {} vs {}",
+                    index,
+                    a,
+                    b
+                );
+            }
+        }
+
+        // Sequential renders stop before the next view, here the first
+        let settings = MultiSettings {
+            mode: MultiMode::Sequential,
+            cancel: Some(&CANCEL),
+        };
+        let (images, report) = render_multi(&cameras, &world, &settings, None);
+        assert!(report.cancelled && images.is_empty() && report.views.is_empty());
+        CANCEL.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    camera::Float,
+    vec3::{Point3, Vec3},
+};
+use bvh::aabb::Aabb;
+
+/// Closest distance a ray may hit something, as a fraction of the scene's size
+const MIN_HIT_DISTANCE_RATIO: Float = 1e-7;
+/// Hits closer than this fraction of the scene's size to the path's previous hit don't count as
+/// moving it forward
+const ZERO_ADVANCE_RATIO: Float = 1e-6;
+/// How far new rays start off a surface, as a fraction of the scene's size
+const RAY_OFFSET_RATIO: Float = 1e-7;
+/// How far new rays start off a surface, as a fraction of the hit point's largest coordinate.
+/// Rounding error in a hit point grows with its distance from the origin.
+const POINT_OFFSET_RATIO: Float = 1e-9;
+
+/// Triangles are treated as edge-on to a ray when the determinant of the ray-triangle test is
+/// below this fraction of the product of their edge lengths, which is the same at any scale.
+/// The default for [`crate::hittable::Triangle::with_epsilon`].
+pub const DEGENERATE_TRIANGLE_RATIO: Float = 1e-12;
+
+/// Tolerances that scale with the scene, so a 0.01-unit ring and a 10000-unit ground plane
+/// both render without self-intersection acne or light leaking through contacts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumericContext {
+    /// Length of the diagonal of the scene's bounds
+    pub scene_scale: Float,
+    /// Closest distance a ray may hit something, so it doesn't hit the surface it started on
+    pub min_hit_distance: Float,
+    /// Hits closer than this to the path's previous hit don't count as moving it forward
+    pub zero_advance_distance: Float,
+    /// Least distance new rays start off the surface they leave from
+    pub ray_offset: Float,
+}
+
+impl Default for NumericContext {
+    fn default() -> Self {
+        NumericContext::from_scale(1.0)
+    }
+}
+
+impl NumericContext {
+    pub fn from_scale(scene_scale: Float) -> Self {
+        NumericContext {
+            scene_scale,
+            min_hit_distance: scene_scale * MIN_HIT_DISTANCE_RATIO,
+            zero_advance_distance: scene_scale * ZERO_ADVANCE_RATIO,
+            ray_offset: scene_scale * RAY_OFFSET_RATIO,
+        }
+    }
+
+    /// Derives the tolerances from the bounds of everything in a scene. Empty or unbounded scenes
+    /// get the tolerances of a scene one unit across.
+    pub fn from_bounds(bounds: &Aabb<Float, 3>) -> Self {
+        let diagonal = (bounds.max - bounds.min).norm();
+        if diagonal.is_finite() && diagonal > 0.0 {
+            NumericContext::from_scale(diagonal)
+        } else {
+            NumericContext::default()
+        }
+    }
+
+    /// Returns where a ray leaving `point` along `direction` should start so it can't hit the
+    /// surface with `normal` it's leaving again. Pushes it to the side `direction` points to,
+    /// further for points far from the origin since their rounding error is larger.
+    pub fn offset_ray_origin(&self, point: &Point3, normal: &Vec3, direction: &Vec3) -> Point3 {
+        let offset = self.ray_offset.max(point.amax() * POINT_OFFSET_RATIO);
+        if direction.dot(normal) < 0.0 {
+            point - normal * offset
+        } else {
+            point + normal * offset
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{Camera, Image},
+        hittable::{Background, Sphere, Triangle, World},
+        material::{Lambertian, Material},
+        sky_importance::luminance,
+    };
+    use std::sync::Arc;
+
+    /// A ball resting on a floor of two triangles under a gradient sky, everything `scale` times
+    /// the size of the same scene one unit across, seen from a camera scaled with it
+    fn render_at_scale(scale: Float) -> Image {
+        let gray: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.6, 0.6, 0.6).into());
+        let red = Arc::new(Lambertian::new_rgb_solid(0.7, 0.3, 0.3).into());
+        let corner = |x: Float, y: Float| Vec3::new(x, y, 0.0) * scale;
+        let mut world = World::build(vec![
+            Triangle::new(
+                corner(-4.0, -4.0),
+                corner(4.0, -4.0),
+                corner(4.0, 4.0),
+                gray.clone(),
+            )
+            .into(),
+            Triangle::new(
+                corner(-4.0, -4.0),
+                corner(4.0, 4.0),
+                corner(-4.0, 4.0),
+                gray,
+            )
+            .into(),
+            Sphere::new(Vec3::new(0.0, 0.0, 1.0) * scale, scale, red).into(),
+        ]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::repeat(0.2),
+            top: Vec3::new(0.6, 0.7, 1.0),
+        };
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -6.0, 3.0) * scale)
+            .with_look_at(Vec3::new(0.0, 0.0, 0.5) * scale)
+            .with_resolution(32, 24)
+            .with_samples(64)
+            .build()
+            .unwrap();
+        camera.seed = Some(5);
+        camera.render_image(&world)
+    }
+
+    /// Counts pixels much darker or brighter than most of their neighbors, like the speckles of
+    /// a surface shadowing itself or light leaking through a contact
+    fn isolated_pixels(image: &Image) -> usize {
+        let lum = |x: usize, y: usize| luminance(&image[(x, y)]);
+        let mut isolated = 0;
+        for y in 1..image.height - 1 {
+            for x in 1..image.width - 1 {
+                let mut neighbors: Vec<Float> = (0..9)
+                    .filter(|&i| i != 4)
+                    .map(|i| lum(x + i % 3 - 1, y + i / 3 - 1))
+                    .collect();
+                neighbors.sort_by(Float::total_cmp);
+                let median = (neighbors[3] + neighbors[4]) / 2.0;
+                let pixel = lum(x, y);
+                if pixel < median * 0.5 || pixel > median * 2.0 {
+                    isolated += 1;
+                }
+            }
+        }
+        isolated
+    }
+
+    fn mean_luminance(image: &Image) -> Float {
+        image.pixels.iter().map(luminance).sum::<Float>() / image.pixels.len() as Float
+    }
+
+    #[test]
+    fn tiny_and_huge_scenes_render_like_unit_ones() {
+        let unit = render_at_scale(1.0);
+        let unit_isolated = isolated_pixels(&unit);
+        for scale in [1e-3, 1e4] {
+            let image = render_at_scale(scale);
+            let isolated = isolated_pixels(&image);
+            assert!(
+                isolated <= unit_isolated + 2,
+                "{} isolated pixels at scale {}, {} at scale 1",
+                isolated,
+                scale,
+                unit_isolated
+            );
+            let (mean, unit_mean) = (mean_luminance(&image), mean_luminance(&unit));
+            assert!(
+                (mean - unit_mean).abs() < unit_mean * 0.02,
+                "mean luminance {} at scale {}, {} at scale 1",
+                mean,
+                scale,
+                unit_mean
+            );
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fmt,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+/// Identifies which scene object (glTF node, OBJ group, procedural helper) a shape came from.
+/// Shapes only store the id; names live once per object in a process-wide table.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ObjectId(u32);
+
+fn names() -> &'static RwLock<Vec<Arc<str>>> {
+    static NAMES: OnceLock<RwLock<Vec<Arc<str>>>> = OnceLock::new();
+    // Id 0 is reserved for shapes nobody bothered to name
+    NAMES.get_or_init(|| RwLock::new(vec![Arc::from("unnamed")]))
+}
+
+impl ObjectId {
+    /// Registers a new object called `name` and returns its id
+    pub fn register(name: &str) -> Self {
+        let mut names = names().write().unwrap();
+        names.push(Arc::from(name));
+        ObjectId((names.len() - 1) as u32)
+    }
+
+    /// Every object registered under `name`, since loading the same file twice registers it
+    /// twice
+    pub fn all_named(name: &str) -> Vec<ObjectId> {
+        let names = names().read().unwrap();
+        (0..names.len())
+            .filter(|&i| &*names[i] == name)
+            .map(|i| ObjectId(i as u32))
+            .collect()
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        names().read().unwrap()[self.0 as usize].clone()
+    }
+
+    pub fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({} \"{}\")", self.0, self.name())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unnamed_shapes_share_the_reserved_id() {
+        assert_eq!(ObjectId::default().index(), 0);
+        assert_eq!(&*ObjectId::default().name(), "unnamed");
+    }
+
+    #[test]
+    fn names_are_kept_once_per_object() {
+        let wheel = ObjectId::register("test/names/wheel");
+        let again = ObjectId::register("test/names/wheel");
+        assert_ne!(wheel, again);
+        assert_eq!(wheel.to_string(), "test/names/wheel");
+        assert_eq!(ObjectId::all_named("test/names/wheel"), vec![wheel, again]);
+        // Every id hands out the one shared name rather than a copy
+        assert!(Arc::ptr_eq(&wheel.name(), &wheel.name()));
+    }
+}
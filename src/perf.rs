@@ -0,0 +1,737 @@
+use crate::watchdog::Watchdog;
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Performance records of a whole run, one JSON object per line: a `session` header describing
+/// the render and the machine, then a `sweep` record for every sweep of samples. Written as the
+/// run goes, so a run that's killed still leaves everything up to its last sweep.
+pub struct PerfLog {
+    file: Mutex<File>,
+    renders: AtomicUsize,
+}
+
+static GLOBAL: OnceLock<PerfLog> = OnceLock::new();
+
+impl PerfLog {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(PerfLog {
+            file: Mutex::new(File::create(path)?),
+            renders: AtomicUsize::new(0),
+        })
+    }
+
+    /// Makes every render in the process log to `path`, which is `--perf-log`. Only the first
+    /// call does anything.
+    pub fn install(path: impl AsRef<Path>) -> io::Result<()> {
+        let log = PerfLog::create(path)?;
+        let _ = GLOBAL.set(log);
+        Ok(())
+    }
+
+    /// The log installed by [`PerfLog::install`], if any
+    pub fn global() -> Option<&'static PerfLog> {
+        GLOBAL.get()
+    }
+
+    pub fn session(&self, header: &SessionHeader) {
+        self.write_line(&header.to_json());
+    }
+
+    /// Numbers a new render, which every sweep of it is recorded with. A preview starts a new
+    /// render whenever an edit restarts it, and a sequence for every frame.
+    pub fn begin_render(&self) -> usize {
+        self.renders.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn sweep(&self, record: &SweepRecord) {
+        self.write_line(&record.to_json());
+    }
+
+    /// Logging is never worth failing a render over, so errors are only printed
+    fn write_line(&self, line: &str) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{}", line) {
+            println!("Warning: couldn't write to the performance log, {}", err);
+        }
+    }
+}
+
+/// The first line of a performance log, saying what was rendered on what
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionHeader {
+    /// `preview` or `batch`
+    pub kind: String,
+    /// From [`crate::snapshot::SceneSnapshot::fingerprint`]
+    pub scene_fingerprint: u64,
+    /// From [`crate::job::RenderJob::fingerprint`], for batch renders
+    pub job_fingerprint: Option<u64>,
+    pub width: usize,
+    pub height: usize,
+    /// Samples per pixel of a batch render. The preview's schedule of sweeps is fixed.
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: usize,
+    pub integrator: String,
+    pub fidelity: String,
+    /// Threads rendering
+    pub threads: usize,
+    /// Cores the machine has, which can be more than `threads`
+    pub cores: usize,
+    pub version: String,
+    /// Commit the binary was built from, when `RT_GIT_HASH` was set while building
+    pub git_hash: Option<String>,
+    /// When the run started, in seconds since the Unix epoch
+    pub started: u64,
+}
+
+impl SessionHeader {
+    /// A header for the machine and build this is running on, with the render's details left
+    /// for the caller to fill in
+    pub fn here(kind: &str) -> Self {
+        SessionHeader {
+            kind: kind.to_string(),
+            threads: rayon::current_num_threads(),
+            cores: std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_hash: option_env!("RT_GIT_HASH").map(str::to_string),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            ..SessionHeader::default()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = JsonObject::new("session");
+        json.string("kind", &self.kind);
+        json.string(
+            "scene_fingerprint",
+            &format!("{:016x}", self.scene_fingerprint),
+        );
+        if let Some(fingerprint) = self.job_fingerprint {
+            json.string("job_fingerprint", &format!("{:016x}", fingerprint));
+        }
+        json.number("width", self.width as f64);
+        json.number("height", self.height as f64);
+        if let Some(samples) = self.samples_per_pixel {
+            json.number("samples_per_pixel", samples as f64);
+        }
+        json.number("max_depth", self.max_depth as f64);
+        json.string("integrator", &self.integrator);
+        json.string("fidelity", &self.fidelity);
+        json.number("threads", self.threads as f64);
+        json.number("cores", self.cores as f64);
+        json.string("version", &self.version);
+        if let Some(hash) = &self.git_hash {
+            json.string("git_hash", hash);
+        }
+        json.number("started", self.started as f64);
+        json.finish()
+    }
+
+    /// The inverse of [`SessionHeader::to_json`]'s fields, with any that are missing or
+    /// unreadable left at their defaults
+    pub fn from_fields(fields: &HashMap<String, JsonValue>) -> Self {
+        let string = |key: &str| {
+            fields
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+        };
+        let number = |key: &str| fields.get(key).and_then(JsonValue::as_f64);
+        let fingerprint = |key: &str| {
+            fields
+                .get(key)
+                .and_then(JsonValue::as_str)
+                .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        };
+        SessionHeader {
+            kind: string("kind").unwrap_or_default(),
+            scene_fingerprint: fingerprint("scene_fingerprint").unwrap_or_default(),
+            job_fingerprint: fingerprint("job_fingerprint"),
+            width: number("width").unwrap_or_default() as usize,
+            height: number("height").unwrap_or_default() as usize,
+            samples_per_pixel: number("samples_per_pixel").map(|samples| samples as usize),
+            max_depth: number("max_depth").unwrap_or_default() as usize,
+            integrator: string("integrator").unwrap_or_default(),
+            fidelity: string("fidelity").unwrap_or_default(),
+            threads: number("threads").unwrap_or_default() as usize,
+            cores: number("cores").unwrap_or_default() as usize,
+            version: string("version").unwrap_or_default(),
+            git_hash: string("git_hash"),
+            started: number("started").unwrap_or_default() as u64,
+        }
+    }
+}
+
+/// One sweep of samples over the image, or one pass of a batch render
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SweepRecord {
+    /// From [`PerfLog::begin_render`]
+    pub render: usize,
+    /// Counts from 1 within the render
+    pub sweep: usize,
+    /// Samples per pixel the sweep added
+    pub samples_added: usize,
+    /// Samples per pixel after the sweep
+    pub total_samples: usize,
+    pub seconds: f64,
+    /// Rays leaving the camera, one per sample of every pixel that was sampled
+    pub camera_rays: u64,
+    /// Every ray traced along the way, bounces included, when the watchdog was counting them
+    pub traced_rays: Option<u64>,
+    /// Fraction of pixels considered converged after the sweep, by whatever the render goes by:
+    /// converged sky pixels in the preview, and the stop criterion with auto stop
+    pub converged_fraction: Option<f64>,
+}
+
+impl SweepRecord {
+    /// Millions of camera rays per second
+    pub fn mrays_per_second(&self) -> f64 {
+        self.camera_rays as f64 / 1e6 / self.seconds.max(f64::MIN_POSITIVE)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = JsonObject::new("sweep");
+        json.number("render", self.render as f64);
+        json.number("sweep", self.sweep as f64);
+        json.number("samples_added", self.samples_added as f64);
+        json.number("total_samples", self.total_samples as f64);
+        json.number("seconds", self.seconds);
+        json.number("camera_rays", self.camera_rays as f64);
+        json.number("mrays_per_second", self.mrays_per_second());
+        if let Some(traced) = self.traced_rays {
+            json.number("traced_rays", traced as f64);
+        }
+        if let Some(fraction) = self.converged_fraction {
+            json.number("converged_fraction", fraction);
+        }
+        json.finish()
+    }
+}
+
+/// Rays `watchdog` counted since it had counted `before`, or `None` if it counted none, which
+/// is what integrators that don't report their bounces to it leave
+pub fn traced_since(watchdog: &Watchdog, before: u64) -> Option<u64> {
+    let traced = watchdog.path_stats().traced_rays.saturating_sub(before);
+    (traced > 0).then_some(traced)
+}
+
+/// Writes a flat JSON object a field at a time, starting with its `type`
+pub(crate) struct JsonObject(String);
+
+impl JsonObject {
+    pub(crate) fn new(kind: &str) -> Self {
+        let mut json = JsonObject(String::from("{"));
+        json.string("type", kind);
+        json
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.0.len() > 1 {
+            self.0.push_str(", ");
+        }
+        write_json_string(&mut self.0, key);
+        self.0.push_str(": ");
+    }
+
+    pub(crate) fn string(&mut self, key: &str, value: &str) {
+        self.key(key);
+        write_json_string(&mut self.0, value);
+    }
+
+    /// Non-finite numbers aren't JSON, so they're written as `null`
+    pub(crate) fn number(&mut self, key: &str, value: f64) {
+        self.key(key);
+        if value.is_finite() {
+            write!(self.0, "{}", value).unwrap();
+        } else {
+            self.0.push_str("null");
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// A field's value in a performance log or a benchmark baseline. Both only ever hold flat
+/// objects, so there are no arrays or nested objects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl JsonValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a line holding one flat JSON object, or returns `None` if it isn't one
+pub fn parse_object(line: &str) -> Option<HashMap<String, JsonValue>> {
+    let mut parser = Parser {
+        chars: line.trim().chars().peekable(),
+    };
+    let mut fields = HashMap::new();
+    parser.expect('{')?;
+    if !parser.eat('}') {
+        loop {
+            let key = parser.string()?;
+            parser.expect(':')?;
+            let value = parser.value()?;
+            fields.insert(key, value);
+            if parser.eat('}') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.skip_whitespace();
+    parser.chars.peek().is_none().then_some(fields)
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    /// Consumes `c` if it's next, after any whitespace
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if_eq(&c).is_some()
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.eat(c).then_some(())
+    }
+
+    fn value(&mut self) -> Option<JsonValue> {
+        self.skip_whitespace();
+        match *self.chars.peek()? {
+            '"' => self.string().map(JsonValue::String),
+            'n' => self.word("null").map(|_| JsonValue::Null),
+            't' => self.word("true").map(|_| JsonValue::Bool(true)),
+            'f' => self.word("false").map(|_| JsonValue::Bool(false)),
+            _ => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+                {
+                    number.push(c);
+                }
+                number.parse().ok().map(JsonValue::Number)
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str) -> Option<()> {
+        word.chars()
+            .all(|c| self.chars.next() == Some(c))
+            .then_some(())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(string),
+                '\\' => string.push(match self.chars.next()? {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    c => c,
+                }),
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+/// What one performance log adds up to, for comparing runs with [`PerfReport`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogSummary {
+    /// The log's first session header, if it has one
+    pub header: Option<SessionHeader>,
+    pub sweeps: usize,
+    /// Every sweep's time added up
+    pub seconds: f64,
+    pub median_sweep_seconds: Option<f64>,
+    pub median_mrays_per_second: Option<f64>,
+    /// Millions of rays traced per second, bounces included, for logs that counted them
+    pub median_traced_mrays_per_second: Option<f64>,
+    /// Converged fraction after the last sweep that recorded one
+    pub final_converged_fraction: Option<f64>,
+    /// Lines that weren't JSON objects, like one cut off by a killed render
+    pub skipped_lines: usize,
+}
+
+impl LogSummary {
+    /// Summarizes a log's text. Lines of unknown types are ignored and fields missing from logs
+    /// written by older versions are left out of the summary, so old logs stay comparable.
+    pub fn from_log(text: &str) -> Self {
+        let mut summary = LogSummary::default();
+        let mut sweep_seconds = Vec::new();
+        let mut mrays = Vec::new();
+        let mut traced_mrays = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let Some(fields) = parse_object(line) else {
+                summary.skipped_lines += 1;
+                continue;
+            };
+            let number = |key: &str| fields.get(key).and_then(JsonValue::as_f64);
+            match fields.get("type").and_then(JsonValue::as_str) {
+                Some("session") if summary.header.is_none() => {
+                    summary.header = Some(SessionHeader::from_fields(&fields));
+                }
+                Some("sweep") => {
+                    summary.sweeps += 1;
+                    let seconds = number("seconds").filter(|seconds| *seconds > 0.0);
+                    if let Some(seconds) = seconds {
+                        summary.seconds += seconds;
+                        sweep_seconds.push(seconds);
+                    }
+                    let camera_rays = number("camera_rays");
+                    let rate =
+                        number("mrays_per_second").or_else(|| Some(camera_rays? / 1e6 / seconds?));
+                    mrays.extend(rate);
+                    if let (Some(traced), Some(seconds)) = (number("traced_rays"), seconds) {
+                        traced_mrays.push(traced / 1e6 / seconds);
+                    }
+                    if let Some(fraction) = number("converged_fraction") {
+                        summary.final_converged_fraction = Some(fraction);
+                    }
+                }
+                _ => {}
+            }
+        }
+        summary.median_sweep_seconds = median(&mut sweep_seconds);
+        summary.median_mrays_per_second = median(&mut mrays);
+        summary.median_traced_mrays_per_second = median(&mut traced_mrays);
+        summary
+    }
+}
+
+/// The middle value, or the mean of the middle two, of `values` with any NaN left out
+pub fn median(values: &mut Vec<f64>) -> Option<f64> {
+    values.retain(|value| !value.is_nan());
+    values.sort_unstable_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(values[middle]),
+        _ => Some((values[middle - 1] + values[middle]) / 2.0),
+    }
+}
+
+/// Logs side by side, with each one's change from the first, which is the `rt perf-report`
+/// table
+#[derive(Debug, Clone)]
+pub struct PerfReport {
+    pub logs: Vec<(String, LogSummary)>,
+}
+
+impl PerfReport {
+    pub fn load(paths: &[String]) -> io::Result<Self> {
+        let logs = paths
+            .iter()
+            .map(|path| {
+                let text = std::fs::read_to_string(path)?;
+                Ok((path.clone(), LogSummary::from_log(&text)))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(PerfReport { logs })
+    }
+}
+
+impl fmt::Display for PerfReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // With how many decimals each is shown
+        type Row = (&'static str, usize, fn(&LogSummary) -> Option<f64>);
+        let rows: [Row; 6] = [
+            ("sweeps", 0, |log| Some(log.sweeps as f64)),
+            ("total seconds", 3, |log| Some(log.seconds)),
+            ("median sweep seconds", 3, |log| log.median_sweep_seconds),
+            ("median Mray/s", 3, |log| log.median_mrays_per_second),
+            ("median traced Mray/s", 3, |log| {
+                log.median_traced_mrays_per_second
+            }),
+            ("final converged", 3, |log| log.final_converged_fraction),
+        ];
+        let header = |log: &LogSummary, describe: fn(&SessionHeader) -> String| {
+            log.header.as_ref().map_or("?".to_string(), describe)
+        };
+        type TextRow = (&'static str, fn(&SessionHeader) -> String);
+        let text_rows: [TextRow; 5] = [
+            ("kind", |header| header.kind.clone()),
+            ("resolution", |header| {
+                format!("{}x{}", header.width, header.height)
+            }),
+            ("threads / cores", |header| {
+                format!("{} / {}", header.threads, header.cores)
+            }),
+            ("integrator", |header| header.integrator.clone()),
+            ("version", |header| match &header.git_hash {
+                Some(hash) => format!("{} ({})", header.version, hash),
+                None => header.version.clone(),
+            }),
+        ];
+
+        let width = 24;
+        write!(f, "{:<22}", "")?;
+        for (path, _) in &self.logs {
+            write!(f, "{:>width$}", shorten(path, width - 2))?;
+        }
+        writeln!(f)?;
+        for (name, describe) in text_rows {
+            write!(f, "{:<22}", name)?;
+            for (_, log) in &self.logs {
+                write!(f, "{:>width$}", header(log, describe))?;
+            }
+            writeln!(f)?;
+        }
+        let base = self.logs.first().map(|(_, log)| log);
+        for (name, decimals, value) in rows {
+            write!(f, "{:<22}", name)?;
+            for (i, (_, log)) in self.logs.iter().enumerate() {
+                let cell = match (value(log), base.and_then(value)) {
+                    (Some(value), Some(base)) if i > 0 && base != 0.0 => {
+                        format!(
+                            "{:.*} ({:+.1}%)",
+                            decimals,
+                            value,
+                            (value / base - 1.0) * 100.0
+                        )
+                    }
+                    (Some(value), _) => format!("{:.*}", decimals, value),
+                    (None, _) => "-".to_string(),
+                };
+                write!(f, "{:>width$}", cell)?;
+            }
+            writeln!(f)?;
+        }
+        for (path, log) in &self.logs {
+            if log.skipped_lines > 0 {
+                writeln!(
+                    f,
+                    "Skipped {} unreadable lines of {}",
+                    log.skipped_lines, path
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The end of `path`, so long paths still fit a column
+fn shorten(path: &str, max: usize) -> String {
+    let count = path.chars().count();
+    if count <= max {
+        path.to_string()
+    } else {
+        let tail: String = path.chars().skip(count - (max - 1)).collect();
+        format!("…{}", tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sweep line with just the fields every version has written
+    fn sweep(render: usize, seconds: f64, camera_rays: u64) -> String {
+        SweepRecord {
+            render,
+            sweep: 1,
+            samples_added: 1,
+            total_samples: 1,
+            seconds,
+            camera_rays,
+            ..SweepRecord::default()
+        }
+        .to_json()
+    }
+
+    fn header() -> SessionHeader {
+        SessionHeader {
+            kind: "batch".to_string(),
+            scene_fingerprint: 0x0123_4567_89ab_cdef,
+            job_fingerprint: Some(42),
+            width: 64,
+            height: 48,
+            samples_per_pixel: Some(16),
+            max_depth: 8,
+            integrator: "path".to_string(),
+            fidelity: "final".to_string(),
+            threads: 4,
+            cores: 8,
+            version: "0.1.0".to_string(),
+            git_hash: Some("abc123".to_string()),
+            started: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn medians_of_odd_and_even_counts_skip_nan() {
+        assert_eq!(median(&mut vec![]), None);
+        assert_eq!(median(&mut vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(&mut vec![4.0, 1.0, 3.0, 2.0]), Some(2.5));
+        assert_eq!(median(&mut vec![f64::NAN, 5.0, 1.0, 9.0]), Some(5.0));
+        assert_eq!(median(&mut vec![f64::NAN]), None);
+    }
+
+    #[test]
+    fn summaries_take_the_medians_of_the_sweeps() {
+        let mut log = header().to_json();
+        // Sweeps of 1, 2, 4, 8 and 16 seconds, each of a million rays
+        for seconds in [4.0, 1.0, 16.0, 2.0, 8.0] {
+            log.push('\n');
+            log.push_str(&sweep(0, seconds, 1_000_000));
+        }
+        let summary = LogSummary::from_log(&log);
+        assert_eq!(summary.header, Some(header()));
+        assert_eq!(summary.sweeps, 5);
+        assert_eq!(summary.seconds, 31.0);
+        assert_eq!(summary.median_sweep_seconds, Some(4.0));
+        assert_eq!(summary.median_mrays_per_second, Some(0.25));
+        assert_eq!(summary.median_traced_mrays_per_second, None);
+        assert_eq!(summary.final_converged_fraction, None);
+        assert_eq!(summary.skipped_lines, 0);
+    }
+
+    #[test]
+    fn missing_fields_are_left_out_rather_than_guessed() {
+        let log = [
+            // An older header with nothing but its type and kind
+            r#"{"type": "session", "kind": "preview"}"#.to_string(),
+            // No rate written, so it's worked out from the rays and seconds
+            r#"{"type": "sweep", "seconds": 2, "camera_rays": 6000000}"#.to_string(),
+            // No seconds, so only its written rate counts
+            r#"{"type": "sweep", "camera_rays": 1000000, "mrays_per_second": 5}"#.to_string(),
+            // Neither, so it only counts as a sweep
+            r#"{"type": "sweep"}"#.to_string(),
+            // A type from a later version
+            r#"{"type": "gpu_batch", "seconds": 100}"#.to_string(),
+            SweepRecord {
+                seconds: 4.0,
+                camera_rays: 4_000_000,
+                traced_rays: Some(20_000_000),
+                converged_fraction: Some(0.75),
+                ..SweepRecord::default()
+            }
+            .to_json(),
+            // Cut off by a killed render
+            r#"{"type": "sweep", "seconds": 3, "cam"#.to_string(),
+        ]
+        .join("\n");
+        let summary = LogSummary::from_log(&log);
+        let header = summary.header.as_ref().unwrap();
+        assert_eq!(header.kind, "preview");
+        assert_eq!(header.width, 0);
+        assert_eq!(header.job_fingerprint, None);
+        assert_eq!(header.git_hash, None);
+        assert_eq!(summary.sweeps, 4);
+        assert_eq!(summary.seconds, 6.0);
+        assert_eq!(summary.median_sweep_seconds, Some(3.0));
+        // 3, 5 and 1 million rays a second
+        assert_eq!(summary.median_mrays_per_second, Some(3.0));
+        assert_eq!(summary.median_traced_mrays_per_second, Some(5.0));
+        assert_eq!(summary.final_converged_fraction, Some(0.75));
+        assert_eq!(summary.skipped_lines, 1);
+    }
+
+    #[test]
+    fn headers_read_back_the_way_they_were_written() {
+        let fields = parse_object(&header().to_json()).unwrap();
+        assert_eq!(SessionHeader::from_fields(&fields), header());
+        let record = SweepRecord {
+            seconds: f64::INFINITY,
+            ..SweepRecord::default()
+        };
+        let fields = parse_object(&record.to_json()).unwrap();
+        assert_eq!(fields["seconds"], JsonValue::Null);
+    }
+
+    #[test]
+    fn reports_show_each_log_against_the_first() {
+        let log = |seconds: f64| {
+            let sweeps = (0..3).map(|_| sweep(0, seconds, 2_000_000));
+            std::iter::once(header().to_json())
+                .chain(sweeps)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let report = PerfReport {
+            logs: vec![
+                ("before.jsonl".to_string(), LogSummary::from_log(&log(2.0))),
+                ("after.jsonl".to_string(), LogSummary::from_log(&log(1.0))),
+            ],
+        };
+        let text = report.to_string();
+        let row = |name: &str| {
+            text.lines()
+                .find(|line| line.starts_with(name))
+                .unwrap()
+                .to_string()
+        };
+        assert!(
+            row("median Mray/s").ends_with("2.000 (+100.0%)"),
+            "{}",
+            text
+        );
+        assert!(row("total seconds").ends_with("3.000 (-50.0%)"), "{}", text);
+        assert!(row("median traced Mray/s").ends_with("-"), "{}", text);
+        assert!(!text.contains("Skipped"));
+    }
+}
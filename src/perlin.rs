@@ -0,0 +1,164 @@
+//! Perlin noise, as in Ray Tracing: The Next Week: random gradients on a lattice, smoothly
+//! interpolated between, and sums of octaves of it for turbulence and fractal Brownian motion
+
+use crate::{
+    camera::Float,
+    vec3::{Point3, Vec3, Vec3Ext},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Lattice points along each axis before the noise repeats
+const POINT_COUNT: usize = 256;
+
+/// How much each octave's weight shrinks by as its frequency doubles
+const GAIN: Float = 0.5;
+
+/// The gradients and the shuffles that hash lattice points to them, all picked from a seed
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    /// Noise picked by `seed`, the same for the same seed on every run
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::random_unit(&mut rng))
+            .collect();
+        let mut permutation = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+        Perlin {
+            perm_x: permutation(),
+            perm_y: permutation(),
+            perm_z: permutation(),
+            gradients,
+        }
+    }
+
+    /// The noise at `point`, from about -1 to 1, trilinearly interpolating the gradients of the
+    /// eight lattice points around it with a Hermite curve so there are no creases along the
+    /// lattice
+    pub fn noise(&self, point: Point3) -> Float {
+        let floor = point.map(Float::floor);
+        let fraction = point - floor;
+        let (i, j, k) = (floor.x as i64, floor.y as i64, floor.z as i64);
+        let wrap = |n: i64| (n & (POINT_COUNT as i64 - 1)) as usize;
+        let smooth = fraction.map(|t| t * t * (3.0 - 2.0 * t));
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let hash = self.perm_x[wrap(i + di)]
+                        ^ self.perm_y[wrap(j + dj)]
+                        ^ self.perm_z[wrap(k + dk)];
+                    let corner = Vec3::new(di as Float, dj as Float, dk as Float);
+                    let weight = corner.zip_map(&smooth, |c, s| c * s + (1.0 - c) * (1.0 - s));
+                    let offset = fraction - corner;
+                    sum += weight.product() * self.gradients[hash].dot(&offset);
+                }
+            }
+        }
+        sum
+    }
+
+    /// Fractal Brownian motion: `octaves` of noise, each at twice the frequency and half the
+    /// weight of the last, summed and divided by the weights so it stays from about -1 to 1
+    pub fn fbm(&self, point: Point3, octaves: usize) -> Float {
+        self.octaves(point, octaves, |noise| noise)
+    }
+
+    /// Like [`Perlin::fbm`] summing each octave's magnitude, so from 0 to about a half, with
+    /// creases where the noise crosses zero
+    pub fn turbulence(&self, point: Point3, octaves: usize) -> Float {
+        self.octaves(point, octaves, Float::abs)
+    }
+
+    fn octaves(&self, point: Point3, octaves: usize, shape: impl Fn(Float) -> Float) -> Float {
+        let (mut sum, mut total, mut weight, mut point) = (0.0, 0.0, 1.0, point);
+        for _ in 0..octaves.max(1) {
+            sum += weight * shape(self.noise(point));
+            total += weight;
+            weight *= GAIN;
+            point *= 2.0;
+        }
+        sum / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    /// Points scattered over a few dozen lattice cells, off the lattice and on it
+    fn points(count: usize) -> Vec<Point3> {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut points: Vec<Point3> = (0..count)
+            .map(|_| Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 40.0 - Vec3::repeat(20.0))
+            .collect();
+        points.extend((0..64).map(|i| Vec3::new(i as Float, -(i as Float), 0.5)));
+        points
+    }
+
+    #[test]
+    fn noise_stays_within_one() {
+        let perlin = Perlin::new(1);
+        for point in points(10_000) {
+            for value in [
+                perlin.noise(point),
+                perlin.fbm(point, 1),
+                perlin.fbm(point, 7),
+            ] {
+                assert!(value.abs() <= 1.0, "{} at {:?}", value, point);
+            }
+            let turbulence = perlin.turbulence(point, 7);
+            assert!((0.0..=1.0).contains(&turbulence), "{}", turbulence);
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_noise() {
+        let (a, b, other) = (Perlin::new(4), Perlin::new(4), Perlin::new(5));
+        let points = points(1000);
+        for point in &points {
+            assert_eq!(a.noise(*point), b.noise(*point));
+            assert_eq!(a.turbulence(*point, 5), b.turbulence(*point, 5));
+        }
+        assert!(points
+            .iter()
+            .any(|point| a.noise(*point) != other.noise(*point)));
+    }
+
+    #[test]
+    fn noise_is_zero_on_the_lattice_and_smooth_between() {
+        let perlin = Perlin::new(2);
+        for i in -3..3 {
+            let corner = Vec3::new(i as Float, 2.0 * i as Float, -7.0);
+            assert_eq!(perlin.noise(corner), 0.0);
+        }
+        // No jumps crossing a lattice plane
+        let step = 1e-7;
+        for point in points(200) {
+            let across = Vec3::new(point.x.round(), point.y, point.z);
+            let jump =
+                perlin.noise(across + Vec3::x() * step) - perlin.noise(across - Vec3::x() * step);
+            assert!(jump.abs() < 1e-5, "{} at {:?}", jump, across);
+        }
+    }
+
+    #[test]
+    fn noise_repeats_every_lattice_period() {
+        let perlin = Perlin::new(3);
+        for point in points(200) {
+            let shifted = point + Vec3::new(0.0, POINT_COUNT as Float, -(POINT_COUNT as Float));
+            assert!((perlin.noise(point) - perlin.noise(shifted)).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use crate::{
+    bracket::ev_label,
+    camera::{Float, Image},
+    tonemap::Tonemap,
+    validation::{self, Stage},
+    vec3::Vec3,
+};
+use rayon::prelude::*;
+use std::f64::consts::TAU;
+
+/// Seeds the fixed-pattern noise, which has to be the same in every frame
+const FIXED_PATTERN_SEED: u64 = 0x5eed_f1c5_ed00_0001;
+
+/// Where film grain is added relative to the output tonemap
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum GrainStage {
+    /// Adds noise to linear values like a real sensor does, before the tonemap compresses it
+    #[default]
+    BeforeTonemap,
+    /// Adds noise to the final display values for a stylized, evenly grainy look
+    AfterTonemap,
+}
+
+impl GrainStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GrainStage::BeforeTonemap => "before",
+            GrainStage::AfterTonemap => "after",
+        }
+    }
+
+    /// The inverse of [`GrainStage::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "before" => Some(GrainStage::BeforeTonemap),
+            "after" => Some(GrainStage::AfterTonemap),
+            _ => None,
+        }
+    }
+}
+
+/// Simulated camera sensor noise, for matching renders to filmed footage.
+///
+/// Each channel of a pixel with value `v` gets noise with a variance of
+/// `iso / 100 * (read_noise² + shot_noise * v + (fixed_pattern * v)²)`, so the variance grows
+/// linearly with the ISO and an ISO of 0.0 leaves the image untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilmGrain {
+    /// Simulated sensitivity, where 100.0 is the base the other settings are given at
+    pub iso: Float,
+    /// Standard deviation of the noise every pixel gets regardless of its brightness
+    pub read_noise: Float,
+    /// Variance of the photon shot noise per unit of pixel value
+    pub shot_noise: Float,
+    /// Standard deviation of each pixel's gain error, which stays the same from frame to frame
+    pub fixed_pattern: Float,
+    pub stage: GrainStage,
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        FilmGrain {
+            iso: 800.0,
+            read_noise: 0.002,
+            shot_noise: 0.0005,
+            fixed_pattern: 0.002,
+            stage: GrainStage::default(),
+        }
+    }
+}
+
+/// Tints of successive ghosts, like the colored reflections between coated lens elements
+const GHOST_TINTS: [Vec3; 4] = [
+    Vec3::new(1.0, 0.8, 0.5),
+    Vec3::new(0.5, 0.9, 1.0),
+    Vec3::new(0.8, 0.5, 1.0),
+    Vec3::new(0.6, 1.0, 0.6),
+];
+
+/// Lens flare from bright sources in frame: ghosts reflected along the line through the
+/// image's center, and a starburst of streaks around each source. Only the part of each pixel
+/// above `threshold` makes flare, so it's driven by real light sources like the sun.
+///
+/// Ghost `k` (counting from 1) of a source at `p` lands at `center + (p - center) * (1 - k *
+/// spacing)`, so ghosts march toward the center and past it to the other side, with
+/// `intensity * falloff^(k - 1)` of the source's brightness and the `k`th tint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensFlare {
+    /// Linear value a channel has to go over to make flare
+    pub threshold: Float,
+    pub ghost_count: usize,
+    /// How far apart ghosts are, as a fraction of the source's distance from the center
+    pub ghost_spacing: Float,
+    /// Brightness of the first ghost relative to the source
+    pub intensity: Float,
+    /// How much dimmer each ghost is than the one before
+    pub ghost_falloff: Float,
+    /// Number of lines through each source making up the starburst, with two streaks each
+    pub streak_count: usize,
+    /// In pixels
+    pub streak_length: usize,
+    /// Brightness of the streaks next to the source, relative to it. Fades out linearly.
+    pub streak_intensity: Float,
+}
+
+impl Default for LensFlare {
+    fn default() -> Self {
+        LensFlare {
+            threshold: 4.0,
+            ghost_count: 4,
+            ghost_spacing: 0.5,
+            intensity: 0.02,
+            ghost_falloff: 0.6,
+            streak_count: 3,
+            streak_length: 24,
+            streak_intensity: 0.01,
+        }
+    }
+}
+
+impl LensFlare {
+    /// Returns the light the flare adds to each pixel of a `width` by `height` image with
+    /// `color` as its linear colors, in rows. A pure function of the colors, so in progressive
+    /// renders it follows the converged bright spots rather than each sweep's noise.
+    pub fn light(
+        &self,
+        width: usize,
+        height: usize,
+        color: impl Fn(usize, usize) -> Vec3,
+    ) -> Vec<Vec3> {
+        let mut light = vec![Vec3::zeros(); width * height];
+        let mut add = |x: Float, y: Float, value: Vec3| {
+            if x >= 0.0 && y >= 0.0 && (x as usize) < width && (y as usize) < height {
+                light[y as usize * width + x as usize] += value;
+            }
+        };
+        let center = (width as Float / 2.0, height as Float / 2.0);
+        let streak_directions: Vec<(Float, Float)> = (0..self.streak_count * 2)
+            .map(|i| {
+                let angle = std::f64::consts::PI * i as Float / self.streak_count as Float;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        for y in 0..height {
+            for x in 0..width {
+                let excess = color(x, y).map(|c| (c - self.threshold).max(0.0));
+                if excess == Vec3::zeros() {
+                    continue;
+                }
+                // From the pixel's center
+                let offset = (x as Float + 0.5 - center.0, y as Float + 0.5 - center.1);
+                let mut strength = self.intensity;
+                for k in 1..=self.ghost_count {
+                    let scale = 1.0 - k as Float * self.ghost_spacing;
+                    let tint = GHOST_TINTS[(k - 1) % GHOST_TINTS.len()];
+                    add(
+                        center.0 + offset.0 * scale,
+                        center.1 + offset.1 * scale,
+                        excess.component_mul(&tint) * strength,
+                    );
+                    strength *= self.ghost_falloff;
+                }
+                for &(dx, dy) in &streak_directions {
+                    for step in 1..=self.streak_length {
+                        let fade = 1.0 - (step - 1) as Float / self.streak_length as Float;
+                        let (sx, sy) = (
+                            x as Float + 0.5 + dx * step as Float,
+                            y as Float + 0.5 + dy * step as Float,
+                        );
+                        add(sx, sy, excess * (self.streak_intensity * fade));
+                    }
+                }
+            }
+        }
+        light
+    }
+}
+
+/// Display processing applied to a copy of a finished image right before it's written out,
+/// so none of it ever ends up in accumulated samples. Off by default.
+///
+/// Grain is scaled by an image's alpha, so see-through pixels stay clean for compositing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostProcess {
+    /// In stops: the linear colors are multiplied by `2^exposure` before grain and the tonemap
+    pub exposure: Float,
+    /// Display transform for the pixels, or `None` to write linear values as they are
+    pub tonemap: Option<Tonemap>,
+    pub grain: Option<FilmGrain>,
+    /// Added to the linear image before grain and the tonemap
+    pub flare: Option<LensFlare>,
+    /// Seeds the grain so each frame of an animation gets its own noise, and re-rendering a
+    /// frame reproduces it exactly
+    pub frame: u64,
+}
+
+/// SplitMix64, used to hash pixel coordinates into independent random streams
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Returns a standard normal random number determined entirely by `seed`
+fn gaussian(seed: u64) -> Float {
+    let a = mix(seed);
+    let b = mix(a);
+    // Uniforms in (0.0, 1.0], so the log is finite
+    let u1 = ((a >> 11) + 1) as Float / (1u64 << 53) as Float;
+    let u2 = (b >> 11) as Float / (1u64 << 53) as Float;
+    (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+}
+
+impl FilmGrain {
+    /// Returns `color` with the grain for pixel `(x, y)` of `frame` added, scaled by the pixel's
+    /// `coverage`
+    fn apply(&self, color: Vec3, x: usize, y: usize, frame: u64, coverage: Float) -> Vec3 {
+        let gain = self.iso / 100.0;
+        let pixel_seed = mix(mix(x as u64) ^ y as u64);
+        let frame_seed = mix(frame);
+        Vec3::from_fn(|channel, _| {
+            let value = color[channel];
+            let seed = pixel_seed ^ mix(channel as u64);
+            let temporal = (gain
+                * (self.read_noise * self.read_noise + self.shot_noise * value.max(0.0)))
+            .sqrt()
+                * gaussian(seed ^ frame_seed);
+            let fixed =
+                gain.sqrt() * self.fixed_pattern * value * gaussian(seed ^ FIXED_PATTERN_SEED);
+            value + (temporal + fixed) * coverage
+        })
+    }
+}
+
+impl PostProcess {
+    /// Returns whether `apply` would change anything
+    pub fn is_enabled(&self) -> bool {
+        self.exposure != 0.0
+            || self.tonemap.is_some()
+            || self.grain.as_ref().is_some_and(|grain| grain.iso > 0.0)
+            || self.flare.is_some()
+    }
+
+    /// Returns a processed copy of `image`, leaving the original alone
+    pub fn apply(&self, image: &Image) -> Image {
+        let flare = self
+            .flare
+            .as_ref()
+            .map(|flare| flare.light(image.width, image.height, |x, y| image[(x, y)]));
+        let pixels = image
+            .pixels
+            .par_iter()
+            .enumerate()
+            .map(|(i, &color)| {
+                let mut color = color;
+                if let Some(flare) = &flare {
+                    color += flare[i];
+                }
+                let coverage = image.alpha.as_ref().map_or(1.0, |alpha| alpha[i]);
+                self.apply_covered_pixel(color, i % image.width, i / image.width, coverage)
+            })
+            .collect();
+
+        let mut metadata = image.metadata.clone();
+        metadata.extend(self.metadata());
+        Image {
+            pixels,
+            width: image.width,
+            height: image.height,
+            gamma: image.gamma,
+            metadata,
+            alpha: image.alpha.clone(),
+        }
+    }
+
+    /// Applies everything but the flare to the color of pixel `(x, y)`. Each pixel only depends
+    /// on itself, so an image can be processed a piece at a time.
+    pub fn apply_pixel(&self, color: Vec3, x: usize, y: usize) -> Vec3 {
+        self.apply_covered_pixel(color, x, y, 1.0)
+    }
+
+    /// Like [`PostProcess::apply_pixel`] for a pixel with an alpha of `coverage`
+    fn apply_covered_pixel(&self, color: Vec3, x: usize, y: usize, coverage: Float) -> Vec3 {
+        validation::check_at(Stage::PresentationInput, &color, Some((x, y)));
+        let grain = self.grain.as_ref().filter(|grain| grain.iso > 0.0);
+        let add_grain = |color: Vec3, stage: GrainStage| match grain {
+            Some(grain) if grain.stage == stage => grain.apply(color, x, y, self.frame, coverage),
+            _ => color,
+        };
+        let mut color = add_grain(color * self.exposure_scale(), GrainStage::BeforeTonemap);
+        if let Some(tonemap) = &self.tonemap {
+            color = tonemap.apply(color);
+        }
+        add_grain(color, GrainStage::AfterTonemap)
+    }
+
+    /// What the linear colors are multiplied by for the exposure
+    pub fn exposure_scale(&self) -> Float {
+        self.exposure.exp2()
+    }
+
+    /// Lines describing the processing, for an image's metadata
+    pub fn metadata(&self) -> Vec<String> {
+        let grain = self.grain.as_ref().filter(|grain| grain.iso > 0.0);
+        let mut metadata = Vec::new();
+        if self.exposure != 0.0 {
+            metadata.push(format!("exposure: {} EV", ev_label(self.exposure)));
+        }
+        if let Some(tonemap) = &self.tonemap {
+            metadata.push(format!("output tonemap: {}", tonemap.name()));
+        }
+        if let Some(grain) = grain {
+            metadata.push(format!(
+                "film grain: iso {}, read noise {}, shot noise {}, fixed pattern {}, {} tonemap",
+                grain.iso,
+                grain.read_noise,
+                grain.shot_noise,
+                grain.fixed_pattern,
+                grain.stage.name()
+            ));
+            metadata.push(format!("frame: {}", self.frame));
+        }
+        if let Some(flare) = &self.flare {
+            metadata.push(format!(
+                "lens flare: threshold {}, {} ghosts {} apart at {} falling off by {}, {} streaks {} long at {}",
+                flare.threshold,
+                flare.ghost_count,
+                flare.ghost_spacing,
+                flare.intensity,
+                flare.ghost_falloff,
+                flare.streak_count,
+                flare.streak_length,
+                flare.streak_intensity
+            ));
+        }
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 64;
+
+    /// A uniform patch of `value`
+    fn grey(value: Float) -> Image {
+        Image {
+            pixels: vec![Vec3::new(value, value, value); SIZE * SIZE],
+            width: SIZE,
+            height: SIZE,
+            gamma: 2.2,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+
+    fn grainy(iso: Float, frame: u64) -> PostProcess {
+        PostProcess {
+            grain: Some(FilmGrain {
+                iso,
+                ..FilmGrain::default()
+            }),
+            frame,
+            ..PostProcess::default()
+        }
+    }
+
+    /// The noise added to each channel of each pixel of `image`
+    fn noise(post_process: &PostProcess, image: &Image) -> Vec<Float> {
+        let processed = post_process.apply(image);
+        processed
+            .pixels
+            .iter()
+            .zip(&image.pixels)
+            .flat_map(|(after, before)| (after - before).iter().copied().collect::<Vec<_>>())
+            .collect()
+    }
+
+    fn variance(values: &[Float]) -> Float {
+        let mean = values.iter().sum::<Float>() / values.len() as Float;
+        values.iter().map(|v| (v - mean).powi(2)).sum::<Float>() / values.len() as Float
+    }
+
+    #[test]
+    fn zero_iso_leaves_images_untouched() {
+        let image = grey(0.18);
+        let post_process = grainy(0.0, 3);
+        assert!(!post_process.is_enabled());
+        assert_eq!(post_process.apply(&image).pixels, image.pixels);
+    }
+
+    #[test]
+    fn grain_variance_follows_the_iso() {
+        let image = grey(0.18);
+        let base = variance(&noise(&grainy(100.0, 1), &image));
+        let grain = FilmGrain::default();
+        let expected = grain.read_noise.powi(2)
+            + grain.shot_noise * 0.18
+            + (grain.fixed_pattern * 0.18).powi(2);
+        assert!(
+            (base / expected - 1.0).abs() < 0.1,
+            "{} vs {}",
+            base,
+            expected
+        );
+        let ratio = variance(&noise(&grainy(400.0, 1), &image)) / base;
+        assert!((ratio - 4.0).abs() < 0.4, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn frames_repeat_exactly_and_differ_from_each_other() {
+        let image = grey(0.5);
+        let mut post_process = grainy(800.0, 7);
+        post_process.grain.as_mut().unwrap().fixed_pattern = 0.0;
+        let first = noise(&post_process, &image);
+        assert_eq!(first, noise(&post_process, &image));
+
+        post_process.frame = 8;
+        let second = noise(&post_process, &image);
+        let covariance =
+            first.iter().zip(&second).map(|(a, b)| a * b).sum::<Float>() / first.len() as Float;
+        let correlation = covariance / (variance(&first) * variance(&second)).sqrt();
+        assert!(correlation.abs() < 0.05, "correlation {}", correlation);
+    }
+
+    #[test]
+    fn fixed_pattern_stays_put_between_frames() {
+        let image = grey(0.5);
+        let mut post_process = grainy(800.0, 1);
+        let grain = post_process.grain.as_mut().unwrap();
+        (grain.read_noise, grain.shot_noise) = (0.0, 0.0);
+        let first = noise(&post_process, &image);
+        post_process.frame = 2;
+        assert_eq!(first, noise(&post_process, &image));
+        assert!(variance(&first) > 0.0);
+    }
+
+    #[test]
+    fn see_through_pixels_stay_clean() {
+        let mut image = grey(0.5);
+        image.alpha = Some((0..SIZE * SIZE).map(|i| (i % 2) as Float).collect());
+        let processed = grainy(800.0, 1).apply(&image);
+        for (i, (after, before)) in processed.pixels.iter().zip(&image.pixels).enumerate() {
+            assert_eq!(after == before, i % 2 == 0, "pixel {}", i);
+        }
+    }
+
+    /// A black `width` by `height` image with one pixel at `value`
+    fn hot_pixel(width: usize, height: usize, x: usize, y: usize, value: Float) -> Image {
+        let mut pixels = vec![Vec3::zeros(); width * height];
+        pixels[y * width + x] = Vec3::repeat(value);
+        Image {
+            pixels,
+            width,
+            height,
+            gamma: 2.2,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+
+    #[test]
+    fn ghosts_land_on_the_line_through_the_center() {
+        let (width, height) = (64, 48);
+        let image = hot_pixel(width, height, 10, 8, 100.0);
+        let flare = LensFlare {
+            streak_count: 0,
+            ..LensFlare::default()
+        };
+        let light = flare.light(width, height, |x, y| image[(x, y)]);
+
+        // From the pixel's center at (10.5, 8.5), the image's center at (32, 24) is
+        // (21.5, 15.5) away, so the ghosts are half that apart, through the center to the
+        // mirror image of the source
+        let excess = Vec3::repeat(100.0 - flare.threshold);
+        let ghosts = [(21, 16), (32, 24), (42, 31), (53, 39)];
+        let mut strength = flare.intensity;
+        for (k, &(x, y)) in ghosts.iter().enumerate() {
+            let expected = excess.component_mul(&GHOST_TINTS[k]) * strength;
+            assert!(
+                (light[y * width + x] - expected).norm() < 1e-9,
+                "ghost {} at ({}, {}): {:?}",
+                k + 1,
+                x,
+                y,
+                light[y * width + x]
+            );
+            strength *= flare.ghost_falloff;
+        }
+        let lit = light.iter().filter(|l| **l != Vec3::zeros()).count();
+        assert_eq!(lit, ghosts.len());
+    }
+
+    #[test]
+    fn streaks_fade_out_from_the_source() {
+        let (width, height) = (64, 48);
+        let image = hot_pixel(width, height, 30, 20, 14.0);
+        let flare = LensFlare {
+            ghost_count: 0,
+            streak_count: 2,
+            streak_length: 8,
+            ..LensFlare::default()
+        };
+        let light = flare.light(width, height, |x, y| image[(x, y)]);
+        let excess = 14.0 - flare.threshold;
+        // Two lines make four streaks, along the axes from the pixel's center
+        for step in 1..=8 {
+            let fade = 1.0 - (step - 1) as Float / 8.0;
+            let expected = excess * flare.streak_intensity * fade;
+            for (x, y) in [
+                (30 + step, 20),
+                (30, 20 + step),
+                (30 - step, 20),
+                (30, 20 - step),
+            ] {
+                let l = light[y * width + x];
+                assert!((l.x - expected).abs() < 1e-9, "({}, {}): {:?}", x, y, l);
+            }
+        }
+        assert_eq!(light[20 * width + 39], Vec3::zeros());
+        assert_eq!(light[20 * width + 30], Vec3::zeros());
+    }
+
+    #[test]
+    fn disabled_flare_leaves_images_bit_exact() {
+        let image = hot_pixel(64, 48, 10, 8, 100.0);
+        let post_process = PostProcess::default();
+        assert!(!post_process.is_enabled());
+        let same = |a: &Image, b: &Image| {
+            a.pixels.iter().zip(&b.pixels).all(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .all(|(a, b)| a.to_bits() == b.to_bits())
+            })
+        };
+        assert!(same(&post_process.apply(&image), &image));
+        assert_eq!(post_process.apply(&image).encode_ppm(), image.encode_ppm());
+
+        // Nothing over the threshold makes no flare either
+        let dim = PostProcess {
+            flare: Some(LensFlare {
+                threshold: 100.0,
+                ..LensFlare::default()
+            }),
+            ..PostProcess::default()
+        };
+        assert!(same(&dim.apply(&image), &image));
+    }
+}
@@ -0,0 +1,391 @@
+//! Showing the preview's frames, and carrying on when the window's surface is lost, like after
+//! a suspend or a GPU switch, rather than abandoning a long accumulation
+
+use pixels::{Pixels, SurfaceTexture};
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use winit::window::Window;
+
+/// Attempts at recreating a lost surface before giving up on showing frames
+pub const MAX_RECOVERY_ATTEMPTS: usize = 5;
+/// How long to wait before the first attempt at recreating a lost surface, doubling after each
+/// one that fails
+pub const FIRST_BACKOFF: Duration = Duration::from_millis(250);
+/// How often a preview that's given up on its surface saves what it's rendered so far
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// What the preview shows its frames through, an RGBA buffer scaled onto a surface
+pub trait Presenter {
+    /// The RGBA buffer the next frame is drawn into
+    fn frame_mut(&mut self) -> &mut [u8];
+
+    /// Shows the buffer on the surface
+    fn present(&mut self) -> Result<(), String>;
+
+    /// Fits the surface to a window that's now `width` by `height` physical pixels
+    fn resize_surface(&mut self, width: u32, height: u32) -> Result<(), String>;
+
+    /// Makes the buffer `width` by `height` pixels
+    fn resize_buffer(&mut self, width: u32, height: u32) -> Result<(), String>;
+
+    /// Throws the surface away and makes a new one, keeping the buffer's size and contents
+    fn recreate(&mut self) -> Result<(), String>;
+}
+
+/// Presents through [`Pixels`] onto a window
+pub struct PixelsPresenter {
+    pixels: Pixels,
+    window: Rc<Window>,
+    /// Size of the buffer, which a recreated surface has to be given again
+    width: u32,
+    height: u32,
+}
+
+impl PixelsPresenter {
+    pub fn new(window: Rc<Window>, width: u32, height: u32) -> Result<Self, pixels::Error> {
+        Ok(PixelsPresenter {
+            pixels: Self::surface(&window, width, height)?,
+            window,
+            width,
+            height,
+        })
+    }
+
+    fn surface(window: &Window, width: u32, height: u32) -> Result<Pixels, pixels::Error> {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, window);
+        Pixels::new(width, height, surface_texture)
+    }
+
+    /// The buffer pixel under `position` in the window, or the closest one to it
+    pub fn pixel_at(&self, position: (f32, f32)) -> (usize, usize) {
+        self.pixels
+            .window_pos_to_pixel(position)
+            .unwrap_or_else(|outside| self.pixels.clamp_pixel_pos(outside))
+    }
+}
+
+impl Presenter for PixelsPresenter {
+    fn frame_mut(&mut self) -> &mut [u8] {
+        self.pixels.frame_mut()
+    }
+
+    fn present(&mut self) -> Result<(), String> {
+        self.pixels.render().map_err(|err| err.to_string())
+    }
+
+    fn resize_surface(&mut self, width: u32, height: u32) -> Result<(), String> {
+        self.pixels
+            .resize_surface(width, height)
+            .map_err(|err| err.to_string())
+    }
+
+    fn resize_buffer(&mut self, width: u32, height: u32) -> Result<(), String> {
+        self.pixels
+            .resize_buffer(width, height)
+            .map_err(|err| err.to_string())?;
+        (self.width, self.height) = (width, height);
+        Ok(())
+    }
+
+    fn recreate(&mut self) -> Result<(), String> {
+        let mut pixels =
+            Self::surface(&self.window, self.width, self.height).map_err(|err| err.to_string())?;
+        pixels.frame_mut().copy_from_slice(self.pixels.frame());
+        self.pixels = pixels;
+        Ok(())
+    }
+}
+
+/// Where a [`SurfaceGuard`]'s surface stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceState {
+    Healthy,
+    /// Lost, with `attempts` at recreating it failed so far and the next due at `retry_at`
+    Recovering {
+        attempts: usize,
+        retry_at: Instant,
+    },
+    /// Given up on, so frames aren't shown but the render carries on
+    Degraded,
+}
+
+/// What presenting a frame through a [`SurfaceGuard`] came to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presented {
+    Shown,
+    /// The surface failed just now
+    Lost,
+    /// The surface is lost and the next attempt at recreating it isn't due yet, or just failed
+    Waiting,
+    /// The surface was recreated and showed the frame
+    Recovered,
+    /// The last attempt at recreating the surface failed, so it's been given up on
+    GaveUp,
+    /// The surface was given up on before
+    Degraded,
+}
+
+/// Keeps presenting frames through a [`Presenter`] whose surface can be lost, recreating it a
+/// few times with growing waits in between before giving up on it. Everything it does is
+/// logged, since the window can't say.
+#[derive(Debug)]
+pub struct SurfaceGuard {
+    state: SurfaceState,
+    max_attempts: usize,
+    first_backoff: Duration,
+}
+
+impl Default for SurfaceGuard {
+    fn default() -> Self {
+        SurfaceGuard::new(MAX_RECOVERY_ATTEMPTS, FIRST_BACKOFF)
+    }
+}
+
+impl SurfaceGuard {
+    pub fn new(max_attempts: usize, first_backoff: Duration) -> Self {
+        SurfaceGuard {
+            state: SurfaceState::Healthy,
+            max_attempts,
+            first_backoff,
+        }
+    }
+
+    pub fn state(&self) -> SurfaceState {
+        self.state
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.state == SurfaceState::Degraded
+    }
+
+    /// Notes that using the surface failed with `err` at `now`, scheduling the first attempt at
+    /// recreating it unless it's already being recovered or given up on
+    pub fn lost(&mut self, err: &str, now: Instant) {
+        if self.state != SurfaceState::Healthy {
+            return;
+        }
+        println!(
+            "The preview's surface failed ({}), recreating it in {} ms",
+            err,
+            self.first_backoff.as_millis()
+        );
+        self.state = SurfaceState::Recovering {
+            attempts: 0,
+            retry_at: now + self.first_backoff,
+        };
+    }
+
+    /// Presents the frame through `presenter` at `now` if the surface is healthy, or makes the
+    /// next attempt at recreating it and presenting if one is due
+    pub fn present(&mut self, presenter: &mut impl Presenter, now: Instant) -> Presented {
+        match self.state {
+            SurfaceState::Healthy => match presenter.present() {
+                Ok(()) => Presented::Shown,
+                Err(err) => {
+                    self.lost(&err, now);
+                    Presented::Lost
+                }
+            },
+            SurfaceState::Recovering { retry_at, .. } if now < retry_at => Presented::Waiting,
+            SurfaceState::Recovering { attempts, .. } => {
+                let attempts = attempts + 1;
+                match presenter.recreate().and_then(|()| presenter.present()) {
+                    Ok(()) => {
+                        println!(
+                            "Recreated the preview's surface after {} attempt(s)",
+                            attempts
+                        );
+                        self.state = SurfaceState::Healthy;
+                        Presented::Recovered
+                    }
+                    Err(err) if attempts >= self.max_attempts => {
+                        println!(
+                            "Gave up on the preview's surface after {} attempts ({}). The render \
+                             carries on and is saved every {} minutes, and on closing the window",
+                            attempts,
+                            err,
+                            AUTOSAVE_INTERVAL.as_secs() / 60
+                        );
+                        self.state = SurfaceState::Degraded;
+                        Presented::GaveUp
+                    }
+                    Err(err) => {
+                        let backoff = self.first_backoff * 2u32.pow(attempts as u32);
+                        println!(
+                            "Recreating the preview's surface failed ({}), trying again in {} ms",
+                            err,
+                            backoff.as_millis()
+                        );
+                        self.state = SurfaceState::Recovering {
+                            attempts,
+                            retry_at: now + backoff,
+                        };
+                        Presented::Waiting
+                    }
+                }
+            }
+            SurfaceState::Degraded => Presented::Degraded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Presents into memory, failing the next few presents or recreations it's told to
+    #[derive(Default)]
+    struct FakePresenter {
+        frame: Vec<u8>,
+        shown: Option<Vec<u8>>,
+        failing_presents: Vec<&'static str>,
+        failing_recreates: usize,
+        recreates: usize,
+    }
+
+    impl Presenter for FakePresenter {
+        fn frame_mut(&mut self) -> &mut [u8] {
+            &mut self.frame
+        }
+
+        fn present(&mut self) -> Result<(), String> {
+            if let Some(err) = self.failing_presents.pop() {
+                return Err(err.into());
+            }
+            self.shown = Some(self.frame.clone());
+            Ok(())
+        }
+
+        fn resize_surface(&mut self, _width: u32, _height: u32) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn resize_buffer(&mut self, width: u32, height: u32) -> Result<(), String> {
+            self.frame = vec![0; width as usize * height as usize * 4];
+            Ok(())
+        }
+
+        fn recreate(&mut self) -> Result<(), String> {
+            self.recreates += 1;
+            if self.failing_recreates > 0 {
+                self.failing_recreates -= 1;
+                return Err("no adapter".into());
+            }
+            Ok(())
+        }
+    }
+
+    fn frame() -> Vec<u8> {
+        (0..64).collect()
+    }
+
+    #[test]
+    fn lost_and_outdated_surfaces_are_recreated_after_the_backoff() {
+        for err in [
+            "The underlying surface has been lost",
+            "The surface is outdated",
+        ] {
+            let backoff = Duration::from_millis(100);
+            let mut guard = SurfaceGuard::new(3, backoff);
+            let mut presenter = FakePresenter {
+                frame: frame(),
+                failing_presents: vec![err],
+                ..Default::default()
+            };
+            let start = Instant::now();
+
+            assert_eq!(guard.present(&mut presenter, start), Presented::Lost);
+            assert_eq!(
+                guard.state(),
+                SurfaceState::Recovering {
+                    attempts: 0,
+                    retry_at: start + backoff
+                }
+            );
+            let early = start + backoff / 2;
+            assert_eq!(guard.present(&mut presenter, early), Presented::Waiting);
+            assert_eq!(presenter.recreates, 0, "recreated before the backoff");
+
+            let due = start + backoff;
+            assert_eq!(guard.present(&mut presenter, due), Presented::Recovered);
+            assert_eq!(guard.state(), SurfaceState::Healthy);
+            assert_eq!(presenter.recreates, 1);
+            assert_eq!(presenter.shown, Some(frame()));
+            assert_eq!(guard.present(&mut presenter, due), Presented::Shown);
+        }
+    }
+
+    #[test]
+    fn repeated_failures_double_the_backoff_then_give_up() {
+        let backoff = Duration::from_millis(100);
+        let mut guard = SurfaceGuard::new(3, backoff);
+        let mut presenter = FakePresenter {
+            frame: frame(),
+            failing_presents: vec!["lost"],
+            failing_recreates: usize::MAX,
+            ..Default::default()
+        };
+        let mut now = Instant::now();
+
+        assert_eq!(guard.present(&mut presenter, now), Presented::Lost);
+        for attempts in 1..3 {
+            now += backoff * 2u32.pow(attempts as u32 - 1);
+            assert_eq!(guard.present(&mut presenter, now), Presented::Waiting);
+            assert_eq!(
+                guard.state(),
+                SurfaceState::Recovering {
+                    attempts,
+                    retry_at: now + backoff * 2u32.pow(attempts as u32)
+                }
+            );
+        }
+        now += backoff * 4;
+        assert_eq!(guard.present(&mut presenter, now), Presented::GaveUp);
+        assert!(guard.is_degraded());
+        assert_eq!(presenter.recreates, 3);
+
+        // Given up on for good, so nothing more is tried
+        now += Duration::from_secs(3600);
+        assert_eq!(guard.present(&mut presenter, now), Presented::Degraded);
+        assert_eq!(presenter.recreates, 3);
+        assert_eq!(presenter.shown, None);
+    }
+
+    #[test]
+    fn the_frame_survives_failed_presents() {
+        let backoff = Duration::from_millis(100);
+        let mut guard = SurfaceGuard::new(2, backoff);
+        let mut presenter = FakePresenter {
+            frame: frame(),
+            failing_presents: vec!["lost", "lost"],
+            failing_recreates: 1,
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        // A failed present, a failed recreation, then a recreation whose present fails too
+        assert_eq!(guard.present(&mut presenter, start), Presented::Lost);
+        assert_eq!(presenter.frame_mut(), frame().as_slice());
+        assert_eq!(
+            guard.present(&mut presenter, start + backoff),
+            Presented::Waiting
+        );
+        assert_eq!(presenter.frame_mut(), frame().as_slice());
+        assert_eq!(
+            guard.present(&mut presenter, start + backoff * 3),
+            Presented::GaveUp
+        );
+        assert_eq!(presenter.frame_mut(), frame().as_slice());
+
+        // Still there to be saved once the surface is given up on
+        assert_eq!(
+            guard.present(&mut presenter, start + backoff * 4),
+            Presented::Degraded
+        );
+        assert_eq!(presenter.frame_mut(), frame().as_slice());
+        assert_eq!(presenter.shown, None);
+    }
+}
@@ -0,0 +1,531 @@
+use crate::{
+    camera::{Camera, Float},
+    hittable::Shape,
+    material::Material,
+    vec3::{Point3, Ray, Vec3, Vec3Ext},
+};
+use bvh::aabb::{Aabb, Bounded};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Cells along the longest side of the proxy grid
+pub const PROXY_RESOLUTION: usize = 64;
+/// How far from the camera the proxy reaches, in multiples of its focus distance. Anything
+/// further is left out, so a huge ground plane doesn't stretch the grid out to nothing.
+const PROXY_REACH: Float = 8.0;
+/// Light that reaches proxy surfaces facing away from the key light
+pub(crate) const AMBIENT: Float = 0.35;
+/// Stands in for instances, whose prototypes can hold any number of materials
+pub(crate) const INSTANCE_ALBEDO: Float = 0.6;
+
+/// A cell a ray passes through, from [`ProxyGrid::traverse`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellCrossing {
+    pub cell: [usize; 3],
+    /// Distances along the ray where it enters and leaves the cell
+    pub enter: Float,
+    pub exit: Float,
+    /// The axis of the face the ray entered through, `None` if it started inside the cell
+    pub entry_axis: Option<usize>,
+}
+
+/// The cells a ray passes through, in order
+pub struct Traversal<'a> {
+    grid: &'a ProxyGrid,
+    direction: Vec3,
+    /// `None` once the ray has left the grid
+    state: Option<TraversalState>,
+}
+
+struct TraversalState {
+    cell: [i64; 3],
+    enter: Float,
+    entry_axis: Option<usize>,
+    /// Distance along the ray to the next cell boundary on each axis
+    next_boundary: [Float; 3],
+    /// Distance along the ray between cell boundaries on each axis
+    boundary_spacing: [Float; 3],
+    /// Where the ray leaves the grid
+    exit: Float,
+}
+
+impl Iterator for Traversal<'_> {
+    type Item = CellCrossing;
+
+    fn next(&mut self) -> Option<CellCrossing> {
+        let state = self.state.as_mut()?;
+        let axis = (0..3)
+            .min_by(|&a, &b| state.next_boundary[a].total_cmp(&state.next_boundary[b]))
+            .expect("there are three axes");
+        let crossing = CellCrossing {
+            cell: state.cell.map(|c| c as usize),
+            enter: state.enter,
+            exit: state.next_boundary[axis].min(state.exit),
+            entry_axis: state.entry_axis,
+        };
+        state.cell[axis] += if self.direction[axis] > 0.0 { 1 } else { -1 };
+        state.enter = state.next_boundary[axis];
+        state.next_boundary[axis] += state.boundary_spacing[axis];
+        state.entry_axis = Some(axis);
+        let outside = !(0..self.grid.dimensions[axis] as i64).contains(&state.cell[axis]);
+        if outside || state.enter > state.exit {
+            self.state = None;
+        }
+        Some(crossing)
+    }
+}
+
+/// A blocky stand-in for a scene that's cheap to build and to render without a BVH, for
+/// something to look at while the real one builds. Each cell of a coarse grid holds the average
+/// albedo of the shapes whose bounds overlap it.
+pub struct ProxyGrid {
+    min: Point3,
+    /// Cells are cubes
+    cell_size: Float,
+    dimensions: [usize; 3],
+    /// `None` where nothing overlaps the cell. X varies fastest.
+    cells: Vec<Option<Vec3>>,
+}
+
+/// The material of a shape that's drawn in the proxy. Volumes are left out since they're not
+/// solid, and so are lights that rays can't hit.
+pub(crate) fn shape_material(shape: &Shape) -> Option<Option<&Material>> {
+    match shape {
+        Shape::Sphere(sphere) => Some(Some(&sphere.material)),
+        Shape::Triangle(triangle) => Some(Some(&triangle.material)),
+        Shape::TriangleFragment(fragment) => Some(Some(&fragment.triangle().material)),
+        Shape::AaBox(aabox) => Some(Some(&aabox.material)),
+        Shape::RoundedBox(rounded) => Some(Some(&rounded.material)),
+        Shape::Instance(_) => Some(None),
+        Shape::Mesh(mesh) => Some(Some(&mesh.material)),
+        Shape::HeterogeneousMedium(_) => None,
+        Shape::RectLight(light) => light.visible.then_some(Some(&light.material)),
+        Shape::SpotLight(_) => None,
+    }
+}
+
+/// Tells materials apart by where they are, since shapes share them through `Arc`s
+pub(crate) fn material_key(material: &Material) -> usize {
+    material as *const Material as usize
+}
+
+impl ProxyGrid {
+    /// Voxelizes the bounds of `shapes` inside `bounds` into cubic cells, `resolution` of them
+    /// along the longest side. Shapes outside `bounds` are left out.
+    pub fn build(shapes: &[Shape], bounds: &Aabb<Float, 3>, resolution: usize) -> Self {
+        let extent = Vec3::from_fn(|axis, _| (bounds.max[axis] - bounds.min[axis]).max(0.0));
+        if !extent.iter().all(|e| e.is_finite()) || extent.max() <= 0.0 || resolution == 0 {
+            return ProxyGrid {
+                min: Point3::zeros(),
+                cell_size: 1.0,
+                dimensions: [0; 3],
+                cells: Vec::new(),
+            };
+        }
+        let cell_size = extent.max() / resolution as Float;
+        let dimensions: [usize; 3] =
+            std::array::from_fn(|axis| ((extent[axis] / cell_size).ceil() as usize).max(1));
+        let min = Point3::from_fn(|axis, _| bounds.min[axis]);
+        let mut grid = ProxyGrid {
+            min,
+            cell_size,
+            dimensions,
+            cells: vec![None; dimensions.iter().product()],
+        };
+
+        // Images can be big, so each material is only averaged once however many shapes use it
+        let mut materials: HashMap<usize, &Material> = HashMap::new();
+        for material in shapes.iter().filter_map(shape_material).flatten() {
+            materials.insert(material_key(material), material);
+        }
+        let albedos: HashMap<usize, Vec3> = materials
+            .into_par_iter()
+            .map(|(key, material)| (key, material.average_albedo()))
+            .collect();
+
+        let cell_count = grid.cells.len();
+        let (sums, counts) = shapes
+            .par_iter()
+            .fold(
+                || (vec![Vec3::zeros(); cell_count], vec![0u32; cell_count]),
+                |(mut sums, mut counts), shape| {
+                    let Some(material) = shape_material(shape) else {
+                        return (sums, counts);
+                    };
+                    let albedo = material.map_or(Vec3::repeat(INSTANCE_ALBEDO), |material| {
+                        albedos[&material_key(material)]
+                    });
+                    if let Some(range) = grid.cell_range(&shape.aabb()) {
+                        for index in range {
+                            sums[index] += albedo;
+                            counts[index] += 1;
+                        }
+                    }
+                    (sums, counts)
+                },
+            )
+            .reduce(
+                || (vec![Vec3::zeros(); cell_count], vec![0u32; cell_count]),
+                |(mut sums, mut counts), (other_sums, other_counts)| {
+                    for i in 0..cell_count {
+                        sums[i] += other_sums[i];
+                        counts[i] += other_counts[i];
+                    }
+                    (sums, counts)
+                },
+            );
+        for (cell, (sum, count)) in grid.cells.iter_mut().zip(sums.into_iter().zip(counts)) {
+            if count > 0 {
+                *cell = Some(sum / count as Float);
+            }
+        }
+        grid
+    }
+
+    /// Voxelizes the shapes around what `camera` looks at, leaving out anything too far away
+    /// to make out at this resolution anyway
+    pub fn around_camera(shapes: &[Shape], camera: &Camera, resolution: usize) -> Self {
+        let reach = PROXY_REACH * camera.focus_distance.max(1.0);
+        let near = Aabb::with_bounds(
+            (camera.center - Vec3::repeat(reach)).into(),
+            (camera.center + Vec3::repeat(reach)).into(),
+        );
+        let scene = shapes
+            .iter()
+            .filter(|shape| shape_material(shape).is_some())
+            .fold(Aabb::empty(), |bounds, shape| bounds.join(&shape.aabb()));
+        let bounds = Aabb::with_bounds(
+            scene.min.coords.sup(&near.min.coords).into(),
+            scene.max.coords.inf(&near.max.coords).into(),
+        );
+        ProxyGrid::build(shapes, &bounds, resolution)
+    }
+
+    pub fn dimensions(&self) -> [usize; 3] {
+        self.dimensions
+    }
+
+    fn index(&self, cell: [usize; 3]) -> usize {
+        let [x, y, z] = cell;
+        x + self.dimensions[0] * (y + self.dimensions[1] * z)
+    }
+
+    /// Returns the average albedo of what overlaps `cell`, `None` if it's empty or outside
+    pub fn cell(&self, cell: [usize; 3]) -> Option<Vec3> {
+        if (0..3).any(|axis| cell[axis] >= self.dimensions[axis]) {
+            return None;
+        }
+        self.cells[self.index(cell)]
+    }
+
+    /// Returns the indices of every cell `bounds` overlaps, `None` if it misses the grid
+    fn cell_range(&self, bounds: &Aabb<Float, 3>) -> Option<impl Iterator<Item = usize> + '_> {
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        for axis in 0..3 {
+            let to_cell = |c: Float| (c - self.min[axis]) / self.cell_size;
+            let (from, to) = (to_cell(bounds.min[axis]), to_cell(bounds.max[axis]));
+            if !(from <= self.dimensions[axis] as Float && to >= 0.0) {
+                return None;
+            }
+            low[axis] = from.max(0.0) as usize;
+            high[axis] = (to.max(0.0) as usize).min(self.dimensions[axis] - 1);
+        }
+        let cells = (low[2]..=high[2]).flat_map(move |z| {
+            (low[1]..=high[1])
+                .flat_map(move |y| (low[0]..=high[0]).map(move |x| self.index([x, y, z])))
+        });
+        Some(cells)
+    }
+
+    /// Walks the cells `ray` passes through in order, with a 3D DDA
+    pub fn traverse<'a>(&'a self, ray: &Ray) -> Traversal<'a> {
+        Traversal {
+            grid: self,
+            direction: ray.direction,
+            state: self.enter(ray),
+        }
+    }
+
+    /// Finds where `ray` first enters the grid and sets up the walk from there
+    fn enter(&self, ray: &Ray) -> Option<TraversalState> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let (mut enter, mut exit, mut entry_axis) = (0.0, Float::INFINITY, None);
+        for axis in 0..3 {
+            let low = self.min[axis];
+            let high = low + self.dimensions[axis] as Float * self.cell_size;
+            let (origin, direction) = (ray.origin[axis], ray.direction[axis]);
+            if direction == 0.0 {
+                if origin < low || origin > high {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((low - origin) / direction, (high - origin) / direction);
+            let (near, far) = (t0.min(t1), t0.max(t1));
+            if near > enter {
+                (enter, entry_axis) = (near, Some(axis));
+            }
+            exit = exit.min(far);
+        }
+        if enter > exit {
+            return None;
+        }
+        let start = ray.origin.coords + ray.direction * enter;
+        let mut cell = [0; 3];
+        let mut next_boundary = [Float::INFINITY; 3];
+        let mut boundary_spacing = [Float::INFINITY; 3];
+        for axis in 0..3 {
+            let position = ((start[axis] - self.min[axis]) / self.cell_size).floor();
+            cell[axis] = (position as i64).clamp(0, self.dimensions[axis] as i64 - 1);
+            let direction = ray.direction[axis];
+            if direction != 0.0 {
+                let next = cell[axis] + if direction > 0.0 { 1 } else { 0 };
+                let boundary = self.min[axis] + next as Float * self.cell_size;
+                next_boundary[axis] = (boundary - ray.origin[axis]) / direction;
+                boundary_spacing[axis] = self.cell_size / direction.abs();
+            }
+        }
+        Some(TraversalState {
+            cell,
+            enter,
+            entry_axis,
+            next_boundary,
+            boundary_spacing,
+            exit,
+        })
+    }
+
+    /// Returns the distance to the first occupied cell along `ray`, the normal of the face it
+    /// went in through and the cell's albedo
+    pub fn hit(&self, ray: &Ray) -> Option<(Float, Vec3, Vec3)> {
+        self.traverse(ray).find_map(|crossing| {
+            let albedo = self.cell(crossing.cell)?;
+            let mut normal = Vec3::zeros();
+            match crossing.entry_axis {
+                Some(axis) => normal[axis] = -ray.direction[axis].signum(),
+                // Started inside, so face the camera
+                None => normal = -ray.direction.normalize(),
+            }
+            Some((crossing.enter, normal, albedo))
+        })
+    }
+
+    /// Renders the proxy as `camera` sees it, lit by a light above and behind the camera and a
+    /// plain sky, one ray per pixel. Row-major like an [`crate::camera::Image`].
+    pub fn render(&self, camera: &Camera) -> Vec<Vec3> {
+        let (width, height) = (camera.image_width, camera.image_height);
+        let up = camera.up.normalize();
+        let light = key_light(camera);
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let ray = camera.debug_ray((i % width) as Float, (i / width) as Float);
+                match self.hit(&ray) {
+                    Some((_, normal, albedo)) => {
+                        let lit = AMBIENT + (1.0 - AMBIENT) * normal.dot(&light).max(0.0);
+                        (albedo * lit).map(|c| c.clamp(0.0, 1.0))
+                    }
+                    None => sky(&ray.direction, &up),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Direction towards the light previews are lit by, above and behind `camera`
+pub(crate) fn key_light(camera: &Camera) -> Vec3 {
+    let up = camera.up.normalize();
+    let back = (camera.center - camera.lookat).normalize();
+    (2.0 * up + back).normalize()
+}
+
+/// The plain sky previews show where nothing was hit, white at the horizon fading to blue
+/// straight `up`
+pub(crate) fn sky(direction: &Vec3, up: &Vec3) -> Vec3 {
+    let height = direction.normalize().dot(up);
+    let t = 0.5 * (height + 1.0);
+    Vec3::ONE * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Sphere,
+        material::{Lambertian, Volumetric},
+        medium::{HeterogeneousMedium, VoxelGrid},
+        scenes,
+    };
+    use std::sync::Arc;
+
+    /// A grid of unit cells from the origin to (4, 4, 4)
+    fn unit_grid(shapes: &[Shape]) -> ProxyGrid {
+        let bounds = Aabb::with_bounds(Vec3::zeros().into(), Vec3::repeat(4.0).into());
+        ProxyGrid::build(shapes, &bounds, 4)
+    }
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray::new(origin.into(), direction)
+    }
+
+    fn cells(grid: &ProxyGrid, ray: &Ray) -> Vec<[usize; 3]> {
+        grid.traverse(ray).map(|crossing| crossing.cell).collect()
+    }
+
+    fn ball(center: Vec3, radius: Float, albedo: Float) -> Shape {
+        let material = Lambertian::new_rgb_solid(albedo, albedo, albedo);
+        Sphere::new(center, radius, Arc::new(material.into())).into()
+    }
+
+    fn camera(from: Vec3, at: Vec3) -> Camera {
+        Camera::builder()
+            .with_look_from(from)
+            .with_look_at(at)
+            .with_resolution(32, 18)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn rays_along_an_axis_cross_a_row_of_cells() {
+        let grid = unit_grid(&[]);
+        assert_eq!(grid.dimensions(), [4, 4, 4]);
+        let along_x = ray(Vec3::new(-1.0, 0.5, 0.5), Vec3::x());
+        let crossings: Vec<_> = grid.traverse(&along_x).collect();
+        let expected = [[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0]];
+        assert_eq!(
+            crossings.iter().map(|c| c.cell).collect::<Vec<_>>(),
+            expected
+        );
+        for (i, crossing) in crossings.iter().enumerate() {
+            assert_eq!(
+                (crossing.enter, crossing.exit),
+                (i as Float + 1.0, i as Float + 2.0)
+            );
+            assert_eq!(crossing.entry_axis, Some(0));
+        }
+        // Backwards, from the far side
+        let back = ray(Vec3::new(5.0, 3.5, 2.5), -Vec3::x());
+        assert_eq!(
+            cells(&grid, &back),
+            [[3, 3, 2], [2, 3, 2], [1, 3, 2], [0, 3, 2]]
+        );
+    }
+
+    #[test]
+    fn slanted_rays_cross_cells_in_the_order_they_meet_their_faces() {
+        let grid = unit_grid(&[]);
+        // Crosses X boundaries 0.75, 1.75, 2.75 and 3.75 along, and Y ones 1 and 3
+        let slanted = ray(Vec3::new(0.25, 0.5, 0.5), Vec3::new(1.0, 0.5, 0.0));
+        let crossings: Vec<_> = grid.traverse(&slanted).collect();
+        // Rays' directions are normalized, so distances along them are a little shorter
+        let scale = Vec3::new(1.0, 0.5, 0.0).norm();
+        let expected = [
+            ([0, 0, 0], 0.0, 0.75, None),
+            ([1, 0, 0], 0.75, 1.0, Some(0)),
+            ([1, 1, 0], 1.0, 1.75, Some(1)),
+            ([2, 1, 0], 1.75, 2.75, Some(0)),
+            ([3, 1, 0], 2.75, 3.0, Some(0)),
+            ([3, 2, 0], 3.0, 3.75, Some(1)),
+        ];
+        assert_eq!(crossings.len(), expected.len());
+        for (crossing, (cell, enter, exit, axis)) in crossings.iter().zip(expected) {
+            assert_eq!(crossing.cell, cell);
+            assert!(
+                (crossing.enter - enter * scale).abs() < 1e-12,
+                "{:?}",
+                crossing
+            );
+            assert!(
+                (crossing.exit - exit * scale).abs() < 1e-12,
+                "{:?}",
+                crossing
+            );
+            assert_eq!(crossing.entry_axis, axis);
+        }
+        // Rays that miss, or point away, cross nothing
+        assert!(cells(&grid, &ray(Vec3::new(-1.0, 5.0, 0.5), Vec3::x())).is_empty());
+        assert!(cells(&grid, &ray(Vec3::new(-1.0, 0.5, 0.5), -Vec3::x())).is_empty());
+    }
+
+    #[test]
+    fn hits_stop_at_the_first_occupied_cell() {
+        // Only overlaps cell (2, 0, 0), and a darker ball further along only (3, 0, 0)
+        let grid = unit_grid(&[
+            ball(Vec3::new(2.5, 0.5, 0.5), 0.2, 0.8),
+            ball(Vec3::new(3.5, 0.5, 0.5), 0.2, 0.1),
+        ]);
+        assert_eq!(grid.cell([2, 0, 0]), Some(Vec3::repeat(0.8)));
+        assert_eq!(grid.cell([1, 0, 0]), None);
+        assert_eq!(grid.cell([9, 0, 0]), None);
+        let (distance, normal, albedo) = grid
+            .hit(&ray(Vec3::new(-1.0, 0.5, 0.5), Vec3::x()))
+            .unwrap();
+        assert_eq!(
+            (distance, normal, albedo),
+            (3.0, -Vec3::x(), Vec3::repeat(0.8))
+        );
+        // From the other side the dark one is first
+        let (_, normal, albedo) = grid
+            .hit(&ray(Vec3::new(5.0, 0.5, 0.5), -Vec3::x()))
+            .unwrap();
+        assert_eq!((normal, albedo), (Vec3::x(), Vec3::repeat(0.1)));
+        assert_eq!(grid.hit(&ray(Vec3::new(-1.0, 1.5, 0.5), Vec3::x())), None);
+    }
+
+    #[test]
+    fn empty_scenes_render_as_sky() {
+        let camera = camera(Vec3::new(0.0, -5.0, 1.0), Vec3::zeros());
+        let up = camera.up.normalize();
+        let grid = VoxelGrid::from_fn([4; 3], Vec3::repeat(-1.0), Vec3::repeat(1.0), |_| 1.0);
+        let smoke_material = Arc::new(Volumetric::new(Vec3::repeat(0.9), 0.3).into());
+        let smoke = HeterogeneousMedium::new(Arc::new(grid), 0.25, smoke_material);
+        for shapes in [Vec::new(), vec![smoke.into()]] {
+            let grid = ProxyGrid::around_camera(&shapes, &camera, PROXY_RESOLUTION);
+            assert_eq!(grid.dimensions(), [0; 3]);
+            let pixels = grid.render(&camera);
+            assert_eq!(pixels.len(), 32 * 18);
+            let ray = camera.debug_ray(3.0, 2.0);
+            assert_eq!(pixels[2 * 32 + 3], sky(&ray.direction, &up));
+        }
+    }
+
+    #[test]
+    fn scenes_larger_than_the_grid_are_cut_to_it() {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        // A ground plane far wider than the proxy reaches, and a ball far past it
+        let mut shapes = scenes::generate_ground_plane(10000.0, 10000.0, 0.0, gray, true);
+        shapes.push(ball(Vec3::new(0.0, 5000.0, 1.0), 1.0, 0.9));
+        shapes.push(ball(Vec3::new(0.0, 0.0, 1.0), 1.0, 0.9));
+        // Level and above the grid, whose cells are coarse enough to hold the ball in the bottom
+        // layer with the ground
+        let camera = camera(Vec3::new(0.0, -5.0, 4.0), Vec3::new(0.0, 0.0, 4.0));
+        let grid = ProxyGrid::around_camera(&shapes, &camera, PROXY_RESOLUTION);
+        assert!(grid
+            .dimensions()
+            .iter()
+            .all(|&d| d > 0 && d <= PROXY_RESOLUTION));
+        let pixels = grid.render(&camera);
+        assert!(pixels
+            .iter()
+            .all(|color| color.iter().all(|c| (0.0..=1.0).contains(c))));
+        // The ground shows below the horizon, and the sky above it
+        let up = camera.up.normalize();
+        let sky_at =
+            |x: usize, y: usize| sky(&camera.debug_ray(x as Float, y as Float).direction, &up);
+        assert_ne!(pixels[17 * 32 + 16], sky_at(16, 17));
+        assert_eq!(pixels[0], sky_at(0, 0));
+
+        // Built with bounds inside one shape, every cell is that shape
+        let bounds = Aabb::with_bounds(
+            Vec3::new(-0.1, -0.1, 0.9).into(),
+            Vec3::new(0.1, 0.1, 1.1).into(),
+        );
+        let inside = ProxyGrid::build(&shapes[shapes.len() - 1..], &bounds, 8);
+        assert_eq!(inside.dimensions(), [8, 8, 8]);
+        assert_eq!(inside.cell([7, 7, 7]), Some(Vec3::repeat(0.9)));
+    }
+}
@@ -0,0 +1,658 @@
+use crate::{
+    camera::{Camera, Float},
+    hittable::Shape,
+    proxy::{self, AMBIENT, INSTANCE_ALBEDO},
+    vec3::{Point3, Vec3},
+};
+use bvh::aabb::{Aabb, Bounded};
+use rayon::prelude::*;
+use std::{collections::HashMap, ops::Range};
+
+/// Closest anything can be in front of the camera and still be drawn, as a fraction of its
+/// focus distance. Triangles reaching closer are clipped there.
+const NEAR: Float = 1e-4;
+/// Rows rasterized together, each band on a thread of its own
+const BAND_ROWS: usize = 16;
+
+/// A pinhole projection through the center of a camera's lens onto its image, with the same
+/// viewport as its camera rays.
+///
+/// Raster coordinates are those of [`Camera::debug_ray`]: `(x, y)` is where the ray towards
+/// `pixel00_loc + x * pixel_du + y * pixel_dv` crosses the image. Pixel `(x, y)`'s camera rays
+/// cover `[x, x + 1)` by `[y, y + 1)`, so the rasterizer samples each pixel in the middle of
+/// that, at `(x + 0.5, y + 0.5)`, for its silhouettes to line up with the ray traced ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    center: Point3,
+    /// The camera's basis: across the image, down it and into the scene
+    right: Vec3,
+    down: Vec3,
+    forward: Vec3,
+    /// Raster coordinates of the point straight ahead
+    principal: (Float, Float),
+    /// Pixels per unit across and down, at a depth of 1
+    focal: (Float, Float),
+    /// Depth of the near plane
+    pub near: Float,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Projection {
+    pub fn new(camera: &Camera) -> Self {
+        let forward = camera.pixel_du.cross(&camera.pixel_dv).normalize();
+        let (right, down) = (camera.pixel_du.normalize(), camera.pixel_dv.normalize());
+        let (du, dv) = (camera.pixel_du.norm(), camera.pixel_dv.norm());
+        let to_viewport = camera.pixel00_loc - camera.center;
+        let distance = to_viewport.dot(&forward);
+        Projection {
+            center: camera.center,
+            right,
+            down,
+            forward,
+            principal: (-to_viewport.dot(&right) / du, -to_viewport.dot(&down) / dv),
+            focal: (distance / du, distance / dv),
+            near: NEAR * camera.focus_distance,
+            width: camera.image_width,
+            height: camera.image_height,
+        }
+    }
+
+    /// `point` in camera space: how far across, down and in front of the camera it is
+    pub fn to_view(&self, point: &Point3) -> Vec3 {
+        let offset = point - self.center;
+        Vec3::new(
+            offset.dot(&self.right),
+            offset.dot(&self.down),
+            offset.dot(&self.forward),
+        )
+    }
+
+    /// Raster coordinates of `view`, a point in camera space, with its depth as Z. Only
+    /// meaningful in front of the near plane.
+    pub fn view_to_raster(&self, view: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.principal.0 + self.focal.0 * view.x / view.z,
+            self.principal.1 + self.focal.1 * view.y / view.z,
+            view.z,
+        )
+    }
+
+    /// Raster coordinates of `point` with its depth as Z, or `None` if it's not in front of the
+    /// near plane. Points outside the image still project, off its edges.
+    pub fn project(&self, point: &Point3) -> Option<Vec3> {
+        let view = self.to_view(point);
+        (view.z >= self.near).then(|| self.view_to_raster(&view))
+    }
+
+    /// Direction of the ray through raster coordinates `(x, y)`, scaled to a depth of 1
+    pub fn ray_direction(&self, x: Float, y: Float) -> Vec3 {
+        self.right * ((x - self.principal.0) / self.focal.0)
+            + self.down * ((y - self.principal.1) / self.focal.1)
+            + self.forward
+    }
+}
+
+/// Clips a triangle with `corners` in camera space to the part in front of the near plane at
+/// depth `near`, returning the corners of the polygon that's left in order: none, 3 or 4
+pub fn clip_near(corners: [Vec3; 3], near: Float) -> Vec<Vec3> {
+    let mut clipped = Vec::with_capacity(4);
+    for i in 0..3 {
+        let (a, b) = (corners[i], corners[(i + 1) % 3]);
+        let (a_in, b_in) = (a.z >= near, b.z >= near);
+        if a_in {
+            clipped.push(a);
+        }
+        if a_in != b_in {
+            let mut crossing = a.lerp(&b, (near - a.z) / (b.z - a.z));
+            // Exactly on the plane despite rounding, so it always projects
+            crossing.z = near;
+            clipped.push(crossing);
+        }
+    }
+    if clipped.len() < 3 {
+        clipped.clear();
+    }
+    clipped
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p` in raster coordinates, positive when
+/// `p` is clockwise from `a` to `b` on screen
+fn edge(a: &Vec3, b: &Vec3, p: (Float, Float)) -> Float {
+    (b.x - a.x) * (p.1 - a.y) - (b.y - a.y) * (p.0 - a.x)
+}
+
+/// A triangle in raster coordinates with depth as Z, already clipped and flat shaded
+#[derive(Debug, Clone, PartialEq)]
+struct ScreenTriangle {
+    corners: [Vec3; 3],
+    color: Vec3,
+}
+
+/// A sphere, drawn by intersecting each pixel's ray with it within `bounds`
+#[derive(Debug, Clone, PartialEq)]
+struct ScreenSphere {
+    center: Point3,
+    radius: Float,
+    albedo: Vec3,
+    /// Raster coordinates it can cover, across then down
+    bounds: (Range<Float>, Range<Float>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Primitive {
+    Triangle(ScreenTriangle),
+    Sphere(ScreenSphere),
+}
+
+impl Primitive {
+    fn rows(&self) -> Range<Float> {
+        match self {
+            Primitive::Triangle(triangle) => {
+                let ys = triangle.corners.map(|corner| corner.y);
+                ys[0].min(ys[1]).min(ys[2])..ys[0].max(ys[1]).max(ys[2])
+            }
+            Primitive::Sphere(sphere) => sphere.bounds.1.clone(),
+        }
+    }
+}
+
+/// A frame rasterized by [`rasterize`], row-major like an [`crate::camera::Image`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub colors: Vec<Vec3>,
+    /// Depth of the nearest surface drawn in each pixel, infinite where only sky shows
+    pub depths: Vec<Float>,
+}
+
+impl Raster {
+    /// Whether anything was drawn in pixel `(x, y)`
+    pub fn covered(&self, x: usize, y: usize) -> bool {
+        self.depths[y * self.width + x].is_finite()
+    }
+}
+
+/// The corners of `bounds`, with bits 0, 1 and 2 of the index picking the high X, Y and Z
+fn aabb_corners(bounds: &Aabb<Float, 3>) -> [Point3; 8] {
+    std::array::from_fn(|i| {
+        Point3::from_fn(|axis, _| match i >> axis & 1 {
+            0 => bounds.min[axis],
+            _ => bounds.max[axis],
+        })
+    })
+}
+
+/// The twelve triangles of the faces of `bounds`
+fn box_corners(bounds: &Aabb<Float, 3>) -> Vec<[Point3; 3]> {
+    let corners = aabb_corners(bounds);
+    // Each face's corners in order around it
+    let faces = [
+        [0, 2, 6, 4],
+        [1, 3, 7, 5],
+        [0, 1, 5, 4],
+        [2, 3, 7, 6],
+        [0, 1, 3, 2],
+        [4, 5, 7, 6],
+    ];
+    faces
+        .iter()
+        .flat_map(|&[a, b, c, d]| {
+            [
+                [corners[a], corners[b], corners[c]],
+                [corners[a], corners[c], corners[d]],
+            ]
+        })
+        .collect()
+}
+
+/// Shades, clips and projects one triangle with `corners` in world space
+fn screen_triangles(
+    projection: &Projection,
+    corners: [Point3; 3],
+    albedo: Vec3,
+    light: &Vec3,
+) -> Vec<Primitive> {
+    let [a, b, c] = corners;
+    let mut normal = (b - a).cross(&(c - a)).normalize();
+    if !normal.iter().all(|n| n.is_finite()) {
+        return Vec::new();
+    }
+    // Lit from whichever side faces the camera, since there's no telling which way it's wound
+    if normal.dot(&(projection.center - a)) < 0.0 {
+        normal = -normal;
+    }
+    let lit = AMBIENT + (1.0 - AMBIENT) * normal.dot(light).max(0.0);
+    let color = (albedo * lit).map(|c| c.clamp(0.0, 1.0));
+    let polygon = clip_near(
+        corners.map(|corner| projection.to_view(&corner)),
+        projection.near,
+    );
+    let polygon: Vec<Vec3> = polygon
+        .iter()
+        .map(|view| projection.view_to_raster(view))
+        .collect();
+    (1..polygon.len().saturating_sub(1))
+        .map(|i| {
+            Primitive::Triangle(ScreenTriangle {
+                corners: [polygon[0], polygon[i], polygon[i + 1]],
+                color,
+            })
+        })
+        .collect()
+}
+
+/// Bounds a sphere covers on screen, going by its bounding box, or the whole image when it
+/// reaches past the near plane
+fn sphere_bounds(projection: &Projection, bounds: &Aabb<Float, 3>) -> (Range<Float>, Range<Float>) {
+    let whole = (
+        0.0..projection.width as Float,
+        0.0..projection.height as Float,
+    );
+    let mut projected = Vec::with_capacity(8);
+    for corner in &aabb_corners(bounds) {
+        match projection.project(corner) {
+            Some(raster) => projected.push(raster),
+            None => return whole,
+        }
+    }
+    let fold = |axis: usize| {
+        projected
+            .iter()
+            .fold(Float::INFINITY..Float::NEG_INFINITY, |range, p| {
+                range.start.min(p[axis])..range.end.max(p[axis])
+            })
+    };
+    (fold(0), fold(1))
+}
+
+/// Rasterizes `shapes` as `camera` sees them through the center of its lens, lit and against a
+/// sky like [`crate::proxy::ProxyGrid::render`]. Triangles are drawn as they are, and spheres
+/// exactly by intersecting each pixel's ray with them, but boxes and instances are drawn as
+/// their bounds, and volumes not at all. Every surface is flat shaded with the average albedo
+/// of its material. Nothing is traced against the world, so this needs no BVH, and anything
+/// closer than the near plane is clipped.
+pub fn rasterize(shapes: &[Shape], camera: &Camera) -> Raster {
+    let projection = Projection::new(camera);
+    let (width, height) = (projection.width, projection.height);
+    let light = proxy::key_light(camera);
+
+    // Shared materials are only averaged once, like the proxy does
+    let mut materials = HashMap::new();
+    for material in shapes.iter().filter_map(proxy::shape_material).flatten() {
+        materials.insert(proxy::material_key(material), material);
+    }
+    let albedos: HashMap<usize, Vec3> = materials
+        .into_par_iter()
+        .map(|(key, material)| (key, material.average_albedo()))
+        .collect();
+
+    let primitives: Vec<Primitive> = shapes
+        .par_iter()
+        .flat_map_iter(|shape| {
+            let Some(material) = proxy::shape_material(shape) else {
+                return Vec::new();
+            };
+            let albedo = material.map_or(Vec3::repeat(INSTANCE_ALBEDO), |material| {
+                albedos[&proxy::material_key(material)]
+            });
+            let triangle = |corners| screen_triangles(&projection, corners, albedo, &light);
+            match shape {
+                Shape::Sphere(sphere) => {
+                    let view = projection.to_view(&sphere.center());
+                    if view.z + sphere.radius() < projection.near {
+                        return Vec::new();
+                    }
+                    vec![Primitive::Sphere(ScreenSphere {
+                        center: sphere.center(),
+                        radius: sphere.radius(),
+                        albedo,
+                        bounds: sphere_bounds(&projection, &sphere.aabb()),
+                    })]
+                }
+                Shape::Triangle(t) => triangle([t.a, t.b, t.c]),
+                Shape::TriangleFragment(fragment) => {
+                    let t = fragment.triangle();
+                    triangle([t.a, t.b, t.c])
+                }
+                Shape::Mesh(mesh) => mesh.face_corners().flat_map(triangle).collect(),
+                _ => box_corners(&shape.aabb())
+                    .into_iter()
+                    .flat_map(triangle)
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let up = camera.up.normalize();
+    let mut colors: Vec<Vec3> = (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as Float + 0.5, (i / width) as Float + 0.5);
+            proxy::sky(&projection.ray_direction(x, y), &up)
+        })
+        .collect();
+    let mut depths = vec![Float::INFINITY; width * height];
+    if width > 0 {
+        colors
+            .par_chunks_mut(BAND_ROWS * width)
+            .zip(depths.par_chunks_mut(BAND_ROWS * width))
+            .enumerate()
+            .for_each(|(band, (colors, depths))| {
+                let rows = band * BAND_ROWS..band * BAND_ROWS + colors.len() / width;
+                let mut target = Target {
+                    projection: &projection,
+                    light: &light,
+                    rows,
+                    colors,
+                    depths,
+                };
+                for primitive in &primitives {
+                    target.draw(primitive);
+                }
+            });
+    }
+    Raster {
+        width,
+        height,
+        colors,
+        depths,
+    }
+}
+
+/// A band of rows of the frame being rasterized
+struct Target<'a> {
+    projection: &'a Projection,
+    light: &'a Vec3,
+    rows: Range<usize>,
+    colors: &'a mut [Vec3],
+    depths: &'a mut [Float],
+}
+
+/// The pixels whose middles are within `range` of raster coordinates, up to `count`
+fn pixels_within(range: &Range<Float>, count: usize) -> Range<usize> {
+    if range.start.is_nan() || range.end.is_nan() {
+        return 0..0;
+    }
+    let first = (range.start - 0.5).ceil().max(0.0);
+    let last = (range.end - 0.5).floor().min(count as Float - 1.0);
+    if last < first {
+        return 0..0;
+    }
+    first as usize..last as usize + 1
+}
+
+impl Target<'_> {
+    fn draw(&mut self, primitive: &Primitive) {
+        let rows = pixels_within(&primitive.rows(), self.projection.height);
+        let rows = rows.start.max(self.rows.start)..rows.end.min(self.rows.end);
+        if rows.is_empty() {
+            return;
+        }
+        match primitive {
+            Primitive::Triangle(triangle) => self.draw_triangle(triangle, rows),
+            Primitive::Sphere(sphere) => self.draw_sphere(sphere, rows),
+        }
+    }
+
+    /// Keeps `color` at pixel `(x, y)` if it's nearer than what's there
+    fn write(&mut self, x: usize, y: usize, depth: Float, color: impl FnOnce() -> Vec3) {
+        let index = (y - self.rows.start) * self.projection.width + x;
+        if depth < self.depths[index] {
+            self.depths[index] = depth;
+            self.colors[index] = color();
+        }
+    }
+
+    /// Scan-converts the triangle, interpolating the reciprocal of depth, which unlike depth
+    /// itself varies linearly across the screen
+    fn draw_triangle(&mut self, triangle: &ScreenTriangle, rows: Range<usize>) {
+        let [a, b, c] = &triangle.corners;
+        let area = edge(a, b, (c.x, c.y));
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+        let xs = a.x.min(b.x).min(c.x)..a.x.max(b.x).max(c.x);
+        let columns = pixels_within(&xs, self.projection.width);
+        for y in rows {
+            for x in columns.clone() {
+                let p = (x as Float + 0.5, y as Float + 0.5);
+                let weights = [
+                    edge(b, c, p) / area,
+                    edge(c, a, p) / area,
+                    edge(a, b, p) / area,
+                ];
+                if weights.iter().any(|&weight| weight < 0.0) {
+                    continue;
+                }
+                let inverse_depth = weights[0] / a.z + weights[1] / b.z + weights[2] / c.z;
+                self.write(x, y, 1.0 / inverse_depth, || triangle.color);
+            }
+        }
+    }
+
+    fn draw_sphere(&mut self, sphere: &ScreenSphere, rows: Range<usize>) {
+        let columns = pixels_within(&sphere.bounds.0, self.projection.width);
+        let from_center = self.projection.center - sphere.center;
+        let c = from_center.norm_squared() - sphere.radius * sphere.radius;
+        for y in rows {
+            for x in columns.clone() {
+                let direction = self
+                    .projection
+                    .ray_direction(x as Float + 0.5, y as Float + 0.5);
+                let a = direction.norm_squared();
+                let half_b = from_center.dot(&direction);
+                let discriminant = half_b * half_b - a * c;
+                if discriminant < 0.0 {
+                    continue;
+                }
+                let root = discriminant.sqrt();
+                // The direction has a depth of 1, so distances along it are depths
+                let near_hit = (-half_b - root) / a;
+                let depth = if near_hit >= self.projection.near {
+                    near_hit
+                } else {
+                    (-half_b + root) / a
+                };
+                if depth < self.projection.near {
+                    continue;
+                }
+                let point = self.projection.center + direction * depth;
+                let mut normal = (point - sphere.center) / sphere.radius;
+                if normal.dot(&direction) > 0.0 {
+                    normal = -normal;
+                }
+                let lit = AMBIENT + (1.0 - AMBIENT) * normal.dot(self.light).max(0.0);
+                self.write(x, y, depth, || {
+                    (sphere.albedo * lit).map(|c| c.clamp(0.0, 1.0))
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Background, Sphere, Triangle, World},
+        material::{Lambertian, Material},
+    };
+    use approx::assert_relative_eq;
+    use std::sync::Arc;
+
+    /// At the origin looking along +Y with +Z up, so +X is to the right, with a 90 degree field
+    /// of view across both sides of a `size` by `size` image
+    fn camera(size: usize) -> Camera {
+        Camera::builder()
+            .with_look_from(Vec3::zeros())
+            .with_look_at(Vec3::y())
+            .with_vertical_fov(90.0)
+            .with_resolution(size, size)
+            .with_samples(16)
+            .with_max_depth(2)
+            .build()
+            .unwrap()
+    }
+
+    fn gray() -> Arc<Material> {
+        Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into())
+    }
+
+    #[test]
+    fn a_known_triangle_projects_to_known_pixels() {
+        let projection = Projection::new(&camera(100));
+        // Raster coordinates are 0 at the middle of the first pixel, so the middle of the image
+        // is at 49.5, and at a depth of 2 the edges of the image are 2 across and down from it
+        let expected = [
+            (Vec3::new(0.0, 2.0, 0.0), Vec3::new(49.5, 49.5, 2.0)),
+            (Vec3::new(1.0, 2.0, 0.0), Vec3::new(74.5, 49.5, 2.0)),
+            (Vec3::new(0.0, 2.0, -1.0), Vec3::new(49.5, 74.5, 2.0)),
+            (Vec3::new(-2.0, 2.0, 2.0), Vec3::new(-0.5, -0.5, 2.0)),
+            (Vec3::new(4.0, 4.0, -4.0), Vec3::new(99.5, 99.5, 4.0)),
+        ];
+        for (point, raster) in expected {
+            let projected = projection.project(&point).unwrap();
+            assert_relative_eq!(projected, raster, epsilon = 1e-9);
+            // The ray back through it points at the point
+            let direction = projection.ray_direction(projected.x, projected.y);
+            assert_relative_eq!(direction * projected.z, point, epsilon = 1e-9);
+        }
+        assert_eq!(projection.project(&Vec3::new(0.0, -1.0, 0.0)), None);
+        assert_eq!(projection.project(&Vec3::new(1.0, 0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn projections_agree_with_camera_rays() {
+        let mut camera = camera(64).with_resolution(80, 45);
+        camera = camera.with_view(Vec3::new(1.0, -3.0, 2.0), Vec3::new(0.2, 0.5, 0.1), 2.5);
+        let projection = Projection::new(&camera);
+        for (x, y) in [(0.0, 0.0), (79.0, 44.0), (12.25, 30.5), (-3.0, 50.0)] {
+            let ray = camera.debug_ray(x, y);
+            let point = camera.center + ray.direction * 3.7;
+            let projected = projection.project(&point).unwrap();
+            assert_relative_eq!(projected.x, x, epsilon = 1e-9);
+            assert_relative_eq!(projected.y, y, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn clipping_keeps_what_is_in_front_of_the_near_plane() {
+        let near = 0.5;
+        // One corner behind, so a quad is left
+        let quad = clip_near(
+            [
+                Vec3::new(0.0, 0.0, 2.0),
+                Vec3::new(1.0, 0.0, -2.0),
+                Vec3::new(0.0, 1.0, 2.0),
+            ],
+            near,
+        );
+        assert_eq!(quad.len(), 4);
+        assert!(quad.iter().all(|corner| corner.z >= near));
+        assert_relative_eq!(quad[1], Vec3::new(0.375, 0.0, 0.5), epsilon = 1e-12);
+        assert_relative_eq!(quad[2], Vec3::new(0.375, 0.625, 0.5), epsilon = 1e-12);
+        // Two behind, so a smaller triangle
+        let triangle = clip_near(
+            [
+                Vec3::new(0.0, 0.0, 2.0),
+                Vec3::new(1.0, 0.0, -2.0),
+                Vec3::new(0.0, 1.0, -2.0),
+            ],
+            near,
+        );
+        assert_eq!(triangle.len(), 3);
+        assert!(triangle
+            .iter()
+            .all(|corner| corner.z == near || corner.z == 2.0));
+        // All behind, so nothing
+        let behind = [Vec3::new(0.0, 0.0, -1.0), Vec3::x(), Vec3::y()];
+        assert!(clip_near(behind, near).is_empty());
+        // Touching it from in front, so untouched
+        let touching = [
+            Vec3::new(0.0, 0.0, near),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+        ];
+        assert_eq!(clip_near(touching, near), touching.to_vec());
+        // All in front, so untouched
+        let front = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 3.0),
+        ];
+        assert_eq!(clip_near(front, near), front.to_vec());
+    }
+
+    #[test]
+    fn floors_reaching_behind_the_camera_cover_the_bottom_half() {
+        // A floor under the camera that goes far behind it and ahead of it
+        let floor = Triangle::new(
+            Vec3::new(-1000.0, -1000.0, -1.0),
+            Vec3::new(1000.0, -1000.0, -1.0),
+            Vec3::new(0.0, 1000.0, -1.0),
+            gray(),
+        );
+        let raster = rasterize(&[floor.into()], &camera(32));
+        for y in 0..32 {
+            for x in 0..32 {
+                // Up to the horizon, slightly below the middle at a finite distance
+                assert_eq!(raster.covered(x, y), y >= 16, "pixel ({}, {})", x, y);
+            }
+        }
+        let depth = raster.depths[31 * 32 + 16];
+        assert!(depth.is_finite() && depth > 0.0);
+    }
+
+    #[test]
+    fn silhouettes_line_up_with_the_path_traced_image() {
+        // Black shapes against an even sky, so a pixel's brightness is the sky's times the
+        // fraction of its samples that missed them
+        let black: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.0, 0.0, 0.0).into());
+        let shapes = vec![
+            Sphere::new(Vec3::new(-0.6, 4.0, 0.3), 0.9, black.clone()).into(),
+            Triangle::new(
+                Vec3::new(0.2, 3.0, -1.5),
+                Vec3::new(2.0, 5.0, -1.0),
+                Vec3::new(1.0, 3.5, 1.5),
+                black.clone(),
+            )
+            .into(),
+        ];
+        let raster = rasterize(&shapes, &camera(48));
+        let mut world = World::build(shapes);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::repeat(1.0),
+            top: Vec3::repeat(1.0),
+        };
+        let mut camera = camera(48);
+        camera.seed = Some(4);
+        let image = camera.render_image(&world);
+        let sky = image
+            .pixels
+            .iter()
+            .map(|color| color.x)
+            .fold(0.0, Float::max);
+        let mut edges = 0;
+        for y in 0..48 {
+            for x in 0..48 {
+                let covered = raster.covered(x, y);
+                let missed = image[(x, y)].x / sky;
+                let neighbors = (y.saturating_sub(1)..(y + 2).min(48))
+                    .flat_map(|ny| (x.saturating_sub(1)..(x + 2).min(48)).map(move |nx| (nx, ny)));
+                if neighbors
+                    .clone()
+                    .all(|(nx, ny)| raster.covered(nx, ny) == covered)
+                {
+                    // More than a pixel from a silhouette, so entirely in or out of it
+                    let expected = if covered { 0.0 } else { 1.0 };
+                    assert!((missed - expected).abs() < 1e-9, "pixel ({}, {})", x, y);
+                } else {
+                    edges += 1;
+                }
+            }
+        }
+        assert!(edges > 50, "{} pixels on the silhouettes", edges);
+    }
+}
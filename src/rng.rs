@@ -0,0 +1,191 @@
+use rand::{thread_rng, RngCore};
+use std::cell::RefCell;
+
+/// The random numbers one sample draws from, handed down to everything that shades it. A seeded
+/// one is a SplitMix64 stream that depends only on the seed, the pixel and the sample index, so
+/// the sample can be replayed exactly. An unseeded one draws from the thread's generator.
+#[derive(Debug, Clone, Default)]
+pub struct SampleRng {
+    /// SplitMix64 state, or `None` to use the thread's generator
+    state: Option<u64>,
+}
+
+impl SampleRng {
+    /// The stream of sample `sample` of pixel `(x, y)`
+    pub fn seeded(seed: u64, x: usize, y: usize, sample: usize) -> Self {
+        let state = mix(mix(mix(seed) ^ x as u64) ^ y as u64) ^ mix(sample as u64);
+        SampleRng { state: Some(state) }
+    }
+
+    /// [`SampleRng::seeded`] if there's a `seed`, and the thread's generator otherwise, like for a
+    /// camera whose seed is optional
+    pub fn for_sample(seed: Option<u64>, x: usize, y: usize, sample: usize) -> Self {
+        match seed {
+            Some(seed) => SampleRng::seeded(seed, x, y, sample),
+            None => SampleRng::default(),
+        }
+    }
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match &mut self.state {
+            Some(state) => {
+                let value = mix(*state);
+                *state = state.wrapping_add(GOLDEN_GAMMA);
+                value
+            }
+            None => thread_rng().next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// What SplitMix64 steps its state by
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// SplitMix64, for combining the seed and sample coordinates into well-mixed stream seeds
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+thread_local! {
+    /// The stream intersection tests on this thread draw from, if the sample was seeded
+    static HIT_STREAM: RefCell<Option<SampleRng>> = const { RefCell::new(None) };
+}
+
+/// The random numbers intersection tests draw from, like alpha masks deciding whether a ray goes
+/// through. [`crate::hittable::Hit`] has no way to hand shapes the sample's [`SampleRng`], so
+/// they use this thread's stream instead, which [`seed_hits`] sets up from it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HitRng;
+
+impl RngCore for HitRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        HIT_STREAM.with_borrow_mut(|stream| match stream {
+            Some(stream) => stream.next_u64(),
+            None => thread_rng().next_u64(),
+        })
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        HIT_STREAM.with_borrow_mut(|stream| match stream {
+            Some(stream) => stream.fill_bytes(dest),
+            None => thread_rng().fill_bytes(dest),
+        })
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Restores the stream that was in use before [`seed_hits`] when dropped
+pub struct HitSeedGuard {
+    previous: Option<SampleRng>,
+}
+
+impl Drop for HitSeedGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        HIT_STREAM.with_borrow_mut(|stream| *stream = previous);
+    }
+}
+
+/// Seeds this thread's [`HitRng`] from `rng` until the returned guard is dropped, so a seeded
+/// sample's intersection tests are as reproducible as the rest of it. An unseeded `rng` leaves
+/// them on the thread's generator. The previous stream comes back afterwards, in case rayon
+/// runs another sample on this thread while this one waits.
+pub fn seed_hits(rng: &mut SampleRng) -> HitSeedGuard {
+    let stream = rng.state.is_some().then(|| SampleRng {
+        state: Some(rng.next_u64()),
+    });
+    let previous = HIT_STREAM.with_borrow_mut(|current| std::mem::replace(current, stream));
+    HitSeedGuard { previous }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn seeded_streams_repeat() {
+        let draws = |mut rng: SampleRng| (0..8).map(|_| rng.next_u64()).collect::<Vec<_>>();
+        assert_eq!(
+            draws(SampleRng::seeded(1, 2, 3, 4)),
+            draws(SampleRng::seeded(1, 2, 3, 4))
+        );
+    }
+
+    #[test]
+    fn streams_differ_by_pixel_and_sample() {
+        let first = |mut rng: SampleRng| rng.next_u64();
+        let base = first(SampleRng::seeded(1, 2, 3, 4));
+        assert_ne!(base, first(SampleRng::seeded(0, 2, 3, 4)));
+        assert_ne!(base, first(SampleRng::seeded(1, 3, 3, 4)));
+        assert_ne!(base, first(SampleRng::seeded(1, 2, 4, 4)));
+        assert_ne!(base, first(SampleRng::seeded(1, 2, 3, 5)));
+        // Swapping the coordinates mustn't land on the same stream either
+        assert_ne!(
+            first(SampleRng::seeded(1, 2, 3, 4)),
+            first(SampleRng::seeded(1, 3, 2, 4))
+        );
+    }
+
+    #[test]
+    fn hit_streams_follow_the_sample() {
+        let hit_draw = |seed| {
+            let mut rng = SampleRng::seeded(seed, 0, 0, 0);
+            let _hits = seed_hits(&mut rng);
+            HitRng.gen::<u64>()
+        };
+        assert_eq!(hit_draw(9), hit_draw(9));
+        assert_ne!(hit_draw(9), hit_draw(10));
+    }
+
+    #[test]
+    fn hit_streams_are_restored() {
+        let mut outer = SampleRng::seeded(1, 0, 0, 0);
+        let _outer = seed_hits(&mut outer);
+        let expected = HIT_STREAM.with_borrow(|stream| stream.clone().unwrap().next_u64());
+        {
+            let mut inner = SampleRng::seeded(2, 0, 0, 0);
+            let _inner = seed_hits(&mut inner);
+        }
+        assert_eq!(HitRng.next_u64(), expected);
+    }
+
+    #[test]
+    fn fills_partial_words() {
+        let mut bytes = [0u8; 11];
+        SampleRng::seeded(5, 0, 0, 0).fill_bytes(&mut bytes);
+        let mut rng = SampleRng::seeded(5, 0, 0, 0);
+        let words = [rng.next_u64().to_le_bytes(), rng.next_u64().to_le_bytes()];
+        assert_eq!(bytes[..8], words[0]);
+        assert_eq!(bytes[8..], words[1][..3]);
+    }
+}
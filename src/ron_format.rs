@@ -0,0 +1,125 @@
+use ron::{
+    error::{Position, SpannedError},
+    extensions::Extensions,
+    ser::PrettyConfig,
+    Deserializer, Options,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// How scene files and material libraries are read: `Some` can be left out around optional
+/// settings, so `fuzz: 0.1` reads as `fuzz: Some(0.1)`
+pub(crate) fn options() -> Options {
+    Options::default().with_default_extension(Extensions::IMPLICIT_SOME)
+}
+
+/// Parses `source` as a `T`, calling `skipped` with the path of every setting `T` doesn't
+/// have, e.g. one written by a newer version, instead of failing on it
+pub(crate) fn parse<'a, T: Deserialize<'a>>(
+    source: &'a str,
+    mut skipped: impl FnMut(String),
+) -> Result<T, SpannedError> {
+    let mut deserializer = Deserializer::from_str_with_options(source, &options())?;
+    let value = serde_ignored::deserialize(&mut deserializer, |path| skipped(path.to_string()))
+        .map_err(|err| deserializer.span_error(err))?;
+    deserializer
+        .end()
+        .map_err(|err| deserializer.span_error(err))?;
+    Ok(value)
+}
+
+/// Like [`parse`] for `part`, a value somewhere inside `source`, with errors placed where they
+/// are in `source`
+pub(crate) fn parse_part<'a, T: Deserialize<'a>>(
+    source: &str,
+    part: &'a str,
+    skipped: impl FnMut(String),
+) -> Result<T, SpannedError> {
+    parse(part, skipped).map_err(|mut err| {
+        let start = position_of(source, part);
+        for position in [&mut err.span.start, &mut err.span.end] {
+            if position.line == 1 {
+                position.col += start.col - 1;
+            }
+            position.line += start.line - 1;
+        }
+        err
+    })
+}
+
+/// Where `part`, a slice of `source`, starts in it
+pub(crate) fn position_of(source: &str, part: &str) -> Position {
+    let offset = (part.as_ptr() as usize)
+        .checked_sub(source.as_ptr() as usize)
+        .filter(|offset| *offset <= source.len())
+        .expect("part is a slice of source");
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Position {
+        line: before.matches('\n').count() + 1,
+        col: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// Where in a file something is, `file:line:column`, or `line L, column C` for text that didn't
+/// come from a file
+pub(crate) fn location(file: Option<&Path>, position: Position) -> String {
+    match file {
+        Some(file) => format!("{}:{}:{}", file.display(), position.line, position.col),
+        None => format!("line {}, column {}", position.line, position.col),
+    }
+}
+
+/// Writes `value` over several indented lines, leaving out `Some` the way [`options`] reads it
+pub(crate) fn to_string<T: Serialize>(value: &T) -> String {
+    let config = PrettyConfig::new()
+        .struct_names(false)
+        .extensions(Extensions::IMPLICIT_SOME);
+    options()
+        .to_string_pretty(value, config)
+        .expect("descriptions only have strings for keys")
+}
+
+/// Named things kept in the order they're written, like a library's materials, written as a
+/// map from their names. A name written twice comes out twice, for the caller to decide which
+/// wins.
+pub(crate) mod named {
+    use serde::{
+        de::{MapAccess, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use std::{fmt, marker::PhantomData};
+
+    pub fn serialize<S: Serializer, T: Serialize>(
+        entries: &[(String, T)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(entries.iter().map(|(name, value)| (name, value)))
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Vec<(String, T)>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        struct Entries<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for Entries<T> {
+            type Value = Vec<(String, T)>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map from names")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(entries)
+            }
+        }
+
+        deserializer.deserialize_map(Entries(PhantomData))
+    }
+}
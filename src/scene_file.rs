@@ -0,0 +1,1301 @@
+use crate::{
+    animation::{Animation, Interpolation, Keyframe},
+    assets::{AssetError, AssetReference, AssetResolver},
+    camera::{Camera, Float, Integrator, RenderFidelity},
+    hittable::{self, LoadOptions, LoadedMeshes, Shape, Sphere, Triangle},
+    include::{self, IncludeError},
+    instance::{Instance, Prototype},
+    material::{Lambertian, Material},
+    material_library::{LibraryError, MaterialLibrary, MaterialSpec},
+    object::ObjectId,
+    ron_format,
+    scene_lights::{AngularProfile, RectLight, SpotLight},
+    scenes, technical,
+    texture::LoadReport,
+    vec3::Vec3,
+    window::{HEIGHT, WIDTH},
+};
+use nalgebra::{Similarity3, Translation3, Unit, UnitQuaternion};
+use rand::{rngs::StdRng, SeedableRng};
+use ron::{error::SpannedError, value::RawValue};
+use serde::{Deserialize, Deserializer};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Version of the scene format that this build understands
+pub const SCENE_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SceneError {
+    /// Something that couldn't be parsed or built, with where it is
+    Malformed {
+        location: String,
+        message: String,
+    },
+    Missing(&'static str),
+    /// The scene's files couldn't be read, or include each other in a loop
+    Include(IncludeError),
+    /// A material library the scene loads, or one of the scene's own materials, is broken
+    Library(LibraryError),
+    /// The scene is nowhere an [`AssetResolver`] looked
+    Asset(AssetError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Malformed { location, message } => write!(f, "{}: {}", location, message),
+            SceneError::Missing(key) => write!(f, "scene has no '{}'", key),
+            SceneError::Include(err) => write!(f, "{}", err),
+            SceneError::Library(err) => write!(f, "{}", err),
+            SceneError::Asset(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl SceneError {
+    /// A RON error in the file at `file`, or in text that didn't come from one
+    fn parse(file: Option<&Path>, err: SpannedError) -> Self {
+        SceneError::Malformed {
+            location: ron_format::location(file, err.span.start),
+            message: err.code.to_string(),
+        }
+    }
+}
+
+impl From<LibraryError> for SceneError {
+    fn from(err: LibraryError) -> Self {
+        SceneError::Library(err)
+    }
+}
+
+impl From<IncludeError> for SceneError {
+    fn from(err: IncludeError) -> Self {
+        SceneError::Include(err)
+    }
+}
+
+/// Where a mesh goes: scaled, then rotated, then moved
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Placement {
+    pub translate: Vec3,
+    /// Degrees around the x, y and z axes, applied in that order
+    pub rotate: Vec3,
+    pub scale: Float,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement {
+            translate: Vec3::zeros(),
+            rotate: Vec3::zeros(),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Placement {
+    pub fn similarity(&self) -> Similarity3<Float> {
+        let rotate = self.rotate.map(Float::to_radians);
+        Similarity3::from_parts(
+            Translation3::from(self.translate),
+            UnitQuaternion::from_euler_angles(rotate.x, rotate.y, rotate.z),
+            self.scale,
+        )
+    }
+}
+
+/// Something a scene file puts in the scene, with materials referred to by name
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum SceneObject {
+    Sphere {
+        material: String,
+        center: Vec3,
+        radius: Float,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    Triangle {
+        material: String,
+        vertices: [Vec3; 3],
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// A square facing up at height `z`
+    Ground {
+        material: String,
+        z: Float,
+        #[serde(default = "ground_size")]
+        size: Float,
+    },
+    /// Every model in an OBJ file, all in one material
+    Mesh {
+        material: String,
+        path: PathBuf,
+        #[serde(default)]
+        placement: Placement,
+        #[serde(default)]
+        options: LoadOptions,
+    },
+    /// A glTF file with the materials it comes with
+    Gltf {
+        path: PathBuf,
+        #[serde(default)]
+        placement: Placement,
+        #[serde(default)]
+        options: LoadOptions,
+    },
+    /// The random field of little spheres from [`scenes::cover_scene`], the same every time if
+    /// it has a seed
+    Cover {
+        grid_i: i16,
+        grid_j: i16,
+        z: Float,
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    /// A [`RectLight`] spanning two edges from a corner
+    RectLight {
+        corner: Vec3,
+        edges: [Vec3; 2],
+        radiance: Vec3,
+        #[serde(default)]
+        double_sided: bool,
+        #[serde(default = "visible")]
+        visible: bool,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// A [`SpotLight`] with cone angles in degrees, shaped by the [`AngularProfile`] in the file
+    /// at `profile` if there is one
+    SpotLight {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner: Float,
+        outer: Float,
+        #[serde(default)]
+        profile: Option<PathBuf>,
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+fn ground_size() -> Float {
+    10000.0
+}
+
+fn visible() -> bool {
+    true
+}
+
+impl SceneObject {
+    /// Makes the paths in the object relative to `directory` instead of to the file it's in
+    fn resolve_paths(&mut self, directory: &Path) {
+        match self {
+            SceneObject::Mesh { path, .. }
+            | SceneObject::Gltf { path, .. }
+            | SceneObject::SpotLight {
+                profile: Some(path),
+                ..
+            } => *path = directory.join(&*path),
+            _ => {}
+        }
+    }
+}
+
+/// A scene described in a text file instead of in code, so it can be changed without
+/// recompiling. A scene file is a [RON](https://github.com/ron-rs/ron) struct, like a
+/// [`MaterialLibrary`]:
+///
+/// ```text
+/// (
+///     version: 1,
+///     include: ["../shared/lights.ron"],
+///     camera: (
+///         center: (3, -5, 0.6),
+///         lookat: (0, 0, 0),
+///         vertical_fov: 20,
+///     ),
+///     libraries: ["../shared/metals.ron"],
+///     materials: {
+///         "floor": Lambertian(
+///             texture: Checker(scale: 3, even: Solid((0.1, 0.1, 0.1)), odd: Solid((0.95, 0.95, 0.95))),
+///         ),
+///     },
+///     objects: [
+///         Ground(material: "floor", z: -0.2),
+///         Sphere(material: "brass", center: (0, 0, 0.5), radius: 0.5, name: "teapot_stand"),
+///         Mesh(
+///             material: "plaster",
+///             path: "models/bunny.obj",
+///             placement: (scale: 12, rotate: (90, 0, 0), translate: (1, 0, 0)),
+///         ),
+///         Gltf(path: "models/old car/scene.gltf", options: (repair_orientation: true)),
+///         Cover(grid_i: 30, grid_j: 30, z: -0.2, seed: 7),
+///         RectLight(
+///             corner: (-1, -1, 3),
+///             edges: ((2, 0, 0), (0, 2, 0)),
+///             radiance: (8, 8, 7.5),
+///             name: "softbox",
+///         ),
+///         SpotLight(
+///             position: (2, -2, 3),
+///             direction: (-1, 1, -1),
+///             intensity: (40, 40, 40),
+///             inner: 15,
+///             outer: 30,
+///             profile: "beam.profile",
+///         ),
+///     ],
+///     animation: [
+///         Translate(object: "teapot_stand", frame: 0, to: (0, 0, 0.5)),
+///         Translate(object: "teapot_stand", frame: 24, to: (1, 0, 0.5), interpolation: Smooth),
+///     ],
+/// )
+/// ```
+///
+/// The camera takes `center` and `lookat` (both required), `up`, `vertical_fov`,
+/// `focus_distance` (the distance to `lookat` by default), `defocus_angle`, `max_depth`, `near`,
+/// `far`, `integrator` and `fidelity`, by name. Its resolution is the preview's, and render jobs
+/// set their own.
+///
+/// `libraries` loads whole [`MaterialLibrary`] files, and `materials` are written just like in
+/// one. Objects are [`SceneObject`]s, which name the material they're made of and can use
+/// materials from any file of the scene. Meshes and glTF files take a [`Placement`] and
+/// [`LoadOptions`], e.g. `thin_open_glass: true` to shade the glass on meshes that aren't
+/// watertight as thin panes. A rect light faces along its first edge crossed with its second,
+/// and `visible: false` lights the scene without showing up in it. A spot light's intensity is
+/// in watts per steradian and its cone angles are in degrees. Settings that can be left out,
+/// like names, are written without `Some`, and settings and objects a version doesn't know about
+/// are skipped with a warning.
+///
+/// `include` pulls in other scene files first, so the file including them can change their
+/// camera and materials. Paths are relative to the file they're in, and files that aren't there
+/// are looked for by the scene's [`AssetResolver`] instead.
+///
+/// `animation` holds keyframes, `Translate` to a position, `Rotate` by `degrees` around an
+/// `axis` or `Scale` by a `factor` at a `frame`, each eased `Linear`ly by default or `Smooth`ly.
+/// They move named spheres and triangles, turning and scaling them around their middle, and glTF
+/// meshes that several nodes share. Render jobs play them on each frame, and a job's own
+/// keyframes for an object replace the scene's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneFile {
+    pub center: Option<Vec3>,
+    pub lookat: Option<Vec3>,
+    pub up: Vec3,
+    pub vertical_fov: Float,
+    pub focus_distance: Option<Float>,
+    pub defocus_angle: Float,
+    pub max_depth: usize,
+    pub near: Float,
+    pub far: Float,
+    pub integrator: Option<Integrator>,
+    pub fidelity: Option<RenderFidelity>,
+    pub materials: MaterialLibrary,
+    /// Each with where it's written, for errors while building it
+    pub objects: Vec<(String, SceneObject)>,
+    pub animation: Animation,
+    /// Settings that were skipped while loading, e.g. ones added in a newer version
+    pub warnings: Vec<String>,
+    /// Finds the meshes, images and libraries the scene refers to
+    pub assets: AssetResolver,
+    /// The scene file and every file it includes or loads materials from, in the order they're
+    /// read
+    pub sources: Vec<PathBuf>,
+}
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        SceneFile {
+            center: None,
+            lookat: None,
+            up: Vec3::z(),
+            vertical_fov: 20.0,
+            focus_distance: None,
+            defocus_angle: 0.0,
+            max_depth: scenes::MAX_DEPTH,
+            near: 0.0,
+            far: Float::MAX,
+            integrator: None,
+            fidelity: None,
+            materials: MaterialLibrary::default(),
+            objects: Vec::new(),
+            animation: Animation::default(),
+            warnings: Vec::new(),
+            assets: AssetResolver::from_env(Path::new("")),
+            sources: Vec::new(),
+        }
+    }
+}
+
+/// One scene file as it's written. Objects and keyframes are kept as written until they're
+/// parsed one at a time, so each can be found again in the file.
+#[derive(Deserialize)]
+struct SceneDescription<'a> {
+    #[serde(default = "first_version")]
+    version: u32,
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    camera: CameraSettings,
+    #[serde(default)]
+    libraries: Vec<PathBuf>,
+    #[serde(default, with = "ron_format::named")]
+    materials: Vec<(String, MaterialSpec)>,
+    #[serde(default, borrow)]
+    objects: Vec<&'a RawValue>,
+    #[serde(default, borrow)]
+    animation: Vec<&'a RawValue>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// The camera settings in one scene file, which replace the ones in files it includes
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct CameraSettings {
+    center: Option<Vec3>,
+    lookat: Option<Vec3>,
+    up: Option<Vec3>,
+    vertical_fov: Option<Float>,
+    focus_distance: Option<Float>,
+    defocus_angle: Option<Float>,
+    max_depth: Option<usize>,
+    near: Option<Float>,
+    far: Option<Float>,
+    #[serde(deserialize_with = "integrator")]
+    integrator: Option<Integrator>,
+    #[serde(deserialize_with = "fidelity")]
+    fidelity: Option<RenderFidelity>,
+}
+
+fn integrator<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Integrator>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Integrator::from_name(&name)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown integrator '{}'", name)))
+}
+
+fn fidelity<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<RenderFidelity>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    RenderFidelity::from_name(&name)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown fidelity '{}'", name)))
+}
+
+/// A keyframe as it's written in a scene file's `animation`
+#[derive(Deserialize)]
+enum KeyframeDescription {
+    Translate {
+        object: String,
+        frame: Float,
+        to: Vec3,
+        #[serde(default)]
+        interpolation: Interpolation,
+    },
+    Rotate {
+        object: String,
+        frame: Float,
+        axis: Vec3,
+        degrees: Float,
+        #[serde(default)]
+        interpolation: Interpolation,
+    },
+    Scale {
+        object: String,
+        frame: Float,
+        factor: Float,
+        #[serde(default)]
+        interpolation: Interpolation,
+    },
+}
+
+impl KeyframeDescription {
+    fn add_to(self, animation: &mut Animation) -> Result<(), String> {
+        match self {
+            KeyframeDescription::Translate {
+                object,
+                frame,
+                to,
+                interpolation,
+            } => animation.track_mut(&object).translation.insert(Keyframe {
+                frame,
+                value: to,
+                interpolation,
+            }),
+            KeyframeDescription::Rotate {
+                object,
+                frame,
+                axis,
+                degrees,
+                interpolation,
+            } => {
+                let axis = Unit::try_new(axis, Float::EPSILON)
+                    .ok_or_else(|| "rotation axis can't be zero".to_string())?;
+                animation.track_mut(&object).rotation.insert(Keyframe {
+                    frame,
+                    value: UnitQuaternion::from_axis_angle(&axis, degrees.to_radians()),
+                    interpolation,
+                })
+            }
+            KeyframeDescription::Scale {
+                object,
+                frame,
+                factor,
+                interpolation,
+            } => animation.track_mut(&object).scale.insert(Keyframe {
+                frame,
+                value: factor,
+                interpolation,
+            }),
+        }
+        .map_err(|err| err.to_string())
+    }
+}
+
+/// What one scene file adds to the scene, parsed and with its paths resolved
+struct ScenePart {
+    /// The file, or `scene` for text that didn't come from one
+    location: String,
+    version: u32,
+    include: Vec<PathBuf>,
+    camera: CameraSettings,
+    libraries: Vec<PathBuf>,
+    materials: Vec<(String, MaterialSpec)>,
+    /// Each with where it's written
+    objects: Vec<(String, SceneObject)>,
+    keyframes: Vec<(String, KeyframeDescription)>,
+    /// Settings and objects that were skipped, with where they're written
+    skipped: Vec<String>,
+}
+
+impl ScenePart {
+    /// Parses `source`, the text of `file` if it came from one, with relative paths in it
+    /// resolved against `directory`
+    fn parse(source: &str, file: Option<&Path>, directory: &Path) -> Result<Self, SceneError> {
+        let mut skipped = Vec::new();
+        let description: SceneDescription =
+            ron_format::parse(source, |setting| skipped.push(setting))
+                .map_err(|err| SceneError::parse(file, err))?;
+        let location = match file {
+            Some(file) => file.display().to_string(),
+            None => "scene".to_string(),
+        };
+        let mut skipped: Vec<String> = skipped
+            .into_iter()
+            .map(|setting| format!("{}: skipped unknown setting '{}'", location, setting))
+            .collect();
+
+        let objects = parse_parts::<SceneObject>(
+            source,
+            file,
+            &description.objects,
+            "SceneObject",
+            &mut skipped,
+        )?
+        .into_iter()
+        .map(|(location, mut object)| {
+            object.resolve_paths(directory);
+            (location, object)
+        })
+        .collect();
+        let keyframes = parse_parts(
+            source,
+            file,
+            &description.animation,
+            "KeyframeDescription",
+            &mut skipped,
+        )?;
+        let materials = description
+            .materials
+            .into_iter()
+            .map(|(name, mut spec)| {
+                spec.resolve_images(directory);
+                (name, spec)
+            })
+            .collect();
+        Ok(ScenePart {
+            location,
+            version: description.version,
+            include: description.include,
+            camera: description.camera,
+            libraries: description
+                .libraries
+                .iter()
+                .map(|library| directory.join(library))
+                .collect(),
+            materials,
+            objects,
+            keyframes,
+            skipped,
+        })
+    }
+}
+
+/// Parses each of `parts`, which are in `source`, as a `T` with where it's written. `T` is the
+/// enum named `kinds`, and parts of a kind it doesn't have, e.g. added in a newer version, are
+/// skipped and noted in `skipped` along with the settings skipped in the rest.
+fn parse_parts<'a, T: Deserialize<'a>>(
+    source: &str,
+    file: Option<&Path>,
+    parts: &[&'a RawValue],
+    kinds: &str,
+    skipped: &mut Vec<String>,
+) -> Result<Vec<(String, T)>, SceneError> {
+    let mut parsed = Vec::new();
+    for part in parts {
+        let part = part.trim().get_ron();
+        let location = ron_format::location(file, ron_format::position_of(source, part));
+        let mut skipped_settings = Vec::new();
+        match ron_format::parse_part(source, part, |setting| skipped_settings.push(setting)) {
+            Ok(value) => parsed.push((location.clone(), value)),
+            Err(SpannedError {
+                code: ron::Error::NoSuchEnumVariant { found, outer, .. },
+                ..
+            }) if outer.as_deref() == Some(kinds) => {
+                skipped.push(format!(
+                    "{}: skipped unknown {} '{}'",
+                    location, kinds, found
+                ));
+                continue;
+            }
+            Err(err) => return Err(SceneError::parse(file, err)),
+        }
+        skipped.extend(
+            skipped_settings
+                .into_iter()
+                .map(|setting| format!("{}: skipped unknown setting '{}'", location, setting)),
+        );
+    }
+    Ok(parsed)
+}
+
+impl SceneFile {
+    /// Loads a scene file along with the files it `include`s
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let mut scene = SceneFile {
+            assets: AssetResolver::from_env(path.parent().unwrap_or(Path::new(""))),
+            sources: vec![path.to_path_buf()],
+            ..SceneFile::default()
+        };
+        for (file, part) in SceneFile::read_parts(&[path])? {
+            if !scene.sources.contains(&file) {
+                scene.sources.push(file);
+            }
+            scene.add_part(part)?;
+        }
+        Ok(scene)
+    }
+
+    /// Parses a scene, resolving relative paths and includes against `directory`
+    pub fn parse(source: &str, directory: &Path) -> Result<Self, SceneError> {
+        let part = ScenePart::parse(source, None, directory)?;
+        let includes: Vec<PathBuf> = part
+            .include
+            .iter()
+            .map(|included| directory.join(included))
+            .collect();
+        let mut scene = SceneFile {
+            assets: AssetResolver::from_env(directory),
+            ..SceneFile::default()
+        };
+        for (file, included) in SceneFile::read_parts(&includes)? {
+            scene.sources.push(file);
+            scene.add_part(included)?;
+        }
+        scene.add_part(part)?;
+        Ok(scene)
+    }
+
+    /// Reads the scene files at `paths` and the files they include, each after the ones it
+    /// includes
+    fn read_parts(paths: &[impl AsRef<Path>]) -> Result<Vec<(PathBuf, ScenePart)>, SceneError> {
+        include::read_descriptions(paths, |file, source| {
+            let directory = file.parent().unwrap_or(Path::new(""));
+            let part = ScenePart::parse(source, Some(file), directory)?;
+            let includes = part.include.clone();
+            Ok((part, includes))
+        })
+    }
+
+    /// Adds what one of the scene's files describes, replacing the camera settings and
+    /// materials earlier files set
+    fn add_part(&mut self, part: ScenePart) -> Result<(), SceneError> {
+        if part.version > SCENE_VERSION {
+            self.warnings.push(format!(
+                "{}: scene is version {} but only version {} is understood, so newer settings \
+                 will be skipped",
+                part.location, part.version, SCENE_VERSION
+            ));
+        }
+        self.warnings.extend(part.skipped);
+
+        let camera = part.camera;
+        self.center = camera.center.or(self.center);
+        self.lookat = camera.lookat.or(self.lookat);
+        self.up = camera.up.unwrap_or(self.up);
+        self.vertical_fov = camera.vertical_fov.unwrap_or(self.vertical_fov);
+        self.focus_distance = camera.focus_distance.or(self.focus_distance);
+        self.defocus_angle = camera.defocus_angle.unwrap_or(self.defocus_angle);
+        self.max_depth = camera.max_depth.unwrap_or(self.max_depth);
+        self.near = camera.near.unwrap_or(self.near);
+        self.far = camera.far.unwrap_or(self.far);
+        self.integrator = camera.integrator.or(self.integrator);
+        self.fidelity = camera.fidelity.or(self.fidelity);
+
+        for library in &part.libraries {
+            let library =
+                self.assets
+                    .resolve_file(library)
+                    .map_err(|err| SceneError::Malformed {
+                        location: part.location.clone(),
+                        message: err.to_string(),
+                    })?;
+            let (library, sources) = MaterialLibrary::load_all_with_sources(&[library.path])?;
+            for source in sources {
+                if !self.sources.contains(&source) {
+                    self.sources.push(source);
+                }
+            }
+            self.add_materials(library);
+        }
+        for (name, spec) in part.materials {
+            self.materials.set(&name, spec);
+        }
+
+        self.objects.extend(part.objects);
+        for (location, keyframe) in part.keyframes {
+            keyframe
+                .add_to(&mut self.animation)
+                .map_err(|message| SceneError::Malformed { location, message })?;
+        }
+        Ok(())
+    }
+
+    /// Every file the scene needs to render, as written in it: its sources, meshes, glTF files
+    /// and the images its materials use. Images compiled into the binary are included, since
+    /// they're still found by path first.
+    pub fn asset_references(&self) -> Vec<AssetReference> {
+        let mut references: Vec<AssetReference> = self
+            .sources
+            .iter()
+            .map(|source| AssetReference {
+                location: "scene".to_string(),
+                kind: "source",
+                path: source.clone(),
+            })
+            .collect();
+        for (location, object) in &self.objects {
+            let (kind, path) = match object {
+                SceneObject::Mesh { path, .. } => ("mesh", path),
+                SceneObject::Gltf { path, .. } => ("gltf", path),
+                SceneObject::SpotLight {
+                    profile: Some(path),
+                    ..
+                } => ("profile", path),
+                _ => continue,
+            };
+            references.push(AssetReference {
+                location: location.clone(),
+                kind,
+                path: path.clone(),
+            });
+        }
+        references.extend(self.materials.images().into_iter().map(|(material, path)| {
+            AssetReference {
+                location: format!("material {}", material),
+                kind: "image",
+                path: path.to_path_buf(),
+            }
+        }));
+        references
+    }
+
+    /// Adds every material in `library`, replacing any the scene already has with the same name
+    fn add_materials(&mut self, library: MaterialLibrary) {
+        for (name, spec) in library.materials {
+            self.materials.set(&name, spec);
+        }
+        self.warnings.extend(library.warnings);
+    }
+
+    /// Makes the scene's camera, at the preview's resolution
+    pub fn camera(&self) -> Result<Camera, SceneError> {
+        let center = self.center.ok_or(SceneError::Missing("center"))?;
+        let lookat = self.lookat.ok_or(SceneError::Missing("lookat"))?;
+        let mut camera = Camera::new(
+            center,
+            lookat,
+            self.up,
+            self.focus_distance
+                .unwrap_or_else(|| center.metric_distance(&lookat)),
+            self.defocus_angle,
+            WIDTH as usize,
+            HEIGHT as usize,
+            32,
+            self.max_depth,
+            self.vertical_fov,
+            self.near..self.far,
+        );
+        match self.integrator {
+            Some(Integrator::Technical) => technical::preset(&mut camera),
+            Some(integrator) => camera.integrator = integrator,
+            None => {}
+        }
+        if let Some(fidelity) = self.fidelity {
+            camera.fidelity = fidelity;
+        }
+        Ok(camera)
+    }
+
+    /// Builds the camera and every object in the scene, with files found by its
+    /// [`AssetResolver`]. Images and meshes that load with problems are recorded in `report`,
+    /// but meshes that can't be found at all are errors.
+    pub fn build(&self, report: &mut LoadReport) -> Result<(Camera, Vec<Shape>), SceneError> {
+        let camera = self.camera()?;
+        let materials = self.materials.build_all(&self.assets, report)?;
+        let mut shapes = Vec::new();
+        for (location, object) in &self.objects {
+            let malformed = |message: String| SceneError::Malformed {
+                location: location.clone(),
+                message,
+            };
+            let material = |name: &String| -> Result<Arc<Material>, SceneError> {
+                materials
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| malformed(format!("no material named '{}'", name)))
+            };
+            let existing = |path: &PathBuf| -> Result<String, SceneError> {
+                let file = self
+                    .assets
+                    .resolve_file(path)
+                    .map_err(|err| malformed(err.to_string()))?;
+                Ok(file.path.to_string_lossy().into_owned())
+            };
+            let named = |name: &Option<String>| name.as_deref().map(ObjectId::register);
+            let animated = |name: &Option<String>| {
+                name.as_ref().is_some_and(|name| {
+                    self.animation
+                        .tracks
+                        .iter()
+                        .any(|track| track.object == *name)
+                })
+            };
+            match object {
+                SceneObject::Sphere {
+                    material: name,
+                    center,
+                    radius,
+                    name: object,
+                } if animated(object) => {
+                    let sphere = Sphere::try_new(Vec3::zeros(), *radius, material(name)?)
+                        .map_err(|err| malformed(err.to_string()))?;
+                    shapes.push(animatable(sphere.into(), *center, named(object)));
+                }
+                SceneObject::Sphere {
+                    material: name,
+                    center,
+                    radius,
+                    name: object,
+                } => {
+                    let mut sphere = Sphere::try_new(*center, *radius, material(name)?)
+                        .map_err(|err| malformed(err.to_string()))?;
+                    if let Some(object) = named(object) {
+                        sphere = sphere.with_object(object);
+                    }
+                    shapes.push(sphere.into());
+                }
+                SceneObject::Triangle {
+                    material: name,
+                    vertices: [a, b, c],
+                    name: object,
+                } if animated(object) => {
+                    let middle = (a + b + c) / 3.0;
+                    let triangle =
+                        Triangle::try_new(a - middle, b - middle, c - middle, material(name)?)
+                            .map_err(|err| malformed(err.to_string()))?;
+                    shapes.push(animatable(triangle.into(), middle, named(object)));
+                }
+                SceneObject::Triangle {
+                    material: name,
+                    vertices: [a, b, c],
+                    name: object,
+                } => {
+                    let mut triangle = Triangle::try_new(*a, *b, *c, material(name)?)
+                        .map_err(|err| malformed(err.to_string()))?;
+                    if let Some(object) = named(object) {
+                        triangle = triangle.with_object(object);
+                    }
+                    shapes.push(triangle.into());
+                }
+                SceneObject::Ground {
+                    material: name,
+                    z,
+                    size,
+                } => shapes.extend(scenes::generate_ground_plane(
+                    *size,
+                    *size,
+                    *z,
+                    material(name)?,
+                    true,
+                )),
+                SceneObject::Mesh {
+                    material: name,
+                    path,
+                    placement,
+                    options,
+                } => {
+                    let (meshes, mesh_report) =
+                        hittable::load_obj(&existing(path)?, material(name)?, None, false, options)
+                            .map_err(|err| malformed(format!("{}: {}", path.display(), err)))?;
+                    report.merge(mesh_report);
+                    let loaded = LoadedMeshes {
+                        meshes,
+                        instances: Vec::new(),
+                    };
+                    shapes.extend(loaded.place(&placement.similarity()).into_shapes());
+                }
+                SceneObject::Gltf {
+                    path,
+                    placement,
+                    options,
+                } => {
+                    // glTF files bring their own materials, so the loader never uses this
+                    let fallback = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+                    let (loaded, gltf_report) =
+                        hittable::load_gltf(&existing(path)?, fallback, options)
+                            .map_err(|err| malformed(format!("{}: {}", path.display(), err)))?;
+                    report.merge(gltf_report);
+                    shapes.extend(loaded.place(&placement.similarity()).into_shapes());
+                }
+                SceneObject::Cover {
+                    grid_i,
+                    grid_j,
+                    z,
+                    seed,
+                } => {
+                    let mut rng = match seed {
+                        Some(seed) => StdRng::seed_from_u64(*seed),
+                        None => StdRng::from_entropy(),
+                    };
+                    shapes.extend(scenes::cover_scene(*grid_i, *grid_j, &camera, *z, &mut rng));
+                }
+                SceneObject::RectLight {
+                    corner,
+                    edges: [u, v],
+                    radiance,
+                    double_sided,
+                    visible,
+                    name: object,
+                } => {
+                    let mut light = RectLight::new(*corner, *u, *v, *radiance)
+                        .with_double_sided(*double_sided)
+                        .with_visible(*visible);
+                    if let Some(object) = named(object) {
+                        light = light.with_object(object);
+                    }
+                    shapes.push(light.into());
+                }
+                SceneObject::SpotLight {
+                    position,
+                    direction,
+                    intensity,
+                    inner,
+                    outer,
+                    profile,
+                    name: object,
+                } => {
+                    let mut light =
+                        SpotLight::new(*position, *direction, *intensity, *inner, *outer);
+                    if let Some(profile) = profile {
+                        let profile = AngularProfile::load(Path::new(&existing(profile)?))
+                            .map_err(malformed)?;
+                        light = light.with_profile(Arc::new(profile));
+                    }
+                    if let Some(object) = named(object) {
+                        light = light.with_object(object);
+                    }
+                    shapes.push(light.into());
+                }
+            }
+        }
+        Ok((camera, shapes))
+    }
+}
+
+/// `shape`, made around the origin, as an instance of its own resting at `middle`, so an
+/// animation can move it without rebuilding it and turns and scales it around its middle
+fn animatable(shape: Shape, middle: Vec3, object: Option<ObjectId>) -> Shape {
+    let prototype = Arc::new(Prototype::new(vec![shape]));
+    let rest = Similarity3::from_parts(Translation3::from(middle), UnitQuaternion::identity(), 1.0);
+    Instance::new(prototype, rest)
+        .with_object(object.expect("only named objects are animated"))
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material_library::TextureSpec;
+    use crate::scratch::{scratch, write};
+    use std::fs;
+
+    /// A scene with a camera and a material named `gray` around `objects`
+    fn with_objects(objects: &str) -> String {
+        format!(
+            "(\n\
+             camera: (center: (0, -5, 1), lookat: (0, 0, 0)),\n\
+             materials: {{\"gray\": Lambertian(texture: Solid((0.5, 0.5, 0.5)))}},\n\
+             objects: [\n{}\n],\n\
+             )",
+            objects
+        )
+    }
+
+    fn parse_error(source: &str) -> String {
+        SceneFile::parse(source, Path::new(""))
+            .expect_err("the scene is malformed")
+            .to_string()
+    }
+
+    /// Builds `objects` under a camera and a material named `gray`, returning the error
+    fn build_error(objects: &str) -> String {
+        let scene = SceneFile::parse(&with_objects(objects), Path::new("")).unwrap();
+        scene
+            .build(&mut LoadReport::default())
+            .err()
+            .expect("the scene is invalid")
+            .to_string()
+    }
+
+    #[test]
+    fn every_setting_is_read() {
+        let source = r#"
+            // A bit of everything
+            (
+                version: 1,
+                camera: (
+                    center: (3, -5, 0.6),
+                    lookat: (0, 0, 0),
+                    up: (0, 1, 0),
+                    vertical_fov: 35,
+                    focus_distance: 4.5,
+                    max_depth: 8,
+                    integrator: "technical",
+                ),
+                materials: {
+                    "floor": Lambertian(
+                        texture: Checker(scale: 3, even: Solid((0, 0, 0)), odd: Image("tiles.png")),
+                    ),
+                },
+                objects: [
+                    Ground(material: "floor", z: -0.2),
+                    Mesh(
+                        material: "floor",
+                        path: "models/bunny.obj",
+                        placement: (scale: 12, rotate: (90, 0, 0)),
+                        options: (repair_orientation: true, flat_shading: true),
+                    ),
+                    RectLight(corner: (-1, -1, 3), edges: ((2, 0, 0), (0, 2, 0)), radiance: (8, 8, 7.5), visible: false),
+                    SpotLight(position: (2, -2, 3), direction: (-1, 1, -1), intensity: (40, 40, 40), inner: 15, outer: 30, profile: "beam.profile"),
+                ],
+                animation: [
+                    Scale(object: "ball", frame: 12, factor: 2, interpolation: Smooth),
+                ],
+            )
+        "#;
+        let scene = SceneFile::parse(source, Path::new("assets")).unwrap();
+        assert_eq!(scene.center, Some(Vec3::new(3.0, -5.0, 0.6)));
+        assert_eq!(scene.up, Vec3::y());
+        assert_eq!(scene.vertical_fov, 35.0);
+        assert_eq!(scene.focus_distance, Some(4.5));
+        assert_eq!(scene.max_depth, 8);
+        assert_eq!(scene.integrator, Some(Integrator::Technical));
+        // Left at their defaults
+        assert_eq!(scene.defocus_angle, 0.0);
+        assert_eq!(scene.fidelity, None);
+        assert!(scene.warnings.is_empty());
+
+        // Relative paths are relative to the scene
+        assert_eq!(
+            scene.materials.images(),
+            [("floor", Path::new("assets/tiles.png"))]
+        );
+        let objects: Vec<&SceneObject> = scene.objects.iter().map(|(_, object)| object).collect();
+        assert_eq!(
+            objects,
+            [
+                &SceneObject::Ground {
+                    material: "floor".to_string(),
+                    z: -0.2,
+                    size: 10000.0,
+                },
+                &SceneObject::Mesh {
+                    material: "floor".to_string(),
+                    path: PathBuf::from("assets/models/bunny.obj"),
+                    placement: Placement {
+                        rotate: Vec3::new(90.0, 0.0, 0.0),
+                        scale: 12.0,
+                        ..Placement::default()
+                    },
+                    options: LoadOptions {
+                        repair_orientation: true,
+                        flat_shading: true,
+                        ..LoadOptions::default()
+                    },
+                },
+                &SceneObject::RectLight {
+                    corner: Vec3::new(-1.0, -1.0, 3.0),
+                    edges: [Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)],
+                    radiance: Vec3::new(8.0, 8.0, 7.5),
+                    double_sided: false,
+                    visible: false,
+                    name: None,
+                },
+                &SceneObject::SpotLight {
+                    position: Vec3::new(2.0, -2.0, 3.0),
+                    direction: Vec3::new(-1.0, 1.0, -1.0),
+                    intensity: Vec3::new(40.0, 40.0, 40.0),
+                    inner: 15.0,
+                    outer: 30.0,
+                    profile: Some(PathBuf::from("assets/beam.profile")),
+                    name: None,
+                },
+            ]
+        );
+        // Objects remember where they're written, past the comment before the scene
+        let locations: Vec<&str> = scene.objects.iter().map(|(at, _)| at.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "line 20, column 21",
+                "line 21, column 21",
+                "line 27, column 21",
+                "line 28, column 21"
+            ]
+        );
+        assert_eq!(scene.animation.tracks.len(), 1);
+        assert_eq!(scene.animation.tracks[0].object, "ball");
+    }
+
+    #[test]
+    fn included_files_are_overridden_by_the_files_including_them() {
+        let directory = scratch("includes");
+        write(
+            &directory.join("shared/base.ron"),
+            r#"(
+                camera: (center: (0, -5, 1), lookat: (0, 0, 0), vertical_fov: 40),
+                libraries: ["metals.ron"],
+                materials: {
+                    "gray": Lambertian(texture: Solid((0.5, 0.5, 0.5))),
+                    "wood": Lambertian(texture: Image("wood.png")),
+                },
+                objects: [Sphere(material: "gray", center: (0, 0, 0), radius: 1)],
+            )"#,
+        );
+        write(
+            &directory.join("shared/metals.ron"),
+            r#"(materials: {"brass": Metal(texture: Solid((0.8, 0.6, 0.2)))})"#,
+        );
+        let main = directory.join("main.ron");
+        write(
+            &main,
+            r#"(
+                include: ["shared/base.ron"],
+                camera: (vertical_fov: 25),
+                materials: {"gray": Metal(texture: Solid((0.9, 0.9, 0.9)), fuzz: 0.1)},
+                objects: [Sphere(material: "wood", center: (0, 0, 2), radius: 1)],
+            )"#,
+        );
+        let scene = SceneFile::load(&main).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            scene.sources,
+            [
+                main.clone(),
+                directory.join("shared/base.ron"),
+                directory.join("shared/metals.ron")
+            ]
+        );
+        assert!(scene.materials.get("brass").is_some());
+        // The camera settings the including file doesn't change come from the included one
+        assert_eq!(scene.center, Some(Vec3::new(0.0, -5.0, 1.0)));
+        assert_eq!(scene.vertical_fov, 25.0);
+        assert_eq!(
+            scene.materials.get("gray"),
+            Some(&MaterialSpec::Metal {
+                texture: TextureSpec::Solid(Vec3::new(0.9, 0.9, 0.9)),
+                fuzz: Some(0.1),
+            })
+        );
+        // Images are relative to the file they're written in
+        assert_eq!(
+            scene.materials.images(),
+            [("wood", &*directory.join("shared/wood.png"))]
+        );
+        let locations: Vec<String> = scene.objects.iter().map(|(at, _)| at.clone()).collect();
+        assert_eq!(
+            locations,
+            [
+                format!("{}:8:27", directory.join("shared/base.ron").display()),
+                format!("{}:5:27", main.display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_errors_say_where_they_are() {
+        assert_eq!(
+            parse_error("(\n  camera: (center: (0, 0, 0)\n  objects: [],\n)"),
+            "line 3, column 3: Expected comma"
+        );
+        assert_eq!(
+            parse_error(&with_objects(
+                "Sphere(material: \"gray\", center: (0, 0, 0), radius: big)"
+            )),
+            "line 5, column 52: Expected float"
+        );
+        assert_eq!(
+            parse_error(&with_objects(
+                "Sphere(material: \"gray\", center: (0, 0), radius: 1)"
+            )),
+            "line 5, column 39: Expected a matrix array but found 2 elements instead"
+        );
+    }
+
+    #[test]
+    fn bad_settings_say_where_they_are() {
+        let directory = scratch("bad-settings");
+        let path = directory.join("scene.ron");
+        write(&path, "(\n  camera: (integrator: \"raymarching\"),\n)");
+        let err = SceneFile::load(&path).unwrap_err().to_string();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            err,
+            format!("{}:2:25: unknown integrator 'raymarching'", path.display())
+        );
+
+        assert_eq!(
+            parse_error(&with_objects("Sphere(material: \"gray\", radius: 1)")),
+            "line 5, column 35: Unexpected missing field named `center` in `Sphere`"
+        );
+        assert_eq!(
+            parse_error(
+                "(animation: [\n  Rotate(object: \"ball\", frame: 0, axis: (0, 0, 0), degrees: 90),\n])"
+            ),
+            "line 2, column 3: rotation axis can't be zero"
+        );
+        assert_eq!(
+            parse_error(
+                "(animation: [\n  Scale(object: \"ball\", frame: 1, factor: 2),\n  Scale(object: \"ball\", frame: 1, factor: 3),\n])"
+            ),
+            "line 3, column 3: there's already a keyframe on frame 1"
+        );
+    }
+
+    #[test]
+    fn unknown_settings_and_objects_are_skipped_with_a_warning() {
+        let source = r#"(
+            version: 2,
+            camera: (center: (0, -5, 1), lookat: (0, 0, 0), exposure: 2),
+            objects: [
+                Torus(material: "gray", radius: 1),
+                Sphere(material: "gray", center: (0, 0, 0), radius: 1, shininess: 4),
+            ],
+        )"#;
+        let scene = SceneFile::parse(source, Path::new("")).unwrap();
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(
+            scene.warnings,
+            [
+                "scene: scene is version 2 but only version 1 is understood, so newer settings \
+                 will be skipped",
+                "scene: skipped unknown setting 'camera.exposure'",
+                "line 5, column 17: skipped unknown SceneObject 'Torus'",
+                "line 6, column 17: skipped unknown setting 'shininess'",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_shapes_are_reported_where_they_are() {
+        assert_eq!(
+            build_error("Sphere(material: \"gray\", center: (0, 0, 0), radius: 0)"),
+            "line 5, column 1: radius 0 isn't positive"
+        );
+        assert_eq!(
+            build_error(
+                "  Sphere(material: \"gray\", center: (0, 0, 0), radius: -1, name: \"ball\")"
+            ),
+            "line 5, column 3: radius -1 isn't positive"
+        );
+        assert_eq!(
+            build_error(
+                "Triangle(material: \"gray\", vertices: ((0, 0, 0), (1, 0, 0), (2, 0, 0)))"
+            ),
+            "line 5, column 1: triangle has no area"
+        );
+        assert_eq!(
+            build_error("Sphere(material: \"grey\", center: (0, 0, 0), radius: 1)"),
+            "line 5, column 1: no material named 'grey'"
+        );
+    }
+
+    #[test]
+    fn broken_meshes_are_errors_where_they_are_used() {
+        let directory = scratch("broken-meshes");
+        // The face uses a vertex the file doesn't have
+        write(
+            &directory.join("broken.obj"),
+            "o broken\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 9\n",
+        );
+        write(&directory.join("broken.gltf"), "{ not json");
+        let build_error = |objects: &str| {
+            let scene = SceneFile::parse(&with_objects(objects), &directory).unwrap();
+            scene
+                .build(&mut LoadReport::default())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        let missing = build_error("Mesh(material: \"gray\", path: \"missing.obj\")");
+        let obj = build_error("Mesh(material: \"gray\", path: \"broken.obj\")");
+        let gltf = build_error("Gltf(path: \"broken.gltf\")");
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(
+            missing.starts_with("line 5, column 1: ") && missing.contains("missing.obj"),
+            "{}",
+            missing
+        );
+        assert_eq!(
+            obj,
+            format!(
+                "line 5, column 1: {}: face vertex index out of bounds",
+                directory.join("broken.obj").display()
+            )
+        );
+        assert!(
+            gltf.starts_with(&format!(
+                "line 5, column 1: {}: ",
+                directory.join("broken.gltf").display()
+            )),
+            "{}",
+            gltf
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Lights that aren't just emissive surfaces: rectangular area lights like softboxes, and spot
+//! lights with a cone and optionally a measured angular profile. Both go in a scene as shapes,
+//! and the path tracer samples them directly from diffuse surfaces.
+
+use crate::{
+    camera::Float,
+    hittable::Hit,
+    intersection::Intersection,
+    material::{DiffuseLight, Material},
+    object::ObjectId,
+    sky_importance::SkySample,
+    texture::SolidColor,
+    vec3::{Point3, Ray, Vec2, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+};
+use rand::Rng;
+use std::{ops::Range, path::Path, sync::Arc};
+
+/// A parallelogram giving off the same radiance everywhere on it, from its front or from both
+/// sides. Visible lights can be hit like any surface, so they show up in reflections and in the
+/// camera as a bright rectangle. Hidden ones only light the scene.
+pub struct RectLight {
+    corner: Point3,
+    /// Edges from `corner` to its two neighbors
+    edges: [Vec3; 2],
+    /// Unit normal of the front, along the cross product of the edges
+    normal: Vec3,
+    radiance: Vec3,
+    /// Gives off `radiance` from its front, shared by every hit
+    pub material: Arc<Material>,
+    pub double_sided: bool,
+    pub visible: bool,
+    node_index: usize,
+    /// The scene object this light belongs to
+    pub object: ObjectId,
+}
+
+impl RectLight {
+    /// Returns a visible, one-sided light spanning `u` and `v` from `corner`, whose front faces
+    /// along `u` cross `v`
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, radiance: Vec3) -> Self {
+        let material = DiffuseLight::new(SolidColor::new(radiance).into()).into();
+        RectLight {
+            corner,
+            edges: [u, v],
+            normal: u.cross(&v).try_normalize(0.0).unwrap_or_else(Vec3::z),
+            radiance,
+            material: Arc::new(material),
+            double_sided: false,
+            visible: true,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+
+    pub fn with_visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    pub fn radiance(&self) -> Vec3 {
+        self.radiance
+    }
+
+    pub fn area(&self) -> Float {
+        self.edges[0].cross(&self.edges[1]).norm()
+    }
+
+    /// Maps `(s, t)` in the unit square to a point on the light, evenly by area, and returns it
+    /// with the front's normal and its texture coordinates, which are `(s, t)`
+    pub fn surface_point(&self, s: Float, t: Float) -> (Point3, Vec3, Vec2) {
+        let point = self.corner + self.edges[0] * s + self.edges[1] * t;
+        (point, self.normal, Vec2::new(s, t))
+    }
+
+    /// The `(s, t)` that [`RectLight::surface_point`] maps to `point` on the light
+    pub fn surface_coordinates(&self, point: &Point3) -> (Float, Float) {
+        let [u, v] = self.edges;
+        let offset = point - self.corner;
+        let (d00, d01, d11) = (u.dot(&u), u.dot(&v), v.dot(&v));
+        let (d20, d21) = (offset.dot(&u), offset.dot(&v));
+        let denominator = d00 * d11 - d01 * d01;
+        if denominator <= 0.0 {
+            return (0.0, 0.0);
+        }
+        (
+            (d11 * d20 - d01 * d21) / denominator,
+            (d00 * d21 - d01 * d20) / denominator,
+        )
+    }
+
+    /// Whether `point` lies on the light, give or take `tolerance`
+    pub fn contains(&self, point: &Point3, tolerance: Float) -> bool {
+        if (point - self.corner).dot(&self.normal).abs() > tolerance {
+            return false;
+        }
+        let (s, t) = self.surface_coordinates(point);
+        let [u, v] = self.edges;
+        let slack = tolerance / u.norm().min(v.norm()).max(Float::MIN_POSITIVE);
+        (-slack..=1.0 + slack).contains(&s) && (-slack..=1.0 + slack).contains(&t)
+    }
+
+    /// The radiance the light gives off toward `direction`, which points away from it
+    fn radiance_toward(&self, direction: &Vec3) -> Vec3 {
+        if self.double_sided || direction.dot(&self.normal) > 0.0 {
+            self.radiance
+        } else {
+            Vec3::zeros()
+        }
+    }
+
+    /// Picks a point on the light evenly by area and returns the direction to it from `point`,
+    /// with its probability density per solid angle, along with the point itself. Doesn't check
+    /// whether anything is in the way. `None` if the light can't reach `point`.
+    pub fn sample<R: Rng + ?Sized>(
+        &self,
+        point: &Point3,
+        rng: &mut R,
+    ) -> Option<(SkySample, Point3)> {
+        let (on_light, ..) = self.surface_point(rng.gen(), rng.gen());
+        let direction = (on_light - point).try_normalize(0.0)?;
+        let radiance = self.radiance_toward(&-direction);
+        let pdf = self.pdf(point, &on_light);
+        (radiance != Vec3::zeros() && pdf > 0.0).then_some((
+            SkySample {
+                direction,
+                radiance,
+                pdf,
+            },
+            on_light,
+        ))
+    }
+
+    /// Returns the probability density of [`RectLight::sample`] picking `on_light` from `point`,
+    /// per solid angle
+    pub fn pdf(&self, point: &Point3, on_light: &Point3) -> Float {
+        let to_light = on_light - point;
+        let distance_squared = to_light.norm_squared();
+        let cos_light = (to_light.dot(&self.normal) / distance_squared.sqrt()).abs();
+        let area = self.area();
+        if cos_light <= 0.0 || area <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cos_light * area)
+    }
+}
+
+impl Bounded<Float, 3> for RectLight {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        let [u, v] = self.edges;
+        let corners = [self.corner + u, self.corner + v, self.corner + u + v];
+        let (min, max) = corners
+            .iter()
+            .fold((self.corner, self.corner), |(min, max), corner| {
+                (min.inf(corner), max.sup(corner))
+            });
+        Aabb::with_bounds(min.into(), max.into())
+    }
+}
+
+impl BHShape<Float, 3> for RectLight {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+impl Hit for RectLight {
+    /// Like a triangle, a one-sided light can only be hit from its front
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        if !self.visible {
+            return None;
+        }
+        let facing = ray.direction.dot(&self.normal);
+        if facing == 0.0 || (facing > 0.0 && !self.double_sided) {
+            return None;
+        }
+        let t = (self.corner - ray.origin.coords).dot(&self.normal) / facing;
+        if !range.contains(&t) {
+            return None;
+        }
+        let point = ray.origin.coords + ray.direction * t;
+        let (s, t_edge) = self.surface_coordinates(&point);
+        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t_edge) {
+            return None;
+        }
+        // Both sides of a double-sided light count as its front, so both give off light
+        let normal = if facing < 0.0 {
+            self.normal
+        } else {
+            -self.normal
+        };
+        let mut intersection =
+            Intersection::new(point, normal, t, &self.material, true, Vec2::new(s, t_edge))
+                .with_tangents(self.edges[0], self.edges[1]);
+        intersection.object = self.object;
+        Some(intersection)
+    }
+}
+
+/// A light at a point that shines down a cone, fading smoothly from full intensity inside
+/// `inner` degrees of its axis to nothing at `outer` degrees. An [`AngularProfile`] can shape
+/// the beam further. Nothing can hit it, so it only lights the scene.
+pub struct SpotLight {
+    position: Point3,
+    /// Unit vector the light shines along
+    direction: Vec3,
+    /// Watts per steradian along the axis, per channel
+    intensity: Vec3,
+    cos_inner: Float,
+    cos_outer: Float,
+    pub profile: Option<Arc<AngularProfile>>,
+    node_index: usize,
+    /// The scene object this light belongs to
+    pub object: ObjectId,
+}
+
+impl SpotLight {
+    /// Returns a light at `position` shining along `direction` with `intensity` watts per
+    /// steradian, with cone angles in degrees from the axis. An inner angle past the outer one
+    /// is moved in to it.
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner: Float,
+        outer: Float,
+    ) -> Self {
+        let outer = outer.clamp(0.0, 180.0);
+        SpotLight {
+            position,
+            direction: direction.try_normalize(0.0).unwrap_or(-Vec3::z()),
+            intensity,
+            cos_inner: inner.clamp(0.0, outer).to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+            profile: None,
+            node_index: 0,
+            object: ObjectId::default(),
+        }
+    }
+
+    pub fn with_profile(mut self, profile: Arc<AngularProfile>) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn with_object(mut self, object: ObjectId) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub fn position(&self) -> Point3 {
+        self.position
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    pub fn intensity(&self) -> Vec3 {
+        self.intensity
+    }
+
+    /// Cosines of the inner and outer cone angles
+    pub fn cone(&self) -> [Float; 2] {
+        [self.cos_inner, self.cos_outer]
+    }
+
+    /// How much of its intensity the light sends along the unit vector `direction`: the cone's
+    /// smooth falloff, times the profile's value at that angle if it has one
+    pub fn falloff(&self, direction: &Vec3) -> Float {
+        let cos_angle = direction.dot(&self.direction).clamp(-1.0, 1.0);
+        let cone = if self.cos_inner <= self.cos_outer {
+            Float::from(u8::from(cos_angle >= self.cos_outer))
+        } else {
+            let x =
+                ((cos_angle - self.cos_outer) / (self.cos_inner - self.cos_outer)).clamp(0.0, 1.0);
+            x * x * (3.0 - 2.0 * x)
+        };
+        match &self.profile {
+            Some(profile) if cone > 0.0 => cone * profile.value(cos_angle.acos().to_degrees()),
+            _ => cone,
+        }
+    }
+
+    /// The direction from `point` to the light, with the radiance that arriving from it would
+    /// need to light `point` as the light does, along with the light's position. Only that one
+    /// direction reaches the light, so its density is 1 and nothing else can find it. `None` if
+    /// the light doesn't shine toward `point`.
+    pub fn sample(&self, point: &Point3) -> Option<(SkySample, Point3)> {
+        let to_light = self.position - point;
+        let distance_squared = to_light.norm_squared();
+        let direction = to_light.try_normalize(0.0)?;
+        let falloff = self.falloff(&-direction);
+        (falloff > 0.0).then(|| {
+            let sample = SkySample {
+                direction,
+                radiance: self.intensity * (falloff / distance_squared),
+                pdf: 1.0,
+            };
+            (sample, self.position)
+        })
+    }
+}
+
+impl Bounded<Float, 3> for SpotLight {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        Aabb::with_bounds(self.position.into(), self.position.into())
+    }
+}
+
+impl BHShape<Float, 3> for SpotLight {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+impl Hit for SpotLight {
+    fn hit(&self, _ray: &Ray, _range: &Range<Float>) -> Option<Intersection<'_>> {
+        None
+    }
+}
+
+/// A spot light's measured beam: how bright it is at angles from its axis, relative to its
+/// intensity, like a simplified IES file's vertical angles and candela values. Linearly
+/// interpolated between the angles, and held at the first and last values beyond them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AngularProfile {
+    /// Degrees from the axis, increasing
+    angles: Vec<Float>,
+    values: Vec<Float>,
+}
+
+impl AngularProfile {
+    /// A profile through `points` of an angle in degrees and the relative intensity there, in
+    /// order of increasing angle
+    pub fn new(points: Vec<(Float, Float)>) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("an angular profile needs at least one angle".to_string());
+        }
+        if let Some(pair) = points.windows(2).find(|pair| pair[1].0 <= pair[0].0) {
+            return Err(format!(
+                "angle {} comes after {}, but angles have to increase",
+                pair[1].0, pair[0].0
+            ));
+        }
+        if let Some((angle, value)) = points
+            .iter()
+            .find(|(angle, value)| !angle.is_finite() || !value.is_finite() || *value < 0.0)
+        {
+            return Err(format!("{} at {} degrees isn't an intensity", value, angle));
+        }
+        let (angles, values) = points.into_iter().unzip();
+        Ok(AngularProfile { angles, values })
+    }
+
+    /// Reads a profile from lines of an angle in degrees and an intensity, skipping blank lines
+    /// and ones starting with `#`
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut points = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let point = match words[..] {
+                [angle, value] => angle.parse().ok().zip(value.parse().ok()),
+                _ => None,
+            };
+            points.push(point.ok_or_else(|| {
+                format!(
+                    "line {}: '{}' isn't an angle and an intensity",
+                    number + 1,
+                    line
+                )
+            })?);
+        }
+        AngularProfile::new(points)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+        AngularProfile::parse(&source).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// The relative intensity `degrees` from the axis
+    pub fn value(&self, degrees: Float) -> Float {
+        let next = self.angles.partition_point(|&angle| angle <= degrees);
+        if next == 0 {
+            return self.values[0];
+        }
+        if next == self.angles.len() {
+            return self.values[next - 1];
+        }
+        let (a0, a1) = (self.angles[next - 1], self.angles[next]);
+        let (v0, v1) = (self.values[next - 1], self.values[next]);
+        v0 + (v1 - v0) * (degrees - a0) / (a1 - a0)
+    }
+}
+
+/// A spot or rect light in a world, see [`crate::hittable::World::scene_lights`]
+#[derive(Clone, Copy)]
+pub enum SceneLight<'a> {
+    Rect(&'a RectLight),
+    Spot(&'a SpotLight),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::f64::consts::PI;
+
+    /// The fraction of the light leaving a point facing up that reaches an `a` by `b` rectangle
+    /// facing down `h` above it, with a corner right over the point
+    fn corner_form_factor(a: Float, b: Float, h: Float) -> Float {
+        let (a, b) = (a / h, b / h);
+        let (root_a, root_b) = ((1.0 + a * a).sqrt(), (1.0 + b * b).sqrt());
+        (a / root_a * (b / root_a).atan() + b / root_b * (a / root_b).atan()) / (2.0 * PI)
+    }
+
+    #[test]
+    fn rect_light_irradiance_matches_the_form_factor() {
+        let (a, b, h) = (2.0, 1.0, 1.5);
+        let light = RectLight::new(
+            Vec3::new(0.0, 0.0, h),
+            Vec3::new(0.0, b, 0.0),
+            Vec3::new(a, 0.0, 0.0),
+            Vec3::repeat(3.0),
+        );
+        assert_eq!(light.normal(), -Vec3::z());
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let samples = 200_000;
+        let irradiance: Float = (0..samples)
+            .filter_map(|_| light.sample(&Vec3::zeros(), &mut rng))
+            .map(|(sample, _)| sample.radiance.x * sample.direction.z.max(0.0) / sample.pdf)
+            .sum::<Float>()
+            / samples as Float;
+        // A surface giving off radiance L lights what sees it by pi L times the form factor
+        let expected = PI * 3.0 * corner_form_factor(a, b, h);
+        assert!(
+            (irradiance - expected).abs() < 0.01 * expected,
+            "{} instead of {}",
+            irradiance,
+            expected
+        );
+    }
+
+    #[test]
+    fn one_sided_rect_lights_only_light_their_front() {
+        let light = RectLight::new(Vec3::zeros(), Vec3::x(), Vec3::y(), Vec3::repeat(1.0));
+        let mut rng = StdRng::seed_from_u64(2);
+        assert!(light.sample(&Vec3::new(0.5, 0.5, 1.0), &mut rng).is_some());
+        assert!(light.sample(&Vec3::new(0.5, 0.5, -1.0), &mut rng).is_none());
+        let light = light.with_double_sided(true);
+        assert!(light.sample(&Vec3::new(0.5, 0.5, -1.0), &mut rng).is_some());
+    }
+
+    /// The unit vector `degrees` off straight down, toward +x
+    fn off_down(degrees: Float) -> Vec3 {
+        let angle = degrees.to_radians();
+        Vec3::new(angle.sin(), 0.0, -angle.cos())
+    }
+
+    #[test]
+    fn spot_falloff_follows_the_profile_inside_the_cone() {
+        let profile = AngularProfile::new(vec![(0.0, 1.0), (10.0, 0.5), (20.0, 0.25)]).unwrap();
+        let spot = SpotLight::new(Vec3::zeros(), -Vec3::z(), Vec3::repeat(1.0), 30.0, 40.0)
+            .with_profile(Arc::new(profile));
+        for (degrees, expected) in [
+            (0.0, 1.0),
+            (5.0, 0.75),
+            (10.0, 0.5),
+            (15.0, 0.375),
+            (20.0, 0.25),
+            // Held at the last value past the table, until the cone takes over
+            (28.0, 0.25),
+        ] {
+            let falloff = spot.falloff(&off_down(degrees));
+            assert!(
+                (falloff - expected).abs() < 1e-9,
+                "{} at {} degrees instead of {}",
+                falloff,
+                degrees,
+                expected
+            );
+        }
+        let fading = spot.falloff(&off_down(35.0));
+        assert!(0.0 < fading && fading < 0.25, "{}", fading);
+        assert_eq!(spot.falloff(&off_down(41.0)), 0.0);
+    }
+
+    #[test]
+    fn spot_cones_fade_smoothly_between_their_angles() {
+        let spot = SpotLight::new(Vec3::zeros(), -Vec3::z(), Vec3::repeat(8.0), 20.0, 30.0);
+        assert_eq!(spot.falloff(&off_down(19.0)), 1.0);
+        assert_eq!(spot.falloff(&off_down(31.0)), 0.0);
+        let falloffs: Vec<Float> = (20..=30)
+            .map(|degrees| spot.falloff(&off_down(degrees as Float)))
+            .collect();
+        assert!(falloffs.windows(2).all(|pair| pair[1] < pair[0]));
+        // About half way at the middle angle
+        assert!(0.3 < falloffs[5] && falloffs[5] < 0.7, "{}", falloffs[5]);
+
+        // Falls off with the square of the distance, straight below
+        let (sample, position) = spot.sample(&Vec3::new(0.0, 0.0, -2.0)).unwrap();
+        assert_eq!(position, Vec3::zeros());
+        assert_eq!(sample.direction, Vec3::z());
+        assert_eq!(sample.radiance, Vec3::repeat(2.0));
+        assert!(spot.sample(&Vec3::new(0.0, 0.0, 2.0)).is_none());
+    }
+
+    #[test]
+    fn profiles_parse_from_angle_and_intensity_lines() {
+        let source = "# a narrow beam\n0 1\n\n10 0.5\n  20 0.25\n";
+        assert_eq!(
+            AngularProfile::parse(source).unwrap(),
+            AngularProfile::new(vec![(0.0, 1.0), (10.0, 0.5), (20.0, 0.25)]).unwrap()
+        );
+        let err = AngularProfile::parse("0 1\n10 0.5 2\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        assert!(AngularProfile::parse("10 1\n5 0.5\n").is_err());
+        assert!(AngularProfile::parse("0 -1\n").is_err());
+        assert!(AngularProfile::parse("# nothing\n").is_err());
+    }
+}
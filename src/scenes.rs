@@ -1,140 +1,310 @@
 #![allow(unused)]
 use crate::{
-    camera::{Camera, Float},
-    hittable::{self, load_gltf, Shape, Sphere, Triangle, World},
-    material::{Dielectric, Lambertian, Material, Metal},
-    texture::{CheckerTexture, ImageTexture, SolidColor},
-    vec3::{Vec3, Vec3Ext},
+    assets::{self, AssetResolver},
+    boxes::{AaBox, RoundedBox},
+    camera::{Camera, Float, Image, Integrator, RenderFidelity},
+    environment::EnvironmentMap,
+    gltf_scene::{self, Light},
+    hittable::{
+        self, load_gltf, Background, LoadOptions, Shape, SkySettings, Sphere, SunDisc,
+        Surroundings, Triangle, World,
+    },
+    instance::{self, Instance, Prototype},
+    material::{
+        AlphaMask, Dielectric, DiffuseLight, Lambertian, Material, Metal, ShadowCatcher, Volumetric,
+    },
+    material_inference::ImageSource,
+    medium::{HeterogeneousMedium, VoxelGrid},
+    object::ObjectId,
+    scene_file::{SceneError, SceneFile},
+    texture::{CheckerTexture, ImageTexture, LoadReport, NoiseStyle, NoiseTexture, SolidColor},
+    tonemap::Tonemap,
+    uv::UvMode,
+    vec3::{Vec2, Vec3, Vec3Ext},
     window::{HEIGHT, WIDTH},
 };
 use itertools::Itertools;
-use nalgebra::{Matrix4, Rotation3};
-use rand::{thread_rng, Rng};
-use std::{io, sync::Arc};
+use nalgebra::{Matrix4, Rotation3, Similarity3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, io, path::Path, sync::Arc};
+
+/// Only a backstop, since russian roulette ends dim paths long before this
+pub(crate) const MAX_DEPTH: usize = 32;
+
+/// Loads a scene from a file written as described in [`SceneFile`], from a glTF file with a
+/// camera in it, or from a texture to look at with [`quick_sphere`], printing what went wrong loading its images and meshes if anything did.
+/// Only scene files have surroundings of their own, which carry their animation.
+pub fn load_scene(path: &Path) -> Result<(Camera, Vec<Shape>, Surroundings), SceneError> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    if matches!(extension, Some("gltf" | "glb")) {
+        let file = AssetResolver::from_env(Path::new(""))
+            .resolve_file(path)
+            .map_err(SceneError::Asset)?;
+        let (camera, mut shapes, lights) =
+            load_gltf_scene(&file.path).map_err(|message| SceneError::Malformed {
+                location: path.display().to_string(),
+                message,
+            })?;
+        let camera = camera.ok_or(SceneError::Missing("camera"))?;
+        shapes.extend(gltf_scene::light_shapes(&lights, &shapes));
+        return Ok((camera, shapes, Surroundings::default()));
+    }
+    if image::ImageFormat::from_path(path).is_ok() {
+        let file = AssetResolver::from_env(Path::new(""))
+            .resolve_file(path)
+            .map_err(SceneError::Asset)?;
+        let (camera, shapes) =
+            quick_sphere(&file.path).map_err(|message| SceneError::Malformed {
+                location: path.display().to_string(),
+                message,
+            })?;
+        return Ok((camera, shapes, Surroundings::default()));
+    }
+    let scene = SceneFile::load(path)?;
+    for warning in &scene.warnings {
+        println!("Warning: {}", warning);
+    }
+    let mut report = LoadReport::default();
+    let (camera, shapes) = scene.build(&mut report)?;
+    if !report.is_empty() {
+        println!("{}", report);
+    }
+    Ok((
+        camera,
+        shapes,
+        Surroundings::default().with_animation(scene.animation),
+    ))
+}
 
-const MAX_DEPTH: usize = 100;
+/// The first camera in a glTF file, its meshes and its punctual lights
+pub type GltfScene = (Option<Camera>, Vec<Shape>, Vec<Light>);
+
+/// Loads everything in a glTF file exported from e.g. Blender: its meshes, the first of its
+/// cameras, and its punctual lights, which [`gltf_scene::light_shapes`] turns into shapes to add
+/// to the scene. Prints what went wrong loading its textures if anything did, and fails if the
+/// file itself is broken.
+pub fn load_gltf_scene(path: &Path) -> Result<GltfScene, String> {
+    let path = path.to_string_lossy();
+    // glTF files bring their own materials, so the loader never uses this
+    let fallback = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    let (loaded, mut report) = load_gltf(&path, fallback, &LoadOptions::default())?;
+    let (cameras, lights) = gltf_scene::load_cameras_and_lights(&path, &mut report)?;
+    if !report.is_empty() {
+        println!("{}", report);
+    }
+    Ok((cameras.into_iter().next(), loaded.into_shapes(), lights))
+}
 
-pub fn cam1() -> Camera {
-    let image_width = WIDTH as usize;
-    let image_height = HEIGHT as usize;
-    let samples_per_pixel = 32; // not relevant for window_preview
-                                // let max_depth = 100;
-    let defocus_angle = 0.0;
+/// Meshes [`mesh_scene`] loads
+const MESH_SCENE_ASSETS: [&str; 5] = [
+    "stanford-bunny.obj",
+    "bimba.obj",
+    "teapot.obj",
+    "Nefertiti.obj",
+    "armadillo.obj",
+];
+/// glTF files [`gltf_test`] loads
+const GLTF_TEST_ASSETS: [&str; 1] = ["meshes/dodge_charger/scene.gltf"];
+const SPONZA_ASSET: &str = "meshes/main1_sponza/NewSponza_Main_glTF_003.gltf";
+
+/// Where the built-in scenes' mesh at `path` is, found by an [`AssetResolver`] from the working
+/// directory. Panics with everywhere it looked if it's nowhere, since the loaders would anyway.
+fn asset_path(path: &str) -> String {
+    match AssetResolver::from_env(Path::new("")).resolve_file(Path::new(path)) {
+        Ok(file) => file.path.to_string_lossy().into_owned(),
+        Err(err) => panic!("{}", err),
+    }
+}
 
-    let center = Vec3::new(3.0, -5.0, 0.6);
-    let lookat = Vec3::new(0.0, 0.0, 0.0);
-    // let focus_distance = 10.0;
-    let focus_distance = center.metric_distance(&lookat);
+/// An image compiled into the binary, see [`assets::EMBEDDED_ASSETS`]
+fn embedded(name: &str) -> &'static [u8] {
+    assets::embedded_asset(name).expect("built-in scenes only use embedded images")
+}
 
-    Camera::new(
-        center,
-        lookat,
-        Vec3::z_axis().into_inner(),
-        focus_distance,
-        defocus_angle,
-        image_width,
-        image_height,
-        samples_per_pixel,
-        MAX_DEPTH,
-        20.0,
-        0.0..Float::MAX,
-    )
+/// Names of the scenes built into this file that `--scene` can pick instead of a scene file
+pub const BUILT_IN_SCENES: [&str; 19] = [
+    "cover",
+    "earth",
+    "mesh",
+    "gltf_test",
+    "gltf_shadow_catcher",
+    "checkered",
+    "perlin",
+    "smoke",
+    "foliage",
+    "stained_glass",
+    "boxes",
+    "rtiow",
+    "cornell_box",
+    "enclosed_room",
+    "uv_mapping",
+    "glowing_sphere",
+    "bumpy_moon",
+    "sunset",
+    "environment",
+];
+
+/// Seed of the spheres the built-in scenes scatter with [`cover_scene`], so they're laid out the
+/// same every run and renders of them can be compared
+pub const BUILT_IN_COVER_SEED: u64 = 0x5eed;
+
+/// The scenes in [`BUILT_IN_SCENES`] that are laid out from a seed, so look different with each
+pub const PROCEDURAL_SCENES: [&str; 1] = ["cover"];
+
+/// The files the scene called `name` in [`BUILT_IN_SCENES`] loads from disk rather than from
+/// the binary, or `None` if there's no such scene
+pub fn built_in_scene_assets(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "mesh" => Some(&MESH_SCENE_ASSETS),
+        "gltf_test" | "gltf_shadow_catcher" => Some(&GLTF_TEST_ASSETS),
+        _ if BUILT_IN_SCENES.contains(&name) => Some(&[]),
+        _ => None,
+    }
 }
 
-pub fn cam2() -> Camera {
-    let image_width = WIDTH as usize;
-    let image_height = HEIGHT as usize;
-    let samples_per_pixel = 32; // not relevant for window_preview
-                                // let max_depth = 100;
-    let defocus_angle = 0.7;
-    let focus_distance = 16.0;
+/// Builds the scene called `name` in [`BUILT_IN_SCENES`], with a camera framing it and the
+/// surroundings to build its world in, or `None` if there's no such scene. The
+/// [`PROCEDURAL_SCENES`] are laid out by `seed`.
+pub fn built_in_scene(name: &str, seed: u64) -> Option<(Camera, Vec<Shape>, Surroundings)> {
+    if PROCEDURAL_SCENES.contains(&name) {
+        let (camera, shapes) = procedural_scene(name, seed, &CoverMaterials::load())?;
+        return Some((camera, shapes, Surroundings::default()));
+    }
+    let plain = |shapes| (shapes, Surroundings::default());
+    let (camera, (shapes, surroundings)) = match name {
+        "earth" => (cam2(), plain(earth_shapes())),
+        "mesh" => (cam1(), plain(mesh_scene())),
+        "gltf_test" | "gltf_shadow_catcher" => {
+            // On a shadow catcher, renders are the car and its shadow over a transparent
+            // background, ready to be composited over something else
+            let ground = if name == "gltf_shadow_catcher" {
+                Arc::new(ShadowCatcher.into())
+            } else {
+                checker_ground()
+            };
+            let mut shapes = generate_ground_plane(10000.0, 10000.0, -0.2, ground, true);
+            let (gltf_shapes, report) = gltf_test();
+            println!("{}", report);
+            shapes.extend(gltf_shapes);
+            (cam1(), plain(shapes))
+        }
+        "checkered" => (cam2(), plain(gen_checkered())),
+        "perlin" => (perlin_camera(), plain(perlin_demo())),
+        "smoke" => (smoke_ball_camera(), plain(smoke_ball())),
+        "foliage" => {
+            let ground = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+            let mut shapes = generate_ground_plane(20.0, 20.0, 0.0, ground, true);
+            shapes.extend(foliage(
+                3000,
+                Vec3::new(0.0, 0.0, 1.5),
+                1.5,
+                BUILT_IN_COVER_SEED,
+            ));
+            (foliage_camera(), plain(shapes))
+        }
+        "stained_glass" => (stained_glass_camera(), plain(stained_glass())),
+        "boxes" => (boxes_camera(), plain(boxes())),
+        "rtiow" => (rtiow_camera(), rtiow_final(BUILT_IN_COVER_SEED)),
+        "cornell_box" => (cornell_box_camera(), cornell_box()),
+        "enclosed_room" => (enclosed_room_camera(), enclosed_room()),
+        "uv_mapping" => (uv_mapping_camera(), uv_mapping()),
+        "glowing_sphere" => (glowing_sphere_camera(), glowing_sphere()),
+        "bumpy_moon" => (bumpy_moon_camera(), bumpy_moon()),
+        "sunset" => (sunset_camera(), sunset()),
+        "environment" => {
+            // The earth's map is equirectangular too, so it makes a panorama that wraps around
+            let earth = EnvironmentMap::load_from_memory(embedded("textures/earth.png"))
+                .expect("the embedded earth map decodes");
+            (
+                environment_spheres_camera(),
+                environment_spheres(earth, 0.0),
+            )
+        }
+        _ => return None,
+    };
+    Some((camera, shapes, surroundings))
+}
 
-    let lookfrom = Vec3::new(14.0, 3.0, 10.0);
-    let lookat = Vec3::new(0.0, 0.0, 0.0);
+/// The checkered ground the built-in scenes stand on
+fn checker_ground() -> Arc<Material> {
+    let even_texture = SolidColor::new(Vec3::new(0.1, 0.1, 0.1)).into();
+    let odd_texture = SolidColor::new(Vec3::new(0.95, 0.95, 0.95)).into();
+    let checker_tex = CheckerTexture::new_filtered(3.0, even_texture, odd_texture).into();
+    Arc::new(Lambertian::new(checker_tex).into())
+}
 
-    Camera::new(
-        lookfrom,
-        lookat,
-        Vec3::new(0.0, 0.0, 1.0),
-        focus_distance,
-        defocus_angle,
-        image_width,
-        image_height,
-        samples_per_pixel,
-        MAX_DEPTH,
-        20.0,
-        0.0..Float::MAX,
-    )
+/// Builds the scene called `name` in [`PROCEDURAL_SCENES`] laid out by `seed`, with a camera
+/// framing it, from `materials` loaded once for every layout, or `None` if there's no such scene
+pub fn procedural_scene(
+    name: &str,
+    seed: u64,
+    materials: &CoverMaterials,
+) -> Option<(Camera, Vec<Shape>)> {
+    match name {
+        "cover" => {
+            let camera = cam1();
+            let mut shapes =
+                generate_ground_plane(10000.0, 10000.0, -0.2, materials.ground.clone(), true);
+            let mut rng = StdRng::seed_from_u64(seed);
+            shapes.extend(cover_scene_with(
+                materials, 300, 300, &camera, -0.2, &mut rng,
+            ));
+            Some((camera, shapes))
+        }
+        _ => None,
+    }
 }
 
-pub fn widecam() -> Camera {
-    let image_width = WIDTH as usize;
-    let image_height = HEIGHT as usize;
-    let samples_per_pixel = 32; // not relevant for window_preview
-                                // let max_depth = 100;
-    let defocus_angle = 0.0;
+pub fn cam1() -> Camera {
+    Camera::builder()
+        .with_look_from(Vec3::new(3.0, -5.0, 0.6))
+        .with_look_at(Vec3::zeros())
+        .build()
+        .expect("cam1 is valid")
+}
 
-    let center = Vec3::new(-14.0, -10.0, 7.0);
-    let lookat = Vec3::new(0.0, 0.0, 5.0);
-    // let focus_distance = 10.0;
-    let focus_distance = center.metric_distance(&lookat);
+pub fn cam2() -> Camera {
+    Camera::builder()
+        .with_look_from(Vec3::new(14.0, 3.0, 10.0))
+        .with_look_at(Vec3::zeros())
+        .with_focus_distance(16.0)
+        .with_defocus_angle(0.7)
+        .build()
+        .expect("cam2 is valid")
+}
 
-    Camera::new(
-        center,
-        lookat,
-        Vec3::z_axis().into_inner(),
-        focus_distance,
-        defocus_angle,
-        image_width,
-        image_height,
-        samples_per_pixel,
-        MAX_DEPTH,
-        40.0,
-        0.0..Float::MAX,
-    )
+pub fn widecam() -> Camera {
+    Camera::builder()
+        .with_look_from(Vec3::new(-14.0, -10.0, 7.0))
+        .with_look_at(Vec3::new(0.0, 0.0, 5.0))
+        .with_vertical_fov(40.0)
+        .build()
+        .expect("widecam is valid")
 }
 
 pub fn topdown_cam() -> Camera {
-    let image_width = WIDTH as usize;
-    let image_height = HEIGHT as usize;
-    let samples_per_pixel = 32; // not relevant for window_preview
-                                // let max_depth = 100;
-    let defocus_angle = 0.7;
-
-    let up = Vec3::new(0.0, 0.0, 1.0); // let Z be the up direction
-
-    // TODO: figure out why it breaks when lookfrom is along the z axis
-    let lookfrom = Vec3::new(0.1, 0.1, 20.0);
-    let lookat = Vec3::new(0.0, 0.0, 0.0);
-
-    let focus_distance = lookfrom.metric_distance(&lookat);
-
-    Camera::new(
-        lookfrom,
-        lookat,
-        up,
-        focus_distance,
-        defocus_angle,
-        image_width,
-        image_height,
-        samples_per_pixel,
-        MAX_DEPTH,
-        20.0,
-        0.0..Float::MAX,
-    )
+    Camera::builder()
+        .with_look_from(Vec3::new(0.0, 0.0, 20.0))
+        .with_look_at(Vec3::zeros())
+        .with_defocus_angle(0.7)
+        .build()
+        .expect("topdown_cam is valid")
 }
 
 pub fn earth_scene() -> io::Result<World> {
+    Ok(World::build(earth_shapes()))
+}
+
+pub fn earth_shapes() -> Vec<Shape> {
     let mut shapes = Vec::new();
-    let earth_bytes: &[u8] = include_bytes!("./assets/textures/earth.png");
+    let earth_bytes: &[u8] = embedded("textures/earth.png");
     let earth_image = ImageTexture::load_embedded_image(earth_bytes);
-    let earth_tex = ImageTexture::new(earth_image).into();
+    let earth_tex = ImageTexture::shared(earth_image).into();
     let earth_mat = Arc::new(Lambertian::new(earth_tex).into());
     let earth_ball = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 2.0, earth_mat).into();
 
     shapes.push(earth_ball);
-
-    Ok(World::build(shapes))
+    shapes
 }
 
 // TODO: figure out what the fuck is up with this weird moiré pattern looking abomination
@@ -143,36 +313,52 @@ pub fn earth_scene() -> io::Result<World> {
 // (scare quotes placed intentionally, that shit is NOT how you're supposed to do it)
 // (very unsure as to why it's normally indistinguishable anyhow)
 // (should probably un-implement it until i've actually figured out how the fuck it works)
-pub fn cover_scene(grid_i: i16, grid_j: i16, camera: &Camera, z: Float) -> Vec<Shape> {
-    let mut rng = thread_rng();
-    let mut shapes = Vec::new();
-
-    let earth_bytes = include_bytes!("./assets/textures/earth.png");
-    let mars_bytes = include_bytes!("./assets/textures/mars.jpg");
-    let thing_bytes = include_bytes!("./assets/textures/moon_hires.jpg");
-    let saul_bytes = include_bytes!("./assets/textures/saul.webp");
-
-    let earth_image = ImageTexture::load_embedded_image(earth_bytes);
-    let mars_image = ImageTexture::load_embedded_image(mars_bytes);
-    let thing_image = ImageTexture::load_embedded_image(thing_bytes);
-    let saul_image = ImageTexture::load_embedded_image(saul_bytes);
+pub fn cover_scene(
+    grid_i: i16,
+    grid_j: i16,
+    camera: &Camera,
+    z: Float,
+    rng: &mut impl Rng,
+) -> Vec<Shape> {
+    cover_scene_with(&CoverMaterials::load(), grid_i, grid_j, camera, z, rng)
+}
 
-    let earth_tex = ImageTexture::new(earth_image).into();
-    let mars_tex = ImageTexture::new(mars_image).into();
-    let moon_tex = ImageTexture::new(thing_image).into();
-    let saul_tex = ImageTexture::new(saul_image).into();
+/// The materials of the big spheres in [`cover_scene`] and the ground under it, loaded once so
+/// [`cover_scene_with`] can scatter many layouts without decoding the textures again for each
+#[derive(Clone)]
+pub struct CoverMaterials {
+    pub ground: Arc<Material>,
+    pub glass: Arc<Material>,
+    pub metal: Arc<Material>,
+    pub saul: Arc<Material>,
+}
 
-    let even_texture = SolidColor::new(Vec3::new(0.0, 0.0, 0.0)).into();
-    let odd_texture = SolidColor::new(Vec3::new(0.95, 0.95, 0.95)).into();
-    let checker_tex = CheckerTexture::new(0.31, even_texture, odd_texture).into();
+impl CoverMaterials {
+    pub fn load() -> Self {
+        let saul_image = ImageTexture::load_embedded_image(embedded("textures/saul.webp"));
+        let saul_tex = ImageTexture::shared(saul_image).into();
+        CoverMaterials {
+            ground: checker_ground(),
+            glass: Arc::new(Dielectric::new(1.5).into()),
+            metal: Arc::new(Metal::new_solid(Vec3::new(0.7, 0.6, 0.5), None).into()),
+            saul: Arc::new(Lambertian::new(saul_tex).into()),
+        }
+    }
+}
 
-    let checker_mat: Arc<Material> = Arc::new(Lambertian::new(checker_tex).into());
-    let mars_mat: Arc<Material> = Arc::new(Lambertian::new(mars_tex).into());
-    let earth_mat: Arc<Material> = Arc::new(Lambertian::new(earth_tex).into());
-    let moon_mat: Arc<Material> = Arc::new(Lambertian::new(moon_tex).into());
-    let saul_mat: Arc<Material> = Arc::new(Lambertian::new(saul_tex).into());
-    let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
-    let metal: Arc<Material> = Arc::new(Metal::new_solid(Vec3::new(0.7, 0.6, 0.5), None).into());
+/// Like [`cover_scene`], with the big spheres made of `materials`
+pub fn cover_scene_with(
+    materials: &CoverMaterials,
+    grid_i: i16,
+    grid_j: i16,
+    camera: &Camera,
+    z: Float,
+    rng: &mut impl Rng,
+) -> Vec<Shape> {
+    let mut shapes = Vec::new();
+    let glass = materials.glass.clone();
+    let metal = materials.metal.clone();
+    let saul_mat = materials.saul.clone();
 
     let ground = Vec3::new(0.0, 0.0, -1000.0);
     let big_6_radius = 0.7;
@@ -185,20 +371,30 @@ pub fn cover_scene(grid_i: i16, grid_j: i16, camera: &Camera, z: Float) -> Vec<S
     let p5 = Vec3::new(1.0, -1.732, big_6_radius + z); // Bottom-right sphere
 
     // shapes.push(Sphere::new(ground, ground.z.abs(), checker_mat).into());
-    shapes.push(Sphere::new(p1, big_6_radius, glass).into());
+    shapes.push(
+        Sphere::new(p1, big_6_radius, glass)
+            .with_object(ObjectId::register("cover_glass_sphere"))
+            .into(),
+    );
     // shapes.push(Sphere::new(p2, big_6_radius, mars_mat).into());
-    shapes.push(Sphere::new(p3, big_6_radius, metal).into());
+    shapes.push(
+        Sphere::new(p3, big_6_radius, metal)
+            .with_object(ObjectId::register("cover_metal_sphere"))
+            .into(),
+    );
     // shapes.push(Sphere::new(p4, big_6_radius, earth_mat).into());
     // shapes.push(Sphere::new(p5, big_6_radius, moon_mat).into());
 
     let you_the_viewer = camera.center;
-    let saul_sphere = Sphere::new_facing(saul_loc, big_6_radius, saul_mat, you_the_viewer).into();
+    let saul_sphere = Sphere::new_facing(saul_loc, big_6_radius, saul_mat, you_the_viewer)
+        .with_object(ObjectId::register("cover_saul_sphere"))
+        .into();
     shapes.push(saul_sphere);
 
     for i in -grid_i..grid_i {
         for j in -grid_j..grid_j {
             let radius = 0.2;
-            let albedo: Vec3 = Vec3::random(&mut rng, 0.0, 1.0);
+            let albedo: Vec3 = Vec3::random(rng, 0.0, 1.0);
             let offset: Vec3 = Vec3::new(rng.gen_range(0.0..0.9), rng.gen_range(0.0..0.9), z);
             let i_offset = 1.0;
             let j_offset = 1.0;
@@ -228,7 +424,8 @@ pub fn cover_scene(grid_i: i16, grid_j: i16, camera: &Camera, z: Float) -> Vec<S
                         Lambertian::new(texture).into()
                     }
                 });
-                Sphere::new(center, radius, mat).into()
+                let object = ObjectId::register(&format!("cover_sphere_{}", shapes.len()));
+                Sphere::new(center, radius, mat).with_object(object).into()
             };
 
             shapes.push(sphere);
@@ -259,6 +456,54 @@ pub fn gen_checkered() -> Vec<Shape> {
     shapes
 }
 
+/// Looks down at the two spheres of [`perlin_demo`] side by side
+pub fn perlin_camera() -> Camera {
+    let center = Vec3::new(3.0, -5.0, 1.6);
+    let lookat = Vec3::new(0.0, 0.0, 0.5);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH as usize,
+        HEIGHT as usize,
+        32,
+        MAX_DEPTH,
+        25.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// Perlin noise textures, see [`NoiseTexture`]: a marble sphere veined by turbulence and a
+/// metal one bumped by it, on a ground of smooth noise
+pub fn perlin_demo() -> Vec<Shape> {
+    let ground_noise = NoiseTexture::new(NoiseStyle::Smooth, 4.0, 1, 1);
+    let ground: Arc<Material> = Arc::new(Lambertian::new(ground_noise.into()).into());
+    let marble_noise = NoiseTexture::new(NoiseStyle::Marble, 4.0, 7, 2);
+    let marble: Arc<Material> = Arc::new(Lambertian::new(marble_noise.into()).into());
+    let bumps = NoiseTexture::new(NoiseStyle::Turbulence, 12.0, 4, 3);
+    let metal: Arc<Material> = Arc::new(
+        Metal::new_solid(Vec3::new(0.8, 0.7, 0.6), Some(0.05))
+            .with_bump(bumps.into(), 0.02)
+            .into(),
+    );
+    // Side by side across the view of `perlin_camera`
+    let across = Vec3::new(5.0, 3.0, 0.0).normalize() * 0.7;
+    let center = Vec3::new(0.0, 0.0, 0.6);
+    vec![
+        Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, ground)
+            .with_object(ObjectId::register("perlin_ground"))
+            .into(),
+        Sphere::new(center - across, 0.6, marble)
+            .with_object(ObjectId::register("perlin_marble"))
+            .into(),
+        Sphere::new(center + across, 0.6, metal)
+            .with_object(ObjectId::register("perlin_bumpy_metal"))
+            .into(),
+    ]
+}
+
 pub fn triangle_scene() -> Vec<Shape> {
     let mut shapes = Vec::new();
 
@@ -284,15 +529,15 @@ pub fn triangle_scene() -> Vec<Shape> {
     let c = Vec3::new(0.0, 0.0, 1.0);
     let tri2 = Triangle::new(a, b, c, mat2).into();
 
-    let earth_bytes: &[u8] = include_bytes!("./assets/textures/earth.png");
+    let earth_bytes: &[u8] = embedded("textures/earth.png");
     let earth_image = ImageTexture::load_embedded_image(earth_bytes);
-    let earth_tex = ImageTexture::new(earth_image).into();
+    let earth_tex = ImageTexture::shared(earth_image).into();
     let earth_mat = Arc::new(Lambertian::new(earth_tex).into());
     let earth_ball = Sphere::new(Vec3::new(0.4, 0.4, 0.4), 0.3, earth_mat).into();
 
-    let saul_bytes = include_bytes!("./assets/textures/saul.webp");
+    let saul_bytes = embedded("textures/saul.webp");
     let saul_image = ImageTexture::load_embedded_image(saul_bytes);
-    let saul_tex = ImageTexture::new(saul_image).into();
+    let saul_tex = ImageTexture::shared(saul_image).into();
     let saul_mat = Arc::new(Lambertian::new(saul_tex).into());
 
     let a = Vec3::new(0.0, 0.0, 0.0);
@@ -329,26 +574,908 @@ pub fn generate_ground_plane(
     let c = Vec3::new(half_width, half_length, z);
     let d = Vec3::new(-half_width, half_length, z);
 
+    let object = ObjectId::register("ground_plane");
+
     // Create two triangles to form the ground plane
     if top_is_up {
-        let tri1 = Triangle::new(a, b, c, material.clone());
-        let tri2 = Triangle::new(a, c, d, material.clone());
+        let tri1 = Triangle::new(a, b, c, material.clone()).with_object(object);
+        let tri2 = Triangle::new(a, c, d, material.clone()).with_object(object);
         vec![tri1.into(), tri2.into()]
     } else {
-        let tri1 = Triangle::new_opposite_normal(a, b, c, material.clone());
-        let tri2 = Triangle::new_opposite_normal(a, c, d, material.clone());
+        let tri1 = Triangle::new_opposite_normal(a, b, c, material.clone()).with_object(object);
+        let tri2 = Triangle::new_opposite_normal(a, c, d, material.clone()).with_object(object);
         vec![tri1.into(), tri2.into()]
     }
 }
 
+/// Looks at a clump of [`foliage`] 1.5 above the ground from the side, with the sky showing
+/// through its holes
+pub fn foliage_camera() -> Camera {
+    let center = Vec3::new(0.0, -6.0, 2.0);
+    let lookat = Vec3::new(0.0, 0.0, 1.5);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH as usize,
+        HEIGHT as usize,
+        64,
+        MAX_DEPTH,
+        35.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// Generates `num_leaves` randomly placed and oriented alpha-masked quads in a clump centered at
+/// `center`, for stress testing transparency. Leaves are double-sided and have checkered holes.
+pub fn foliage(num_leaves: usize, center: Vec3, radius: Float, seed: u64) -> Vec<Shape> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut shapes = Vec::with_capacity(num_leaves * 2);
+
+    let hole = SolidColor::new(Vec3::zeros()).into();
+    let solid = SolidColor::new(Vec3::ONE).into();
+    let coverage = CheckerTexture::new(0.04, hole, solid).into();
+    let leaf = Lambertian::new_rgb_solid(0.2, 0.5, 0.1).into();
+    let leaf_mat: Arc<Material> = Arc::new(AlphaMask::new(leaf, coverage).into());
+
+    let object = ObjectId::register("foliage");
+    let leaf_size = radius * 0.15;
+    for _ in 0..num_leaves {
+        let offset = Vec3::random_unit(&mut rng) * radius * rng.gen_range(0.0..1.0);
+        let u = Vec3::random_unit(&mut rng);
+        let v = u.cross(&Vec3::random_unit(&mut rng)).normalize();
+        let a = center + offset;
+        let b = a + u * leaf_size;
+        let c = b + v * leaf_size;
+        let d = a + v * leaf_size;
+
+        // Leaves are seen from both sides
+        let leaf = [
+            Triangle::new(a, b, c, leaf_mat.clone()),
+            Triangle::new(a, c, d, leaf_mat.clone()),
+        ];
+        shapes.extend(leaf.map(|tri| tri.with_object(object).with_double_sided(true).into()));
+    }
+    shapes
+}
+
+/// Looks at the pane of [`stained_glass`] from the front and a little above, with the sky
+/// showing through it
+pub fn stained_glass_camera() -> Camera {
+    let center = Vec3::new(1.5, -5.0, 2.5);
+    let lookat = Vec3::new(0.0, 0.0, 0.9);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH as usize,
+        HEIGHT as usize,
+        256,
+        MAX_DEPTH,
+        35.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// A sunlit stained glass pane standing on a white floor, tinted half red and half blue
+pub fn stained_glass() -> Vec<Shape> {
+    let red = SolidColor::new_rgb(0.9, 0.1, 0.1).into();
+    let blue = SolidColor::new_rgb(0.1, 0.2, 0.9).into();
+    // Checkers much bigger than the pane split it down the middle at x = 0. It stands back from
+    // y = 0, where they also split, so hits rounded to either side of it get the same color.
+    let tint = CheckerTexture::new(100.0, red, blue).into();
+    // An index of 1.0 means light goes straight through, like a pane thin enough to ignore.
+    // Glass triangles are double sided, so one pair makes both sides of the pane.
+    let glass: Arc<Material> = Arc::new(Dielectric::new_tinted(1.0, tint).into());
+    let floor: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.9, 0.9, 0.9).into());
+
+    let object = ObjectId::register("stained_glass_pane");
+    let a = Vec3::new(-1.0, 0.5, 0.0);
+    let b = Vec3::new(1.0, 0.5, 0.0);
+    let c = Vec3::new(1.0, 0.5, 2.0);
+    let d = Vec3::new(-1.0, 0.5, 2.0);
+    let pane = [
+        Triangle::new(a, b, c, glass.clone()),
+        Triangle::new(a, c, d, glass),
+    ];
+
+    let mut shapes = generate_ground_plane(20.0, 20.0, 0.0, floor, true);
+    shapes.extend(pane.map(|tri| tri.with_object(object).into()));
+    shapes
+}
+
+/// Looks at the two boxes of [`boxes`] side by side from a little above
+pub fn boxes_camera() -> Camera {
+    let center = Vec3::new(1.0, -6.0, 3.0);
+    let lookat = Vec3::new(0.0, 0.0, 0.6);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH as usize,
+        HEIGHT as usize,
+        64,
+        MAX_DEPTH,
+        35.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// A brushed metal rounded box next to a plain wooden crate on a checkered floor
+pub fn boxes() -> Vec<Shape> {
+    let even_texture = SolidColor::new_rgb(0.1, 0.1, 0.1).into();
+    let odd_texture = SolidColor::new_rgb(0.95, 0.95, 0.95).into();
+    let checker_tex = CheckerTexture::new(0.5, even_texture, odd_texture).into();
+    let checker_mat: Arc<Material> = Arc::new(Lambertian::new(checker_tex).into());
+    let metal: Arc<Material> =
+        Arc::new(Metal::new_solid(Vec3::new(0.8, 0.8, 0.85), Some(0.05)).into());
+    let wood: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.55, 0.35, 0.2).into());
+
+    let rounded = RoundedBox::new(
+        Vec3::new(-1.5, -0.75, 0.0),
+        Vec3::new(0.0, 0.75, 1.5),
+        0.25,
+        metal,
+    )
+    .with_object(ObjectId::register("metal_rounded_box"));
+    let crate_box = AaBox::new(Vec3::new(0.5, -0.5, 0.0), Vec3::new(1.5, 0.5, 1.0), wood)
+        .with_object(ObjectId::register("wooden_crate"));
+
+    let mut shapes = generate_ground_plane(20.0, 20.0, 0.0, checker_mat, true);
+    shapes.push(rounded.into());
+    shapes.push(crate_box.into());
+    shapes
+}
+
+/// Builds a ball of smoke that thins out smoothly from `peak_density` at its center to nothing
+/// at `radius`, in a grid with `resolution` voxels per side
+pub fn smoke_ball_grid(
+    center: Vec3,
+    radius: Float,
+    peak_density: Float,
+    resolution: usize,
+) -> VoxelGrid {
+    // Leave a margin of empty space around the ball, which marching skips
+    let half_size = Vec3::repeat(radius * 1.25);
+    VoxelGrid::from_fn(
+        [resolution; 3],
+        center - half_size,
+        center + half_size,
+        |point| {
+            let falloff = (1.0 - (point - center).norm_squared() / (radius * radius)).max(0.0);
+            peak_density * falloff * falloff
+        },
+    )
+}
+
+/// Looks at the ball of [`smoke_ball`] from a little above, with the floor showing through its
+/// thin edges
+pub fn smoke_ball_camera() -> Camera {
+    let center = Vec3::new(4.0, -6.0, 3.0);
+    let lookat = Vec3::new(0.0, 0.0, 1.3);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH as usize,
+        HEIGHT as usize,
+        64,
+        MAX_DEPTH,
+        30.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// A soft ball of smoke hovering over a checkered floor
+pub fn smoke_ball() -> Vec<Shape> {
+    let even_texture = SolidColor::new_rgb(0.1, 0.1, 0.1).into();
+    let odd_texture = SolidColor::new_rgb(0.95, 0.95, 0.95).into();
+    let checker_tex = CheckerTexture::new(0.5, even_texture, odd_texture).into();
+    let checker_mat: Arc<Material> = Arc::new(Lambertian::new(checker_tex).into());
+    let smoke_mat: Arc<Material> = Arc::new(Volumetric::new(Vec3::new(0.9, 0.9, 0.9), 0.3).into());
+
+    let grid = smoke_ball_grid(Vec3::new(0.0, 0.0, 1.5), 1.2, 4.0, 64);
+    let step_size = grid.voxel_size().min() / 2.0;
+    let smoke = HeterogeneousMedium::new(Arc::new(grid), step_size, smoke_mat)
+        .with_object(ObjectId::register("smoke_ball"));
+
+    let mut shapes = generate_ground_plane(20.0, 20.0, 0.0, checker_mat, true);
+    shapes.push(smoke.into());
+    shapes
+}
+
+/// The camera from the final render of Ray Tracing in One Weekend, for use with
+/// [`rtiow_final`]. The book is Y-up, so unlike the other cameras this one uses Y as up.
+/// Encodes images with a gamma of 2 and disables Russian roulette, like the book.
+pub fn rtiow_camera() -> Camera {
+    let mut camera = Camera::new(
+        Vec3::new(13.0, 2.0, 3.0),
+        Vec3::zeros(),
+        Vec3::y(),
+        10.0,
+        0.6,
+        1200,
+        675,
+        500,
+        50,
+        20.0,
+        0.001..Float::MAX,
+    );
+    camera.gamma = 2.0;
+    camera.fidelity = RenderFidelity::Reference;
+    camera
+}
+
+/// The final scene of Ray Tracing in One Weekend: a field of small random spheres around three
+/// big ones on a huge ground sphere, under the book's gradient background and without tonemapping.
+/// The layout is seeded so it's the same every run, but it can't match the book's random
+/// numbers, so only compare the three big spheres and overall statistics against its images.
+pub fn rtiow_final(seed: u64) -> (Vec<Shape>, Surroundings) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut shapes: Vec<Shape> = Vec::new();
+
+    let ground: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    shapes.push(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground).into());
+
+    let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: Float = rng.gen();
+            let center = Vec3::new(
+                a as Float + 0.9 * rng.gen::<Float>(),
+                0.2,
+                b as Float + 0.9 * rng.gen::<Float>(),
+            );
+            if (center - Vec3::new(4.0, 0.2, 0.0)).norm() <= 0.9 {
+                continue;
+            }
+            let material: Arc<Material> = if choose_mat < 0.8 {
+                let albedo = Vec3::random(&mut rng, 0.0, 1.0)
+                    .component_mul(&Vec3::random(&mut rng, 0.0, 1.0));
+                Arc::new(Lambertian::new(SolidColor::new(albedo).into()).into())
+            } else if choose_mat < 0.95 {
+                let albedo = Vec3::random(&mut rng, 0.5, 1.0);
+                let fuzz = rng.gen_range(0.0..0.5);
+                Arc::new(Metal::new_solid(albedo, Some(fuzz)).into())
+            } else {
+                glass.clone()
+            };
+            shapes.push(Sphere::new(center, 0.2, material).into());
+        }
+    }
+
+    let brown: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.4, 0.2, 0.1).into());
+    let bronze: Arc<Material> = Arc::new(Metal::new_solid(Vec3::new(0.7, 0.6, 0.5), None).into());
+    shapes.push(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, glass).into());
+    shapes.push(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, brown).into());
+    shapes.push(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, bronze).into());
+
+    let surroundings = Surroundings::default()
+        .with_background(Background::rtiow())
+        .with_tonemap(Tonemap::Clamp);
+    (shapes, surroundings)
+}
+
+/// Returns the parallelogram spanned by `u` and `v` from `origin` as two triangles, which face
+/// the side `u × v` points to
+fn quad(origin: Vec3, u: Vec3, v: Vec3, material: Arc<Material>) -> [Shape; 2] {
+    [
+        Triangle::new(origin, origin + u, origin + u + v, material.clone()).into(),
+        Triangle::new(origin, origin + u + v, origin + v, material).into(),
+    ]
+}
+
+/// Looks into the open side of [`cornell_box`] and renders it with the bidirectional integrator
+pub fn cornell_box_camera() -> Camera {
+    let center = Vec3::new(0.5, -1.4, 0.5);
+    let lookat = Vec3::new(0.5, 0.5, 0.5);
+    let mut camera = Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        600,
+        600,
+        256,
+        10,
+        40.0,
+        0.0..Float::MAX,
+    );
+    camera.fidelity = RenderFidelity::Reference;
+    camera.integrator = Integrator::Bidirectional;
+    camera
+}
+
+/// A unit Cornell box: white floor, ceiling and back wall, a red wall on the left and a green
+/// one on the right, lit only by a square light on the ceiling. The side facing -Y is open to a
+/// black background, so the light is the only thing lighting the scene.
+pub fn cornell_box() -> (Vec<Shape>, Surroundings) {
+    let white: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.73, 0.73, 0.73).into());
+    let red: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.65, 0.05, 0.05).into());
+    let green: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.12, 0.45, 0.15).into());
+    let light: Arc<Material> = Arc::new(DiffuseLight::new_rgb_solid(15.0, 15.0, 15.0).into());
+    let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
+    let (x, y, z) = (Vec3::x(), Vec3::y(), Vec3::z());
+
+    let mut shapes = Vec::new();
+    shapes.extend(quad(Vec3::zeros(), x, y, white.clone()));
+    shapes.extend(quad(z, y, x, white.clone()));
+    shapes.extend(quad(y, x, z, white.clone()));
+    shapes.extend(quad(Vec3::zeros(), y, z, red));
+    shapes.extend(quad(x, z, y, green));
+    // Just below the ceiling so the two never overlap
+    shapes.extend(quad(Vec3::new(0.4, 0.4, 0.999), 0.2 * y, 0.2 * x, light));
+    shapes.push(Sphere::new(Vec3::new(0.3, 0.6, 0.2), 0.2, white).into());
+    shapes.push(Sphere::new(Vec3::new(0.7, 0.35, 0.2), 0.2, glass).into());
+
+    let black = Background::Gradient {
+        up: z,
+        bottom: Vec3::zeros(),
+        top: Vec3::zeros(),
+    };
+    (shapes, Surroundings::default().with_background(black))
+}
+
+/// Looks across [`enclosed_room`] from just inside its front wall at the ball on the floor, and
+/// renders it with the bidirectional integrator
+pub fn enclosed_room_camera() -> Camera {
+    let center = Vec3::new(0.5, 0.1, 0.5);
+    let lookat = Vec3::new(0.5, 0.6, 0.25);
+    let mut camera = Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        600,
+        600,
+        256,
+        10,
+        70.0,
+        0.0..Float::MAX,
+    );
+    camera.fidelity = RenderFidelity::Reference;
+    camera.integrator = Integrator::Bidirectional;
+    camera
+}
+
+/// A closed white room lit by one tiny, bright light in a corner of the ceiling, for a camera
+/// inside looking from `(0.5, 0.1, 0.5)` like [`enclosed_room_camera`]. Paths from the camera almost never find the light on
+/// their own, which is the case the bidirectional integrator is for.
+pub fn enclosed_room() -> (Vec<Shape>, Surroundings) {
+    let white: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.73, 0.73, 0.73).into());
+    let light: Arc<Material> = Arc::new(DiffuseLight::new_rgb_solid(500.0, 500.0, 500.0).into());
+    let (x, y, z) = (Vec3::x(), Vec3::y(), Vec3::z());
+
+    let mut shapes = Vec::new();
+    shapes.extend(quad(Vec3::zeros(), x, y, white.clone()));
+    shapes.extend(quad(z, y, x, white.clone()));
+    shapes.extend(quad(Vec3::zeros(), z, x, white.clone()));
+    shapes.extend(quad(y, x, z, white.clone()));
+    shapes.extend(quad(Vec3::zeros(), y, z, white.clone()));
+    shapes.extend(quad(x, z, y, white.clone()));
+    shapes.extend(quad(Vec3::new(0.9, 0.9, 0.999), 0.05 * y, 0.05 * x, light));
+    shapes.push(Sphere::new(Vec3::new(0.5, 0.6, 0.2), 0.2, white).into());
+
+    let black = Background::Gradient {
+        up: z,
+        bottom: Vec3::zeros(),
+        top: Vec3::zeros(),
+    };
+    (shapes, Surroundings::default().with_background(black))
+}
+
+/// Looks at [`glowing_sphere`] from the side and renders it with the bidirectional integrator,
+/// since the path tracer only finds the light by chance
+pub fn glowing_sphere_camera() -> Camera {
+    let center = Vec3::new(0.0, -7.0, 2.0);
+    let lookat = Vec3::new(0.0, 0.0, 1.2);
+    let mut camera = Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        800,
+        600,
+        256,
+        MAX_DEPTH,
+        40.0,
+        0.0..Float::MAX,
+    );
+    camera.integrator = Integrator::Bidirectional;
+    camera
+}
+
+/// A glowing sphere hanging above a ground plane at night, lighting a matte sphere and a
+/// mirrored one beside it. The background is a faint blue so everything else in the picture
+/// comes from the light.
+pub fn glowing_sphere() -> (Vec<Shape>, Surroundings) {
+    let ground: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    let matte: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.7, 0.3, 0.2).into());
+    let mirror: Arc<Material> =
+        Arc::new(Metal::new_solid(Vec3::new(0.8, 0.8, 0.8), Some(0.05)).into());
+    let light: Arc<Material> = Arc::new(
+        DiffuseLight::new_rgb_solid(1.0, 0.85, 0.6)
+            .with_intensity(12.0)
+            .into(),
+    );
+
+    let shapes: Vec<Shape> = vec![
+        Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, ground).into(),
+        Sphere::new(Vec3::new(0.0, 0.0, 2.4), 0.6, light).into(),
+        Sphere::new(Vec3::new(-1.3, 0.0, 0.8), 0.8, matte).into(),
+        Sphere::new(Vec3::new(1.3, 0.0, 0.8), 0.8, mirror).into(),
+    ];
+
+    let night = Background::Gradient {
+        up: Vec3::z(),
+        bottom: Vec3::new(0.0, 0.0, 0.0),
+        top: Vec3::new(0.01, 0.015, 0.03),
+    };
+    (shapes, Surroundings::default().with_background(night))
+}
+
+/// Looks across [`sunset`] toward the low sun
+pub fn sunset_camera() -> Camera {
+    let center = Vec3::new(0.0, -6.0, 1.0);
+    let lookat = Vec3::new(0.0, 0.0, 0.8);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        800,
+        450,
+        128,
+        MAX_DEPTH,
+        45.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// A matte sphere and a mirrored one on a ground plane under a hazy sky, with the sun five
+/// degrees above the horizon behind them
+pub fn sunset() -> (Vec<Shape>, Surroundings) {
+    let ground: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    let matte: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.8, 0.8, 0.8).into());
+    let mirror: Arc<Material> =
+        Arc::new(Metal::new_solid(Vec3::new(0.9, 0.9, 0.9), Some(0.02)).into());
+
+    let shapes: Vec<Shape> = vec![
+        Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, ground).into(),
+        Sphere::new(Vec3::new(-1.1, 0.0, 0.8), 0.8, matte).into(),
+        Sphere::new(Vec3::new(1.1, 0.0, 0.8), 0.8, mirror).into(),
+    ];
+
+    let sky = SkySettings::default()
+        .with_turbidity(4.0)
+        .with_sun_direction(Vec3::new(0.3, 1.0, 0.0))
+        .with_sun_elevation(5.0);
+    let surroundings = Surroundings::default()
+        .with_sky(&sky)
+        .expect("the sunset sky is valid")
+        .with_sun_disc(SunDisc::default());
+    (shapes, surroundings)
+}
+
+/// Looks at the spheres of [`environment_spheres`] from the side, so the left edge of the
+/// mirror reflects the column where the panorama wraps around
+pub fn environment_spheres_camera() -> Camera {
+    let center = Vec3::new(0.0, -6.0, 0.0);
+    let lookat = Vec3::zeros();
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        800,
+        450,
+        128,
+        MAX_DEPTH,
+        40.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// A mirrored sphere between two matte ones, floating in the panorama `environment` spun
+/// `rotation` degrees around the up axis, like [`World::build_with_env`]
+pub fn environment_spheres(environment: Image, rotation: Float) -> (Vec<Shape>, Surroundings) {
+    let matte: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.8, 0.8, 0.8).into());
+    let mirror: Arc<Material> =
+        Arc::new(Metal::new_solid(Vec3::new(0.95, 0.95, 0.95), None).into());
+
+    let shapes: Vec<Shape> = vec![
+        Sphere::new(Vec3::new(-2.2, 0.0, 0.0), 0.8, matte.clone()).into(),
+        Sphere::new(Vec3::zeros(), 1.0, mirror).into(),
+        Sphere::new(Vec3::new(2.2, 0.0, 0.0), 0.8, matte).into(),
+    ];
+    let panorama = Background::Environment(EnvironmentMap::new(environment, rotation));
+    (shapes, Surroundings::default().with_background(panorama))
+}
+
+/// Looks at the three spheres of [`uv_mapping`] side by side
+pub fn uv_mapping_camera() -> Camera {
+    let center = Vec3::new(0.0, -9.0, 1.5);
+    let lookat = Vec3::new(0.0, 0.0, 0.9);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        900,
+        360,
+        64,
+        MAX_DEPTH,
+        30.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// The same checker on three spheres: left in world space, where the grid slices through the
+/// sphere in distorted bands, in the middle over spherical UVs, whose checks pinch into slivers
+/// at the poles, and right over equal-area UVs, whose checks all cover the same area. A box
+/// behind them shows the cube map unwrap running over its edges.
+pub fn uv_mapping() -> (Vec<Shape>, Surroundings) {
+    let dark = || SolidColor::new(Vec3::new(0.1, 0.1, 0.1)).into();
+    let light = || SolidColor::new(Vec3::new(0.9, 0.9, 0.9)).into();
+    let world_checker: Arc<Material> =
+        Arc::new(Lambertian::new(CheckerTexture::new(0.3, dark(), light()).into()).into());
+    let uv_checker: Arc<Material> = Arc::new(
+        Lambertian::new(CheckerTexture::new_uv(1.0 / 12.0, dark(), light()).into()).into(),
+    );
+    let ground: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+
+    let radius = 0.9;
+    let shapes: Vec<Shape> = vec![
+        Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, ground).into(),
+        Sphere::new(Vec3::new(-2.0, 0.0, radius), radius, world_checker).into(),
+        Sphere::new(Vec3::new(0.0, 0.0, radius), radius, uv_checker.clone()).into(),
+        Sphere::new(Vec3::new(2.0, 0.0, radius), radius, uv_checker.clone())
+            .with_uv_mode(UvMode::EqualArea)
+            .into(),
+        AaBox::new(
+            Vec3::new(-0.6, 3.0, 0.0),
+            Vec3::new(0.6, 4.2, 1.2),
+            uv_checker,
+        )
+        .with_uv_mode(UvMode::CubeMap)
+        .into(),
+    ];
+
+    (
+        shapes,
+        Surroundings::default().with_background(Background::rtiow()),
+    )
+}
+
+/// Looks at the two moons of [`bumpy_moon`] side by side with the bidirectional integrator,
+/// since the path tracer only finds the small sun by chance
+pub fn bumpy_moon_camera() -> Camera {
+    let center = Vec3::new(0.0, -8.0, 0.0);
+    let lookat = Vec3::zeros();
+    let mut camera = Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        900,
+        450,
+        64,
+        MAX_DEPTH,
+        30.0,
+        0.0..Float::MAX,
+    );
+    camera.integrator = Integrator::Bidirectional;
+    camera
+}
+
+/// The moon texture on two spheres lit from high up on the right, flat on the left and used as
+/// its own bump map on the right, where the craters catch the light toward the terminator
+pub fn bumpy_moon() -> (Vec<Shape>, Surroundings) {
+    let moon_bytes = embedded("textures/moon_hires.jpg");
+    let moon_image = ImageTexture::load_embedded_image(moon_bytes);
+    let moon = || ImageTexture::shared(moon_image.clone()).into();
+    let flat: Arc<Material> = Arc::new(Lambertian::new(moon()).into());
+    let bumpy: Arc<Material> = Arc::new(Lambertian::new(moon()).with_bump(moon(), 0.004).into());
+    let sun: Arc<Material> = Arc::new(
+        DiffuseLight::new_rgb_solid(1.0, 0.95, 0.9)
+            .with_intensity(400.0)
+            .into(),
+    );
+
+    let shapes: Vec<Shape> = vec![
+        Sphere::new(Vec3::new(-1.2, 0.0, 0.0), 1.0, flat).into(),
+        Sphere::new(Vec3::new(1.2, 0.0, 0.0), 1.0, bumpy).into(),
+        Sphere::new(Vec3::new(12.0, -4.0, 36.0), 2.0, sun).into(),
+    ];
+
+    let black = Background::Gradient {
+        up: Vec3::z(),
+        bottom: Vec3::zeros(),
+        top: Vec3::zeros(),
+    };
+    (shapes, Surroundings::default().with_background(black))
+}
+
+/// Looks at the sphere of [`quick_sphere`] from a little above, with it filling most of the
+/// frame
+pub fn quick_sphere_camera() -> Camera {
+    let center = Vec3::new(0.0, -6.0, 2.2);
+    let lookat = Vec3::new(0.0, 0.0, 1.0);
+    Camera::new(
+        center,
+        lookat,
+        Vec3::z(),
+        center.metric_distance(&lookat),
+        0.0,
+        WIDTH as usize,
+        HEIGHT as usize,
+        64,
+        MAX_DEPTH,
+        30.0,
+        0.0..Float::MAX,
+    )
+}
+
+/// A sphere wearing the material [`Material::infer_from_image`] makes of the texture at
+/// `texture_path` and the maps next to it, on a gray floor under the sky, for seeing what a
+/// texture looks like from nothing but its path. Prints which maps it used and what it left out.
+pub fn quick_sphere(texture_path: &Path) -> Result<(Camera, Vec<Shape>), String> {
+    let inferred = Material::infer_from_image(ImageSource::Path(texture_path))?;
+    println!(
+        "{} looks like {}",
+        texture_path.display(),
+        inferred.material.name()
+    );
+    for (kind, path) in &inferred.maps {
+        println!("using {} map {}", kind.name(), path.display());
+    }
+    for note in &inferred.notes {
+        println!("Warning: {}", note);
+    }
+    let ground: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    let shapes = vec![
+        Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, ground).into(),
+        Sphere::new(Vec3::new(0.0, 0.0, 1.0), 1.0, Arc::new(inferred.material)).into(),
+    ];
+    Ok((quick_sphere_camera(), shapes))
+}
+
+/// Subtrees of a sphereflake with at most this many levels are stored as plain spheres,
+/// deeper ones as instances of a shared prototype
+const SPHEREFLAKE_FLAT_LEVELS: usize = 3;
+
+/// Returns the number of spheres in a sphereflake of `depth`: 1 + 9 + 9² + ... + 9^depth
+pub fn sphereflake_sphere_count(depth: usize) -> usize {
+    (9usize.pow(depth as u32 + 1) - 1) / 8
+}
+
+/// Directions of a sphere's 9 children in its local frame, where +Z points away from its parent:
+/// 6 around the equator and 3 higher up between them
+fn sphereflake_child_directions() -> [Vec3; 9] {
+    std::array::from_fn(|i| {
+        let (azimuth, elevation) = if i < 6 {
+            (i as Float * 60.0, 0.0)
+        } else {
+            (30.0 + (i - 6) as Float * 120.0, 60.0 as Float)
+        };
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        )
+    })
+}
+
+/// Adds the sphereflake rooted at `level` to `shapes`, placed by `transform` from a local space
+/// where the root sphere has radius 1 at the origin
+fn add_sphereflake(
+    level: usize,
+    depth: usize,
+    transform: &Similarity3<Float>,
+    materials: &[Arc<Material>],
+    prototypes: &mut HashMap<usize, Arc<Prototype>>,
+    shapes: &mut Vec<Shape>,
+) {
+    let center = (transform * nalgebra::Point3::origin()).coords;
+    shapes.push(Sphere::new(center, transform.scaling(), materials[level].clone()).into());
+    if level == depth {
+        return;
+    }
+
+    let child_levels = depth - level;
+    for direction in sphereflake_child_directions() {
+        let child =
+            transform * instance::place(direction * (1.0 + 1.0 / 3.0), direction, 1.0 / 3.0);
+        if child_levels <= SPHEREFLAKE_FLAT_LEVELS {
+            add_sphereflake(level + 1, depth, &child, materials, prototypes, shapes);
+        } else {
+            let prototype = sphereflake_prototype(level + 1, depth, materials, prototypes);
+            shapes.push(Instance::new(prototype, child).into());
+        }
+    }
+}
+
+/// Returns the shared prototype for a sphereflake rooted at `level`, building it the first time
+fn sphereflake_prototype(
+    level: usize,
+    depth: usize,
+    materials: &[Arc<Material>],
+    prototypes: &mut HashMap<usize, Arc<Prototype>>,
+) -> Arc<Prototype> {
+    if let Some(prototype) = prototypes.get(&level) {
+        return prototype.clone();
+    }
+    let mut shapes = Vec::new();
+    add_sphereflake(
+        level,
+        depth,
+        &Similarity3::identity(),
+        materials,
+        prototypes,
+        &mut shapes,
+    );
+    let prototype = Arc::new(Prototype::new(shapes));
+    prototypes.insert(level, prototype.clone());
+    prototype
+}
+
+/// Generates Eric Haines' sphereflake fractal, resting on the origin with Z up: a sphere of
+/// `base_radius` with 9 spheres a third its size around it, each with 9 of their own, and so on
+/// for `depth` levels. `material_fn` picks the material for each level, with 0 being the root.
+///
+/// Deep levels are instances of shared subtrees, so memory grows with `depth` instead of with
+/// the sphere count from [`sphereflake_sphere_count`]. Built into a `World`, depth 6 (597,871
+/// spheres) and depth 8 (48 million spheres) both take under 1 MB.
+pub fn sphereflake(
+    depth: usize,
+    base_radius: Float,
+    material_fn: impl Fn(usize) -> Arc<Material>,
+) -> Vec<Shape> {
+    let materials: Vec<Arc<Material>> = (0..=depth).map(material_fn).collect();
+    let transform = instance::place(Vec3::new(0.0, 0.0, base_radius), Vec3::z(), base_radius);
+    let mut shapes = Vec::new();
+    add_sphereflake(
+        0,
+        depth,
+        &transform,
+        &materials,
+        &mut HashMap::new(),
+        &mut shapes,
+    );
+    shapes
+}
+
+/// Generates rolling hills `size` units across, centered on the origin with Z up, as a grid of
+/// `cells` by `cells` squares of two triangles each, draped in a generated grass and rock texture
+/// so every hit looks it up. The hills are a few sine waves at random angles and phases, seeded
+/// by `seed`, at most about 2 units high.
+pub fn terrain(size: Float, cells: usize, seed: u64) -> Vec<Shape> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let waves: Vec<(Vec3, Float, Float)> = (0..4)
+        .map(|octave| {
+            let angle: Float = rng.gen_range(0.0..std::f64::consts::TAU);
+            let frequency = 0.4 * (2 as Float).powi(octave);
+            let direction = Vec3::new(angle.cos(), angle.sin(), 0.0) * frequency;
+            (
+                direction,
+                rng.gen_range(0.0..std::f64::consts::TAU),
+                1.0 / frequency.max(1.0),
+            )
+        })
+        .collect();
+    let point = |i: usize, j: usize| {
+        let x = (i as Float / cells as Float - 0.5) * size;
+        let y = (j as Float / cells as Float - 0.5) * size;
+        let z: Float = waves
+            .iter()
+            .map(|(direction, phase, amplitude)| {
+                amplitude * (direction.x * x + direction.y * y + phase).sin()
+            })
+            .sum();
+        (
+            Vec3::new(x, y, z),
+            Vec2::new(i as Float, j as Float) / cells as Float,
+        )
+    };
+    let texture = ImageTexture::new(terrain_image(256, &mut rng)).into();
+    let material: Arc<Material> = Arc::new(Lambertian::new(texture).into());
+    let object = ObjectId::register("terrain");
+
+    let mut shapes = Vec::with_capacity(2 * cells * cells);
+    for (i, j) in (0..cells).cartesian_product(0..cells) {
+        let (a, uv_a) = point(i, j);
+        let (b, uv_b) = point(i + 1, j);
+        let (c, uv_c) = point(i + 1, j + 1);
+        let (d, uv_d) = point(i, j + 1);
+        let lower = Triangle::new_with_uv(a, b, c, uv_a, uv_b, uv_c, material.clone());
+        let upper = Triangle::new_with_uv(a, c, d, uv_a, uv_c, uv_d, material.clone());
+        shapes.push(lower.with_object(object).into());
+        shapes.push(upper.with_object(object).into());
+    }
+    shapes
+}
+
+/// A `size` pixel square of grass green blotched with rocky brown, with a little per-pixel noise
+fn terrain_image(size: usize, rng: &mut StdRng) -> Image {
+    let grass = Vec3::new(0.15, 0.35, 0.08);
+    let rock = Vec3::new(0.4, 0.32, 0.25);
+    let pixels = (0..size * size)
+        .map(|k| {
+            let (u, v) = ((k % size) as Float, (k / size) as Float);
+            let frequency = std::f64::consts::TAU * 5.0 / size as Float;
+            let blotch = 0.5 + 0.5 * (u * frequency).sin() * (v * frequency * 1.3 + 1.0).sin();
+            grass.lerp(&rock, blotch) * rng.gen_range(0.85..1.15)
+        })
+        .collect();
+    Image {
+        pixels,
+        width: size,
+        height: size,
+        gamma: 2.2,
+        metadata: Vec::new(),
+        alpha: None,
+    }
+}
+
+/// Generates a pile of `count` glass spheres of random sizes within 2 units of the origin, on a
+/// checkered floor at Z = 0 with Z up. Rays refract through several spheres on their way to the
+/// floor, so paths run long. The spheres don't overlap, and are placed in the order `seed` gives,
+/// up to `count` of them, so crowded piles can have fewer.
+pub fn glass_cluster(count: usize, seed: u64) -> Vec<Shape> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
+    let even_texture = SolidColor::new(Vec3::new(0.05, 0.05, 0.05)).into();
+    let odd_texture = SolidColor::new(Vec3::new(0.9, 0.9, 0.9)).into();
+    let checker_tex = CheckerTexture::new(0.5, even_texture, odd_texture).into();
+    let floor: Arc<Material> = Arc::new(Lambertian::new(checker_tex).into());
+
+    let mut spheres: Vec<(Vec3, Float)> = Vec::with_capacity(count);
+    for _ in 0..count * 20 {
+        if spheres.len() == count {
+            break;
+        }
+        let radius = rng.gen_range(0.15..0.5);
+        let center = Vec3::new(
+            rng.gen_range(-2.0..2.0),
+            rng.gen_range(-2.0..2.0),
+            radius + rng.gen_range(0.0..1.5),
+        );
+        let overlaps = spheres
+            .iter()
+            .any(|(other, other_radius)| center.metric_distance(other) < radius + other_radius);
+        if !overlaps {
+            spheres.push((center, radius));
+        }
+    }
+    let mut shapes = generate_ground_plane(40.0, 40.0, 0.0, floor, true);
+    shapes.extend(
+        spheres
+            .into_iter()
+            .map(|(center, radius)| Sphere::new(center, radius, glass.clone()).into()),
+    );
+    shapes
+}
+
 pub fn mesh_scene() -> Vec<Shape> {
     let mut shapes = Vec::new();
 
-    let bunny = "stanford-bunny.obj";
-    let bimba = "bimba.obj";
-    let teapot = "teapot.obj";
-    let egypt = "Nefertiti.obj";
-    let dillo = "armadillo.obj";
+    let [bunny, bimba, teapot, egypt, dillo] = MESH_SCENE_ASSETS.map(asset_path);
 
     let even_texture = SolidColor::new(Vec3::new(0.1, 0.1, 0.1)).into();
     let odd_texture = SolidColor::new(Vec3::new(0.95, 0.95, 0.95)).into();
@@ -373,29 +1500,68 @@ pub fn mesh_scene() -> Vec<Shape> {
 
     let headass = scale_rotate_mat(90.0, 0.0, 0.0, 0.02);
 
-    let bimba = hittable::load_obj(bimba, red_metal.clone(), Some(upright_big), false);
-    let bunny = hittable::load_obj(bunny, plaster.clone(), Some(upright_big), false);
-    let teapot = hittable::load_obj(teapot, dull_gray_metal.clone(), Some(smaller), false);
-    let neferiti = hittable::load_obj(egypt, frosty_glass.clone(), Some(headass), false);
-    let armadillo = hittable::load_obj(dillo, dull_gray_metal.clone(), None, false);
+    let bimba = hittable::load_obj(
+        &bimba,
+        red_metal.clone(),
+        Some(upright_big),
+        false,
+        &LoadOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("{}", err))
+    .0;
+    let bunny = hittable::load_obj(
+        &bunny,
+        plaster.clone(),
+        Some(upright_big),
+        false,
+        &LoadOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("{}", err))
+    .0;
+    let teapot = hittable::load_obj(
+        &teapot,
+        dull_gray_metal.clone(),
+        Some(smaller),
+        false,
+        &LoadOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("{}", err))
+    .0;
+    let neferiti = hittable::load_obj(
+        &egypt,
+        frosty_glass.clone(),
+        Some(headass),
+        false,
+        &LoadOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("{}", err))
+    .0;
+    let armadillo = hittable::load_obj(
+        &dillo,
+        dull_gray_metal.clone(),
+        None,
+        false,
+        &LoadOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("{}", err))
+    .0;
 
     let scene = vec![bimba, bunny, teapot, neferiti, armadillo];
 
     for meshes in scene {
         for mesh in meshes {
-            for tri in mesh {
-                shapes.push(tri.into());
-            }
+            shapes.push(mesh.into());
         }
     }
 
     shapes
 }
 
-pub fn gltf_test() -> Vec<Shape> {
+pub fn gltf_test() -> (Vec<Shape>, LoadReport) {
     let mut shapes = Vec::new();
+    let mut report = LoadReport::default();
 
-    let b = "/Users/thabnir/code/rt/src/assets/meshes";
+    let b = "meshes";
     let s = "scene.gltf";
     let skull = format!("{b}/human_skull/{s}");
     let fish = format!("{b}/cut_fish/{s}"); // works
@@ -405,14 +1571,21 @@ pub fn gltf_test() -> Vec<Shape> {
     let cathedral = format!("{b}/cathedral/{s}"); // works-ish
     let dodge = format!("{b}/dodge_charger/{s}"); // works
 
-    let paths = [dodge];
+    let paths = GLTF_TEST_ASSETS.map(asset_path);
 
     // let plaster: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.95, 0.70, 0.85).into());
     let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
     // let frosty_glass: Arc<Material> = Arc::new(Dielectric::new_frosted(1.5, 0.15).into());
     // let white_plaster: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(1.0, 1.0, 1.0).into());
 
-    let scenes = paths.iter().map(|path| load_gltf(path, glass.clone()));
+    // These assets mix clockwise and counterclockwise triangles
+    let load_options = LoadOptions {
+        repair_orientation: true,
+        ..LoadOptions::default()
+    };
+    let scenes = paths.iter().map(|path| {
+        load_gltf(path, glass.clone(), &load_options).unwrap_or_else(|err| panic!("{}", err))
+    });
 
     let pitch_rads = (0.0 as Float).to_radians();
     let yaw_rads = (0.0 as Float).to_radians();
@@ -426,35 +1599,27 @@ pub fn gltf_test() -> Vec<Shape> {
 
     let rotation_matrix = nalgebra::Rotation3::from_euler_angles(0.0, 0.0, extra) * rotation_matrix;
 
-    for scene in scenes {
-        for mesh in scene {
-            for tri in mesh {
-                let scale_mat = nalgebra::Matrix4::scale(&nalgebra::Matrix4::identity(), 0.35);
-                let tri = tri.transform(&rotation_matrix.matrix().to_homogeneous());
-                let tri = tri.transform(&scale_mat);
-                shapes.push(tri.into());
-            }
-        }
+    let placement = Similarity3::from_parts(
+        nalgebra::Translation3::identity(),
+        nalgebra::UnitQuaternion::from_rotation_matrix(&rotation_matrix),
+        0.35,
+    );
+    for (scene, scene_report) in scenes {
+        report.merge(scene_report);
+        shapes.extend(scene.place(&placement).into_shapes());
     }
 
-    shapes
+    (shapes, report)
 }
 
 // TOOD: make it so that this doesn't eat up 40GB of RAM and then crash before loading
-pub fn sponza() -> Vec<Shape> {
-    let sponza_path =
-        "/Users/thabnir/code/rt/src/assets/meshes/main1_sponza/NewSponza_Main_glTF_003.gltf";
+pub fn sponza() -> (Vec<Shape>, LoadReport) {
+    let sponza_path = asset_path(SPONZA_ASSET);
 
     let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
-    let scene = load_gltf(sponza_path, glass);
-    let mut shapes = Vec::new();
-
-    for mesh in scene {
-        for tri in mesh {
-            shapes.push(tri.into());
-        }
-    }
-    shapes
+    let (scene, report) = load_gltf(&sponza_path, glass, &LoadOptions::default())
+        .unwrap_or_else(|err| panic!("{}", err));
+    (scene.into_shapes(), report)
 }
 
 pub fn scale_rotate_mat(
@@ -473,3 +1638,125 @@ pub fn scale_rotate_mat(
 
     rotation.to_homogeneous() * scalefactor
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        benchmark::{self, BenchScene},
+        camera,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn every_built_in_scene_builds() {
+        for name in BUILT_IN_SCENES {
+            // Meshes that aren't checked in can't be loaded here
+            if !built_in_scene_assets(name).unwrap().is_empty() {
+                continue;
+            }
+            let (_, shapes, surroundings) = built_in_scene(name, BUILT_IN_COVER_SEED)
+                .unwrap_or_else(|| panic!("{} is listed but doesn't build", name));
+            assert!(!shapes.is_empty(), "{} has no shapes", name);
+            // The cover's hundreds of thousands of spheres take too long to put in a BVH here
+            if PROCEDURAL_SCENES.contains(&name) {
+                continue;
+            }
+            let world = surroundings.build(shapes);
+            assert!(world.rejected.is_empty(), "{}: {}", name, world.rejected);
+        }
+        assert!(built_in_scene("no_such_scene", BUILT_IN_COVER_SEED).is_none());
+    }
+
+    /// Shapes actually stored for `shapes`, counting each prototype's only the first time
+    fn stored_shapes(shapes: &[Shape], prototypes: &mut HashSet<usize>) -> usize {
+        let mut stored = shapes.len();
+        for shape in shapes {
+            if let Shape::Instance(instance) = shape {
+                let prototype = instance.prototype();
+                if prototypes.insert(Arc::as_ptr(prototype) as usize) {
+                    stored += stored_shapes(prototype.shapes(), prototypes);
+                }
+            }
+        }
+        stored
+    }
+
+    #[test]
+    fn sphereflakes_have_every_sphere_and_share_their_subtrees() {
+        let gray: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        for depth in 0..=6 {
+            let shapes = sphereflake(depth, 1.0, |_| gray.clone());
+            let spheres: usize = shapes.iter().map(instance::primitive_count).sum();
+            assert_eq!(
+                spheres,
+                (0..=depth)
+                    .map(|level| 9usize.pow(level as u32))
+                    .sum::<usize>()
+            );
+            assert_eq!(spheres, sphereflake_sphere_count(depth));
+        }
+
+        // Each stored shape costs its own size and about two BVH nodes
+        let bytes = |depth| {
+            let shapes = sphereflake(depth, 1.0, |_| gray.clone());
+            let stored = stored_shapes(&shapes, &mut HashSet::new());
+            stored
+                * (std::mem::size_of::<Shape>()
+                    + 2 * std::mem::size_of::<bvh::bvh::BvhNode<Float, 3>>())
+        };
+        assert!(bytes(6) < 1_000_000, "depth 6 takes {} bytes", bytes(6));
+        // Two more levels are 81 times the spheres but only two more unique subtrees
+        assert!(bytes(8) < bytes(6) * 2, "depth 8 takes {} bytes", bytes(8));
+    }
+
+    /// Pixels of `image` gamma encoded as they're written out, from 0 to 1
+    fn encoded(image: &Image) -> Vec<Vec3> {
+        image
+            .pixels
+            .iter()
+            .map(|pixel| {
+                Vec3::from_iterator(camera::rgb8(pixel, image.gamma).map(|c| c as Float / 255.0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rtiow_matches_its_reference() {
+        // The benchmark's sphere field is the same scene, with the same seed
+        assert_eq!(BUILT_IN_COVER_SEED, benchmark::SEED);
+        let reference = BenchScene::SphereField.reference().unwrap();
+        let (camera, shapes, surroundings) = built_in_scene("rtiow", BUILT_IN_COVER_SEED).unwrap();
+        let mut camera = camera
+            .with_resolution(benchmark::WIDTH, benchmark::HEIGHT)
+            .with_sampling(64, camera.max_depth());
+        camera.seed = Some(benchmark::SEED);
+        let rendered = encoded(&camera.render_image(&surroundings.build(shapes)));
+
+        let mse = rendered
+            .iter()
+            .zip(&reference.pixels)
+            .map(|(a, b)| (a - b).norm_squared() / 3.0)
+            .sum::<Float>()
+            / rendered.len() as Float;
+        // Means over 8 by 8 blocks, which noise barely moves but a changed layout, material
+        // or background would
+        let block_means = |pixels: &[Vec3]| -> Vec<Vec3> {
+            let (width, height) = (benchmark::WIDTH, benchmark::HEIGHT);
+            (0..height / 8)
+                .flat_map(|by| (0..width / 8).map(move |bx| (bx, by)))
+                .map(|(bx, by)| {
+                    let block = (0..64).map(|i| pixels[(by * 8 + i / 8) * width + bx * 8 + i % 8]);
+                    block.sum::<Vec3>() / 64.0
+                })
+                .collect()
+        };
+        let worst_block = block_means(&rendered)
+            .iter()
+            .zip(block_means(&reference.pixels))
+            .map(|(a, b)| (a - b).amax())
+            .fold(0.0, Float::max);
+        assert!(mse < benchmark::TARGET_MSE, "mean squared error {}", mse);
+        assert!(worst_block < 0.02, "a block is off by {}", worst_block);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::camera::Float;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+/// Bins of [`Histogram`], one per column of its plot
+pub const HISTOGRAM_BINS: usize = 128;
+/// Rows of [`Waveform`], one per row of its plot
+const WAVEFORM_ROWS: usize = 64;
+/// Most pixels the scopes look at. Larger frames are read every few pixels in each direction,
+/// which keeps them to a millisecond or so however large the preview is.
+const MAX_SAMPLED_PIXELS: usize = 1 << 16;
+/// Size of each plot and the gap around them, in frame pixels
+const PLOT_WIDTH: usize = HISTOGRAM_BINS * 2;
+const PLOT_HEIGHT: usize = WAVEFORM_ROWS * 2;
+const PLOT_MARGIN: usize = 8;
+/// Width of the bars on either side of the histogram showing how much is clipped or crushed
+const CLIP_BAR_WIDTH: usize = 4;
+
+/// Which scopes the preview draws over the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScopeMode {
+    #[default]
+    Off,
+    Histogram,
+    /// The histogram and a waveform beside it
+    Waveform,
+}
+
+impl ScopeMode {
+    /// The mode the L key switches to from this one
+    pub fn next(&self) -> Self {
+        match self {
+            ScopeMode::Off => ScopeMode::Histogram,
+            ScopeMode::Histogram => ScopeMode::Waveform,
+            ScopeMode::Waveform => ScopeMode::Off,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScopeMode::Off => "off",
+            ScopeMode::Histogram => "histogram",
+            ScopeMode::Waveform => "histogram and waveform",
+        }
+    }
+}
+
+/// Luma of an RGBA pixel from its 8-bit channels as they're shown, already gamma encoded the way
+/// the image is written out, from 0 to 1
+fn encoded_luminance(pixel: &[u8]) -> Float {
+    let [r, g, b] = [0, 1, 2].map(|channel| Float::from(pixel[channel]) / 255.0);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// How many pixels apart the scopes read a `width` by `height` frame in each direction
+fn sample_step(width: usize, height: usize) -> usize {
+    let pixels = (width * height) as Float;
+    (pixels / MAX_SAMPLED_PIXELS as Float)
+        .sqrt()
+        .ceil()
+        .max(1.0) as usize
+}
+
+/// Index of the bin `value` from 0 to 1 falls in, out of `bins`
+fn bin(value: Float, bins: usize) -> usize {
+    ((value * bins as Float) as usize).min(bins - 1)
+}
+
+/// How a frame's luminance is spread, from the exposed pixels exactly as the preview shows them,
+/// so exposure changes show up in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub bins: [usize; HISTOGRAM_BINS],
+    /// Pixels with a channel at full brightness, which the exposure clipped
+    pub clipped: usize,
+    /// Pixels with every channel at zero
+    pub crushed: usize,
+    /// Pixels counted, which are only some of the frame's if it's large
+    pub total: usize,
+}
+
+impl Histogram {
+    /// Bins every few pixels of an RGBA `frame` `width` pixels wide
+    pub fn of_frame(frame: &[u8], width: usize) -> Self {
+        let height = frame.len() / 4 / width.max(1);
+        let step = sample_step(width, height);
+        let empty = || Histogram {
+            bins: [0; HISTOGRAM_BINS],
+            clipped: 0,
+            crushed: 0,
+            total: 0,
+        };
+        (0..height.div_ceil(step))
+            .into_par_iter()
+            .map(|row| row * step)
+            .fold(empty, |mut histogram, y| {
+                for x in (0..width).step_by(step) {
+                    let idx = (y * width + x) * 4;
+                    let pixel = &frame[idx..idx + 3];
+                    histogram.bins[bin(encoded_luminance(pixel), HISTOGRAM_BINS)] += 1;
+                    histogram.clipped += usize::from(pixel.contains(&0xff));
+                    histogram.crushed += usize::from(pixel == [0, 0, 0]);
+                    histogram.total += 1;
+                }
+                histogram
+            })
+            .reduce(empty, |mut a, b| {
+                for (a, b) in a.bins.iter_mut().zip(b.bins) {
+                    *a += b;
+                }
+                a.clipped += b.clipped;
+                a.crushed += b.crushed;
+                a.total += b.total;
+                a
+            })
+    }
+
+    /// Fraction of the counted pixels that are clipped
+    pub fn clipped_fraction(&self) -> Float {
+        self.clipped as Float / self.total.max(1) as Float
+    }
+
+    /// Fraction of the counted pixels that are crushed to black
+    pub fn crushed_fraction(&self) -> Float {
+        self.crushed as Float / self.total.max(1) as Float
+    }
+}
+
+/// How luminance is spread down each column of a frame, with the frame's columns squeezed into
+/// `columns` of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    pub columns: usize,
+    /// Pixels in each row of each column, column by column, with the darkest row first
+    pub counts: Vec<usize>,
+}
+
+impl Waveform {
+    /// Bins every few pixels of an RGBA `frame` `width` pixels wide
+    pub fn of_frame(frame: &[u8], width: usize, columns: usize) -> Self {
+        let height = frame.len() / 4 / width.max(1);
+        let step = sample_step(width, height);
+        let counts = (0..height.div_ceil(step))
+            .into_par_iter()
+            .map(|row| row * step)
+            .fold(
+                || vec![0; columns * WAVEFORM_ROWS],
+                |mut counts, y| {
+                    for x in (0..width).step_by(step) {
+                        let idx = (y * width + x) * 4;
+                        let row = bin(encoded_luminance(&frame[idx..idx + 3]), WAVEFORM_ROWS);
+                        let column = x * columns / width;
+                        counts[column * WAVEFORM_ROWS + row] += 1;
+                    }
+                    counts
+                },
+            )
+            .reduce(
+                || vec![0; columns * WAVEFORM_ROWS],
+                |mut a, b| {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        *a += b;
+                    }
+                    a
+                },
+            );
+        Waveform { columns, counts }
+    }
+}
+
+/// A rectangle of the frame that an overlay draws in and nowhere else
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlayRect {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl OverlayRect {
+    /// Sets the pixel `(x, y)` of the rectangle to `color` in an RGBA `frame` `frame_width`
+    /// pixels wide, doing nothing for pixels outside the rectangle or the frame
+    fn set(&self, frame: &mut [u8], frame_width: usize, x: usize, y: usize, color: [u8; 3]) {
+        if x >= self.width || y >= self.height || self.left + x >= frame_width {
+            return;
+        }
+        let idx = ((self.top + y) * frame_width + self.left + x) * 4;
+        if let Some(pixel) = frame.get_mut(idx..idx + 3) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+
+    /// Darkens the rectangle so what's drawn on it reads over any image
+    fn shade(&self, frame: &mut [u8], frame_width: usize) {
+        for y in 0..self.height {
+            for x in 0..self.width.min(frame_width.saturating_sub(self.left)) {
+                let idx = ((self.top + y) * frame_width + self.left + x) * 4;
+                if let Some(pixel) = frame.get_mut(idx..idx + 3) {
+                    pixel.iter_mut().for_each(|channel| *channel /= 4);
+                }
+            }
+        }
+    }
+}
+
+/// Where the scopes go in a `width` by `height` frame: the histogram in the bottom left corner,
+/// and the waveform right of it. Either is `None` if the frame is too small to fit it.
+pub fn scope_rects(width: usize, height: usize) -> (Option<OverlayRect>, Option<OverlayRect>) {
+    let fits = |left: usize| {
+        (left + PLOT_WIDTH + PLOT_MARGIN <= width && PLOT_HEIGHT + 2 * PLOT_MARGIN <= height).then(
+            || OverlayRect {
+                left,
+                top: height - PLOT_HEIGHT - PLOT_MARGIN,
+                width: PLOT_WIDTH,
+                height: PLOT_HEIGHT,
+            },
+        )
+    };
+    (fits(PLOT_MARGIN), fits(2 * PLOT_MARGIN + PLOT_WIDTH))
+}
+
+/// Height of a bar for `count` out of at most `max`, on a log scale so a few dark pixels still
+/// show next to a sky full of bright ones
+fn log_height(count: usize, max: usize, height: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    let scale = (count as Float).ln_1p() / (max.max(1) as Float).ln_1p();
+    ((scale * height as Float).round() as usize).clamp(1, height)
+}
+
+/// Draws `histogram` into `rect` of an RGBA `frame` `width` pixels wide: a grey bar per bin,
+/// with a blue bar on the left as tall as the fraction of crushed pixels and a red one on the
+/// right as tall as the fraction of clipped ones
+pub fn draw_histogram(frame: &mut [u8], width: usize, rect: &OverlayRect, histogram: &Histogram) {
+    rect.shade(frame, width);
+    let plot_width = rect.width - 2 * CLIP_BAR_WIDTH;
+    let max = histogram.bins.iter().copied().max().unwrap_or(0);
+    for x in 0..plot_width {
+        let count = histogram.bins[x * HISTOGRAM_BINS / plot_width];
+        for y in 0..log_height(count, max, rect.height) {
+            rect.set(
+                frame,
+                width,
+                CLIP_BAR_WIDTH + x,
+                rect.height - 1 - y,
+                [0xd0; 3],
+            );
+        }
+    }
+    let fraction_height = |fraction: Float| (fraction * rect.height as Float).ceil() as usize;
+    for (left, fraction, color) in [
+        (0, histogram.crushed_fraction(), [0x40, 0x80, 0xff]),
+        (
+            rect.width - CLIP_BAR_WIDTH,
+            histogram.clipped_fraction(),
+            [0xff, 0x40, 0x40],
+        ),
+    ] {
+        for y in 0..fraction_height(fraction).min(rect.height) {
+            for x in left..left + CLIP_BAR_WIDTH {
+                rect.set(frame, width, x, rect.height - 1 - y, color);
+            }
+        }
+    }
+}
+
+/// Draws `waveform` into `rect` of an RGBA `frame` `width` pixels wide, brighter green where
+/// more of a column's pixels have that luminance, with the brightest at the top
+pub fn draw_waveform(frame: &mut [u8], width: usize, rect: &OverlayRect, waveform: &Waveform) {
+    rect.shade(frame, width);
+    let max = waveform.counts.iter().copied().max().unwrap_or(0);
+    for x in 0..rect.width {
+        let column = x * waveform.columns / rect.width;
+        for y in 0..rect.height {
+            let row = (rect.height - 1 - y) * WAVEFORM_ROWS / rect.height;
+            let count = waveform.counts[column * WAVEFORM_ROWS + row];
+            if count == 0 {
+                continue;
+            }
+            let level = log_height(count, max, 0xff) as u8;
+            rect.set(frame, width, x, y, [level / 3, level, level / 3]);
+        }
+    }
+}
+
+/// Draws the scopes `mode` asks for over an RGBA `frame` `width` pixels wide, and returns the
+/// histogram so its clipping can be shown elsewhere. Returns `None` when the scopes are off or
+/// the frame is too small for them.
+pub fn draw_scopes(frame: &mut [u8], width: usize, mode: ScopeMode) -> Option<Histogram> {
+    if mode == ScopeMode::Off || width == 0 {
+        return None;
+    }
+    let height = frame.len() / 4 / width;
+    let (histogram_rect, waveform_rect) = scope_rects(width, height);
+    let histogram = Histogram::of_frame(frame, width);
+    if mode == ScopeMode::Waveform {
+        if let Some(rect) = waveform_rect {
+            let waveform = Waveform::of_frame(frame, width, rect.width);
+            draw_waveform(frame, width, &rect, &waveform);
+        }
+    }
+    draw_histogram(frame, width, &histogram_rect?, &histogram);
+    Some(histogram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RGBA frame with every pixel set by `color` from its position
+    fn frame(width: usize, height: usize, color: impl Fn(usize, usize) -> [u8; 3]) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let [r, g, b] = color(x, y);
+                [r, g, b, 0xff]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn greys_land_in_the_bin_of_their_encoded_value() {
+        let levels = [0, 1, 2, 127, 128, 254, 255];
+        let histogram = Histogram::of_frame(&frame(7, 3, |x, _| [levels[x]; 3]), 7);
+        let mut expected = [0; HISTOGRAM_BINS];
+        // A bin is two 8-bit levels wide, and full brightness is in the last one
+        for (level, bin) in levels.into_iter().zip([0, 0, 1, 63, 64, 127, 127]) {
+            assert_eq!(super::bin(Float::from(level) / 255.0, HISTOGRAM_BINS), bin);
+            expected[bin] += 3;
+        }
+        assert_eq!(histogram.bins, expected);
+        assert_eq!(
+            (histogram.crushed, histogram.clipped, histogram.total),
+            (3, 3, 21)
+        );
+
+        // A single saturated channel is clipped, though the pixel's luminance is low
+        let blue = Histogram::of_frame(&frame(2, 2, |_, _| [0, 0, 0xff]), 2);
+        assert_eq!(blue.clipped_fraction(), 1.0);
+        assert_eq!(blue.crushed_fraction(), 0.0);
+        assert_eq!(blue.bins[bin(0.0722, HISTOGRAM_BINS)], 4);
+    }
+
+    #[test]
+    fn large_frames_are_read_every_few_pixels() {
+        // Half black and half white, split down the middle
+        let (width, height) = (1024, 512);
+        let histogram = Histogram::of_frame(
+            &frame(width, height, |x, _| {
+                [if x < width / 2 { 0 } else { 0xff }; 3]
+            }),
+            width,
+        );
+        assert_eq!(sample_step(width, height), 3);
+        assert_eq!(histogram.total, 342 * 171);
+        assert!(histogram.total <= MAX_SAMPLED_PIXELS);
+        assert_eq!(histogram.bins.iter().sum::<usize>(), histogram.total);
+        assert!((histogram.crushed_fraction() - 0.5).abs() < 0.01);
+        assert!((histogram.clipped_fraction() - 0.5).abs() < 0.01);
+
+        let waveform =
+            Waveform::of_frame(&frame(width, height, |x, _| [(x / 4) as u8; 3]), width, 4);
+        // Each quarter of a left to right ramp only has the luminances of its quarter
+        for column in 0..4 {
+            let rows = &waveform.counts[column * WAVEFORM_ROWS..(column + 1) * WAVEFORM_ROWS];
+            let lit: Vec<_> = (0..WAVEFORM_ROWS).filter(|&row| rows[row] > 0).collect();
+            assert_eq!(lit.first(), Some(&(column * WAVEFORM_ROWS / 4)));
+            assert_eq!(lit.last(), Some(&((column + 1) * WAVEFORM_ROWS / 4 - 1)));
+        }
+    }
+
+    #[test]
+    fn scopes_only_draw_inside_their_rectangles() {
+        for (width, height) in [(640, 360), (300, 200), (280, 144)] {
+            let before = frame(width, height, |x, y| {
+                [(x % 251) as u8, (y % 241) as u8, 0x80]
+            });
+            let mut after = before.clone();
+            draw_scopes(&mut after, width, ScopeMode::Waveform).unwrap();
+            let rects: Vec<_> = match scope_rects(width, height) {
+                (Some(histogram), waveform) => {
+                    [Some(histogram), waveform].into_iter().flatten().collect()
+                }
+                (None, _) => panic!("no room for the histogram at {}x{}", width, height),
+            };
+            for rect in &rects {
+                assert!(rect.left + rect.width + PLOT_MARGIN <= width);
+                assert!(rect.top + rect.height + PLOT_MARGIN <= height);
+            }
+            for y in 0..height {
+                for x in 0..width {
+                    let inside = rects.iter().any(|r| {
+                        (r.left..r.left + r.width).contains(&x)
+                            && (r.top..r.top + r.height).contains(&y)
+                    });
+                    let idx = (y * width + x) * 4;
+                    if !inside {
+                        assert_eq!(after[idx..idx + 4], before[idx..idx + 4], "({}, {})", x, y);
+                    }
+                    // Alpha is left alone everywhere
+                    assert_eq!(after[idx + 3], 0xff);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_too_small_for_the_scopes_are_left_alone() {
+        // The waveform doesn't fit beside the histogram, and then neither fits
+        assert!(matches!(scope_rects(300, 200), (Some(_), None)));
+        for (width, height) in [(200, 200), (300, 100), (0, 0)] {
+            let before = frame(width, height, |x, y| [x as u8, y as u8, 0x80]);
+            let mut after = before.clone();
+            assert_eq!(scope_rects(width, height), (None, None));
+            assert_eq!(draw_scopes(&mut after, width, ScopeMode::Waveform), None);
+            assert_eq!(after, before);
+        }
+        let mut off = frame(640, 360, |_, _| [0x80; 3]);
+        assert_eq!(draw_scopes(&mut off, 640, ScopeMode::Off), None);
+        assert!(off
+            .iter()
+            .all(|&channel| channel == 0x80 || channel == 0xff));
+    }
+}
@@ -0,0 +1,27 @@
+//! Files on disk for tests that read or write them
+
+use std::{
+    fs,
+    panic::Location,
+    path::{Path, PathBuf},
+};
+
+/// Returns an empty directory in the system's temporary directory for the test `name`, named
+/// after the file the test is in and the process running it, so tests in different files and
+/// runs in parallel don't share one. Anything an earlier run left in it is removed.
+#[track_caller]
+pub fn scratch(name: &str) -> PathBuf {
+    let file = Path::new(Location::caller().file());
+    let module = file.file_stem().unwrap_or_default().to_string_lossy();
+    let directory =
+        std::env::temp_dir().join(format!("rt-{}-{}-{}", module, name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Writes `text` to `path`, making the directories it's in
+pub fn write(path: &Path, text: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, text).unwrap();
+}
@@ -0,0 +1,495 @@
+use crate::{
+    camera::{Camera, Image},
+    job::RenderJob,
+};
+use std::{
+    fmt::Write as _,
+    fs, io,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Set by Ctrl-C once [`cancel_on_interrupt`] is installed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C ask a running sequence to stop after the frame in flight instead of killing it.
+/// Returns the flag to hand to [`render_sequence`]. A second Ctrl-C exits right away.
+pub fn cancel_on_interrupt() -> &'static AtomicBool {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_interrupt(_signal: libc::c_int) {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                // Only async-signal-safe calls are allowed in here
+                unsafe { libc::_exit(130) };
+            }
+        }
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+    }
+    &INTERRUPTED
+}
+
+/// Settings for rendering a range of frames of a job one after another
+#[derive(Debug, Clone)]
+pub struct SequenceOptions {
+    pub frames: Range<u64>,
+    /// Stops at the first frame that fails instead of carrying on with the rest
+    pub fail_fast: bool,
+    /// Skips frames whose image is already on disk and was rendered from the same settings
+    pub resume: bool,
+    /// Where the JSON report of every frame is written
+    pub report_path: String,
+    /// Where the list of finished and unfinished frames is written, for picking the job back up
+    pub manifest_path: String,
+}
+
+impl SequenceOptions {
+    /// Puts the report and manifest next to the job file at `job_path`
+    pub fn new(job_path: &str, frames: Range<u64>) -> Self {
+        SequenceOptions {
+            frames,
+            fail_fast: false,
+            resume: false,
+            report_path: format!("{}.report.json", job_path),
+            manifest_path: format!("{}.manifest", job_path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameStatus {
+    /// Rendered and written, with the samples per pixel it got
+    Rendered {
+        samples_per_pixel: usize,
+    },
+    /// Left alone since a valid image of it was already on disk
+    Skipped,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameRecord {
+    pub frame: u64,
+    pub output_path: String,
+    pub status: FrameStatus,
+    pub duration: Duration,
+}
+
+/// What happened to every frame a sequence got to
+#[derive(Debug, Clone, Default)]
+pub struct SequenceReport {
+    pub frames: Vec<FrameRecord>,
+    /// Frames that were never attempted, because of a cancellation or `fail_fast`
+    pub pending: Vec<u64>,
+    pub cancelled: bool,
+}
+
+impl SequenceReport {
+    pub fn failed(&self) -> impl Iterator<Item = &FrameRecord> {
+        self.frames
+            .iter()
+            .filter(|record| matches!(record.status, FrameStatus::Failed(_)))
+    }
+
+    /// Serializes the report as JSON, one object per frame
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n  \"frames\": [\n");
+        for (i, record) in self.frames.iter().enumerate() {
+            let (status, error, samples_per_pixel) = match &record.status {
+                FrameStatus::Rendered { samples_per_pixel } => {
+                    ("rendered", "null".to_string(), *samples_per_pixel)
+                }
+                FrameStatus::Skipped => ("skipped", "null".to_string(), 0),
+                FrameStatus::Failed(message) => ("failed", json_string(message), 0),
+            };
+            let _ = write!(
+                json,
+                "    {{\"frame\": {}, \"output\": {}, \"status\": \"{}\", \"error\": {}, \
+                 \"duration_seconds\": {:.3}, \"samples_per_pixel\": {}}}",
+                record.frame,
+                json_string(&record.output_path),
+                status,
+                error,
+                record.duration.as_secs_f64(),
+                samples_per_pixel
+            );
+            json.push_str(if i + 1 < self.frames.len() {
+                ",\n"
+            } else {
+                "\n"
+            });
+        }
+        let pending: Vec<String> = self.pending.iter().map(u64::to_string).collect();
+        let _ = write!(
+            json,
+            "  ],\n  \"pending\": [{}],\n  \"cancelled\": {}\n}}\n",
+            pending.join(", "),
+            self.cancelled
+        );
+        json
+    }
+
+    /// Serializes which frames are done and which still need rendering, as `key value...` lines
+    pub fn to_manifest_string(&self, job: &RenderJob) -> String {
+        let frames = |records: &mut dyn Iterator<Item = u64>| -> String {
+            records.map(|frame| format!(" {}", frame)).collect()
+        };
+        let done = frames(&mut self.frames.iter().filter_map(|record| {
+            (!matches!(record.status, FrameStatus::Failed(_))).then_some(record.frame)
+        }));
+        let failed = frames(&mut self.failed().map(|record| record.frame));
+        let pending = frames(&mut self.pending.iter().copied());
+        format!(
+            "# rt sequence manifest\nrender_job {:016x}\ndone{}\nfailed{}\npending{}\ncancelled {}\n",
+            job.fingerprint(),
+            done,
+            failed,
+            pending,
+            self.cancelled
+        )
+    }
+
+    fn save(&self, job: &RenderJob, options: &SequenceOptions) -> io::Result<()> {
+        fs::write(&options.report_path, self.to_json())?;
+        fs::write(&options.manifest_path, self.to_manifest_string(job))
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Returns the output path of `frame`, with `{frame}` in `path` replaced by the zero-padded frame
+/// number, or the number added before the extension if there's no `{frame}`
+pub fn frame_path(path: &str, frame: u64) -> String {
+    let number = format!("{:04}", frame);
+    if path.contains("{frame}") {
+        return path.replace("{frame}", &number);
+    }
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => {
+            format!("{}_{}.{}", stem, number, extension)
+        }
+        _ => format!("{}_{}", path, number),
+    }
+}
+
+/// Returns the metadata in the header of the PPM `bytes`, or `None` if its pixel data is cut
+/// short
+fn complete_ppm_metadata(bytes: &[u8]) -> Option<Vec<String>> {
+    let mut metadata = Vec::new();
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    // The magic number, the size and the maximum value, with comments in between
+    while lines.len() < 3 {
+        let end = offset + bytes[offset..].iter().position(|&b| b == b'\n')?;
+        let line = std::str::from_utf8(&bytes[offset..end]).ok()?;
+        match line.strip_prefix("# ") {
+            Some(comment) => metadata.push(comment.to_string()),
+            None => lines.push(line.to_string()),
+        }
+        offset = end + 1;
+    }
+    let (width, height) = lines[1].split_once(' ')?;
+    let pixel_bytes = width.parse::<usize>().ok()? * height.parse::<usize>().ok()? * 3;
+    (lines[0] == "P6" && bytes.len() - offset == pixel_bytes).then_some(metadata)
+}
+
+/// Returns the `Comment` text of the PNG `bytes`, which is where [`Image::encode_png`] keeps
+/// the metadata, or `None` if its pixel data doesn't decode
+fn complete_png_metadata(bytes: &[u8]) -> Option<Vec<String>> {
+    let mut reader = png::Decoder::new(io::Cursor::new(bytes)).read_info().ok()?;
+    let mut pixels = vec![0; reader.output_buffer_size()?];
+    reader.next_frame(&mut pixels).ok()?;
+    let comments = reader.info().utf8_text.iter();
+    comments
+        .filter(|chunk| chunk.keyword == "Comment")
+        .map(|chunk| chunk.get_text().ok())
+        .collect()
+}
+
+/// Returns the metadata of the PPM or PNG at `path`, or `None` if it can't be read or is cut
+/// short
+fn complete_metadata(path: &Path) -> Option<Vec<String>> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        complete_png_metadata(&bytes)
+    } else {
+        complete_ppm_metadata(&bytes)
+    }
+}
+
+/// Returns whether the image at `path` is a complete render of exactly `job`
+fn is_rendered(path: &Path, job: &RenderJob) -> bool {
+    let expected = format!("render job: {:016x}", job.fingerprint());
+    complete_metadata(path).is_some_and(|metadata| metadata.contains(&expected))
+}
+
+/// Renders one frame with `render_frame`, turning panics and broken images into errors
+fn render_frame_safely(
+    frame_job: &RenderJob,
+    render_frame: &mut impl FnMut(&RenderJob) -> Result<Image, String>,
+) -> Result<(), String> {
+    let mut image =
+        panic::catch_unwind(AssertUnwindSafe(|| render_frame(frame_job))).map_err(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            format!("panicked: {}", message)
+        })??;
+    // Resuming relies on this, so it can't be left to `render_frame`
+    let job_line = format!("render job: {:016x}", frame_job.fingerprint());
+    if !image.metadata.contains(&job_line) {
+        image.metadata.push(job_line);
+    }
+    let non_finite = image
+        .pixels
+        .iter()
+        .filter(|color| !color.iter().all(|c| c.is_finite()))
+        .count();
+    if non_finite > 0 {
+        return Err(format!("{} pixels are NaN or infinite", non_finite));
+    }
+    Camera::save_image(image, Path::new(&frame_job.output_path)).map_err(|err| err.to_string())
+}
+
+/// Renders every frame in `options.frames` with `render_frame`, which is given the job for each
+/// frame and returns its image. A frame that fails, panics or comes out with NaNs is recorded
+/// and the rest are still rendered unless `options.fail_fast` is set. Once `cancel` is set, the
+/// frame in flight is finished and the rest are left pending. The report and manifest are
+/// rewritten after every frame, so even a crashed run leaves a record of what got done.
+pub fn render_sequence(
+    job: &RenderJob,
+    options: &SequenceOptions,
+    cancel: &AtomicBool,
+    mut render_frame: impl FnMut(&RenderJob) -> Result<Image, String>,
+) -> io::Result<SequenceReport> {
+    let mut report = SequenceReport {
+        pending: options.frames.clone().collect(),
+        ..SequenceReport::default()
+    };
+    for frame in options.frames.clone() {
+        if cancel.load(Ordering::SeqCst) {
+            report.cancelled = true;
+            break;
+        }
+        let mut frame_job = job.clone();
+        frame_job.post_process.frame = frame;
+        frame_job.output_path = frame_path(&job.output_path, frame);
+
+        let frame_start = Instant::now();
+        let status = if options.resume && is_rendered(Path::new(&frame_job.output_path), &frame_job)
+        {
+            FrameStatus::Skipped
+        } else {
+            match render_frame_safely(&frame_job, &mut render_frame) {
+                Ok(()) => FrameStatus::Rendered {
+                    samples_per_pixel: frame_job.samples_per_pixel,
+                },
+                Err(message) => FrameStatus::Failed(message),
+            }
+        };
+        let failed = matches!(status, FrameStatus::Failed(_));
+        match &status {
+            FrameStatus::Rendered { .. } => println!("Rendered {}", frame_job.output_path),
+            FrameStatus::Skipped => println!("Skipped {}", frame_job.output_path),
+            FrameStatus::Failed(message) => println!("Frame {} failed: {}", frame, message),
+        }
+        report.pending.retain(|&pending| pending != frame);
+        report.frames.push(FrameRecord {
+            frame,
+            output_path: frame_job.output_path,
+            status,
+            duration: frame_start.elapsed(),
+        });
+        report.save(job, options)?;
+        if failed && options.fail_fast {
+            break;
+        }
+    }
+    report.cancelled |= cancel.load(Ordering::SeqCst) && !report.pending.is_empty();
+    report.save(job, options)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch;
+    use crate::{hittable::World, job::HandoffSettings, vec3::Vec3};
+    use std::cell::RefCell;
+
+    /// A job writing its frames into `directory`, with the report and manifest next to them
+    fn job_in(directory: &Path) -> (RenderJob, SequenceOptions) {
+        let camera = Camera::builder().with_resolution(4, 3).build().unwrap();
+        let settings = HandoffSettings {
+            samples_per_pixel: 2,
+            resolution_scale: 1.0,
+            ..HandoffSettings::default()
+        };
+        let mut job = RenderJob::from_camera(&camera, &World::build(Vec::new()), &settings);
+        job.output_path = directory.join("frame.ppm").display().to_string();
+        let options =
+            SequenceOptions::new(&directory.join("turntable").display().to_string(), 0..5);
+        (job, options)
+    }
+
+    /// A flat gray frame, standing in for rendering one
+    fn frame_image(job: &RenderJob) -> Image {
+        Image {
+            pixels: vec![Vec3::repeat(0.5); job.width * job.height],
+            width: job.width,
+            height: job.height,
+            gamma: 2.2,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+
+    #[test]
+    fn a_failing_frame_is_reported_and_resume_renders_only_it() {
+        let directory = scratch("resume");
+        let (job, options) = job_in(&directory);
+        let never = AtomicBool::new(false);
+
+        let report = render_sequence(&job, &options, &never, |frame_job| {
+            match frame_job.post_process.frame {
+                2 => Err("out of disk".to_string()),
+                3 => panic!("NaN storm"),
+                _ => Ok(frame_image(frame_job)),
+            }
+        })
+        .unwrap();
+        let failed: Vec<u64> = report.failed().map(|record| record.frame).collect();
+        assert_eq!(failed, [2, 3]);
+        assert!(report.pending.is_empty() && !report.cancelled);
+        for frame in [0, 1, 4] {
+            assert!(Path::new(&frame_path(&job.output_path, frame)).is_file());
+        }
+        let json = fs::read_to_string(&options.report_path).unwrap();
+        assert!(json.contains("\"frame\": 2") && json.contains("\"error\": \"out of disk\""));
+        assert!(json.contains("\"error\": \"panicked: NaN storm\""));
+        let manifest = fs::read_to_string(&options.manifest_path).unwrap();
+        assert!(manifest.contains("\ndone 0 1 4\nfailed 2 3\npending\n"));
+
+        let rendered = RefCell::new(Vec::new());
+        let resume = SequenceOptions {
+            resume: true,
+            ..options.clone()
+        };
+        let report = render_sequence(&job, &resume, &never, |frame_job| {
+            rendered.borrow_mut().push(frame_job.post_process.frame);
+            Ok(frame_image(frame_job))
+        })
+        .unwrap();
+        assert_eq!(*rendered.borrow(), [2, 3]);
+        assert_eq!(report.failed().count(), 0);
+
+        // Frames of different settings don't count as done, even where the files are
+        rendered.borrow_mut().clear();
+        let changed = RenderJob {
+            samples_per_pixel: 3,
+            ..job.clone()
+        };
+        render_sequence(&changed, &resume, &never, |frame_job| {
+            rendered.borrow_mut().push(frame_job.post_process.frame);
+            Ok(frame_image(frame_job))
+        })
+        .unwrap();
+        assert_eq!(*rendered.borrow(), [0, 1, 2, 3, 4]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn resume_skips_png_frames_that_are_finished() {
+        let directory = scratch("png");
+        let (mut job, options) = job_in(&directory);
+        job.output_path = directory.join("frame.png").display().to_string();
+        let never = AtomicBool::new(false);
+        render_sequence(&job, &options, &never, |frame_job| {
+            Ok(frame_image(frame_job))
+        })
+        .unwrap();
+
+        // A frame cut off partway through writing it isn't finished
+        let cut = frame_path(&job.output_path, 3);
+        let bytes = fs::read(&cut).unwrap();
+        fs::write(&cut, &bytes[..bytes.len() / 2]).unwrap();
+        let rendered = RefCell::new(Vec::new());
+        let resume = SequenceOptions {
+            resume: true,
+            ..options
+        };
+        let report = render_sequence(&job, &resume, &never, |frame_job| {
+            rendered.borrow_mut().push(frame_job.post_process.frame);
+            Ok(frame_image(frame_job))
+        })
+        .unwrap();
+        assert_eq!(*rendered.borrow(), [3]);
+        let skipped = report
+            .frames
+            .iter()
+            .filter(|record| record.status == FrameStatus::Skipped)
+            .count();
+        assert_eq!(skipped, 4);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn cancelling_or_failing_fast_leaves_the_rest_pending() {
+        let directory = scratch("cancel");
+        let (job, options) = job_in(&directory);
+
+        let cancel = AtomicBool::new(false);
+        let report = render_sequence(&job, &options, &cancel, |frame_job| {
+            // Ctrl-C during frame 1, which still gets finished
+            if frame_job.post_process.frame == 1 {
+                cancel.store(true, Ordering::SeqCst);
+            }
+            Ok(frame_image(frame_job))
+        })
+        .unwrap();
+        assert_eq!(report.frames.len(), 2);
+        assert_eq!(report.pending, [2, 3, 4]);
+        assert!(report.cancelled);
+
+        let fail_fast = SequenceOptions {
+            fail_fast: true,
+            ..options
+        };
+        let report =
+            render_sequence(
+                &job,
+                &fail_fast,
+                &AtomicBool::new(false),
+                |frame_job| match frame_job.post_process.frame {
+                    1 => Err("bad frame".to_string()),
+                    _ => Ok(frame_image(frame_job)),
+                },
+            )
+            .unwrap();
+        assert_eq!(report.failed().count(), 1);
+        assert_eq!(report.pending, [2, 3, 4]);
+        assert!(!report.cancelled);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,771 @@
+use crate::{
+    camera::{Camera, Float, Image, Integrator},
+    hittable::World,
+    technical,
+    vec3::Vec3,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::{self, Display},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    iter::Peekable,
+    path::Path,
+    str::Chars,
+    time::Instant,
+};
+
+/// Version of the session format, written in every header so older recordings are refused
+/// rather than replayed wrongly
+pub const SESSION_VERSION: u32 = 1;
+
+/// What the preview was showing when a recording started, the first line of a session. Replays
+/// start from this rather than from whatever camera the scene has now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionStart {
+    /// The `--scene` the preview was given, which a replay loads unless given another
+    pub scene: Option<String>,
+    pub width: usize,
+    pub height: usize,
+    pub center: Vec3,
+    pub lookat: Vec3,
+    pub up: Vec3,
+    pub vertical_fov: Float,
+    pub focus_distance: Float,
+    pub exposure: Float,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub integrator: String,
+    pub fidelity: String,
+    /// Seed headless replays render the final view with, from `--seed`
+    pub seed: Option<u64>,
+}
+
+impl SessionStart {
+    pub fn new(camera: &Camera, scene: Option<&str>, seed: Option<u64>) -> Self {
+        SessionStart {
+            scene: scene.map(str::to_string),
+            width: camera.image_width,
+            height: camera.image_height,
+            center: camera.center,
+            lookat: camera.lookat,
+            up: camera.up,
+            vertical_fov: camera.vertical_fov,
+            focus_distance: camera.focus_distance,
+            exposure: camera.post_process.exposure,
+            samples_per_pixel: camera.samples_per_pixel(),
+            max_depth: camera.max_depth(),
+            integrator: camera.integrator.name().to_string(),
+            fidelity: camera.fidelity.name().to_string(),
+            seed,
+        }
+    }
+
+    /// `camera`, which should be the scene's, as it was when the recording started
+    pub fn camera(&self, camera: &Camera) -> Camera {
+        let mut camera = camera
+            .with_resolution(self.width, self.height)
+            .with_sampling(self.samples_per_pixel, self.max_depth);
+        camera.up = self.up;
+        camera.vertical_fov = self.vertical_fov;
+        camera.post_process.exposure = self.exposure;
+        match Integrator::from_name(&self.integrator) {
+            Some(Integrator::Technical) => technical::preset(&mut camera),
+            Some(integrator) => camera.integrator = integrator,
+            None => {}
+        }
+        camera.with_view(self.center, self.lookat, self.focus_distance)
+    }
+
+    /// The session's first line, in RON like the rest
+    pub fn to_ron(&self) -> String {
+        let mut line = Line::new("Session");
+        line.field("version", SESSION_VERSION);
+        line.field("scene", Optional(self.scene.as_deref().map(Quoted)));
+        line.field("width", self.width);
+        line.field("height", self.height);
+        line.field("center", Vector(&self.center));
+        line.field("lookat", Vector(&self.lookat));
+        line.field("up", Vector(&self.up));
+        line.field("vertical_fov", Number(self.vertical_fov));
+        line.field("focus_distance", Number(self.focus_distance));
+        line.field("exposure", Number(self.exposure));
+        line.field("samples_per_pixel", self.samples_per_pixel);
+        line.field("max_depth", self.max_depth);
+        line.field("integrator", Quoted(&self.integrator));
+        line.field("fidelity", Quoted(&self.fidelity));
+        line.field("seed", Optional(self.seed));
+        line.finish()
+    }
+
+    /// The inverse of [`SessionStart::to_ron`]'s fields, or what's missing or unreadable
+    fn from_fields(fields: &HashMap<String, Value>) -> Result<Self, String> {
+        let fields = Fields(fields);
+        let version = fields.integer("version")? as u32;
+        if version != SESSION_VERSION {
+            return Err(format!(
+                "it was recorded in version {} of the session format, and this reads version {}",
+                version, SESSION_VERSION
+            ));
+        }
+        Ok(SessionStart {
+            scene: fields.optional("scene", Value::as_str)?.map(str::to_string),
+            width: fields.integer("width")? as usize,
+            height: fields.integer("height")? as usize,
+            center: fields.vector("center")?,
+            lookat: fields.vector("lookat")?,
+            up: fields.vector("up")?,
+            vertical_fov: fields.number("vertical_fov")?,
+            focus_distance: fields.number("focus_distance")?,
+            exposure: fields.number("exposure")?,
+            samples_per_pixel: fields.integer("samples_per_pixel")? as usize,
+            max_depth: fields.integer("max_depth")? as usize,
+            integrator: fields.string("integrator")?.to_string(),
+            fidelity: fields.string("fidelity")?.to_string(),
+            seed: fields.optional("seed", Value::as_integer)?,
+        })
+    }
+}
+
+/// Something the preview did that changes what it renders, recorded as it was sent to the
+/// render thread
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    /// The camera moved or its exposure changed, with where it ended up
+    View {
+        center: Vec3,
+        lookat: Vec3,
+        focus_distance: Float,
+        exposure: Float,
+    },
+    /// The window was resized, so the preview renders at this size from then on
+    Resize { width: usize, height: usize },
+    /// T turned draft frames while the camera moves on or off
+    Draft(bool),
+    /// The scene finished building, with this [`crate::snapshot::SceneSnapshot::fingerprint`]
+    SceneLoaded(u64),
+}
+
+impl SessionEvent {
+    /// Where `camera` is looking, as a [`SessionEvent::View`]
+    pub fn view(camera: &Camera) -> Self {
+        SessionEvent::View {
+            center: camera.center,
+            lookat: camera.lookat,
+            focus_distance: camera.focus_distance,
+            exposure: camera.post_process.exposure,
+        }
+    }
+
+    /// `camera` with the event applied, or `None` for events that don't change the camera
+    pub fn apply(&self, camera: &Camera) -> Option<Camera> {
+        match *self {
+            SessionEvent::View {
+                center,
+                lookat,
+                focus_distance,
+                exposure,
+            } => {
+                let mut camera = camera.with_view(center, lookat, focus_distance);
+                camera.post_process.exposure = exposure;
+                Some(camera)
+            }
+            SessionEvent::Resize { width, height } => Some(camera.with_resolution(width, height)),
+            SessionEvent::Draft(_) | SessionEvent::SceneLoaded(_) => None,
+        }
+    }
+
+    fn to_ron(&self, seconds: f64) -> String {
+        let mut line;
+        match self {
+            SessionEvent::View {
+                center,
+                lookat,
+                focus_distance,
+                exposure,
+            } => {
+                line = Line::new("View");
+                line.field("t", Number(seconds));
+                line.field("center", Vector(center));
+                line.field("lookat", Vector(lookat));
+                line.field("focus_distance", Number(*focus_distance));
+                line.field("exposure", Number(*exposure));
+            }
+            SessionEvent::Resize { width, height } => {
+                line = Line::new("Resize");
+                line.field("t", Number(seconds));
+                line.field("width", width);
+                line.field("height", height);
+            }
+            SessionEvent::Draft(enabled) => {
+                line = Line::new("Draft");
+                line.field("t", Number(seconds));
+                line.field("enabled", enabled);
+            }
+            SessionEvent::SceneLoaded(fingerprint) => {
+                line = Line::new("SceneLoaded");
+                line.field("t", Number(seconds));
+                line.field("fingerprint", format!("0x{:016x}", fingerprint));
+            }
+        }
+        line.finish()
+    }
+
+    /// The inverse of [`SessionEvent::to_ron`], with the time it was recorded at
+    fn from_fields(kind: &str, fields: &HashMap<String, Value>) -> Result<SessionEntry, String> {
+        let fields = Fields(fields);
+        let event = match kind {
+            "View" => SessionEvent::View {
+                center: fields.vector("center")?,
+                lookat: fields.vector("lookat")?,
+                focus_distance: fields.number("focus_distance")?,
+                exposure: fields.number("exposure")?,
+            },
+            "Resize" => SessionEvent::Resize {
+                width: fields.integer("width")? as usize,
+                height: fields.integer("height")? as usize,
+            },
+            "Draft" => SessionEvent::Draft(fields.boolean("enabled")?),
+            "SceneLoaded" => SessionEvent::SceneLoaded(fields.integer("fingerprint")?),
+            _ => return Err(format!("'{}' isn't something sessions record", kind)),
+        };
+        Ok(SessionEntry {
+            seconds: fields.number("t")?,
+            event,
+        })
+    }
+}
+
+/// An event and when it happened, in seconds since the recording started
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionEntry {
+    pub seconds: f64,
+    pub event: SessionEvent,
+}
+
+/// Writes what the preview does to a session file as it happens, one RON struct per line, so a
+/// preview that crashes still leaves everything up to the crash
+pub struct SessionRecorder {
+    file: File,
+    start: Instant,
+}
+
+impl SessionRecorder {
+    pub fn create(path: impl AsRef<Path>, start: &SessionStart) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", start.to_ron())?;
+        Ok(SessionRecorder {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    /// Recording is never worth stopping the preview over, so errors are only printed
+    pub fn record(&mut self, event: &SessionEvent) {
+        let line = event.to_ron(self.start.elapsed().as_secs_f64());
+        if let Err(err) = writeln!(self.file, "{}", line) {
+            println!("Warning: couldn't write to the session recording, {}", err);
+        }
+    }
+}
+
+/// A recorded session, read back for replaying
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub start: SessionStart,
+    /// In the order they were recorded, which is also the order of their times
+    pub entries: Vec<SessionEntry>,
+}
+
+impl Session {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Session::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut start = None;
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let error = |err: String| format!("line {} of the session: {}", index + 1, err);
+            let (kind, fields) = parse_line(line).map_err(error)?;
+            match (kind.as_str(), &start) {
+                ("Session", None) => {
+                    start = Some(SessionStart::from_fields(&fields).map_err(error)?)
+                }
+                ("Session", Some(_)) => return Err(error("a second session header".into())),
+                (_, None) => return Err(error("comes before the session header".into())),
+                (kind, Some(_)) => {
+                    entries.push(SessionEvent::from_fields(kind, &fields).map_err(error)?)
+                }
+            }
+        }
+        Ok(Session {
+            start: start.ok_or("the session is empty")?,
+            entries,
+        })
+    }
+
+    /// Fingerprint of the scene the session was recorded on, if it finished loading before the
+    /// recording stopped
+    pub fn scene_fingerprint(&self) -> Option<u64> {
+        self.entries.iter().find_map(|entry| match entry.event {
+            SessionEvent::SceneLoaded(fingerprint) => Some(fingerprint),
+            _ => None,
+        })
+    }
+
+    /// Refuses to replay against any scene but the one the session was recorded on, since the
+    /// same moves would show something else
+    pub fn check_scene(&self, world: &World) -> Result<(), String> {
+        let recorded = self.scene_fingerprint().ok_or(
+            "the preview was closed before its scene loaded, so there's no telling which scene \
+             the session is of",
+        )?;
+        let fingerprint = world.snapshot().fingerprint();
+        if fingerprint != recorded {
+            return Err(format!(
+                "the session was recorded on scene {:016x}, but this scene is {:016x}",
+                recorded, fingerprint
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hands out a session's events once the clock reaches their time. The preview drives it with
+/// the wall clock, scaled by the replay speed, and headless replays with a virtual clock that
+/// jumps straight to each event's time so they take no longer than applying them does.
+pub struct SessionPlayer {
+    entries: Vec<SessionEntry>,
+    next: usize,
+}
+
+impl SessionPlayer {
+    pub fn new(session: &Session) -> Self {
+        SessionPlayer {
+            entries: session.entries.clone(),
+            next: 0,
+        }
+    }
+
+    /// When the next event happens, or `None` once they've all been handed out
+    pub fn next_time(&self) -> Option<f64> {
+        self.entries.get(self.next).map(|entry| entry.seconds)
+    }
+
+    /// Every event not handed out yet that happened by `seconds` into the session
+    pub fn due(&mut self, seconds: f64) -> &[SessionEntry] {
+        let first = self.next;
+        while self
+            .entries
+            .get(self.next)
+            .is_some_and(|entry| entry.seconds <= seconds)
+        {
+            self.next += 1;
+        }
+        &self.entries[first..self.next]
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next == self.entries.len()
+    }
+}
+
+/// Replays `session` from `camera`, the scene's, on a virtual clock, returning the camera as it
+/// was when the recording stopped. It's seeded with the recording's seed, or 0, so renders of it
+/// can be compared between runs.
+pub fn replay_headless(session: &Session, camera: &Camera) -> Camera {
+    let mut camera = session.start.camera(camera);
+    camera.seed = session.start.seed.or(Some(0));
+    let mut player = SessionPlayer::new(session);
+    while let Some(seconds) = player.next_time() {
+        for entry in player.due(seconds) {
+            if let Some(changed) = entry.event.apply(&camera) {
+                camera = changed;
+            }
+        }
+    }
+    camera
+}
+
+/// A hash of every pixel's exact color, for checking that a replay renders the same image bit
+/// for bit as before. Only stable between runs with a seed.
+pub fn image_fingerprint(image: &Image) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (image.width, image.height).hash(&mut hasher);
+    for color in &image.pixels {
+        for channel in color.iter() {
+            channel.to_bits().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// One line of a session being written, a RON struct named after what it records
+struct Line {
+    text: String,
+    first: bool,
+}
+
+impl Line {
+    fn new(name: &str) -> Self {
+        Line {
+            text: format!("{}(", name),
+            first: true,
+        }
+    }
+
+    fn field(&mut self, key: &str, value: impl Display) {
+        if !self.first {
+            self.text.push_str(", ");
+        }
+        self.first = false;
+        self.text.push_str(&format!("{}: {}", key, value));
+    }
+
+    fn finish(mut self) -> String {
+        self.text.push(')');
+        self.text
+    }
+}
+
+/// A float written so it reads back as exactly the same float, and always with a decimal point
+/// or exponent so RON reads it as one
+struct Number(Float);
+
+impl Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// A vector as a RON tuple of three floats
+struct Vector<'a>(&'a Vec3);
+
+impl Display for Vector<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = self.0;
+        write!(f, "({}, {}, {})", Number(v.x), Number(v.y), Number(v.z))
+    }
+}
+
+struct Quoted<'a>(&'a str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let escaped = self.0.replace('\\', "\\\\").replace('"', "\\\"");
+        write!(f, "\"{}\"", escaped)
+    }
+}
+
+/// `Some(value)` or `None`, as RON writes options
+struct Optional<T>(Option<T>);
+
+impl<T: Display> Display for Optional<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => write!(f, "Some({})", value),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+/// A value in a session line, as much of RON as sessions write
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    /// As written, so large integers like seeds don't lose their bottom bits as floats
+    Number(String),
+    String(String),
+    Bool(bool),
+    Tuple(Vec<Value>),
+    Optional(Option<Box<Value>>),
+}
+
+impl Value {
+    fn as_float(&self) -> Option<Float> {
+        match self {
+            Value::Number(number) => number.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_integer(&self) -> Option<u64> {
+        match self {
+            Value::Number(number) => match number.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => number.parse().ok(),
+            },
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn as_vector(&self) -> Option<Vec3> {
+        match self {
+            Value::Tuple(values) => match &values[..] {
+                [x, y, z] => Some(Vec3::new(x.as_float()?, y.as_float()?, z.as_float()?)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The name and fields of a line like `View(t: 1.5, exposure: 0.0)`
+fn parse_line(line: &str) -> Result<(String, HashMap<String, Value>), String> {
+    let mut parser = Parser {
+        chars: line.chars().peekable(),
+    };
+    let name = parser.identifier();
+    if name.is_empty() {
+        return Err("doesn't start with what it records".into());
+    }
+    parser.expect('(')?;
+    let mut fields = HashMap::new();
+    while !parser.eat(')') {
+        let key = parser.identifier();
+        if key.is_empty() {
+            return Err(format!("'{}' has a field without a name", name));
+        }
+        parser.expect(':')?;
+        fields.insert(key, parser.value()?);
+        if !parser.eat(',') {
+            parser.expect(')')?;
+            break;
+        }
+    }
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        Some(c) => Err(format!("'{}' is followed by '{}'", name, c)),
+        None => Ok((name, fields)),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if_eq(&c).is_some()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.eat(c) {
+            true => Ok(()),
+            false => Err(format!("expected '{}'", c)),
+        }
+    }
+
+    /// A name, or a number, which RON spells with the same characters
+    fn identifier(&mut self) -> String {
+        self.skip_whitespace();
+        let mut word = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '+'))
+        {
+            word.push(c);
+        }
+        word
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.eat('"') {
+            let mut string = String::new();
+            loop {
+                match self.chars.next() {
+                    Some('"') => return Ok(Value::String(string)),
+                    Some('\\') => string.extend(self.chars.next()),
+                    Some(c) => string.push(c),
+                    None => return Err("a string isn't closed".into()),
+                }
+            }
+        }
+        if self.eat('(') {
+            let mut values = Vec::new();
+            while !self.eat(')') {
+                values.push(self.value()?);
+                if !self.eat(',') {
+                    self.expect(')')?;
+                    break;
+                }
+            }
+            return Ok(Value::Tuple(values));
+        }
+        let word = self.identifier();
+        match word.as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            "None" => Ok(Value::Optional(None)),
+            "Some" => {
+                self.expect('(')?;
+                let value = self.value()?;
+                self.expect(')')?;
+                Ok(Value::Optional(Some(Box::new(value))))
+            }
+            "" => Err("expected a value".into()),
+            _ if word.starts_with(|c: char| c.is_ascii_digit() || "-+.".contains(c))
+                || word == "inf"
+                || word == "NaN" =>
+            {
+                Ok(Value::Number(word))
+            }
+            _ => Err(format!("'{}' isn't a value", word)),
+        }
+    }
+}
+
+/// A session line's fields, with errors saying which one is missing
+struct Fields<'a>(&'a HashMap<String, Value>);
+
+impl Fields<'_> {
+    fn get<'v, T>(
+        &'v self,
+        key: &str,
+        read: impl Fn(&'v Value) -> Option<T>,
+        what: &str,
+    ) -> Result<T, String> {
+        self.0
+            .get(key)
+            .and_then(read)
+            .ok_or_else(|| format!("'{}' is missing or isn't {}", key, what))
+    }
+
+    fn number(&self, key: &str) -> Result<Float, String> {
+        self.get(key, Value::as_float, "a number")
+    }
+
+    fn integer(&self, key: &str) -> Result<u64, String> {
+        self.get(key, Value::as_integer, "a whole number")
+    }
+
+    fn string(&self, key: &str) -> Result<&str, String> {
+        self.get(key, Value::as_str, "a string")
+    }
+
+    fn boolean(&self, key: &str) -> Result<bool, String> {
+        let read = |value: &Value| match value {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        };
+        self.get(key, read, "true or false")
+    }
+
+    fn vector(&self, key: &str) -> Result<Vec3, String> {
+        self.get(key, Value::as_vector, "three numbers")
+    }
+
+    /// `Some` of the value read by `read`, `None` if it's `None` or missing altogether
+    fn optional<'v, T>(
+        &'v self,
+        key: &str,
+        read: impl Fn(&'v Value) -> Option<T>,
+    ) -> Result<Option<T>, String> {
+        match self.0.get(key) {
+            None | Some(Value::Optional(None)) => Ok(None),
+            Some(Value::Optional(Some(value))) => read(value)
+                .map(Some)
+                .ok_or_else(|| format!("'{}' can't be read", key)),
+            Some(_) => Err(format!("'{}' isn't Some or None", key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_read_back_as_written() {
+        let start = SessionStart {
+            scene: Some("scenes/a \"quoted\" name.scene".into()),
+            width: 320,
+            height: 180,
+            center: Vec3::new(0.1, -2.5, 1e-9),
+            lookat: Vec3::zeros(),
+            up: Vec3::z(),
+            vertical_fov: 40.0,
+            focus_distance: 3.3,
+            exposure: -0.5,
+            samples_per_pixel: 16,
+            max_depth: 8,
+            integrator: "path".into(),
+            fidelity: "production".into(),
+            // Past what a float holds exactly
+            seed: Some(u64::MAX - 1),
+        };
+        let events = [
+            SessionEvent::SceneLoaded(0xfedc_ba98_7654_3210),
+            SessionEvent::View {
+                center: Vec3::new(1.0 / 3.0, 2.0, -7.25),
+                lookat: Vec3::new(0.0, 1.0, 0.0),
+                focus_distance: 4.5,
+                exposure: 0.25,
+            },
+            SessionEvent::Resize {
+                width: 640,
+                height: 360,
+            },
+            SessionEvent::Draft(true),
+        ];
+        let mut text = start.to_ron() + "\n";
+        for (index, event) in events.iter().enumerate() {
+            text += &event.to_ron(index as f64 * 0.1);
+            text.push('\n');
+        }
+        let session = Session::parse(&text).unwrap();
+        assert_eq!(session.start, start);
+        let read: Vec<_> = session.entries.iter().map(|entry| &entry.event).collect();
+        assert_eq!(read, events.iter().collect::<Vec<_>>());
+        assert_eq!(session.entries[3].seconds, 0.30000000000000004);
+    }
+
+    #[test]
+    fn unseeded_sessions_without_a_scene_read_back() {
+        let mut start = SessionStart::new(&crate::scenes::cornell_box_camera(), None, None);
+        start.scene = None;
+        let line = start.to_ron();
+        assert!(
+            line.contains("scene: None") && line.contains("seed: None"),
+            "{}",
+            line
+        );
+        assert_eq!(Session::parse(&line).unwrap().start, start);
+    }
+
+    #[test]
+    fn malformed_lines_say_where_they_are() {
+        let start = SessionStart::new(&crate::scenes::cornell_box_camera(), None, Some(1));
+        let text = format!(
+            "{}\n// a comment\nResize(t: 1.0, width: 10)\n",
+            start.to_ron()
+        );
+        let err = Session::parse(&text).unwrap_err();
+        assert_eq!(
+            err,
+            "line 3 of the session: 'height' is missing or isn't a whole number"
+        );
+        let err = Session::parse("View(t: 0.0)").unwrap_err();
+        assert!(err.contains("before the session header"), "{}", err);
+        let err = Session::parse("Session(version: 99)").unwrap_err();
+        assert!(err.contains("version 99"), "{}", err);
+    }
+}
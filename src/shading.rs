@@ -0,0 +1,286 @@
+use crate::{
+    camera::Float,
+    intersection::Intersection,
+    material::Scatter,
+    object::ObjectId,
+    rng::SampleRng,
+    texture::{Footprint, Texture, TextureEnum},
+    validation::{self, Stage},
+    vec3::{Point3, Ray, Vec2, Vec3},
+};
+use rand::RngCore;
+
+/// Where a path is when it reaches a surface. Paths that don't track it, like the albedo pass,
+/// use [`PathState::default`], as if the camera ray had hit the surface at full strength.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathState {
+    /// Bounces taken before this one, with 0 for the camera ray's own hit
+    pub depth: usize,
+    /// What the light found from here on gets multiplied by on its way back to the camera
+    pub throughput: Vec3,
+    /// How far the path has gone from the camera to get here, which is 0 for paths that don't
+    /// track it
+    pub travelled: Float,
+}
+
+impl Default for PathState {
+    fn default() -> Self {
+        PathState {
+            depth: 0,
+            throughput: Vec3::repeat(1.0),
+            travelled: 0.0,
+        }
+    }
+}
+
+impl PathState {
+    pub fn new(depth: usize, throughput: Vec3) -> Self {
+        PathState {
+            depth,
+            throughput,
+            travelled: 0.0,
+        }
+    }
+}
+
+/// Everything a material gets to decide how a ray scatters off a surface: the ray, the hit, the
+/// random numbers to draw from and the state of the path. Materials ask for what they use through
+/// the accessors, so more can be added here without changing [`Scatter`](crate::material::Scatter).
+///
+/// It lives for a single call to `scatter`, and holds the sampler by `&mut`, so it stays on the
+/// thread that shades the hit.
+pub struct ShadingContext<'a, 'm> {
+    ray_in: &'a Ray,
+    hit: &'a Intersection<'m>,
+    sampler: &'a mut dyn RngCore,
+    path: PathState,
+    /// How wide the cone of rays this one stands for is where it hit, 0 for a single ray
+    footprint: Float,
+}
+
+impl<'a, 'm> ShadingContext<'a, 'm> {
+    /// Shades `hit`, found by `ray_in`, with random numbers from `sampler`
+    pub fn new(ray_in: &'a Ray, hit: &'a Intersection<'m>, sampler: &'a mut dyn RngCore) -> Self {
+        ShadingContext {
+            ray_in,
+            hit,
+            sampler,
+            path: PathState::default(),
+            footprint: 0.0,
+        }
+    }
+
+    pub fn with_path(mut self, path: PathState) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Makes textures that can average themselves over a cone of rays `width` across where it
+    /// hit, like a pixel's, see [`Texture::filtered_value`]
+    pub fn with_footprint(mut self, width: Float) -> Self {
+        self.footprint = width;
+        self
+    }
+
+    /// The ray that found the surface, as it was traced, so its direction isn't normalized
+    pub fn ray_in(&self) -> &Ray {
+        self.ray_in
+    }
+
+    /// The direction the ray arrived in, as it was traced
+    pub fn incoming(&self) -> Vec3 {
+        self.ray_in.direction
+    }
+
+    /// The whole hit, for what the accessors don't cover
+    pub fn hit(&self) -> &Intersection<'m> {
+        self.hit
+    }
+
+    pub fn point(&self) -> Point3 {
+        self.hit.point
+    }
+
+    /// Unit shading normal, facing the side the ray came from and already bumped
+    pub fn normal(&self) -> Vec3 {
+        self.hit.normal
+    }
+
+    pub fn is_front_face(&self) -> bool {
+        self.hit.is_front_face
+    }
+
+    pub fn uv(&self) -> Vec2 {
+        self.hit.uv
+    }
+
+    /// How far the hit point moves along the surface per unit of u and of v, zero on shapes that
+    /// don't work them out
+    pub fn tangents(&self) -> (Vec3, Vec3) {
+        (self.hit.dpdu, self.hit.dpdv)
+    }
+
+    pub fn object(&self) -> ObjectId {
+        self.hit.object
+    }
+
+    /// Looks `texture` up where the ray hit, averaged over the footprint if there is one
+    pub fn texture(&self, texture: &TextureEnum) -> Vec3 {
+        let (u, v) = (self.hit.uv.x, self.hit.uv.y);
+        let color = if self.footprint <= 0.0 {
+            texture.value(u, v, self.hit.point)
+        } else {
+            texture.filtered_value(u, v, self.hit.point, &self.footprint())
+        };
+        validation::check(Stage::TextureFetch, &color);
+        color
+    }
+
+    /// The patch of surface the ray's cone covers where it hit
+    pub fn footprint(&self) -> Footprint {
+        Footprint::new(
+            self.footprint,
+            &self.ray_in.direction,
+            &self.hit.normal,
+            self.hit.dpdu,
+            self.hit.dpdv,
+        )
+    }
+
+    /// The random numbers to draw from, which are the sample's own stream when rendering
+    pub fn sampler(&mut self) -> &mut dyn RngCore {
+        self.sampler
+    }
+
+    pub fn path(&self) -> PathState {
+        self.path
+    }
+}
+
+/// Scatters `ray_in` off `hit` with the random numbers in `rng` and a fresh path, for callers
+/// that only want a material's response, like the albedo pass and debugging
+pub fn scatter_once(ray_in: &Ray, hit: &Intersection, rng: &mut SampleRng) -> Option<(Vec3, Ray)> {
+    hit.material
+        .scatter(&mut ShadingContext::new(ray_in, hit, rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{reflect, reflectance, Dielectric, Lambertian, Material, Metal},
+        texture::{CheckerTexture, SolidColor},
+        vec3::Vec3Ext,
+    };
+    use rand::Rng;
+
+    /// A hit facing up on whatever `material` is
+    fn hit_on(material: &Material) -> Intersection<'_> {
+        Intersection::new(
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::z(),
+            2.0,
+            material,
+            true,
+            Vec2::new(0.3, 0.6),
+        )
+    }
+
+    fn slanted() -> Ray {
+        Ray::new(Vec3::new(0.0, 2.0, 2.0).into(), Vec3::new(1.0, 0.0, -2.0))
+    }
+
+    /// The stream scattering sample `sample` draws from, and an identical one to check it against
+    fn streams(sample: usize) -> (SampleRng, SampleRng) {
+        (
+            SampleRng::seeded(5, 1, 2, sample),
+            SampleRng::seeded(5, 1, 2, sample),
+        )
+    }
+
+    #[test]
+    fn lambertian_scatters_around_the_normal_with_the_samples_numbers() {
+        let albedo = Vec3::new(0.2, 0.4, 0.6);
+        let material: Material = Lambertian::new(SolidColor::new(albedo).into()).into();
+        let hit = hit_on(&material);
+        for sample in 0..100 {
+            let (mut rng, mut same) = streams(sample);
+            let (attenuation, scattered) = scatter_once(&slanted(), &hit, &mut rng).unwrap();
+            let mut expected = Vec3::z() + Vec3::random_unit(&mut same);
+            if expected.near_zero() {
+                expected = Vec3::z();
+            }
+            assert_eq!(attenuation, albedo);
+            assert_eq!(scattered.origin.coords, hit.point);
+            assert!((scattered.direction - expected.normalize()).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn metals_reflect_and_fuzz_with_the_samples_numbers() {
+        let color = Vec3::new(0.9, 0.8, 0.7);
+        let mirror: Material = Metal::new_solid(color, None).into();
+        let (mut rng, _) = streams(0);
+        let (attenuation, scattered) =
+            scatter_once(&slanted(), &hit_on(&mirror), &mut rng).unwrap();
+        assert_eq!(attenuation, color);
+        // Rays come out with unit directions
+        assert!((scattered.direction - Vec3::new(1.0, 0.0, 2.0).normalize()).norm() < 1e-12);
+
+        let fuzzy: Material = Metal::new_solid(color, Some(0.2)).into();
+        let hit = hit_on(&fuzzy);
+        for sample in 0..100 {
+            let (mut rng, mut same) = streams(sample);
+            let (_, scattered) = scatter_once(&slanted(), &hit, &mut rng).unwrap();
+            let expected =
+                reflect(slanted().direction, Vec3::z()) + Vec3::random_unit(&mut same) * 0.2;
+            assert!((scattered.direction - expected.normalize()).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn glass_picks_reflection_or_refraction_with_the_samples_numbers() {
+        let glass: Material = Dielectric::new(1.5).into();
+        let hit = hit_on(&glass);
+        let down = Ray::new(Vec3::new(1.0, 2.0, 1.0).into(), -Vec3::z());
+        let mut reflections = 0;
+        for sample in 0..1000 {
+            let (mut rng, mut same) = streams(sample);
+            let (attenuation, scattered) = scatter_once(&down, &hit, &mut rng).unwrap();
+            let reflects = reflectance(1.0, 1.0 / 1.5) > same.gen_range(0.0..=1.0);
+            reflections += usize::from(reflects);
+            let expected = if reflects { Vec3::z() } else { -Vec3::z() };
+            assert!(
+                (scattered.direction - expected).norm() < 1e-12,
+                "{:?}",
+                scattered
+            );
+            assert_eq!(attenuation, Vec3::ONE);
+        }
+        // Glass reflects 4% of the light hitting it head on
+        assert!((20..=60).contains(&reflections), "{}", reflections);
+    }
+
+    #[test]
+    fn contexts_look_textures_up_where_the_ray_hit() {
+        let checker = || -> TextureEnum {
+            let even = SolidColor::new(Vec3::zeros()).into();
+            let odd = SolidColor::new(Vec3::repeat(1.0)).into();
+            CheckerTexture::new_filtered(0.1, even, odd).into()
+        };
+        let material: Material = Lambertian::new(checker()).into();
+        let hit = hit_on(&material);
+        let ray = slanted();
+        let (mut rng, _) = streams(0);
+        let context = ShadingContext::new(&ray, &hit, &mut rng);
+        let checker = checker();
+        assert_eq!(
+            context.texture(&checker),
+            checker.value(0.3, 0.6, hit.point)
+        );
+        assert_eq!(context.path(), PathState::default());
+        assert_eq!(context.incoming(), ray.direction);
+        let path = PathState::new(3, Vec3::repeat(0.5));
+        assert_eq!(context.with_path(path).path(), path);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    hittable::{Hit, World, SUN_ANGLE},
+    object::ObjectId,
+    rng::{self, SampleRng},
+    sky_importance::luminance,
+    vec3::{Point3, Ray, RayExt, Vec3, Vec3Ext},
+};
+use itertools::Itertools;
+use rayon::prelude::*;
+use std::{collections::HashSet, fmt};
+
+/// The sun's real size, see [`SUN_ANGLE`]
+pub const DEFAULT_SUN_ANGLE: Float = SUN_ANGLE;
+
+/// How much of the light on an open patch of ground comes straight from the sun on a clear day.
+/// The rest comes from the sky.
+pub const DEFAULT_SUN_FRACTION: Float = 0.8;
+
+/// What CG shadows fall on in a shadow matte
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShadowCatcher {
+    /// An infinite plane that isn't part of the scene, like the ground in the footage
+    Plane { point: Point3, normal: Vec3 },
+    /// The scene objects with these names
+    Objects(Vec<String>),
+}
+
+/// A pass holding only the shadows CG objects cast onto the catcher, for multiplying over
+/// footage: 1 where nothing is in the way of the light, 0 in full shadow, and in between in
+/// penumbras and on pixels the catcher only partly covers. Material colors don't matter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowMatte {
+    pub catcher: ShadowCatcher,
+    /// Names of the objects that cast shadows, or `None` for everything but the catcher
+    pub casters: Option<Vec<String>>,
+    /// See [`DEFAULT_SUN_ANGLE`]
+    pub sun_angle: Float,
+    /// See [`DEFAULT_SUN_FRACTION`]
+    pub sun_fraction: Float,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShadowMatteError {
+    /// A catcher or caster names an object the scene doesn't have
+    UnknownObject(String),
+    /// The catcher plane's normal has no direction
+    NoPlaneNormal,
+}
+
+impl fmt::Display for ShadowMatteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShadowMatteError::UnknownObject(name) => {
+                write!(f, "the scene has no object called '{}'", name)
+            }
+            ShadowMatteError::NoPlaneNormal => {
+                write!(f, "the shadow catcher plane's normal is zero")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShadowMatteError {}
+
+/// Which objects catch and cast shadows, looked up from the matte's names
+struct Roles {
+    plane: Option<(Point3, Vec3)>,
+    catchers: HashSet<ObjectId>,
+    /// `None` for everything but the catchers
+    casters: Option<HashSet<ObjectId>>,
+}
+
+impl Roles {
+    fn casts(&self, object: ObjectId) -> bool {
+        match &self.casters {
+            Some(casters) => casters.contains(&object),
+            None => !self.catchers.contains(&object),
+        }
+    }
+}
+
+fn lookup(names: &[String]) -> Result<HashSet<ObjectId>, ShadowMatteError> {
+    let mut ids = HashSet::new();
+    for name in names {
+        let named = ObjectId::all_named(name);
+        if named.is_empty() {
+            return Err(ShadowMatteError::UnknownObject(name.clone()));
+        }
+        ids.extend(named);
+    }
+    Ok(ids)
+}
+
+/// The fraction of the light from the sun and sky at `point` on a shadow catcher facing
+/// `normal` that gets there past the rest of the scene, from one sample toward each, weighted by
+/// how much light each gives. 1 where neither lights the catcher at all, so there's nothing to
+/// block. Random numbers come from `rng`. Shades [`crate::material::ShadowCatcher`].
+pub fn caught_light(world: &World, point: &Point3, normal: &Vec3, rng: &mut SampleRng) -> Float {
+    let shadow_range = world.numeric.min_hit_distance..Float::MAX;
+    let (mut total, mut visible) = (0.0, 0.0);
+    let samples = [
+        world.sample_sun(rng),
+        Some(world.sample_sky_importance(rng)),
+    ];
+    for sample in samples.into_iter().flatten() {
+        let cos_theta = sample.direction.dot(normal);
+        if cos_theta <= 0.0 || sample.pdf <= 0.0 {
+            continue;
+        }
+        let weight = luminance(&sample.radiance) * cos_theta / sample.pdf;
+        let origin = world
+            .numeric
+            .offset_ray_origin(point, normal, &sample.direction);
+        let shadow_ray = Ray::new(origin.into(), sample.direction);
+        total += weight;
+        if !world.blocked(&shadow_ray, &shadow_range, |_| true) {
+            visible += weight;
+        }
+    }
+    if total > 0.0 {
+        visible / total
+    } else {
+        1.0
+    }
+}
+
+/// What one camera ray found for the matte
+enum SampleResult {
+    /// The ray didn't reach the catcher, so it isn't shadowed
+    Missed,
+    /// The ray hit the catcher, with 1 if the sun sample got through and 0 if not, the sky
+    /// sample's share of the irradiance, and that share again only if the sky sample got through
+    Caught {
+        sun_visible: Float,
+        sky_weight: Float,
+        sky_visible_weight: Float,
+    },
+}
+
+impl ShadowMatte {
+    /// A matte of shadows on `catcher` from everything else, under the usual sun
+    pub fn new(catcher: ShadowCatcher) -> Self {
+        ShadowMatte {
+            catcher,
+            casters: None,
+            sun_angle: DEFAULT_SUN_ANGLE,
+            sun_fraction: DEFAULT_SUN_FRACTION,
+        }
+    }
+
+    fn roles(&self) -> Result<Roles, ShadowMatteError> {
+        let (plane, catchers) = match &self.catcher {
+            ShadowCatcher::Plane { point, normal } => {
+                let normal = normal
+                    .try_normalize(0.0)
+                    .filter(|normal| normal.iter().all(|c| c.is_finite()))
+                    .ok_or(ShadowMatteError::NoPlaneNormal)?;
+                (Some((*point, normal)), HashSet::new())
+            }
+            ShadowCatcher::Objects(names) => (None, lookup(names)?),
+        };
+        let casters = self.casters.as_deref().map(lookup).transpose()?;
+        Ok(Roles {
+            plane,
+            catchers,
+            casters,
+        })
+    }
+
+    /// Renders the matte of what `camera` sees in `world`, as a gray linear image. Takes the
+    /// camera's samples per pixel, jittered like the render's, so the catcher's silhouette is
+    /// antialiased the same way. Each sample checks one direction toward the sun's disc and
+    /// one toward the sky, importance sampled like the render's direct lighting.
+    pub fn render(&self, camera: &Camera, world: &World) -> Result<Image, ShadowMatteError> {
+        let roles = self.roles()?;
+        let num_samples = camera.samples_per_pixel().max(1);
+        let pixels = (0..camera.image_height)
+            .cartesian_product(0..camera.image_width)
+            .collect_vec()
+            .into_par_iter()
+            .map(|(y, x)| {
+                let (mut missed, mut caught, mut sun_visible) = (0, 0, 0.0);
+                let (mut sky_weight, mut sky_visible_weight) = (0.0, 0.0);
+                for i in 0..num_samples {
+                    match self.sample(camera, world, &roles, x, y, i) {
+                        SampleResult::Missed => missed += 1,
+                        SampleResult::Caught {
+                            sun_visible: sun,
+                            sky_weight: weight,
+                            sky_visible_weight: visible_weight,
+                        } => {
+                            caught += 1;
+                            sun_visible += sun;
+                            sky_weight += weight;
+                            sky_visible_weight += visible_weight;
+                        }
+                    }
+                }
+                // The sky's share is estimated over all of the pixel's samples at once, which
+                // is much less noisy than averaging each sample's hit or miss
+                let sky_visible = if sky_weight > 0.0 {
+                    sky_visible_weight / sky_weight
+                } else {
+                    1.0
+                };
+                let lit = self.sun_fraction * sun_visible
+                    + (1.0 - self.sun_fraction) * sky_visible * caught as Float;
+                let value = (missed as Float + lit) / num_samples as Float;
+                Vec3::repeat(value.clamp(0.0, 1.0))
+            })
+            .collect();
+        Ok(Image {
+            pixels,
+            width: camera.image_width,
+            height: camera.image_height,
+            gamma: 1.0,
+            metadata: vec![
+                "pass: shadow matte".to_string(),
+                format!("samples per pixel: {}", num_samples),
+                format!("sun angle: {}", self.sun_angle),
+                format!("sun fraction: {}", self.sun_fraction),
+            ],
+            alpha: None,
+        })
+    }
+
+    fn sample(
+        &self,
+        camera: &Camera,
+        world: &World,
+        roles: &Roles,
+        x: usize,
+        y: usize,
+        i: usize,
+    ) -> SampleResult {
+        let ray = camera.primary_ray(x, y, i);
+        // The light samples get a stream of their own, counted down from the top so it can't
+        // be one of the camera's and correlate with the ray's jitter
+        let mut rng = SampleRng::for_sample(camera.seed, x, y, usize::MAX - i);
+        let _hits = rng::seed_hits(&mut rng);
+        let range = world.numeric.min_hit_distance..camera.t_range().end;
+        let hit = world.hit(&ray, &range);
+        let (point, normal) = match (roles.plane, &hit) {
+            (Some((origin, normal)), hit) => {
+                let facing = ray.direction.dot(&normal);
+                if facing == 0.0 {
+                    return SampleResult::Missed;
+                }
+                let t = (origin - ray.origin.coords).dot(&normal) / facing;
+                // Anything in the scene in front of the plane hides it
+                let hidden = hit.as_ref().is_some_and(|hit| hit.t < t);
+                if !range.contains(&t) || hidden {
+                    return SampleResult::Missed;
+                }
+                let normal = if facing > 0.0 { -normal } else { normal };
+                (ray.at(t), normal)
+            }
+            (None, Some(hit)) if roles.catchers.contains(&hit.object) => (hit.point, hit.normal),
+            (None, _) => return SampleResult::Missed,
+        };
+        let shadow_range = world.numeric.min_hit_distance..camera.t_range().end;
+        let visible = |direction: &Vec3| {
+            let origin = world.numeric.offset_ray_origin(&point, &normal, direction);
+            let shadow_ray = Ray::new(origin.into(), *direction);
+            !world.blocked(&shadow_ray, &shadow_range, |object| roles.casts(object))
+        };
+
+        let sun = Vec3::random_in_cone(
+            &mut rng,
+            &world.sun_direction(),
+            self.sun_angle.to_radians() / 2.0,
+        );
+        // The catcher faces away from the sun there, so it's already in its own shadow
+        let sun_visible = if sun.dot(&normal) <= 0.0 || visible(&sun) {
+            1.0
+        } else {
+            0.0
+        };
+
+        let sky = world.sample_sky_importance(&mut rng);
+        let cos_theta = sky.direction.dot(&normal);
+        let sky_weight = if cos_theta > 0.0 && sky.pdf > 0.0 {
+            luminance(&sky.radiance) * cos_theta / sky.pdf
+        } else {
+            0.0
+        };
+        let sky_visible_weight = if sky_weight > 0.0 && visible(&sky.direction) {
+            sky_weight
+        } else {
+            0.0
+        };
+        SampleResult::Caught {
+            sun_visible,
+            sky_weight,
+            sky_visible_weight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{SkySettings, Surroundings, Triangle},
+        material::{Lambertian, Material},
+    };
+    use std::{f64::consts::PI, sync::Arc};
+
+    /// Height of the caster's edge over the ground
+    const HEIGHT: Float = 2.0;
+
+    /// Ground at z = 0 under a sun straight overhead, with a plate `HEIGHT` above it covering
+    /// everything with x < 0, so its shadow's edge runs along the y axis. The plate faces down,
+    /// toward what it shades.
+    fn plate() -> World {
+        let gray: Arc<Material> = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let corner = |x: Float, y: Float| Vec3::new(x, y, HEIGHT);
+        let plate = vec![
+            Triangle::new(
+                corner(-50.0, -50.0),
+                corner(0.0, 50.0),
+                corner(0.0, -50.0),
+                gray.clone(),
+            )
+            .into(),
+            Triangle::new(
+                corner(-50.0, -50.0),
+                corner(-50.0, 50.0),
+                corner(0.0, 50.0),
+                gray,
+            )
+            .into(),
+        ];
+        Surroundings::default()
+            .with_sky(&SkySettings::default())
+            .unwrap()
+            .build(plate)
+    }
+
+    /// Fraction of a uniformly bright disc on the side of a chord `t` radii from its center,
+    /// which is how much of the sun a point that far out from the shadow's edge sees
+    fn disc_fraction(t: Float) -> Float {
+        let t = t.clamp(-1.0, 1.0);
+        (PI - t.acos() + t * (1.0 - t * t).sqrt()) / PI
+    }
+
+    #[test]
+    fn penumbras_widen_with_the_sun() {
+        // Looking down the shadow's edge at a slant from the side of the plate that's open, so
+        // the ground under it is in view. Rows of the image run across the edge.
+        let camera = Camera::builder()
+            .with_look_from(Vec3::new(20.0, 0.0, 10.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(0.9)
+            .with_resolution(4, 96)
+            .with_samples(256)
+            .with_max_depth(1)
+            .build()
+            .unwrap();
+        let world = plate();
+        let catcher = ShadowCatcher::Plane {
+            point: Vec3::zeros(),
+            normal: Vec3::z(),
+        };
+        // Where each row's middle meets the ground
+        let ground_x: Vec<Float> = (0..96)
+            .map(|y| {
+                let ray = camera.debug_ray(1.5, y as f64);
+                ray.origin.x as Float - ray.direction.x * ray.origin.z as Float / ray.direction.z
+            })
+            .collect();
+        assert!(ground_x[0] < -0.3 && ground_x[95] > 0.3, "{:?}", ground_x);
+
+        for sun_angle in [4.0, 8.0] {
+            let matte = ShadowMatte {
+                sun_angle,
+                sun_fraction: 1.0,
+                ..ShadowMatte::new(catcher.clone())
+            };
+            let image = matte.render(&camera, &world).unwrap();
+            let radius = HEIGHT * (sun_angle.to_radians() / 2.0).tan();
+            for (y, &x) in ground_x.iter().enumerate() {
+                let value = (0..4).map(|column| image[(column, y)].x).sum::<Float>() / 4.0;
+                let expected = disc_fraction(x / radius);
+                assert!(
+                    (value - expected).abs() < 0.08,
+                    "{} degrees, {} from the edge: {} rather than {}",
+                    sun_angle,
+                    x,
+                    value,
+                    expected
+                );
+            }
+            // A radius of the sun's image either side of the edge, and no further
+            let penumbra = ground_x
+                .iter()
+                .enumerate()
+                .filter(|(y, _)| {
+                    let value = (0..4).map(|column| image[(column, *y)].x).sum::<Float>() / 4.0;
+                    value > 0.0 && value < 1.0
+                })
+                .map(|(_, x)| *x)
+                .collect_vec();
+            let (first, last) = (penumbra[0], penumbra[penumbra.len() - 1]);
+            let pixel = ground_x[1] - ground_x[0];
+            assert!(
+                (first + radius).abs() < 2.0 * pixel.abs()
+                    && (last - radius).abs() < 2.0 * pixel.abs(),
+                "{} degrees: from {} to {}, rather than {} either side",
+                sun_angle,
+                first,
+                last,
+                radius
+            );
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Camera samples a pixel and all its neighbors need to have sent straight to the sky before the
+/// pixel stops being sampled. Enough to anti-alias sky gradients, which are smooth over a pixel.
+pub const MIN_SKY_SAMPLES: usize = 16;
+
+/// Recorded for a pixel once any of its camera rays hits geometry
+const HIT_GEOMETRY: usize = usize::MAX;
+
+/// Whether progressive rendering still adds samples to a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelState {
+    Sampling,
+    /// Only ever saw sky, as did its neighbors, so more samples wouldn't change it
+    SkyConverged,
+}
+
+/// Tracks which pixels of a progressive render are pure sky, so sweeps can skip them once
+/// they've converged. A pixel next to one that has hit geometry is never skipped, which keeps
+/// silhouettes anti-aliased. Samples are recorded from the render workers while a sweep runs, and
+/// pixels are reclassified between sweeps with [`SkyCache::update`].
+#[derive(Debug)]
+pub struct SkyCache {
+    width: usize,
+    height: usize,
+    min_samples: usize,
+    /// Per pixel, how many samples so far all missed geometry, or [`HIT_GEOMETRY`]
+    sky_samples: Vec<AtomicUsize>,
+    states: Vec<PixelState>,
+}
+
+impl SkyCache {
+    pub fn new(width: usize, height: usize, min_samples: usize) -> Self {
+        SkyCache {
+            width,
+            height,
+            min_samples,
+            sky_samples: (0..width * height).map(|_| AtomicUsize::new(0)).collect(),
+            states: vec![PixelState::Sampling; width * height],
+        }
+    }
+
+    /// Forgets everything, for when the camera or scene changes
+    pub fn reset(&mut self) {
+        for samples in &mut self.sky_samples {
+            *samples.get_mut() = 0;
+        }
+        self.states.fill(PixelState::Sampling);
+    }
+
+    pub fn state(&self, x: usize, y: usize) -> PixelState {
+        self.states[y * self.width + x]
+    }
+
+    /// Records that `escaped` of `samples` new camera rays through pixel `(x, y)` missed all
+    /// geometry
+    pub fn record(&self, x: usize, y: usize, samples: usize, escaped: usize) {
+        let sky_samples = &self.sky_samples[y * self.width + x];
+        if escaped < samples {
+            sky_samples.store(HIT_GEOMETRY, Ordering::Relaxed);
+        } else {
+            // Leaves a pixel that hit geometry alone
+            let _ = sky_samples.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count != HIT_GEOMETRY).then(|| count.saturating_add(samples).min(HIT_GEOMETRY - 1))
+            });
+        }
+    }
+
+    fn is_settled_sky(&self, x: usize, y: usize) -> bool {
+        let count = self.sky_samples[y * self.width + x].load(Ordering::Relaxed);
+        count != HIT_GEOMETRY && count >= self.min_samples
+    }
+
+    /// Whether the pixel and every pixel around it has only seen sky, for long enough
+    fn neighborhood_is_sky(&self, x: usize, y: usize) -> bool {
+        let columns = x.saturating_sub(1)..=(x + 1).min(self.width - 1);
+        let rows = y.saturating_sub(1)..=(y + 1).min(self.height - 1);
+        rows.into_iter()
+            .all(|y| columns.clone().all(|x| self.is_settled_sky(x, y)))
+    }
+
+    /// Marks pixels whose whole neighborhood is settled sky as converged, and puts converged
+    /// pixels with a neighbor that has since hit geometry back to sampling. Call between sweeps.
+    /// Returns how many pixels are converged.
+    pub fn update(&mut self) -> usize {
+        let mut converged = 0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let state = if self.neighborhood_is_sky(x, y) {
+                    converged += 1;
+                    PixelState::SkyConverged
+                } else {
+                    PixelState::Sampling
+                };
+                self.states[y * self.width + x] = state;
+            }
+        }
+        converged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::{self, Camera, Float},
+        hittable::{Background, Sphere, World},
+        material::Lambertian,
+        vec3::Vec3,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn pixels_near_geometry_keep_sampling() {
+        let mut cache = SkyCache::new(6, 4, 4);
+        for y in 0..4 {
+            for x in 0..6 {
+                cache.record(x, y, 4, 4);
+            }
+        }
+        // Geometry at the left edge, seen by only some of a pixel's rays
+        cache.record(0, 1, 4, 3);
+        assert_eq!(cache.update(), 24 - 6);
+        for (x, y) in [(0, 0), (1, 0), (1, 1), (0, 2), (1, 2)] {
+            assert_eq!(cache.state(x, y), PixelState::Sampling);
+        }
+        assert_eq!(cache.state(2, 1), PixelState::SkyConverged);
+
+        // Something moving into view brings its neighbors back
+        cache.record(4, 3, 4, 0);
+        assert_eq!(cache.update(), 24 - 6 - 6);
+        assert_eq!(cache.state(3, 2), PixelState::Sampling);
+
+        cache.reset();
+        assert_eq!(cache.update(), 0);
+    }
+
+    #[test]
+    fn skipping_converged_sky_gives_the_same_image() {
+        let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let mut world = World::build(vec![Sphere::new(Vec3::zeros(), 1.0, gray).into()]);
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::new(0.9, 0.8, 0.7),
+            top: Vec3::new(0.2, 0.4, 0.9),
+        };
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -8.0, 0.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(40.0)
+            .with_resolution(24, 16)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        let (width, height) = (camera.image_width, camera.image_height);
+
+        // Sweeps of the preview, each with samples of its own, with and without the cache
+        let render = |camera: &mut Camera, skip_sky: bool| {
+            let mut cache = SkyCache::new(width, height, MIN_SKY_SAMPLES);
+            let mut sums = vec![(Vec3::zeros(), 0); width * height];
+            let mut sampled = Vec::new();
+            for sweep in 0..8 {
+                camera.seed = Some(sweep);
+                let mut pixels = 0;
+                for (i, (sum, count)) in sums.iter_mut().enumerate() {
+                    let (x, y) = (i % width, i / width);
+                    if skip_sky && cache.state(x, y) == PixelState::SkyConverged {
+                        continue;
+                    }
+                    let (color, escaped) = camera.render_pixel_escapes(&world, x, y, 16);
+                    cache.record(x, y, 16, escaped);
+                    *sum += color * 16.0;
+                    *count += 16;
+                    pixels += 1;
+                }
+                sampled.push(pixels);
+                cache.update();
+            }
+            let pixels: Vec<Vec3> = sums
+                .iter()
+                .map(|(sum, count)| sum / *count as Float)
+                .collect();
+            (pixels, sampled)
+        };
+        let (full, _) = render(&mut camera, false);
+        let (skipped, sampled) = render(&mut camera, true);
+
+        // Most of the frame is sky, which stops being sampled after the first sweep
+        assert_eq!(sampled[0], width * height);
+        assert!(sampled[7] * 2 < width * height, "{:?}", sampled);
+        for (a, b) in full.iter().zip(&skipped) {
+            let (a, b) = (camera::rgb8(a, camera.gamma), camera::rgb8(b, camera.gamma));
+            assert!(
+                a.iter().zip(&b).all(|(a, b)| a.abs_diff(*b) <= 1),
+                "{:?} against {:?}",
+                a,
+                b
+            );
+        }
+    }
+}
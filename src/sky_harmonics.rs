@@ -0,0 +1,208 @@
+use crate::{camera::Float, sky_importance::table_bins, vec3::Vec3};
+use rayon::iter::ParallelIterator;
+use std::f64::consts::{PI, TAU};
+
+/// Spherical harmonics up to band 2
+const COEFFICIENTS: usize = 9;
+
+/// The only harmonic in band 0, which is the same everywhere
+const BAND_0: Float = 0.282_094_8;
+
+/// How much convolving with a clamped cosine lobe scales each band, from Ramamoorthi and
+/// Hanrahan's "An Efficient Representation for Irradiance Environment Maps"
+const COSINE_LOBE: [Float; 3] = [PI, TAU / 3.0, PI / 4.0];
+
+/// The real spherical harmonics of bands 0 to 2 at the unit vector `direction`, in the usual
+/// order of l and then m
+pub fn basis(direction: &Vec3) -> [Float; COEFFICIENTS] {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    [
+        BAND_0,
+        0.488_602_5 * y,
+        0.488_602_5 * z,
+        0.488_602_5 * x,
+        1.092_548_4 * x * y,
+        1.092_548_4 * y * z,
+        0.315_391_6 * (3.0 * z * z - 1.0),
+        1.092_548_4 * x * z,
+        0.546_274_2 * (x * x - y * y),
+    ]
+}
+
+/// Band of each coefficient
+fn band(coefficient: usize) -> usize {
+    match coefficient {
+        0 => 0,
+        1..=3 => 1,
+        _ => 2,
+    }
+}
+
+/// The sky's radiance projected onto the first nine spherical harmonics. That's too coarse to
+/// see the sun's disk in, but lights a diffuse surface within a few percent of the whole sky,
+/// for the cost of a dot product.
+#[derive(Debug, Clone)]
+pub struct SkyHarmonics {
+    coefficients: [Vec3; COEFFICIENTS],
+}
+
+impl SkyHarmonics {
+    /// Projects `radiance`, which returns the sky color looking along a unit vector, by summing
+    /// it over the same table [`crate::sky_importance::SkyImportance`] samples from
+    pub fn new(radiance: impl Fn(&Vec3) -> Vec3 + Sync) -> Self {
+        let zero = || [Vec3::zeros(); COEFFICIENTS];
+        let coefficients = table_bins()
+            .fold(zero, |mut sum, (direction, solid_angle)| {
+                let color = radiance(&direction) * solid_angle;
+                for (sum, y) in sum.iter_mut().zip(basis(&direction)) {
+                    *sum += color * y;
+                }
+                sum
+            })
+            .reduce(zero, |mut a, b| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a += b;
+                }
+                a
+            });
+        SkyHarmonics { coefficients }
+    }
+
+    pub fn coefficients(&self) -> &[Vec3; COEFFICIENTS] {
+        &self.coefficients
+    }
+
+    /// The projection's radiance looking along the unit vector `direction`, a blurred sky
+    pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+        self.coefficients
+            .iter()
+            .zip(basis(direction))
+            .map(|(c, y)| c * y)
+            .sum::<Vec3>()
+            .map(|c| c.max(0.0))
+    }
+
+    /// Light a white Lambertian surface facing the unit vector `normal` gives back when the
+    /// whole sky shines on it unblocked, which is its irradiance over pi
+    pub fn diffuse(&self, normal: &Vec3) -> Vec3 {
+        self.coefficients
+            .iter()
+            .zip(basis(normal))
+            .enumerate()
+            .map(|(i, (c, y))| c * (y * COSINE_LOBE[band(i)] / PI))
+            .sum::<Vec3>()
+            .map(|c| c.max(0.0))
+    }
+
+    /// Average radiance over every direction
+    pub fn average(&self) -> Vec3 {
+        // The other harmonics average out to zero
+        self.coefficients[0] * BAND_0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integrates `f` over the sphere on a grid of its own, finer than the table the
+    /// projection sums over and with its cells in other places
+    fn integrate(f: impl Fn(&Vec3) -> Vec3) -> Vec3 {
+        const ROWS: usize = 600;
+        const COLUMNS: usize = 1200;
+        let solid_angle = 2.0 / ROWS as Float * TAU / COLUMNS as Float;
+        let mut sum = Vec3::zeros();
+        for row in 0..ROWS {
+            let z = 1.0 - 2.0 * (row as Float + 0.5) / ROWS as Float;
+            let r = (1.0 - z * z).sqrt();
+            for column in 0..COLUMNS {
+                let phi = TAU * (column as Float + 0.25) / COLUMNS as Float;
+                sum += f(&Vec3::new(r * phi.cos(), r * phi.sin(), z)) * solid_angle;
+            }
+        }
+        sum
+    }
+
+    /// What a white Lambertian surface facing `normal` gives back under `radiance`, by direct
+    /// integration of the radiance times the clamped cosine over pi
+    fn diffuse_directly(radiance: &impl Fn(&Vec3) -> Vec3, normal: &Vec3) -> Vec3 {
+        integrate(|direction| radiance(direction) * direction.dot(normal).max(0.0)) / PI
+    }
+
+    fn normals() -> Vec<Vec3> {
+        vec![
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, 3.0).normalize(),
+            Vec3::new(-2.0, 1.0, -0.5).normalize(),
+        ]
+    }
+
+    #[test]
+    fn basis_is_orthonormal() {
+        for i in 0..COEFFICIENTS {
+            for j in 0..COEFFICIENTS {
+                let product = integrate(|d| Vec3::repeat(basis(d)[i] * basis(d)[j])).x;
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!(
+                    (product - expected).abs() < 1e-3,
+                    "{} {}: {}",
+                    i,
+                    j,
+                    product
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn an_even_sky_lights_every_way_alike() {
+        let sky = SkyHarmonics::new(|_| Vec3::new(0.2, 0.4, 0.8));
+        // Only as close as the table sums the sphere's area
+        assert!((sky.average() - Vec3::new(0.2, 0.4, 0.8)).norm() < 1e-3);
+        for normal in normals() {
+            let diffuse = sky.diffuse(&normal);
+            assert!(
+                (diffuse - Vec3::new(0.2, 0.4, 0.8)).norm() < 1e-3,
+                "{:?}: {:?}",
+                normal,
+                diffuse
+            );
+        }
+    }
+
+    /// Checks the projection lights each of [`normals`] within `tolerance` of integrating
+    /// `radiance` directly, relative to the light from straight up
+    fn assert_matches_direct(radiance: impl Fn(&Vec3) -> Vec3 + Sync, tolerance: Float) {
+        let sky = SkyHarmonics::new(&radiance);
+        // Relative to the brightest side, so the dim side's small errors don't blow up
+        let scale = diffuse_directly(&radiance, &Vec3::z()).max();
+        for normal in normals() {
+            let approximate = sky.diffuse(&normal);
+            let direct = diffuse_directly(&radiance, &normal);
+            let error = (approximate - direct).abs().max() / scale;
+            assert!(
+                error < tolerance,
+                "{:?}: {:?} against {:?}",
+                normal,
+                approximate,
+                direct
+            );
+        }
+    }
+
+    #[test]
+    fn diffuse_light_matches_direct_integration() {
+        let gradient = |d: &Vec3| Vec3::new(0.3, 0.5, 1.0) * d.z.max(0.0) + Vec3::repeat(0.1);
+        assert_matches_direct(gradient, 0.01);
+
+        // A small bright lobe is what nine harmonics fit worst
+        let sun = Vec3::new(0.3, -0.4, 0.6).normalize();
+        let sunny = |d: &Vec3| {
+            Vec3::new(0.3, 0.5, 1.0) * d.z.max(0.0)
+                + Vec3::new(40.0, 30.0, 20.0) * d.dot(&sun).max(0.0).powi(64)
+        };
+        assert_matches_direct(sunny, 0.04);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{camera::Float, vec3::Vec3};
+use rand::Rng;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use std::f64::consts::{PI, TAU};
+
+/// Number of bins around the up axis
+pub(crate) const AZIMUTH_BINS: usize = 128;
+/// Number of bins from straight up to straight down
+pub(crate) const POLAR_BINS: usize = 64;
+/// Every bin gets at least this fraction of the average bin's weight, so directions the table
+/// underestimates (e.g. near the sun between bin centers) can still be sampled
+const MIN_WEIGHT_FRACTION: Float = 1e-3;
+
+/// A direction sampled from the sky in proportion to its brightness
+#[derive(Debug, Clone, Copy)]
+pub struct SkySample {
+    /// Unit vector pointing toward the sky
+    pub direction: Vec3,
+    /// Sky radiance seen looking along `direction`
+    pub radiance: Vec3,
+    /// Probability density of sampling `direction`, per unit solid angle
+    pub pdf: Float,
+}
+
+/// Brightness of a linear color as the eye sees it
+pub fn luminance(color: &Vec3) -> Float {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Cosine of the polar angle at the top edge of `row`, measured from +Z
+fn row_top(row: usize) -> Float {
+    (PI * row as Float / POLAR_BINS as Float).cos()
+}
+
+/// Solid angle covered by each bin in `row`
+pub(crate) fn bin_solid_angle(row: usize) -> Float {
+    (row_top(row) - row_top(row + 1)) * TAU / AZIMUTH_BINS as Float
+}
+
+/// Picks the index whose span of the running total `cdf` contains `u`
+fn search(cdf: &[Float], u: Float) -> usize {
+    cdf.partition_point(|&total| total <= u).min(cdf.len() - 1)
+}
+
+/// The direction through the center of every bin of the latitude-longitude table the sky is
+/// tabulated on, with the solid angle the bin covers, one row after another from straight up
+pub(crate) fn table_bins() -> impl IndexedParallelIterator<Item = (Vec3, Float)> {
+    (0..POLAR_BINS * AZIMUTH_BINS).into_par_iter().map(|bin| {
+        let (row, column) = (bin / AZIMUTH_BINS, bin % AZIMUTH_BINS);
+        let z = (row_top(row) + row_top(row + 1)) / 2.0;
+        let phi = TAU * (column as Float + 0.5) / AZIMUTH_BINS as Float;
+        (direction(z, phi), bin_solid_angle(row))
+    })
+}
+
+/// A latitude-longitude table of the sky's brightness for importance sampling it, like the
+/// HDRI samplers in offline renderers. Each bin is picked in proportion to its luminance times
+/// its solid angle, then a direction is picked uniformly inside it.
+pub struct SkyImportance {
+    /// Running total of each row's probability
+    row_cdf: Vec<Float>,
+    /// Running total of each bin's probability within its row, one row after another
+    bin_cdfs: Vec<Float>,
+    /// Probability of picking each bin, one row after another
+    bin_probabilities: Vec<Float>,
+}
+
+impl SkyImportance {
+    /// Tabulates `radiance`, which returns the sky color looking along a unit vector
+    pub fn new(radiance: impl Fn(&Vec3) -> Vec3 + Sync) -> Self {
+        let mut weights: Vec<Float> = table_bins()
+            .map(|(direction, solid_angle)| luminance(&radiance(&direction)).max(0.0) * solid_angle)
+            .collect();
+
+        let average = weights.iter().sum::<Float>() / weights.len() as Float;
+        for (bin, weight) in weights.iter_mut().enumerate() {
+            // A black sky still needs a valid distribution, which is then uniform
+            let floor = if average > 0.0 {
+                average * MIN_WEIGHT_FRACTION
+            } else {
+                1.0
+            };
+            *weight = weight.max(floor * bin_solid_angle(bin / AZIMUTH_BINS));
+        }
+        let total: Float = weights.iter().sum();
+        let bin_probabilities: Vec<Float> = weights.iter().map(|w| w / total).collect();
+
+        let mut row_cdf = Vec::with_capacity(POLAR_BINS);
+        let mut bin_cdfs = Vec::with_capacity(POLAR_BINS * AZIMUTH_BINS);
+        let mut rows_total = 0.0;
+        for row in bin_probabilities.chunks(AZIMUTH_BINS) {
+            let row_probability: Float = row.iter().sum();
+            rows_total += row_probability;
+            row_cdf.push(rows_total);
+            let mut bins_total = 0.0;
+            for probability in row {
+                bins_total += probability / row_probability;
+                bin_cdfs.push(bins_total);
+            }
+        }
+
+        SkyImportance {
+            row_cdf,
+            bin_cdfs,
+            bin_probabilities,
+        }
+    }
+
+    /// Returns a random unit vector toward the sky and its probability density per solid angle
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> (Vec3, Float) {
+        let row = search(
+            &self.row_cdf,
+            rng.gen::<Float>() * self.row_cdf[POLAR_BINS - 1],
+        );
+        let row_cdf = &self.bin_cdfs[row * AZIMUTH_BINS..(row + 1) * AZIMUTH_BINS];
+        let column = search(row_cdf, rng.gen::<Float>() * row_cdf[AZIMUTH_BINS - 1]);
+
+        // Uniform in z is uniform in solid angle
+        let z = rng.gen_range(row_top(row + 1)..=row_top(row));
+        let phi = TAU * (column as Float + rng.gen::<Float>()) / AZIMUTH_BINS as Float;
+        let pdf = self.bin_probabilities[row * AZIMUTH_BINS + column] / bin_solid_angle(row);
+        (direction(z, phi), pdf)
+    }
+
+    /// Returns the probability density of [`SkyImportance::sample`] picking the unit vector
+    /// `direction`, per solid angle
+    pub fn pdf(&self, direction: &Vec3) -> Float {
+        let polar = direction.z.clamp(-1.0, 1.0).acos();
+        let row = ((polar / PI * POLAR_BINS as Float) as usize).min(POLAR_BINS - 1);
+        let phi = direction.y.atan2(direction.x).rem_euclid(TAU);
+        let column = ((phi / TAU * AZIMUTH_BINS as Float) as usize).min(AZIMUTH_BINS - 1);
+        self.bin_probabilities[row * AZIMUTH_BINS + column] / bin_solid_angle(row)
+    }
+}
+
+/// Returns the unit vector with height `z` at azimuth `phi` around +Z
+fn direction(z: Float, phi: Float) -> Vec3 {
+    let radius = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(radius * phi.cos(), radius * phi.sin(), z)
+}
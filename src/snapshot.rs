@@ -0,0 +1,377 @@
+use crate::{
+    camera::Float,
+    hittable::{Shape, TransparencyMode, World},
+    material::Material,
+    object::ObjectId,
+};
+use bvh::aabb::Bounded;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+/// What identifies one shape in a snapshot. Materials are compared by pointer rather than by
+/// hashing their textures, which keeps snapshots cheap enough to take after every edit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ShapeFingerprint {
+    geometry: u64,
+    material: usize,
+    object: ObjectId,
+}
+
+fn hash_floats(hasher: &mut DefaultHasher, floats: impl IntoIterator<Item = Float>) {
+    for float in floats {
+        float.to_bits().hash(hasher);
+    }
+}
+
+fn material_id(material: &Arc<Material>) -> usize {
+    Arc::as_ptr(material) as usize
+}
+
+impl ShapeFingerprint {
+    fn new(shape: &Shape) -> Self {
+        let mut hasher = DefaultHasher::new();
+        // Animated instances are compared where they rest, since they move every frame
+        let aabb = match shape {
+            Shape::Instance(instance) => instance.rest_bounds(),
+            _ => shape.aabb(),
+        };
+        hash_floats(&mut hasher, aabb.min.iter().chain(aabb.max.iter()).copied());
+        // Bounds don't pin down everything, e.g. which way a triangle faces
+        let (kind, material, object) = match shape {
+            Shape::Sphere(sphere) => (0, material_id(&sphere.material), sphere.object),
+            Shape::Triangle(triangle) => {
+                hash_floats(&mut hasher, triangle.a.iter().copied());
+                hash_floats(&mut hasher, triangle.b.iter().copied());
+                hash_floats(&mut hasher, triangle.c.iter().copied());
+                (1, material_id(&triangle.material), triangle.object)
+            }
+            Shape::TriangleFragment(fragment) => {
+                let triangle = fragment.triangle();
+                hash_floats(&mut hasher, triangle.a.iter().copied());
+                hash_floats(&mut hasher, triangle.b.iter().copied());
+                hash_floats(&mut hasher, triangle.c.iter().copied());
+                (2, material_id(&triangle.material), triangle.object)
+            }
+            Shape::AaBox(aa_box) => (3, material_id(&aa_box.material), aa_box.object),
+            Shape::RoundedBox(rounded) => {
+                hash_floats(&mut hasher, [rounded.radius()]);
+                (4, material_id(&rounded.material), rounded.object)
+            }
+            // Instances stand in for a whole group of materials, so the prototype is compared instead
+            Shape::Instance(instance) => (
+                5,
+                Arc::as_ptr(instance.prototype()) as usize,
+                ObjectId::default(),
+            ),
+            Shape::Mesh(mesh) => {
+                hash_floats(
+                    &mut hasher,
+                    mesh.positions().iter().flat_map(|p| p.iter().copied()),
+                );
+                mesh.triangle_count().hash(&mut hasher);
+                (7, material_id(&mesh.material), mesh.object)
+            }
+            Shape::HeterogeneousMedium(medium) => {
+                // Hashing every voxel would be too slow, so only the grid's shape is compared
+                hash_floats(&mut hasher, [medium.step_size]);
+                medium.grid().dims().hash(&mut hasher);
+                (6, material_id(&medium.material), medium.object)
+            }
+            Shape::RectLight(light) => {
+                hash_floats(&mut hasher, light.normal().iter().copied());
+                hash_floats(&mut hasher, light.radiance().iter().copied());
+                (light.double_sided, light.visible).hash(&mut hasher);
+                (8, material_id(&light.material), light.object)
+            }
+            Shape::SpotLight(light) => {
+                hash_floats(&mut hasher, light.direction().iter().copied());
+                hash_floats(&mut hasher, light.intensity().iter().copied());
+                hash_floats(&mut hasher, light.cone());
+                let profile = light
+                    .profile
+                    .as_ref()
+                    .map_or(0, |p| Arc::as_ptr(p) as usize);
+                (9, profile, light.object)
+            }
+        };
+        kind.hash(&mut hasher);
+        ShapeFingerprint {
+            geometry: hasher.finish(),
+            material,
+            object,
+        }
+    }
+}
+
+/// A compact fingerprint of a world, for checking exactly what an edit changed
+#[derive(Debug, Clone)]
+pub struct SceneSnapshot {
+    shapes: Vec<ShapeFingerprint>,
+    sun_direction: [u64; 3],
+    /// Hash of the sky model's turbidity, albedo and solar elevation, and the sun's disc
+    sky: u64,
+    /// Hash of the tonemap's settings, which also decide how the sky looks
+    tonemap: u64,
+    background: u64,
+    transparency: TransparencyMode,
+}
+
+/// The differences between two snapshots. Shapes are matched up by their index in the world.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SceneDiff {
+    /// Indices of shapes whose geometry changed
+    pub geometry_changed: Vec<usize>,
+    /// Indices of shapes that were given a different material
+    pub material_changed: Vec<usize>,
+    /// Indices of shapes that were moved to a different object
+    pub object_changed: Vec<usize>,
+    /// Number of distinct materials the shapes in `material_changed` now use
+    pub new_materials: usize,
+    pub shapes_added: usize,
+    pub shapes_removed: usize,
+    pub sun_changed: bool,
+    pub sky_changed: bool,
+    pub tonemap_changed: bool,
+    pub background_changed: bool,
+    pub transparency_changed: bool,
+}
+
+impl World {
+    /// Takes a snapshot of the world, hashing shapes in parallel
+    pub fn snapshot(&self) -> SceneSnapshot {
+        let sun_direction = self.sun_direction();
+        let settings_hash = |settings: String| {
+            let mut hasher = DefaultHasher::new();
+            settings.hash(&mut hasher);
+            hasher.finish()
+        };
+        SceneSnapshot {
+            shapes: self.shapes.par_iter().map(ShapeFingerprint::new).collect(),
+            sun_direction: [0, 1, 2].map(|i| sun_direction[i].to_bits()),
+            // The disc is only hashed when there is one, so older scenes keep their fingerprints
+            sky: settings_hash(match &self.sun_disc {
+                Some(disc) => format!("{:?} {:?}", self.sky_params(), disc),
+                None => format!("{:?}", self.sky_params()),
+            }),
+            tonemap: settings_hash(format!("{:?}", self.tonemap)),
+            background: settings_hash(format!("{:?}", self.background)),
+            transparency: self.transparency,
+        }
+    }
+}
+
+impl SceneSnapshot {
+    /// Returns a hash of the scene's geometry and settings that's stable between runs, for
+    /// checking that two renders show the same scene. Materials are left out since snapshots
+    /// only know them by address.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for shape in &self.shapes {
+            shape.geometry.hash(&mut hasher);
+        }
+        self.sun_direction.hash(&mut hasher);
+        self.sky.hash(&mut hasher);
+        self.tonemap.hash(&mut hasher);
+        self.background.hash(&mut hasher);
+        format!("{:?}", self.transparency).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns what changed going from this snapshot to `other`
+    pub fn diff(&self, other: &SceneSnapshot) -> SceneDiff {
+        let mut diff = SceneDiff {
+            shapes_added: other.shapes.len().saturating_sub(self.shapes.len()),
+            shapes_removed: self.shapes.len().saturating_sub(other.shapes.len()),
+            sun_changed: self.sun_direction != other.sun_direction,
+            sky_changed: self.sky != other.sky,
+            tonemap_changed: self.tonemap != other.tonemap,
+            background_changed: self.background != other.background,
+            transparency_changed: self.transparency != other.transparency,
+            ..Default::default()
+        };
+        let mut new_materials = HashSet::new();
+        for (i, (old, new)) in self.shapes.iter().zip(&other.shapes).enumerate() {
+            if old.geometry != new.geometry {
+                diff.geometry_changed.push(i);
+            }
+            if old.material != new.material {
+                diff.material_changed.push(i);
+                new_materials.insert(new.material);
+            }
+            if old.object != new.object {
+                diff.object_changed.push(i);
+            }
+        }
+        diff.new_materials = new_materials.len();
+        diff
+    }
+}
+
+impl SceneDiff {
+    /// Returns whether the two snapshots are identical
+    pub fn is_empty(&self) -> bool {
+        self == &SceneDiff::default()
+    }
+}
+
+fn shapes(count: usize) -> String {
+    if count == 1 {
+        "1 shape".to_string()
+    } else {
+        format!("{} shapes", count)
+    }
+}
+
+impl fmt::Display for SceneDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.material_changed.is_empty() {
+            parts.push(format!(
+                "{} material{} changed on {}",
+                self.new_materials,
+                if self.new_materials == 1 { "" } else { "s" },
+                shapes(self.material_changed.len())
+            ));
+        }
+        if !self.geometry_changed.is_empty() {
+            parts.push(format!(
+                "geometry changed on {}",
+                shapes(self.geometry_changed.len())
+            ));
+        }
+        if !self.object_changed.is_empty() {
+            parts.push(format!(
+                "object changed on {}",
+                shapes(self.object_changed.len())
+            ));
+        }
+        if self.shapes_added > 0 {
+            parts.push(format!("{} added", shapes(self.shapes_added)));
+        }
+        if self.shapes_removed > 0 {
+            parts.push(format!("{} removed", shapes(self.shapes_removed)));
+        }
+        if parts.is_empty() {
+            parts.push("no shapes changed".to_string());
+        }
+        parts.push(
+            if self.sun_changed {
+                "sun changed"
+            } else {
+                "sun unchanged"
+            }
+            .to_string(),
+        );
+        if self.sky_changed {
+            parts.push("sky changed".to_string());
+        }
+        if self.tonemap_changed {
+            parts.push("tonemap changed".to_string());
+        }
+        if self.background_changed {
+            parts.push("background changed".to_string());
+        }
+        if self.transparency_changed {
+            parts.push("transparency mode changed".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Background, Sphere},
+        material::Lambertian,
+        vec3::Vec3,
+    };
+
+    fn gray() -> Arc<Material> {
+        Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into())
+    }
+
+    fn spheres(materials: &[Arc<Material>], moved: Option<usize>) -> Vec<Shape> {
+        materials
+            .iter()
+            .enumerate()
+            .map(|(i, material)| {
+                let z = if moved == Some(i) { 3.0 } else { 0.0 };
+                Sphere::new(Vec3::new(i as Float * 3.0, 0.0, z), 1.0, material.clone()).into()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diffs_list_exactly_what_changed() {
+        let materials: Vec<_> = (0..5).map(|_| gray()).collect();
+        let before = World::build(spheres(&materials, None)).snapshot();
+        assert!(before
+            .diff(&World::build(spheres(&materials, None)).snapshot())
+            .is_empty());
+
+        let mut swapped = materials.clone();
+        swapped[1] = gray();
+        swapped[3] = swapped[1].clone();
+        let diff = before.diff(&World::build(spheres(&swapped, None)).snapshot());
+        assert_eq!(
+            diff,
+            SceneDiff {
+                material_changed: vec![1, 3],
+                new_materials: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "1 material changed on 2 shapes, sun unchanged"
+        );
+
+        let mut world = World::build(spheres(&materials, Some(2)));
+        world.background = Background::Gradient {
+            up: Vec3::z(),
+            bottom: Vec3::zeros(),
+            top: Vec3::zeros(),
+        };
+        let diff = before.diff(&world.snapshot());
+        assert_eq!(
+            diff,
+            SceneDiff {
+                geometry_changed: vec![2],
+                background_changed: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "geometry changed on 1 shape, sun unchanged, background changed"
+        );
+
+        let mut fewer = materials.clone();
+        fewer.pop();
+        let diff = before.diff(&World::build(spheres(&fewer, None)).snapshot());
+        assert_eq!(diff.shapes_removed, 1);
+        assert!(diff.geometry_changed.is_empty() && diff.material_changed.is_empty());
+    }
+
+    #[test]
+    fn fingerprints_ignore_materials_but_not_geometry() {
+        let materials: Vec<_> = (0..3).map(|_| gray()).collect();
+        let others: Vec<_> = (0..3).map(|_| gray()).collect();
+        let fingerprint = |materials: &[Arc<Material>], moved| {
+            World::build(spheres(materials, moved))
+                .snapshot()
+                .fingerprint()
+        };
+        assert_eq!(fingerprint(&materials, None), fingerprint(&others, None));
+        assert_ne!(
+            fingerprint(&materials, None),
+            fingerprint(&materials, Some(0))
+        );
+    }
+}
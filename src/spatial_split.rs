@@ -0,0 +1,281 @@
+use crate::{
+    camera::Float,
+    hittable::{Hit, Shape, Triangle},
+    intersection::Intersection,
+    vec3::{Point3, Ray, Vec3},
+};
+use bvh::{
+    aabb::{Aabb, Bounded},
+    bounding_hierarchy::BHShape,
+};
+use std::{ops::Range, sync::Arc};
+
+/// Triangles longer than this many times the median primitive size get split
+const SPLIT_SIZE_RATIO: Float = 16.0;
+/// Caps how many fragments a single huge triangle (e.g. the ground plane) can turn into
+const MAX_FRAGMENTS_PER_TRIANGLE: usize = 1024;
+
+/// The part of a large triangle that lies inside one cell of a grid laid over it.
+/// Fragments share their triangle and give the BVH tight bounds instead of one huge box.
+pub struct TriangleFragment {
+    triangle: Arc<Triangle>,
+    bounds: Aabb<Float, 3>,
+    node_index: usize,
+}
+
+impl TriangleFragment {
+    /// The whole triangle this fragment is part of
+    pub fn triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+
+    /// Returns whether `point` is inside this fragment's bounds, with some slack for rounding
+    fn contains(&self, point: &Point3) -> bool {
+        let slack = (self.bounds.max - self.bounds.min).norm() * 1e-9 + 1e-12;
+        (0..3).all(|i| {
+            point[i] >= self.bounds.min[i] - slack && point[i] <= self.bounds.max[i] + slack
+        })
+    }
+}
+
+impl Hit for TriangleFragment {
+    fn hit(&self, ray: &Ray, range: &Range<Float>) -> Option<Intersection<'_>> {
+        // Only report hits inside this fragment so the other fragments don't report them again
+        self.triangle
+            .hit(ray, range)
+            .filter(|hit| self.contains(&hit.point))
+    }
+}
+
+impl Bounded<Float, 3> for TriangleFragment {
+    fn aabb(&self) -> Aabb<Float, 3> {
+        self.bounds
+    }
+}
+
+impl BHShape<Float, 3> for TriangleFragment {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+/// Clips a convex polygon to the side of the plane `point[axis] = value` given by `keep_above`
+fn clip_polygon(polygon: &[Point3], axis: usize, value: Float, keep_above: bool) -> Vec<Point3> {
+    let inside = |p: &Point3| {
+        if keep_above {
+            p[axis] >= value
+        } else {
+            p[axis] <= value
+        }
+    };
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        if inside(current) {
+            clipped.push(*current);
+        }
+        if inside(current) != inside(next) {
+            let t = (value - current[axis]) / (next[axis] - current[axis]);
+            let mut crossing = current + (next - current) * t;
+            crossing[axis] = value; // Avoid drifting off the plane
+            clipped.push(crossing);
+        }
+    }
+    clipped
+}
+
+/// Returns the bounds of the part of `triangle` inside `cell`, if any
+fn clipped_bounds(triangle: &Triangle, cell: &Aabb<Float, 3>) -> Option<Aabb<Float, 3>> {
+    let mut polygon = vec![triangle.a, triangle.b, triangle.c];
+    for axis in 0..3 {
+        polygon = clip_polygon(&polygon, axis, cell.min[axis], true);
+        polygon = clip_polygon(&polygon, axis, cell.max[axis], false);
+        if polygon.is_empty() {
+            return None;
+        }
+    }
+    let min = polygon.iter().fold(polygon[0], |min, p| min.inf(p));
+    let max = polygon.iter().fold(polygon[0], |max, p| max.sup(p));
+    Some(Aabb::with_bounds(min.into(), max.into()))
+}
+
+fn extent(shape: &Shape) -> Vec3 {
+    let aabb = shape.aabb();
+    aabb.max - aabb.min
+}
+
+/// Splits every triangle that's much bigger than the typical primitive into fragments with
+/// tight bounds, so the BVH doesn't have to put a huge box around everything near them.
+/// Rendered images are unchanged since each fragment reports only the hits inside it.
+pub fn split_large_triangles(shapes: Vec<Shape>) -> Vec<Shape> {
+    if shapes.is_empty() {
+        return shapes;
+    }
+    let mut sizes: Vec<Float> = shapes.iter().map(|shape| extent(shape).max()).collect();
+    let middle = sizes.len() / 2;
+    let median_size = *sizes
+        .select_nth_unstable_by(middle, |a, b| a.total_cmp(b))
+        .1;
+    let target_size = median_size * SPLIT_SIZE_RATIO;
+    if target_size <= 0.0 {
+        return shapes;
+    }
+
+    let mut split_shapes = Vec::with_capacity(shapes.len());
+    for shape in shapes {
+        let size = extent(&shape);
+        let triangle = match shape {
+            Shape::Triangle(triangle) if size.max() > target_size => triangle,
+            _ => {
+                split_shapes.push(shape);
+                continue;
+            }
+        };
+
+        // Pick a grid resolution per axis, shrinking the longest axis until under the cap
+        let mut cells = size.map(|s| (s / target_size).ceil().max(1.0) as usize);
+        while cells.iter().product::<usize>() > MAX_FRAGMENTS_PER_TRIANGLE {
+            let longest = cells.imax();
+            cells[longest] = cells[longest].div_ceil(2);
+        }
+
+        let bounds = triangle.aabb();
+        let cell_size = size.component_div(&cells.map(|c| c as Float));
+        let triangle = Arc::new(triangle);
+        for i in 0..cells.x {
+            for j in 0..cells.y {
+                for k in 0..cells.z {
+                    let min = bounds.min.coords
+                        + cell_size.component_mul(&Vec3::new(i as Float, j as Float, k as Float));
+                    let cell = Aabb::with_bounds(min.into(), (min + cell_size).into());
+                    if let Some(fragment_bounds) = clipped_bounds(&triangle, &cell) {
+                        split_shapes.push(
+                            TriangleFragment {
+                                triangle: triangle.clone(),
+                                bounds: fragment_bounds,
+                                node_index: 0,
+                            }
+                            .into(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    split_shapes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{BuildOptions, BuildQuality, Sphere, World},
+        material::{Lambertian, Material},
+        texture::SolidColor,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// A grid of small balls on a ground plane of two triangles much larger than them
+    fn balls_on_a_plane() -> Vec<Shape> {
+        let material = |albedo: Float| -> Arc<Material> {
+            Arc::new(Lambertian::new(SolidColor::new(Vec3::repeat(albedo)).into()).into())
+        };
+        let ground = material(0.5);
+        let corners = [
+            Vec3::new(-100.0, -100.0, 0.0),
+            Vec3::new(100.0, -100.0, 0.0),
+            Vec3::new(100.0, 100.0, 0.0),
+            Vec3::new(-100.0, 100.0, 0.0),
+        ];
+        let mut shapes: Vec<Shape> = vec![
+            Triangle::new(corners[0], corners[1], corners[2], ground.clone()).into(),
+            Triangle::new(corners[0], corners[2], corners[3], ground).into(),
+        ];
+        for i in 0..10 {
+            for j in 0..10 {
+                let center = Vec3::new(i as Float - 4.5, j as Float - 4.5, 0.2);
+                shapes.push(Sphere::new(center, 0.2, material(0.1 * i as Float)).into());
+            }
+        }
+        shapes
+    }
+
+    #[test]
+    fn only_large_triangles_are_split() {
+        let split = split_large_triangles(balls_on_a_plane());
+        let fragments = split
+            .iter()
+            .filter(|shape| matches!(shape, Shape::TriangleFragment(_)))
+            .count();
+        let spheres = split
+            .iter()
+            .filter(|shape| matches!(shape, Shape::Sphere(_)))
+            .count();
+        assert_eq!(spheres, 100);
+        assert_eq!(split.len(), spheres + fragments);
+        assert!(
+            (2..=2 * MAX_FRAGMENTS_PER_TRIANGLE).contains(&fragments),
+            "{}",
+            fragments
+        );
+        // Nothing to split in a scene of similar shapes
+        let balls: Vec<Shape> = balls_on_a_plane().into_iter().skip(2).collect();
+        assert_eq!(split_large_triangles(balls).len(), 100);
+    }
+
+    #[test]
+    fn split_worlds_find_the_same_hits() {
+        let fast = World::build(balls_on_a_plane());
+        let split = World::build_with_options(
+            balls_on_a_plane(),
+            BuildOptions {
+                quality: BuildQuality::HighQuality,
+            },
+        );
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..10_000 {
+            let origin = Vec3::new(
+                rng.gen_range(-20.0..20.0),
+                rng.gen_range(-20.0..20.0),
+                rng.gen_range(0.5..10.0),
+            );
+            let target = Vec3::new(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0), 0.0);
+            let ray = Ray::new(origin.into(), target - origin);
+            let expected = fast.hit(&ray, &(0.001..Float::MAX));
+            let found = split.hit(&ray, &(0.001..Float::MAX));
+            assert_eq!(
+                expected.map(|hit| (hit.t, hit.point)),
+                found.map(|hit| (hit.t, hit.point)),
+                "{:?}",
+                ray
+            );
+        }
+    }
+
+    #[test]
+    fn split_worlds_render_identical_images() {
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -12.0, 3.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(40.0)
+            .with_resolution(32, 18)
+            .with_samples(2)
+            .with_max_depth(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(2);
+        let fast = camera.render_image(&World::build(balls_on_a_plane()));
+        let split = camera.render_image(&World::build_with_options(
+            balls_on_a_plane(),
+            BuildOptions {
+                quality: BuildQuality::HighQuality,
+            },
+        ));
+        assert!(fast.pixels == split.pixels);
+    }
+}
@@ -0,0 +1,481 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    rng,
+    sky_importance::{bin_solid_angle, AZIMUTH_BINS, POLAR_BINS},
+    vec3::{Point3, Vec3},
+};
+use std::{
+    f64::consts::{PI, TAU},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+/// Most splats of each kind kept while recording. Past this, reservoir sampling keeps an even
+/// sample of all of them, so memory stays bounded however long the render runs.
+pub const RESERVOIR_CAPACITY: usize = 1 << 18;
+/// Cells along each side of a light's heatmap
+pub const LIGHT_CELLS: usize = 32;
+/// How many standard deviations of the uniformity statistic a heatmap can be off by before it's
+/// flagged. Far enough out that a correct sampler is practically never flagged.
+const FLAG_SIGMAS: Float = 5.0;
+/// Fewest splats a heatmap cell should expect for the uniformity statistic to mean anything
+const MIN_EXPECTED_PER_CELL: Float = 5.0;
+/// Pixels per heatmap cell along each side in the images
+const CELL_PIXELS: usize = 8;
+
+/// The light a light sample was taken on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SplatLight {
+    /// The area light at this index, see [`crate::lights::AreaLights`]
+    Area(usize),
+    Sun,
+}
+
+impl std::fmt::Display for SplatLight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SplatLight::Area(index) => write!(f, "light {}", index),
+            SplatLight::Sun => write!(f, "sun"),
+        }
+    }
+}
+
+/// A point picked on a light, in coordinates from 0 to 1 that are proportional to the light's
+/// area, so a sampler that picks points evenly by area spreads them evenly over the square
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSplat {
+    pub light: SplatLight,
+    pub s: Float,
+    pub t: Float,
+}
+
+/// A direction picked toward the sky, with the density its sampler gave it per solid angle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySplat {
+    pub direction: Vec3,
+    pub pdf: Float,
+}
+
+/// Where a shadow ray that was blocked stopped, and how far it got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSplat {
+    pub point: Point3,
+    pub distance: Float,
+}
+
+/// Keeps an even random sample of at most `capacity` of the items pushed into it, so any number
+/// of them fit in bounded memory
+#[derive(Debug, Clone)]
+pub struct Reservoir<T> {
+    items: Vec<T>,
+    seen: u64,
+    capacity: usize,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Reservoir {
+            items: Vec::new(),
+            seen: 0,
+            capacity,
+        }
+    }
+
+    /// Keeps `item` with the odds every item pushed so far had of being kept
+    pub fn push(&mut self, item: T) {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            // Hashing the count instead of drawing from the sample's stream leaves renders alone
+            let slot = rng::mix(self.seen) % (self.seen + 1);
+            if let Some(kept) = self.items.get_mut(slot as usize) {
+                *kept = item;
+            }
+        }
+        self.seen += 1;
+    }
+
+    /// The items kept
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// How many items were pushed, kept or not
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+}
+
+/// Everything light sampling recorded during an instrumentation render
+#[derive(Debug, Clone)]
+pub struct Splats {
+    pub lights: Reservoir<LightSplat>,
+    pub sky: Reservoir<SkySplat>,
+    pub shadows: Reservoir<ShadowSplat>,
+}
+
+impl Splats {
+    pub fn new(capacity: usize) -> Self {
+        Splats {
+            lights: Reservoir::new(capacity),
+            sky: Reservoir::new(capacity),
+            shadows: Reservoir::new(capacity),
+        }
+    }
+
+    /// The lights that were sampled, with how many of their samples were kept, most first
+    pub fn sampled_lights(&self) -> Vec<(SplatLight, usize)> {
+        let mut lights: Vec<(SplatLight, usize)> = Vec::new();
+        for splat in self.lights.items() {
+            match lights.iter_mut().find(|(light, _)| *light == splat.light) {
+                Some((_, count)) => *count += 1,
+                None => lights.push((splat.light, 1)),
+            }
+        }
+        lights.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        lights
+    }
+}
+
+/// Whether light sampling is being recorded, checked before taking the lock so renders that
+/// aren't recorded pay next to nothing
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static SPLATS: Mutex<Option<Splats>> = Mutex::new(None);
+
+/// Starts recording where light samples, sky samples and shadow rays land, throwing away
+/// anything recorded before. Only builds with the `diagnostics` feature record anything.
+pub fn start_recording() {
+    *SPLATS.lock().unwrap_or_else(PoisonError::into_inner) = Some(Splats::new(RESERVOIR_CAPACITY));
+    RECORDING.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording and returns what was recorded
+pub fn stop_recording() -> Splats {
+    RECORDING.store(false, Ordering::Relaxed);
+    SPLATS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .unwrap_or_else(|| Splats::new(RESERVOIR_CAPACITY))
+}
+
+/// Whether light sampling is being recorded, for hooks that have extra work to do to record
+pub fn recording() -> bool {
+    RECORDING.load(Ordering::Relaxed)
+}
+
+fn record(add: impl FnOnce(&mut Splats)) {
+    if !recording() {
+        return;
+    }
+    if let Some(splats) = SPLATS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_mut()
+    {
+        add(splats);
+    }
+}
+
+pub fn record_light(splat: LightSplat) {
+    record(|splats| splats.lights.push(splat));
+}
+
+pub fn record_sky(splat: SkySplat) {
+    record(|splats| splats.sky.push(splat));
+}
+
+pub fn record_shadow(splat: ShadowSplat) {
+    record(|splats| splats.shadows.push(splat));
+}
+
+/// Coordinates of the unit vector `direction` in the cone of directions within `half_angle`
+/// radians of the unit vector `axis`, proportional to solid angle like [`LightSplat`]'s. Uses
+/// the same frame around the axis as [`crate::vec3::Vec3Ext::random_in_cone`].
+pub fn cone_coordinates(axis: &Vec3, half_angle: Float, direction: &Vec3) -> (Float, Float) {
+    let helper = if axis.x.abs() > 0.9 {
+        Vec3::y()
+    } else {
+        Vec3::x()
+    };
+    let u = axis.cross(&helper).normalize();
+    let v = axis.cross(&u);
+    let cos_theta = direction.dot(axis).clamp(-1.0, 1.0);
+    let s = (1.0 - cos_theta) / (1.0 - half_angle.cos()).max(Float::MIN_POSITIVE);
+    let t = direction.dot(&v).atan2(direction.dot(&u)).rem_euclid(TAU) / TAU;
+    (s.min(1.0), t)
+}
+
+/// Splats binned over a grid of cells, next to how many each cell should have got from a
+/// sampler that matches its density
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heatmap {
+    pub columns: usize,
+    pub rows: usize,
+    /// Splats in each cell, a row at a time from the top
+    pub counts: Vec<Float>,
+    /// Splats each cell should expect, laid out like `counts`
+    pub expected: Vec<Float>,
+}
+
+impl Heatmap {
+    /// Bins points from 0 to 1 on both axes, expecting them spread evenly over the square
+    pub fn uniform(points: impl IntoIterator<Item = (Float, Float)>, cells: usize) -> Self {
+        let mut counts = vec![0.0; cells * cells];
+        let mut total = 0.0;
+        for (s, t) in points {
+            counts[cell(t, cells) * cells + cell(s, cells)] += 1.0;
+            total += 1.0;
+        }
+        Heatmap {
+            columns: cells,
+            rows: cells,
+            counts,
+            expected: vec![total / (cells * cells) as Float; cells * cells],
+        }
+    }
+
+    /// Bins sky directions over a latitude-longitude grid from straight up, matching the sky's
+    /// importance table. Each cell expects its share of the splats going by the density the
+    /// sampler gave the splats in it, which is constant over each cell of that table.
+    pub fn sky(splats: &[SkySplat]) -> Self {
+        let cells = AZIMUTH_BINS * POLAR_BINS;
+        let mut counts = vec![0.0; cells];
+        let mut pdf_totals = vec![0.0; cells];
+        for splat in splats {
+            let polar = splat.direction.z.clamp(-1.0, 1.0).acos();
+            let phi = splat.direction.y.atan2(splat.direction.x).rem_euclid(TAU);
+            let index = cell(polar / PI, POLAR_BINS) * AZIMUTH_BINS + cell(phi / TAU, AZIMUTH_BINS);
+            counts[index] += 1.0;
+            pdf_totals[index] += splat.pdf;
+        }
+        let total = splats.len() as Float;
+        let expected = (0..cells)
+            .map(|index| {
+                if counts[index] == 0.0 {
+                    return 0.0;
+                }
+                let mean_pdf = pdf_totals[index] / counts[index];
+                total * mean_pdf * bin_solid_angle(index / AZIMUTH_BINS)
+            })
+            .collect();
+        Heatmap {
+            columns: AZIMUTH_BINS,
+            rows: POLAR_BINS,
+            counts,
+            expected,
+        }
+    }
+
+    /// Pearson's chi-squared statistic over its degrees of freedom, which is about 1 for splats
+    /// spread the way they're expected to be and grows with how much they cluster. `None` if
+    /// there are too few splats to tell.
+    pub fn uniformity(&self) -> Option<Float> {
+        let cells: Vec<(Float, Float)> = self
+            .counts
+            .iter()
+            .zip(&self.expected)
+            .filter(|(_, expected)| **expected > 0.0)
+            .map(|(count, expected)| (*count, *expected))
+            .collect();
+        let expected_total: Float = cells.iter().map(|(_, expected)| expected).sum();
+        if cells.len() < 2 || expected_total < MIN_EXPECTED_PER_CELL * cells.len() as Float {
+            return None;
+        }
+        let chi_squared: Float = cells
+            .iter()
+            .map(|(count, expected)| (count - expected).powi(2) / expected)
+            .sum();
+        Some(chi_squared / (cells.len() - 1) as Float)
+    }
+
+    /// Whether the splats cluster more than chance allows for a sampler that matches its
+    /// density, i.e. [`Heatmap::uniformity`] is more than [`FLAG_SIGMAS`] standard deviations
+    /// above 1
+    pub fn clustered(&self) -> Option<bool> {
+        let uniformity = self.uniformity()?;
+        let cells = self
+            .expected
+            .iter()
+            .filter(|expected| **expected > 0.0)
+            .count();
+        let deviation = (2.0 / (cells - 1) as Float).sqrt();
+        Some(uniformity > 1.0 + FLAG_SIGMAS * deviation)
+    }
+
+    /// Draws each cell's splats over what it expected: mid grey where they match, brighter
+    /// where it has too many and darker where it has too few, and black where it expected none
+    pub fn to_image(&self) -> Image {
+        let (width, height) = (self.columns * CELL_PIXELS, self.rows * CELL_PIXELS);
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let index = (y / CELL_PIXELS) * self.columns + x / CELL_PIXELS;
+                let expected = self.expected[index];
+                let level = if expected > 0.0 {
+                    (self.counts[index] / expected / 2.0).min(1.0)
+                } else {
+                    0.0
+                };
+                Vec3::repeat(level)
+            })
+            .collect();
+        Image {
+            pixels,
+            width,
+            height,
+            gamma: 1.0,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+}
+
+/// Index of the cell `value` from 0 to 1 falls in, out of `cells`
+fn cell(value: Float, cells: usize) -> usize {
+    ((value * cells as Float) as usize).min(cells - 1)
+}
+
+/// Marks where each shadow ray in `splats` stopped on `image`, a render from `camera`: red for
+/// ones that stopped within `self_hit_distance` of where they started, which are most likely
+/// hitting the surface they left, and yellow for the rest
+pub fn shadow_overlay(
+    image: &mut Image,
+    camera: &Camera,
+    splats: &[ShadowSplat],
+    self_hit_distance: Float,
+) {
+    for splat in splats {
+        let Some((x, y)) = camera.pixel_of(&splat.point) else {
+            continue;
+        };
+        let color = if splat.distance < self_hit_distance {
+            Vec3::new(1.0, 0.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.85, 0.0)
+        };
+        if let Some(pixel) = image.pixels.get_mut(y * image.width + x) {
+            *pixel = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rng::SampleRng, sky_importance::SkyImportance, vec3::Vec3Ext};
+    use rand::Rng;
+
+    const HALF_ANGLE: Float = 0.3;
+
+    fn rng() -> SampleRng {
+        SampleRng::seeded(5, 0, 0, 0)
+    }
+
+    /// Mean brightness of the heatmap image's leftmost and rightmost columns of cells
+    fn edge_levels(heatmap: &Heatmap) -> (Float, Float) {
+        let image = heatmap.to_image();
+        let column = |x: usize| {
+            (0..image.height).map(|y| image[(x, y)].x).sum::<Float>() / image.height as Float
+        };
+        (column(0), column(image.width - 1))
+    }
+
+    #[test]
+    fn uneven_cone_samplers_are_flagged_and_even_ones_are_not() {
+        let axis = Vec3::new(0.3, -0.2, 0.9).normalize();
+        let mut rng = rng();
+        let splat = |direction: Vec3| cone_coordinates(&axis, HALF_ANGLE, &direction);
+        let even: Vec<_> = (0..50_000)
+            .map(|_| splat(Vec3::random_in_cone(&mut rng, &axis, HALF_ANGLE)))
+            .collect();
+        // Picking the angle off the axis evenly, instead of its cosine, crowds the middle
+        let u = axis.cross(&Vec3::x()).normalize();
+        let v = axis.cross(&u);
+        let crowded: Vec<_> = (0..50_000)
+            .map(|_| {
+                let theta = rng.gen::<Float>() * HALF_ANGLE;
+                let phi = TAU * rng.gen::<Float>();
+                splat((u * phi.cos() + v * phi.sin()) * theta.sin() + axis * theta.cos())
+            })
+            .collect();
+
+        let even = Heatmap::uniform(even, LIGHT_CELLS);
+        let crowded = Heatmap::uniform(crowded, LIGHT_CELLS);
+        let uniformity = even.uniformity().unwrap();
+        assert!((uniformity - 1.0).abs() < 0.15, "{}", uniformity);
+        assert_eq!(even.clustered(), Some(false));
+        assert!(crowded.uniformity().unwrap() > 10.0 * uniformity);
+        assert_eq!(crowded.clustered(), Some(true));
+
+        // Mid grey across the even one, and bright near the axis and dark at the rim in the other
+        let (middle, rim) = edge_levels(&even);
+        assert!((middle - 0.5).abs() < 0.05 && (rim - 0.5).abs() < 0.05);
+        let (middle, rim) = edge_levels(&crowded);
+        assert!(middle > 0.9 && rim < 0.4, "{} {}", middle, rim);
+    }
+
+    #[test]
+    fn sky_samples_are_checked_against_their_own_density() {
+        let radiance = |direction: &Vec3| Vec3::repeat(0.2 + 2.0 * direction.z.max(0.0).powi(2));
+        let importance = SkyImportance::new(radiance);
+        let mut rng = rng();
+        let splats: Vec<SkySplat> = (0..200_000)
+            .map(|_| {
+                let (direction, pdf) = importance.sample(&mut rng);
+                SkySplat { direction, pdf }
+            })
+            .collect();
+        let matching = Heatmap::sky(&splats);
+        assert_eq!(
+            matching.clustered(),
+            Some(false),
+            "{:?}",
+            matching.uniformity()
+        );
+
+        // The same directions, claimed to be spread evenly over the sphere
+        let even_pdf = 1.0 / (4.0 * PI);
+        let claimed: Vec<SkySplat> = splats
+            .iter()
+            .map(|splat| SkySplat {
+                pdf: even_pdf,
+                ..*splat
+            })
+            .collect();
+        let mismatched = Heatmap::sky(&claimed);
+        assert_eq!(mismatched.clustered(), Some(true));
+        assert!(mismatched.uniformity().unwrap() > 10.0 * matching.uniformity().unwrap());
+    }
+
+    #[test]
+    fn too_few_splats_are_not_judged() {
+        let heatmap = Heatmap::uniform([(0.1, 0.1), (0.9, 0.9)], LIGHT_CELLS);
+        assert_eq!(heatmap.uniformity(), None);
+        assert_eq!(heatmap.clustered(), None);
+    }
+
+    #[test]
+    fn reservoirs_keep_an_even_sample_in_bounded_memory() {
+        let mut reservoir = Reservoir::new(2_000);
+        for i in 0..200_000u32 {
+            reservoir.push(i);
+        }
+        assert_eq!(reservoir.seen(), 200_000);
+        assert_eq!(reservoir.items().len(), 2_000);
+        // As many from each quarter of the stream as any other
+        let heatmap = Heatmap::uniform(
+            reservoir
+                .items()
+                .iter()
+                .map(|&i| (i as Float / 200_000.0, 0.5)),
+            4,
+        );
+        let quarters: Vec<Float> = heatmap.counts[8..12].to_vec();
+        for count in &quarters {
+            assert!((count - 500.0).abs() < 80.0, "{:?}", quarters);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    camera::{self, Camera},
+    hittable::World,
+    postprocess::PostProcess,
+    tiles::{TileRenderer, DEFAULT_TILE_SIZE},
+    validation,
+};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Why a streaming render couldn't be done
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    /// A step that needs pixels from the whole frame, which a streaming render never has
+    NeedsWholeFrame(&'static str),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Io(err) => write!(f, "failed to write streamed render: {}", err),
+            StreamError::NeedsWholeFrame(step) => write!(
+                f,
+                "{} needs the whole frame, so it can't be used with a streaming render",
+                step
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+    fn from(err: io::Error) -> Self {
+        StreamError::Io(err)
+    }
+}
+
+/// A binary PPM written a tile at a time. The header goes first and every row has a fixed place
+/// after it, so tiles can be written in any order and none of the image is kept in memory.
+pub struct TiledPpmWriter {
+    file: File,
+    header_len: u64,
+    width: usize,
+    height: usize,
+}
+
+impl TiledPpmWriter {
+    /// Creates the file at `path` with room for a `width` by `height` image, with each
+    /// `metadata` line as a comment in its header
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        metadata: &[String],
+    ) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        let header = camera::ppm_header(width, height, metadata);
+        file.write_all(header.as_bytes())?;
+        let header_len = header.len() as u64;
+        // Sparse where the file system allows it, so pixels only take space once written
+        file.set_len(header_len + (width * height * 3) as u64)?;
+        Ok(TiledPpmWriter {
+            file,
+            header_len,
+            width,
+            height,
+        })
+    }
+
+    /// Writes the 8-bit RGB pixels of the tile covering `xs` and `ys`, row by row
+    pub fn write_tile(&mut self, xs: Range<usize>, ys: Range<usize>, rgb: &[u8]) -> io::Result<()> {
+        assert!(xs.end <= self.width && ys.end <= self.height);
+        let row_bytes = xs.len() * 3;
+        for (y, row) in ys.zip(rgb.chunks_exact(row_bytes)) {
+            let offset = self.header_len + ((y * self.width + xs.start) * 3) as u64;
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(row)?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure everything written is on disk
+    pub fn finish(self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// How a streaming render went
+#[derive(Debug, Clone)]
+pub struct StreamReport {
+    pub tiles: usize,
+    pub tile_size: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for StreamReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "streamed {} tiles of {}x{} pixels in {:.1} seconds",
+            self.tiles,
+            self.tile_size,
+            self.tile_size,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Where a streaming render to `path` is written until it's done, so a render that's cut short
+/// never leaves something that looks finished
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// Renders what `camera` sees a tile at a time, each to its full sample count, and writes each
+/// batch of tiles to `path` as soon as it's done, with `post_process` applied. Only the tiles
+/// being rendered are ever in memory, however large the image. A path ending in `.png` is
+/// converted from the finished PPM at the end, which does hold the whole 8-bit image once.
+///
+/// Lens flare and denoising need every pixel at once, so they can't be streamed. Tonemapping and grain only depend on each pixel, so a seeded camera gives the same pixels as
+/// rendering the whole image in memory and post-processing it.
+pub fn render_streaming(
+    camera: &Camera,
+    world: &World,
+    post_process: &PostProcess,
+    metadata: Vec<String>,
+    path: &Path,
+    tiles: Option<&TileRenderer>,
+) -> Result<StreamReport, StreamError> {
+    if post_process.flare.is_some() {
+        return Err(StreamError::NeedsWholeFrame("lens flare"));
+    }
+    let start = Instant::now();
+    let (width, height) = (camera.image_width, camera.image_height);
+    let tile_size = DEFAULT_TILE_SIZE;
+    let mut header = camera.image_from_pixels(Vec::new()).metadata;
+    header.extend(metadata);
+    header.extend(post_process.metadata());
+    header.push(format!("streamed: {}x{} tiles", tile_size, tile_size));
+
+    let partial = partial_path(path);
+    let mut writer = TiledPpmWriter::create(&partial, width, height, &header)?;
+    let columns = width.div_ceil(tile_size);
+    let count = columns * height.div_ceil(tile_size);
+    let tile = |index: usize| {
+        let (x, y) = ((index % columns) * tile_size, (index / columns) * tile_size);
+        (
+            x..(x + tile_size).min(width),
+            y..(y + tile_size).min(height),
+        )
+    };
+    // Enough tiles at once to keep every thread busy, in rows so writes are mostly sequential
+    let batch = tiles.map_or_else(rayon::current_num_threads, |tiles| tiles.threads());
+    let progress = ProgressBar::new(count as u64);
+    let render_batch = |indices: Range<usize>| -> Vec<Vec<u8>> {
+        indices
+            .into_par_iter()
+            .map(|index| {
+                let (xs, ys) = tile(index);
+                let mut rgb = Vec::with_capacity(xs.len() * ys.len() * 3);
+                for y in ys {
+                    for x in xs.clone() {
+                        let color = camera.render_pixel(world, x, y, camera.samples_per_pixel());
+                        let color = post_process.apply_pixel(color, x, y);
+                        validation::enter_pixel(x, y);
+                        rgb.extend_from_slice(&camera::rgb8(&color, camera.gamma));
+                    }
+                }
+                rgb
+            })
+            .collect()
+    };
+    for first in (0..count).step_by(batch) {
+        let indices = first..(first + batch).min(count);
+        let rendered = match tiles {
+            Some(tiles) => tiles.install(|| render_batch(indices.clone())),
+            None => render_batch(indices.clone()),
+        };
+        let rendered_count = indices.len();
+        for (index, rgb) in indices.zip(rendered) {
+            let (xs, ys) = tile(index);
+            writer.write_tile(xs, ys, &rgb)?;
+        }
+        progress.inc(rendered_count as u64);
+    }
+    progress.finish();
+    writer.finish()?;
+
+    let is_png = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if is_png {
+        let ppm = io::BufReader::new(File::open(&partial)?);
+        let image = image::load(ppm, image::ImageFormat::Pnm).map_err(io::Error::other)?;
+        image
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(io::Error::other)?;
+        fs::remove_file(&partial)?;
+    } else {
+        fs::rename(&partial, path)?;
+    }
+    Ok(StreamReport {
+        tiles: count,
+        tile_size,
+        elapsed: start.elapsed(),
+    })
+}
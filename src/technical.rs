@@ -0,0 +1,139 @@
+use crate::{
+    camera::{Camera, Integrator},
+    intersection::Intersection,
+    material::Material,
+    material_override::{MaterialOverride, CLAY_ALBEDO},
+    postprocess::PostProcess,
+    texture::Texture,
+    vec3::{Ray, Vec3},
+};
+
+/// What camera rays that miss everything see: a flat white dome, so silhouettes stand out
+pub fn background() -> Vec3 {
+    Vec3::repeat(1.0)
+}
+
+/// Back faces, which a mesh with its winding reversed shows from outside
+pub fn back_face() -> Vec3 {
+    Vec3::new(1.0, 0.0, 1.0)
+}
+
+/// Hits with a position, distance or normal that isn't a number, or a normal with no length,
+/// which usually come from degenerate triangles
+pub fn degenerate() -> Vec3 {
+    Vec3::new(1.0, 0.0, 0.0)
+}
+
+/// Sets `camera` up for the technical preset, the first look at a misbehaving asset: the
+/// [`Integrator::Technical`] integrator, every surface [`MaterialOverride::clay`] and no
+/// exposure, tonemap, grain or flare to change the greys
+pub fn preset(camera: &mut Camera) {
+    camera.integrator = Integrator::Technical;
+    camera.material_override = Some(MaterialOverride::clay());
+    camera.post_process = post_process(&camera.post_process);
+}
+
+/// `post_process` with everything that changes colors turned off, as the technical preset has it
+pub fn post_process(post_process: &PostProcess) -> PostProcess {
+    PostProcess {
+        frame: post_process.frame,
+        ..PostProcess::default()
+    }
+}
+
+/// Shades what a camera `ray` hit first by how squarely it faces the camera, as if lit by a
+/// headlight, so form reads without any lights or sky. Hits are the diffuse albedo of their
+/// material, or clay grey for materials without one, unless they're a [`back_face`] or
+/// [`degenerate`]. Misses are the [`background`]. Nothing is random, so one sample is as good as
+/// many.
+pub fn radiance(ray: &Ray, hit: Option<Intersection>) -> Vec3 {
+    let Some(hit) = hit else {
+        return background();
+    };
+    let finite = |v: &Vec3| v.iter().all(|c| c.is_finite());
+    if !hit.t.is_finite() || !finite(&hit.point) || !finite(&hit.normal) || hit.normal.norm() < 0.5
+    {
+        return degenerate();
+    }
+    if !hit.is_front_face {
+        return back_face();
+    }
+    let albedo = match hit.material {
+        Material::Lambertian(lambertian) => lambertian.texture.value(hit.uv.x, hit.uv.y, hit.point),
+        _ => Vec3::repeat(CLAY_ALBEDO),
+    };
+    let headlight = ray.direction.normalize().dot(&hit.normal).abs();
+    albedo * headlight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Sphere, Triangle, World},
+        material::{Dielectric, DiffuseLight, Lambertian, Metal},
+        vec3::Point3,
+    };
+    use std::sync::Arc;
+
+    /// A red ball, a gold one and a light, with a glass pane between them wound facing away
+    fn scene() -> (Camera, World) {
+        let red = Arc::new(Lambertian::new_rgb_solid(0.8, 0.1, 0.1).into());
+        let gold = Arc::new(Metal::new_solid(Vec3::new(0.9, 0.7, 0.2), None).into());
+        let light = Arc::new(DiffuseLight::new_rgb_solid(4.0, 3.0, 1.0).into());
+        let glass: Arc<Material> = Arc::new(Dielectric::new(1.5).into());
+        let p = |x, z| Point3::new(x, -1.0, z);
+        let world = World::build(vec![
+            Sphere::new(Vec3::new(-1.2, 0.0, 0.0), 0.7, red).into(),
+            Sphere::new(Vec3::new(1.2, 0.0, 0.0), 0.7, gold).into(),
+            Sphere::new(Vec3::new(0.0, 0.5, -1.0), 0.4, light).into(),
+            Triangle::new(p(-0.5, 0.8), p(-0.5, 1.6), p(0.5, 0.8), glass.clone()).into(),
+            Triangle::new(p(-0.5, 1.6), p(0.5, 1.6), p(0.5, 0.8), glass).into(),
+        ]);
+        let camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -5.0, 0.0))
+            .with_look_at(Vec3::zeros())
+            .with_vertical_fov(60.0)
+            .with_resolution(48, 32)
+            .with_samples(1)
+            .with_max_depth(8)
+            .build()
+            .unwrap();
+        (camera, world)
+    }
+
+    #[test]
+    fn the_technical_preset_renders_grey_and_magenta() {
+        let (mut camera, world) = scene();
+        let pane = camera.pixel_of(&Point3::new(0.0, -1.0, 1.2)).unwrap();
+        let red = camera.pixel_of(&Point3::new(-1.2, -0.7, 0.0)).unwrap();
+        // Without the preset, the pane is glass and the red ball red
+        let plain = camera.render_image(&world);
+        assert_ne!(plain[pane], back_face());
+        assert!(plain[red].x > 2.0 * plain[red].y);
+
+        preset(&mut camera);
+        let image = camera.render_image(&world);
+        let magenta = image
+            .pixels
+            .iter()
+            .filter(|pixel| **pixel == back_face())
+            .count();
+        assert_eq!(image[pane], back_face());
+        assert!(magenta > 10, "{} magenta pixels", magenta);
+        // Everything else, the light included, is a grey from black to the white background
+        let mut greys = 0;
+        for pixel in image.pixels.iter().filter(|pixel| **pixel != back_face()) {
+            assert!(pixel.x == pixel.y && pixel.y == pixel.z, "{}", pixel);
+            assert!((0.0..=1.0).contains(&pixel.x), "{}", pixel);
+            greys += usize::from(pixel.x < 1.0);
+        }
+        assert!(greys > 100, "{} grey pixels", greys);
+        // Nearly head on, clay is nearly its albedo
+        assert!((image[red].x - CLAY_ALBEDO).abs() < 0.03, "{}", image[red]);
+
+        let post = post_process(&camera.post_process);
+        assert!(post.tonemap.is_none() && post.grain.is_none() && post.flare.is_none());
+        assert_eq!(post.exposure, 0.0);
+    }
+}
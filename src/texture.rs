@@ -1,12 +1,32 @@
 use crate::{
-    camera::{Float, Image},
+    camera::{Float, Image, DEFAULT_GAMMA},
+    hittable::{InstancingReport, RejectReport, RepairReport},
+    mesh_analysis::MeshAnalysis,
+    perlin::Perlin,
+    texture_cache::{ContentHash, TextureCache},
     vec3::{Point3, Vec3},
 };
 use enum_dispatch::enum_dispatch;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[enum_dispatch(TextureEnum)]
 pub trait Texture {
     fn value(&self, u: Float, v: Float, point: Point3) -> Vec3;
+
+    /// The texture averaged over `footprint` around the lookup, for textures that can work that
+    /// out cheaply. The rest are looked up at its center.
+    fn filtered_value(&self, u: Float, v: Float, point: Point3, footprint: &Footprint) -> Vec3 {
+        let _ = footprint;
+        self.value(u, v, point)
+    }
+
+    /// The texture's color averaged over its area, for when one color has to stand in for it
+    fn average(&self) -> Vec3;
 }
 
 #[enum_dispatch]
@@ -15,6 +35,7 @@ pub enum TextureEnum {
     SolidColor,
     CheckerTexture,
     ImageTexture,
+    NoiseTexture,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +47,10 @@ impl Texture for SolidColor {
     fn value(&self, _u: Float, _v: Float, _point: Point3) -> Vec3 {
         self.color
     }
+
+    fn average(&self) -> Vec3 {
+        self.color
+    }
 }
 
 impl SolidColor {
@@ -40,10 +65,110 @@ impl SolidColor {
     }
 }
 
+/// Grazing angles' cosines are kept above this, so a ray skimming a surface gets a long
+/// footprint instead of an infinite one
+const MIN_FOOTPRINT_COSINE: Float = 1e-4;
+
+/// The patch of surface a texture lookup stands for, like what a pixel covers where its ray hit:
+/// an ellipse around the hit point, as wide as the ray's cone across and stretched along the
+/// surface the more the ray grazes it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Footprint {
+    /// The ellipse's two diameters, in world units
+    pub axes: [Vec3; 2],
+    /// How far the hit point moves per unit of u and of v, zero where the shape doesn't say
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+}
+
+impl Footprint {
+    /// The footprint of a cone `width` across where a ray going in `direction` hits a surface
+    /// with unit `normal`
+    pub fn new(width: Float, direction: &Vec3, normal: &Vec3, dpdu: Vec3, dpdv: Vec3) -> Self {
+        let direction = direction.normalize();
+        let cosine = direction.dot(normal).abs().max(MIN_FOOTPRINT_COSINE);
+        let along = direction - normal * direction.dot(normal);
+        let along = if along.norm_squared() > 0.0 {
+            along.normalize()
+        } else {
+            // Head on, where any direction in the surface will do
+            let helper = if normal.x.abs() < 0.9 {
+                Vec3::new(1.0, 0.0, 0.0)
+            } else {
+                Vec3::new(0.0, 1.0, 0.0)
+            };
+            normal.cross(&helper).normalize()
+        };
+        let across = normal.cross(&along);
+        Footprint {
+            axes: [across * width, along * (width / cosine)],
+            dpdu,
+            dpdv,
+        }
+    }
+
+    /// How far the footprint reaches along unit `axis`, from one side to the other
+    pub fn width_along(&self, axis: &Vec3) -> Float {
+        self.axes[0].dot(axis).hypot(self.axes[1].dot(axis))
+    }
+
+    /// How far the footprint reaches in u and in v, or `None` where the shape doesn't say how
+    /// its UVs are laid out
+    pub fn uv_widths(&self) -> Option<(Float, Float)> {
+        // Each axis in UV units, from the tangents' Gram matrix
+        let (uu, uv, vv) = (
+            self.dpdu.dot(&self.dpdu),
+            self.dpdu.dot(&self.dpdv),
+            self.dpdv.dot(&self.dpdv),
+        );
+        let determinant = uu * vv - uv * uv;
+        if determinant <= Float::EPSILON * uu * vv {
+            return None;
+        }
+        let [a, b] = self.axes.map(|axis| {
+            let (pu, pv) = (axis.dot(&self.dpdu), axis.dot(&self.dpdv));
+            (
+                (vv * pu - uv * pv) / determinant,
+                (uu * pv - uv * pu) / determinant,
+            )
+        });
+        Some((a.0.hypot(b.0), a.1.hypot(b.1)))
+    }
+}
+
+/// Antiderivative of a square wave that's 1 on `[0, 1)` and -1 on `[1, 2)`, which is a
+/// triangle wave between 0 and 1
+fn square_wave_integral(x: Float) -> Float {
+    1.0 - (1.0 - x.rem_euclid(2.0)).abs()
+}
+
+/// A square wave that's 1 on `[0, 1)` and -1 on `[1, 2)`, averaged over a box `width` wide
+/// around `x`. Goes to 0 as the box takes in many periods.
+fn filtered_square_wave(x: Float, width: Float) -> Float {
+    if width <= 0.0 || !width.is_finite() {
+        return if x.rem_euclid(2.0) < 1.0 { 1.0 } else { -1.0 };
+    }
+    let half = width / 2.0;
+    (square_wave_integral(x + half) - square_wave_integral(x - half)) / width
+}
+
+/// Where a [`CheckerTexture`]'s grid lives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckerSpace {
+    /// A 3D grid that objects are cut out of, so checks don't follow their surfaces
+    #[default]
+    World,
+    /// A 2D grid over the surface's UVs, laid out by the shape's [`crate::uv::UvMode`]
+    Uv,
+}
+
 #[derive(Debug)]
 pub struct CheckerTexture {
     /// Larger scale values correspond to larger checker sizes
     scale_inverted: Float,
+    space: CheckerSpace,
+    /// Averages the checks over the lookup's footprint, see [`CheckerTexture::new_filtered`]
+    filtered: bool,
     even_texture: Box<TextureEnum>, // Boxed to avoid infinite size with recursion
     odd_texture: Box<TextureEnum>,
 }
@@ -52,58 +177,489 @@ impl CheckerTexture {
     pub fn new(scale: Float, even_texture: TextureEnum, odd_texture: TextureEnum) -> Self {
         CheckerTexture {
             scale_inverted: 1.0 / scale,
+            space: CheckerSpace::World,
+            filtered: false,
             even_texture: Box::new(even_texture),
             odd_texture: Box::new(odd_texture),
         }
     }
+
+    /// Returns a checker over the surface's UVs, with checks `scale` wide in UV units
+    pub fn new_uv(scale: Float, even_texture: TextureEnum, odd_texture: TextureEnum) -> Self {
+        CheckerTexture {
+            space: CheckerSpace::Uv,
+            ..CheckerTexture::new(scale, even_texture, odd_texture)
+        }
+    }
+
+    /// Returns a checker that averages its checks over the footprint of each lookup in closed
+    /// form, so far away, where a pixel covers many checks, it fades to the even and odd
+    /// textures' average instead of shimmering. Shapes that don't give UV tangents get a hard
+    /// UV checker either way.
+    pub fn new_filtered(scale: Float, even_texture: TextureEnum, odd_texture: TextureEnum) -> Self {
+        CheckerTexture::new(scale, even_texture, odd_texture).with_filtering(true)
+    }
+
+    pub fn with_filtering(mut self, filtered: bool) -> Self {
+        self.filtered = filtered;
+        self
+    }
+
+    pub fn is_filtered(&self) -> bool {
+        self.filtered
+    }
+
+    pub fn space(&self) -> CheckerSpace {
+        self.space
+    }
+
+    /// Size of each check
+    pub fn scale(&self) -> Float {
+        1.0 / self.scale_inverted
+    }
+
+    pub fn even_texture(&self) -> &TextureEnum {
+        &self.even_texture
+    }
+
+    pub fn odd_texture(&self) -> &TextureEnum {
+        &self.odd_texture
+    }
 }
 
 impl Texture for CheckerTexture {
     fn value(&self, u: Float, v: Float, point: Point3) -> Vec3 {
-        let x_int = (self.scale_inverted * point.x).floor() as i32;
-        let y_int = (self.scale_inverted * point.y).floor() as i32;
-        let z_int = (self.scale_inverted * point.z).floor() as i32;
-
-        let is_even = (x_int + y_int + z_int) % 2 == 0;
+        let is_even = match self.space {
+            CheckerSpace::World => {
+                let x_int = (self.scale_inverted * point.x).floor() as i32;
+                let y_int = (self.scale_inverted * point.y).floor() as i32;
+                let z_int = (self.scale_inverted * point.z).floor() as i32;
+                (x_int + y_int + z_int) % 2 == 0
+            }
+            CheckerSpace::Uv => {
+                let u_int = (self.scale_inverted * u).floor() as i32;
+                let v_int = (self.scale_inverted * v).floor() as i32;
+                (u_int + v_int) % 2 == 0
+            }
+        };
         if is_even {
             self.even_texture.value(u, v, point)
         } else {
             self.odd_texture.value(u, v, point)
         }
     }
+
+    /// Box filters the checks over the footprint's reach along each of the grid's axes. The
+    /// checks are a product of one square wave per axis, so the filtered checks are the product
+    /// of each filtered wave.
+    fn filtered_value(&self, u: Float, v: Float, point: Point3, footprint: &Footprint) -> Vec3 {
+        if !self.filtered {
+            return self.value(u, v, point);
+        }
+        let waves = match self.space {
+            CheckerSpace::World => [Vec3::x(), Vec3::y(), Vec3::z()]
+                .iter()
+                .zip([point.x, point.y, point.z])
+                .map(|(axis, x)| {
+                    let width = footprint.width_along(axis);
+                    filtered_square_wave(x * self.scale_inverted, width * self.scale_inverted)
+                })
+                .product::<Float>(),
+            CheckerSpace::Uv => {
+                let (u_width, v_width) = footprint.uv_widths().unwrap_or((0.0, 0.0));
+                filtered_square_wave(u * self.scale_inverted, u_width * self.scale_inverted)
+                    * filtered_square_wave(v * self.scale_inverted, v_width * self.scale_inverted)
+            }
+        };
+        let even = (1.0 + waves) / 2.0;
+        let mut color = Vec3::zeros();
+        if even > 0.0 {
+            color += self.even_texture.filtered_value(u, v, point, footprint) * even;
+        }
+        if even < 1.0 {
+            color += self.odd_texture.filtered_value(u, v, point, footprint) * (1.0 - even);
+        }
+        color
+    }
+
+    /// Checks are half even and half odd
+    fn average(&self) -> Vec3 {
+        (self.even_texture.average() + self.odd_texture.average()) / 2.0
+    }
+}
+
+/// How a [`NoiseTexture`] turns Perlin noise into brightness
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoiseStyle {
+    /// Smooth rolling blotches of fractal Brownian motion
+    #[default]
+    Smooth,
+    /// Billowy turbulence, dark along the creases where the noise crosses zero
+    Turbulence,
+    /// Veins of a sine wave along z, bent by turbulence like marble
+    Marble,
+}
+
+/// A solid 3D texture of Perlin noise, see [`Perlin`], looked up at the hit point rather than
+/// its UVs so it runs through objects like they were carved from it
+#[derive(Debug)]
+pub struct NoiseTexture {
+    perlin: Perlin,
+    style: NoiseStyle,
+    /// Frequency of the noise, so larger values give smaller features
+    scale: Float,
+    /// How many octaves of noise are summed, each adding finer detail
+    octaves: usize,
+    /// The color at full brightness
+    color: Vec3,
+}
+
+impl NoiseTexture {
+    /// White noise in `style`, `scale` times as fine as the lattice, summing `octaves`
+    /// octaves of the noise `seed` picks
+    pub fn new(style: NoiseStyle, scale: Float, octaves: usize, seed: u64) -> Self {
+        NoiseTexture {
+            perlin: Perlin::new(seed),
+            style,
+            scale,
+            octaves,
+            color: Vec3::repeat(1.0),
+        }
+    }
+
+    pub fn with_color(mut self, color: Vec3) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn style(&self) -> NoiseStyle {
+        self.style
+    }
+
+    /// Brightness at `point`, from 0 to 1
+    pub fn brightness(&self, point: Point3) -> Float {
+        let scaled = point * self.scale;
+        let brightness = match self.style {
+            NoiseStyle::Smooth => 0.5 * (1.0 + self.perlin.fbm(scaled, self.octaves)),
+            NoiseStyle::Turbulence => 2.0 * self.perlin.turbulence(scaled, self.octaves),
+            NoiseStyle::Marble => {
+                let turbulence = self.perlin.turbulence(point, self.octaves);
+                0.5 * (1.0 + (scaled.z + 10.0 * turbulence).sin())
+            }
+        };
+        brightness.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: Float, _v: Float, point: Point3) -> Vec3 {
+        self.color * self.brightness(point)
+    }
+
+    /// Averages the brightness over a few lattice cells' worth of points, since the noise has
+    /// no closed form average
+    fn average(&self) -> Vec3 {
+        const STEPS: usize = 8;
+        let step = 4.0 / (STEPS as Float * self.scale.abs().max(Float::EPSILON));
+        let mut sum = 0.0;
+        for i in 0..STEPS {
+            for j in 0..STEPS {
+                for k in 0..STEPS {
+                    let point = Vec3::new(i as Float, j as Float, k as Float) * step;
+                    // Off the lattice, where the noise is always zero
+                    sum += self.brightness(point + Vec3::repeat(0.37 * step));
+                }
+            }
+        }
+        self.color * sum / (STEPS * STEPS * STEPS) as Float
+    }
 }
 
 pub struct ImageTexture {
-    pub image: Image,
+    /// Shared with every other texture with the same content, see [`TextureCache`]
+    pub image: Arc<Image>,
+    /// The file the image was loaded from, if it came from one
+    pub source: Option<PathBuf>,
 }
 
 impl std::fmt::Debug for ImageTexture {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ImageTexture")
             .field("image", &"<image data>")
+            .field("source", &self.source)
             .finish()
     }
 }
 
+/// Width and height of the placeholder texture in pixels
+const PLACEHOLDER_SIZE: usize = 64;
+/// Width and height of the placeholder texture's checks in pixels
+const PLACEHOLDER_CHECK_SIZE: usize = 8;
+
 impl ImageTexture {
-    pub fn load_embedded_image(data: &[u8]) -> Image {
-        let img = image::load_from_memory(data).expect("Failed to load image");
-        img.into()
+    /// Decodes an image file held in memory
+    pub fn load_image(data: &[u8]) -> Result<Image, image::ImageError> {
+        Ok(image::load_from_memory(data)?.into())
+    }
+
+    /// Decodes an image compiled into the binary through the global [`TextureCache`], falling
+    /// back to the placeholder if it's corrupt
+    pub fn load_embedded_image(data: &[u8]) -> Arc<Image> {
+        ImageTexture::from_bytes(data)
+            .map(|texture| texture.image)
+            .unwrap_or_else(|err| {
+                println!(
+                    "Warning: failed to load embedded image, using placeholder: {}",
+                    err
+                );
+                ImageTexture::placeholder().image
+            })
     }
 
     pub fn new(image: Image) -> Self {
-        ImageTexture { image }
+        ImageTexture::shared(Arc::new(image))
+    }
+
+    /// Makes a texture out of an image other textures may also use
+    pub fn shared(image: Arc<Image>) -> Self {
+        ImageTexture {
+            image,
+            source: None,
+        }
+    }
+
+    /// Decodes an image file held in memory, sharing the result with every other texture made
+    /// from the same bytes through the global [`TextureCache`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let image = TextureCache::global()
+            .get_or_insert_with(ContentHash::of_bytes(data), || {
+                ImageTexture::load_image(data)
+            })
+            .map_err(|err| err.to_string())?;
+        Ok(ImageTexture::shared(image))
+    }
+
+    /// Loads the image file at `path`, remembering where it came from. Files with the same
+    /// content share one image, wherever they are.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|err| err.to_string())?;
+        Ok(ImageTexture {
+            source: Some(path.to_path_buf()),
+            ..ImageTexture::from_bytes(&data)?
+        })
+    }
+
+    /// Returns a magenta and black checkerboard that stands in for textures that failed to load,
+    /// so broken textures are obvious in renders instead of crashing the load
+    pub fn placeholder() -> Self {
+        let magenta = Vec3::new(1.0, 0.0, 1.0);
+        let pixels = (0..PLACEHOLDER_SIZE * PLACEHOLDER_SIZE)
+            .map(|i| {
+                let (x, y) = (i % PLACEHOLDER_SIZE, i / PLACEHOLDER_SIZE);
+                let is_even =
+                    (x / PLACEHOLDER_CHECK_SIZE + y / PLACEHOLDER_CHECK_SIZE).is_multiple_of(2);
+                if is_even {
+                    magenta
+                } else {
+                    Vec3::zeros()
+                }
+            })
+            .collect();
+        ImageTexture::new(Image {
+            pixels,
+            width: PLACEHOLDER_SIZE,
+            height: PLACEHOLDER_SIZE,
+            gamma: DEFAULT_GAMMA,
+            metadata: Vec::new(),
+            alpha: None,
+        })
+    }
+}
+
+/// A texture that couldn't be loaded and was replaced with the placeholder
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureLoadFailure {
+    /// The file the texture came from, and which image in it if there's more than one
+    pub source: String,
+    pub reason: String,
+    /// Name of the material that uses the texture, if it has one
+    pub material: Option<String>,
+}
+
+/// How many distinct images a scene's textures were decoded from, and how often they were used
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextureSharingReport {
+    /// Images decoded, once each
+    pub decoded: usize,
+    /// Materials using them
+    pub references: usize,
+    /// Pixels the references beyond the first to each image would have copied
+    pub pixels_saved: usize,
+}
+
+impl TextureSharingReport {
+    /// Records `image`, decoded once and used by `references` materials
+    pub fn record(&mut self, image: &Image, references: usize) {
+        self.decoded += 1;
+        self.references += references;
+        self.pixels_saved += image.pixels.len() * references.saturating_sub(1);
+    }
+
+    pub fn merge(&mut self, other: &TextureSharingReport) {
+        self.decoded += other.decoded;
+        self.references += other.references;
+        self.pixels_saved += other.pixels_saved;
+    }
+
+    /// Memory the copies would have taken
+    pub fn bytes_saved(&self) -> usize {
+        self.pixels_saved * std::mem::size_of::<Vec3>()
+    }
+}
+
+impl fmt::Display for TextureSharingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} texture(s) decoded for {} reference(s), {} deduplicated ({:.1} MB)",
+            self.decoded,
+            self.references,
+            self.references.saturating_sub(self.decoded),
+            self.bytes_saved() as f64 / 1e6
+        )
+    }
+}
+
+/// Problems found while loading a scene that didn't stop it from loading, and how much of it
+/// was shared between instances
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub texture_failures: Vec<TextureLoadFailure>,
+    /// Meshes whose winding was repaired or couldn't be fully repaired, by name
+    pub mesh_repairs: Vec<(String, RepairReport)>,
+    /// Meshes with shapes that were left out for being degenerate or not finite, by name
+    pub rejected_shapes: Vec<(String, RejectReport)>,
+    /// Analyzed meshes that aren't watertight, by name
+    pub mesh_analyses: Vec<(String, MeshAnalysis)>,
+    /// Cameras and lights that were left out or imported as something else, by node path, with
+    /// what happened to them
+    pub import_notes: Vec<(String, String)>,
+    pub instancing: InstancingReport,
+    pub textures: TextureSharingReport,
+}
+
+impl LoadReport {
+    pub fn is_empty(&self) -> bool {
+        self.texture_failures.is_empty()
+            && self.mesh_repairs.is_empty()
+            && self.rejected_shapes.is_empty()
+            && self.mesh_analyses.is_empty()
+            && self.import_notes.is_empty()
+            && self.instancing == InstancingReport::default()
+            && self.textures == TextureSharingReport::default()
+    }
+
+    /// Records a texture failure, ignoring repeats of one that's already recorded
+    pub fn record_texture_failure(&mut self, failure: TextureLoadFailure) {
+        if !self.texture_failures.contains(&failure) {
+            self.texture_failures.push(failure);
+        }
+    }
+
+    /// Records the shapes left out of the mesh `name`, if there were any
+    pub fn record_rejects(&mut self, name: &str, rejects: RejectReport) {
+        if !rejects.is_empty() {
+            self.rejected_shapes.push((name.to_string(), rejects));
+        }
+    }
+
+    /// Adds everything in `other` to this report
+    pub fn merge(&mut self, other: LoadReport) {
+        for failure in other.texture_failures {
+            self.record_texture_failure(failure);
+        }
+        self.mesh_repairs.extend(other.mesh_repairs);
+        self.rejected_shapes.extend(other.rejected_shapes);
+        self.mesh_analyses.extend(other.mesh_analyses);
+        self.import_notes.extend(other.import_notes);
+        self.instancing.merge(&other.instancing);
+        self.textures.merge(&other.textures);
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "Everything loaded without problems");
+        }
+        let mut sections = Vec::new();
+        if !self.texture_failures.is_empty() {
+            let mut section = format!(
+                "{} texture(s) failed to load and were replaced with placeholders:",
+                self.texture_failures.len()
+            );
+            for failure in &self.texture_failures {
+                section += &format!("\n  {}", failure.source);
+                if let Some(material) = &failure.material {
+                    section += &format!(" (used by material '{}')", material);
+                }
+                section += &format!(": {}", failure.reason);
+            }
+            sections.push(section);
+        }
+        if !self.mesh_repairs.is_empty() {
+            let mut section = format!(
+                "{} mesh(es) had inconsistent or open windings:",
+                self.mesh_repairs.len()
+            );
+            for (mesh, repair) in &self.mesh_repairs {
+                section += &format!("\n  {}: {}", mesh, repair);
+            }
+            sections.push(section);
+        }
+        if !self.rejected_shapes.is_empty() {
+            let mut section = format!(
+                "{} mesh(es) had shapes that couldn't be built:",
+                self.rejected_shapes.len()
+            );
+            for (mesh, rejects) in &self.rejected_shapes {
+                section += &format!("\n  {}: {}", mesh, rejects);
+            }
+            sections.push(section);
+        }
+        if !self.mesh_analyses.is_empty() {
+            let mut section = format!(
+                "{} mesh(es) aren't watertight, so glass on them may shade wrongly:",
+                self.mesh_analyses.len()
+            );
+            for (mesh, analysis) in &self.mesh_analyses {
+                section += &format!("\n  {}: {}", mesh, analysis);
+            }
+            sections.push(section);
+        }
+        if !self.import_notes.is_empty() {
+            let mut section = format!(
+                "{} camera(s) and light(s) couldn't be imported as authored:",
+                self.import_notes.len()
+            );
+            for (node, note) in &self.import_notes {
+                section += &format!("\n  {}: {}", node, note);
+            }
+            sections.push(section);
+        }
+        if self.instancing.instances > 0 {
+            sections.push(self.instancing.to_string());
+        }
+        if self.textures.references > 0 {
+            sections.push(self.textures.to_string());
+        }
+        write!(f, "{}", sections.join("\n"))
     }
 }
 
 impl Texture for ImageTexture {
     fn value(&self, u: Float, v: Float, _point: Point3) -> Vec3 {
-        // let r = 0.0..=1.0;
-        // if self.image.height == 0 || self.image.width == 0 || !r.contains(&u) || !r.contains(&v) {
-        // println!("Error: (u, v)=({}, {}) out of bounds", u, v);
-        // return Vec3::new(1.0, 0.0, 0.0); // Debug color
-        // }
-
         // Clamp input coords to [0, 1]
         let u = u.clamp(0.0, 1.0);
         let v = v.clamp(0.0, 1.0);
@@ -111,9 +667,175 @@ impl Texture for ImageTexture {
         let x = (u * (self.image.width - 1) as Float) as usize;
         let y = (v * (self.image.height - 1) as Float) as usize;
 
-        // Debug view:
-        // Vec3::new(u, v, (1.0 - u - v).max(0.0))
-
         self.image[(x, y)]
     }
+
+    fn average(&self) -> Vec3 {
+        let sum: Vec3 = self.image.pixels.iter().sum();
+        sum / self.image.pixels.len().max(1) as Float
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn noise_textures_stay_between_black_and_their_color() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let color = Vec3::new(0.9, 0.5, 0.2);
+        for style in [
+            NoiseStyle::Smooth,
+            NoiseStyle::Turbulence,
+            NoiseStyle::Marble,
+        ] {
+            for (scale, octaves) in [(0.5, 1), (4.0, 7), (40.0, 3)] {
+                let texture = NoiseTexture::new(style, scale, octaves, 6).with_color(color);
+                let mut brightest: Float = 0.0;
+                for _ in 0..4000 {
+                    let point = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 10.0;
+                    let brightness = texture.brightness(point);
+                    assert!(
+                        (0.0..=1.0).contains(&brightness),
+                        "{:?} {}",
+                        style,
+                        brightness
+                    );
+                    assert_eq!(
+                        texture.value(rng.gen(), rng.gen(), point),
+                        color * brightness
+                    );
+                    brightest = brightest.max(brightness);
+                }
+                // Not stuck at black
+                assert!(brightest > 0.3, "{:?} at scale {}", style, scale);
+                let average = texture.average();
+                assert!(
+                    (0.0..=1.0).contains(&(average.x / color.x)),
+                    "{:?}",
+                    average
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn noise_textures_with_the_same_seed_match() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let a = NoiseTexture::new(NoiseStyle::Marble, 4.0, 7, 11);
+        let b = NoiseTexture::new(NoiseStyle::Marble, 4.0, 7, 11);
+        let other = NoiseTexture::new(NoiseStyle::Marble, 4.0, 7, 12);
+        let points: Vec<Point3> = (0..1000)
+            .map(|_| Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 5.0)
+            .collect();
+        assert!(points
+            .iter()
+            .all(|point| a.brightness(*point) == b.brightness(*point)));
+        assert!(points
+            .iter()
+            .any(|point| a.brightness(*point) != other.brightness(*point)));
+    }
+
+    /// A unit checker of white and black, filtered or not
+    fn black_and_white(filtered: bool) -> CheckerTexture {
+        let white = SolidColor::new(Vec3::repeat(1.0)).into();
+        let black = SolidColor::new(Vec3::zeros()).into();
+        CheckerTexture::new(1.0, white, black).with_filtering(filtered)
+    }
+
+    /// Looking across a checkered floor from a unit above it, how far each lookup's brightness
+    /// is from the checks' true average over its footprint, as the root mean square over
+    /// lookups `distances` away with pixels `pixel_angle` radians across
+    fn footprint_error(
+        texture: &CheckerTexture,
+        distances: std::ops::Range<Float>,
+        pixel_angle: Float,
+    ) -> Float {
+        let mut rng = StdRng::seed_from_u64(3);
+        let eye = Vec3::new(0.0, 0.0, 1.0);
+        let squares: Float = (0..200)
+            .map(|_| {
+                let point = Vec3::new(
+                    rng.gen_range(distances.clone()),
+                    rng.gen_range(-5.0..5.0),
+                    0.0,
+                );
+                let direction = point - eye;
+                let width = direction.norm() * pixel_angle;
+                let footprint =
+                    Footprint::new(width, &direction, &Vec3::z(), Vec3::zeros(), Vec3::zeros());
+                // The checks averaged over a grid of points inside the footprint's ellipse
+                let (mut sum, mut count) = (0.0, 0.0);
+                for i in 0..64 {
+                    for j in 0..64 {
+                        let (s, t) = (
+                            (i as Float + 0.5) / 32.0 - 1.0,
+                            (j as Float + 0.5) / 32.0 - 1.0,
+                        );
+                        if s * s + t * t <= 1.0 {
+                            let inside =
+                                point + (footprint.axes[0] * s + footprint.axes[1] * t) / 2.0;
+                            sum += texture.value(0.0, 0.0, inside).x;
+                            count += 1.0;
+                        }
+                    }
+                }
+                (texture.filtered_value(0.0, 0.0, point, &footprint).x - sum / count).powi(2)
+            })
+            .sum();
+        (squares / 200.0).sqrt()
+    }
+
+    #[test]
+    fn filtered_checkers_alias_less_far_away() {
+        // Pixels a milliradian across see dozens of checks at a grazing 100 units or more
+        let pixel_angle = 1e-3;
+        let filtered = footprint_error(&black_and_white(true), 100.0..400.0, pixel_angle);
+        let unfiltered = footprint_error(&black_and_white(false), 100.0..400.0, pixel_angle);
+        assert!(unfiltered > 0.3, "{}", unfiltered);
+        assert!(
+            filtered < unfiltered / 4.0,
+            "{} vs {}",
+            filtered,
+            unfiltered
+        );
+
+        // Close up, where a pixel covers a sliver of a check, both are nearly right
+        let filtered = footprint_error(&black_and_white(true), 2.0..4.0, pixel_angle);
+        let unfiltered = footprint_error(&black_and_white(false), 2.0..4.0, pixel_angle);
+        assert!(
+            filtered < 0.01 && unfiltered < 0.05,
+            "{} and {}",
+            filtered,
+            unfiltered
+        );
+    }
+
+    #[test]
+    fn filtered_checkers_fade_to_their_average() {
+        let checker = black_and_white(true);
+        let point = Vec3::new(0.3, 0.7, 0.0);
+        let huge = Footprint::new(
+            1e3,
+            &Vec3::new(1.0, 0.0, -1.0),
+            &Vec3::z(),
+            Vec3::zeros(),
+            Vec3::zeros(),
+        );
+        let value = checker.filtered_value(0.0, 0.0, point, &huge);
+        assert!((value - checker.average()).abs().max() < 1e-3, "{}", value);
+        // A footprint of nothing is a point lookup
+        let none = Footprint::new(
+            0.0,
+            &Vec3::new(1.0, 0.0, -1.0),
+            &Vec3::z(),
+            Vec3::zeros(),
+            Vec3::zeros(),
+        );
+        assert_eq!(
+            checker.filtered_value(0.0, 0.0, point, &none),
+            checker.value(0.0, 0.0, point)
+        );
+    }
 }
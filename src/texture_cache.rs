@@ -0,0 +1,440 @@
+use crate::{
+    camera::{Float, Image},
+    vec3::Vec3,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt, fs,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// Identifies an image by what's in it rather than where it came from, so the same image loaded
+/// from different files, assets or scenes is only kept once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash(u64);
+
+impl ContentHash {
+    /// Hashes an encoded image file, like a PNG or JPEG
+    pub fn of_bytes(bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        "encoded".hash(&mut hasher);
+        bytes.hash(&mut hasher);
+        ContentHash(hasher.finish())
+    }
+
+    /// Hashes pixels that were already decoded, like the images glTF files embed
+    pub fn of_pixels(width: usize, height: usize, format: &str, pixels: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        "decoded".hash(&mut hasher);
+        (width, height, format).hash(&mut hasher);
+        pixels.hash(&mut hasher);
+        ContentHash(hasher.finish())
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// How many bytes a decoded image takes up in memory
+pub fn image_bytes(image: &Image) -> usize {
+    image.pixels.len() * size_of::<Vec3>()
+}
+
+/// Counters describing how well the cache is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextureCacheStats {
+    /// Unique images the cache knows about, whether they're in memory or not
+    pub textures: usize,
+    /// Unique images held in memory
+    pub resident: usize,
+    /// Images only held in spill files on disk
+    pub spilled: usize,
+    /// Memory taken up by resident images. Since every copy is shared, this is the total of
+    /// unique images, not of every texture that uses them.
+    pub resident_bytes: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    /// Evicted images that were read back from their spill files
+    pub reloads: usize,
+}
+
+impl fmt::Display for TextureCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} unique texture(s), {} in memory ({:.1} MiB), {} spilled to disk; \
+             {} hit(s), {} miss(es), {} eviction(s), {} reload(s)",
+            self.textures,
+            self.resident,
+            self.resident_bytes as Float / (1024.0 * 1024.0),
+            self.spilled,
+            self.hits,
+            self.misses,
+            self.evictions,
+            self.reloads
+        )
+    }
+}
+
+struct Entry {
+    /// `None` once evicted
+    image: Option<Arc<Image>>,
+    /// Where the image was written when it was evicted in disk-backed mode
+    spill_file: Option<PathBuf>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<ContentHash, Entry>,
+    /// Evicted images are written here to be read back on their next use, instead of being
+    /// forgotten and decoded again
+    spill_directory: Option<PathBuf>,
+    stats: TextureCacheStats,
+}
+
+/// Decoded images shared by every texture in the process that has the same content. Images
+/// stay in memory while any texture uses them; unused ones can be evicted, and optionally
+/// spilled to disk so they don't have to be decoded again.
+#[derive(Default)]
+pub struct TextureCache {
+    state: Mutex<State>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        TextureCache::default()
+    }
+
+    /// The cache every loader in the process shares
+    pub fn global() -> &'static TextureCache {
+        static GLOBAL: OnceLock<TextureCache> = OnceLock::new();
+        GLOBAL.get_or_init(TextureCache::new)
+    }
+
+    /// Makes evicted images spill to files in `directory`, or be forgotten if `None`
+    pub fn set_spill_directory(&self, directory: Option<PathBuf>) -> io::Result<()> {
+        if let Some(directory) = &directory {
+            fs::create_dir_all(directory)?;
+        }
+        self.state.lock().unwrap().spill_directory = directory;
+        Ok(())
+    }
+
+    /// Returns the image with `hash`, calling `decode` to make it only if the cache has never
+    /// seen it or it was evicted without being spilled. Decoding happens outside the lock, so
+    /// loaders on other threads aren't held up by it.
+    pub fn get_or_insert_with<E>(
+        &self,
+        hash: ContentHash,
+        decode: impl FnOnce() -> Result<Image, E>,
+    ) -> Result<Arc<Image>, E> {
+        if let Some(image) = self.get(hash) {
+            return Ok(image);
+        }
+        let image = Arc::new(decode()?);
+        let mut state = self.state.lock().unwrap();
+        state.stats.misses += 1;
+        let entry = state.entries.entry(hash).or_insert_with(|| Entry {
+            image: None,
+            spill_file: None,
+            last_used: Instant::now(),
+        });
+        entry.last_used = Instant::now();
+        // Another thread may have decoded the same image in the meantime
+        let image = entry.image.get_or_insert(image).clone();
+        state.refresh_stats();
+        Ok(image)
+    }
+
+    /// Returns the image with `hash` if it's in memory or can be read back from disk
+    pub fn get(&self, hash: ContentHash) -> Option<Arc<Image>> {
+        let mut state = self.state.lock().unwrap();
+        let State { entries, stats, .. } = &mut *state;
+        let entry = entries.get_mut(&hash)?;
+        entry.last_used = Instant::now();
+        if let Some(image) = &entry.image {
+            stats.hits += 1;
+            return Some(image.clone());
+        }
+        let spill_file = entry.spill_file.clone()?;
+        match read_spill_file(&spill_file) {
+            Ok(image) => {
+                let image = Arc::new(image);
+                entry.image = Some(image.clone());
+                stats.hits += 1;
+                stats.reloads += 1;
+                state.refresh_stats();
+                Some(image)
+            }
+            Err(err) => {
+                println!(
+                    "Warning: failed to reload texture {} from {}, decoding it again: {}",
+                    hash,
+                    spill_file.display(),
+                    err
+                );
+                entry.spill_file = None;
+                None
+            }
+        }
+    }
+
+    /// Evicts every image no texture has used for at least `max_idle`, spilling it to disk if
+    /// there's a spill directory. Images still held by textures, like every one in the current
+    /// `World`, are never evicted. Returns how many were evicted.
+    pub fn evict_unused(&self, max_idle: Duration) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            entries,
+            spill_directory,
+            stats,
+        } = &mut *state;
+        let mut evicted = 0;
+        entries.retain(|hash, entry| {
+            let Some(image) = &entry.image else {
+                return true;
+            };
+            // The cache's own reference is the only one left
+            if Arc::strong_count(image) > 1 || entry.last_used.elapsed() < max_idle {
+                return true;
+            }
+            evicted += 1;
+            let Some(directory) = spill_directory else {
+                return false;
+            };
+            if entry.spill_file.is_none() {
+                let path = directory.join(format!("{}.texture", hash));
+                match write_spill_file(&path, image) {
+                    Ok(()) => entry.spill_file = Some(path),
+                    Err(err) => {
+                        println!(
+                            "Warning: failed to spill texture {} to {}, dropping it: {}",
+                            hash,
+                            path.display(),
+                            err
+                        );
+                        return false;
+                    }
+                }
+            }
+            entry.image = None;
+            true
+        });
+        stats.evictions += evicted;
+        state.refresh_stats();
+        evicted
+    }
+
+    pub fn stats(&self) -> TextureCacheStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+impl State {
+    /// Recounts the stats that describe what's in the cache rather than what happened to it
+    fn refresh_stats(&mut self) {
+        let resident = self
+            .entries
+            .values()
+            .filter_map(|entry| entry.image.as_ref());
+        self.stats.textures = self.entries.len();
+        self.stats.resident = resident.clone().count();
+        self.stats.resident_bytes = resident.map(|image| image_bytes(image)).sum();
+        self.stats.spilled = self.stats.textures - self.stats.resident;
+    }
+}
+
+impl Drop for TextureCache {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|err| err.into_inner());
+        for path in state.entries.values().filter_map(|e| e.spill_file.as_ref()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Writes an image's size, gamma and linear colors as little-endian numbers. Pixel coordinates
+/// aren't written since they follow from the order.
+fn write_spill_file(path: &PathBuf, image: &Image) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(24 + image.pixels.len() * 24);
+    bytes.extend((image.width as u64).to_le_bytes());
+    bytes.extend((image.height as u64).to_le_bytes());
+    bytes.extend(image.gamma.to_le_bytes());
+    for color in &image.pixels {
+        for channel in color.iter() {
+            bytes.extend(channel.to_le_bytes());
+        }
+    }
+    fs::File::create(path)?.write_all(&bytes)
+}
+
+/// The inverse of [`write_spill_file`]
+fn read_spill_file(path: &PathBuf) -> io::Result<Image> {
+    let mut bytes = Vec::new();
+    fs::File::open(path)?.read_to_end(&mut bytes)?;
+    let mut words = bytes
+        .chunks_exact(8)
+        .map(|chunk| <[u8; 8]>::try_from(chunk).unwrap());
+    let mut next = || {
+        words
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "spill file is too short"))
+    };
+    let width = u64::from_le_bytes(next()?) as usize;
+    let height = u64::from_le_bytes(next()?) as usize;
+    let gamma = Float::from_le_bytes(next()?);
+    let mut pixels = Vec::with_capacity(width * height);
+    for _ in 0..width * height {
+        let mut channel = || next().map(Float::from_le_bytes);
+        pixels.push(Vec3::new(channel()?, channel()?, channel()?));
+    }
+    Ok(Image {
+        pixels,
+        width,
+        height,
+        gamma,
+        metadata: Vec::new(),
+        alpha: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch;
+    use crate::texture::ImageTexture;
+
+    /// A `size` by `size` gradient, different for every `seed`
+    fn gradient(size: usize, seed: u8) -> Image {
+        Image {
+            pixels: (0..size * size)
+                .map(|i| Vec3::new((i % size) as Float, (i / size) as Float, seed as Float))
+                .collect(),
+            width: size,
+            height: size,
+            gamma: 2.2,
+            metadata: Vec::new(),
+            alpha: None,
+        }
+    }
+
+    /// Inserts a copy of `image` into `cache`, counting how many times it had to be decoded
+    fn insert(cache: &TextureCache, hash: u64, image: &Image, decoded: &mut usize) -> Arc<Image> {
+        cache
+            .get_or_insert_with(ContentHash(hash), || {
+                *decoded += 1;
+                Ok::<_, ()>(Image {
+                    pixels: image.pixels.clone(),
+                    metadata: Vec::new(),
+                    alpha: None,
+                    ..*image
+                })
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn the_same_file_in_two_places_is_one_image() {
+        let directory = scratch("two-paths");
+        // Content no other test loads into the global cache
+        let png = image::RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8 * 40, y as u8, 77]));
+        let (first, second) = (
+            directory.join("brick.png"),
+            directory.join("copy/brick.png"),
+        );
+        fs::create_dir_all(second.parent().unwrap()).unwrap();
+        png.save(&first).unwrap();
+        fs::copy(&first, &second).unwrap();
+        let (first, second) = (
+            ImageTexture::open(&first).unwrap(),
+            ImageTexture::open(&second).unwrap(),
+        );
+        assert!(Arc::ptr_eq(&first.image, &second.image));
+        assert_ne!(first.source, second.source);
+
+        let other = image::RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8 * 40, y as u8, 78]));
+        other.save(directory.join("other.png")).unwrap();
+        let other = ImageTexture::open(directory.join("other.png")).unwrap();
+        assert!(!Arc::ptr_eq(&first.image, &other.image));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn spilled_images_read_back_the_same() {
+        let directory = scratch("spill");
+        let cache = TextureCache::new();
+        cache.set_spill_directory(Some(directory.clone())).unwrap();
+        let original = gradient(7, 1);
+        let mut decoded = 0;
+        let held = insert(&cache, 1, &original, &mut decoded);
+        // Still used, so it stays
+        assert_eq!(cache.evict_unused(Duration::ZERO), 0);
+        drop(held);
+        assert_eq!(cache.evict_unused(Duration::ZERO), 1);
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.resident, stats.spilled, stats.resident_bytes),
+            (0, 1, 0)
+        );
+
+        let reloaded = insert(&cache, 1, &original, &mut decoded);
+        assert_eq!(
+            decoded, 1,
+            "read back from its spill file rather than decoded again"
+        );
+        assert_eq!(reloaded.pixels, original.pixels);
+        assert_eq!((reloaded.width, reloaded.height), (7, 7));
+        assert_eq!(reloaded.gamma, original.gamma);
+        let stats = cache.stats();
+        assert_eq!((stats.resident, stats.spilled, stats.reloads), (1, 0, 1));
+        drop(cache);
+        // Spill files go with the cache
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn evicted_images_without_a_spill_directory_are_decoded_again() {
+        let cache = TextureCache::new();
+        let original = gradient(4, 2);
+        let mut decoded = 0;
+        drop(insert(&cache, 2, &original, &mut decoded));
+        assert_eq!(cache.evict_unused(Duration::ZERO), 1);
+        assert_eq!(cache.stats().textures, 0);
+        let again = insert(&cache, 2, &original, &mut decoded);
+        assert_eq!(decoded, 2);
+        assert_eq!(again.pixels, original.pixels);
+        // Recently used ones aren't evicted
+        drop(again);
+        assert_eq!(cache.evict_unused(Duration::from_secs(3600)), 0);
+    }
+
+    #[test]
+    fn memory_is_the_total_of_unique_images() {
+        let cache = TextureCache::new();
+        let images = [gradient(8, 3), gradient(16, 4), gradient(32, 5)];
+        let mut decoded = 0;
+        // Three assets, each with textures using the same few images
+        let textures: Vec<_> = [[0, 1], [1, 2], [0, 2]]
+            .iter()
+            .flat_map(|asset| asset.iter().chain(asset))
+            .map(|&i| insert(&cache, 10 + i as u64, &images[i], &mut decoded))
+            .collect();
+        assert_eq!(textures.len(), 12);
+        assert_eq!(decoded, 3);
+        let stats = cache.stats();
+        let unique: usize = images.iter().map(image_bytes).sum();
+        assert_eq!(stats.resident_bytes, unique);
+        assert_eq!((stats.textures, stats.resident), (3, 3));
+        assert_eq!((stats.hits, stats.misses), (9, 3));
+    }
+}
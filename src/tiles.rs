@@ -0,0 +1,377 @@
+use crate::{
+    camera::{Camera, Float, Image},
+    convergence::PixelMoments,
+    hittable::World,
+    vec3::Vec3,
+};
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::{ops::Range, sync::Mutex};
+
+/// Width and height of the square tiles the image is split into, in pixels
+pub const DEFAULT_TILE_SIZE: usize = 16;
+
+/// How renders are spread over threads. Meant for machines with many cores, where one shared
+/// pool and buffer make threads fight over memory.
+#[derive(Debug, Clone)]
+pub struct ExecutionOptions {
+    /// How many threads render, or `None` for one per core
+    pub threads: Option<usize>,
+    /// Pins each thread to its own core, so its tiles stay in that core's caches and memory.
+    /// Only works on Linux when built with the `affinity` feature.
+    pub affinity: bool,
+    pub tile_size: usize,
+}
+
+impl Default for ExecutionOptions {
+    fn default() -> Self {
+        ExecutionOptions {
+            threads: None,
+            affinity: false,
+            tile_size: DEFAULT_TILE_SIZE,
+        }
+    }
+}
+
+/// Running sums of every sample each pixel has gotten, so sweeps with different sample counts
+/// average out correctly. Samples added with their [`PixelMoments`] also count toward each
+/// pixel's variance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accumulation {
+    pub width: usize,
+    pub height: usize,
+    sums: Vec<Vec3>,
+    samples: Vec<usize>,
+    moments: Vec<PixelMoments>,
+}
+
+impl Accumulation {
+    pub fn new(width: usize, height: usize) -> Self {
+        Accumulation {
+            width,
+            height,
+            sums: vec![Vec3::zeros(); width * height],
+            samples: vec![0; width * height],
+            moments: vec![PixelMoments::default(); width * height],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sums.fill(Vec3::zeros());
+        self.samples.fill(0);
+        self.moments.fill(PixelMoments::default());
+    }
+
+    /// Adds `samples` samples averaging `color` to pixel `(x, y)`
+    pub fn add(&mut self, x: usize, y: usize, color: Vec3, samples: usize) {
+        let i = y * self.width + x;
+        self.sums[i] += color * samples as Float;
+        self.samples[i] += samples;
+    }
+
+    /// Adds the samples `moments` has seen to pixel `(x, y)`, variance and all
+    pub fn add_moments(&mut self, x: usize, y: usize, moments: &PixelMoments) {
+        let i = y * self.width + x;
+        self.sums[i] += moments.color_sum;
+        self.samples[i] += moments.samples;
+        self.moments[i].merge(moments);
+    }
+
+    /// Everything known about the variance of pixel `(x, y)`, from the samples added with
+    /// [`Accumulation::add_moments`]
+    pub fn moments(&self, x: usize, y: usize) -> &PixelMoments {
+        &self.moments[y * self.width + x]
+    }
+
+    /// Every pixel's moments, a row at a time from the top
+    pub fn all_moments(&self) -> &[PixelMoments] {
+        &self.moments
+    }
+
+    /// Returns the average of every sample pixel `(x, y)` has gotten, or black if none
+    pub fn color(&self, x: usize, y: usize) -> Vec3 {
+        let i = y * self.width + x;
+        match self.samples[i] {
+            0 => Vec3::zeros(),
+            samples => self.sums[i] / samples as Float,
+        }
+    }
+
+    /// Every pixel's average, a row at a time from the top like [`Image::pixels`]
+    pub fn colors(&self) -> Vec<Vec3> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.color(x, y))
+            .collect()
+    }
+
+    pub fn samples(&self, x: usize, y: usize) -> usize {
+        self.samples[y * self.width + x]
+    }
+}
+
+/// The pixels of one tile, as `(x, y)` ranges
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Tile {
+    pub xs: Range<usize>,
+    pub ys: Range<usize>,
+}
+
+impl Tile {
+    /// Every pixel of the tile, a row at a time from the top
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.ys
+            .clone()
+            .flat_map(move |y| self.xs.clone().map(move |x| (x, y)))
+    }
+}
+
+/// Spreads `bits` out so there's a zero between each of them
+fn spread_bits(mut bits: u64) -> u64 {
+    bits &= 0xffff_ffff;
+    bits = (bits | (bits << 16)) & 0x0000_ffff_0000_ffff;
+    bits = (bits | (bits << 8)) & 0x00ff_00ff_00ff_00ff;
+    bits = (bits | (bits << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    bits = (bits | (bits << 2)) & 0x3333_3333_3333_3333;
+    (bits | (bits << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Splits a `width` by `height` image into tiles, ordered along a Z-order curve so any run of
+/// consecutive tiles covers a compact patch of the image
+pub(crate) fn tiles(width: usize, height: usize, tile_size: usize) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let mut tiles: Vec<(u64, Tile)> = (0..height.div_ceil(tile_size))
+        .flat_map(|row| (0..width.div_ceil(tile_size)).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            let z_order = spread_bits(column as u64) | (spread_bits(row as u64) << 1);
+            let x = column * tile_size;
+            let y = row * tile_size;
+            let tile = Tile {
+                xs: x..(x + tile_size).min(width),
+                ys: y..(y + tile_size).min(height),
+            };
+            (z_order, tile)
+        })
+        .collect();
+    tiles.sort_unstable_by_key(|(z_order, _)| *z_order);
+    tiles.into_iter().map(|(_, tile)| tile).collect()
+}
+
+/// Tile indices a thread has yet to render. The thread works forward from the front of its own
+/// queue, and threads that run dry steal from the back of others', far from where the owner is
+/// working, so both sides keep rendering neighboring tiles.
+struct TileQueue(Mutex<Range<usize>>);
+
+impl TileQueue {
+    fn pop(&self) -> Option<usize> {
+        self.0.lock().unwrap().next()
+    }
+
+    fn steal(&self) -> Option<usize> {
+        self.0.lock().unwrap().next_back()
+    }
+}
+
+/// Renders images tile by tile on its own pool of threads. Each thread accumulates the tiles it
+/// renders privately and they're merged once the sweep is done, so no lock is shared per pixel.
+pub struct TileRenderer {
+    pool: ThreadPool,
+    tile_size: usize,
+}
+
+impl TileRenderer {
+    pub fn new(options: &ExecutionOptions) -> Result<Self, ThreadPoolBuildError> {
+        let mut builder = ThreadPoolBuilder::new().thread_name(|index| format!("tile_{}", index));
+        if let Some(threads) = options.threads {
+            builder = builder.num_threads(threads);
+        }
+        if options.affinity {
+            if affinity::SUPPORTED {
+                builder = builder.start_handler(affinity::pin_to_core);
+            } else {
+                println!(
+                    "Warning: pinning threads to cores needs Linux and the `affinity` feature"
+                );
+            }
+        }
+        Ok(TileRenderer {
+            pool: builder.build()?,
+            tile_size: options.tile_size,
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Runs `op` in the renderer's pool, so rayon work inside it runs on the renderer's threads
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(op)
+    }
+
+    /// Renders every pixel of `accumulation` with `render_pixel`, which returns the average of
+    /// `samples` new samples, or `None` to leave the pixel alone (e.g. when it's converged or the
+    /// sweep is being abandoned). Tiles finish in a different order from run to run, but the
+    /// merged result is identical for any number of threads, since every pixel is rendered and
+    /// added exactly once.
+    pub fn render_sweep(
+        &self,
+        accumulation: &mut Accumulation,
+        samples: usize,
+        render_pixel: impl Fn(usize, usize) -> Option<Vec3> + Sync,
+    ) {
+        let rendered = self.render_tiles(accumulation.width, accumulation.height, render_pixel);
+        for (x, y, color) in rendered {
+            accumulation.add(x, y, color, samples);
+        }
+    }
+
+    /// Like [`TileRenderer::render_sweep`], but `render_pixel` returns the [`PixelMoments`] of
+    /// however many samples it took, so pixels can get different numbers of them
+    pub fn render_sweep_moments(
+        &self,
+        accumulation: &mut Accumulation,
+        render_pixel: impl Fn(usize, usize) -> Option<PixelMoments> + Sync,
+    ) {
+        let rendered = self.render_tiles(accumulation.width, accumulation.height, render_pixel);
+        for (x, y, moments) in rendered {
+            accumulation.add_moments(x, y, &moments);
+        }
+    }
+
+    /// Renders every pixel of a `width` by `height` image with `render_pixel` tile by tile,
+    /// returning what it gave for each pixel it didn't leave alone. Which thread renders which
+    /// tile depends on timing, so the order they come back in varies, but each pixel comes back
+    /// once.
+    fn render_tiles<T: Send>(
+        &self,
+        width: usize,
+        height: usize,
+        render_pixel: impl Fn(usize, usize) -> Option<T> + Sync,
+    ) -> Vec<(usize, usize, T)> {
+        let tiles = tiles(width, height, self.tile_size);
+        let threads = self.threads().min(tiles.len()).max(1);
+        // Consecutive threads get consecutive stretches of the curve, which are pinned to
+        // consecutive cores, so neighboring parts of the image mostly stay on one socket
+        let queues: Vec<TileQueue> = (0..threads)
+            .map(|thread| {
+                let start = tiles.len() * thread / threads;
+                let end = tiles.len() * (thread + 1) / threads;
+                TileQueue(Mutex::new(start..end))
+            })
+            .collect();
+        let rendered: Vec<Vec<(usize, usize, T)>> = self.pool.broadcast(|context| {
+            let thread = context.index();
+            let mut pixels = Vec::new();
+            if thread >= threads {
+                return pixels;
+            }
+            let next_tile = || {
+                queues[thread].pop().or_else(|| {
+                    (1..threads).find_map(|offset| queues[(thread + offset) % threads].steal())
+                })
+            };
+            while let Some(tile) = next_tile() {
+                let Tile { xs, ys } = &tiles[tile];
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        if let Some(pixel) = render_pixel(x, y) {
+                            pixels.push((x, y, pixel));
+                        }
+                    }
+                }
+            }
+            pixels
+        });
+        rendered.into_iter().flatten().collect()
+    }
+
+    /// Renders the camera's image with every sample in one sweep, like
+    /// [`Camera::render_image`], including the metadata
+    pub fn render_image(&self, camera: &Camera, world: &World) -> Image {
+        let mut accumulation = Accumulation::new(camera.image_width, camera.image_height);
+        self.render_sweep(&mut accumulation, camera.samples_per_pixel(), |x, y| {
+            Some(camera.render_pixel(world, x, y, camera.samples_per_pixel()))
+        });
+        let mut image = camera.image_from_pixels(accumulation.colors());
+        image.metadata.push(format!("threads: {}", self.threads()));
+        image
+    }
+}
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+mod affinity {
+    pub const SUPPORTED: bool = true;
+
+    /// Pins the calling thread to the `index`th core it's allowed to run on
+    pub fn pin_to_core(index: usize) {
+        unsafe {
+            let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+            if libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut allowed) != 0 {
+                return;
+            }
+            let cores: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+                .filter(|&core| libc::CPU_ISSET(core, &allowed))
+                .collect();
+            if cores.is_empty() {
+                return;
+            }
+            let mut pinned: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(cores[index % cores.len()], &mut pinned);
+            libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &pinned);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+mod affinity {
+    pub const SUPPORTED: bool = false;
+
+    pub fn pin_to_core(_index: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Sphere,
+        material::{Lambertian, Metal},
+    };
+    use std::sync::Arc;
+
+    fn renderer(threads: usize) -> TileRenderer {
+        TileRenderer::new(&ExecutionOptions {
+            threads: Some(threads),
+            affinity: false,
+            tile_size: 4,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn many_threads_merge_to_the_single_threaded_image() {
+        let ground = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+        let mirror = Arc::new(Metal::new_solid(Vec3::repeat(0.9), Some(0.2)).into());
+        let world = World::build(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -1000.0), 1000.0, ground).into(),
+            Sphere::new(Vec3::new(0.0, 0.0, 1.0), 1.0, mirror).into(),
+        ]);
+        let mut camera = Camera::builder()
+            .with_look_from(Vec3::new(0.0, -6.0, 2.0))
+            .with_look_at(Vec3::new(0.0, 0.0, 1.0))
+            .with_resolution(23, 17)
+            .with_samples(4)
+            .build()
+            .unwrap();
+        camera.seed = Some(11);
+
+        let single = renderer(1).render_image(&camera, &world);
+        for threads in [2, 3, 8] {
+            let many = renderer(threads).render_image(&camera, &world);
+            assert!(
+                many.pixels == single.pixels,
+                "{} threads rendered a different image",
+                threads
+            );
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use crate::{
+    camera::Float,
+    vec3::{Vec3, Vec3Ext},
+};
+use std::{fmt, fs, io, path::Path};
+
+/// Display transforms that map unbounded linear radiance to colors in [0.0, 1.0]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tonemap {
+    /// Clamps each channel to [0.0, 1.0]
+    Clamp,
+    /// [John Hable's Uncharted 2 filmic curve](https://nelari.us/post/weekend_raytracing_with_wgpu_2/)
+    Uncharted2 { exposure_bias: Float },
+    /// An external display transform loaded from a `.cube` file
+    Lut(Lut),
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Tonemap::Uncharted2 { exposure_bias: 1.1 }
+    }
+}
+
+// Taken from this blog post: https://nelari.us/post/weekend_raytracing_with_wgpu_2/
+// Notes on tomemapping and color space transformations: https://computergraphics.stackexchange.com/questions/10315/tone-mapping-vs-gamma-correction
+// In essence: yes, keep the gamma correction at the end.
+fn uncharted2_tonemap(x: Vec3) -> Vec3 {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+
+    let numerator =
+        x.component_mul(&(a * x + Vec3::new(c * b, c * b, c * b))) + Vec3::new(d * e, d * e, d * e);
+    let denominator =
+        x.component_mul(&(a * x + Vec3::new(b, b, b))) + Vec3::new(d * f, d * f, d * f);
+
+    numerator.component_div(&denominator) - Vec3::new(e / f, e / f, e / f)
+}
+
+impl Tonemap {
+    /// Takes an unclamped linear `color` and returns a color with values in the range [0.0, 1.0]
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        match self {
+            Tonemap::Clamp => color.map(|c| c.clamp(0.0, 1.0)),
+            Tonemap::Uncharted2 { exposure_bias } => {
+                let curr = uncharted2_tonemap(*exposure_bias * color);
+                let w = 11.2;
+                let white_scale = Vec3::ONE.component_div(&uncharted2_tonemap(Vec3::new(w, w, w)));
+                white_scale.component_mul(&curr).map(|c| c.clamp(0.0, 1.0))
+            }
+            Tonemap::Lut(lut) => lut.apply(color),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tonemap::Clamp => "clamp",
+            Tonemap::Uncharted2 { .. } => "uncharted2",
+            Tonemap::Lut(_) => "lut",
+        }
+    }
+
+    /// Bakes this transform followed by a `1/gamma` encode into a 3D LUT with `size` points per
+    /// axis covering input values from 0.0 to `domain_max`. Use a gamma of 1.0 to skip encoding.
+    pub fn to_lut(&self, size: usize, domain_max: Float, gamma: Float) -> Lut {
+        let size = size.max(2);
+        let step = domain_max / (size - 1) as Float;
+        let mut table = Vec::with_capacity(size * size * size);
+        // Red changes fastest, as the .cube format expects
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let input = Vec3::new(r as Float, g as Float, b as Float) * step;
+                    let output = self.apply(input).map(|c| c.max(0.0).powf(1.0 / gamma));
+                    table.push(output);
+                }
+            }
+        }
+        Lut {
+            title: None,
+            dimension: LutDimension::ThreeD,
+            size,
+            domain_min: Vec3::zeros(),
+            domain_max: Vec3::new(domain_max, domain_max, domain_max),
+            table,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LutDimension {
+    OneD,
+    ThreeD,
+}
+
+/// A 1D or 3D lookup table in the [Adobe/Resolve `.cube` format](https://resolve.cafe/developers/luts/)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut {
+    pub title: Option<String>,
+    pub dimension: LutDimension,
+    /// Number of lattice points per axis
+    pub size: usize,
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+    /// For 3D LUTs, `size³` entries with red changing fastest. For 1D LUTs, `size` entries.
+    table: Vec<Vec3>,
+}
+
+#[derive(Debug)]
+pub enum LutError {
+    Io(io::Error),
+    /// A line that couldn't be parsed, with its 1-based line number
+    Malformed {
+        line: usize,
+        message: String,
+    },
+    MissingSize,
+    /// DOMAIN_MAX isn't greater than DOMAIN_MIN on every channel
+    InvalidDomain,
+    WrongEntryCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LutError::Io(err) => write!(f, "failed to read LUT: {}", err),
+            LutError::Malformed { line, message } => write!(f, "line {}: {}", line, message),
+            LutError::MissingSize => write!(f, "LUT has no LUT_1D_SIZE or LUT_3D_SIZE"),
+            LutError::InvalidDomain => write!(f, "DOMAIN_MAX must be greater than DOMAIN_MIN"),
+            LutError::WrongEntryCount { expected, found } => {
+                write!(f, "LUT should have {} entries but has {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LutError {}
+
+impl From<io::Error> for LutError {
+    fn from(err: io::Error) -> Self {
+        LutError::Io(err)
+    }
+}
+
+fn parse_triple(words: &[&str], line: usize) -> Result<Vec3, LutError> {
+    let malformed = |message: String| LutError::Malformed { line, message };
+    if words.len() != 3 {
+        return Err(malformed(format!(
+            "expected 3 values but found {}",
+            words.len()
+        )));
+    }
+    let mut values = [0.0; 3];
+    for (value, word) in values.iter_mut().zip(words) {
+        *value = word
+            .parse()
+            .map_err(|_| malformed(format!("'{}' is not a number", word)))?;
+    }
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+impl Lut {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LutError> {
+        Lut::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, LutError> {
+        let mut title = None;
+        let mut dimension_and_size = None;
+        let mut domain_min = Vec3::zeros();
+        let mut domain_max = Vec3::ONE;
+        let mut table = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let malformed = |message: &str| LutError::Malformed {
+                line: line_number,
+                message: message.to_string(),
+            };
+            let parse_size = |words: &[&str]| -> Result<usize, LutError> {
+                match words {
+                    [size] => size
+                        .parse()
+                        .ok()
+                        .filter(|&size| size >= 2)
+                        .ok_or_else(|| malformed("LUT size must be an integer of at least 2")),
+                    _ => Err(malformed("expected a single LUT size")),
+                }
+            };
+
+            match words[0] {
+                "TITLE" => {
+                    title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string());
+                }
+                "LUT_1D_SIZE" => {
+                    dimension_and_size = Some((LutDimension::OneD, parse_size(&words[1..])?))
+                }
+                "LUT_3D_SIZE" => {
+                    dimension_and_size = Some((LutDimension::ThreeD, parse_size(&words[1..])?))
+                }
+                "DOMAIN_MIN" => domain_min = parse_triple(&words[1..], line_number)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(&words[1..], line_number)?,
+                keyword
+                    if keyword
+                        .chars()
+                        .next()
+                        .is_some_and(|c| c.is_ascii_alphabetic()) =>
+                {
+                    // Other keywords (e.g. LUT_1D_INPUT_RANGE) aren't supported
+                    return Err(malformed(&format!("unsupported keyword '{}'", keyword)));
+                }
+                _ => table.push(parse_triple(&words, line_number)?),
+            }
+        }
+
+        let (dimension, size) = dimension_and_size.ok_or(LutError::MissingSize)?;
+        let expected = match dimension {
+            LutDimension::OneD => size,
+            LutDimension::ThreeD => size * size * size,
+        };
+        if table.len() != expected {
+            return Err(LutError::WrongEntryCount {
+                expected,
+                found: table.len(),
+            });
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err(LutError::InvalidDomain);
+        }
+
+        Ok(Lut {
+            title,
+            dimension,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Serializes the LUT in the `.cube` format
+    pub fn to_cube_string(&self) -> String {
+        let mut out = String::new();
+        if let Some(title) = &self.title {
+            out += &format!("TITLE \"{}\"\n", title);
+        }
+        let size_keyword = match self.dimension {
+            LutDimension::OneD => "LUT_1D_SIZE",
+            LutDimension::ThreeD => "LUT_3D_SIZE",
+        };
+        out += &format!("{} {}\n", size_keyword, self.size);
+        let (min, max) = (self.domain_min, self.domain_max);
+        out += &format!("DOMAIN_MIN {} {} {}\n", min.x, min.y, min.z);
+        out += &format!("DOMAIN_MAX {} {} {}\n", max.x, max.y, max.z);
+        for entry in &self.table {
+            out += &format!("{:.6} {:.6} {:.6}\n", entry.x, entry.y, entry.z);
+        }
+        out
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_cube_string())
+    }
+
+    /// Maps `color` through the LUT, interpolating linearly between lattice points.
+    /// Inputs outside the LUT's domain are clamped to it.
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let last = (self.size - 1) as Float;
+        // Continuous lattice coordinates in [0, size - 1]
+        let coords = (color - self.domain_min)
+            .component_div(&(self.domain_max - self.domain_min))
+            .map(|c| (c * last).clamp(0.0, last));
+
+        match self.dimension {
+            LutDimension::OneD => Vec3::new(
+                self.lerp_1d(coords.x, 0),
+                self.lerp_1d(coords.y, 1),
+                self.lerp_1d(coords.z, 2),
+            ),
+            LutDimension::ThreeD => self.trilinear(coords),
+        }
+    }
+
+    /// Returns the lower lattice index and the fractional position toward the next one
+    fn cell(&self, coord: Float) -> (usize, Float) {
+        let lower = (coord.floor() as usize).min(self.size - 2);
+        (lower, coord - lower as Float)
+    }
+
+    fn lerp_1d(&self, coord: Float, channel: usize) -> Float {
+        let (i, t) = self.cell(coord);
+        let a = self.table[i][channel];
+        let b = self.table[i + 1][channel];
+        a + (b - a) * t
+    }
+
+    fn at(&self, r: usize, g: usize, b: usize) -> Vec3 {
+        self.table[r + g * self.size + b * self.size * self.size]
+    }
+
+    fn trilinear(&self, coords: Vec3) -> Vec3 {
+        let (r, tr) = self.cell(coords.x);
+        let (g, tg) = self.cell(coords.y);
+        let (b, tb) = self.cell(coords.z);
+
+        let lerp = |a: Vec3, b: Vec3, t: Float| a + (b - a) * t;
+        let c00 = lerp(self.at(r, g, b), self.at(r + 1, g, b), tr);
+        let c10 = lerp(self.at(r, g + 1, b), self.at(r + 1, g + 1, b), tr);
+        let c01 = lerp(self.at(r, g, b + 1), self.at(r + 1, g, b + 1), tr);
+        let c11 = lerp(self.at(r, g + 1, b + 1), self.at(r + 1, g + 1, b + 1), tr);
+        lerp(lerp(c00, c10, tg), lerp(c01, c11, tg), tb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colors across the domain, some past the white point
+    fn sweep() -> Vec<Vec3> {
+        let steps = [0.0, 0.03, 0.2, 0.5, 0.9, 1.7, 3.1, 4.0];
+        steps
+            .iter()
+            .flat_map(|&r| steps.iter().map(move |&g| Vec3::new(r, g, 4.0 - r)))
+            .collect()
+    }
+
+    #[test]
+    fn exported_luts_reproduce_the_tonemap() {
+        let tonemap = Tonemap::default();
+        let lut = Lut::parse(&tonemap.to_lut(33, 4.0, 1.0).to_cube_string()).unwrap();
+        for color in sweep() {
+            let difference = (lut.apply(color) - tonemap.apply(color)).abs().max();
+            assert!(difference < 5e-3, "{:?} is off by {}", color, difference);
+        }
+    }
+
+    #[test]
+    fn exported_luts_bake_in_the_gamma() {
+        let lut = Tonemap::Clamp.to_lut(2, 1.0, 2.0);
+        let color = lut.apply(Vec3::new(0.25, 1.0, 0.0));
+        // The lattice only has the corners, so the curve between them is linear
+        assert_eq!(color, Vec3::new(0.25, 1.0, 0.0));
+        assert_eq!(
+            Tonemap::Clamp.to_lut(3, 1.0, 2.0).apply(Vec3::ONE * 0.5).x,
+            0.5_f64.sqrt()
+        );
+    }
+
+    #[test]
+    fn tiny_luts_apply_exactly() {
+        // Swaps red and blue, and inverts green
+        let source = "\
+# A hand-written LUT
+TITLE \"swap\"
+LUT_3D_SIZE 2
+0 1 0
+0 1 1
+0 0 0
+0 0 1
+1 1 0
+1 1 1
+1 0 0
+1 0 1
+";
+        let lut = Lut::parse(source).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("swap"));
+        assert_eq!(
+            lut.apply(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            lut.apply(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            lut.apply(Vec3::new(0.25, 0.5, 0.75)),
+            Vec3::new(0.75, 0.5, 0.25)
+        );
+    }
+
+    #[test]
+    fn one_d_luts_use_their_domain() {
+        let source = "\
+LUT_1D_SIZE 3
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 2 4 2
+0 0 0
+0.5 0.25 1
+1 1 1
+";
+        let lut = Lut::parse(source).unwrap();
+        assert_eq!(lut.dimension, LutDimension::OneD);
+        assert_eq!(
+            lut.apply(Vec3::new(1.0, 1.0, 0.5)),
+            Vec3::new(0.5, 0.125, 0.5)
+        );
+        // Clamped to the domain
+        assert_eq!(
+            lut.apply(Vec3::new(-1.0, 9.0, 2.0)),
+            Vec3::new(0.0, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn saved_luts_parse_back_the_same() {
+        let mut lut = Tonemap::Clamp.to_lut(2, 2.0, 1.0);
+        lut.title = Some("clamp".to_string());
+        assert_eq!(Lut::parse(&lut.to_cube_string()).unwrap(), lut);
+    }
+
+    #[test]
+    fn malformed_luts_are_rejected() {
+        let error = |source: &str| Lut::parse(source).unwrap_err();
+        assert!(matches!(error("0 0 0\n"), LutError::MissingSize));
+        assert!(matches!(
+            error("LUT_1D_SIZE 2\n0 0 0\n"),
+            LutError::WrongEntryCount {
+                expected: 2,
+                found: 1
+            }
+        ));
+        assert!(matches!(
+            error("LUT_1D_SIZE 2\nDOMAIN_MAX 1 0 1\n0 0 0\n1 1 1\n"),
+            LutError::InvalidDomain
+        ));
+        assert!(matches!(
+            error("LUT_1D_SIZE 2\n0 0\n1 1 1\n"),
+            LutError::Malformed { line: 2, .. }
+        ));
+        assert!(matches!(
+            error("LUT_1D_SIZE 2\n0 zero 0\n1 1 1\n"),
+            LutError::Malformed { line: 2, .. }
+        ));
+        assert!(matches!(
+            error("LUT_3D_SIZE 1\n0 0 0\n"),
+            LutError::Malformed { line: 1, .. }
+        ));
+        assert!(matches!(
+            error("LUT_1D_INPUT_RANGE 0 1\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n"),
+            LutError::Malformed { line: 1, .. }
+        ));
+    }
+}
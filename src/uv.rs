@@ -0,0 +1,294 @@
+use crate::{
+    camera::Float,
+    vec3::{Point3, Vec2, Vec3},
+};
+use std::f64::consts::{PI, TAU};
+
+/// Columns and rows of the [`UvMode::CubeMap`] atlas
+const CUBE_MAP_COLUMNS: Float = 4.0;
+const CUBE_MAP_ROWS: Float = 3.0;
+
+/// How a shape lays its surface out in texture space, which decides what image and UV-space
+/// checker textures look like on it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UvMode {
+    /// Longitude and latitude, like a globe. Texels bunch up into slivers toward the poles.
+    #[default]
+    Spherical,
+    /// Longitude and the height along the pole axis (Lambert's cylindrical projection), which
+    /// gives every texel the same area on a sphere so nothing pinches at the poles
+    EqualArea,
+    /// Each cube face gets the whole texture
+    Faces,
+    /// All six cube faces in one 4x3 atlas, laid out as a cross: the four sides run around the
+    /// middle row with the top above and the bottom below the first one. Textures run across
+    /// every edge the cross keeps together without a seam.
+    CubeMap,
+}
+
+impl UvMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UvMode::Spherical => "spherical",
+            UvMode::EqualArea => "equal-area",
+            UvMode::Faces => "faces",
+            UvMode::CubeMap => "cube-map",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "spherical" => Some(UvMode::Spherical),
+            "equal-area" => Some(UvMode::EqualArea),
+            "faces" => Some(UvMode::Faces),
+            "cube-map" => Some(UvMode::CubeMap),
+            _ => None,
+        }
+    }
+
+    /// Returns the UV of a point on a shape from `local`, its position in the shape's own frame
+    /// scaled so the shape fits in the cube from -1 to 1 (so a sphere is the unit sphere and a
+    /// box is the cube itself). Points are projected out from the center onto the sphere or
+    /// cube when the mode needs it, so spheres can use cube layouts and boxes spherical ones.
+    pub fn uv(&self, local: Vec3) -> Vec2 {
+        self.uv_on_face(local, local.abs().imax())
+    }
+
+    /// Like [`UvMode::uv`], for a point known to be on the cube face perpendicular to `axis`,
+    /// which is more reliable than guessing it from `local` along a box's edges
+    pub fn uv_on_face(&self, local: Vec3, axis: usize) -> Vec2 {
+        match self {
+            UvMode::Spherical => {
+                let (theta, phi) = to_unit_spherical(local.normalize());
+                Vec2::new(phi.rem_euclid(TAU) / TAU, theta / PI)
+            }
+            UvMode::EqualArea => {
+                let direction = local.normalize();
+                let (_theta, phi) = to_unit_spherical(direction);
+                let height = ((1.0 + direction.z) / 2.0).clamp(0.0, 1.0);
+                Vec2::new(phi.rem_euclid(TAU) / TAU, height)
+            }
+            UvMode::Faces => {
+                let face = local / local[axis].abs();
+                let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+                Vec2::new(
+                    ((face[i] + 1.0) / 2.0).clamp(0.0, 1.0),
+                    ((face[j] + 1.0) / 2.0).clamp(0.0, 1.0),
+                )
+            }
+            UvMode::CubeMap => {
+                let face = (local / local[axis].abs()).map(|c| c.clamp(-1.0, 1.0));
+                let (column, row, u, v) = cube_map_cell(face, axis);
+                Vec2::new(
+                    (column + u.clamp(0.0, 1.0)) / CUBE_MAP_COLUMNS,
+                    (row + v.clamp(0.0, 1.0)) / CUBE_MAP_ROWS,
+                )
+            }
+        }
+    }
+}
+
+/// Returns the atlas column and row of the face of the cube `point` is on, and where it is on
+/// that face from 0.0 to 1.0. Across the middle row the sides go +X, +Y, -X, -Y, counterclockwise
+/// seen from above, with u running the same way round and v running down from the top. The top
+/// and bottom hang off +X, so they share its edges.
+fn cube_map_cell(point: Point3, axis: usize) -> (Float, Float, Float, Float) {
+    let (x, y, z) = (point.x, point.y, point.z);
+    let down = (1.0 - z) / 2.0;
+    match (axis, point[axis] >= 0.0) {
+        (0, true) => (0.0, 1.0, (y + 1.0) / 2.0, down),
+        (1, true) => (1.0, 1.0, (1.0 - x) / 2.0, down),
+        (0, false) => (2.0, 1.0, (1.0 - y) / 2.0, down),
+        (1, false) => (3.0, 1.0, (x + 1.0) / 2.0, down),
+        (_, true) => (0.0, 0.0, (y + 1.0) / 2.0, (x + 1.0) / 2.0),
+        (_, false) => (0.0, 2.0, (y + 1.0) / 2.0, (1.0 - x) / 2.0),
+    }
+}
+
+/// Returns the polar angle from -Z and the azimuth from -X of a point on the unit sphere. Points
+/// rounded slightly off the sphere still get angles, and the poles, where every azimuth is the
+/// same point, get an azimuth of pi.
+pub fn to_unit_spherical(point: Point3) -> (Float, Float) {
+    let theta = (-point.z).clamp(-1.0, 1.0).acos(); // acos is slow as balls
+    let phi = if point.x == 0.0 && point.y == 0.0 {
+        PI
+    } else {
+        Float::atan2(point.y, point.x) + PI
+    };
+    (theta, phi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3Ext;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const MODES: [UvMode; 4] = [
+        UvMode::Spherical,
+        UvMode::EqualArea,
+        UvMode::Faces,
+        UvMode::CubeMap,
+    ];
+
+    fn in_unit_square(uv: Vec2) -> bool {
+        uv.iter().all(|c| (0.0..=1.0).contains(c))
+    }
+
+    /// Random unit vectors, half of them rounded slightly off the sphere, and the poles and axes
+    fn directions(count: usize) -> Vec<Vec3> {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut directions: Vec<Vec3> = (0..count)
+            .map(|i| {
+                let direction = Vec3::random_unit(&mut rng);
+                if i % 2 == 0 {
+                    direction
+                } else {
+                    direction * (1.0 + rng.gen_range(-1e-12..1e-12))
+                }
+            })
+            .collect();
+        for axis in 0..3 {
+            for sign in [1.0, -1.0, 1.0 + 1e-15, -1.0 - 1e-15] {
+                let mut direction = Vec3::zeros();
+                direction[axis] = sign;
+                directions.push(direction);
+            }
+        }
+        directions.push(Vec3::new(-0.0, 0.0, 1.0));
+        directions.push(Vec3::new(0.0, -0.0, -1.0));
+        directions
+    }
+
+    #[test]
+    fn every_mode_gives_unit_square_uvs_on_the_whole_sphere() {
+        for direction in directions(100_000) {
+            for mode in MODES {
+                let uv = mode.uv(direction);
+                assert!(in_unit_square(uv), "{:?} {:?} at {:?}", mode, uv, direction);
+            }
+        }
+    }
+
+    #[test]
+    fn points_just_off_the_sphere_still_get_angles() {
+        for z in [1.0 + 1e-15, -1.0 - 1e-15, 1.0 + 1e-9] {
+            let (theta, phi) = to_unit_spherical(Vec3::new(0.0, 0.0, z));
+            assert!(theta.is_finite() && phi.is_finite(), "z {}", z);
+        }
+        assert_eq!(to_unit_spherical(Vec3::new(0.0, 0.0, 1.0)), (PI, PI));
+        assert_eq!(to_unit_spherical(Vec3::new(0.0, 0.0, -1.0)), (0.0, PI));
+        // Away from the poles it's the usual angles
+        let (theta, phi) = to_unit_spherical(Vec3::new(1.0, 0.0, 0.0));
+        assert!((theta - PI / 2.0).abs() < 1e-12 && (phi - PI).abs() < 1e-12);
+        let (_, phi) = to_unit_spherical(Vec3::new(0.0, 1.0, 0.0));
+        assert!((phi - 1.5 * PI).abs() < 1e-12);
+    }
+
+    #[test]
+    fn textured_sphere_uvs_are_finite_at_any_orientation() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for direction in directions(20_000) {
+            let (pitch, yaw, rotation) = (
+                rng.gen_range(-PI..PI),
+                rng.gen_range(-PI..PI),
+                rng.gen_range(-TAU..TAU),
+            );
+            let uv = crate::hittable::unit_sphere_uv(direction, pitch, yaw, rotation);
+            assert!(in_unit_square(uv), "{:?} at {:?}", uv, direction);
+        }
+    }
+
+    /// How many of `count` uniformly random points on the sphere land in each of a `bins` by
+    /// `bins` grid over `mode`'s UVs, so texels covering equal areas get equal counts
+    fn texel_counts(mode: UvMode, bins: usize, count: usize) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut counts = vec![0; bins * bins];
+        for _ in 0..count {
+            let uv = mode.uv(Vec3::random_unit(&mut rng));
+            let bin = |c: Float| ((c * bins as Float) as usize).min(bins - 1);
+            counts[bin(uv.y) * bins + bin(uv.x)] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn equal_area_texels_cover_equal_areas() {
+        let counts = texel_counts(UvMode::EqualArea, 8, 256_000);
+        // 4000 expected in each, give or take about 63
+        for (bin, &count) in counts.iter().enumerate() {
+            assert!((3700..4300).contains(&count), "bin {}: {}", bin, count);
+        }
+    }
+
+    #[test]
+    fn spherical_texels_shrink_toward_the_poles() {
+        let counts = texel_counts(UvMode::Spherical, 8, 256_000);
+        let row = |r: usize| counts[r * 8..(r + 1) * 8].iter().sum::<usize>() as Float;
+        // Rows of latitude cover the sphere in proportion to the difference of the cosines
+        // of their edges, so the polar rows get a fifth of what the equator's get
+        for (pole, equator) in [(0, 3), (7, 4)] {
+            let ratio = row(pole) / row(equator);
+            let expected = (1.0 - (PI / 8.0).cos()) / (3.0 * PI / 8.0).cos();
+            assert!((ratio - expected).abs() < 0.02, "{} vs {}", ratio, expected);
+        }
+    }
+
+    /// The cube map UV of `point` on the face perpendicular to `axis`, for points on an edge,
+    /// which belong to two faces
+    fn cube_map_uv(point: Vec3, axis: usize) -> Vec2 {
+        UvMode::CubeMap.uv_on_face(point, axis)
+    }
+
+    #[test]
+    fn cube_maps_are_seamless_across_the_edges_the_cross_keeps_together() {
+        // Faces next to each other in the atlas, by the axis each is perpendicular to and the
+        // point where their shared edge meets the middle of the cube's side
+        let kept = [
+            ((0, 1), Vec3::new(1.0, 1.0, 0.0)),
+            ((1, 0), Vec3::new(-1.0, 1.0, 0.0)),
+            ((0, 1), Vec3::new(-1.0, -1.0, 0.0)),
+            ((0, 2), Vec3::new(1.0, 0.0, 1.0)),
+            ((0, 2), Vec3::new(1.0, 0.0, -1.0)),
+        ];
+        for ((a, b), middle) in kept {
+            // Along the edge, which runs along the third axis
+            let along = 3 - a - b;
+            for t in [-1.0, -0.6, 0.0, 0.3, 1.0] {
+                let mut point = middle;
+                point[along] = t;
+                let (from_a, from_b) = (cube_map_uv(point, a), cube_map_uv(point, b));
+                assert!(
+                    (from_a - from_b).norm() < 1e-12,
+                    "{:?}: {:?} vs {:?}",
+                    point,
+                    from_a,
+                    from_b
+                );
+            }
+        }
+        // The -Y side's far edge meets +X's across the atlas's wrap, so it's seamless when the
+        // texture repeats
+        for z in [-1.0, 0.0, 0.5] {
+            let point = Vec3::new(1.0, -1.0, z);
+            let (from_x, from_y) = (cube_map_uv(point, 0), cube_map_uv(point, 1));
+            assert!((from_y.x - 1.0 - from_x.x).abs() < 1e-12);
+            assert!((from_y.y - from_x.y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn longitude_wraps_without_a_seam() {
+        // Either side of the -X meridian, where the azimuth wraps from just under 2 pi to 0
+        for mode in [UvMode::Spherical, UvMode::EqualArea] {
+            for z in [-0.9, -0.3, 0.0, 0.5, 0.9] {
+                let r = Float::sqrt(1.0 - z * z);
+                let side = |y: Float| mode.uv(Vec3::new(-(r * r - y * y).sqrt(), y, z));
+                let (below, above) = (side(-1e-9), side(1e-9));
+                let du = (below.x - above.x).rem_euclid(1.0);
+                assert!(du.min(1.0 - du) < 1e-8, "{:?} at z {}", mode, z);
+                assert!((below.y - above.y).abs() < 1e-8, "{:?} at z {}", mode, z);
+            }
+        }
+    }
+}
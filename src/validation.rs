@@ -0,0 +1,227 @@
+use crate::{camera::Float, vec3::Vec3};
+#[cfg(feature = "validation")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    cell::Cell,
+    fmt,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+/// Whether the color pipeline checks its values at all, which only builds with the `validation`
+/// feature do. Every check returns straight away otherwise, so it compiles to nothing.
+pub const ENABLED: bool = cfg!(feature = "validation");
+
+/// Violations kept in full for the report, beyond which they're only counted
+const MAX_EXAMPLES: usize = 8;
+
+/// A boundary in the color pipeline where values are checked, in the order colors cross them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// What a texture lookup returned: finite and non-negative, though it can be over 1
+    TextureFetch,
+    /// What a non-emissive material keeps of the light it scatters: within [0, 1]
+    ScatterAttenuation,
+    /// A sample's color going into a pixel's average: finite and non-negative
+    AccumulationInput,
+    /// A pixel's linear color going into post-processing: finite
+    PresentationInput,
+    /// A pixel's channels as they're rounded to bytes: within [0, 255]
+    EncodedOutput,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::TextureFetch,
+        Stage::ScatterAttenuation,
+        Stage::AccumulationInput,
+        Stage::PresentationInput,
+        Stage::EncodedOutput,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::TextureFetch => "texture fetch",
+            Stage::ScatterAttenuation => "scatter attenuation",
+            Stage::AccumulationInput => "accumulation input",
+            Stage::PresentationInput => "presentation input",
+            Stage::EncodedOutput => "encoded output",
+        }
+    }
+
+    /// What's wrong with `value` at this stage, if anything
+    pub fn problem(&self, value: &Vec3) -> Option<&'static str> {
+        if !value.iter().all(|c| c.is_finite()) {
+            return Some("not finite");
+        }
+        let (min, max) = match self {
+            Stage::TextureFetch | Stage::AccumulationInput => (0.0, Float::INFINITY),
+            Stage::ScatterAttenuation => (0.0, 1.0),
+            Stage::PresentationInput => return None,
+            Stage::EncodedOutput => (0.0, 255.0),
+        };
+        if value.min() < min {
+            Some("negative")
+        } else if value.max() > max {
+            Some("too large")
+        } else {
+            None
+        }
+    }
+}
+
+/// A value that failed its stage's check
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub stage: Stage,
+    /// The pixel being rendered or written, when known
+    pub pixel: Option<(usize, usize)>,
+    pub value: Vec3,
+    pub problem: &'static str,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ", self.stage.name(), self.problem)?;
+        write!(f, "({}, {}, {})", self.value.x, self.value.y, self.value.z)?;
+        match self.pixel {
+            Some((x, y)) => write!(f, " at pixel ({}, {})", x, y),
+            None => write!(f, " at an unknown pixel"),
+        }
+    }
+}
+
+/// Every violation since the last [`take_report`], counted by stage, with the first few of
+/// them in full
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    /// In the order of [`Stage::ALL`]
+    pub counts: [u64; Stage::ALL.len()],
+    pub examples: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn count(&self, stage: Stage) -> u64 {
+        self.counts[stage as usize]
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.total() == 0 {
+            return write!(f, "Color pipeline validation: no violations");
+        }
+        write!(f, "Color pipeline validation: {} violations", self.total())?;
+        for stage in Stage::ALL {
+            if self.count(stage) > 0 {
+                write!(f, "\n  {}: {}", stage.name(), self.count(stage))?;
+            }
+        }
+        for violation in &self.examples {
+            write!(f, "\n  e.g. {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+static REPORT: Mutex<ValidationReport> = Mutex::new(ValidationReport {
+    counts: [0; Stage::ALL.len()],
+    examples: Vec::new(),
+});
+
+thread_local! {
+    /// The pixel this thread is rendering a sample of, for attributing violations
+    static PIXEL: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Whether violations panic instead of being collected, which `RT_VALIDATION=panic` asks for
+/// to get a backtrace from the first one
+fn panics() -> bool {
+    static PANICS: OnceLock<bool> = OnceLock::new();
+    *PANICS.get_or_init(|| std::env::var("RT_VALIDATION").is_ok_and(|mode| mode == "panic"))
+}
+
+/// Attributes violations on this thread to pixel `(x, y)` until another pixel is entered
+#[inline]
+pub fn enter_pixel(x: usize, y: usize) {
+    if ENABLED {
+        PIXEL.with(|pixel| pixel.set(Some((x, y))));
+    }
+}
+
+/// Checks `value` against `stage`'s rule, attributing it to the pixel entered last on this
+/// thread
+#[inline]
+pub fn check(stage: Stage, value: &Vec3) {
+    if ENABLED {
+        check_at(stage, value, PIXEL.with(Cell::get));
+    }
+}
+
+/// Checks `value` against `stage`'s rule, attributing it to `pixel`
+#[inline]
+pub fn check_at(stage: Stage, value: &Vec3, pixel: Option<(usize, usize)>) {
+    if !ENABLED {
+        return;
+    }
+    #[cfg(feature = "validation")]
+    let injected = take_injected(stage);
+    #[cfg(feature = "validation")]
+    let value = injected.as_ref().unwrap_or(value);
+    let Some(problem) = stage.problem(value) else {
+        return;
+    };
+    let violation = Violation {
+        stage,
+        pixel,
+        value: *value,
+        problem,
+    };
+    if panics() {
+        panic!("Color pipeline violation, {}", violation);
+    }
+    let mut report = REPORT.lock().unwrap_or_else(PoisonError::into_inner);
+    report.counts[stage as usize] += 1;
+    if report.examples.len() < MAX_EXAMPLES {
+        report.examples.push(violation);
+    }
+}
+
+/// Values queued by [`inject`], each checked once in place of the next value its stage sees
+#[cfg(feature = "validation")]
+static INJECTED: Mutex<Vec<(Stage, Vec3)>> = Mutex::new(Vec::new());
+
+/// Whether anything is queued in [`INJECTED`], so checks don't take its lock when nothing is
+#[cfg(feature = "validation")]
+static ANY_INJECTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the next check at `stage`, on whichever thread gets there first, see `value` instead
+/// of the value it was given. Lets tests break a boundary on purpose and see the violation
+/// reported there, without breaking the renderer itself.
+#[cfg(feature = "validation")]
+pub fn inject(stage: Stage, value: Vec3) {
+    let mut injected = INJECTED.lock().unwrap_or_else(PoisonError::into_inner);
+    injected.push((stage, value));
+    ANY_INJECTED.store(true, Ordering::Release);
+}
+
+/// Takes the first value [`inject`] queued for `stage`, if there is one
+#[cfg(feature = "validation")]
+fn take_injected(stage: Stage) -> Option<Vec3> {
+    if !ANY_INJECTED.load(Ordering::Acquire) {
+        return None;
+    }
+    let mut injected = INJECTED.lock().unwrap_or_else(PoisonError::into_inner);
+    let index = injected.iter().position(|(queued, _)| *queued == stage)?;
+    let (_, value) = injected.remove(index);
+    ANY_INJECTED.store(!injected.is_empty(), Ordering::Release);
+    Some(value)
+}
+
+/// Returns every violation since the last call and starts counting afresh
+pub fn take_report() -> ValidationReport {
+    std::mem::take(&mut *REPORT.lock().unwrap_or_else(PoisonError::into_inner))
+}
@@ -0,0 +1,205 @@
+//! Renders a procedural scene, one of [`scenes::PROCEDURAL_SCENES`], at many seeds as small
+//! thumbnails on a contact sheet labeled with each one's seed, to pick a layout from before
+//! rendering it properly with `--seed`
+
+use crate::{
+    camera::{Camera, Float, Image},
+    glyphs,
+    hittable::World,
+    scenes::{self, CoverMaterials},
+    vec3::Vec3,
+};
+
+/// How many seeds a sheet shows unless told otherwise
+pub const DEFAULT_COUNT: usize = 16;
+/// How many pixels wide thumbnails are unless told otherwise
+pub const DEFAULT_THUMBNAIL_WIDTH: usize = 192;
+/// Samples per pixel of thumbnails unless told otherwise, enough to read the layout by
+pub const DEFAULT_SAMPLES: usize = 8;
+/// Pixels between thumbnails and around the edge of the sheet
+const GAP: usize = 4;
+/// Pixels per dot of the seed labels
+const LABEL_SCALE: usize = 2;
+/// How bright the sheet is between thumbnails
+const BACKGROUND: Float = 0.05;
+
+/// One seed's layout of a procedural scene, rendered small
+pub struct Variation {
+    pub seed: u64,
+    pub image: Image,
+}
+
+/// `camera` shrunk to `width` pixels wide at the same aspect ratio, with `samples` samples per
+/// pixel and seeded with `seed`, as a thumbnail of the layout `seed` gives is rendered
+pub fn thumbnail_camera(camera: &Camera, width: usize, samples: usize, seed: u64) -> Camera {
+    let height = (width * camera.image_height / camera.image_width).max(1);
+    let mut camera = camera
+        .with_resolution(width, height)
+        .with_sampling(samples, camera.max_depth());
+    camera.seed = Some(seed);
+    camera
+}
+
+/// Renders the procedural scene called `name` laid out by each of `seeds` with
+/// [`thumbnail_camera`], so `rt --headless --seed` renders the same image at that size and
+/// sampling. The scene's materials and textures are loaded once for every seed.
+pub fn render_variations(
+    name: &str,
+    seeds: &[u64],
+    width: usize,
+    samples: usize,
+) -> Result<Vec<Variation>, String> {
+    if !scenes::PROCEDURAL_SCENES.contains(&name) {
+        return Err(format!(
+            "'{}' isn't laid out from a seed, only {} are",
+            name,
+            scenes::PROCEDURAL_SCENES.join(", ")
+        ));
+    }
+    let materials = CoverMaterials::load();
+    let mut variations = Vec::with_capacity(seeds.len());
+    for (k, &seed) in seeds.iter().enumerate() {
+        println!("Rendering seed {} ({} of {})", seed, k + 1, seeds.len());
+        let (camera, shapes) = scenes::procedural_scene(name, seed, &materials)
+            .ok_or_else(|| format!("there's no procedural scene called '{}'", name))?;
+        let camera = thumbnail_camera(&camera, width, samples, seed);
+        let image = camera.render_image(&World::build(shapes));
+        variations.push(Variation { seed, image });
+    }
+    Ok(variations)
+}
+
+/// Lays `variations`, which should all be the same size, out in a grid about as wide as it is
+/// tall, in order left to right and top to bottom, each with its seed in its top left corner.
+/// Returns `None` if there aren't any.
+pub fn contact_sheet(variations: &[Variation]) -> Option<Image> {
+    let first = &variations.first()?.image;
+    let (thumb_width, thumb_height) = (first.width, first.height);
+    let columns = (variations.len() as Float).sqrt().ceil() as usize;
+    let rows = variations.len().div_ceil(columns);
+    let width = columns * (thumb_width + GAP) + GAP;
+    let height = rows * (thumb_height + GAP) + GAP;
+    let mut pixels = vec![Vec3::repeat(BACKGROUND); width * height];
+    for (k, variation) in variations.iter().enumerate() {
+        let left = GAP + (k % columns) * (thumb_width + GAP);
+        let top = GAP + (k / columns) * (thumb_height + GAP);
+        let image = &variation.image;
+        for y in 0..thumb_height.min(image.height) {
+            for x in 0..thumb_width.min(image.width) {
+                pixels[(top + y) * width + left + x] = image.pixels[y * image.width + x];
+            }
+        }
+        let label = glyphs::number(variation.seed);
+        let margin = 2 * LABEL_SCALE;
+        glyphs::stamp(&label, LABEL_SCALE, |x, y, lit| {
+            let (x, y) = (margin + x, margin + y);
+            if x < thumb_width && y < thumb_height {
+                let shade = if lit { 1.0 } else { 0.02 };
+                pixels[(top + y) * width + left + x] = Vec3::repeat(shade);
+            }
+        });
+    }
+    let seeds = variations
+        .iter()
+        .map(|variation| variation.seed.to_string());
+    Some(Image {
+        pixels,
+        width,
+        height,
+        gamma: first.gamma,
+        metadata: vec![format!("seeds: {}", seeds.collect::<Vec<_>>().join(", "))],
+        alpha: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Shape, material::Material, texture::TextureEnum};
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::{collections::HashSet, sync::Arc};
+
+    /// Where each image the spheres of `shapes` are textured with lives in memory
+    fn sphere_textures(shapes: &[Shape]) -> HashSet<*const Image> {
+        shapes
+            .iter()
+            .filter_map(|shape| match shape {
+                Shape::Sphere(sphere) => match sphere.material.as_ref() {
+                    Material::Lambertian(lambertian) => match &lambertian.texture {
+                        TextureEnum::ImageTexture(texture) => Some(Arc::as_ptr(&texture.image)),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thumbnails_differ_and_render_again_from_their_seed() {
+        let variations = render_variations("cover", &[3, 4], 24, 1).unwrap();
+        let [first, second] = &variations[..] else {
+            panic!("{} variations", variations.len());
+        };
+        assert_eq!((first.seed, second.seed), (3, 4));
+        assert!(first.image.pixels != second.image.pixels);
+
+        // The way `rt --headless --scene cover --seed 4` lays it out, at the thumbnail's size
+        let (camera, shapes, surroundings) = scenes::built_in_scene("cover", 4).unwrap();
+        let camera = thumbnail_camera(&camera, 24, 1, 4);
+        let again = camera.render_image(&surroundings.build(shapes));
+        assert_eq!((again.width, again.height), (24, second.image.height));
+        assert!(again.pixels == second.image.pixels);
+
+        assert!(render_variations("earth", &[1], 24, 1).is_err());
+    }
+
+    #[test]
+    fn layouts_share_one_copy_of_each_texture() {
+        let materials = CoverMaterials::load();
+        let camera = scenes::cam1();
+        let mut textures = HashSet::new();
+        for seed in 0..8 {
+            // A corner of the cover's grid, laid out the way the whole scene is
+            let mut rng = StdRng::seed_from_u64(seed);
+            let shapes = scenes::cover_scene_with(&materials, 4, 4, &camera, -0.2, &mut rng);
+            textures.extend(sphere_textures(&shapes));
+            // However many layouts there are, only the one image is textured with
+            assert_eq!(textures.len(), 1, "after {} seeds", seed + 1);
+        }
+    }
+
+    #[test]
+    fn sheets_lay_thumbnails_out_in_a_grid() {
+        let thumbnail = |seed: u64, shade: Float| Variation {
+            seed,
+            image: Image {
+                pixels: vec![Vec3::repeat(shade); 40 * 30],
+                width: 40,
+                height: 30,
+                gamma: 2.2,
+                metadata: Vec::new(),
+                alpha: None,
+            },
+        };
+        let variations: Vec<_> = (0..5)
+            .map(|k| thumbnail(k, 0.5 + 0.1 * k as Float))
+            .collect();
+        let sheet = contact_sheet(&variations).unwrap();
+        // Three across and two down
+        assert_eq!((sheet.width, sheet.height), (3 * 44 + GAP, 2 * 34 + GAP));
+        assert_eq!(sheet.metadata, ["seeds: 0, 1, 2, 3, 4"]);
+        // The bottom right corner of each thumbnail, clear of its label
+        for (k, variation) in variations.iter().enumerate() {
+            let (x, y) = (GAP + (k % 3) * 44 + 39, GAP + (k / 3) * 34 + 29);
+            assert_eq!(sheet[(x, y)], variation.image.pixels[0]);
+        }
+        // The empty sixth slot is background
+        assert_eq!(
+            sheet[(GAP + 2 * 44 + 20, GAP + 34 + 15)],
+            Vec3::repeat(BACKGROUND)
+        );
+        assert!(contact_sheet(&[]).is_none());
+    }
+}
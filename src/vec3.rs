@@ -1,15 +1,20 @@
 use crate::camera::Float;
 use rand::distributions::{Distribution, Uniform};
-use rand::thread_rng;
 use rand::Rng;
+use std::f64::consts::PI;
 
+/// The crate's only ray type. It's the BVH's own, so rays are traversed without converting
+/// them, and has no time since nothing moves during a frame.
 pub type Ray = bvh::ray::Ray<Float, 3>;
+/// Directions, colors and, for now, points
 pub type Vec3 = nalgebra::Vector3<Float>;
+/// Texture coordinates and other 2D values
 pub type Vec2 = nalgebra::Vector2<Float>;
 // pub type Point3 = nalgebra::Point3<Float>;
 pub type Point3 = nalgebra::Vector3<Float>; // TODO: make this use Point3 instead
 
 pub trait RayExt {
+    /// The point `time` lengths of the ray's direction along it from its origin
     fn at(&self, time: Float) -> Vec3;
 }
 
@@ -29,7 +34,9 @@ pub trait Vec3Ext {
     fn random<R: Rng + ?Sized>(rng: &mut R, min: Float, max: Float) -> Self;
     fn random_unit<R: Rng + ?Sized>(rng: &mut R) -> Self;
     fn random_in_unit_disc<R: Rng + ?Sized>(rng: &mut R) -> Self;
-    fn random_on_hemisphere(normal: &Vec3) -> Vec3;
+    fn from_unit_square_to_disc(u: Float, v: Float) -> Self;
+    fn random_on_hemisphere<R: Rng + ?Sized>(rng: &mut R, normal: &Vec3) -> Vec3;
+    fn random_in_cone<R: Rng + ?Sized>(rng: &mut R, axis: &Vec3, half_angle: Float) -> Self;
 }
 
 impl Vec3Ext for Vec3 {
@@ -115,32 +122,210 @@ impl Vec3Ext for Vec3 {
         Vec3::new(range.sample(rng), range.sample(rng), range.sample(rng))
     }
 
+    /// Returns a random unit vector, uniformly distributed over the sphere
     fn random_unit<R: Rng + ?Sized>(rng: &mut R) -> Self {
-        Self::random(rng, -1.0, 1.0).normalize()
+        // Normalizing a point in the cube would favor its corners, so only accept points in the
+        // ball. That succeeds ~52% of the time, so hitting this limit means the RNG is broken.
+        const MAX_ATTEMPTS: usize = 64;
+        for _ in 0..MAX_ATTEMPTS {
+            let v = Self::random(rng, -1.0, 1.0);
+            let norm_squared = v.norm_squared();
+            if norm_squared > 1e-12 && norm_squared <= 1.0 {
+                return v / norm_squared.sqrt();
+            }
+        }
+        Vec3::z() // Deterministic fallback
     }
 
     // TODO: make this not actually random (QMC sampling)
     /// Returns random point in the x-y unit disc
     fn random_in_unit_disc<R: Rng + ?Sized>(rng: &mut R) -> Self {
-        let mut v = Vec3::ONE;
+        // Rejection sampling succeeds ~79% of the time, so hitting this limit means the RNG is broken
+        const MAX_ATTEMPTS: usize = 64;
         let range = -1.0..1.0;
-        while v.norm_squared() > 1.0 {
-            v = Self::new(
+        for _ in 0..MAX_ATTEMPTS {
+            let v = Self::new(
                 rng.gen_range(range.clone()),
                 rng.gen_range(range.clone()),
                 0.0,
             );
+            if v.norm_squared() <= 1.0 {
+                return v;
+            }
+        }
+        Vec3::zeros() // Deterministic fallback: the center of the disc
+    }
+
+    /// Maps `(u, v)` in the unit square to the x-y unit disc with Shirley and Chiu's concentric
+    /// mapping, which keeps stratified points stratified and evenly spread, unlike rejection
+    /// sampling
+    fn from_unit_square_to_disc(u: Float, v: Float) -> Self {
+        let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::zeros();
         }
-        v
+        let (radius, theta) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
+        } else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Self::new(radius * theta.cos(), radius * theta.sin(), 0.0)
     }
 
     /// Returns a random vector in the unit hemisphere with the input `normal` as its pole
-    fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
-        let unit_vector: Vec3 = Vec3::random_unit(&mut thread_rng());
+    fn random_on_hemisphere<R: Rng + ?Sized>(rng: &mut R, normal: &Vec3) -> Vec3 {
+        let unit_vector: Vec3 = Vec3::random_unit(rng);
         if unit_vector.dot(normal) > 0.0 {
             unit_vector
         } else {
             -unit_vector
         }
     }
+
+    /// Returns a unit vector within `half_angle` radians of the unit vector `axis`, uniformly
+    /// over the solid angle
+    fn random_in_cone<R: Rng + ?Sized>(rng: &mut R, axis: &Vec3, half_angle: Float) -> Self {
+        let cos_theta = 1.0 - rng.gen::<Float>() * (1.0 - half_angle.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<Float>();
+        let helper = if axis.x.abs() > 0.9 {
+            Vec3::y()
+        } else {
+            Vec3::x()
+        };
+        let u = axis.cross(&helper).normalize();
+        let v = axis.cross(&u);
+        (u * phi.cos() * sin_theta + v * phi.sin() * sin_theta + axis * cos_theta).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SampleRng;
+
+    const TOLERANCE: Float = 1e-12;
+
+    fn rng() -> SampleRng {
+        SampleRng::seeded(11, 0, 0, 0)
+    }
+
+    #[test]
+    fn at_steps_along_the_unnormalized_direction() {
+        let ray = Ray {
+            origin: nalgebra::Point3::new(1.0, 2.0, 3.0),
+            direction: Vec3::new(0.0, 2.0, 0.0),
+            inv_direction: Vec3::new(Float::INFINITY, 0.5, Float::INFINITY),
+        };
+        assert_eq!(ray.at(0.0), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(ray.at(1.5), Vec3::new(1.0, 5.0, 3.0));
+        assert_eq!(ray.at(-1.0), Vec3::new(1.0, 0.0, 3.0));
+    }
+
+    #[test]
+    fn near_zero_checks_every_component() {
+        let tiny = Float::EPSILON.sqrt() / 2.0;
+        assert!(Vec3::zeros().near_zero());
+        assert!(Vec3::new(tiny, -tiny, tiny).near_zero());
+        assert!(!Vec3::new(tiny, 1e-3, tiny).near_zero());
+        assert!(!Vec3::new(tiny, tiny, -1e-3).near_zero());
+    }
+
+    #[test]
+    fn random_units_have_unit_length() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            assert!((Vec3::random_unit(&mut rng).norm() - 1.0).abs() < TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn random_units_are_spread_over_the_sphere() {
+        let mut rng = rng();
+        let count = 20_000;
+        let mean = (0..count)
+            .map(|_| Vec3::random_unit(&mut rng))
+            .sum::<Vec3>()
+            / count as Float;
+        assert!(mean.norm() < 0.03, "mean {:?}", mean);
+    }
+
+    #[test]
+    fn random_disc_points_stay_in_the_disc() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let point = Vec3::random_in_unit_disc(&mut rng);
+            assert!(point.norm_squared() <= 1.0);
+            assert_eq!(point.z, 0.0);
+        }
+    }
+
+    #[test]
+    fn square_to_disc_stays_in_the_disc_and_reaches_its_rim() {
+        for i in 0..=10 {
+            for j in 0..=10 {
+                let (u, v) = (i as Float / 10.0, j as Float / 10.0);
+                let point = Vec3::from_unit_square_to_disc(u, v);
+                assert!(point.norm() <= 1.0 + TOLERANCE, "({}, {})", u, v);
+                if i == 0 || i == 10 || j == 0 || j == 10 {
+                    assert!((point.norm() - 1.0).abs() < TOLERANCE, "({}, {})", u, v);
+                }
+            }
+        }
+        assert_eq!(Vec3::from_unit_square_to_disc(0.5, 0.5), Vec3::zeros());
+    }
+
+    #[test]
+    fn hemisphere_samples_face_the_normal() {
+        let mut rng = rng();
+        let normal = Vec3::new(1.0, -2.0, 0.5).normalize();
+        for _ in 0..1000 {
+            let direction = Vec3::random_on_hemisphere(&mut rng, &normal);
+            assert!(direction.dot(&normal) >= 0.0);
+            assert!((direction.norm() - 1.0).abs() < TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn cone_samples_stay_within_the_angle() {
+        let mut rng = rng();
+        let axis = Vec3::new(0.3, 0.4, -0.5).normalize();
+        let half_angle: Float = 0.2;
+        for _ in 0..1000 {
+            let direction = Vec3::random_in_cone(&mut rng, &axis, half_angle);
+            assert!((direction.norm() - 1.0).abs() < TOLERANCE);
+            assert!(direction.dot(&axis) >= half_angle.cos() - TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn random_stays_in_range() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let v = Vec3::random(&mut rng, -2.0, 3.0);
+            assert!(v.iter().all(|c| (-2.0..=3.0).contains(c)));
+        }
+    }
+
+    #[test]
+    fn gamma_keeps_black_white_and_brightens_midtones() {
+        assert_eq!(Vec3::zeros().as_gamma_vec(), Vec3::zeros());
+        assert_eq!(Vec3::ONE.as_gamma_vec(), Vec3::ONE);
+        let gray = Vec3::new(0.5, 0.5, 0.5).as_gamma_vec();
+        assert!((gray.x - 0.5_f64.powf(1.0 / 2.2)).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn rgb_conversions_round_to_bytes() {
+        let color = Vec3::new(0.0, 0.5, 1.0);
+        assert_eq!(color.as_rgb_linear(), (0, 128, 255));
+        assert_eq!(color.as_rgb_gamma(), (0, 186, 255));
+        assert_eq!(color.as_rgb_gamma_string(), "0 186 255");
+    }
+
+    #[test]
+    #[should_panic(expected = "green/y")]
+    fn rgb_conversions_reject_out_of_range_colors() {
+        Vec3::new(0.5, 1.5, 0.5).as_rgb_linear();
+    }
 }
@@ -0,0 +1,364 @@
+use crate::{camera::Camera, hittable::World, validation};
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Where the ids of threads and watchdogs come from, so each is told apart for the life of the
+/// process
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(0);
+static NEXT_WATCHDOG: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static THREAD_ID: usize = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+    /// The slot this thread last worked in, with the id of its watchdog, so finding it again
+    /// for every ray doesn't take a lock
+    static CURRENT_SLOT: RefCell<Option<(u64, Arc<WorkerSlot>)>> = const { RefCell::new(None) };
+}
+
+/// What a single render worker is currently doing, plus when it last made progress
+#[derive(Default)]
+struct WorkerSlot {
+    busy: AtomicBool,
+    x: AtomicUsize,
+    y: AtomicUsize,
+    sample: AtomicUsize,
+    depth: AtomicUsize,
+    /// Milliseconds since the watchdog was created
+    heartbeat_ms: AtomicU64,
+    /// Nanoseconds since the watchdog was created when the current sample started
+    sample_start_ns: AtomicU64,
+    /// Rays the current sample has traced so far
+    sample_rays: AtomicU64,
+}
+
+/// What one sample cost, as [`Watchdog::end_sample`] measured it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SampleCost {
+    /// Time from [`Watchdog::begin_sample`] to [`Watchdog::end_sample`], everything included
+    pub nanos: u64,
+    /// Segments of the sample's path, like [`PathStats::traced_rays`]. Shadow rays aren't
+    /// counted, so this undercounts the cost of directly lit surfaces.
+    pub rays: u64,
+}
+
+/// Shared bookkeeping that lets a monitor thread notice when a render worker stops making progress.
+/// Each thread gets a slot of its own the first time it renders, whichever pool it's in, if any.
+pub struct Watchdog {
+    id: u64,
+    /// One for every thread that has rendered, with that thread's id
+    slots: Mutex<Vec<(usize, Arc<WorkerSlot>)>>,
+    start: Instant,
+    /// Number of paths that were cut short because they kept hitting the same point
+    zero_advance_terminations: AtomicUsize,
+    finished_samples: AtomicU64,
+    /// Rays traced by every finished sample, counting each segment of their paths
+    traced_rays: AtomicU64,
+    /// Time spent on every finished sample together, in nanoseconds
+    sample_ns: AtomicU64,
+}
+
+/// How long paths got and how long they took, averaged over every sample the watchdog saw
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PathStats {
+    pub samples: u64,
+    pub traced_rays: u64,
+    /// Summed over every thread, so it's more than the wall time when rendering in parallel
+    pub sample_time: Duration,
+    /// Paths cut off for hitting the same spot over and over
+    pub zero_advance_terminations: usize,
+}
+
+impl PathStats {
+    pub fn mean_path_length(&self) -> f64 {
+        self.traced_rays as f64 / self.samples.max(1) as f64
+    }
+
+    pub fn mean_sample_time(&self) -> Duration {
+        Duration::from_secs_f64(self.sample_time.as_secs_f64() / self.samples.max(1) as f64)
+    }
+}
+
+impl std::fmt::Display for PathStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} samples, {:.2} rays per path, {:.2} µs per sample",
+            self.samples,
+            self.mean_path_length(),
+            self.mean_sample_time().as_secs_f64() * 1e6
+        )?;
+        if self.zero_advance_terminations > 0 {
+            write!(
+                f,
+                ", {} stuck paths cut off",
+                self.zero_advance_terminations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog::new()
+    }
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Watchdog {
+            id: NEXT_WATCHDOG.fetch_add(1, Ordering::Relaxed),
+            slots: Mutex::new(Vec::new()),
+            start: Instant::now(),
+            zero_advance_terminations: AtomicUsize::new(0),
+            finished_samples: AtomicU64::new(0),
+            traced_rays: AtomicU64::new(0),
+            sample_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `f` on the calling thread's slot, adding one if it hasn't rendered before
+    fn with_slot<R>(&self, f: impl FnOnce(&WorkerSlot) -> R) -> R {
+        CURRENT_SLOT.with(|current| {
+            let mut current = current.borrow_mut();
+            if !matches!(&*current, Some((watchdog, _)) if *watchdog == self.id) {
+                let thread = THREAD_ID.with(|id| *id);
+                let mut slots = self.slots.lock().unwrap();
+                let slot = match slots.iter().find(|(owner, _)| *owner == thread) {
+                    Some((_, slot)) => slot.clone(),
+                    None => {
+                        let slot = Arc::new(WorkerSlot::default());
+                        slots.push((thread, slot.clone()));
+                        slot
+                    }
+                };
+                *current = Some((self.id, slot));
+            }
+            f(&current.as_ref().expect("set above").1)
+        })
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn now_ns(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+
+    /// Records that the calling worker has started tracing sample `sample` of pixel `(x, y)`
+    pub fn begin_sample(&self, x: usize, y: usize, sample: usize) {
+        validation::enter_pixel(x, y);
+        self.with_slot(|slot| {
+            slot.x.store(x, Ordering::Relaxed);
+            slot.y.store(y, Ordering::Relaxed);
+            slot.sample.store(sample, Ordering::Relaxed);
+            slot.depth.store(0, Ordering::Relaxed);
+            slot.heartbeat_ms.store(self.now_ms(), Ordering::Relaxed);
+            slot.sample_start_ns.store(self.now_ns(), Ordering::Relaxed);
+            slot.sample_rays.store(0, Ordering::Relaxed);
+            slot.busy.store(true, Ordering::Release);
+        });
+    }
+
+    /// Records the bounce depth the calling worker has reached, once per ray it traces. Doesn't
+    /// touch the heartbeat, so a path that bounces forever still shows up as stalled. Rays are
+    /// only counted in the worker's own slot here, so threads don't contend over one counter.
+    pub fn record_depth(&self, depth: usize) {
+        self.with_slot(|slot| {
+            slot.depth.store(depth, Ordering::Relaxed);
+            slot.sample_rays.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    /// Marks the calling worker as idle, returning what its sample cost, and adds its rays to
+    /// the total
+    pub fn end_sample(&self) -> SampleCost {
+        let cost = self.with_slot(|slot| {
+            let started = slot.sample_start_ns.load(Ordering::Relaxed);
+            slot.busy.store(false, Ordering::Release);
+            SampleCost {
+                nanos: self.now_ns().saturating_sub(started),
+                rays: slot.sample_rays.load(Ordering::Relaxed),
+            }
+        });
+        self.sample_ns.fetch_add(cost.nanos, Ordering::Relaxed);
+        self.traced_rays.fetch_add(cost.rays, Ordering::Relaxed);
+        self.finished_samples.fetch_add(1, Ordering::Relaxed);
+        cost
+    }
+
+    pub fn path_stats(&self) -> PathStats {
+        PathStats {
+            samples: self.finished_samples.load(Ordering::Relaxed),
+            traced_rays: self.traced_rays.load(Ordering::Relaxed),
+            sample_time: Duration::from_nanos(self.sample_ns.load(Ordering::Relaxed)),
+            zero_advance_terminations: self.zero_advance_terminations(),
+        }
+    }
+
+    pub fn record_zero_advance_termination(&self) {
+        self.zero_advance_terminations
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn zero_advance_terminations(&self) -> usize {
+        self.zero_advance_terminations.load(Ordering::Relaxed)
+    }
+
+    /// Returns `(x, y, sample, depth, stalled_for)` for every busy worker whose heartbeat is older
+    /// than `threshold`
+    pub fn stalled_workers(
+        &self,
+        threshold: Duration,
+    ) -> Vec<(usize, usize, usize, usize, Duration)> {
+        let now = self.now_ms();
+        let slots = self.slots.lock().unwrap();
+        slots
+            .iter()
+            .map(|(_, slot)| slot)
+            .filter(|slot| slot.busy.load(Ordering::Acquire))
+            .filter_map(|slot| {
+                let stalled_for = Duration::from_millis(
+                    now.saturating_sub(slot.heartbeat_ms.load(Ordering::Relaxed)),
+                );
+                (stalled_for >= threshold).then(|| {
+                    (
+                        slot.x.load(Ordering::Relaxed),
+                        slot.y.load(Ordering::Relaxed),
+                        slot.sample.load(Ordering::Relaxed),
+                        slot.depth.load(Ordering::Relaxed),
+                        stalled_for,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Spawns a thread that checks the camera's watchdog every `interval` and reports any worker that
+/// has been stuck on the same sample for longer than `threshold`. Exits once `closing` is set.
+pub fn spawn_monitor(
+    camera: Arc<Camera>,
+    world: Arc<World>,
+    closing: Arc<AtomicBool>,
+    interval: Duration,
+    threshold: Duration,
+) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .name("watchdog_thread".into())
+        .spawn(move || {
+            let scene_hash = world.scene_hash();
+            while !closing.load(Ordering::Relaxed) {
+                std::thread::sleep(interval);
+                for (x, y, sample, depth, stalled_for) in
+                    camera.watchdog.stalled_workers(threshold)
+                {
+                    let ray = camera.debug_ray(x as f64, y as f64);
+                    eprintln!(
+                        "Watchdog: render worker stalled for {:.1}s on pixel ({}, {}), sample {}, depth {}\n\
+                         camera ray origin {:?} direction {:?}, scene hash {:016x}",
+                        stalled_for.as_secs_f64(),
+                        x,
+                        y,
+                        sample,
+                        depth,
+                        ray.origin,
+                        ray.direction,
+                        scene_hash,
+                    );
+                }
+            }
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn threads_in_any_pool_get_slots_of_their_own() {
+        let watchdog = Watchdog::new();
+        // Two pools whose threads have the same indices, and the calling thread outside both
+        let pools: Vec<rayon::ThreadPool> = (0..2)
+            .map(|_| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(2)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let started = Barrier::new(5);
+        let finish = Barrier::new(5);
+        let work = |x: usize| {
+            watchdog.begin_sample(x, 0, 0);
+            started.wait();
+            finish.wait();
+            watchdog.end_sample();
+        };
+        std::thread::scope(|scope| {
+            for (p, pool) in pools.iter().enumerate() {
+                let work = &work;
+                scope.spawn(move || {
+                    pool.broadcast(|context| work(2 * p + context.index() + 1));
+                });
+            }
+            watchdog.begin_sample(0, 0, 0);
+            started.wait();
+            let mut busy: Vec<usize> = watchdog
+                .stalled_workers(Duration::ZERO)
+                .into_iter()
+                .map(|(x, ..)| x)
+                .collect();
+            busy.sort_unstable();
+            assert_eq!(busy, vec![0, 1, 2, 3, 4]);
+            finish.wait();
+            watchdog.end_sample();
+        });
+        assert!(watchdog.stalled_workers(Duration::ZERO).is_empty());
+        assert_eq!(watchdog.path_stats().samples, 5);
+    }
+
+    #[test]
+    fn mean_sample_times_survive_more_samples_than_fit_in_32_bits() {
+        let stats = PathStats {
+            samples: 3 << 32,
+            sample_time: Duration::from_secs(6 << 32),
+            ..PathStats::default()
+        };
+        assert_eq!(stats.mean_sample_time(), Duration::from_secs(2));
+        assert_eq!(PathStats::default().mean_sample_time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rays_count_towards_the_total_once_their_sample_ends() {
+        let watchdog = Watchdog::new();
+        std::thread::scope(|scope| {
+            for x in 0..4 {
+                let watchdog = &watchdog;
+                scope.spawn(move || {
+                    for sample in 0..10 {
+                        watchdog.begin_sample(x, 0, sample);
+                        for depth in 0..=x {
+                            watchdog.record_depth(depth);
+                        }
+                        assert_eq!(watchdog.end_sample().rays, x as u64 + 1);
+                    }
+                });
+            }
+        });
+        watchdog.begin_sample(0, 1, 0);
+        watchdog.record_depth(0);
+        // 10 samples each of 1, 2, 3 and 4 rays, and none from the sample still going
+        assert_eq!(watchdog.path_stats().traced_rays, 100);
+        watchdog.end_sample();
+        assert_eq!(watchdog.path_stats().traced_rays, 101);
+    }
+}
@@ -1,36 +1,171 @@
 use crate::{
-    camera::{Camera, Float, Image},
-    hittable::World,
-    vec3::{Vec3, Vec3Ext},
+    bracket::ev_label,
+    camera::{self, Camera, Float, Image, Integrator},
+    compare::{CompareMode, Comparison},
+    controls::CameraController,
+    convergence::{self, PixelMoments, StopCriterion},
+    cost::{CostMap, CostMetric, CostScale},
+    display::{DisplayBuffer, DisplayWriter},
+    glyphs::{self, Glyph},
+    gpu::GpuPrimary,
+    hittable::{Shape, Surroundings, World},
+    job::{HandoffAction, HandoffSettings, RenderJob},
+    multiview,
+    perf::{self, PerfLog, SessionHeader, SweepRecord},
+    presenter::{PixelsPresenter, Presented, Presenter, SurfaceGuard, AUTOSAVE_INTERVAL},
+    proxy::{ProxyGrid, PROXY_RESOLUTION},
+    raster,
+    scopes::{self, ScopeMode},
+    session::{Session, SessionEvent, SessionPlayer, SessionRecorder},
+    sky_cache::{PixelState, SkyCache, MIN_SKY_SAMPLES},
+    snapshot::SceneSnapshot,
+    tiles::{Accumulation, TileRenderer},
+    vec3::Vec3,
+    watchdog,
 };
-use core::array;
-use indicatif::ParallelProgressIterator;
-use pixels::{Error, Pixels, SurfaceTexture};
+use indicatif::{ParallelProgressIterator, ProgressBar};
+use pixels::Error;
 use rayon::{
-    iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
-    slice::ParallelSlice,
+    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
 };
 use std::{
-    fs::File,
-    ops::Deref,
+    io,
+    path::Path,
+    rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        mpsc::{self, Receiver},
+        Arc, Mutex, OnceLock, PoisonError,
     },
+    thread::JoinHandle,
     time::{Duration, Instant},
 };
 use winit::{
-    dpi::{LogicalPosition, LogicalSize, PhysicalPosition},
-    event::{ElementState, Event, MouseButton, WindowEvent},
+    dpi::{LogicalSize, PhysicalPosition},
+    event::{
+        ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode,
+        WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
+/// Resolution the built-in cameras are made with. The preview opens at whatever resolution its
+/// camera has, and renders at the window's size once it's resized.
 pub const WIDTH: u32 = 800;
 pub const HEIGHT: u32 = 600;
+/// Rows of the preview rendered between publishing frames when not rendering on tiles
+const PUBLISH_ROWS: usize = 32;
+
+/// Progressive sweeps that give every pixel the same samples, so each has a variance to go by
+/// before later sweeps share their samples out by it
+const UNIFORM_SWEEPS: usize = 4;
+/// How long the camera has to stay still after moving before draft frames give way to path
+/// tracing again
+const DRAFT_SETTLE: Duration = Duration::from_millis(200);
+
+/// The "PROXY" watermark stamped on frames rendered from the scene proxy
+const PROXY_WATERMARK: [Glyph; 5] = [
+    [0b111, 0b101, 0b111, 0b100, 0b100],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010],
+];
+/// The "RASTER" watermark stamped on rasterized frames
+const RASTER_WATERMARK: [Glyph; 6] = [
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110],
+    [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+];
+/// Where a preview that's lost its display saves what it's rendered every so often
+const AUTOSAVE_PATH: &str = "preview_autosave.png";
+/// Pixels per watermark glyph dot
+const WATERMARK_SCALE: usize = 4;
+
+/// Where the preview gets its scene from
+pub enum PreviewScene {
+    /// A world that's already built
+    Ready(Box<World>),
+    /// Shapes whose world is built in the surroundings in the background while the preview
+    /// shows a blocky proxy of them, so there's something to frame the shot with from the first
+    /// moment
+    Loading(Vec<Shape>, Box<Surroundings>),
+}
+
+/// Changes made in the preview window that the render thread has to pick up
+pub enum SceneEdit {
+    /// Replaces the camera and restarts accumulation from scratch
+    Camera(Arc<Camera>),
+    /// Replaces the camera with one at another resolution, which is published to a display
+    /// buffer of that size from then on
+    Resize(Arc<Camera>, DisplayWriter),
+}
+
+/// Whether the preview records what's done in it, or replays a recording, see
+/// [`crate::session`]
+pub enum PreviewSession {
+    Off,
+    /// Records every edit sent to the render thread
+    Record(SessionRecorder),
+    /// Makes the recorded edits again, at `speed` times the speed they were made at. The
+    /// camera should already be the session's starting one.
+    Replay {
+        session: Box<Session>,
+        speed: Float,
+    },
+}
 
-// TODO: figure out how to apply gamma correction to the preview in a performant way
 pub fn render_with_preview(camera: Camera, world: World) -> Result<(), Error> {
+    render_with_handoff(
+        camera,
+        PreviewScene::Ready(Box::new(world)),
+        HandoffSettings::default(),
+        None,
+        None,
+        false,
+        PreviewSession::Off,
+        CostScale::default(),
+    )
+}
+
+/// Opens the interactive preview. Pressing F12 closes it and hands the current view off to a
+/// final render as described by `handoff`. With a `comparison`, V cycles between the live
+/// render, a split view against the reference that's dragged with the left mouse button, and
+/// their difference. With `tiles`, sweeps are rendered tile by tile on its threads and shown
+/// once each is done, instead of pixel by pixel on the global pool. A `scene` that's still
+/// loading shows a voxel proxy, titled and watermarked as such, until its world is built. With
+/// `gpu_primary`, camera rays' first hits are found on the GPU when there is one and the scene
+/// allows it; this is ignored when rendering on tiles. While the camera moves, frames are shaded
+/// with the draft integrator so they keep up, which T turns off and on. + and - change the
+/// exposure a stop at a time, which the handoff keeps, and the title shows it. The preview opens
+/// at the camera's resolution, and resizing the window renders from scratch at its new size,
+/// which the handoff keeps too. L cycles through drawing a luminance histogram of what's shown in
+/// the bottom left corner, that and a waveform beside it, and neither, with how much of the frame
+/// is clipped or crushed in the title while they're on. `session` records the edits made, or
+/// replays them, after which the camera can be moved on from where the replay left it. H cycles
+/// through showing what each pixel's samples cost in time, in rays, and neither, in false color
+/// up to `cost_scale`, with the cost at the top of the ramp in the title. The first frame, and
+/// with drafts off the first after every camera edit, starts out as the scene's shapes
+/// rasterized and watermarked "RASTER", see [`raster::rasterize`], which the sweeps paint over.
+/// If the window's surface is lost, it's recreated a few times before the preview gives up on
+/// showing frames, see [`SurfaceGuard`], and the render carries on unshown, saved to
+/// `preview_autosave.png` every few minutes and to `preview_out.png` on closing as usual.
+#[allow(clippy::too_many_arguments)]
+pub fn render_with_handoff(
+    camera: Camera,
+    scene: PreviewScene,
+    handoff: HandoffSettings,
+    mut comparison: Option<Comparison>,
+    tiles: Option<TileRenderer>,
+    gpu_primary: bool,
+    session: PreviewSession,
+    cost_scale: CostScale,
+) -> Result<(), Error> {
     let update_interval = Duration::from_secs_f32(1.0 / 30.0); // 30 FPS
     let start_time = Instant::now();
 
@@ -40,45 +175,89 @@ pub fn render_with_preview(camera: Camera, world: World) -> Result<(), Error> {
     // or just the unstable portable SIMD feature https://doc.rust-lang.org/std/simd/index.html
 
     // Initialized to 0xff so that the alpha channel is 255, since alpha isn't updated in the render loop
-    let render_buffer = Arc::new(RwLock::new([0xffu8; (WIDTH * HEIGHT * 4) as usize]));
+    let (width, height) = (camera.image_width, camera.image_height);
+    let (mut render_buffer, display_writer) = DisplayBuffer::new(width * height * 4, 0xff);
 
     let event_loop = EventLoop::new();
-    let size = LogicalSize::new(WIDTH, HEIGHT);
+    let size = LogicalSize::new(width as u32, height as u32);
 
     let camera = Arc::new(camera); // To share the camera between different threads.
-    let world = Arc::new(world);
+                                   // Set once the world is built, which a loading scene's is on the render thread
+    let world: Arc<OnceLock<Arc<World>>> = Arc::new(OnceLock::new());
+    let shapes = match scene {
+        PreviewScene::Ready(ready) => {
+            let _ = world.set(Arc::from(ready));
+            None
+        }
+        PreviewScene::Loading(shapes, surroundings) => Some((shapes, *surroundings)),
+    };
+    let mut loading = shapes.is_some();
+    let (mut recorder, mut replay) = match session {
+        PreviewSession::Off => (None, None),
+        PreviewSession::Record(recorder) => (Some(recorder), None),
+        PreviewSession::Replay { session, speed } => {
+            (None, Some((SessionPlayer::new(&session), speed)))
+        }
+    };
+    if let (Some(recorder), Some(world)) = (&mut recorder, world.get()) {
+        recorder.record(&SessionEvent::SceneLoaded(world.snapshot().fingerprint()));
+    }
 
     let window = WindowBuilder::new()
         .with_visible(false)
-        .with_title("Ray Tracer Preview")
+        .with_title(preview_title(loading, camera.post_process.exposure))
         .with_inner_size(size)
-        .with_min_inner_size(size)
         .build(&event_loop)
         .unwrap();
+    let window = Rc::new(window);
 
-    let mut pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture)?
-    };
+    let mut presenter = PixelsPresenter::new(window.clone(), width as u32, height as u32)?;
+    // Losing the surface only stops frames being shown, never the render
+    let mut surface = SurfaceGuard::default();
+    let mut autosave: Option<JoinHandle<io::Result<()>>> = None;
+    let mut last_autosave = Instant::now();
 
     // TODO: maybe use a Condvar for this? https://doc.rust-lang.org/std/sync/struct.Condvar.html
     // (Only if bored tho cause this already works just fine)
     let closing = Arc::new(AtomicBool::new(false));
+    // Set when an edit is sent so the render thread can abandon its current sweep
+    let restart = Arc::new(AtomicBool::new(false));
+    // Whether the camera moving switches to draft frames
+    let draft = Arc::new(AtomicBool::new(true));
+    let (edit_sender, edit_receiver) = mpsc::channel();
+    // What every pixel's samples have cost in the current render, replaced when it's resized
+    let costs = Arc::new(Mutex::new(Arc::new(CostMap::new(width, height))));
+    // Every pixel's samples at full precision, which the display buffer is only an encoding of
+    let accumulated = Arc::new(Mutex::new(Accumulation::new(width, height)));
 
     window.set_visible(true);
 
     // Ray tracing thread
     std::thread::Builder::new()
         .name("rt_thread".into())
-        .stack_size((WIDTH * HEIGHT * 4 * 3) as usize) // Avoid stack overflow at high res
         .spawn({
-            let render_buffer = render_buffer.clone();
             let closing = closing.clone();
+            let restart = restart.clone();
+            let draft = draft.clone();
             let camera = camera.clone();
             let world = world.clone();
+            let costs = costs.clone();
+            let accumulated = accumulated.clone();
             move || {
-                render_thread(camera, world, render_buffer, &closing);
+                render_thread(
+                    camera,
+                    world,
+                    shapes,
+                    display_writer,
+                    &closing,
+                    &restart,
+                    edit_receiver,
+                    tiles,
+                    gpu_primary,
+                    &draft,
+                    &costs,
+                    &accumulated,
+                );
             }
         })
         .unwrap();
@@ -86,6 +265,18 @@ pub fn render_with_preview(camera: Camera, world: World) -> Result<(), Error> {
     // Preview window event loop
     let mut last_update = Instant::now();
     let mut cursor_position: Option<PhysicalPosition<f64>> = None;
+    let mut modifiers = ModifiersState::empty();
+    let mut last_cursor_move = Instant::now();
+    let mut last_tick = Instant::now();
+    let mut camera = camera;
+    let mut controller = CameraController::new(&camera);
+    let mut save_thread: Option<JoinHandle<io::Result<()>>> = None;
+    // Set while the left button drags the comparison split instead of the camera
+    let mut dragging_split = false;
+    // The last frame the render thread published that's been copied to the window
+    let mut shown_epoch = usize::MAX;
+    let mut scope_mode = ScopeMode::Off;
+    let mut cost_metric = None;
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
@@ -94,120 +285,570 @@ pub fn render_with_preview(camera: Camera, world: World) -> Result<(), Error> {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
+                if save_thread.is_some() {
+                    // Already saving, so the window will close once that's done
+                    return;
+                }
                 // Write the image as it is on close request
                 println!(
                     "Total rendering time: {} seconds",
                     start_time.elapsed().as_secs_f64()
                 );
                 closing.store(true, Ordering::Relaxed);
-                let write_thread = std::thread::Builder::new()
+                window.set_title("Ray Tracer Preview (saving...)");
+                let spawned = std::thread::Builder::new()
                     .name("write_thread".into())
-                    .stack_size((WIDTH * HEIGHT * 4 * 3) as usize) // Avoid stack overflow
                     .spawn({
                         let render_buffer = render_buffer.clone();
+                        let accumulated = accumulated.clone();
+                        let camera = camera.clone();
+                        let mut metadata = vec![format!("fidelity: {}", camera.fidelity.name())];
+                        if camera.post_process.exposure != 0.0 {
+                            metadata.push(format!(
+                                "exposure: {} EV",
+                                ev_label(camera.post_process.exposure)
+                            ));
+                        }
+                        match world.get() {
+                            Some(world) => metadata.push(format!(
+                                "scene fingerprint: {:016x}",
+                                world.snapshot().fingerprint()
+                            )),
+                            None => metadata.push("proxy: the scene was still loading".into()),
+                        }
                         move || {
-                            let out_file = File::create("preview_out.ppm").unwrap();
-                            let mut copy_buf = [0u8; (WIDTH * HEIGHT * 4) as usize];
-                            {
-                                let buffer = render_buffer.read().unwrap();
-                                copy_buf.clone_from_slice(buffer.deref());
-                            }
-                            let pixels = copy_buf
-                                .par_chunks(4)
-                                .enumerate()
-                                .map(|(i, chunk)| {
-                                    let x = i % WIDTH as usize;
-                                    let y = i / WIDTH as usize;
-                                    let c = Vec3::new(
-                                        chunk[0] as Float / 255.0,
-                                        chunk[1] as Float / 255.0,
-                                        chunk[2] as Float / 255.0,
-                                    );
-                                    (x, y, c)
-                                })
-                                .collect::<_>();
-                            let image = Image {
-                                pixels,
-                                width: WIDTH as usize,
-                                height: HEIGHT as usize,
-                            };
-                            Camera::write_image(image, out_file).unwrap();
+                            // Waits for the render thread to stop
+                            let accumulation =
+                                accumulated.lock().unwrap_or_else(PoisonError::into_inner);
+                            save_preview(
+                                &render_buffer,
+                                Some(&accumulation),
+                                &camera,
+                                "preview_out.png",
+                                metadata,
+                            )
                         }
-                    })
-                    .unwrap();
-                write_thread.join().unwrap();
-                *control_flow = ControlFlow::Exit
+                    });
+                match spawned {
+                    Ok(handle) => save_thread = Some(handle),
+                    Err(err) => {
+                        println!("Failed to start saving the preview: {}", err);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::MouseInput { state, button, .. },
                 ..
             } => {
-                if button == MouseButton::Left && state == ElementState::Pressed {
-                    if let Some(physical_pos) = cursor_position {
-                        let logical_pos: LogicalPosition<f64> =
-                            physical_pos.to_logical(window.scale_factor());
-                        let (x, y) = (logical_pos.x, logical_pos.y);
-                        let dray = camera.debug_ray(x - 1.0, y - 1.0); // offset for 0-idx
-
-                        if let Some((hit, _color, _maybe_reflected_ray)) =
-                            camera.debug_raycast(&world, &dray)
-                        {
-                            // if let Some(ray) = maybe_reflected_ray {
-                            //     println!(
-                            //         "Input ray at\n{:?}\nan object of color\n{:?}\nthen reflected to\n{:?}",
-                            //         dray, color, ray
-                            //     );
-                            // } else {
-                            //     println!(
-                            //         "Input ray:\n{:?}\nhit object of color\n{:?}\nand was absorbed",
-                            //         dray, dray
-                            //     );
-                            // }
-                            println!("Hit info:\n{:?}", hit);
-                        } else {
-                            println!("Ray missed any objects (hit the skybox).");
-                        }
+                let splitting = comparison
+                    .as_ref()
+                    .is_some_and(|comparison| comparison.mode == CompareMode::Split);
+                if button == MouseButton::Left && (splitting || dragging_split) {
+                    dragging_split = state == ElementState::Pressed;
+                    if let (Some(comparison), Some(position)) = (&mut comparison, cursor_position) {
+                        comparison.split = position.x / window.inner_size().width as Float;
                     }
+                    return;
+                }
+                controller.mouse_input(button, state);
+                if button != MouseButton::Left || state != ElementState::Pressed {
+                    return;
                 }
+                let Some(world) = world.get() else {
+                    println!("The scene is still loading, so there's nothing to pick yet");
+                    return;
+                };
+                if let Some(physical_pos) = cursor_position {
+                    // The image is scaled to fit the window, so window and image pixels differ
+                    let position = (physical_pos.x as f32, physical_pos.y as f32);
+                    let (x, y) = presenter.pixel_at(position);
+                    if modifiers.ctrl() {
+                        // Ctrl-click logs every bounce of one sample through the pixel
+                        println!("{}", camera.trace_sample(world, x, y, 0));
+                        return;
+                    }
+                    let dray = camera.debug_ray(x as f64, y as f64);
+
+                    if let Some((hit, _color, _maybe_reflected_ray)) =
+                        camera.debug_raycast(world, &dray)
+                    {
+                        println!("Hit info for {}:\n{:?}", hit.object, hit);
+                        controller.select(hit.point); // Press F to orbit around this point
+                    } else {
+                        println!("Ray missed any objects (hit the skybox).");
+                    }
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => {
+                modifiers = state;
             }
             Event::WindowEvent {
                 event: WindowEvent::CursorMoved { position, .. },
                 ..
             } => {
                 cursor_position = Some(position);
+                if dragging_split {
+                    if let Some(comparison) = &mut comparison {
+                        comparison.split = position.x / window.inner_size().width as Float;
+                    }
+                    return;
+                }
+                let dt = last_cursor_move.elapsed().as_secs_f64();
+                last_cursor_move = Instant::now();
+                controller.cursor_moved(position, dt, &camera);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => {
+                controller.scroll(delta);
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::F),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                controller.focus_selection();
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::T),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let enabled = !draft.fetch_xor(true, Ordering::Relaxed);
+                if let Some(recorder) = &mut recorder {
+                    recorder.record(&SessionEvent::Draft(enabled));
+                }
+                println!(
+                    "Draft frames while the camera moves: {}",
+                    if enabled { "on" } else { "off" }
+                );
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::V),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => match &mut comparison {
+                Some(comparison)
+                    if comparison.size() != (camera.image_width, camera.image_height) =>
+                {
+                    let (width, height) = comparison.size();
+                    println!(
+                        "The reference is {}x{} but the preview is now {}x{}, so they can't be \
+                         compared until the window is that size again",
+                        width, height, camera.image_width, camera.image_height
+                    );
+                }
+                Some(comparison) => {
+                    comparison.mode = comparison.mode.next();
+                    dragging_split = false;
+                    // Redraws the live render when leaving a comparison
+                    shown_epoch = usize::MAX;
+                    let (largest, differing) =
+                        render_buffer.read(|buffer| comparison.difference_stats(buffer));
+                    println!(
+                        "Comparing against the reference: {} ({} pixels differ, by at most {}/255)",
+                        comparison.mode.name(),
+                        differing,
+                        largest
+                    );
+                }
+                None => println!(
+                    "No reference image to compare against (start with --reference <image>)"
+                ),
+            },
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::L),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                scope_mode = scope_mode.next();
+                // Redraws the frame without the scopes, or with the new ones
+                shown_epoch = usize::MAX;
+                if scope_mode == ScopeMode::Off {
+                    window.set_title(&preview_title(loading, camera.post_process.exposure));
+                }
+                println!("Scopes: {}", scope_mode.name());
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::H),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                cost_metric = match cost_metric {
+                    None => Some(CostMetric::Time),
+                    Some(CostMetric::Time) => Some(CostMetric::Rays),
+                    Some(CostMetric::Rays) => None,
+                };
+                // Redraws the live render when leaving the heatmap
+                shown_epoch = usize::MAX;
+                if cost_metric.is_none() {
+                    window.set_title(&preview_title(loading, camera.post_process.exposure));
+                }
+                println!(
+                    "Cost heatmap: {}",
+                    cost_metric.map_or("off", |metric| metric.name())
+                );
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode:
+                                    Some(
+                                        key @ (VirtualKeyCode::Equals
+                                        | VirtualKeyCode::Plus
+                                        | VirtualKeyCode::NumpadAdd
+                                        | VirtualKeyCode::Minus
+                                        | VirtualKeyCode::NumpadSubtract),
+                                    ),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let stops = match key {
+                    VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => -1.0,
+                    _ => 1.0,
+                };
+                let mut exposed = (*camera).clone();
+                exposed.post_process.exposure += stops;
+                camera = Arc::new(exposed);
+                let _ = edit_sender.send(SceneEdit::Camera(camera.clone()));
+                restart.store(true, Ordering::Relaxed);
+                if let Some(recorder) = &mut recorder {
+                    recorder.record(&SessionEvent::view(&camera));
+                }
+                window.set_title(&preview_title(loading, camera.post_process.exposure));
+                println!(
+                    "Showing the render at {} EV",
+                    ev_label(camera.post_process.exposure)
+                );
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                if save_thread.is_some() {
+                    return;
+                }
+                let Some(world) = world.get().cloned() else {
+                    println!("The scene is still loading, so it can't be handed off yet");
+                    return;
+                };
+                // Stop the preview first so the final render gets every core
+                closing.store(true, Ordering::Relaxed);
+                window.set_visible(false);
+                let job = RenderJob::from_camera(&camera, &world, &handoff);
+                let spawned = std::thread::Builder::new()
+                    .name("handoff_thread".into())
+                    .spawn({
+                        let action = handoff.action.clone();
+                        move || match action {
+                            HandoffAction::RenderNow => {
+                                println!(
+                                    "Handing off to a final {}x{} render at {} samples per pixel",
+                                    job.width, job.height, job.samples_per_pixel
+                                );
+                                job.run(&world)
+                            }
+                            HandoffAction::WriteJob(path) => {
+                                job.save(&path)?;
+                                println!(
+                                    "Wrote render job to {} (run it with `rt render {}`)",
+                                    path, path
+                                );
+                                Ok(())
+                            }
+                        }
+                    });
+                match spawned {
+                    Ok(handle) => save_thread = Some(handle),
+                    Err(err) => {
+                        println!("Failed to start the final render: {}", err);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(key),
+                                state,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                controller.key_input(key, state);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } => {
+                controller.release_keys();
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(new_size),
                 ..
             } => {
-                if let Err(err) = pixels.resize_surface(new_size.width, new_size.height) {
-                    println!("pixels.resize_surface error {}", err);
-                    *control_flow = ControlFlow::Exit;
+                if let Err(err) = presenter.resize_surface(new_size.width, new_size.height) {
+                    surface.lost(&err, Instant::now());
+                }
+                // The preview renders a pixel per logical pixel of the window
+                let size: LogicalSize<u32> = new_size.to_logical(window.scale_factor());
+                let (width, height) = (size.width as usize, size.height as usize);
+                // Minimized windows have no size, and the frame can't be handed off mid-render
+                if width == 0 || height == 0 || save_thread.is_some() {
+                    return;
+                }
+                if (width, height) == (camera.image_width, camera.image_height) {
+                    return;
+                }
+                // The render keeps its size if the buffer can't have the new one
+                if let Err(err) = presenter.resize_buffer(width as u32, height as u32) {
+                    surface.lost(&err, Instant::now());
+                    return;
+                }
+                let (buffer, writer) = DisplayBuffer::new(width * height * 4, 0xff);
+                render_buffer = buffer;
+                // Keeps the window's last frame until the new buffer has one of its own
+                shown_epoch = 0;
+                camera = Arc::new(camera.with_resolution(width, height));
+                let _ = edit_sender.send(SceneEdit::Resize(camera.clone(), writer));
+                restart.store(true, Ordering::Relaxed);
+                if let Some(recorder) = &mut recorder {
+                    recorder.record(&SessionEvent::Resize { width, height });
                 }
             }
             Event::MainEventsCleared => {
+                if save_thread
+                    .as_ref()
+                    .is_some_and(|handle| handle.is_finished())
+                {
+                    match save_thread.take().unwrap().join() {
+                        Ok(Ok(())) => (),
+                        Ok(Err(err)) => println!("Failed to save: {}", err),
+                        Err(_) => println!("Failed to save: write thread panicked"),
+                    }
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                if autosave.as_ref().is_some_and(|handle| handle.is_finished()) {
+                    match autosave.take().unwrap().join() {
+                        Ok(Ok(())) => (),
+                        Ok(Err(err)) => println!("Failed to autosave: {}", err),
+                        Err(_) => println!("Failed to autosave: autosave thread panicked"),
+                    }
+                }
+                // Nothing's shown, so what's been rendered is saved every so often instead
+                if surface.is_degraded()
+                    && save_thread.is_none()
+                    && autosave.is_none()
+                    && last_autosave.elapsed() >= AUTOSAVE_INTERVAL
+                {
+                    last_autosave = Instant::now();
+                    let spawned = std::thread::Builder::new()
+                        .name("autosave_thread".into())
+                        .spawn({
+                            let render_buffer = render_buffer.clone();
+                            let accumulated = accumulated.clone();
+                            let camera = camera.clone();
+                            move || {
+                                // The render thread holds the samples while it renders, so
+                                // they're only used if it's between renders
+                                let accumulation = accumulated.try_lock().ok();
+                                save_preview(
+                                    &render_buffer,
+                                    accumulation.as_deref(),
+                                    &camera,
+                                    AUTOSAVE_PATH,
+                                    vec!["autosave: the preview's display was lost".into()],
+                                )
+                            }
+                        });
+                    match spawned {
+                        Ok(handle) => autosave = Some(handle),
+                        Err(err) => println!("Failed to start autosaving: {}", err),
+                    }
+                }
+                if let (true, Some(world)) = (loading, world.get()) {
+                    loading = false;
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(&SessionEvent::SceneLoaded(world.snapshot().fingerprint()));
+                    }
+                    window.set_title(&preview_title(loading, camera.post_process.exposure));
+                }
+                controller.tick(last_tick.elapsed().as_secs_f64());
+                last_tick = Instant::now();
+                if let Some(moved) = controller.take_camera(&camera) {
+                    camera = Arc::new(moved);
+                    // Sent before flagging the restart so the render thread always finds it
+                    // The render thread only exits once closing, so this can't fail before then
+                    let _ = edit_sender.send(SceneEdit::Camera(camera.clone()));
+                    restart.store(true, Ordering::Relaxed);
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(&SessionEvent::view(&camera));
+                    }
+                }
+                if let Some((player, speed)) = &mut replay {
+                    for entry in player.due(start_time.elapsed().as_secs_f64() * *speed) {
+                        match entry.event {
+                            // Resizing the window makes the edit, just like the user doing it
+                            SessionEvent::Resize { width, height } => {
+                                window.set_inner_size(LogicalSize::new(width as u32, height as u32))
+                            }
+                            SessionEvent::Draft(enabled) => draft.store(enabled, Ordering::Relaxed),
+                            SessionEvent::SceneLoaded(_) => (),
+                            SessionEvent::View { .. } => {
+                                if let Some(moved) = entry.event.apply(&camera) {
+                                    camera = Arc::new(moved);
+                                    let _ = edit_sender.send(SceneEdit::Camera(camera.clone()));
+                                    restart.store(true, Ordering::Relaxed);
+                                    // Moving on from the replayed view rather than the last one
+                                    controller = CameraController::new(&camera);
+                                    window.set_title(&preview_title(
+                                        loading,
+                                        camera.post_process.exposure,
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                    if player.finished() {
+                        println!(
+                            "Replay finished, at {}",
+                            multiview::describe_camera(&camera)
+                        );
+                        replay = None;
+                    }
+                }
                 if last_update.elapsed() >= update_interval {
                     window.request_redraw();
                     last_update = Instant::now();
                 }
             }
             Event::RedrawRequested(_) => {
-                let frame = pixels.frame_mut();
-                // TODO: Find a better way to convert the preview to gamma space. This code is comically slow.
-                // maybe this could be my first foray into GPU code...
-                // frame.par_iter_mut().for_each(|color| {
-                //     let normed = *color as f32 / 255.0;
-                //     *color = (gamma_corrected(normed) * 255.0).round() as u8;
-                // });
-
+                if surface.is_degraded() {
+                    return;
+                }
+                let frame = presenter.frame_mut();
                 // Update the pixel buffer based on the new rays/pixel colors
-                {
-                    let buffer = render_buffer.read().unwrap();
-                    frame.clone_from_slice(buffer.deref());
+                // Comparing only changes what's shown, never the accumulated samples
+                // Never waits on the render thread, which only ever writes the other frame
+                let comparing = comparison.as_ref().is_some_and(|comparison| {
+                    comparison.mode != CompareMode::Off
+                        && comparison.size() == (camera.image_width, camera.image_height)
+                });
+                // The window keeps its frame, so it only needs copying when there's a new one
+                let epoch = render_buffer.epoch();
+                // Costs keep changing without a new frame, so the heatmap is drawn every time
+                if comparing || cost_metric.is_some() || epoch != shown_epoch {
+                    shown_epoch = epoch;
+                    render_buffer.read(|buffer| match &comparison {
+                        Some(comparison) if comparing => comparison.present(buffer, frame),
+                        _ => frame.clone_from_slice(buffer),
+                    });
+                    if let Some(metric) = cost_metric {
+                        let costs = costs.lock().unwrap().clone();
+                        if (costs.width, costs.height) == (camera.image_width, camera.image_height)
+                        {
+                            let max = costs.present(metric, cost_scale, frame);
+                            window.set_title(&format!(
+                                "{} - cost: {}, 0 to {:.2} {}",
+                                preview_title(loading, camera.post_process.exposure),
+                                metric.name(),
+                                max,
+                                metric.unit()
+                            ));
+                        }
+                    }
+                    // Drawn over the copy, so the scopes see exactly what's shown
+                    if let Some(histogram) =
+                        scopes::draw_scopes(frame, camera.image_width, scope_mode)
+                    {
+                        window.set_title(&format!(
+                            "{} - {:.1}% clipped, {:.1}% crushed",
+                            preview_title(loading, camera.post_process.exposure),
+                            histogram.clipped_fraction() * 100.0,
+                            histogram.crushed_fraction() * 100.0
+                        ));
+                    }
                 }
 
-                if pixels.render().is_err() {
-                    *control_flow = ControlFlow::Exit;
+                match surface.present(&mut presenter, Instant::now()) {
+                    Presented::Lost => {
+                        window.set_title("Ray Tracer Preview (display lost, reconnecting...)")
+                    }
+                    Presented::Recovered => {
+                        window.set_title(&preview_title(loading, camera.post_process.exposure))
+                    }
+                    Presented::GaveUp => {
+                        window.set_title(
+                            "Ray Tracer Preview (display lost, still rendering, close to save)",
+                        );
+                        last_autosave = Instant::now();
+                    }
+                    Presented::Shown | Presented::Waiting | Presented::Degraded => (),
                 }
             }
             _ => (),
@@ -216,19 +857,266 @@ pub fn render_with_preview(camera: Camera, world: World) -> Result<(), Error> {
     // Ok(())
 }
 
-// fn gamma_corrected(color_value: Float) -> Float {
-//     let gamma = 1.0 / 2.2;
-//     color_value.powf(gamma)
-// }
+/// Writes what the preview rendered to `path`, as a PNG or PPM depending on its extension, from
+/// the full precision samples in `accumulation` through the preview's exposure. Pixels without
+/// samples yet, like before the first sweep is done or while the scene loads, or all of them
+/// without an `accumulation`, are taken from the last frame the render thread published
+/// instead, so the image is never from partway through writing one.
+fn save_preview(
+    render_buffer: &DisplayBuffer,
+    accumulation: Option<&Accumulation>,
+    camera: &Camera,
+    path: &str,
+    metadata: Vec<String>,
+) -> io::Result<()> {
+    let save_start = Instant::now();
+    let (width, height) = (camera.image_width, camera.image_height);
+    let exposure_scale = camera.post_process.exposure_scale();
+    let accumulation = accumulation
+        .filter(|accumulation| (accumulation.width, accumulation.height) == (width, height));
+    let shown = render_buffer.snapshot();
+    let pixels = shown
+        .par_chunks(4)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let (x, y) = (idx % width, idx / width);
+            if let Some(accumulation) = accumulation.filter(|a| a.samples(x, y) > 0) {
+                return accumulation.color(x, y) * exposure_scale;
+            }
+            Vec3::new(chunk[0] as Float, chunk[1] as Float, chunk[2] as Float)
+                .map(|c| (c / 255.0).powf(camera.gamma))
+        })
+        .collect::<_>();
+    let image = Image {
+        pixels,
+        width,
+        height,
+        gamma: camera.gamma,
+        metadata,
+        alpha: None,
+    };
+    let image = match &camera.quick_denoise {
+        Some(params) => image.denoise(params),
+        None => image,
+    };
+    Camera::save_image(image, Path::new(path))?;
+    println!(
+        "Saved {} in {:.3} seconds",
+        path,
+        save_start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
 
-fn render_thread(
-    camera: Arc<Camera>,
-    world: Arc<World>,
-    render_buffer: Arc<RwLock<[u8; (WIDTH * HEIGHT * 4) as usize]>>,
+fn apply_edit(edit: SceneEdit, camera: &mut Arc<Camera>, display: &mut DisplayWriter) {
+    match edit {
+        SceneEdit::Camera(new_camera) => *camera = new_camera,
+        SceneEdit::Resize(new_camera, writer) => {
+            *camera = new_camera;
+            *display = writer;
+        }
+    }
+}
+
+/// Applies a batch of edits and logs what they changed in the world, so an edit that touches
+/// more than it should shows up right away
+fn apply_edits(
+    edits: impl IntoIterator<Item = SceneEdit>,
+    camera: &mut Arc<Camera>,
+    display: &mut DisplayWriter,
+    world: &World,
+    snapshot: &mut SceneSnapshot,
+) {
+    for edit in edits {
+        apply_edit(edit, camera, display);
+    }
+    let new_snapshot = world.snapshot();
+    println!("Applied scene edits: {}", snapshot.diff(&new_snapshot));
+    *snapshot = new_snapshot;
+}
+
+/// Shows a blocky proxy of `shapes` while their world is built in `surroundings` on a thread of
+/// its own, and sets `world` once it is. Camera edits re-render the proxy in the meantime.
+#[allow(clippy::too_many_arguments)]
+fn show_proxy_while_loading(
+    camera: &mut Arc<Camera>,
+    world: &Arc<OnceLock<Arc<World>>>,
+    shapes: Vec<Shape>,
+    surroundings: Surroundings,
+    display: &mut DisplayWriter,
     closing: &AtomicBool,
+    restart: &AtomicBool,
+    edits: &Receiver<SceneEdit>,
 ) {
-    let render_pixels: [u32; (WIDTH * HEIGHT) as usize] = array::from_fn(|i| i as u32);
+    let load_start = Instant::now();
+    // Only the shapes near the first view make it in, which is plenty for framing the shot
+    let proxy = ProxyGrid::around_camera(&shapes, camera, PROXY_RESOLUTION);
+    let [x, y, z] = proxy.dimensions();
+    println!(
+        "Built a {}x{}x{} scene proxy in {:.3} seconds",
+        x,
+        y,
+        z,
+        load_start.elapsed().as_secs_f64()
+    );
+    let builder = std::thread::Builder::new()
+        .name("bvh_thread".into())
+        .spawn({
+            let world = world.clone();
+            move || {
+                let _ = world.set(Arc::new(surroundings.build(shapes)));
+            }
+        });
+    if let Err(err) = builder {
+        // The shapes went with the failed thread, so there's nothing left to render
+        println!("Failed to start building the scene: {}", err);
+        return;
+    }
+    let mut stale = true;
+    while world.get().is_none() {
+        if closing.load(Ordering::Relaxed) {
+            return;
+        }
+        if restart.swap(false, Ordering::Relaxed) {
+            for edit in edits.try_iter() {
+                apply_edit(edit, camera, display);
+            }
+            stale = true;
+        }
+        if stale {
+            stale = false;
+            let colors = proxy.render(camera);
+            display.publish(|back, _front| {
+                for (pixel, color) in back.chunks_exact_mut(4).zip(&colors) {
+                    pixel[..3].copy_from_slice(&camera::rgb8(color, camera.gamma));
+                }
+                stamp_watermark(back, camera.image_width, &PROXY_WATERMARK);
+            });
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    println!(
+        "Built the scene in {:.3} seconds, switching from the proxy",
+        load_start.elapsed().as_secs_f64()
+    );
+}
+
+/// The preview window's title, with the exposure it's showing unless that's 0 EV
+fn preview_title(loading: bool, exposure: Float) -> String {
+    let mut title = "Ray Tracer Preview".to_string();
+    if loading {
+        title += " (proxy)";
+    }
+    if exposure != 0.0 {
+        title += &format!(" at {} EV", ev_label(exposure));
+    }
+    title
+}
+
+/// Stamps a word of `glyphs`, like [`PROXY_WATERMARK`], into the top left corner of an RGBA
+/// `frame` `width` pixels wide, in white with a dark shadow so it reads on any background
+fn stamp_watermark(frame: &mut [u8], width: usize, glyphs: &[Glyph]) {
+    let margin = 2 * WATERMARK_SCALE;
+    glyphs::stamp(glyphs, WATERMARK_SCALE, |x, y, lit| {
+        let (x, y) = (margin + x, margin + y);
+        let idx = (y * width + x) * 4;
+        if x < width && idx + 3 <= frame.len() {
+            frame[idx..idx + 3].copy_from_slice(&[if lit { 0xff } else { 0x20 }; 3]);
+        }
+    });
+}
 
+/// Publishes the world's shapes rasterized as `camera` sees them, watermarked as such, for a
+/// sharp look at the layout before any ray is traced
+fn show_raster(camera: &Camera, world: &World, display: &mut DisplayWriter) {
+    let raster = raster::rasterize(&world.shapes, camera);
+    display.publish(|back, _front| {
+        for (pixel, color) in back.chunks_exact_mut(4).zip(&raster.colors) {
+            pixel[..3].copy_from_slice(&camera::rgb8(color, camera.gamma));
+        }
+        stamp_watermark(back, camera.image_width, &RASTER_WATERMARK);
+    });
+}
+
+/// Renders the rows in `rows` with their camera rays' first hits found on `gpu`, returning each
+/// pixel's color like `render_pixel` in [`render_thread`]. Returns no colors once `stopped`.
+fn render_band_primary(
+    gpu: &GpuPrimary,
+    camera: &Camera,
+    world: &World,
+    sky_cache: &SkyCache,
+    rows: std::ops::Range<usize>,
+    num_samples: usize,
+    stopped: impl Fn() -> bool,
+) -> Vec<Option<Vec3>> {
+    let width = camera.image_width;
+    let mut colors = vec![None; rows.len() * width];
+    if stopped() {
+        return colors;
+    }
+    let pixels: Vec<(usize, usize)> = rows
+        .clone()
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| sky_cache.state(x, y) != PixelState::SkyConverged)
+        .collect();
+    let rendered = gpu.render_pixels(camera, world, &pixels, num_samples);
+    for (&(x, y), (color, escaped)) in pixels.iter().zip(rendered) {
+        sky_cache.record(x, y, num_samples, escaped);
+        colors[(y - rows.start) * width + x] = Some(color);
+    }
+    colors
+}
+
+/// Encodes a linear `color` for the display buffer with the preview's exposure and the
+/// camera's `gamma`, clamping it
+fn exposed_rgb(color: Vec3, exposure_scale: Float, gamma: Float) -> [u8; 3] {
+    camera::rgb8(&(color * exposure_scale), gamma)
+}
+
+/// Renders a whole frame with the draft integrator at one sample per pixel and publishes it,
+/// unless `stopped` before it's done
+fn render_draft(
+    camera: &Camera,
+    world: &World,
+    display: &mut DisplayWriter,
+    stopped: impl Fn() -> bool + Sync,
+) {
+    let mut camera = camera.clone();
+    // The technical integrator is already noise free from the first sample
+    if camera.integrator != Integrator::Technical {
+        camera.integrator = Integrator::Draft;
+    }
+    let width = camera.image_width;
+    let colors: Option<Vec<Vec3>> = (0..width * camera.image_height)
+        .into_par_iter()
+        .map(|idx| (!stopped()).then(|| camera.render_pixel(world, idx % width, idx / width, 1)))
+        .collect();
+    let Some(colors) = colors else {
+        return;
+    };
+    let scale = camera.post_process.exposure_scale();
+    display.publish(|back, _front| {
+        for (pixel, color) in back.chunks_exact_mut(4).zip(&colors) {
+            pixel[..3].copy_from_slice(&exposed_rgb(*color, scale, camera.gamma));
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_thread(
+    mut camera: Arc<Camera>,
+    world: Arc<OnceLock<Arc<World>>>,
+    shapes: Option<(Vec<Shape>, Surroundings)>,
+    mut display: DisplayWriter,
+    closing: &Arc<AtomicBool>,
+    restart: &AtomicBool,
+    edits: Receiver<SceneEdit>,
+    tiles: Option<TileRenderer>,
+    gpu_primary: bool,
+    draft: &AtomicBool,
+    costs: &Mutex<Arc<CostMap>>,
+    accumulated: &Mutex<Accumulation>,
+) {
     // Does a sweep with a single ray per pixel for a fast preview, then accumulates detail
     let num_samples_at_pass: Vec<usize> = vec![
         // If you want more samples than this, that's YOUR problem
@@ -253,74 +1141,287 @@ fn render_thread(
         })
         .collect();
 
-    // Accumulates samples in multiple passes
-    let first_start = Instant::now();
-    for (i, (num_samples, total_samples)) in num_samples_at_pass
-        .iter()
-        .zip(num_samples_total)
-        .enumerate()
-    {
-        let sweep_start = Instant::now();
-        println!(
-            "On sweep {} adding {} sample(s) for a total of {} sample(s) per pixel",
-            i + 1,
-            num_samples,
-            total_samples,
+    if let Some((shapes, surroundings)) = shapes {
+        show_proxy_while_loading(
+            &mut camera,
+            &world,
+            shapes,
+            surroundings,
+            &mut display,
+            closing,
+            restart,
+            &edits,
         );
-        render_pixels.par_iter().progress().for_each(|idx| {
-            if closing.load(Ordering::Relaxed) {
-                return;
+    }
+    let Some(world) = world.get().cloned() else {
+        return; // Closed before the scene finished loading
+    };
+    // Reports render workers that stop making progress instead of letting them hang silently
+    watchdog::spawn_monitor(
+        camera.clone(),
+        world.clone(),
+        closing.clone(),
+        Duration::from_secs(2),
+        Duration::from_secs(10),
+    );
+
+    let gpu = GpuPrimary::try_new(&world, gpu_primary && tiles.is_none());
+    if let Some(log) = PerfLog::global() {
+        log.session(&SessionHeader {
+            scene_fingerprint: world.snapshot().fingerprint(),
+            width: camera.image_width,
+            height: camera.image_height,
+            max_depth: camera.max_depth(),
+            integrator: camera.integrator.name().to_string(),
+            fidelity: camera.fidelity.name().to_string(),
+            threads: tiles
+                .as_ref()
+                .map_or_else(rayon::current_num_threads, TileRenderer::threads),
+            ..SessionHeader::here("preview")
+        });
+    }
+
+    let mut snapshot = world.snapshot();
+    // Pure sky pixels stop being sampled once they've converged, if the fidelity allows it
+    let mut sky_cache = SkyCache::new(camera.image_width, camera.image_height, MIN_SKY_SAMPLES);
+    // Judges which pixels count as converged in the sweeps' reports
+    let criterion = StopCriterion::default();
+    // Every render after the first was started by an edit, so it starts with draft frames
+    // until the camera has been still for `DRAFT_SETTLE`
+    let mut edited = false;
+    'render: loop {
+        // Held until the render stops or finishes, so saving on close waits for what it
+        // accumulated and never sees a previous view's samples
+        let mut accumulation = accumulated.lock().unwrap();
+        let (width, height) = (camera.image_width, camera.image_height);
+        if (width, height) == (accumulation.width, accumulation.height) {
+            sky_cache.reset();
+            accumulation.clear();
+        } else {
+            sky_cache = SkyCache::new(width, height, MIN_SKY_SAMPLES);
+            *accumulation = Accumulation::new(width, height);
+        }
+        // Sweeps paint over the rasterized frame as they go
+        if !edited || !draft.load(Ordering::Relaxed) {
+            show_raster(&camera, &world, &mut display);
+        }
+        if edited && draft.load(Ordering::Relaxed) {
+            render_draft(&camera, &world, &mut display, || {
+                closing.load(Ordering::Relaxed) || restart.load(Ordering::Relaxed)
+            });
+            let still_since = Instant::now();
+            while still_since.elapsed() < DRAFT_SETTLE {
+                if closing.load(Ordering::Relaxed) {
+                    return;
+                }
+                if restart.swap(false, Ordering::Relaxed) {
+                    let batch = edits.try_iter();
+                    apply_edits(batch, &mut camera, &mut display, &world, &mut snapshot);
+                    continue 'render;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+        let cost_map = {
+            let mut shared = costs.lock().unwrap();
+            if (shared.width, shared.height) == (width, height) {
+                shared.clear();
+            } else {
+                *shared = Arc::new(CostMap::new(width, height));
             }
-            let x = idx % WIDTH;
-            let y = idx / WIDTH;
-            let i = (idx * 4) as usize;
-            let new_color = camera.render_pixel(&world, x as usize, y as usize, *num_samples);
-
-            let old_color = {
-                // This could MAYBE be done without a lock for better performance
-                if let Ok(buffer) = render_buffer.read() {
-                    Vec3::new(
-                        buffer[i] as Float / 255.0,
-                        buffer[i + 1] as Float / 255.0,
-                        buffer[i + 2] as Float / 255.0,
-                    )
-                } else {
-                    panic!("Failed to acquire buffer read lock in ray tracing loop");
+            shared.clone()
+        };
+        let mut sky_converged = 0;
+        // Changing it restarts the render, like any other edit to the camera
+        let exposure_scale = camera.post_process.exposure_scale();
+        let gamma = camera.gamma;
+        let perf_render = PerfLog::global().map(PerfLog::begin_render);
+        // Accumulates samples in multiple passes
+        let first_start = Instant::now();
+        for (i, (num_samples, total_samples)) in num_samples_at_pass
+            .iter()
+            .zip(num_samples_total.iter().copied())
+            .enumerate()
+        {
+            let sweep_start = Instant::now();
+            let traced_before = camera.watchdog.path_stats().traced_rays;
+            println!(
+                "On sweep {} adding {} sample(s) for a total of {} sample(s) per pixel",
+                i + 1,
+                num_samples,
+                total_samples,
+            );
+            // After the first few sweeps, pixels get samples in proportion to how noisy they
+            // still are if the fidelity allows it. The GPU renders whole bands at one count, so
+            // it keeps them even.
+            let adaptive = camera.fidelity.adaptive_sampling() && gpu.is_none();
+            let shares = (i >= UNIFORM_SWEEPS && adaptive).then(|| {
+                let variances: Vec<Option<Float>> = accumulation
+                    .all_moments()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, moments)| {
+                        let (x, y) = (idx % width, idx / width);
+                        (sky_cache.state(x, y) != PixelState::SkyConverged)
+                            .then(|| moments.luminance_variance())
+                    })
+                    .collect();
+                let budget = num_samples * variances.iter().flatten().count();
+                convergence::share_samples(&variances, budget)
+            });
+            let render_pixel = |x: usize, y: usize| {
+                if closing.load(Ordering::Relaxed) || restart.load(Ordering::Relaxed) {
+                    return None;
+                }
+                if sky_cache.state(x, y) == PixelState::SkyConverged {
+                    return None;
                 }
+                let samples = shares
+                    .as_ref()
+                    .map_or(*num_samples, |shares| shares[y * width + x]);
+                let (moments, escaped, cost) = camera.render_pixel_moments(&world, x, y, samples);
+                sky_cache.record(x, y, samples, escaped);
+                cost_map.add(x, y, &cost);
+                Some(moments)
             };
-
-            // Mixes pixel colors proportionally to number of rays used to calculate them
-            let new_ratio = *num_samples as Float / total_samples as Float;
-            let old_ratio = 1.0 - new_ratio;
-            let combined_color = (new_color * new_ratio) + (old_color * old_ratio);
-
-            // Colors must be in a linear color space to accumulate correctly.
-            // The math relies on linearity. Gamma is nonlinear.
-            // Using a gamma color space with c <- sqrt(c) within the range [0, 1]
-            // all colors tends toward white under repeated gamma correction, since sqrt(x) > x for 0 < x < 1
-            let (r, g, b) = combined_color.as_rgb_linear();
-
-            if let Ok(mut buffer) = render_buffer.write() {
-                buffer[i] = r;
-                buffer[i + 1] = g;
-                buffer[i + 2] = b;
-                // buffer[i + 3] is the alpha channel. Should always contain 0xff.
+            if let Some(tiles) = &tiles {
+                tiles.render_sweep_moments(&mut accumulation, render_pixel);
+                // Recomputed from the running mean every sweep, so it follows the converged
+                // bright spots instead of the latest sweep's noise
+                let flare = camera
+                    .post_process
+                    .flare
+                    .as_ref()
+                    .map(|flare| flare.light(width, height, |x, y| accumulation.color(x, y)));
+                // Published once per sweep, covering every pixel
+                display.publish(|back, _front| {
+                    for (idx, pixel) in back.chunks_exact_mut(4).enumerate() {
+                        let (x, y) = (idx % width, idx / width);
+                        let mut color = accumulation.color(x, y);
+                        if let Some(flare) = &flare {
+                            color += flare[idx];
+                        }
+                        pixel[..3].copy_from_slice(&exposed_rgb(color, exposure_scale, gamma));
+                    }
+                });
             } else {
-                panic!("Failed to acquire buffer write lock in ray tracing loop");
+                let progress = ProgressBar::new((width * height) as u64);
+                // Published every few rows so the sweep shows up as it goes
+                for band_start in (0..height).step_by(PUBLISH_ROWS) {
+                    let band_end = (band_start + PUBLISH_ROWS).min(height);
+                    let band_pixels = band_start * width..band_end * width;
+                    // With a GPU, the band's camera rays are all traced at once up front
+                    match &gpu {
+                        Some(gpu) => {
+                            let colors = render_band_primary(
+                                gpu,
+                                &camera,
+                                &world,
+                                &sky_cache,
+                                band_start..band_end,
+                                *num_samples,
+                                || {
+                                    closing.load(Ordering::Relaxed)
+                                        || restart.load(Ordering::Relaxed)
+                                },
+                            );
+                            for (idx, color) in band_pixels.clone().zip(colors) {
+                                if let Some(color) = color {
+                                    accumulation.add(idx % width, idx / width, color, *num_samples);
+                                }
+                            }
+                            progress.inc(band_pixels.len() as u64);
+                        }
+                        None => {
+                            let rendered: Vec<Option<PixelMoments>> = band_pixels
+                                .clone()
+                                .into_par_iter()
+                                .progress_with(progress.clone())
+                                .map(|idx| render_pixel(idx % width, idx / width))
+                                .collect();
+                            for (idx, moments) in band_pixels.clone().zip(rendered) {
+                                if let Some(moments) = moments {
+                                    accumulation.add_moments(idx % width, idx / width, &moments);
+                                }
+                            }
+                        }
+                    }
+                    // The whole band comes from the float buffer, so pixels the sweep skipped
+                    // keep their colors
+                    let band = band_pixels.start * 4..band_pixels.end * 4;
+                    display.publish_range(band, |back| {
+                        back.par_chunks_exact_mut(4)
+                            .enumerate()
+                            .for_each(|(j, pixel)| {
+                                let idx = band_pixels.start + j;
+                                let color = accumulation.color(idx % width, idx / width);
+                                pixel[..3].copy_from_slice(&exposed_rgb(
+                                    color,
+                                    exposure_scale,
+                                    gamma,
+                                ));
+                            });
+                    });
+                    if closing.load(Ordering::Relaxed) || restart.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                progress.finish();
+            }
+            if closing.load(Ordering::Relaxed) {
+                return;
+            }
+            if restart.swap(false, Ordering::Relaxed) {
+                // Start accumulating from scratch with the edited scene
+                let batch = edits.try_iter();
+                apply_edits(batch, &mut camera, &mut display, &world, &mut snapshot);
+                edited = true;
+                continue 'render;
+            }
+            let sweep_duration = sweep_start.elapsed().as_secs_f64();
+            let total_duration = first_start.elapsed().as_secs_f64();
+            let sampled_pixels = width * height - sky_converged;
+            let total_rays_this_sweep = num_samples * sampled_pixels;
+            let total_rays = total_samples * width * height;
+            let converged = criterion.converged_fraction(accumulation.all_moments());
+            println!(
+                "Rendered sweep {} in {:.3} seconds at {:.1} million rays/second, skipping {} \
+                 converged sky pixels, overall speed: {:.1} Mray/s, {:.1}% of pixels converged",
+                i + 1,
+                sweep_duration,
+                total_rays_this_sweep as f64 / 1_000_000.0 / sweep_duration,
+                sky_converged,
+                total_rays as f64 / 1_000_000.0 / total_duration,
+                converged * 100.0,
+            );
+            // Pixels only ever get skipped once marked converged here
+            if camera.fidelity.sky_cache() {
+                sky_converged = sky_cache.update();
+            }
+            if let (Some(log), Some(render)) = (PerfLog::global(), perf_render) {
+                log.sweep(&SweepRecord {
+                    render,
+                    sweep: i + 1,
+                    samples_added: *num_samples,
+                    total_samples,
+                    seconds: sweep_duration,
+                    camera_rays: total_rays_this_sweep as u64,
+                    traced_rays: perf::traced_since(&camera.watchdog, traced_before),
+                    converged_fraction: Some(sky_converged as f64 / (width * height) as f64),
+                });
             }
-        });
-        if closing.load(Ordering::Relaxed) {
-            return;
         }
-        let sweep_duration = sweep_start.elapsed().as_secs_f64();
-        let total_duration = first_start.elapsed().as_secs_f64();
-        let total_rays_this_sweep = num_samples * WIDTH as usize * HEIGHT as usize;
-        let total_rays = total_samples * WIDTH as usize * HEIGHT as usize;
-        println!(
-            "Rendered sweep {} at {:.1} million rays/second, overall speed: {:.1} Mray/s",
-            i + 1,
-            total_rays_this_sweep as f64 / 1_000_000.0 / sweep_duration,
-            total_rays as f64 / 1_000_000.0 / total_duration,
-        );
+
+        // Every sweep is done, so wait for the next edit before rendering again
+        drop(accumulation);
+        match edits.recv() {
+            Ok(edit) => {
+                restart.store(false, Ordering::Relaxed);
+                let batch = std::iter::once(edit).chain(edits.try_iter());
+                apply_edits(batch, &mut camera, &mut display, &world, &mut snapshot);
+                edited = true;
+            }
+            Err(_) => return, // The window is gone
+        }
     }
 }
@@ -0,0 +1,48 @@
+//! Replays sessions recorded in the preview and checks they end on the same image as when they
+//! were recorded, which is the only coverage the preview's camera controls get
+
+use rt::{
+    scenes,
+    session::{self, Session},
+    vec3::Vec3,
+};
+
+/// Replays the session at `path` under tests/fixtures headlessly, returning the fingerprint of
+/// the final view as `rt --headless --replay` prints it
+fn replay(path: &str) -> (rt::camera::Camera, u64) {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), path);
+    let session = Session::load(path).unwrap();
+    let scene = session.start.scene.as_deref().unwrap();
+    let (camera, shapes, surroundings) =
+        scenes::built_in_scene(scene, scenes::BUILT_IN_COVER_SEED).unwrap();
+    let world = surroundings.build(shapes);
+    session.check_scene(&world).unwrap();
+    let camera = session::replay_headless(&session, &camera);
+    let fingerprint = session::image_fingerprint(&camera.render_image(&world));
+    (camera, fingerprint)
+}
+
+#[test]
+fn cornell_orbit_replays_to_the_recorded_image() {
+    let (camera, fingerprint) = replay("cornell_orbit.ron");
+    assert_eq!(camera.center, Vec3::new(0.9, -1.36, 0.55));
+    assert_eq!((camera.image_width, camera.image_height), (24, 18));
+    assert_eq!(camera.post_process.exposure, 1.0);
+    assert_eq!(camera.seed, Some(7));
+    assert_eq!(fingerprint, 0x3d3d_184d_8096_fcdb, "{:016x}", fingerprint);
+}
+
+#[test]
+fn replays_refuse_other_scenes() {
+    let path = format!(
+        "{}/tests/fixtures/cornell_orbit.ron",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let session = Session::load(path).unwrap();
+    let (_, shapes, surroundings) =
+        scenes::built_in_scene("enclosed_room", scenes::BUILT_IN_COVER_SEED).unwrap();
+    let err = session
+        .check_scene(&surroundings.build(shapes))
+        .unwrap_err();
+    assert!(err.contains("08f58752a0f2e3a7"), "{}", err);
+}
@@ -0,0 +1,210 @@
+//! Streams renders to disk and checks they come out the same as rendering in memory, that memory
+//! stays flat however large the image, and what's left behind when a render fails. Allocations
+//! are counted for the whole process, so the tests run one at a time.
+
+use rt::{
+    camera::Camera,
+    hittable::{Sphere, World},
+    material::Lambertian,
+    postprocess::{FilmGrain, LensFlare, PostProcess},
+    streaming::{self, StreamError},
+    tonemap::Tonemap,
+    vec3::Vec3,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+/// The system allocator, keeping count of how many bytes are allocated and the most there have
+/// been at once
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = System.alloc(layout);
+        if !pointer.is_null() {
+            let now = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(now, Ordering::Relaxed);
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+/// Held by every test, so none's allocations count towards another's peak
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A path in the temporary directory for this test's output
+fn scratch(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rt_streaming_{}_{}", std::process::id(), name))
+}
+
+/// A gray ball over a gradient sky, in a seeded view
+fn ball(width: usize, height: usize, samples: usize) -> (Camera, World) {
+    let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    let world = World::build(vec![Sphere::new(Vec3::zeros(), 1.0, gray).into()]);
+    let mut camera = Camera::builder()
+        .with_look_from(Vec3::new(0.0, -4.0, 0.5))
+        .with_look_at(Vec3::zeros())
+        .with_vertical_fov(40.0)
+        .with_resolution(width, height)
+        .with_samples(samples)
+        .with_max_depth(4)
+        .build()
+        .unwrap();
+    camera.seed = Some(3);
+    (camera, world)
+}
+
+/// The pixels of a binary PPM, which are its last `width * height * 3` bytes whatever comments
+/// its header has
+fn ppm_pixels(bytes: &[u8], width: usize, height: usize) -> &[u8] {
+    &bytes[bytes.len() - width * height * 3..]
+}
+
+#[test]
+fn streamed_pixels_are_the_bytes_an_in_memory_render_writes() {
+    let _serial = serial();
+    // Not a multiple of the tile size, so the edge tiles are partial
+    let (width, height) = (100, 70);
+    let (camera, world) = ball(width, height, 4);
+    let post_process = PostProcess {
+        exposure: 0.5,
+        tonemap: Some(Tonemap::default()),
+        grain: Some(FilmGrain::default()),
+        ..PostProcess::default()
+    };
+    let path = scratch("identical.ppm");
+    streaming::render_streaming(&camera, &world, &post_process, Vec::new(), &path, None).unwrap();
+    let streamed = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let image = post_process.apply(&camera.render_image(&world));
+    let in_memory = image.encode_ppm();
+    assert_eq!(
+        ppm_pixels(&streamed, width, height),
+        ppm_pixels(&in_memory, width, height)
+    );
+}
+
+#[test]
+fn streaming_memory_stays_flat_however_large_the_image() {
+    let _serial = serial();
+    // A fixed number of threads, since as many tiles are rendered at once as there are threads
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+    let peak = |size: usize| {
+        let (camera, world) = ball(size, size, 1);
+        let path = scratch(&format!("memory_{}.ppm", size));
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        PEAK.store(before, Ordering::Relaxed);
+        pool.install(|| {
+            streaming::render_streaming(
+                &camera,
+                &world,
+                &PostProcess::default(),
+                Vec::new(),
+                &path,
+                None,
+            )
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        PEAK.load(Ordering::Relaxed) - before
+    };
+    let small = peak(128);
+    let large = peak(1024);
+    // The 1024x1024 image alone would take 3 MB as 8-bit RGB, and 24 MB as colors
+    const BUDGET: usize = 512 * 1024;
+    assert!(small < BUDGET, "{} bytes at 128x128", small);
+    assert!(large < BUDGET, "{} bytes at 1024x1024", large);
+    assert!(
+        large < 2 * small,
+        "{} bytes at 128x128, {} at 1024x1024",
+        small,
+        large
+    );
+}
+
+#[test]
+fn finished_renders_leave_no_partial_file() {
+    let _serial = serial();
+    let (camera, world) = ball(16, 16, 1);
+    let path = scratch("finished.ppm");
+    streaming::render_streaming(
+        &camera,
+        &world,
+        &PostProcess::default(),
+        Vec::new(),
+        &path,
+        None,
+    )
+    .unwrap();
+    assert!(path.is_file());
+    assert!(!streaming::partial_path(&path).exists());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn failed_renders_never_touch_the_output() {
+    let _serial = serial();
+    let (camera, world) = ball(16, 16, 1);
+    let path = scratch("failed.ppm");
+    let partial = streaming::partial_path(&path);
+
+    // Refused before anything is written
+    fs::write(&path, b"an earlier render").unwrap();
+    let flare = PostProcess {
+        flare: Some(LensFlare::default()),
+        ..PostProcess::default()
+    };
+    let err = streaming::render_streaming(&camera, &world, &flare, Vec::new(), &path, None);
+    assert!(matches!(err, Err(StreamError::NeedsWholeFrame(_))));
+    assert_eq!(fs::read(&path).unwrap(), b"an earlier render");
+    assert!(!partial.exists());
+    fs::remove_file(&path).unwrap();
+
+    // Failing at the end, since a directory is in the way, keeps the pixels in the partial file
+    fs::create_dir(&path).unwrap();
+    let err = streaming::render_streaming(
+        &camera,
+        &world,
+        &PostProcess::default(),
+        Vec::new(),
+        &path,
+        None,
+    );
+    assert!(matches!(err, Err(StreamError::Io(_))));
+    assert!(path.is_dir());
+    let expected = PostProcess::default()
+        .apply(&camera.render_image(&world))
+        .encode_ppm();
+    assert_eq!(
+        ppm_pixels(&fs::read(&partial).unwrap(), 16, 16),
+        ppm_pixels(&expected, 16, 16)
+    );
+    fs::remove_dir(&path).unwrap();
+    fs::remove_file(&partial).unwrap();
+}
@@ -0,0 +1,139 @@
+//! Breaks each boundary of the color pipeline on purpose and checks the violation is reported
+//! at that stage, and that the golden scenes render without any. Violations are counted for the
+//! whole process, so these run in a test binary of their own and one at a time.
+
+use rt::{
+    benchmark::BenchScene,
+    camera::{Camera, Float},
+    hittable::{Sphere, World},
+    material::Lambertian,
+    postprocess::PostProcess,
+    validation::{self, Stage, ValidationReport},
+    vec3::Vec3,
+};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Held by every test, so none sees another's violations
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn serial() -> MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    // Forgets anything left over from a test that failed
+    validation::take_report();
+    guard
+}
+
+/// A gray ball filling most of a tiny, seeded view
+fn ball() -> (Camera, World) {
+    let gray = Arc::new(Lambertian::new_rgb_solid(0.5, 0.5, 0.5).into());
+    let world = World::build(vec![Sphere::new(Vec3::zeros(), 1.0, gray).into()]);
+    let mut camera = Camera::builder()
+        .with_look_from(Vec3::new(0.0, -4.0, 0.0))
+        .with_look_at(Vec3::zeros())
+        .with_vertical_fov(30.0)
+        .with_resolution(4, 4)
+        .with_samples(4)
+        .with_max_depth(4)
+        .build()
+        .unwrap();
+    camera.seed = Some(1);
+    (camera, world)
+}
+
+/// Checks `report` has exactly one violation, at `stage`, with `problem`
+fn assert_only(report: &ValidationReport, stage: Stage, problem: &str) {
+    assert_eq!(report.total(), 1, "{}", report);
+    assert_eq!(report.count(stage), 1, "{}", report);
+    let violation = &report.examples[0];
+    assert_eq!(violation.stage, stage);
+    assert_eq!(violation.problem, problem);
+    let (x, y) = violation.pixel.expect("the pixel is known at every stage");
+    assert!(x < 4 && y < 4, "{}", violation);
+}
+
+#[test]
+fn texture_fetches_are_checked() {
+    let _serial = serial();
+    let (camera, world) = ball();
+    validation::inject(Stage::TextureFetch, Vec3::new(0.5, Float::NAN, 0.5));
+    camera.render_image(&world);
+    assert_only(
+        &validation::take_report(),
+        Stage::TextureFetch,
+        "not finite",
+    );
+}
+
+#[test]
+fn scatter_attenuations_are_checked() {
+    let _serial = serial();
+    let (camera, world) = ball();
+    validation::inject(Stage::ScatterAttenuation, Vec3::new(1.5, 0.5, 0.5));
+    camera.render_image(&world);
+    assert_only(
+        &validation::take_report(),
+        Stage::ScatterAttenuation,
+        "too large",
+    );
+}
+
+#[test]
+fn accumulation_inputs_are_checked() {
+    let _serial = serial();
+    let (camera, world) = ball();
+    validation::inject(Stage::AccumulationInput, Vec3::new(0.2, -0.1, 0.2));
+    camera.render_image(&world);
+    assert_only(
+        &validation::take_report(),
+        Stage::AccumulationInput,
+        "negative",
+    );
+}
+
+#[test]
+fn presentation_inputs_are_checked() {
+    let _serial = serial();
+    let (camera, world) = ball();
+    let image = camera.render_image(&world);
+    assert_eq!(validation::take_report().total(), 0);
+    validation::inject(
+        Stage::PresentationInput,
+        Vec3::new(Float::INFINITY, 0.0, 0.0),
+    );
+    PostProcess::default().apply(&image);
+    assert_only(
+        &validation::take_report(),
+        Stage::PresentationInput,
+        "not finite",
+    );
+}
+
+#[test]
+fn encoded_outputs_are_checked() {
+    let _serial = serial();
+    let (camera, world) = ball();
+    let image = camera.render_image(&world);
+    assert_eq!(validation::take_report().total(), 0);
+    validation::inject(Stage::EncodedOutput, Vec3::new(0.0, 300.0, 0.0));
+    image.encode_ppm();
+    assert_only(
+        &validation::take_report(),
+        Stage::EncodedOutput,
+        "too large",
+    );
+}
+
+#[test]
+fn golden_scenes_render_without_violations() {
+    let _serial = serial();
+    for scene in BenchScene::ALL {
+        let (camera, world) = scene.build();
+        let camera = camera
+            .with_resolution(32, 18)
+            .with_sampling(4, camera.max_depth());
+        let image = PostProcess::default().apply(&camera.render_image(&world));
+        image.encode_ppm();
+        let report = validation::take_report();
+        assert_eq!(report.total(), 0, "{}: {}", scene.name(), report);
+    }
+}